@@ -0,0 +1,91 @@
+//! Compares the number of allocations for receiving the request bodies
+//! with and without the buffer pool.
+//!
+//! ```text
+//! $ cargo bench --bench buffer_pool
+//! ```
+
+use {
+    finchers::{
+        endpoints::body,
+        prelude::*,
+        service::{App, BufferPool},
+    },
+    http::Request,
+    izanami_service::{MakeService, Service},
+    std::{
+        alloc::{GlobalAlloc, Layout, System},
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Instant,
+    },
+    tokio::runtime::current_thread::Runtime,
+};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ITERATIONS: usize = 10_000;
+
+fn run(name: &str, app: App<impl Endpoint<Vec<u8>, Output = (String,)>>) {
+    let mut rt = Runtime::new().unwrap();
+    let mut service = rt.block_on(app.make_service(())).unwrap();
+    let payload = vec![b'x'; 16 * 1024];
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let request = Request::post("/")
+            .header("content-type", "text/plain")
+            .body(payload.clone())
+            .unwrap();
+        rt.block_on(service.call(request)).unwrap();
+    }
+    let elapsed = start.elapsed();
+    let micros = elapsed.as_secs() as f64 * 1e6 + f64::from(elapsed.subsec_nanos()) / 1e3;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed) - allocated_bytes;
+
+    println!(
+        "{:<12} {:>8.1} allocs/req {:>10.1} bytes/req {:>8.2} us/req",
+        name,
+        allocations as f64 / ITERATIONS as f64,
+        allocated_bytes as f64 / ITERATIONS as f64,
+        micros / ITERATIONS as f64,
+    );
+}
+
+fn endpoint() -> impl Endpoint<Vec<u8>, Output = (String,)> {
+    body::text().map(|body: String| body.len().to_string())
+}
+
+fn main() {
+    run("unpooled", App::new(endpoint()));
+    run(
+        "pooled",
+        App::new(endpoint()).with_buffer_pool(BufferPool::default()),
+    );
+}
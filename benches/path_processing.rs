@@ -0,0 +1,73 @@
+//! Measures the time for splitting and decoding the request paths,
+//! with and without the vectorized routines.
+//!
+//! ```text
+//! $ cargo bench --bench path_processing
+//! $ cargo bench --bench path_processing --features simd
+//! ```
+
+use {
+    finchers::{
+        endpoint::syntax::{self, encoded::EncodedStr},
+        prelude::*,
+        service::App,
+    },
+    http::Request,
+    izanami_service::{MakeService, Service},
+    std::time::Instant,
+    tokio::runtime::current_thread::Runtime,
+};
+
+const ITERATIONS: usize = 100_000;
+
+fn report(name: &str, iterations: usize, start: Instant) {
+    let elapsed = start.elapsed();
+    let nanos = elapsed.as_secs() as f64 * 1e9 + f64::from(elapsed.subsec_nanos());
+    println!("{:<16} {:>10.1} ns/iter", name, nanos / iterations as f64);
+}
+
+fn decode(name: &str, path: &str) {
+    let encoded = unsafe { EncodedStr::new_unchecked(path) };
+    let mut total = 0;
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        total += encoded.percent_decode().unwrap().len();
+    }
+    report(name, ITERATIONS, start);
+    assert!(total > 0);
+}
+
+fn route<E>(name: &str, endpoint: E, path: &str)
+where
+    E: Endpoint<Vec<u8>, Output = (String,)> + Send + Sync + 'static,
+{
+    let mut rt = Runtime::new().unwrap();
+    let mut service = rt.block_on(App::new(endpoint).make_service(())).unwrap();
+    let iterations = ITERATIONS / 10;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let request = Request::get(path).body(Vec::new()).unwrap();
+        rt.block_on(service.call(request)).unwrap();
+    }
+    report(name, iterations, start);
+}
+
+fn main() {
+    let plain = "objects/".repeat(64);
+    let escaped = "caf%C3%A9%20au%20lait/".repeat(32);
+    decode("decode-plain", &plain);
+    decode("decode-escaped", &escaped);
+
+    route(
+        "route-proxy",
+        syntax::segment("proxy")
+            .and(syntax::remains::<String>())
+            .map(|rest: String| rest),
+        &format!("/proxy/{}", escaped),
+    );
+    route(
+        "route-segments",
+        syntax::path!("/a/b/c/d/e/f/g/h/i/j/k/l/m/n/o/p/<String>").map(|last: String| last),
+        "/a/b/c/d/e/f/g/h/i/j/k/l/m/n/o/p/q",
+    );
+}
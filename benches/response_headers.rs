@@ -0,0 +1,141 @@
+//! Measures the number of allocations on the response path, for the endpoints
+//! which register no, a few (stored inline) and many supplemental headers.
+//!
+//! ```text
+//! $ cargo bench --bench response_headers
+//! ```
+
+use {
+    finchers::{
+        prelude::*,
+        service::{App, Context, ResponseHeaders},
+    },
+    futures::future,
+    http::{
+        header::{HeaderMap, HeaderName, HeaderValue},
+        Request,
+    },
+    izanami_service::{MakeService, Service},
+    std::{
+        alloc::{GlobalAlloc, Layout, System},
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Duration, Instant},
+    },
+    tokio::runtime::current_thread::Runtime,
+};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ITERATIONS: usize = 10_000;
+
+const NAMES: [&str; 12] = [
+    "x-header-0",
+    "x-header-1",
+    "x-header-2",
+    "x-header-3",
+    "x-header-4",
+    "x-header-5",
+    "x-header-6",
+    "x-header-7",
+    "x-header-8",
+    "x-header-9",
+    "x-header-10",
+    "x-header-11",
+];
+
+fn report(name: &str, allocations: usize, elapsed: Duration) {
+    let micros = elapsed.as_secs() as f64 * 1e6 + f64::from(elapsed.subsec_nanos()) / 1e3;
+    println!(
+        "{:<24} {:>8.1} allocs/iter {:>8.3} us/iter",
+        name,
+        allocations as f64 / ITERATIONS as f64,
+        micros / ITERATIONS as f64,
+    );
+}
+
+fn measure(name: &str, mut f: impl FnMut()) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    report(
+        name,
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        elapsed,
+    );
+}
+
+/// Compares the storage of the supplemental headers alone.
+fn bench_storage(num_headers: usize) {
+    let value = HeaderValue::from_static("value");
+    measure(&format!("HeaderMap ({})", num_headers), || {
+        let mut headers = HeaderMap::new();
+        for name in &NAMES[..num_headers] {
+            headers.append(HeaderName::from_static(name), value.clone());
+        }
+        drop(headers);
+    });
+    measure(&format!("ResponseHeaders ({})", num_headers), || {
+        let mut headers = ResponseHeaders::default();
+        for name in &NAMES[..num_headers] {
+            headers.append(HeaderName::from_static(name), value.clone());
+        }
+        drop(headers);
+    });
+}
+
+/// Measures the whole request handling, including the construction of the response.
+fn bench_service(num_headers: usize) {
+    let endpoint = endpoint::endpoint(move || {
+        future::lazy(move || {
+            Context::with(|cx| {
+                for name in &NAMES[..num_headers] {
+                    cx.append_response_header(
+                        HeaderName::from_static(name),
+                        HeaderValue::from_static("value"),
+                    );
+                }
+            });
+            Ok::<_, finchers::error::Error>(("Hello",))
+        })
+    });
+
+    let mut rt = Runtime::new().unwrap();
+    let mut service = rt.block_on(App::new(endpoint).make_service(())).unwrap();
+    measure(&format!("App ({})", num_headers), || {
+        let request = Request::get("/").body(()).unwrap();
+        rt.block_on(service.call(request)).unwrap();
+    });
+}
+
+fn main() {
+    for &num_headers in &[0, 4, 12] {
+        bench_storage(num_headers);
+    }
+    for &num_headers in &[0, 4, 12] {
+        bench_service(num_headers);
+    }
+}
@@ -0,0 +1,123 @@
+//! Measures the dispatch time of an `or` chain with 200 literal routes,
+//! with and without `EndpointExt::optimize`.
+//!
+//! Only the matched requests are measured, since the unmatched ones are
+//! dispatched to the original chain in both cases.
+//!
+//! ```text
+//! $ cargo bench --bench routing
+//! ```
+
+use {
+    finchers::{
+        endpoint::syntax::{self, verb},
+        prelude::*,
+        service::App,
+    },
+    http::Request,
+    izanami_service::{MakeService, Service},
+    std::time::Instant,
+    tokio::runtime::current_thread::Runtime,
+};
+
+const ITERATIONS: usize = 10_000;
+
+macro_rules! group {
+    ($name:expr) => {
+        syntax::segment($name)
+            .and(syntax::segment("r0"))
+            .and(syntax::eos())
+            .map(|| "0")
+            .or(syntax::segment($name)
+                .and(syntax::segment("r1"))
+                .and(syntax::eos())
+                .map(|| "1"))
+            .or(syntax::segment($name)
+                .and(syntax::segment("r2"))
+                .and(syntax::eos())
+                .map(|| "2"))
+            .or(syntax::segment($name)
+                .and(syntax::segment("r3"))
+                .and(syntax::eos())
+                .map(|| "3"))
+            .or(syntax::segment($name)
+                .and(syntax::segment("r4"))
+                .and(syntax::eos())
+                .map(|| "4"))
+            .or(verb::post()
+                .and(syntax::segment($name))
+                .and(syntax::segment("r5"))
+                .map(|| "5"))
+            .or(verb::post()
+                .and(syntax::segment($name))
+                .and(syntax::segment("r6"))
+                .map(|| "6"))
+            .or(verb::post()
+                .and(syntax::segment($name))
+                .and(syntax::segment("r7"))
+                .map(|| "7"))
+            .or(verb::post()
+                .and(syntax::segment($name))
+                .and(syntax::segment("r8"))
+                .map(|| "8"))
+            .or(verb::post()
+                .and(syntax::segment($name))
+                .and(syntax::segment("r9"))
+                .map(|| "9"))
+    };
+}
+
+macro_rules! routes {
+    () => {
+        (group!("g00")
+            .or(group!("g01"))
+            .or(group!("g02").or(group!("g03"))))
+        .or(group!("g04")
+            .or(group!("g05"))
+            .or(group!("g06").or(group!("g07"))))
+        .or(group!("g08")
+            .or(group!("g09"))
+            .or(group!("g10").or(group!("g11"))))
+        .or(group!("g12")
+            .or(group!("g13"))
+            .or(group!("g14").or(group!("g15"))))
+        .or(group!("g16")
+            .or(group!("g17"))
+            .or(group!("g18").or(group!("g19"))))
+    };
+}
+
+fn dispatch<E>(name: &str, endpoint: E)
+where
+    E: Endpoint<Vec<u8>> + Send + Sync + 'static,
+    E::Output: finchers::output::IntoResponse,
+{
+    let requests = [("GET", "/g00/r0"), ("GET", "/g10/r4"), ("POST", "/g19/r9")];
+    let mut rt = Runtime::new().unwrap();
+    let mut service = rt.block_on(App::new(endpoint).make_service(())).unwrap();
+    for &(method, uri) in &requests {
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Vec::new())
+                .unwrap();
+            rt.block_on(service.call(request)).unwrap();
+        }
+        let elapsed = start.elapsed();
+        let nanos = elapsed.as_secs() as f64 * 1e9 + f64::from(elapsed.subsec_nanos());
+        println!(
+            "{:<10} {:<4} {:<8} {:>10.1} ns/iter",
+            name,
+            method,
+            uri,
+            nanos / ITERATIONS as f64,
+        );
+    }
+}
+
+fn main() {
+    dispatch("or-chain", routes!());
+    dispatch("optimized", routes!().optimize());
+}
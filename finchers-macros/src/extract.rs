@@ -0,0 +1,59 @@
+use {
+    proc_macro::TokenStream,
+    proc_macro2::{Span, TokenStream as TokenStream2},
+    quote::*,
+    syn::{parse::Error, Data, DeriveInput, Fields, Ident},
+};
+
+pub(crate) fn derive(input: TokenStream) -> TokenStream {
+    let input = match syn::parse::<DeriveInput>(input) {
+        Ok(input) => input,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::parse::Result<TokenStream2> {
+    let fields = match input.data {
+        Data::Struct(ref data) => &data.fields,
+        _ => {
+            return Err(Error::new_spanned(
+                input,
+                "#[derive(Extract)] is only available for structs",
+            ));
+        }
+    };
+
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let vars: Vec<_> = (0..types.len())
+        .map(|i| Ident::new(&format!("__x_{}", i), Span::call_site()))
+        .collect();
+    let (types, vars) = (&types, &vars);
+    let construct = match fields {
+        Fields::Named(..) => {
+            let names = fields.iter().map(|field| &field.ident);
+            quote!({ #(#names: #vars,)* })
+        }
+        Fields::Unnamed(..) => quote!(( #(#vars,)* )),
+        Fields::Unit => quote!(),
+    };
+
+    let self_ty = &input.ident;
+    let extract: syn::Path = syn::parse_quote!(finchers::endpoint::Extract);
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #extract<( #(#types,)* )> for #self_ty #ty_generics
+        #where_clause
+        {
+            #[inline]
+            fn extract(output: ( #(#types,)* )) -> Self {
+                let ( #(#vars,)* ) = output;
+                #self_ty #construct
+            }
+        }
+    })
+}
@@ -0,0 +1,337 @@
+use {
+    proc_macro::TokenStream,
+    proc_macro2::{Span, TokenStream as TokenStream2},
+    quote::*,
+    syn::{
+        parse::Error, //
+        FnArg,
+        GenericArgument,
+        Ident,
+        ItemFn,
+        LitStr,
+        Pat,
+        PathArguments,
+        ReturnType,
+        Type,
+        TypeParamBound,
+    },
+};
+
+/// The extractor inferred from an argument of the handler function.
+enum Extractor {
+    /// A path parameter, whose name appears in the path literal.
+    Path,
+    /// `Json<T>`, extracted from the request body.
+    Json(Type),
+    /// `Header<T>`, whose name is derived from the argument name.
+    Header(Type),
+    /// `Option<Header<T>>`.
+    OptionalHeader(Type),
+}
+
+struct Arg {
+    ident: Ident,
+    ty: Type,
+    extractor: Extractor,
+}
+
+impl Arg {
+    fn is_path_param(&self) -> bool {
+        match self.extractor {
+            Extractor::Path => true,
+            _ => false,
+        }
+    }
+}
+
+pub(crate) fn derive(verb: &str, attr: TokenStream, item: TokenStream) -> TokenStream {
+    let path = match syn::parse::<LitStr>(attr) {
+        Ok(path) => path,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let item = match syn::parse::<ItemFn>(item) {
+        Ok(item) => item,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    match expand(verb, &path, item) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+#[allow(nonstandard_style)]
+fn expand(verb: &str, path: &LitStr, item: ItemFn) -> syn::parse::Result<TokenStream2> {
+    if let Some(asyncness) = item.asyncness {
+        return Err(Error::new_spanned(
+            asyncness,
+            "async fn is not supported; return `impl Future` instead",
+        ));
+    }
+    if !item.decl.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &item.decl.generics,
+            "the handler function must not be generic",
+        ));
+    }
+
+    let path_value = path.value();
+    let args = parse_args(&item)?;
+    let (extract_path, path_params) = convert_path(&path_value, path, &args)?;
+
+    let Endpoint: syn::Path = syn::parse_quote!(finchers::endpoint::Endpoint);
+    let EndpointExt: syn::Path = syn::parse_quote!(finchers::endpoint::EndpointExt);
+    let ExtractPath: syn::Path = syn::parse_quote!(finchers::endpoint::syntax::ExtractPath);
+    let BufStream: syn::Path = syn::parse_quote!(finchers::__private::BufStream);
+    let syntax: syn::Path = syn::parse_quote!(finchers::endpoint::syntax);
+    let Json: syn::Path = syn::parse_quote!(finchers::output::Json);
+    let header: syn::Path = syn::parse_quote!(finchers::endpoints::header);
+    let body: syn::Path = syn::parse_quote!(finchers::endpoints::body);
+    let verb = Ident::new(verb, Span::call_site());
+
+    let mut extracts: Vec<TokenStream2> = vec![];
+    let mut closure_args: Vec<TokenStream2> = vec![];
+    let mut needs_body = false;
+
+    // The path parameters are extracted in the order of the path literal,
+    // followed by the other arguments in the order of the declaration.
+    for name in &path_params {
+        let arg = args
+            .iter()
+            .find(|arg| arg.ident == *name)
+            .expect("the path parameter should be matched");
+        let (ident, ty) = (&arg.ident, &arg.ty);
+        closure_args.push(quote!(#ident: #ty));
+    }
+    for arg in &args {
+        let ident = &arg.ident;
+        let header_name = LitStr::new(&ident.to_string().replace('_', "-"), ident.span());
+        match arg.extractor {
+            Extractor::Path => continue,
+            Extractor::Json(ref ty) => {
+                needs_body = true;
+                extracts.push(quote!(#body::json::<#ty>()));
+                closure_args.push(quote!(#ident: #ty));
+            }
+            Extractor::Header(ref ty) => {
+                extracts.push(quote!(#header::parse::<#ty>(#header_name)));
+                closure_args.push(quote!(#ident: #ty));
+            }
+            Extractor::OptionalHeader(ref ty) => {
+                extracts.push(quote!(#header::optional::<#ty>(#header_name)));
+                closure_args.push(quote!(#ident: Option<#ty>));
+            }
+        }
+    }
+
+    let call_args = args.iter().map(|arg| {
+        let ident = &arg.ident;
+        match arg.extractor {
+            Extractor::Path => quote!(#ident),
+            Extractor::Json(..) => quote!(#Json(#ident)),
+            Extractor::Header(..) => quote!(#header::Header(#ident)),
+            Extractor::OptionalHeader(..) => {
+                quote!(#ident.map(#header::Header))
+            }
+        }
+    });
+
+    let (output, is_async) = output_type(&item.decl.output);
+    let apply = if is_async {
+        quote!(and_then)
+    } else {
+        quote!(map)
+    };
+
+    let where_clause = if needs_body {
+        quote! {
+            where
+                Bd: #BufStream,
+                Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+        }
+    } else {
+        quote!()
+    };
+
+    let extracts = extracts.iter().map(|extract| {
+        quote! {
+            let endpoint = #EndpointExt::and(endpoint, #extract);
+        }
+    });
+
+    let attrs = &item.attrs;
+    let vis = &item.vis;
+    let ident = &item.ident;
+    let inputs = &item.decl.inputs;
+    let ret = &item.decl.output;
+    let block = &item.block;
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis fn #ident<Bd>() -> impl #Endpoint<Bd, Output = (#output,)>
+        #where_clause
+        {
+            #[derive(#ExtractPath)]
+            #[extract_path = #extract_path]
+            struct __DerivedExtractPath(());
+
+            fn __handler(#inputs) #ret #block
+
+            let endpoint = #EndpointExt::and(
+                #syntax::verb::#verb(),
+                #syntax::path::<__DerivedExtractPath>(),
+            );
+            #(#extracts)*
+            #EndpointExt::#apply(endpoint, |#(#closure_args),*| __handler(#(#call_args),*))
+        }
+    })
+}
+
+fn parse_args(item: &ItemFn) -> syn::parse::Result<Vec<Arg>> {
+    item.decl
+        .inputs
+        .iter()
+        .map(|input| match input {
+            FnArg::Captured(arg) => {
+                let ident = match arg.pat {
+                    Pat::Ident(ref pat) => pat.ident.clone(),
+                    ref pat => {
+                        return Err(Error::new_spanned(
+                            pat,
+                            "the argument must be a simple identifier",
+                        ));
+                    }
+                };
+                let extractor = if let Some(ty) = generic_arg(&arg.ty, "Json") {
+                    Extractor::Json(ty)
+                } else if let Some(ty) = generic_arg(&arg.ty, "Header") {
+                    Extractor::Header(ty)
+                } else if let Some(ty) =
+                    generic_arg(&arg.ty, "Option").and_then(|ty| generic_arg(&ty, "Header"))
+                {
+                    Extractor::OptionalHeader(ty)
+                } else {
+                    // Resolved as a path parameter in `convert_path`.
+                    Extractor::Path
+                };
+                Ok(Arg {
+                    ident,
+                    ty: arg.ty.clone(),
+                    extractor,
+                })
+            }
+            input => Err(Error::new_spanned(
+                input,
+                "the argument must be of the form `name: Type`",
+            )),
+        })
+        .collect()
+}
+
+/// Converts the path with `{name}` placeholders into the syntax of `ExtractPath`,
+/// and returns it with the names of the path parameters.
+fn convert_path(s: &str, lit: &LitStr, args: &[Arg]) -> syn::parse::Result<(LitStr, Vec<Ident>)> {
+    let mut converted = String::new();
+    let mut params = vec![];
+    for segment in s.trim().split('/').skip(1) {
+        converted.push('/');
+        if !(segment.starts_with('{') && segment.ends_with('}')) {
+            converted.push_str(segment);
+            continue;
+        }
+
+        let (name, catch_all) = match segment[1..segment.len() - 1].trim() {
+            name if name.starts_with('*') => (&name[1..], true),
+            name => (name, false),
+        };
+        let arg = args.iter().find(|arg| arg.ident == name).ok_or_else(|| {
+            Error::new_spanned(
+                lit,
+                format!("missing argument for the parameter `{}`", name),
+            )
+        })?;
+        if !arg.is_path_param() {
+            return Err(Error::new_spanned(
+                &arg.ty,
+                "the type of a path parameter must implement `FromEncodedStr`",
+            ));
+        }
+        let ty = &arg.ty;
+        let ty = quote!(#ty).to_string();
+        if catch_all {
+            converted.push_str(&format!("<..{}>", ty));
+        } else {
+            converted.push_str(&format!("<{}>", ty));
+        }
+        params.push(arg.ident.clone());
+    }
+
+    if let Some(arg) = args
+        .iter()
+        .find(|arg| arg.is_path_param() && !params.contains(&arg.ident))
+    {
+        return Err(Error::new_spanned(
+            &arg.ident,
+            "cannot infer the extractor of this argument; \
+             it must be a path parameter, `Json<T>` or `Header<T>`",
+        ));
+    }
+
+    if converted.is_empty() {
+        converted.push('/');
+    }
+    Ok((LitStr::new(&converted, lit.span()), params))
+}
+
+/// Returns the type of the endpoint output, and whether the handler returns
+/// a `Result` or a future (which is applied by `and_then` instead of `map`).
+fn output_type(ret: &ReturnType) -> (Type, bool) {
+    let ty = match ret {
+        ReturnType::Default => return (syn::parse_quote!(()), false),
+        ReturnType::Type(_, ty) => &**ty,
+    };
+    if let Some(item) = generic_arg(ty, "Result") {
+        return (item, true);
+    }
+    if let Type::ImplTrait(ref impl_trait) = ty {
+        for bound in &impl_trait.bounds {
+            if let TypeParamBound::Trait(ref bound) = bound {
+                let last = match bound.path.segments.last() {
+                    Some(last) => last.into_value(),
+                    None => continue,
+                };
+                if last.ident != "Future" && last.ident != "IntoFuture" {
+                    continue;
+                }
+                if let PathArguments::AngleBracketed(ref args) = last.arguments {
+                    for arg in &args.args {
+                        if let GenericArgument::Binding(ref binding) = arg {
+                            if binding.ident == "Item" {
+                                return (binding.ty.clone(), true);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    (ty.clone(), false)
+}
+
+/// Returns the first generic argument if the last segment of `ty` is `name`.
+fn generic_arg(ty: &Type, name: &str) -> Option<Type> {
+    let path = match ty {
+        Type::Path(ref ty) if ty.qself.is_none() => &ty.path,
+        _ => return None,
+    };
+    let last = path.segments.last()?.into_value();
+    if last.ident != name {
+        return None;
+    }
+    match last.arguments {
+        PathArguments::AngleBracketed(ref args) => args.args.iter().find_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty.clone()),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
@@ -1,5 +1,8 @@
 extern crate proc_macro;
 
+mod extract;
+mod handler;
+
 use {
     proc_macro::TokenStream,
     proc_macro2::Span,
@@ -18,13 +21,13 @@ use {
 ///
 /// This macro is used internally in `path!()`.
 #[allow(nonstandard_style)]
-#[proc_macro_derive(ExtractPath, attributes(path))]
+#[proc_macro_derive(ExtractPath, attributes(extract_path))]
 pub fn ExtractPath(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
     let mut path: Option<LitStr> = None;
     for attr in &input.attrs {
-        if attr.path.is_ident("path") {
+        if attr.path.is_ident("extract_path") {
             match attr.parse_meta() {
                 Ok(syn::Meta::NameValue(meta)) => match meta.lit {
                     syn::Lit::Str(lit) => path = Some(lit),
@@ -37,7 +40,7 @@ pub fn ExtractPath(input: TokenStream) -> TokenStream {
                 Ok(..) => {
                     return syn::parse::Error::new_spanned(
                         attr,
-                        "the attribute must be a `#[path = \"..\"]`",
+                        "the attribute must be a `#[extract_path = \"..\"]`",
                     )
                     .to_compile_error()
                     .into();
@@ -49,15 +52,18 @@ pub fn ExtractPath(input: TokenStream) -> TokenStream {
     let path = match path {
         Some(path) => path,
         None => {
-            return syn::parse::Error::new_spanned(&input, "missing attribute: #[path \"/path\"]")
-                .to_compile_error()
-                .into();
+            return syn::parse::Error::new_spanned(
+                &input,
+                "missing attribute: #[extract_path = \"/path\"]",
+            )
+            .to_compile_error()
+            .into();
         }
     };
 
     let path_value = path.value();
-    let components = match parse_path(&path_value, &path) {
-        Ok(components) => components,
+    let (components, check_eos) = match parse_path(&path_value, &path) {
+        Ok(parsed) => parsed,
         Err(err) => return err.to_compile_error().into(),
     };
     let components = &components; // anchored
@@ -136,6 +142,14 @@ pub fn ExtractPath(input: TokenStream) -> TokenStream {
         }
     }
 
+    if check_eos {
+        extracts.push(syn::parse_quote! {
+            if cx.cursor().next().is_some() {
+                return Err(#ExtractPathError::not_matched());
+            }
+        });
+    }
+
     TokenStream::from(quote! {
         impl #impl_generics #ExtractPath for #Self_ #ty_generics
         #where_clause
@@ -150,6 +164,60 @@ pub fn ExtractPath(input: TokenStream) -> TokenStream {
     })
 }
 
+/// A procedural macro to define code that defines a type that
+/// implements `Extract` from the types of its fields.
+///
+/// The fields are assigned from the elements of the tuple in the order of
+/// their declarations. This macro is re-exported as `finchers::endpoint::Extract`.
+#[allow(nonstandard_style)]
+#[proc_macro_derive(Extract)]
+pub fn Extract(input: TokenStream) -> TokenStream {
+    extract::derive(input)
+}
+
+macro_rules! define_handler_attributes {
+    ($($(#[$m:meta])* $name:ident,)*) => {$(
+        $(#[$m])*
+        #[proc_macro_attribute]
+        pub fn $name(attr: TokenStream, item: TokenStream) -> TokenStream {
+            handler::derive(stringify!($name), attr, item)
+        }
+    )*};
+}
+
+define_handler_attributes! {
+    /// An attribute macro which converts a handler function into a function
+    /// returning an endpoint matched to `GET` requests.
+    ///
+    /// The path is specified in the same syntax as `path!()`, except that
+    /// the parameters are written as `{name}` (or `{*name}` for the catch-all
+    /// parameter) where `name` is the name of the corresponding argument.
+    /// The extractors of the other arguments are inferred from their types:
+    ///
+    /// * `Json<T>` - the request body parsed as JSON by `endpoints::body::json`.
+    /// * `Header<T>` - the header value parsed by `endpoints::header::parse`.
+    ///   The header name is the argument name whose underscores are replaced
+    ///   with hyphens (e.g. `user_agent` for `User-Agent`).
+    /// * `Option<Header<T>>` - same as above, but the header may be missing.
+    ///
+    /// The handler is applied by `map()`, or by `and_then()` if its return type
+    /// is `Result<T, E>` or `impl Future<Item = T, ..>`.
+    /// Since the endpoints are based on futures 0.1, `async fn` is not supported.
+    get,
+    /// Same as `get`, but matched to `POST` requests.
+    post,
+    /// Same as `get`, but matched to `PUT` requests.
+    put,
+    /// Same as `get`, but matched to `DELETE` requests.
+    delete,
+    /// Same as `get`, but matched to `HEAD` requests.
+    head,
+    /// Same as `get`, but matched to `OPTIONS` requests.
+    options,
+    /// Same as `get`, but matched to `PATCH` requests.
+    patch,
+}
+
 #[derive(Debug)]
 enum Component<'a> {
     Static(&'a str),
@@ -157,7 +225,7 @@ enum Component<'a> {
     CatchAllParam(Type),
 }
 
-fn parse_path<'s>(s: &'s str, lit: &LitStr) -> syn::parse::Result<Vec<Component<'s>>> {
+fn parse_path<'s>(s: &'s str, lit: &LitStr) -> syn::parse::Result<(Vec<Component<'s>>, bool)> {
     let s = s.trim();
     if s.is_empty() {
         return Err(syn::parse::Error::new_spanned(
@@ -174,10 +242,11 @@ fn parse_path<'s>(s: &'s str, lit: &LitStr) -> syn::parse::Result<Vec<Component<
     }
 
     if s == "/" {
-        return Ok(vec![]);
+        return Ok((vec![], true));
     }
 
     let mut components = vec![];
+    let mut check_eos = true;
     let mut iter = s.split('/').skip(1).peekable();
     while let Some(segment) = iter.next() {
         if segment.is_empty() {
@@ -190,6 +259,35 @@ fn parse_path<'s>(s: &'s str, lit: &LitStr) -> syn::parse::Result<Vec<Component<
             break;
         }
 
+        if segment == "..." {
+            if iter.peek().is_some() {
+                return Err(syn::parse::Error::new_spanned(
+                    lit,
+                    "'...' must be at the end of path",
+                ));
+            }
+            check_eos = false;
+            break;
+        }
+
+        if segment.starts_with('*') {
+            if syn::parse_str::<Ident>(&segment[1..]).is_err() {
+                return Err(syn::parse::Error::new_spanned(
+                    lit,
+                    "the name of the trailing capture must be an identifier",
+                ));
+            }
+            if iter.peek().is_some() {
+                return Err(syn::parse::Error::new_spanned(
+                    lit,
+                    "the trailing capture must be at the end of path",
+                ));
+            }
+            components.push(Component::CatchAllParam(syn::parse_quote!(String)));
+            check_eos = false;
+            break;
+        }
+
         if segment.starts_with('<') {
             if !segment.ends_with('>') {
                 return Err(syn::parse::Error::new_spanned(
@@ -206,8 +304,9 @@ fn parse_path<'s>(s: &'s str, lit: &LitStr) -> syn::parse::Result<Vec<Component<
             let ty_str = &segment[1..segment.len() - 1];
 
             if ty_str.starts_with("..") {
-                let ty: syn::Type = syn::parse_str(&ty_str[2..]) //
-                    .map_err(|e| syn::parse::Error::new_spanned(lit, e))?;
+                let ty: syn::Type =
+                    syn::parse_str(&ty_str[2..]) //
+                        .map_err(|e| syn::parse::Error::new_spanned(lit, e))?;
                 components.push(Component::CatchAllParam(ty));
 
                 if iter.peek().is_some() {
@@ -217,10 +316,12 @@ fn parse_path<'s>(s: &'s str, lit: &LitStr) -> syn::parse::Result<Vec<Component<
                     ));
                 }
 
+                check_eos = false;
                 break;
             } else {
-                let ty: syn::Type = syn::parse_str(ty_str) //
-                    .map_err(|e| syn::parse::Error::new_spanned(lit, e))?;
+                let ty: syn::Type =
+                    syn::parse_str(ty_str) //
+                        .map_err(|e| syn::parse::Error::new_spanned(lit, e))?;
                 components.push(Component::SingleParam(ty));
             }
         } else {
@@ -228,5 +329,5 @@ fn parse_path<'s>(s: &'s str, lit: &LitStr) -> syn::parse::Result<Vec<Component<
         }
     }
 
-    Ok(components)
+    Ok((components, check_eos))
 }
@@ -41,3 +41,48 @@ fn test_handshake() -> izanami::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_echo() {
+    use {
+        finchers_tungstenite::Message,
+        futures::{Future, Sink, Stream},
+        tokio_tungstenite::WebSocketStream,
+        tungstenite::protocol::Role,
+    };
+
+    let mut runner = finchers::test::runner({
+        finchers_tungstenite::ws(
+            |stream: finchers_tungstenite::WsTransport<finchers::test::ReqBody>| {
+                let (tx, rx) = stream.split();
+                rx.take(1)
+                    .forward(tx)
+                    .map(drop)
+                    .map_err(|e| panic!("{}", e))
+            },
+        )
+    });
+
+    let mut response = runner
+        .perform(
+            Request::get("/")
+                .header("connection", "upgrade")
+                .header("upgrade", "websocket")
+                .header("sec-websocket-version", "13")
+                .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="),
+        )
+        .unwrap();
+    response.assert_status(101);
+
+    let io = response.take_upgraded().expect("should be upgraded");
+    let client = WebSocketStream::from_raw_socket(io, Role::Client, None);
+    let (message, _client) = runner
+        .runtime()
+        .block_on(
+            client
+                .send(Message::text("Hello"))
+                .and_then(|client| client.into_future().map_err(|(e, _)| e)),
+        )
+        .unwrap();
+    assert_eq!(message, Some(Message::text("Hello")));
+}
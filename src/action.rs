@@ -144,19 +144,93 @@ where
 pub struct PreflightContext<'a> {
     context: &'a Context,
     cursor: CursorInner,
+    missed: Option<&'a Missed<'a>>,
     _anchor: PhantomData<Rc<()>>,
 }
 
+/// The error of a preceding branch in the `or` chain.
+#[derive(Debug)]
+struct Missed<'a> {
+    error: &'a Error,
+    parent: Option<&'a Missed<'a>>,
+}
+
+/// An iterator over the errors of the preceding branches.
+#[derive(Debug)]
+pub(crate) struct MissedErrors<'a> {
+    next: Option<&'a Missed<'a>>,
+}
+
+impl<'a> Iterator for MissedErrors<'a> {
+    type Item = &'a Error;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let missed = self.next?;
+        self.next = missed.parent;
+        Some(missed.error)
+    }
+}
+
 impl<'a> PreflightContext<'a> {
     #[inline]
     pub(crate) fn new(context: &'a Context) -> Self {
         PreflightContext {
             context,
             cursor: CursorInner { pos: 1, popped: 0 },
+            missed: None,
             _anchor: PhantomData,
         }
     }
 
+    /// Creates a `PreflightContext` whose cursor starts at the specified
+    /// position, which is obtained from `position`.
+    #[inline]
+    pub(crate) fn resume(context: &'a Context, (pos, popped): (usize, usize)) -> Self {
+        PreflightContext {
+            context,
+            cursor: CursorInner { pos, popped },
+            missed: None,
+            _anchor: PhantomData,
+        }
+    }
+
+    /// Returns the current position of the cursor, i.e. the byte offset
+    /// in the path and the number of the popped segments.
+    #[inline]
+    pub(crate) fn position(&self) -> (usize, usize) {
+        (self.cursor.pos, self.cursor.popped)
+    }
+
+    /// Calls the specified function with a context that records `error`
+    /// as the reason why the preceding branch has not been matched.
+    ///
+    /// The position of cursor is written back after the call.
+    pub(crate) fn with_missed<R>(
+        &mut self,
+        error: &Error,
+        f: impl FnOnce(&mut PreflightContext<'_>) -> R,
+    ) -> R {
+        let missed = Missed {
+            error,
+            parent: self.missed,
+        };
+        let mut cx = PreflightContext {
+            context: self.context,
+            cursor: self.cursor.clone(),
+            missed: Some(&missed),
+            _anchor: PhantomData,
+        };
+        let result = f(&mut cx);
+        self.cursor = cx.cursor;
+        result
+    }
+
+    /// Returns the errors of the preceding branches in the `or` chains,
+    /// from the innermost one.
+    pub(crate) fn missed_errors(&self) -> MissedErrors<'_> {
+        MissedErrors { next: self.missed }
+    }
+
     /// Returns a reference to the request context.
     #[inline]
     pub fn context(&self) -> &Context {
@@ -208,7 +282,8 @@ impl<'cx> Cursor<'cx> {
             return None;
         }
 
-        let s = if let Some(offset) = self.path[self.inner.pos..].find('/') {
+        let remaining = &self.path.as_bytes()[self.inner.pos..];
+        let s = if let Some(offset) = crate::util::scan::find_byte(b'/', remaining) {
             let s = &self.path[self.inner.pos..(self.inner.pos + offset)];
             self.inner.pos += offset + 1;
             self.inner.popped += 1;
@@ -276,6 +351,15 @@ impl<'a, Bd> ActionContext<'a, Bd> {
         self.body.as_mut()
     }
 
+    /// Replaces the instance of request body with the specified one,
+    /// and returns the previous one if exists.
+    ///
+    /// This method is used by the wrappers which transform the request body
+    /// before the inner endpoints read it, such as decompression.
+    pub fn replace_body(&mut self, body: Bd) -> Option<Bd> {
+        self.body.replace(body)
+    }
+
     /// Takes the instance of request body from this context.
     ///
     /// This method will return an `Err` if the body has already taken by someone.
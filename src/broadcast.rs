@@ -0,0 +1,360 @@
+//! A publish/subscribe hub for fanning out the messages to many clients.
+//!
+//! A `Hub` is a cloneable sender, and each call of `Hub::subscribe` creates
+//! a `Subscriber` with its own bounded buffer. `Subscriber` is a `Stream`,
+//! so it can be forwarded into the sink of a WebSocket connection or mapped
+//! into the events of a streaming response.
+//!
+//! A slow subscriber never blocks the sender nor the other subscribers.
+//! When the buffer of a subscriber is full, the oldest message is dropped
+//! and counted as lagged (`LagPolicy::DropOldest`), or the subscriber is
+//! disconnected with the error `Lagged` (`LagPolicy::Disconnect`).
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::broadcast::Hub;
+//! # use futures::{Future, Stream};
+//! let hub = Hub::new(16);
+//! let subscriber = hub.subscribe();
+//!
+//! hub.send("hello");
+//! hub.send("world");
+//! drop(hub);
+//!
+//! let messages = subscriber.collect().wait().unwrap();
+//! assert_eq!(messages, vec!["hello", "world"]);
+//! ```
+
+use {
+    failure::Fail,
+    futures::{
+        task::{self, Task},
+        Async, Poll, Stream,
+    },
+    std::{
+        collections::{HashMap, VecDeque},
+        fmt,
+        sync::{Arc, Mutex},
+    },
+};
+
+/// The policy applied to the subscribers whose buffers are full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// Drops the oldest message in the buffer.
+    DropOldest,
+    /// Disconnects the subscriber after delivering the buffered messages.
+    Disconnect,
+}
+
+/// An error returned from the `Subscriber` disconnected by `LagPolicy::Disconnect`.
+#[derive(Debug, Fail)]
+#[fail(display = "the subscriber lagged behind and has been disconnected")]
+pub struct Lagged(());
+
+impl crate::error::HttpError for Lagged {
+    fn status_code(&self) -> http::StatusCode {
+        http::StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    queue: VecDeque<T>,
+    lagged: u64,
+    disconnected: bool,
+    task: Option<Task>,
+}
+
+impl<T> Slot<T> {
+    fn notify(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.notify();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State<T> {
+    slots: HashMap<usize, Slot<T>>,
+    next_id: usize,
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    policy: LagPolicy,
+}
+
+impl<T> Shared<T> {
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        for slot in state.slots.values_mut() {
+            slot.notify();
+        }
+    }
+}
+
+/// A token held by the senders, which closes the hub when all of them are dropped.
+#[derive(Debug)]
+struct SenderToken<T>(Arc<Shared<T>>);
+
+impl<T> Drop for SenderToken<T> {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+// ==== Hub ====
+
+/// The sending half of a broadcast hub.
+///
+/// The hub is closed when all of the clones of `Hub` are dropped or
+/// `Hub::close` is called, and then the subscribers end after receiving
+/// the buffered messages.
+pub struct Hub<T> {
+    shared: Arc<Shared<T>>,
+    _token: Arc<SenderToken<T>>,
+}
+
+impl<T> Clone for Hub<T> {
+    fn clone(&self) -> Self {
+        Hub {
+            shared: self.shared.clone(),
+            _token: self._token.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Hub<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hub")
+            .field("capacity", &self.shared.capacity)
+            .field("policy", &self.shared.policy)
+            .field("subscribers", &self.subscriber_count())
+            .finish()
+    }
+}
+
+impl<T: Clone> Hub<T> {
+    /// Creates a new `Hub` whose subscribers buffer at most `capacity` messages,
+    /// with `LagPolicy::DropOldest`.
+    ///
+    /// # Panics
+    /// This method panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        Hub::with_lag_policy(capacity, LagPolicy::DropOldest)
+    }
+
+    /// Creates a new `Hub` with the specified capacity and lag policy.
+    ///
+    /// # Panics
+    /// This method panics if `capacity` is zero.
+    pub fn with_lag_policy(capacity: usize, policy: LagPolicy) -> Self {
+        assert!(capacity > 0, "the capacity must be positive");
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                slots: HashMap::new(),
+                next_id: 0,
+                closed: false,
+            }),
+            capacity,
+            policy,
+        });
+        Hub {
+            _token: Arc::new(SenderToken(shared.clone())),
+            shared,
+        }
+    }
+
+    /// Sends a message to all of the current subscribers, and returns
+    /// the number of the subscribers which received it.
+    pub fn send(&self, message: T) -> usize {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return 0;
+        }
+        let mut received = 0;
+        for slot in state.slots.values_mut() {
+            if slot.disconnected {
+                continue;
+            }
+            if slot.queue.len() >= self.shared.capacity {
+                slot.lagged += 1;
+                match self.shared.policy {
+                    LagPolicy::DropOldest => {
+                        slot.queue.pop_front();
+                    }
+                    LagPolicy::Disconnect => {
+                        slot.disconnected = true;
+                        slot.notify();
+                        continue;
+                    }
+                }
+            }
+            slot.queue.push_back(message.clone());
+            slot.notify();
+            received += 1;
+        }
+        received
+    }
+
+    /// Creates a new subscriber, which receives the messages sent after
+    /// this call.
+    ///
+    /// If the hub has already been closed, the returned subscriber ends immediately.
+    pub fn subscribe(&self) -> Subscriber<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.slots.insert(
+            id,
+            Slot {
+                queue: VecDeque::new(),
+                lagged: 0,
+                disconnected: false,
+                task: None,
+            },
+        );
+        Subscriber {
+            shared: self.shared.clone(),
+            id,
+        }
+    }
+}
+
+impl<T> Hub<T> {
+    /// Returns the number of the active subscribers.
+    pub fn subscriber_count(&self) -> usize {
+        self.shared.state.lock().unwrap().slots.len()
+    }
+
+    /// Closes the hub, so that the subscribers end after receiving the
+    /// buffered messages.
+    pub fn close(&self) {
+        self.shared.close();
+    }
+
+    /// Returns whether the hub has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.shared.state.lock().unwrap().closed
+    }
+}
+
+// ==== Subscriber ====
+
+/// The receiving half of a broadcast hub, created by `Hub::subscribe`.
+pub struct Subscriber<T> {
+    shared: Arc<Shared<T>>,
+    id: usize,
+}
+
+impl<T> fmt::Debug for Subscriber<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscriber").field("id", &self.id).finish()
+    }
+}
+
+impl<T> Subscriber<T> {
+    /// Returns the number of the messages which have not been received
+    /// by this subscriber due to the lag.
+    pub fn lagged(&self) -> u64 {
+        let state = self.shared.state.lock().unwrap();
+        state.slots.get(&self.id).map_or(0, |slot| slot.lagged)
+    }
+}
+
+impl<T> Stream for Subscriber<T> {
+    type Item = T;
+    type Error = Lagged;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut state = self.shared.state.lock().unwrap();
+        let closed = state.closed;
+        let slot = match state.slots.get_mut(&self.id) {
+            Some(slot) => slot,
+            None => return Ok(Async::Ready(None)),
+        };
+        if let Some(message) = slot.queue.pop_front() {
+            return Ok(Async::Ready(Some(message)));
+        }
+        if slot.disconnected {
+            state.slots.remove(&self.id);
+            return Err(Lagged(()));
+        }
+        if closed {
+            return Ok(Async::Ready(None));
+        }
+        slot.task = Some(task::current());
+        Ok(Async::NotReady)
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.slots.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, futures::Future};
+
+    #[test]
+    fn test_fan_out() {
+        let hub = Hub::new(4);
+        let sub1 = hub.subscribe();
+        let sub2 = hub.subscribe();
+        assert_eq!(hub.subscriber_count(), 2);
+
+        assert_eq!(hub.send(1), 2);
+        drop(sub2);
+        assert_eq!(hub.subscriber_count(), 1);
+        assert_eq!(hub.clone().send(2), 1);
+
+        hub.close();
+        let late = hub.subscribe();
+        assert_eq!(hub.send(3), 0);
+        assert_eq!(sub1.collect().wait().unwrap(), vec![1, 2]);
+        assert_eq!(late.collect().wait().unwrap(), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let hub = Hub::new(2);
+        let sub = hub.subscribe();
+        for i in 0..5 {
+            hub.send(i);
+        }
+        assert_eq!(sub.lagged(), 3);
+        drop(hub);
+        assert_eq!(sub.collect().wait().unwrap(), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let hub = Hub::with_lag_policy(2, LagPolicy::Disconnect);
+        let slow = hub.subscribe();
+        let fast = hub.subscribe();
+        hub.send(0);
+        hub.send(1);
+        let (received, fast) = fast.into_future().wait().ok().unwrap();
+        assert_eq!(received, Some(0));
+        assert_eq!(hub.send(2), 1);
+
+        let mut slow = slow.wait();
+        assert_eq!(slow.next().unwrap().ok(), Some(0));
+        assert_eq!(slow.next().unwrap().ok(), Some(1));
+        assert!(slow.next().unwrap().is_err());
+        assert_eq!(hub.subscriber_count(), 1);
+
+        drop(hub);
+        assert_eq!(fast.collect().wait().unwrap(), vec![1, 2]);
+    }
+}
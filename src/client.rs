@@ -0,0 +1,211 @@
+//! An HTTP client which shares the runtime with the server.
+//!
+//! The instances of `Client` spawn their background tasks (e.g. the connection pool)
+//! by using the *default* executor of Tokio, which means that the client always runs
+//! on the runtime that drives the current request, including the one used by the
+//! test runner.
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::client::{self, Client};
+//! # use finchers::endpoint::syntax::path;
+//! # fn main() {
+//! let endpoint = path!(@get "/proxy")
+//!     .and(client::client())
+//!     .and_then(|client: Client| {
+//!         client.get_bytes("http://www.example.com/".parse().unwrap())
+//!     })
+//!     .map(|body: bytes::Bytes| body.to_vec());
+//! # drop(endpoint);
+//! # }
+//! ```
+
+use {
+    crate::{
+        action::{
+            Oneshot,
+            OneshotAction,
+            PreflightContext, //
+        },
+        endpoint::{wrapper::TraceContext, Endpoint, IsEndpoint},
+        error::{self, Error},
+    },
+    bytes::Bytes,
+    futures::{Future, Stream},
+    http::{Request, Response, StatusCode, Uri},
+    hyper::client::HttpConnector,
+    serde::de::DeserializeOwned,
+    tokio::executor::DefaultExecutor,
+};
+
+#[doc(no_inline)]
+pub use hyper::Body;
+
+mod typed;
+pub use self::typed::{
+    render_path, //
+    FromResponse,
+    Json,
+    RouteInfo,
+    TypedClient,
+    UpstreamStatus,
+};
+
+thread_local! {
+    static DEFAULT_CLIENT: Client = Client::new();
+}
+
+/// An HTTP client wired to the default executor of Tokio.
+///
+/// This type is cheap to clone, and the cloned values share the same
+/// connection pool.
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: hyper::Client<HttpConnector, Body>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    /// Creates a new `Client` with the default configuration.
+    pub fn new() -> Self {
+        Self::from_hyper(
+            hyper::Client::builder()
+                .executor(DefaultExecutor::current())
+                .build_http(),
+        )
+    }
+
+    /// Creates a `Client` from a pre-configured instance of Hyper's client.
+    pub fn from_hyper(inner: hyper::Client<HttpConnector, Body>) -> Self {
+        Client { inner }
+    }
+
+    /// Returns a reference to the underlying Hyper's client.
+    pub fn get_ref(&self) -> &hyper::Client<HttpConnector, Body> {
+        &self.inner
+    }
+
+    /// Sends the specified request and returns a `Future` that will
+    /// resolve the response header.
+    ///
+    /// If the current request is handled within the wrapper created by
+    /// `wrapper::propagate_trace_context`, the trace context is injected into
+    /// the headers (unless the request already has `traceparent`).
+    pub fn request(
+        &self,
+        mut request: Request<Body>,
+    ) -> impl Future<Item = Response<Body>, Error = Error> + Send + 'static {
+        if !request.headers().contains_key("traceparent") {
+            if let Some(cx) = TraceContext::current() {
+                cx.inject(request.headers_mut());
+            }
+        }
+        self.inner.request(request).map_err(upstream_error)
+    }
+
+    /// Sends a `GET` request to the specified URI.
+    pub fn get(
+        &self,
+        uri: Uri,
+    ) -> impl Future<Item = Response<Body>, Error = Error> + Send + 'static {
+        let mut request = Request::new(Body::empty());
+        *request.uri_mut() = uri;
+        self.request(request)
+    }
+
+    /// Sends a `GET` request to the specified URI and receives the whole of response body.
+    ///
+    /// The returned `Future` will fail if the upstream server returns a response
+    /// whose status code is not successful.
+    pub fn get_bytes(&self, uri: Uri) -> impl Future<Item = Bytes, Error = Error> + Send + 'static {
+        self.get(uri).and_then(receive_bytes)
+    }
+
+    /// Sends a `GET` request to the specified URI and parses the response body as a JSON data.
+    pub fn get_json<T>(&self, uri: Uri) -> impl Future<Item = T, Error = Error> + Send + 'static
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        self.get_bytes(uri).and_then(|body| {
+            serde_json::from_slice(&body).map_err(|err| error::fail(err, StatusCode::BAD_GATEWAY))
+        })
+    }
+}
+
+pub(crate) fn upstream_error(err: hyper::Error) -> Error {
+    error::fail(err, StatusCode::BAD_GATEWAY)
+}
+
+pub(crate) fn receive_bytes(response: Response<Body>) -> impl Future<Item = Bytes, Error = Error> {
+    let status = response.status();
+    response
+        .into_body()
+        .concat2()
+        .map_err(upstream_error)
+        .and_then(move |chunk| {
+            if status.is_success() {
+                Ok(chunk.into_bytes())
+            } else {
+                Err(error::err_msg(
+                    format!("the upstream server returned an error status: {}", status),
+                    StatusCode::BAD_GATEWAY,
+                ))
+            }
+        })
+}
+
+/// Returns a clone of the `Client` shared within the current thread.
+pub fn shared() -> Client {
+    DEFAULT_CLIENT.with(Client::clone)
+}
+
+/// Create an endpoint which returns a `Client`.
+///
+/// The client is taken from the extensions of the request if exists,
+/// otherwise the client shared within the current thread is returned.
+#[inline]
+pub fn client() -> ClientEndpoint {
+    ClientEndpoint(())
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct ClientEndpoint(());
+
+mod client_endpoint {
+    use super::*;
+
+    impl IsEndpoint for ClientEndpoint {}
+
+    impl<Bd> Endpoint<Bd> for ClientEndpoint {
+        type Output = (Client,);
+        type Action = Oneshot<ClientAction>;
+
+        fn action(&self) -> Self::Action {
+            ClientAction(()).into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct ClientAction(());
+
+    impl OneshotAction for ClientAction {
+        type Output = (Client,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            let client = cx
+                .extensions()
+                .get::<Client>()
+                .cloned()
+                .unwrap_or_else(shared);
+            Ok((client,))
+        }
+    }
+}
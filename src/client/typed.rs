@@ -0,0 +1,559 @@
+//! The runtime support of the clients generated by `typed_client!`.
+
+use {
+    super::{upstream_error, Body, Client},
+    crate::error::{self, Error, HttpError},
+    bytes::Bytes,
+    failure::Fail,
+    futures::{future, Future, Stream},
+    http::{header, Method, Request, Response, StatusCode, Uri},
+    percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET},
+    serde::{de::DeserializeOwned, Serialize},
+    std::fmt,
+};
+
+/// Defines a typed client of the routes.
+///
+/// Each route is declared as a method, with the path in the same syntax as
+/// `path!` and its method as `@<method>`. The arguments fill the parameters
+/// of the path (`<T>`, `<..T>` and `*name`) in order, and the rest of them
+/// are sent as the query string, named by the arguments. The argument after
+/// `=>` is sent as the JSON body. The response is converted by `FromResponse`,
+/// e.g. `Json<T>` parses the response body as JSON.
+///
+/// The generated type also has the associated constant `ROUTES`, the table of
+/// the declared routes, so that the server can check its routes against the
+/// ones called by the clients (e.g. by registering them to `Admin::route`,
+/// or by sending the requests in the integration tests).
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::client::Json;
+/// # use finchers::output;
+/// # use finchers::endpoint::syntax::path;
+/// # use serde::{Deserialize, Serialize};
+/// #[derive(Debug, Serialize, Deserialize)]
+/// pub struct User {
+///     id: u32,
+///     name: String,
+/// }
+///
+/// finchers::typed_client! {
+///     /// The client of the users API.
+///     pub struct UsersClient {
+///         /// Fetches a user.
+///         fn get_user(id: u32) -> Json<User> = @get "/users/<u32>";
+///
+///         /// Lists the users on the specified page.
+///         fn list_users(page: u32) -> Json<Vec<User>> = @get "/users";
+///
+///         /// Creates a new user.
+///         fn create_user(=> user: User) -> () = @post "/users";
+///     }
+/// }
+///
+/// assert_eq!(UsersClient::ROUTES[0].path, "/users/<u32>");
+///
+/// // the server serves the same routes.
+/// let endpoint = path!(@get "/users/<u32>")
+///     .map(|id: u32| output::Json(User { id, name: "alice".into() }));
+///
+/// // the client is used within the runtime, e.g. from another service.
+/// let client = UsersClient::new("http://127.0.0.1:4000".parse().unwrap());
+/// let user = client.get_user(42); // impl Future<Item = Json<User>>
+/// # drop((endpoint, user));
+/// ```
+#[macro_export]
+macro_rules! typed_client {
+    (@body) => { ::std::option::Option::None::<&()> };
+    (@body $body:ident) => { ::std::option::Option::Some(&$body) };
+
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $Client:ident {
+            $(
+                $(#[$method_attr:meta])*
+                fn $name:ident (
+                    $($arg:ident : $ty:ty),* $(,)*
+                    $(=> $body:ident : $body_ty:ty)*
+                ) -> $ret:ty = @$verb:ident $path:tt;
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone)]
+        $vis struct $Client {
+            inner: $crate::client::TypedClient,
+        }
+
+        #[allow(dead_code)]
+        impl $Client {
+            /// The table of the routes called by this client.
+            pub const ROUTES: &'static [$crate::client::RouteInfo] = &[$(
+                $crate::client::RouteInfo {
+                    name: stringify!($name),
+                    method: stringify!($verb),
+                    path: $path,
+                },
+            )*];
+
+            /// Creates a client which sends the requests to the specified base URI,
+            /// by using the `Client` shared within the current thread.
+            pub fn new(base: $crate::__private::Uri) -> Self {
+                $Client {
+                    inner: $crate::client::TypedClient::new(base),
+                }
+            }
+
+            /// Creates a client which sends the requests by using the specified `Client`.
+            pub fn with_client(base: $crate::__private::Uri, client: $crate::client::Client) -> Self {
+                $Client {
+                    inner: $crate::client::TypedClient::with_client(base, client),
+                }
+            }
+
+            $(
+                $(#[$method_attr])*
+                pub fn $name(
+                    &self,
+                    $($arg: $ty,)*
+                    $($body: $body_ty)*
+                ) -> impl $crate::__private::Future<Item = $ret, Error = $crate::error::Error>
+                       + Send + 'static {
+                    self.inner.call::<_, $ret>(
+                        stringify!($verb),
+                        $path,
+                        &[$((stringify!($arg), &$arg as &dyn ::std::fmt::Display)),*],
+                        $crate::typed_client!(@body $($body)*),
+                    )
+                }
+            )*
+        }
+    };
+}
+
+/// An entry of the route table generated by `typed_client!`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteInfo {
+    /// The name of the method.
+    pub name: &'static str,
+    /// The HTTP method, in lower case (e.g. `get`).
+    pub method: &'static str,
+    /// The path, in the syntax of `path!`.
+    pub path: &'static str,
+}
+
+impl RouteInfo {
+    /// Returns the HTTP method.
+    ///
+    /// # Panics
+    /// This method panics if the method is not a valid one.
+    pub fn http_method(&self) -> Method {
+        parse_method(self.method).expect("invalid method")
+    }
+}
+
+fn parse_method(method: &str) -> Result<Method, Error> {
+    Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+        .map_err(|err| error::fail(err, StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+/// Renders the path in the syntax of `path!` with the specified arguments.
+///
+/// Each parameter (`<T>`, `<..T>` and `*name`) is replaced with the next
+/// argument, which is percent-encoded except the slashes in the catch-all
+/// ones. The trailing `...` is removed. The rest of the arguments are
+/// appended as the query string.
+pub fn render_path(path: &str, args: &[(&str, &dyn fmt::Display)]) -> Result<String, Error> {
+    let mut args = args.iter();
+    let mut rendered = String::new();
+    for segment in path.split('/').skip(1) {
+        if segment == "..." {
+            break;
+        }
+        rendered.push('/');
+        let is_param = segment.starts_with('<') && segment.ends_with('>');
+        let is_catch_all = segment.starts_with("<..") || segment.starts_with('*');
+        if !is_param && !is_catch_all {
+            rendered.push_str(segment);
+            continue;
+        }
+
+        let value = match args.next() {
+            Some((_, value)) => value.to_string(),
+            None => {
+                return Err(error::err_msg(
+                    format!("missing the argument for `{}' in {}", segment, path),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ));
+            }
+        };
+        if is_catch_all {
+            let encoded: Vec<_> = value
+                .split('/')
+                .map(|s| utf8_percent_encode(s, PATH_SEGMENT_ENCODE_SET).to_string())
+                .collect();
+            rendered.push_str(&encoded.join("/"));
+        } else {
+            rendered.extend(utf8_percent_encode(&value, PATH_SEGMENT_ENCODE_SET));
+        }
+    }
+    if rendered.is_empty() {
+        rendered.push('/');
+    }
+
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    let mut has_query = false;
+    for (name, value) in args {
+        query.append_pair(name, &value.to_string());
+        has_query = true;
+    }
+    if has_query {
+        rendered.push('?');
+        rendered.push_str(&query.finish());
+    }
+
+    Ok(rendered)
+}
+
+// ==== FromResponse ====
+
+/// A trait representing the conversion from the responses into the outputs
+/// of the typed clients.
+pub trait FromResponse: Sized + Send + 'static {
+    /// Converts the response into the output.
+    fn from_response(response: Response<Bytes>) -> Result<Self, Error>;
+}
+
+impl FromResponse for () {
+    fn from_response(_: Response<Bytes>) -> Result<Self, Error> {
+        Ok(())
+    }
+}
+
+impl FromResponse for Bytes {
+    fn from_response(response: Response<Bytes>) -> Result<Self, Error> {
+        Ok(response.into_body())
+    }
+}
+
+impl FromResponse for String {
+    fn from_response(response: Response<Bytes>) -> Result<Self, Error> {
+        String::from_utf8(response.into_body().to_vec())
+            .map_err(|err| error::fail(err, StatusCode::BAD_GATEWAY))
+    }
+}
+
+impl FromResponse for Response<Bytes> {
+    fn from_response(response: Response<Bytes>) -> Result<Self, Error> {
+        Ok(response)
+    }
+}
+
+/// The output of the typed clients which parses the response body as JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Json<T>(pub T);
+
+impl<T> FromResponse for Json<T>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    fn from_response(response: Response<Bytes>) -> Result<Self, Error> {
+        serde_json::from_slice(response.body())
+            .map(Json)
+            .map_err(|err| error::fail(err, StatusCode::BAD_GATEWAY))
+    }
+}
+
+// ==== UpstreamStatus ====
+
+/// An error returned from the typed clients when the server has returned
+/// a response whose status code is not successful.
+///
+/// This error is reported as `502 Bad Gateway`, and the original status
+/// can be retrieved with `Error::downcast_ref`.
+#[derive(Debug, Fail)]
+#[fail(display = "the upstream server returned an error status: {}", status)]
+pub struct UpstreamStatus {
+    status: StatusCode,
+    body: Bytes,
+}
+
+impl UpstreamStatus {
+    /// Returns the status code of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the body of the response.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+}
+
+impl HttpError for UpstreamStatus {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_GATEWAY
+    }
+}
+
+// ==== TypedClient ====
+
+/// The underlying client of the types generated by `typed_client!`.
+#[derive(Debug, Clone)]
+pub struct TypedClient {
+    base: Uri,
+    client: Option<Client>,
+}
+
+impl TypedClient {
+    /// Creates a `TypedClient` which uses the `Client` shared within the current thread.
+    pub fn new(base: Uri) -> Self {
+        TypedClient { base, client: None }
+    }
+
+    /// Creates a `TypedClient` which uses the specified `Client`.
+    pub fn with_client(base: Uri, client: Client) -> Self {
+        TypedClient {
+            base,
+            client: Some(client),
+        }
+    }
+
+    /// Returns the base URI.
+    pub fn base(&self) -> &Uri {
+        &self.base
+    }
+
+    /// Sends a request to the specified route and converts the response.
+    pub fn call<B, T>(
+        &self,
+        method: &str,
+        path: &str,
+        args: &[(&str, &dyn fmt::Display)],
+        body: Option<&B>,
+    ) -> impl Future<Item = T, Error = Error> + Send + 'static
+    where
+        B: Serialize + ?Sized,
+        T: FromResponse,
+    {
+        let request = match self.build_request(method, path, args, body) {
+            Ok(request) => request,
+            Err(err) => return future::Either::A(future::err(err)),
+        };
+        let client = self.client.clone().unwrap_or_else(super::shared);
+        future::Either::B(client.request(request).and_then(|response| {
+            let (parts, body) = response.into_parts();
+            body.concat2()
+                .map_err(upstream_error)
+                .and_then(move |chunk| {
+                    let body = chunk.into_bytes();
+                    if !parts.status.is_success() {
+                        return Err(UpstreamStatus {
+                            status: parts.status,
+                            body,
+                        }
+                        .into());
+                    }
+                    T::from_response(Response::from_parts(parts, body))
+                })
+        }))
+    }
+
+    fn build_request<B>(
+        &self,
+        method: &str,
+        path: &str,
+        args: &[(&str, &dyn fmt::Display)],
+        body: Option<&B>,
+    ) -> Result<Request<Body>, Error>
+    where
+        B: Serialize + ?Sized,
+    {
+        let path = render_path(path, args)?;
+        let base = self.base.to_string();
+        let uri: Uri = format!("{}{}", base.trim_end_matches('/'), path)
+            .parse()
+            .map_err(|err| error::fail(err, StatusCode::INTERNAL_SERVER_ERROR))?;
+
+        let mut request = Request::new(Body::empty());
+        *request.method_mut() = parse_method(method)?;
+        *request.uri_mut() = uri;
+        if let Some(body) = body {
+            let body = serde_json::to_vec(body)
+                .map_err(|err| error::fail(err, StatusCode::INTERNAL_SERVER_ERROR))?;
+            request.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("application/json"),
+            );
+            *request.body_mut() = Body::from(body);
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        serde::Deserialize,
+        std::{
+            io::{BufRead, BufReader, Read, Write},
+            net::TcpListener,
+            thread::{self, JoinHandle},
+        },
+        tokio::runtime::current_thread::Runtime,
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        id: u32,
+        name: String,
+    }
+
+    crate::typed_client! {
+        struct UsersClient {
+            fn get_user(id: u32) -> Json<User> = @get "/users/<u32>";
+            fn list_files(path: String, recursive: bool) -> String = @get "/files/<..PathBuf>";
+            fn create_user(=> user: User) -> () = @post "/users";
+        }
+    }
+
+    /// Accepts a connection, replies the specified response and returns the received request.
+    fn upstream(response: &'static [u8]) -> (Uri, JoinHandle<(Vec<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = vec![];
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                let line = line.trim_end().to_owned();
+                if line.starts_with("content-length: ") {
+                    content_length = line["content-length: ".len()..].parse().unwrap();
+                }
+                head.push(line);
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            (&stream).write_all(response).unwrap();
+            (head, String::from_utf8(body).unwrap())
+        });
+        (uri, handle)
+    }
+
+    #[test]
+    fn test_render_path() {
+        assert_eq!(render_path("/", &[]).unwrap(), "/");
+        assert_eq!(
+            render_path("/users/<u32>/posts", &[("id", &42)]).unwrap(),
+            "/users/42/posts"
+        );
+        assert_eq!(
+            render_path("/files/<..PathBuf>", &[("path", &"a b/c.txt")]).unwrap(),
+            "/files/a%20b/c.txt"
+        );
+        assert_eq!(
+            render_path("/users/<String>/...", &[("name", &"a/b")]).unwrap(),
+            "/users/a%2Fb"
+        );
+        assert_eq!(
+            render_path("/rates/<String>", &[("rate", &"100%")]).unwrap(),
+            "/rates/100%25"
+        );
+        assert_eq!(
+            render_path("/search", &[("q", &"a&b"), ("page", &2)]).unwrap(),
+            "/search?q=a%26b&page=2"
+        );
+        assert!(render_path("/users/<u32>", &[]).is_err());
+    }
+
+    #[test]
+    fn test_routes() {
+        assert_eq!(UsersClient::ROUTES.len(), 3);
+        assert_eq!(
+            UsersClient::ROUTES[2],
+            RouteInfo {
+                name: "create_user",
+                method: "post",
+                path: "/users",
+            }
+        );
+        assert_eq!(UsersClient::ROUTES[2].http_method(), Method::POST);
+    }
+
+    #[test]
+    fn test_call_json() {
+        let (uri, handle) = upstream(
+            b"HTTP/1.1 200 OK\r\ncontent-length: 26\r\n\r\n{\"id\":42,\"name\":\"alice\"}  ",
+        );
+        let client = UsersClient::new(uri);
+        let mut rt = Runtime::new().unwrap();
+        let Json(user) = rt.block_on(client.get_user(42)).unwrap();
+        assert_eq!(
+            user,
+            User {
+                id: 42,
+                name: "alice".into()
+            }
+        );
+
+        let (head, _) = handle.join().unwrap();
+        assert_eq!(head[0], "GET /users/42 HTTP/1.1");
+    }
+
+    #[test]
+    fn test_call_with_query() {
+        let (uri, handle) = upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nfiles");
+        let client = UsersClient::new(uri);
+        let mut rt = Runtime::new().unwrap();
+        let body = rt
+            .block_on(client.list_files("docs/a b".into(), true))
+            .unwrap();
+        assert_eq!(body, "files");
+
+        let (head, _) = handle.join().unwrap();
+        assert_eq!(head[0], "GET /files/docs/a%20b?recursive=true HTTP/1.1");
+    }
+
+    #[test]
+    fn test_call_with_body() {
+        let (uri, handle) = upstream(b"HTTP/1.1 204 No Content\r\n\r\n");
+        let client = UsersClient::new(uri);
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(client.create_user(User {
+            id: 1,
+            name: "bob".into(),
+        }))
+        .unwrap();
+
+        let (head, body) = handle.join().unwrap();
+        assert_eq!(head[0], "POST /users HTTP/1.1");
+        assert!(head.iter().any(|h| h == "content-type: application/json"));
+        assert_eq!(body, r#"{"id":1,"name":"bob"}"#);
+    }
+
+    #[test]
+    fn test_call_error_status() {
+        let (uri, handle) =
+            upstream(b"HTTP/1.1 404 Not Found\r\ncontent-length: 9\r\n\r\nnot found");
+        let client = UsersClient::new(uri);
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(client.get_user(1)).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_GATEWAY);
+        let status = err.downcast_ref::<UpstreamStatus>().unwrap();
+        assert_eq!(status.status(), StatusCode::NOT_FOUND);
+        assert_eq!(status.body(), "not found");
+        handle.join().unwrap();
+    }
+}
@@ -2,6 +2,6 @@ mod combine;
 mod func;
 mod hlist;
 
-pub(crate) use self::combine::Combine;
+pub(crate) use self::combine::{Combine, Flatten};
 pub(crate) use self::func::Func;
 pub(crate) use self::hlist::Tuple;
@@ -47,12 +47,62 @@ where
     }
 }
 
+pub trait FlattenList: HList {
+    type Out: HList;
+
+    fn flatten(self) -> Self::Out;
+}
+
+impl FlattenList for HNil {
+    type Out = HNil;
+
+    #[inline(always)]
+    fn flatten(self) -> Self::Out {
+        HNil
+    }
+}
+
+impl<H: Tuple, T: FlattenList> FlattenList for HCons<H, T>
+where
+    H::HList: CombineList<T::Out>,
+    HCons<H, T>: HList,
+{
+    type Out = <H::HList as CombineList<T::Out>>::Out;
+
+    #[inline(always)]
+    fn flatten(self) -> Self::Out {
+        self.head.hlist().combine(self.tail.flatten())
+    }
+}
+
+/// A tuple whose elements are all tuples, which can be concatenated into a tuple.
+pub trait Flatten: Tuple + sealed::SealedFlatten {
+    type Out: Tuple;
+
+    fn flatten(self) -> Self::Out;
+}
+
+impl<T: Tuple> Flatten for T
+where
+    T::HList: FlattenList,
+{
+    type Out = <<T::HList as FlattenList>::Out as HList>::Tuple;
+
+    fn flatten(self) -> Self::Out {
+        self.hlist().flatten().tuple()
+    }
+}
+
 mod sealed {
-    use super::{CombineList, Tuple};
+    use super::{CombineList, FlattenList, Tuple};
 
     pub trait Sealed<T> {}
 
     impl<H: Tuple, T: Tuple> Sealed<T> for H where H::HList: CombineList<T::HList> {}
+
+    pub trait SealedFlatten {}
+
+    impl<T: Tuple> SealedFlatten for T where T::HList: FlattenList {}
 }
 
 #[cfg(test)]
@@ -89,6 +139,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn case6_large() {
+        // The standard library does not implement `PartialEq` for such large tuples.
+        let (t0, .., t19) = combine(
+            (0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11),
+            ("12", 13, 14, 15, 16, 17, 18, 19),
+        );
+        assert_eq!(t0, 0);
+        assert_eq!(t19, 19);
+    }
+
+    #[test]
+    fn flatten_nested() {
+        assert_eq!(Flatten::flatten(((), ())), ());
+        assert_eq!(
+            Flatten::flatten((("a", "b"), (), (10,), ((20, 30),))),
+            ("a", "b", 10, (20, 30))
+        );
+    }
+
     #[test]
     fn case5_nested() {
         assert_eq!(
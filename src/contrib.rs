@@ -0,0 +1,19 @@
+//! Integrations with the third-party libraries.
+//!
+//! Each integration is enabled by the Cargo feature of the same name.
+//! The integrations with the connection pools (`diesel` and `redis`) also
+//! require the feature `r2d2`, and the feature `tokio-threadpool` is
+//! recommended for checking out the connections within the blocking section.
+
+#[cfg(feature = "r2d2")]
+mod pool;
+
+#[cfg(all(feature = "diesel", feature = "r2d2"))]
+pub mod diesel;
+#[cfg(all(feature = "redis", feature = "r2d2"))]
+pub mod redis;
+#[cfg(feature = "s3")]
+pub mod s3;
+
+#[cfg(feature = "r2d2")]
+pub use self::pool::{Connection, ConnectionAction, PoolConfig};
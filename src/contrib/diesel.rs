@@ -0,0 +1,164 @@
+//! Integration with Diesel.
+//!
+//! The connection pool is attached to the application as a state, and the
+//! endpoint `connection` checks out a connection from it for each request.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use finchers::prelude::*;
+//! # use finchers::contrib::{diesel, PoolConfig};
+//! # use finchers::endpoints::health::{self, Registry};
+//! # use finchers::endpoint::syntax::path;
+//! use ::diesel::sqlite::SqliteConnection;
+//! # fn find_post(_: &SqliteConnection, id: i32) -> Result<String, finchers::error::Error> {
+//! #     Ok(id.to_string())
+//! # }
+//! # fn main() -> Result<(), failure::Error> {
+//!
+//! let pool = diesel::pool::<SqliteConnection>("app.db", &PoolConfig::default())?;
+//!
+//! let registry = Registry::new();
+//! diesel::register_health_check(&registry, "database", &pool);
+//!
+//! let get_post = path!(@get "/posts/<i32>")
+//!     .and(diesel::connection::<SqliteConnection>())
+//!     .and_then(|id: i32, conn: diesel::PooledConnection<SqliteConnection>| {
+//!         find_post(&conn, id)
+//!     });
+//!
+//! let endpoint = endpoint::scope("/", get_post)
+//!     .with_state(pool)
+//!     .or(path!(@get "/readyz").and(health::readiness(&registry)));
+//! # drop(endpoint);
+//! # Ok(())
+//! # }
+//! ```
+
+use {
+    super::pool::{self, Connection, PoolConfig},
+    crate::{
+        endpoint::{self, ExtractState},
+        endpoints::health::Registry,
+    },
+    ::diesel::{
+        connection::TransactionManager, //
+        r2d2::ConnectionManager,
+        result::QueryResult,
+        Connection as DieselConnection,
+    },
+    std::{
+        fmt,
+        sync::{Arc, Mutex, MutexGuard},
+    },
+};
+
+/// A connection pool of Diesel.
+pub type Pool<C> = r2d2::Pool<ConnectionManager<C>>;
+
+/// A connection checked out from `Pool<C>`.
+pub type PooledConnection<C> = r2d2::PooledConnection<ConnectionManager<C>>;
+
+/// Creates a connection pool to the specified database.
+pub fn pool<C>(
+    database_url: impl Into<String>,
+    config: &PoolConfig,
+) -> Result<Pool<C>, failure::Error>
+where
+    C: DieselConnection + 'static,
+{
+    Ok(config.build(ConnectionManager::new(database_url))?)
+}
+
+/// Creates an endpoint which checks out a connection from the pool
+/// attached by `Scope::with_state`.
+///
+/// See also the documentation of `Connection`.
+pub fn connection<C>() -> Connection<ConnectionManager<C>>
+where
+    C: DieselConnection + 'static,
+{
+    Connection::new()
+}
+
+/// Registers a check of the database to the readiness endpoint.
+pub fn register_health_check<C>(registry: &Registry, name: impl Into<String>, pool: &Pool<C>)
+where
+    C: DieselConnection + 'static,
+{
+    pool::register_health_check(registry, name, pool)
+}
+
+/// A connection within the transaction started by `wrapper::transactional`.
+///
+/// The value is shared between its clones, and the transaction is committed
+/// or rolled back by the wrapper after the wrapped endpoint has completed.
+pub struct Transaction<C: DieselConnection + 'static> {
+    conn: Arc<Mutex<PooledConnection<C>>>,
+}
+
+impl<C> Clone for Transaction<C>
+where
+    C: DieselConnection + 'static,
+{
+    fn clone(&self) -> Self {
+        Transaction {
+            conn: self.conn.clone(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for Transaction<C>
+where
+    C: DieselConnection + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transaction").finish()
+    }
+}
+
+impl<C> Transaction<C>
+where
+    C: DieselConnection + 'static,
+{
+    /// Acquires the lock of the underlying connection.
+    pub fn connection(&self) -> MutexGuard<'_, PooledConnection<C>> {
+        // The connection is still usable for rolling back the transaction
+        // even if a handler has panicked while holding the lock.
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn begin(conn: PooledConnection<C>) -> QueryResult<Self> {
+        {
+            let conn: &C = &conn;
+            conn.transaction_manager().begin_transaction(conn)?;
+        }
+        Ok(Transaction {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub(crate) fn commit(&self) -> QueryResult<()> {
+        let guard = self.connection();
+        let conn: &C = &guard;
+        conn.transaction_manager().commit_transaction(conn)
+    }
+
+    pub(crate) fn rollback(&self) -> QueryResult<()> {
+        let guard = self.connection();
+        let conn: &C = &guard;
+        conn.transaction_manager().rollback_transaction(conn)
+    }
+}
+
+/// Creates an endpoint which extracts the transaction started by
+/// `wrapper::transactional`.
+///
+/// This endpoint reports `500 Internal Server Error` if the endpoint is
+/// not wrapped with `wrapper::transactional`.
+pub fn transaction<C>() -> ExtractState<Transaction<C>>
+where
+    C: DieselConnection + 'static,
+{
+    endpoint::state()
+}
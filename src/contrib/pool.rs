@@ -0,0 +1,277 @@
+//! The components shared by the integrations with the `r2d2` connection pools.
+
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        endpoints::health::Registry,
+        error::{self, Error},
+        util::blocking,
+    },
+    futures::{Async, Future, Poll},
+    http::StatusCode,
+    r2d2::{ManageConnection, Pool, PooledConnection},
+    serde::Deserialize,
+    std::{fmt, marker::PhantomData, time::Duration},
+};
+
+/// The configuration of a connection pool, which can be loaded from
+/// a configuration file with any Serde-compatible format.
+///
+/// All of the fields are optional.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    /// The maximum number of connections managed by the pool.
+    pub max_size: u32,
+
+    /// The minimum number of idle connections maintained by the pool.
+    ///
+    /// If omitted, the pool maintains `max_size` idle connections.
+    pub min_idle: Option<u32>,
+
+    /// The maximum duration for waiting to check out a connection, in seconds.
+    pub connection_timeout: u64,
+
+    /// The duration after which the idle connections are closed, in seconds.
+    pub idle_timeout: Option<u64>,
+
+    /// The lifetime of each connection, in seconds.
+    pub max_lifetime: Option<u64>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: 10,
+            min_idle: None,
+            connection_timeout: 30,
+            idle_timeout: Some(10 * 60),
+            max_lifetime: Some(30 * 60),
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Creates a builder of `r2d2::Pool` configured with this value.
+    pub fn builder<M>(&self) -> r2d2::Builder<M>
+    where
+        M: ManageConnection,
+    {
+        Pool::builder()
+            .max_size(self.max_size)
+            .min_idle(self.min_idle)
+            .connection_timeout(Duration::from_secs(self.connection_timeout))
+            .idle_timeout(self.idle_timeout.map(Duration::from_secs))
+            .max_lifetime(self.max_lifetime.map(Duration::from_secs))
+    }
+
+    /// Builds a connection pool with the specified connection manager.
+    ///
+    /// This method blocks until the initial connections have been established.
+    pub fn build<M>(&self, manager: M) -> Result<Pool<M>, r2d2::Error>
+    where
+        M: ManageConnection,
+    {
+        self.builder().build(manager)
+    }
+}
+
+/// An endpoint which checks out a connection from the pool attached
+/// to the enclosing scopes by `Scope::with_state`.
+///
+/// The checkout is run within the blocking section, so that the other tasks
+/// on the runtime can proceed while waiting for an available connection.
+/// This endpoint reports `500 Internal Server Error` if the pool is not
+/// attached, and `503 Service Unavailable` if no connection is available
+/// within the configured timeout.
+pub struct Connection<M> {
+    _marker: PhantomData<fn() -> M>,
+}
+
+impl<M> Connection<M> {
+    pub(super) fn new() -> Self {
+        Connection {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<M> Copy for Connection<M> {}
+
+impl<M> Clone for Connection<M> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M> fmt::Debug for Connection<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection").finish()
+    }
+}
+
+impl<M> IsEndpoint for Connection<M> {}
+
+impl<M, Bd> Endpoint<Bd> for Connection<M>
+where
+    M: ManageConnection,
+{
+    type Output = (PooledConnection<M>,);
+    type Action = ConnectionAction<M>;
+
+    fn action(&self) -> Self::Action {
+        ConnectionAction { pool: None }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct ConnectionAction<M: ManageConnection> {
+    pool: Option<Pool<M>>,
+}
+
+impl<M, Bd> EndpointAction<Bd> for ConnectionAction<M>
+where
+    M: ManageConnection,
+{
+    type Output = (PooledConnection<M>,);
+
+    fn preflight(
+        &mut self,
+        _: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        Ok(Preflight::Incomplete)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        if self.pool.is_none() {
+            let pool = cx.extensions().get::<Pool<M>>().cloned().ok_or_else(|| {
+                error::internal_server_error("the connection pool is not attached")
+            })?;
+            self.pool = Some(pool);
+        }
+        let pool = self.pool.as_ref().unwrap();
+        let conn = futures::try_ready!(blocking(|| pool.get())
+            .poll()
+            .map_err(|e| error::fail(e, StatusCode::SERVICE_UNAVAILABLE)));
+        Ok(Async::Ready((conn,)))
+    }
+}
+
+/// Registers a check which succeeds if a connection can be checked out from the pool.
+pub(super) fn register_health_check<M>(registry: &Registry, name: impl Into<String>, pool: &Pool<M>)
+where
+    M: ManageConnection,
+{
+    let pool = pool.clone();
+    registry.register(name, move || {
+        let pool = pool.clone();
+        blocking(move || pool.get().map(drop))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{endpoint::syntax, prelude::*, test},
+        std::sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    #[derive(Debug, Default)]
+    struct DummyState {
+        connected: AtomicUsize,
+        broken: AtomicBool,
+    }
+
+    #[derive(Debug, Default, Clone)]
+    struct DummyManager(Arc<DummyState>);
+
+    #[derive(Debug)]
+    struct DummyError;
+
+    impl fmt::Display for DummyError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("broken")
+        }
+    }
+
+    impl std::error::Error for DummyError {}
+
+    impl ManageConnection for DummyManager {
+        type Connection = usize;
+        type Error = DummyError;
+
+        fn connect(&self) -> Result<usize, DummyError> {
+            if self.0.broken.load(Ordering::SeqCst) {
+                return Err(DummyError);
+            }
+            Ok(self.0.connected.fetch_add(1, Ordering::SeqCst))
+        }
+
+        fn is_valid(&self, _: &mut usize) -> Result<(), DummyError> {
+            if self.0.broken.load(Ordering::SeqCst) {
+                return Err(DummyError);
+            }
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut usize) -> bool {
+            false
+        }
+    }
+
+    fn config() -> PoolConfig {
+        PoolConfig {
+            max_size: 1,
+            connection_timeout: 1,
+            ..PoolConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_connection() {
+        let manager = DummyManager::default();
+        let pool = config().build(manager.clone()).unwrap();
+
+        let endpoint = syntax::segment("conn")
+            .and(Connection::<DummyManager>::new())
+            .map(|conn: PooledConnection<DummyManager>| format!("conn {}", *conn));
+        let mut runner = test::runner(endpoint::scope("/", endpoint).with_state(pool));
+
+        runner.perform("/conn").unwrap().assert_body("conn 0");
+        runner.perform("/conn").unwrap().assert_body("conn 0");
+        assert_eq!(manager.0.connected.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_connection_without_pool() {
+        let endpoint = Connection::<DummyManager>::new().map(|_: PooledConnection<_>| "");
+        let mut runner = test::runner(endpoint);
+        runner.perform("/").unwrap().assert_status(500);
+    }
+
+    #[test]
+    fn test_health_check() {
+        let manager = DummyManager::default();
+        let pool = config().build(manager.clone()).unwrap();
+
+        let registry = Registry::new();
+        register_health_check(&registry, "dummy", &pool);
+        let mut runner = test::runner(crate::endpoints::health::readiness(&registry));
+
+        runner.perform("/").unwrap().assert_status(200);
+
+        manager.0.broken.store(true, Ordering::SeqCst);
+        runner.perform("/").unwrap().assert_status(503);
+    }
+}
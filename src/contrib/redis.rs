@@ -0,0 +1,96 @@
+//! Integration with Redis.
+//!
+//! The connection pool is attached to the application as a state, and the
+//! endpoint `connection` checks out a connection from it for each request.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use finchers::prelude::*;
+//! # use finchers::contrib::{redis, PoolConfig};
+//! # use finchers::endpoints::health::{self, Registry};
+//! # use finchers::endpoint::syntax::path;
+//! # fn main() -> Result<(), failure::Error> {
+//! let pool = redis::pool("redis://127.0.0.1/", &PoolConfig::default())?;
+//!
+//! let registry = Registry::new();
+//! redis::register_health_check(&registry, "redis", &pool);
+//!
+//! let visit = path!(@post "/visits")
+//!     .and(redis::connection())
+//!     .and_then(|mut conn: redis::PooledConnection| {
+//!         ::redis::cmd("INCR")
+//!             .arg("visits")
+//!             .query::<u64>(&mut *conn)
+//!             .map(|visits| visits.to_string())
+//!             .map_err(finchers::error::internal_server_error)
+//!     });
+//!
+//! let endpoint = endpoint::scope("/", visit)
+//!     .with_state(pool)
+//!     .or(path!(@get "/readyz").and(health::readiness(&registry)));
+//! # drop(endpoint);
+//! # Ok(())
+//! # }
+//! ```
+
+use {
+    super::pool::{self, Connection, PoolConfig},
+    crate::endpoints::health::Registry,
+    ::redis::{Client, RedisError},
+};
+
+/// A connection manager of `r2d2` which connects to a Redis server.
+#[derive(Debug, Clone)]
+pub struct ConnectionManager {
+    client: Client,
+}
+
+impl ConnectionManager {
+    /// Creates a new `ConnectionManager` from the specified client.
+    pub fn new(client: Client) -> Self {
+        ConnectionManager { client }
+    }
+}
+
+impl r2d2::ManageConnection for ConnectionManager {
+    type Connection = ::redis::Connection;
+    type Error = RedisError;
+
+    fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        self.client.get_connection()
+    }
+
+    fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        ::redis::cmd("PING").query(conn)
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        !conn.is_open()
+    }
+}
+
+/// A connection pool of Redis.
+pub type Pool = r2d2::Pool<ConnectionManager>;
+
+/// A connection checked out from `Pool`.
+pub type PooledConnection = r2d2::PooledConnection<ConnectionManager>;
+
+/// Creates a connection pool to the specified Redis server.
+pub fn pool(url: &str, config: &PoolConfig) -> Result<Pool, failure::Error> {
+    let client = Client::open(url)?;
+    Ok(config.build(ConnectionManager::new(client))?)
+}
+
+/// Creates an endpoint which checks out a connection from the pool
+/// attached by `Scope::with_state`.
+///
+/// See also the documentation of `Connection`.
+pub fn connection() -> Connection<ConnectionManager> {
+    Connection::new()
+}
+
+/// Registers a check of the Redis server to the readiness endpoint.
+pub fn register_health_check(registry: &Registry, name: impl Into<String>, pool: &Pool) {
+    pool::register_health_check(registry, name, pool)
+}
@@ -2,17 +2,30 @@
 
 mod boxed;
 pub mod ext;
+mod fallback;
+mod join;
+mod scope;
+mod split;
 pub mod syntax;
+pub mod wrapper;
 
 // re-exports
 pub use self::{
-    boxed::{EndpointObj, LocalEndpointObj},
-    ext::EndpointExt,
+    boxed::{boxed, boxed_local, EndpointObj, LocalEndpointObj, LocalRoute, Route},
+    ext::{or_all, EndpointExt, Extract},
+    fallback::{fallback, Fallback, FallbackAction, RouteMiss},
+    join::{join, Join, JoinAction},
+    scope::{scope, state, ExtractState, Scope, ScopeWithState},
+    split::{split, Split, SplitAction, SplitKey, SplitStats, Variant},
 };
+pub use crate::routes;
+
+pub(crate) use self::scope::StateOverrides;
 
 use {
     crate::{
         action::{
+            ActionContext,
             EndpointAction, //
             Oneshot,
             OneshotAction,
@@ -21,6 +34,7 @@ use {
         common::Tuple,
         error::Error,
     },
+    futures::{Future, IntoFuture, Poll},
     std::{rc::Rc, sync::Arc},
 };
 
@@ -212,7 +226,7 @@ mod unit {
 ///     .and(value(conn))
 ///     .and_then(|id: u32, conn: Conn| {
 ///         // ...
-/// #       drop(id);
+/// #       let _ = id;
 /// #       futures::future::ok::<_, finchers::util::Never>(conn)
 ///     });
 /// # endpoint
@@ -250,4 +264,96 @@ mod value {
     }
 }
 
+/// Create an endpoint which constructs a value asynchronously for each request.
+///
+/// The closure is called when the action of this endpoint is polled for the
+/// first time, that is, after all of the endpoints in the chain have been matched
+/// to the request. Therefore, the per-request resources (e.g. a database connection
+/// checked out from a pool) are not acquired for the requests rejected by the routing.
+/// The closure may also return a `Result`, since it implements `IntoFuture`.
+///
+/// The acquired value is passed to the downstream endpoints via `and` as usual.
+/// If the rest of the chain fails, or the request is cancelled before completing,
+/// the future and the acquired value are dropped at that point, so the resource
+/// should be released by its `Drop` implementation.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax;
+/// # use futures::future;
+/// # struct Pool;
+/// # struct Conn;
+/// # impl Pool {
+/// #     fn checkout(&self) -> impl futures::Future<Item = Conn, Error = finchers::error::Error> {
+/// #         future::ok(Conn)
+/// #     }
+/// # }
+/// # impl Conn {
+/// #     fn find_user(&self, id: u32) -> String { format!("user {}", id) }
+/// # }
+/// let pool = std::sync::Arc::new(Pool);
+///
+/// let endpoint = syntax::segment("users")
+///     .and(syntax::param::<u32>())
+///     .and(endpoint::lazy_async(move || pool.checkout()))
+///     .map(|id: u32, conn: Conn| conn.find_user(id));
+/// # let mut runner = finchers::test::runner(endpoint);
+/// # runner.perform("/users/42").unwrap().assert_body("user 42");
+/// ```
+#[inline]
+pub fn lazy_async<Bd, F, R>(
+    f: F,
+) -> impl Endpoint<
+    Bd,
+    Output = (R::Item,),
+    Action = self::lazy_async::LazyAsyncAction<F, R::Future>, // private
+>
+where
+    F: Fn() -> R + Clone,
+    R: IntoFuture,
+    R::Error: Into<Error>,
+{
+    endpoint(move || self::lazy_async::LazyAsyncAction {
+        f: Some(f.clone()),
+        future: None,
+    })
+}
+
+mod lazy_async {
+    use super::*;
+
+    // not a public API.
+    #[allow(missing_debug_implementations)]
+    pub struct LazyAsyncAction<F, Fut> {
+        pub(super) f: Option<F>,
+        pub(super) future: Option<Fut>,
+    }
+
+    impl<F, R, Bd> EndpointAction<Bd> for LazyAsyncAction<F, R::Future>
+    where
+        F: Fn() -> R,
+        R: IntoFuture,
+        R::Error: Into<Error>,
+    {
+        type Output = (R::Item,);
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            let (f, future) = (&mut self.f, &mut self.future);
+            cx.context_mut().set(|| {
+                if let Some(f) = f.take() {
+                    *future = Some(f().into_future());
+                }
+                future
+                    .as_mut()
+                    .expect("the action has already been polled")
+                    .poll()
+                    .map(|x| x.map(|item| (item,)))
+                    .map_err(Into::into)
+            })
+        }
+    }
+}
+
 // ==== EndpointAction ====
@@ -9,11 +9,16 @@ use {
         common::Tuple,
         endpoint::{Endpoint, IsEndpoint},
         error::Error,
+        output::{AnyBody, IntoResponse},
     },
-    futures::Poll,
-    std::fmt,
+    futures::{Async, Poll},
+    http::Response,
+    izanami_util::buf_stream::BufStream,
+    std::{error, fmt},
 };
 
+type BoxedError = Box<dyn error::Error + Send + Sync + 'static>;
+
 trait BoxedEndpoint<Bd> {
     type Output: Tuple;
 
@@ -206,3 +211,115 @@ where
         self.inner.poll_action(cx)
     }
 }
+
+// ==== Route ====
+
+/// A type-erased endpoint which returns an HTTP response.
+///
+/// Unlike `EndpointObj`, the output of the original endpoint is converted
+/// into `Response` before erasing its type, so the functions which construct
+/// the routes can be shared between modules with a short, nameable return type
+/// instead of `impl Endpoint<Bd, Output = ...>`.
+pub type Route<Bd, ResBody = AnyBody> = EndpointObj<Bd, (Response<ResBody>,)>;
+
+/// A type-erased endpoint which returns an HTTP response, without the thread safety.
+pub type LocalRoute<Bd, ResBody = AnyBody> = LocalEndpointObj<Bd, (Response<ResBody>,)>;
+
+/// Converts the specified endpoint into a `Route`.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::{self, syntax, Route};
+/// # use finchers::output::Json;
+/// fn users<Bd: 'static>() -> Route<Bd> {
+///     endpoint::boxed(syntax::segment("users").map(|| Json(vec!["alice", "bob"])))
+/// }
+///
+/// fn health<Bd: 'static>() -> Route<Bd> {
+///     endpoint::boxed(syntax::segment("health").map(|| "ok"))
+/// }
+///
+/// let endpoint = users().or_strict(health());
+/// # let mut runner = finchers::test::runner(endpoint);
+/// # runner.perform("/health").unwrap().assert_body("ok");
+/// ```
+pub fn boxed<Bd, E>(endpoint: E) -> Route<Bd>
+where
+    E: Endpoint<Bd> + Send + Sync + 'static,
+    E::Action: Send + 'static,
+    E::Output: IntoResponse,
+    <E::Output as IntoResponse>::Body: BufStream + Send + 'static,
+    <<E::Output as IntoResponse>::Body as BufStream>::Item: Send + 'static,
+    <<E::Output as IntoResponse>::Body as BufStream>::Error: Into<BoxedError>,
+{
+    EndpointObj::new(IntoRoute { endpoint })
+}
+
+/// Converts the specified endpoint into a `LocalRoute`.
+pub fn boxed_local<Bd, E>(endpoint: E) -> LocalRoute<Bd>
+where
+    E: Endpoint<Bd> + 'static,
+    E::Action: 'static,
+    E::Output: IntoResponse,
+    <E::Output as IntoResponse>::Body: BufStream + Send + 'static,
+    <<E::Output as IntoResponse>::Body as BufStream>::Item: Send + 'static,
+    <<E::Output as IntoResponse>::Body as BufStream>::Error: Into<BoxedError>,
+{
+    LocalEndpointObj::new(IntoRoute { endpoint })
+}
+
+struct IntoRoute<E> {
+    endpoint: E,
+}
+
+impl<E: IsEndpoint> IsEndpoint for IntoRoute<E> {}
+
+impl<E, Bd> Endpoint<Bd> for IntoRoute<E>
+where
+    E: Endpoint<Bd>,
+    E::Output: IntoResponse,
+    <E::Output as IntoResponse>::Body: BufStream + Send + 'static,
+    <<E::Output as IntoResponse>::Body as BufStream>::Item: Send + 'static,
+    <<E::Output as IntoResponse>::Body as BufStream>::Error: Into<BoxedError>,
+{
+    type Output = (Response<AnyBody>,);
+    type Action = IntoRouteAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        IntoRouteAction {
+            action: self.endpoint.action(),
+        }
+    }
+}
+
+struct IntoRouteAction<A> {
+    action: A,
+}
+
+impl<A, Bd> EndpointAction<Bd> for IntoRouteAction<A>
+where
+    A: EndpointAction<Bd>,
+    A::Output: IntoResponse,
+    <A::Output as IntoResponse>::Body: BufStream + Send + 'static,
+    <<A::Output as IntoResponse>::Body as BufStream>::Item: Send + 'static,
+    <<A::Output as IntoResponse>::Body as BufStream>::Error: Into<BoxedError>,
+{
+    type Output = (Response<AnyBody>,);
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let preflight = self.action.preflight(cx)?;
+        Ok(preflight.map(|output| (output.into_response(cx.request()).map(AnyBody::new),)))
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        let output = futures::try_ready!(self.action.poll_action(cx));
+        Ok(Async::Ready((output
+            .into_response(cx.request())
+            .map(AnyBody::new),)))
+    }
+}
@@ -2,24 +2,39 @@
 
 mod and;
 mod and_then;
+mod extract;
+mod flatten;
 mod map;
 mod map_err;
+mod optimize;
+mod optional;
 mod or;
+mod or_all;
 mod or_strict;
 mod recover;
 
 pub use self::{
     and::And, //
     and_then::AndThen,
+    extract::{Extract, Extracted},
+    flatten::Flatten,
     map::Map,
     map_err::MapErr,
+    optimize::{
+        LiteralPattern, LiteralRoute, LiteralRoutes, Optimized, OptimizedAction, OrRouteAction,
+        RouteDispatch, RoutePattern,
+    },
+    optional::{Optional, OrDefault},
     or::Or,
+    or_all::{or_all, OrAll},
     or_strict::OrStrict,
     recover::Recover,
 };
 
+pub use finchers_macros::Extract;
+
 use {
-    super::IsEndpoint, //
+    super::{scope::Scope, syntax::UnsupportedApiVersion, wrapper::Wrapper, IsEndpoint},
     crate::error::{Error, HttpError},
 };
 
@@ -40,6 +55,10 @@ pub trait EndpointExt: IsEndpoint + Sized {
     ///
     /// The returned future from this endpoint contains the one returned
     /// from either `self` or `e` matched "better" to the input.
+    ///
+    /// The output of the chain of `or`s is a nested `Either` (e.g.
+    /// `Either<Either<T1, T2>, T3>`), which can be converted into the flat
+    /// variants in `util::either` (e.g. `Either3<T1, T2, T3>`) by `From`.
     fn or<E>(self, other: E) -> Or<Self, E> {
         Or {
             e1: self,
@@ -69,6 +88,47 @@ pub trait EndpointExt: IsEndpoint + Sized {
         Map { endpoint: self, f }
     }
 
+    /// Create an endpoint which converts the output of `self` into a value of `T`.
+    ///
+    /// It is useful for passing many extracted values to the handler by name,
+    /// instead of as a long list of the arguments. See the documentation of
+    /// `Extract` for details.
+    fn extract<T>(self) -> Extracted<Self, T> {
+        Extracted {
+            endpoint: self,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Create an endpoint which concatenates the tuples in the output of `self`.
+    ///
+    /// Each element of the output must be a tuple, e.g. an output
+    /// `((a, b), (), (c,))` is flattened into `(a, b, c)`. It is useful for
+    /// splicing the grouped outputs of the sub-endpoints into the arguments
+    /// of the handler, without regrouping them in the closures.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use finchers::prelude::*;
+    /// # use finchers::endpoint::syntax;
+    /// // A reusable endpoint which groups its outputs into a tuple.
+    /// let pagination = syntax::param::<u32>()
+    ///     .and(syntax::param::<u32>())
+    ///     .map(|page: u32, per_page: u32| (page, per_page));
+    ///
+    /// let endpoint = syntax::segment("items")
+    ///     .and(pagination)
+    ///     .flatten()
+    ///     .map(|page: u32, per_page: u32| format!("{}..{}", page * per_page, (page + 1) * per_page));
+    ///
+    /// # let mut runner = finchers::test::runner(endpoint);
+    /// # runner.perform("/items/2/10").unwrap().assert_body("20..30");
+    /// ```
+    fn flatten(self) -> Flatten<Self> {
+        Flatten { endpoint: self }
+    }
+
     #[allow(missing_docs)]
     fn and_then<F>(self, f: F) -> AndThen<Self, F> {
         AndThen { endpoint: self, f }
@@ -83,6 +143,83 @@ pub trait EndpointExt: IsEndpoint + Sized {
     fn recover<F>(self, f: F) -> Recover<Self, F> {
         Recover { endpoint: self, f }
     }
+
+    /// Create an endpoint which returns `None` instead of being rejected
+    /// if `self` is not matched to the request.
+    ///
+    /// The rejection is regarded as "not matched" if its status code is
+    /// `404 Not Found`, and no path segments are consumed in that case.
+    /// The other errors, such as `400 Bad Request` from the malformed
+    /// input, are not recovered.
+    fn optional(self) -> Optional<Self> {
+        Optional { endpoint: self }
+    }
+
+    /// Create an endpoint which returns `Default::default()` instead of being
+    /// rejected if `self` is not matched to the request.
+    ///
+    /// See the documentation of `optional` for the rejections to be recovered.
+    fn or_default(self) -> OrDefault<Self> {
+        OrDefault { endpoint: self }
+    }
+
+    /// Mounts `self` under the specified prefix.
+    ///
+    /// This method is equivalent to `endpoint::scope(prefix, self)`.
+    fn nest(self, prefix: &str) -> Scope<Self> {
+        super::scope(prefix, self)
+    }
+
+    /// Create an endpoint which dispatches the requests to the routes in `self`
+    /// by a precomputed table, instead of trying each branch of the `or` chain.
+    ///
+    /// This method is available for the `or` chains which consist only of
+    /// the literal segments and the verbs (see `LiteralPattern`), optionally
+    /// followed by `map` or `and_then`. The routes are indexed by their
+    /// segments at this call, and each request is dispatched to the route
+    /// chosen in the same way as `or` (the longest one, and the former one
+    /// among the equal ones). If no route is found in the table, the request
+    /// is applied to the original chain, so that the same error is reported.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use finchers::prelude::*;
+    /// # use finchers::endpoint::syntax::{self, verb};
+    /// # use finchers::test;
+    /// let endpoint = verb::get()
+    ///     .and(syntax::segment("users"))
+    ///     .and(syntax::eos())
+    ///     .map(|| "list users")
+    ///     .or(verb::post()
+    ///         .and(syntax::segment("users"))
+    ///         .and(syntax::eos())
+    ///         .map(|| "create user"))
+    ///     .or(verb::get()
+    ///         .and(syntax::segment("health"))
+    ///         .map(|| "ok"))
+    ///     .optimize();
+    ///
+    /// let mut runner = test::runner(endpoint);
+    /// runner.perform("/users").unwrap().assert_body("list users");
+    /// runner.perform(http::Request::post("/users")).unwrap().assert_body("create user");
+    /// runner.perform("/health/live").unwrap().assert_body("ok");
+    /// runner.perform("/posts").unwrap().assert_status(404);
+    /// ```
+    fn optimize(self) -> Optimized<Self>
+    where
+        Self: LiteralRoutes,
+    {
+        Optimized::new(self)
+    }
+
+    /// Wraps `self` with the specified `Wrapper`.
+    fn wrap<W>(self, wrapper: W) -> W::Endpoint
+    where
+        W: Wrapper<Self>,
+    {
+        wrapper.wrap(self)
+    }
 }
 
 impl<E: IsEndpoint> EndpointExt for E {}
@@ -90,8 +227,14 @@ impl<E: IsEndpoint> EndpointExt for E {}
 /// An `HttpError` indicating that the endpoint could not determine the route.
 ///
 /// The value of this error is typically thrown from `Or` or `OrStrict`.
-#[derive(Debug, failure::Fail)]
-#[fail(display = "not matched")]
+///
+/// This error is reported as `404 Not Found` in general. If one of the
+/// endpoints has been rejected with `415 Unsupported Media Type` and the other
+/// one has not been matched, it is reported as `415 Unsupported Media Type`
+/// instead, since the request has reached the right route with the wrong
+/// content type. Likewise, `UnsupportedApiVersion` takes precedence over
+/// `404 Not Found`.
+#[derive(Debug)]
 pub struct NotMatched {
     /// The error value returned from the first endpoint.
     pub left: Error,
@@ -102,8 +245,80 @@ pub struct NotMatched {
     _priv: (),
 }
 
+impl NotMatched {
+    /// Returns the errors of the *near-miss* routes, which have been rejected
+    /// for a reason other than `404 Not Found` (e.g. the wrong method, the wrong
+    /// content type or a missing header).
+    ///
+    /// The nested `NotMatched`s are flattened.
+    pub fn candidates(&self) -> Vec<&Error> {
+        let mut candidates = vec![];
+        self.collect_candidates(&mut candidates);
+        candidates
+    }
+
+    fn collect_candidates<'a>(&'a self, candidates: &mut Vec<&'a Error>) {
+        for err in &[&self.left, &self.right] {
+            match err.downcast_ref::<NotMatched>() {
+                Some(not_matched) => not_matched.collect_candidates(candidates),
+                None if err.status_code() != http::StatusCode::NOT_FOUND => candidates.push(err),
+                None => {}
+            }
+        }
+    }
+
+    /// Returns the error to be reported instead of `404 Not Found`.
+    fn reported_error(&self) -> Option<&Error> {
+        use http::StatusCode;
+        let (left, right) = (self.left.status_code(), self.right.status_code());
+        match (left, right) {
+            (StatusCode::UNSUPPORTED_MEDIA_TYPE, StatusCode::NOT_FOUND)
+            | (StatusCode::UNSUPPORTED_MEDIA_TYPE, StatusCode::UNSUPPORTED_MEDIA_TYPE) => {
+                return Some(&self.left);
+            }
+            (StatusCode::NOT_FOUND, StatusCode::UNSUPPORTED_MEDIA_TYPE) => {
+                return Some(&self.right)
+            }
+            _ => {}
+        }
+
+        match (
+            is_unsupported_api_version(&self.left),
+            is_unsupported_api_version(&self.right),
+        ) {
+            (true, true) => Some(&self.left),
+            (true, false) if right == StatusCode::NOT_FOUND => Some(&self.left),
+            (false, true) if left == StatusCode::NOT_FOUND => Some(&self.right),
+            _ => None,
+        }
+    }
+}
+
+fn is_unsupported_api_version(err: &Error) -> bool {
+    match err.downcast_ref::<NotMatched>() {
+        Some(not_matched) => not_matched
+            .reported_error()
+            .map_or(false, is_unsupported_api_version),
+        None => err.is::<UnsupportedApiVersion>(),
+    }
+}
+
+impl std::fmt::Display for NotMatched {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.reported_error() {
+            Some(err) => std::fmt::Display::fmt(err, f),
+            None => f.write_str("not matched"),
+        }
+    }
+}
+
+impl failure::Fail for NotMatched {}
+
 impl HttpError for NotMatched {
     fn status_code(&self) -> http::StatusCode {
-        http::StatusCode::NOT_FOUND
+        match self.reported_error() {
+            Some(err) => err.status_code(),
+            None => http::StatusCode::NOT_FOUND,
+        }
     }
 }
@@ -11,6 +11,7 @@ use {
         error::Error,
     },
     futures::{Async, Poll},
+    http::StatusCode,
 };
 
 #[allow(missing_docs)]
@@ -74,7 +75,23 @@ where
         &mut self,
         cx: &mut PreflightContext<'_>,
     ) -> Result<Preflight<Self::Output>, Error> {
-        let x1 = self.f1.preflight(cx)?;
+        let x1 = match self.f1.preflight(cx) {
+            Ok(x1) => x1,
+            Err(err) => {
+                // The method is checked before the path in the verb-first routes
+                // such as `verb::get().and(path)`, so `405 Method Not Allowed`
+                // is reported only if the rest of the route does not reject
+                // the request with `404 Not Found`.
+                if err.status_code() == StatusCode::METHOD_NOT_ALLOWED {
+                    if let Err(err2) = self.f2.preflight(cx) {
+                        if err2.status_code() == StatusCode::NOT_FOUND {
+                            return Err(err2);
+                        }
+                    }
+                }
+                return Err(err);
+            }
+        };
         let x2 = self.f2.preflight(cx)?;
         if x1.is_completed() && x2.is_completed() {
             let out = self.take_item().expect("the value shoud be ready.");
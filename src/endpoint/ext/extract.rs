@@ -0,0 +1,123 @@
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        common::Tuple,
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+    },
+    futures::Poll,
+    std::{fmt, marker::PhantomData},
+};
+
+/// A trait representing the types constructed from the output of an endpoint.
+///
+/// This trait is typically implemented by `#[derive(Extract)]`, which assigns
+/// the elements of the output to the fields in the order of their declarations.
+/// The types of the fields are checked against the output at compile time.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::{syntax, Extract};
+/// #[derive(Extract)]
+/// struct SearchParams {
+///     user_id: u32,
+///     repo: String,
+///     page: u32,
+///     per_page: u32,
+/// }
+///
+/// let endpoint = syntax::segment("users")
+///     .and(syntax::param::<u32>())
+///     .and(syntax::param::<String>())
+///     .and(syntax::param::<u32>())
+///     .and(syntax::param::<u32>())
+///     .extract::<SearchParams>()
+///     .map(|params: SearchParams| {
+///         format!("{}/{}: page {} ({} items)", params.user_id, params.repo, params.page, params.per_page)
+///     });
+/// # let mut runner = finchers::test::runner(endpoint);
+/// # runner.perform("/users/42/finchers/3/20").unwrap().assert_body("42/finchers: page 3 (20 items)");
+/// ```
+pub trait Extract<Args: Tuple>: Sized {
+    /// Constructs the value from the output of an endpoint.
+    fn extract(args: Args) -> Self;
+}
+
+#[allow(missing_docs)]
+pub struct Extracted<E, T> {
+    pub(super) endpoint: E,
+    pub(super) _marker: PhantomData<fn() -> T>,
+}
+
+impl<E: fmt::Debug, T> fmt::Debug for Extracted<E, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extracted")
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+impl<E: Clone, T> Clone for Extracted<E, T> {
+    fn clone(&self) -> Self {
+        Extracted {
+            endpoint: self.endpoint.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<E: Copy, T> Copy for Extracted<E, T> {}
+
+impl<E: IsEndpoint, T> IsEndpoint for Extracted<E, T> {}
+
+impl<E, T, Bd> Endpoint<Bd> for Extracted<E, T>
+where
+    E: Endpoint<Bd>,
+    T: Extract<E::Output>,
+{
+    type Output = (T,);
+    type Action = ExtractedAction<E::Action, T>;
+
+    fn action(&self) -> Self::Action {
+        ExtractedAction {
+            action: self.endpoint.action(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[allow(missing_debug_implementations)]
+pub struct ExtractedAction<A, T> {
+    action: A,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<A, T, Bd> EndpointAction<Bd> for ExtractedAction<A, T>
+where
+    A: EndpointAction<Bd>,
+    T: Extract<A::Output>,
+{
+    type Output = (T,);
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        self.action
+            .preflight(cx)
+            .map(|x| x.map(|args| (T::extract(args),)))
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        self.action
+            .poll_action(cx)
+            .map(|x| x.map(|args| (T::extract(args),)))
+    }
+}
@@ -0,0 +1,65 @@
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        common::Flatten as FlattenTuple,
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+    },
+    futures::Poll,
+};
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct Flatten<E> {
+    pub(super) endpoint: E,
+}
+
+impl<E: IsEndpoint> IsEndpoint for Flatten<E> {}
+
+impl<E, Bd> Endpoint<Bd> for Flatten<E>
+where
+    E: Endpoint<Bd>,
+    E::Output: FlattenTuple,
+{
+    type Output = <E::Output as FlattenTuple>::Out;
+    type Action = FlattenAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        FlattenAction {
+            action: self.endpoint.action(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FlattenAction<A> {
+    action: A,
+}
+
+impl<A, Bd> EndpointAction<Bd> for FlattenAction<A>
+where
+    A: EndpointAction<Bd>,
+    A::Output: FlattenTuple,
+{
+    type Output = <A::Output as FlattenTuple>::Out;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        self.action
+            .preflight(cx)
+            .map(|x| x.map(FlattenTuple::flatten))
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        self.action
+            .poll_action(cx)
+            .map(|x| x.map(FlattenTuple::flatten))
+    }
+}
@@ -0,0 +1,448 @@
+use {
+    super::{And, AndThen, Map, Or},
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{syntax::verb::Verbs, Endpoint, IsEndpoint},
+        error::Error,
+    },
+    either::Either,
+    futures::Poll,
+    http::Method,
+    smallvec::SmallVec,
+    std::{collections::HashMap, fmt, sync::Arc},
+};
+
+// ==== RoutePattern ====
+
+/// The condition of a route which consists only of the literal segments
+/// and the HTTP methods.
+#[derive(Debug, Clone, Default)]
+pub struct RoutePattern {
+    segments: Vec<String>,
+    verbs: Vec<Verbs>,
+    methods: Vec<Method>,
+    exact: bool,
+    unreachable: bool,
+}
+
+impl RoutePattern {
+    pub(crate) fn push_segment(&mut self, encoded: &str) {
+        if self.exact {
+            // The segments after `eos()` never match.
+            self.unreachable = true;
+        }
+        self.segments.push(encoded.to_owned());
+    }
+
+    pub(crate) fn restrict_verbs(&mut self, verbs: Verbs) {
+        self.verbs.push(verbs);
+    }
+
+    pub(crate) fn restrict_method(&mut self, method: Method) {
+        self.methods.push(method);
+    }
+
+    pub(crate) fn set_exact(&mut self) {
+        self.exact = true;
+    }
+}
+
+/// A trait representing the endpoints whose matching is determined only
+/// by the literal path segments and the HTTP methods, such as
+/// `syntax::segment`, `syntax::eos` and the endpoints in `syntax::verb`.
+///
+/// This trait is also implemented by `And`s of such endpoints and by `Map`s
+/// and `AndThen`s of them.
+pub trait LiteralPattern: IsEndpoint {
+    /// Appends the condition of this endpoint to `pattern`.
+    fn literal_pattern(&self, pattern: &mut RoutePattern);
+}
+
+impl<E1, E2> LiteralPattern for And<E1, E2>
+where
+    E1: LiteralPattern,
+    E2: LiteralPattern,
+{
+    fn literal_pattern(&self, pattern: &mut RoutePattern) {
+        self.e1.literal_pattern(pattern);
+        self.e2.literal_pattern(pattern);
+    }
+}
+
+impl<E, F> LiteralPattern for Map<E, F>
+where
+    E: LiteralPattern,
+{
+    fn literal_pattern(&self, pattern: &mut RoutePattern) {
+        self.endpoint.literal_pattern(pattern);
+    }
+}
+
+impl<E, F> LiteralPattern for AndThen<E, F>
+where
+    E: LiteralPattern,
+{
+    fn literal_pattern(&self, pattern: &mut RoutePattern) {
+        self.endpoint.literal_pattern(pattern);
+    }
+}
+
+// ==== LiteralRoutes ====
+
+/// A route collected by `LiteralRoutes`.
+#[derive(Debug, Clone)]
+pub struct LiteralRoute {
+    pattern: RoutePattern,
+    branch: Box<[bool]>,
+}
+
+/// A trait representing the `or` chains which consist only of the endpoints
+/// implementing `LiteralPattern`.
+pub trait LiteralRoutes: IsEndpoint {
+    /// Appends the routes in this endpoint to `routes`, in the order of
+    /// the precedence in the `or` chain.
+    ///
+    /// `branch` is the sequence of the branches of `Or`s to reach this
+    /// endpoint, where `false` means the left one.
+    fn literal_routes(&self, branch: &mut Vec<bool>, routes: &mut Vec<LiteralRoute>);
+}
+
+impl<E: LiteralPattern> LiteralRoutes for E {
+    fn literal_routes(&self, branch: &mut Vec<bool>, routes: &mut Vec<LiteralRoute>) {
+        let mut pattern = RoutePattern::default();
+        self.literal_pattern(&mut pattern);
+        routes.push(LiteralRoute {
+            pattern,
+            branch: branch.clone().into(),
+        });
+    }
+}
+
+impl<E1, E2> LiteralRoutes for Or<E1, E2>
+where
+    E1: LiteralRoutes,
+    E2: LiteralRoutes,
+{
+    fn literal_routes(&self, branch: &mut Vec<bool>, routes: &mut Vec<LiteralRoute>) {
+        branch.push(false);
+        self.e1.literal_routes(branch, routes);
+        branch.pop();
+        branch.push(true);
+        self.e2.literal_routes(branch, routes);
+        branch.pop();
+    }
+}
+
+/// A trait for creating the action of a route chosen by `Optimized`.
+pub trait RouteDispatch<Bd>: Endpoint<Bd> + LiteralRoutes {
+    /// The type of action for a single route.
+    type RouteAction: EndpointAction<Bd, Output = Self::Output>;
+
+    /// Creates the action of the route reached by following `branch`.
+    fn route_action(&self, branch: &mut std::slice::Iter<'_, bool>) -> Self::RouteAction;
+}
+
+impl<E, Bd> RouteDispatch<Bd> for E
+where
+    E: Endpoint<Bd> + LiteralPattern,
+{
+    type RouteAction = E::Action;
+
+    fn route_action(&self, _: &mut std::slice::Iter<'_, bool>) -> Self::RouteAction {
+        self.action()
+    }
+}
+
+impl<E1, E2, T1, T2, Bd> RouteDispatch<Bd> for Or<E1, E2>
+where
+    E1: RouteDispatch<Bd, Output = (T1,)>,
+    E2: RouteDispatch<Bd, Output = (T2,)>,
+{
+    type RouteAction = OrRouteAction<E1::RouteAction, E2::RouteAction>;
+
+    fn route_action(&self, branch: &mut std::slice::Iter<'_, bool>) -> Self::RouteAction {
+        match branch.next() {
+            Some(false) => OrRouteAction(Either::Left(self.e1.route_action(branch))),
+            Some(true) => OrRouteAction(Either::Right(self.e2.route_action(branch))),
+            None => panic!("unexpected condition"),
+        }
+    }
+}
+
+#[allow(missing_debug_implementations)]
+pub struct OrRouteAction<L, R>(Either<L, R>);
+
+impl<L, R, T1, T2, Bd> EndpointAction<Bd> for OrRouteAction<L, R>
+where
+    L: EndpointAction<Bd, Output = (T1,)>,
+    R: EndpointAction<Bd, Output = (T2,)>,
+{
+    type Output = (Either<T1, T2>,);
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        match self.0 {
+            Either::Left(ref mut action) => Ok(action
+                .preflight(cx)?
+                .map(|(output,)| (Either::Left(output),))),
+            Either::Right(ref mut action) => Ok(action
+                .preflight(cx)?
+                .map(|(output,)| (Either::Right(output),))),
+        }
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        match self.0 {
+            Either::Left(ref mut action) => action
+                .poll_action(cx)
+                .map(|x| x.map(|(output,)| (Either::Left(output),))),
+            Either::Right(ref mut action) => action
+                .poll_action(cx)
+                .map(|x| x.map(|(output,)| (Either::Right(output),))),
+        }
+    }
+}
+
+// ==== RouteTable ====
+
+#[derive(Debug)]
+struct Entry {
+    verbs: Vec<Verbs>,
+    methods: Vec<Method>,
+    exact: bool,
+    branch: Box<[bool]>,
+}
+
+/// The routes indexed by the joined path segments.
+#[derive(Debug, Default)]
+struct RouteTable {
+    routes: HashMap<String, Vec<Entry>>,
+}
+
+impl RouteTable {
+    fn new(routes: Vec<LiteralRoute>) -> Self {
+        let mut table = RouteTable::default();
+        for route in routes {
+            let LiteralRoute { pattern, branch } = route;
+            if pattern.unreachable {
+                continue;
+            }
+            table
+                .routes
+                .entry(pattern.segments.join("/"))
+                .or_default()
+                .push(Entry {
+                    verbs: pattern.verbs,
+                    methods: pattern.methods,
+                    exact: pattern.exact,
+                    branch,
+                });
+        }
+        table
+    }
+
+    /// Returns the branch of the route chosen by the `or` chain.
+    ///
+    /// As with `Or`, the route which consumes the larger number of segments
+    /// is preferred, and the former one is chosen if they are equal.
+    fn lookup(&self, cx: &PreflightContext<'_>) -> Option<&[bool]> {
+        let path = cx.uri().path();
+        let base = path.as_ptr() as usize;
+        let mut start = None;
+        let mut ends = SmallVec::<[usize; 16]>::new();
+        let mut probe = cx.clone();
+        for segment in probe.cursor() {
+            let offset = segment.as_bytes().as_ptr() as usize - base;
+            start.get_or_insert(offset);
+            ends.push(offset + segment.as_bytes().len());
+        }
+
+        let method = cx.method();
+        (0..=ends.len()).rev().find_map(|len| {
+            let key = match (start, len) {
+                (Some(start), len) if len > 0 => &path[start..ends[len - 1]],
+                _ => "",
+            };
+            self.routes.get(key)?.iter().find_map(|entry| {
+                if (entry.exact && len < ends.len())
+                    || !entry.verbs.iter().all(|verbs| verbs.contains(method))
+                    || !entry.methods.iter().all(|m| m == method)
+                {
+                    return None;
+                }
+                Some(&*entry.branch)
+            })
+        })
+    }
+}
+
+// ==== Optimized ====
+
+/// An endpoint created by `EndpointExt::optimize`.
+pub struct Optimized<E> {
+    inner: Arc<Inner<E>>,
+}
+
+struct Inner<E> {
+    endpoint: E,
+    table: RouteTable,
+}
+
+impl<E> Optimized<E>
+where
+    E: LiteralRoutes,
+{
+    pub(super) fn new(endpoint: E) -> Self {
+        let mut routes = vec![];
+        endpoint.literal_routes(&mut vec![], &mut routes);
+        Optimized {
+            inner: Arc::new(Inner {
+                endpoint,
+                table: RouteTable::new(routes),
+            }),
+        }
+    }
+}
+
+impl<E> Clone for Optimized<E> {
+    fn clone(&self) -> Self {
+        Optimized {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<E: fmt::Debug> fmt::Debug for Optimized<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Optimized")
+            .field("endpoint", &self.inner.endpoint)
+            .finish()
+    }
+}
+
+impl<E: IsEndpoint> IsEndpoint for Optimized<E> {}
+
+impl<E, Bd> Endpoint<Bd> for Optimized<E>
+where
+    E: RouteDispatch<Bd>,
+{
+    type Output = E::Output;
+    type Action = OptimizedAction<E, Bd>;
+
+    fn action(&self) -> Self::Action {
+        OptimizedAction {
+            inner: self.inner.clone(),
+            state: State::Init,
+        }
+    }
+}
+
+#[allow(missing_debug_implementations)]
+enum State<R, A> {
+    Init,
+    Route(R),
+    Fallback(A),
+}
+
+#[allow(missing_debug_implementations)]
+pub struct OptimizedAction<E, Bd>
+where
+    E: RouteDispatch<Bd>,
+{
+    inner: Arc<Inner<E>>,
+    state: State<E::RouteAction, E::Action>,
+}
+
+impl<E, Bd> EndpointAction<Bd> for OptimizedAction<E, Bd>
+where
+    E: RouteDispatch<Bd>,
+{
+    type Output = E::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        if let Some(branch) = self.inner.table.lookup(cx) {
+            let orig_cx = cx.clone();
+            let mut action = self.inner.endpoint.route_action(&mut branch.iter());
+            match action.preflight(cx) {
+                Ok(preflight) => {
+                    self.state = State::Route(action);
+                    return Ok(preflight);
+                }
+                Err(..) => *cx = orig_cx,
+            }
+        }
+
+        // Falls back to the original `or` chain, in order to report the same
+        // errors as the unoptimized endpoint.
+        let mut action = self.inner.endpoint.action();
+        let preflight = action.preflight(cx)?;
+        self.state = State::Fallback(action);
+        Ok(preflight)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        match self.state {
+            State::Route(ref mut action) => action.poll_action(cx),
+            State::Fallback(ref mut action) => action.poll_action(cx),
+            State::Init => panic!("unexpected condition"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            endpoint::{syntax, EndpointExt},
+            service::Context,
+        },
+        http::Request,
+    };
+
+    fn lookup(table: &RouteTable, request: Request<()>) -> Option<Vec<bool>> {
+        let context = Context::new(request);
+        let cx = PreflightContext::new(&context);
+        table.lookup(&cx).map(ToOwned::to_owned)
+    }
+
+    #[test]
+    fn test_route_table_lookup() {
+        let optimized = syntax::verb::get()
+            .and(syntax::segment("users"))
+            .and(syntax::eos())
+            .or(syntax::segment("users"))
+            .or(syntax::segment("users").and(syntax::segment("me")))
+            .or(syntax::eos().and(syntax::segment("never")))
+            .optimize();
+        let table = &optimized.inner.table;
+
+        let get = |uri| Request::get(uri).body(()).unwrap();
+        let post = |uri| Request::post(uri).body(()).unwrap();
+        assert_eq!(
+            lookup(table, get("/users")),
+            Some(vec![false, false, false])
+        );
+        assert_eq!(
+            lookup(table, post("/users")),
+            Some(vec![false, false, true])
+        );
+        assert_eq!(
+            lookup(table, get("/users/x")),
+            Some(vec![false, false, true])
+        );
+        assert_eq!(lookup(table, get("/users/me/x")), Some(vec![false, true]));
+        assert_eq!(lookup(table, get("/posts")), None);
+        assert_eq!(lookup(table, get("/")), None);
+    }
+}
@@ -0,0 +1,125 @@
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+    },
+    futures::{Async, Poll},
+    http::StatusCode,
+};
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct Optional<E> {
+    pub(super) endpoint: E,
+}
+
+impl<E: IsEndpoint> IsEndpoint for Optional<E> {}
+
+impl<E, T, Bd> Endpoint<Bd> for Optional<E>
+where
+    E: Endpoint<Bd, Output = (T,)>,
+{
+    type Output = (Option<T>,);
+    type Action = OptionalAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        OptionalAction {
+            action: Some(self.endpoint.action()),
+        }
+    }
+}
+
+#[allow(missing_debug_implementations)]
+pub struct OptionalAction<A> {
+    action: Option<A>,
+}
+
+impl<A, T, Bd> EndpointAction<Bd> for OptionalAction<A>
+where
+    A: EndpointAction<Bd, Output = (T,)>,
+{
+    type Output = (Option<T>,);
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let action = self.action.as_mut().expect("unexpected condition");
+        let orig_cx = cx.clone();
+        match action.preflight(cx) {
+            Ok(preflight) => Ok(preflight.map(|(x,)| (Some(x),))),
+            Err(ref err) if err.status_code() == StatusCode::NOT_FOUND => {
+                *cx = orig_cx;
+                self.action = None;
+                Ok(Preflight::Completed((None,)))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        match self.action {
+            Some(ref mut action) => action.poll_action(cx).map(|x| x.map(|(x,)| (Some(x),))),
+            None => Ok(Async::Ready((None,))),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct OrDefault<E> {
+    pub(super) endpoint: E,
+}
+
+impl<E: IsEndpoint> IsEndpoint for OrDefault<E> {}
+
+impl<E, T, Bd> Endpoint<Bd> for OrDefault<E>
+where
+    E: Endpoint<Bd, Output = (T,)>,
+    T: Default,
+{
+    type Output = (T,);
+    type Action = OrDefaultAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        OrDefaultAction {
+            action: OptionalAction {
+                action: Some(self.endpoint.action()),
+            },
+        }
+    }
+}
+
+#[allow(missing_debug_implementations)]
+pub struct OrDefaultAction<A> {
+    action: OptionalAction<A>,
+}
+
+impl<A, T, Bd> EndpointAction<Bd> for OrDefaultAction<A>
+where
+    A: EndpointAction<Bd, Output = (T,)>,
+    T: Default,
+{
+    type Output = (T,);
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        self.action
+            .preflight(cx)
+            .map(|x| x.map(|(x,)| (x.unwrap_or_default(),)))
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        self.action
+            .poll_action(cx)
+            .map(|x| x.map(|(x,)| (x.unwrap_or_default(),)))
+    }
+}
@@ -67,7 +67,10 @@ where
                 let orig_cx = cx.clone();
                 let left_output = left.preflight(cx);
                 let mut cx1 = std::mem::replace(cx, orig_cx);
-                let right_output = right.preflight(cx);
+                let right_output = match left_output {
+                    Ok(..) => right.preflight(cx),
+                    Err(ref err) => cx.with_missed(err, |cx| right.preflight(cx)),
+                };
 
                 match (left_output, right_output) {
                     (Ok(l), Ok(r)) => {
@@ -0,0 +1,161 @@
+use {
+    super::NotMatched,
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+    },
+    futures::Poll,
+    std::sync::Arc,
+};
+
+/// A macro for composing many endpoints into one, adopting the first one
+/// matched to the request.
+///
+/// Each endpoint is boxed after its output is converted into `AnyResponse`,
+/// so the endpoints returning the different types can be composed, and the
+/// type of the composed endpoint is flat (`OrAll<EndpointObj<..>>`) rather
+/// than the deeply nested `Or<Or<Or<..>>>`.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax;
+/// # use finchers::output::Json;
+/// # use finchers::routes;
+/// let endpoint = routes![
+///     syntax::segment("hello").map(|| "Hello"),
+///     syntax::segment("count").map(|| Json(vec![1, 2, 3])),
+///     syntax::segment("status").map(|| http::StatusCode::ACCEPTED),
+/// ];
+///
+/// let mut runner = finchers::test::runner(endpoint);
+/// runner.perform("/hello").unwrap().assert_body("Hello");
+/// runner.perform("/count").unwrap().assert_body("[1,2,3]");
+/// runner.perform("/status").unwrap().assert_status(202);
+/// ```
+#[macro_export]
+macro_rules! routes {
+    ($($e:expr),+) => {
+        $crate::endpoint::or_all(vec![$(
+            $crate::endpoint::IsEndpoint::boxed(
+                $crate::endpoint::EndpointExt::map($e, $crate::output::AnyResponse::new),
+            ),
+        )+])
+    };
+    ($($e:expr),+ ,) => {
+        $crate::routes!($($e),+)
+    };
+}
+
+/// Create an endpoint which tries the specified endpoints in order, and
+/// adopts the first one matched to the request.
+///
+/// Unlike the chain of `or_strict`, the type of the returned endpoint does not
+/// depend on the number of alternatives. The endpoints are typically the
+/// boxed ones created by `routes!`.
+///
+/// If none of the endpoints are matched, the errors are merged into a
+/// `NotMatched` in the same way as `or_strict`.
+pub fn or_all<I>(endpoints: I) -> OrAll<I::Item>
+where
+    I: IntoIterator,
+    I::Item: IsEndpoint,
+{
+    OrAll {
+        endpoints: endpoints.into_iter().collect::<Vec<_>>().into(),
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct OrAll<E> {
+    endpoints: Arc<[E]>,
+}
+
+impl<E> Clone for OrAll<E> {
+    fn clone(&self) -> Self {
+        OrAll {
+            endpoints: self.endpoints.clone(),
+        }
+    }
+}
+
+impl<E: IsEndpoint> IsEndpoint for OrAll<E> {}
+
+impl<E, Bd> Endpoint<Bd> for OrAll<E>
+where
+    E: Endpoint<Bd>,
+{
+    type Output = E::Output;
+    type Action = OrAllAction<E, E::Action>;
+
+    fn action(&self) -> Self::Action {
+        OrAllAction {
+            endpoints: self.endpoints.clone(),
+            action: None,
+        }
+    }
+}
+
+#[allow(missing_debug_implementations)]
+pub struct OrAllAction<E, A> {
+    endpoints: Arc<[E]>,
+    action: Option<A>,
+}
+
+impl<E, Bd> EndpointAction<Bd> for OrAllAction<E, E::Action>
+where
+    E: Endpoint<Bd>,
+{
+    type Output = E::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let orig_cx = cx.clone();
+        let mut last_err: Option<Error> = None;
+        for endpoint in self.endpoints.iter() {
+            let mut action = endpoint.action();
+            let preflight = match last_err {
+                Some(ref err) => cx.with_missed(err, |cx| action.preflight(cx)),
+                None => action.preflight(cx),
+            };
+            match preflight {
+                Ok(Preflight::Incomplete) => {
+                    self.action = Some(action);
+                    return Ok(Preflight::Incomplete);
+                }
+                Ok(Preflight::Completed(output)) => return Ok(Preflight::Completed(output)),
+                Err(err) => {
+                    *cx = orig_cx.clone();
+                    last_err = Some(match last_err {
+                        Some(left) => NotMatched {
+                            left,
+                            right: err,
+                            _priv: (),
+                        }
+                        .into(),
+                        None => err,
+                    });
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| error::not_found("not matched")))
+    }
+
+    #[inline]
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        self.action
+            .as_mut()
+            .expect("unexpected condition")
+            .poll_action(cx)
+    }
+}
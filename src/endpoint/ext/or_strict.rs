@@ -69,7 +69,7 @@ where
                     Ok(Preflight::Completed(output)) => return Ok(Preflight::Completed(output)),
                     Err(e1) => {
                         *cx = orig_cx;
-                        match right.preflight(cx) {
+                        match cx.with_missed(&e1, |cx| right.preflight(cx)) {
                             Ok(Preflight::Incomplete) => State::Right(right),
                             Ok(Preflight::Completed(output)) => {
                                 return Ok(Preflight::Completed(output));
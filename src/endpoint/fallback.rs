@@ -0,0 +1,283 @@
+use {
+    crate::{
+        action::{ActionContext, EndpointAction, Preflight, PreflightContext},
+        endpoint::{
+            ext::NotMatched,
+            syntax::{encoded::EncodedStr, SegmentMismatch},
+            Endpoint, IsEndpoint,
+        },
+        error::Error,
+    },
+    futures::{Future, IntoFuture, Poll},
+    http::{Method, StatusCode},
+    std::{fmt, sync::Arc},
+};
+
+/// Create an endpoint which handles the requests not matched to any of the
+/// preceding routes in the `or` chain.
+///
+/// The endpoint always matches without consuming any path segments, so it
+/// is chosen only if the preceding branches have failed when it is placed
+/// at the end of `or` (or `or_strict`, `or_all`). The closure receives a
+/// `RouteMiss`, which summarizes why the preceding branches have failed,
+/// and its return value is converted into the output asynchronously.
+///
+/// Unlike `recover`, the closure is called only for the requests that have
+/// not been routed, and the errors occurred after routing are reported as
+/// they are.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::{self, syntax, RouteMiss};
+/// # use finchers::test;
+/// let endpoint = syntax::segment("users")
+///     .map(|| "users".to_owned())
+///     .or_strict(syntax::segment("posts").map(|| "posts".to_owned()))
+///     .or_strict(endpoint::fallback(|miss: RouteMiss| {
+///         Ok::<_, finchers::error::Error>(match miss.suggestions().first() {
+///             Some(path) => format!("did you mean {}?", path),
+///             None => format!("{} not found", miss.path()),
+///         })
+///     }));
+///
+/// let mut runner = test::runner(endpoint);
+/// assert_eq!(runner.apply("/users").unwrap(), "users");
+/// assert_eq!(runner.apply("/usres").unwrap(), "did you mean /users?");
+/// assert_eq!(runner.apply("/comments").unwrap(), "/comments not found");
+/// ```
+pub fn fallback<F, R>(f: F) -> Fallback<F>
+where
+    F: Fn(RouteMiss) -> R,
+    R: IntoFuture,
+    R::Error: Into<Error>,
+{
+    Fallback { f: Arc::new(f) }
+}
+
+/// A summary of the reasons why the preceding routes have not been matched.
+#[derive(Debug, Clone)]
+pub struct RouteMiss {
+    method: Method,
+    path: String,
+    candidates: Vec<(StatusCode, String)>,
+    mismatches: Vec<SegmentMismatch>,
+}
+
+impl RouteMiss {
+    fn new(cx: &PreflightContext<'_>) -> Self {
+        let mut miss = RouteMiss {
+            method: cx.method().clone(),
+            path: cx.uri().path().to_owned(),
+            candidates: vec![],
+            mismatches: vec![],
+        };
+        for err in cx.missed_errors() {
+            miss.collect(err);
+        }
+        miss
+    }
+
+    fn collect(&mut self, err: &Error) {
+        if let Some(not_matched) = err.downcast_ref::<NotMatched>() {
+            self.collect(&not_matched.left);
+            self.collect(&not_matched.right);
+        } else if let Some(mismatch) = err.downcast_ref::<SegmentMismatch>() {
+            self.mismatches.push(mismatch.clone());
+        } else if err.status_code() != StatusCode::NOT_FOUND {
+            self.candidates.push((err.status_code(), err.to_string()));
+        }
+    }
+
+    /// Returns the method of the request.
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// Returns the path of the request.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the status codes and messages of the *near-miss* routes,
+    /// which have been rejected for a reason other than `404 Not Found`
+    /// (e.g. the wrong method or a missing header).
+    pub fn candidates(&self) -> &[(StatusCode, String)] {
+        &self.candidates
+    }
+
+    /// Returns the static segments of the routes which have not matched to the request.
+    pub fn mismatches(&self) -> &[SegmentMismatch] {
+        &self.mismatches
+    }
+
+    /// Returns the paths of the routes similar to the requested one, from the closest.
+    ///
+    /// Each suggestion replaces a mismatched segment of the request path with
+    /// the expected one, if the edit distance between them is not more than
+    /// half the length of the expected segment. The suggestions are ordered by
+    /// the edit distance, and the ones mismatched at the deeper segment come
+    /// first if the distances are equal.
+    pub fn suggestions(&self) -> Vec<String> {
+        let segments: Vec<_> = self
+            .path
+            .trim_start_matches('/')
+            .split('/')
+            .map(|s| unsafe { EncodedStr::new_unchecked(s) }.percent_decode_lossy())
+            .collect();
+
+        let mut suggestions = vec![];
+        for mismatch in &self.mismatches {
+            let expected = unsafe { EncodedStr::new_unchecked(mismatch.expected()) };
+            let expected = expected.percent_decode_lossy();
+            let actual = segments
+                .get(mismatch.position())
+                .map_or("", |segment| &**segment);
+            let distance = edit_distance(actual, &expected);
+            if distance == 0 || distance * 2 > expected.chars().count() {
+                continue;
+            }
+
+            let mut path = String::new();
+            for (i, segment) in segments.iter().enumerate() {
+                path.push('/');
+                path.push_str(if i == mismatch.position() {
+                    &expected
+                } else {
+                    segment
+                });
+            }
+            if mismatch.position() >= segments.len() {
+                path.push('/');
+                path.push_str(&expected);
+            }
+            suggestions.push((distance, std::cmp::Reverse(mismatch.position()), path));
+        }
+
+        suggestions.sort();
+        let mut paths: Vec<String> = suggestions.into_iter().map(|(_, _, path)| path).collect();
+        paths.dedup();
+        paths
+    }
+}
+
+/// Computes the Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { prev } else { prev + 1 };
+            prev = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(prev + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[allow(missing_docs)]
+pub struct Fallback<F> {
+    f: Arc<F>,
+}
+
+impl<F> Clone for Fallback<F> {
+    fn clone(&self) -> Self {
+        Fallback { f: self.f.clone() }
+    }
+}
+
+impl<F> fmt::Debug for Fallback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fallback").finish()
+    }
+}
+
+impl<F> IsEndpoint for Fallback<F> {}
+
+impl<F, R, Bd> Endpoint<Bd> for Fallback<F>
+where
+    F: Fn(RouteMiss) -> R,
+    R: IntoFuture,
+    R::Error: Into<Error>,
+{
+    type Output = (R::Item,);
+    type Action = FallbackAction<F, R::Future>;
+
+    fn action(&self) -> Self::Action {
+        FallbackAction {
+            f: self.f.clone(),
+            miss: None,
+            future: None,
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct FallbackAction<F, Fut> {
+    f: Arc<F>,
+    miss: Option<RouteMiss>,
+    future: Option<Fut>,
+}
+
+impl<F, R, Bd> EndpointAction<Bd> for FallbackAction<F, R::Future>
+where
+    F: Fn(RouteMiss) -> R,
+    R: IntoFuture,
+    R::Error: Into<Error>,
+{
+    type Output = (R::Item,);
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        // The closure is deferred until this action is chosen by `or`.
+        self.miss = Some(RouteMiss::new(cx));
+        Ok(Preflight::Incomplete)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        let (f, miss, future) = (&self.f, &mut self.miss, &mut self.future);
+        cx.context_mut().set(|| {
+            if let Some(miss) = miss.take() {
+                *future = Some(f(miss).into_future());
+            }
+            future
+                .as_mut()
+                .expect("the action has already been polled")
+                .poll()
+                .map(|x| x.map(|item| (item,)))
+                .map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("users", "users"), 0);
+        assert_eq!(edit_distance("usres", "users"), 2);
+        assert_eq!(edit_distance("user", "users"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let miss = |path: &str| RouteMiss {
+            method: Method::GET,
+            path: path.into(),
+            candidates: vec![],
+            mismatches: vec![SegmentMismatch::new(Arc::new("users".into()), 0)],
+        };
+        assert_eq!(miss("/usres").suggestions(), ["/users"]);
+        assert_eq!(miss("/usres/1").suggestions(), ["/users/1"]);
+        assert!(miss("").suggestions().is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        common::Flatten,
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+    },
+    futures::{Async, Poll},
+};
+
+/// Create an endpoint which applies a tuple of endpoints to the same request
+/// and combines their outputs.
+///
+/// Unlike `and`, which advances a shared cursor from one endpoint to the next,
+/// each endpoint sees the path segments from the same position, and the cursor
+/// is advanced to the furthest position reached by them. After all of the
+/// endpoints have been matched, their actions are polled concurrently rather
+/// than one after another.
+///
+/// Note that at most one of the endpoints can take the request body.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax;
+/// # use finchers::endpoints::{header, query};
+/// # use finchers::test;
+/// # use serde::Deserialize;
+/// #[derive(Debug, Deserialize)]
+/// struct Pagination {
+///     page: u32,
+/// }
+///
+/// let endpoint = endpoint::join((
+///     syntax::segment("posts"),
+///     header::raw("x-api-key"),
+///     query::required::<Pagination>(),
+/// ))
+/// .map(|api_key: Option<http::HeaderValue>, pagination: Pagination| {
+///     format!("{:?} {}", api_key, pagination.page)
+/// });
+///
+/// let mut runner = test::runner(endpoint);
+/// runner
+///     .perform("/posts?page=2")
+///     .unwrap()
+///     .assert_body("None 2");
+/// ```
+pub fn join<T>(endpoints: T) -> Join<T> {
+    Join { endpoints }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy)]
+pub struct Join<T> {
+    endpoints: T,
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct JoinAction<T> {
+    actions: T,
+}
+
+#[allow(missing_debug_implementations)]
+#[doc(hidden)]
+pub enum JoinState<Bd, A: EndpointAction<Bd>> {
+    InFlight(A),
+    Ready(A::Output),
+    Gone,
+}
+
+impl<Bd, A: EndpointAction<Bd>> JoinState<Bd, A> {
+    fn preflight(&mut self, cx: &mut PreflightContext<'_>) -> Result<bool, Error> {
+        let output = match self {
+            JoinState::InFlight(ref mut action) => match action.preflight(cx)? {
+                Preflight::Completed(output) => output,
+                Preflight::Incomplete => return Ok(false),
+            },
+            _ => panic!("unexpected condition"),
+        };
+        *self = JoinState::Ready(output);
+        Ok(true)
+    }
+
+    fn poll_ready(&mut self, cx: &mut ActionContext<'_, Bd>) -> Result<bool, Error> {
+        let output = match self {
+            JoinState::InFlight(ref mut action) => match action.poll_action(cx)? {
+                Async::Ready(output) => output,
+                Async::NotReady => return Ok(false),
+            },
+            JoinState::Ready(..) => return Ok(true),
+            JoinState::Gone => panic!("The action has already been polled."),
+        };
+        *self = JoinState::Ready(output);
+        Ok(true)
+    }
+
+    fn take_output(&mut self) -> A::Output {
+        match std::mem::replace(self, JoinState::Gone) {
+            JoinState::Ready(output) => output,
+            _ => panic!("the output is not ready"),
+        }
+    }
+}
+
+macro_rules! impl_join {
+    ($($E:ident => $i:tt),*) => {
+        impl<$($E: IsEndpoint),*> IsEndpoint for Join<($($E,)*)> {}
+
+        impl<Bd, $($E),*> Endpoint<Bd> for Join<($($E,)*)>
+        where
+            $( $E: Endpoint<Bd>, )*
+            ($($E::Output,)*): Flatten,
+        {
+            type Output = <($($E::Output,)*) as Flatten>::Out;
+            type Action = JoinAction<($(JoinState<Bd, $E::Action>,)*)>;
+
+            fn action(&self) -> Self::Action {
+                JoinAction {
+                    actions: ($(JoinState::InFlight(self.endpoints.$i.action()),)*),
+                }
+            }
+        }
+
+        impl<Bd, $($E),*> EndpointAction<Bd> for JoinAction<($(JoinState<Bd, $E>,)*)>
+        where
+            $( $E: EndpointAction<Bd>, )*
+            ($($E::Output,)*): Flatten,
+        {
+            type Output = <($($E::Output,)*) as Flatten>::Out;
+
+            fn preflight(
+                &mut self,
+                cx: &mut PreflightContext<'_>,
+            ) -> Result<Preflight<Self::Output>, Error> {
+                let mut completed = true;
+                let mut furthest: Option<PreflightContext<'_>> = None;
+                $(
+                    let mut branch = cx.clone();
+                    completed &= self.actions.$i.preflight(&mut branch)?;
+                    let popped = branch.cursor().num_popped_segments();
+                    if furthest
+                        .as_mut()
+                        .map_or(true, |furthest| furthest.cursor().num_popped_segments() < popped)
+                    {
+                        furthest = Some(branch);
+                    }
+                )*
+                if let Some(furthest) = furthest {
+                    *cx = furthest;
+                }
+
+                if completed {
+                    Ok(Preflight::Completed(Flatten::flatten((
+                        $( self.actions.$i.take_output(), )*
+                    ))))
+                } else {
+                    Ok(Preflight::Incomplete)
+                }
+            }
+
+            fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+                // All of the actions are polled before returning `NotReady`,
+                // so that they progress concurrently.
+                let mut ready = true;
+                $(
+                    ready &= self.actions.$i.poll_ready(cx)?;
+                )*
+                if !ready {
+                    return Ok(Async::NotReady);
+                }
+                Ok(Async::Ready(Flatten::flatten((
+                    $( self.actions.$i.take_output(), )*
+                ))))
+            }
+        }
+    };
+}
+
+impl_join!(E0 => 0);
+impl_join!(E0 => 0, E1 => 1);
+impl_join!(E0 => 0, E1 => 1, E2 => 2);
+impl_join!(E0 => 0, E1 => 1, E2 => 2, E3 => 3);
+impl_join!(E0 => 0, E1 => 1, E2 => 2, E3 => 3, E4 => 4);
+impl_join!(E0 => 0, E1 => 1, E2 => 2, E3 => 3, E4 => 4, E5 => 5);
+impl_join!(E0 => 0, E1 => 1, E2 => 2, E3 => 3, E4 => 4, E5 => 5, E6 => 6);
+impl_join!(E0 => 0, E1 => 1, E2 => 2, E3 => 3, E4 => 4, E5 => 5, E6 => 6, E7 => 7);
@@ -0,0 +1,355 @@
+use {
+    super::syntax::SEGMENT_ENCODE_SET,
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+    },
+    futures::{Async, Poll},
+    percent_encoding::percent_encode,
+    std::{
+        any::{Any, TypeId},
+        collections::HashMap,
+        fmt,
+        marker::PhantomData,
+        sync::Arc,
+    },
+};
+
+// ==== Scope ====
+
+/// Create an endpoint which mounts `endpoint` under the specified prefix.
+///
+/// The segments of the prefix are matched and stripped before `endpoint`
+/// is applied, so that the routes in `endpoint` can be defined relative to
+/// its own root. The wrappers applied to the returned endpoint (by `wrap()`)
+/// affect the entire subtree.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax;
+/// # use finchers::test;
+/// let users = syntax::segment("users")
+///     .and(syntax::param::<u32>())
+///     .map(|id: u32| format!("user {}", id));
+///
+/// let mut runner = test::runner(endpoint::scope("/api/v1", users));
+/// assert_eq!(runner.apply("/api/v1/users/42").unwrap(), "user 42");
+/// assert!(runner.apply("/users/42").is_err());
+/// ```
+pub fn scope<E>(prefix: &str, endpoint: E) -> Scope<E>
+where
+    E: IsEndpoint,
+{
+    let prefix = prefix
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| percent_encode(s.as_bytes(), SEGMENT_ENCODE_SET).to_string())
+        .collect::<Vec<_>>()
+        .into();
+    Scope { prefix, endpoint }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct Scope<E> {
+    prefix: Arc<[String]>,
+    endpoint: E,
+}
+
+impl<E> Scope<E> {
+    /// Attaches the specified state to the subtree.
+    ///
+    /// The state is stored in the extensions of the request before the
+    /// mounted endpoint is polled, and can be extracted by `endpoint::state`.
+    /// Note that the state is *not* visible from the synchronous part of
+    /// the mounted endpoint (i.e. `preflight`).
+    pub fn with_state<S>(self, state: S) -> ScopeWithState<E, S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        ScopeWithState { scope: self, state }
+    }
+}
+
+mod scoped {
+    use super::*;
+
+    impl<E: IsEndpoint> IsEndpoint for Scope<E> {}
+
+    impl<E, Bd> Endpoint<Bd> for Scope<E>
+    where
+        E: Endpoint<Bd>,
+    {
+        type Output = E::Output;
+        type Action = ScopeAction<E::Action>;
+
+        fn action(&self) -> Self::Action {
+            ScopeAction {
+                prefix: self.prefix.clone(),
+                action: self.endpoint.action(),
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct ScopeAction<A> {
+        prefix: Arc<[String]>,
+        action: A,
+    }
+
+    impl<A, Bd> EndpointAction<Bd> for ScopeAction<A>
+    where
+        A: EndpointAction<Bd>,
+    {
+        type Output = A::Output;
+
+        fn preflight(
+            &mut self,
+            cx: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            for expected in self.prefix.iter() {
+                match cx.cursor().next() {
+                    Some(s) if s == expected.as_str() => (),
+                    _ => return Err(error::not_found("not matched")),
+                }
+            }
+            self.action.preflight(cx)
+        }
+
+        #[inline]
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            self.action.poll_action(cx)
+        }
+    }
+}
+
+// ==== ScopeWithState ====
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct ScopeWithState<E, S> {
+    scope: Scope<E>,
+    state: S,
+}
+
+mod scope_with_state {
+    use super::*;
+
+    impl<E: IsEndpoint, S> IsEndpoint for ScopeWithState<E, S> {}
+
+    impl<E, S, Bd> Endpoint<Bd> for ScopeWithState<E, S>
+    where
+        E: Endpoint<Bd>,
+        S: Clone + Send + Sync + 'static,
+    {
+        type Output = E::Output;
+        type Action = ScopeWithStateAction<E::Action, S, E::Output>;
+
+        fn action(&self) -> Self::Action {
+            ScopeWithStateAction {
+                action: self.scope.action(),
+                state: Some(self.state.clone()),
+                output: None,
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct ScopeWithStateAction<A, S, T> {
+        action: scoped::ScopeAction<A>,
+        state: Option<S>,
+        output: Option<T>,
+    }
+
+    impl<A, S, Bd> EndpointAction<Bd> for ScopeWithStateAction<A, S, A::Output>
+    where
+        A: EndpointAction<Bd>,
+        S: Send + Sync + 'static,
+    {
+        type Output = A::Output;
+
+        fn preflight(
+            &mut self,
+            cx: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            // The completed output is held until `poll_action` in order to
+            // store the state into the context.
+            if let Preflight::Completed(output) = self.action.preflight(cx)? {
+                self.output = Some(output);
+            }
+            Ok(Preflight::Incomplete)
+        }
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            if let Some(state) = self.state.take() {
+                cx.extensions_mut().insert(state);
+            }
+            match self.output.take() {
+                Some(output) => Ok(Async::Ready(output)),
+                None => self.action.poll_action(cx),
+            }
+        }
+    }
+}
+
+// ==== State ====
+
+/// Create an endpoint which extracts the state attached by `Scope::with_state`.
+///
+/// This endpoint reports `500 Internal Server Error` if the state of the
+/// specified type is not attached to the enclosing scopes.
+///
+/// Within the test runner, the state can be replaced with a fake value by
+/// `TestRunner::override_state`, regardless of the value attached to the scopes.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax;
+/// # use finchers::test;
+/// #[derive(Clone)]
+/// struct Greeting(&'static str);
+///
+/// let hello = syntax::segment("hello")
+///     .and(endpoint::state::<Greeting>())
+///     .map(|greeting: Greeting| greeting.0);
+///
+/// let endpoint = endpoint::scope("/en", hello.clone())
+///     .with_state(Greeting("Hello"))
+///     .or_strict(endpoint::scope("/ja", hello).with_state(Greeting("Konnichiwa")));
+///
+/// let mut runner = test::runner(endpoint);
+/// assert_eq!(runner.apply("/ja/hello").unwrap(), "Konnichiwa");
+/// ```
+pub fn state<S>() -> ExtractState<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    ExtractState {
+        _marker: PhantomData,
+    }
+}
+
+#[allow(missing_docs)]
+pub struct ExtractState<S> {
+    _marker: PhantomData<fn() -> S>,
+}
+
+impl<S> Copy for ExtractState<S> {}
+
+impl<S> Clone for ExtractState<S> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S> fmt::Debug for ExtractState<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtractState").finish()
+    }
+}
+
+mod extract_state {
+    use super::*;
+
+    impl<S> IsEndpoint for ExtractState<S> {}
+
+    impl<S, Bd> Endpoint<Bd> for ExtractState<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        type Output = (S,);
+        type Action = ExtractStateAction<S>;
+
+        fn action(&self) -> Self::Action {
+            ExtractStateAction {
+                _marker: PhantomData,
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct ExtractStateAction<S> {
+        _marker: PhantomData<fn() -> S>,
+    }
+
+    impl<S, Bd> EndpointAction<Bd> for ExtractStateAction<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        type Output = (S,);
+
+        fn preflight(
+            &mut self,
+            _: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            Ok(Preflight::Incomplete)
+        }
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            cx.extensions()
+                .get::<StateOverrides>()
+                .and_then(StateOverrides::get::<S>)
+                .or_else(|| cx.extensions().get::<S>().cloned())
+                .map(|state| Async::Ready((state,)))
+                .ok_or_else(|| error::internal_server_error("the state is not attached"))
+        }
+    }
+}
+
+// ==== StateOverrides ====
+
+/// The fake states registered by `TestRunner::override_state`, which take
+/// precedence over the states attached by `Scope::with_state`.
+#[derive(Clone, Default)]
+pub(crate) struct StateOverrides {
+    states: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl fmt::Debug for StateOverrides {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StateOverrides")
+            .field("len", &self.states.len())
+            .finish()
+    }
+}
+
+impl StateOverrides {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    pub(crate) fn insert<S>(&mut self, state: S)
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.states.insert(TypeId::of::<S>(), Arc::new(state));
+    }
+
+    pub(crate) fn remove<S>(&mut self) -> bool
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.states.remove(&TypeId::of::<S>()).is_some()
+    }
+
+    fn get<S>(&self) -> Option<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.states
+            .get(&TypeId::of::<S>())
+            .and_then(|state| state.downcast_ref::<S>())
+            .cloned()
+    }
+}
@@ -0,0 +1,377 @@
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+        service::Context,
+        util::stable_position,
+    },
+    futures::{Async, Poll},
+    http::header::{self, HeaderName},
+    serde::Serialize,
+    std::{
+        borrow::Cow,
+        fmt,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    },
+};
+
+/// Create an endpoint which splits the traffic between two endpoints.
+///
+/// Each request is routed to `a` or `b` deterministically by the hash of its
+/// key (see `SplitKey`), so the same client keeps seeing the same variant.
+/// `weight` is the ratio (between `0.0` and `1.0`) of the keys routed to `a`.
+/// The keys are assigned to a stable position, so changing the weight only
+/// moves the keys between the variants by the changed ratio. The requests
+/// without the key are routed to `a`.
+///
+/// The chosen variant is stored in the extensions of the request as a
+/// `Variant` before the chosen endpoint is polled, so that it can be used for
+/// logging. The number of requests routed to each variant is available from
+/// `Split::stats`, and `Variant::labels` provides the labels for the external
+/// metrics collectors.
+///
+/// # Panics
+/// This function panics if `weight` is not between `0.0` and `1.0`.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::{self, syntax, SplitKey};
+/// # use finchers::test;
+/// # use http::Request;
+/// let checkout = endpoint::split(
+///     0.9,
+///     syntax::segment("checkout").map(|| "current"),
+///     syntax::segment("checkout").map(|| "redesigned"),
+/// )
+/// .name("checkout-redesign")
+/// .key(SplitKey::cookie("uid"));
+///
+/// let mut runner = test::runner(checkout.clone());
+/// let first = runner
+///     .perform(Request::get("/checkout").header("cookie", "uid=alice"))
+///     .unwrap();
+/// let second = runner
+///     .perform(Request::get("/checkout").header("cookie", "uid=alice"))
+///     .unwrap();
+/// assert_eq!(first.to_utf8_lossy(), second.to_utf8_lossy());
+/// assert_eq!(checkout.stats().requests(), 2);
+/// ```
+pub fn split<A, B>(weight: f64, a: A, b: B) -> Split<A, B> {
+    assert!(
+        weight >= 0.0 && weight <= 1.0,
+        "the weight must be between 0.0 and 1.0"
+    );
+    Split {
+        a,
+        b,
+        inner: Arc::new(SplitInner {
+            name: "split".into(),
+            weight,
+            key: SplitKey::RemoteAddr,
+            stats: SplitStats::default(),
+        }),
+    }
+}
+
+type KeyFn = dyn Fn(&Context) -> Option<String> + Send + Sync + 'static;
+
+/// The key which determines the variant of a request.
+#[derive(Clone)]
+pub enum SplitKey {
+    /// The value of the cookie with the specified name.
+    Cookie(Cow<'static, str>),
+    /// The value of the specified header.
+    Header(HeaderName),
+    /// The IP address of the client.
+    ///
+    /// The address is taken from the `SocketAddr` in the connection extensions,
+    /// which should be inserted by `ConnectionHooks::on_open` from
+    /// `Connection::remote_addr`.
+    RemoteAddr,
+    /// The value extracted by the specified function.
+    Custom(Arc<KeyFn>),
+}
+
+impl fmt::Debug for SplitKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SplitKey::Cookie(ref name) => f.debug_tuple("Cookie").field(name).finish(),
+            SplitKey::Header(ref name) => f.debug_tuple("Header").field(name).finish(),
+            SplitKey::RemoteAddr => f.write_str("RemoteAddr"),
+            SplitKey::Custom(..) => f.debug_tuple("Custom").finish(),
+        }
+    }
+}
+
+impl SplitKey {
+    /// Creates a `SplitKey` which takes the value of the specified cookie.
+    pub fn cookie(name: impl Into<Cow<'static, str>>) -> Self {
+        SplitKey::Cookie(name.into())
+    }
+
+    /// Creates a `SplitKey` which takes the value of the specified header.
+    ///
+    /// # Panics
+    /// This function panics if the name is not a valid header name.
+    pub fn header(name: &str) -> Self {
+        SplitKey::Header(name.parse().expect("invalid header name"))
+    }
+
+    /// Creates a `SplitKey` which takes the value extracted by the specified function.
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(&Context) -> Option<String> + Send + Sync + 'static,
+    {
+        SplitKey::Custom(Arc::new(f))
+    }
+
+    fn extract(&self, cx: &Context) -> Option<String> {
+        match *self {
+            SplitKey::Cookie(ref name) => cx
+                .headers()
+                .get_all(header::COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .filter_map(|pair| {
+                    let mut parts = pair.trim().splitn(2, '=');
+                    match (parts.next(), parts.next()) {
+                        (Some(n), Some(value)) if n == name => Some(value.to_owned()),
+                        _ => None,
+                    }
+                })
+                .next(),
+            SplitKey::Header(ref name) => cx
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned),
+            SplitKey::RemoteAddr => cx
+                .connection_extensions()
+                .and_then(|ext| ext.get::<SocketAddr>())
+                .map(|addr| addr.ip().to_string()),
+            SplitKey::Custom(ref f) => f(cx),
+        }
+    }
+}
+
+/// The variant chosen by `split`, stored in the extensions of the request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Variant {
+    experiment: Cow<'static, str>,
+    variant: &'static str,
+}
+
+impl Variant {
+    /// Returns the name of the split.
+    pub fn experiment(&self) -> &str {
+        &self.experiment
+    }
+
+    /// Returns the name of the chosen variant, `"a"` or `"b"`.
+    pub fn variant(&self) -> &'static str {
+        self.variant
+    }
+
+    /// Returns whether the request has been routed to `b`.
+    pub fn is_b(&self) -> bool {
+        self.variant == "b"
+    }
+
+    /// Returns the pairs of the label names and values which identify the variant
+    /// in the metrics, i.e. `experiment` and `variant`.
+    pub fn labels(&self) -> [(&'static str, &str); 2] {
+        [("experiment", &self.experiment), ("variant", self.variant)]
+    }
+}
+
+/// The number of requests routed to each variant by `split`.
+#[derive(Debug, Default)]
+pub struct SplitStats {
+    a: AtomicUsize,
+    b: AtomicUsize,
+}
+
+impl SplitStats {
+    /// Returns the number of requests routed to `a`.
+    pub fn a(&self) -> u64 {
+        self.a.load(Ordering::Relaxed) as u64
+    }
+
+    /// Returns the number of requests routed to `b`.
+    pub fn b(&self) -> u64 {
+        self.b.load(Ordering::Relaxed) as u64
+    }
+
+    /// Returns the total number of requests.
+    pub fn requests(&self) -> u64 {
+        self.a() + self.b()
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct Split<A, B> {
+    a: A,
+    b: B,
+    inner: Arc<SplitInner>,
+}
+
+#[derive(Debug)]
+struct SplitInner {
+    name: Cow<'static, str>,
+    weight: f64,
+    key: SplitKey,
+    stats: SplitStats,
+}
+
+impl<A, B> Split<A, B> {
+    fn configure(mut self, f: impl FnOnce(&mut SplitInner)) -> Self {
+        f(Arc::get_mut(&mut self.inner).expect("the split has already been shared"));
+        self
+    }
+
+    /// Sets the name of the split, which is used as the salt of the hash
+    /// and reported in `Variant`.
+    ///
+    /// The splits with different names assign the keys independently.
+    /// The default value is `"split"`.
+    ///
+    /// # Panics
+    /// This method panics if the `Split` has already been cloned.
+    pub fn name(self, name: impl Into<Cow<'static, str>>) -> Self {
+        let name = name.into();
+        self.configure(|inner| inner.name = name)
+    }
+
+    /// Sets the key which determines the variant.
+    ///
+    /// The default value is `SplitKey::RemoteAddr`.
+    ///
+    /// # Panics
+    /// This method panics if the `Split` has already been cloned.
+    pub fn key(self, key: SplitKey) -> Self {
+        self.configure(|inner| inner.key = key)
+    }
+
+    /// Returns the number of requests routed to each variant.
+    pub fn stats(&self) -> &SplitStats {
+        &self.inner.stats
+    }
+}
+
+impl<A, B> IsEndpoint for Split<A, B>
+where
+    A: IsEndpoint,
+    B: IsEndpoint,
+{
+}
+
+impl<A, B, Bd> Endpoint<Bd> for Split<A, B>
+where
+    A: Endpoint<Bd>,
+    B: Endpoint<Bd, Output = A::Output>,
+{
+    type Output = A::Output;
+    type Action = SplitAction<A::Action, B::Action, A::Output>;
+
+    fn action(&self) -> Self::Action {
+        SplitAction {
+            state: State::Init(self.a.action(), self.b.action()),
+            inner: self.inner.clone(),
+            variant: None,
+            output: None,
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct SplitAction<A, B, T> {
+    state: State<A, B>,
+    inner: Arc<SplitInner>,
+    variant: Option<Variant>,
+    output: Option<T>,
+}
+
+enum State<A, B> {
+    Init(A, B),
+    A(A),
+    B(B),
+    Done,
+}
+
+impl<A, B, Bd> EndpointAction<Bd> for SplitAction<A, B, A::Output>
+where
+    A: EndpointAction<Bd>,
+    B: EndpointAction<Bd, Output = A::Output>,
+{
+    type Output = A::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let (a, b) = match std::mem::replace(&mut self.state, State::Done) {
+            State::Init(a, b) => (a, b),
+            _ => panic!("unexpected condition"),
+        };
+
+        let inner = &*self.inner;
+        let is_b = inner.key.extract(cx).map_or(false, |key| {
+            stable_position(&[inner.name.as_bytes(), key.as_bytes()]) >= inner.weight
+        });
+
+        let preflight = if is_b {
+            inner.stats.b.fetch_add(1, Ordering::Relaxed);
+            self.state = State::B(b);
+            match self.state {
+                State::B(ref mut b) => b.preflight(cx)?,
+                _ => unreachable!(),
+            }
+        } else {
+            inner.stats.a.fetch_add(1, Ordering::Relaxed);
+            self.state = State::A(a);
+            match self.state {
+                State::A(ref mut a) => a.preflight(cx)?,
+                _ => unreachable!(),
+            }
+        };
+        self.variant = Some(Variant {
+            experiment: inner.name.clone(),
+            variant: if is_b { "b" } else { "a" },
+        });
+
+        // The completed output is held until `poll_action` in order to
+        // store the variant into the context.
+        if let Preflight::Completed(output) = preflight {
+            self.output = Some(output);
+        }
+        Ok(Preflight::Incomplete)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        if let Some(variant) = self.variant.take() {
+            cx.extensions_mut().insert(variant);
+        }
+        if let Some(output) = self.output.take() {
+            return Ok(Async::Ready(output));
+        }
+        match self.state {
+            State::A(ref mut a) => a.poll_action(cx),
+            State::B(ref mut b) => b.poll_action(cx),
+            _ => panic!("unexpected condition"),
+        }
+    }
+}
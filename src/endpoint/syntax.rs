@@ -1,26 +1,39 @@
 //! Components for building endpoints which matches to a specific HTTP path.
 
+mod api_version;
 pub mod encoded;
+mod glob;
+pub mod matrix;
 pub mod verb;
 
 pub use {
+    self::api_version::{
+        api_version, //
+        ApiVersion,
+        MatchApiVersion,
+        OnlyApiVersion,
+        UnsupportedApiVersion,
+    },
+    self::glob::{glob, GlobCaptures, MatchGlob, MatchGlobAction},
     crate::path, //
     finchers_macros::ExtractPath,
 };
 
 use {
-    self::encoded::FromEncodedStr,
+    self::encoded::{EncodedStr, FromEncodedStr, SharedStr},
     crate::{
         common::Tuple,
         endpoint::{
+            ext::{LiteralPattern, RoutePattern},
             Endpoint, //
             IsEndpoint,
             Oneshot,
             OneshotAction,
             PreflightContext,
         },
-        error::Error,
+        error::{Error, HttpError},
     },
+    http::StatusCode,
     percent_encoding::{
         percent_encode, //
         DEFAULT_ENCODE_SET,
@@ -35,20 +48,85 @@ use {
 // ==== ExtractPath ====
 
 /// A macro for creating an endpoint that matches to the specific HTTP path.
+///
+/// The path literal consists of the following segments:
+///
+/// * `foo` - a static segment, which must be equal to the segment of the request.
+/// * `<T>` - a parameter, which is converted into `T` by `FromEncodedStr`.
+/// * `<..T>` - a catch-all parameter, which converts the remaining path into `T`.
+///   It must be at the end of path.
+/// * `*name` - a trailing capture, which is equivalent to `<..String>`.
+///   The name is only for readability.
+///
+/// The endpoint checks that all the segments of the request have been
+/// consumed, unless the path ends with a catch-all parameter, a trailing
+/// capture or `/...`, which opts out of the check.
+///
+/// The path can be followed by the query parameters after `?`, separated
+/// by `&`, which are extracted by the functions in `endpoints::query`,
+/// and the endpoint extracting the request body after `=>`.
+/// The HTTP method can be restricted by the prefix `@<method>`, which refers
+/// to the function in `syntax::verb`.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax;
+/// # use finchers::endpoints::body;
+/// # use finchers::test;
+/// # use http::Request;
+/// let mut runner = test::runner(
+///     syntax::path!(@get "/posts/<u32>" ? optional_param::<u32>("page"))
+///         .map(|id: u32, page: Option<u32>| format!("{}:{}", id, page.unwrap_or(1))),
+/// );
+/// assert_eq!(runner.apply("/posts/42?page=3").unwrap(), "42:3");
+/// assert_eq!(runner.apply("/posts/42").unwrap(), "42:1");
+/// assert!(runner.apply("/posts/42/comments").is_err());
+///
+/// let mut runner = test::runner(syntax::path!(@post "/echo/..." => body::text()));
+/// let request = Request::post("/echo/foo").body("Hello").unwrap();
+/// assert_eq!(runner.apply(request).unwrap(), "Hello");
+///
+/// let mut runner = test::runner(syntax::path!("/static/*rest"));
+/// assert_eq!(runner.apply("/static/css/index.css").unwrap(), "css/index.css");
+/// ```
 #[macro_export]
 macro_rules! path {
-    ($path:expr) => {{
+    (@$verb:ident $($rest:tt)+) => {
+        $crate::endpoint::ext::EndpointExt::and(
+            $crate::endpoint::syntax::verb::$verb(),
+            $crate::endpoint::syntax::path!($($rest)+),
+        )
+    };
+
+    ($path:tt) => {{
         #[derive($crate::endpoint::syntax::ExtractPath)]
-        #[path = $path]
+        #[extract_path = $path]
         struct __DerivedExtractPath(());
 
         $crate::endpoint::syntax::path::<__DerivedExtractPath>()
     }};
 
-    (@$verb:ident $path:expr) => {
+    ($path:tt => $body:expr) => {
+        $crate::endpoint::ext::EndpointExt::and($crate::endpoint::syntax::path!($path), $body)
+    };
+
+    ($path:tt ? $($query:ident $(::<$ty:ty>)* ($($arg:expr),*))&+) => {{
+        let endpoint = $crate::endpoint::syntax::path!($path);
+        $(
+            let endpoint = $crate::endpoint::ext::EndpointExt::and(
+                endpoint,
+                $crate::endpoints::query::$query $(::<$ty>)* ($($arg),*),
+            );
+        )+
+        endpoint
+    }};
+
+    ($path:tt ? $($query:ident $(::<$ty:ty>)* ($($arg:expr),*))&+ => $body:expr) => {
         $crate::endpoint::ext::EndpointExt::and(
-            $crate::endpoint::syntax::verb::$verb(),
-            $crate::endpoint::syntax::path!($path),
+            $crate::endpoint::syntax::path!($path ? $($query $(::<$ty>)* ($($arg),*))&+),
+            $body,
         )
     };
 }
@@ -83,6 +161,47 @@ impl ExtractPathError {
     }
 }
 
+/// An `HttpError` indicating that a static segment of the path has not been matched.
+///
+/// This error is reported as `404 Not Found` with the same message as other
+/// unmatched routes, but carries the expected segment so that the closest
+/// routes can be suggested by `endpoint::fallback`.
+#[derive(Debug, Clone)]
+pub struct SegmentMismatch {
+    expected: Arc<String>,
+    position: usize,
+}
+
+impl SegmentMismatch {
+    pub(crate) fn new(expected: Arc<String>, position: usize) -> Self {
+        SegmentMismatch { expected, position }
+    }
+
+    /// Returns the (percent-encoded) segment expected by the route.
+    pub fn expected(&self) -> &str {
+        &self.expected
+    }
+
+    /// Returns the zero-based index of the mismatched segment in the request path.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl fmt::Display for SegmentMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("not matched")
+    }
+}
+
+impl failure::Fail for SegmentMismatch {}
+
+impl HttpError for SegmentMismatch {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::NOT_FOUND
+    }
+}
+
 /// Creates an endpoint that matches to the specific HTTP path.
 pub fn path<T>() -> Path<T>
 where
@@ -165,6 +284,12 @@ pub struct MatchSegment {
 
 impl IsEndpoint for MatchSegment {}
 
+impl LiteralPattern for MatchSegment {
+    fn literal_pattern(&self, pattern: &mut RoutePattern) {
+        pattern.push_segment(&self.encoded);
+    }
+}
+
 impl<Bd> Endpoint<Bd> for MatchSegment {
     type Output = ();
     type Action = Oneshot<MatchSegmentAction>;
@@ -187,14 +312,10 @@ impl OneshotAction for MatchSegmentAction {
     type Output = ();
 
     fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
-        let s = cx
-            .cursor()
-            .next()
-            .ok_or_else(|| crate::error::not_found("not matched"))?;
-        if s == *self.encoded {
-            Ok(())
-        } else {
-            Err(crate::error::not_found("not matched"))
+        let position = cx.cursor().num_popped_segments();
+        match cx.cursor().next() {
+            Some(s) if s == *self.encoded => Ok(()),
+            _ => Err(SegmentMismatch::new(self.encoded, position).into()),
         }
     }
 }
@@ -215,6 +336,12 @@ pub struct MatchEos {
 
 impl IsEndpoint for MatchEos {}
 
+impl LiteralPattern for MatchEos {
+    fn literal_pattern(&self, pattern: &mut RoutePattern) {
+        pattern.set_exact();
+    }
+}
+
 impl<Bd> Endpoint<Bd> for MatchEos {
     type Output = ();
     type Action = Oneshot<MatchEosAction>;
@@ -247,6 +374,11 @@ impl OneshotAction for MatchEosAction {
 ///
 /// This endpoint will skip the current request
 /// if the segments is empty or the conversion is failed.
+///
+/// The segment is fully percent-decoded by the implementations of
+/// `FromEncodedStr` for the primitive types and `String`. The other policies
+/// can be selected by the wrappers in `encoded` (`Raw`, `DecodeExceptSlash`
+/// and `PercentDecoded`).
 #[inline]
 pub fn param<T>() -> Param<T>
 where
@@ -316,11 +448,87 @@ where
     }
 }
 
+// ==== ParamStr ====
+
+/// Create an endpoint which extracts a path segment as a `SharedStr`.
+///
+/// Unlike `param::<String>()`, the extracted value shares the buffer of the
+/// request path (see `Context::shared_path`), so that the extraction does
+/// not allocate unless the segment contains the percent-encoded characters.
+/// This endpoint will skip the current request if the segment is empty.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax::{self, encoded::SharedStr};
+/// # use finchers::test;
+/// let endpoint = syntax::segment("users")
+///     .and(syntax::param_str())
+///     .map(|name: SharedStr| format!("Hello, {}", name));
+///
+/// let mut runner = test::runner(endpoint);
+/// assert_eq!(runner.apply("/users/alice").unwrap(), "Hello, alice");
+/// assert_eq!(runner.apply("/users/J%C3%BCrgen").unwrap(), "Hello, J\u{fc}rgen");
+/// ```
+#[inline]
+pub fn param_str() -> ParamStr {
+    ParamStr(())
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct ParamStr(());
+
+impl IsEndpoint for ParamStr {}
+
+impl<Bd> Endpoint<Bd> for ParamStr {
+    type Output = (SharedStr,);
+    type Action = Oneshot<ParamStrAction>;
+
+    fn action(&self) -> Self::Action {
+        ParamStrAction(()).into_action()
+    }
+}
+
+#[doc(hidden)]
+#[allow(missing_debug_implementations)]
+pub struct ParamStrAction(());
+
+impl OneshotAction for ParamStrAction {
+    type Output = (SharedStr,);
+
+    fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+        let base = cx.uri().path().as_ptr() as usize;
+        let (start, end) = match cx.cursor().next() {
+            Some(s) if !s.as_bytes().is_empty() => {
+                let start = s.as_bytes().as_ptr() as usize - base;
+                (start, start + s.as_bytes().len())
+            }
+            _ => return Err(crate::error::not_found("not matched")),
+        };
+        let shared_path = cx.shared_path();
+        let segment = &shared_path[start..end];
+        if !segment.contains(&b'%') {
+            let value = shared_path.slice(start, end);
+            // The path of the URI consists only of the ASCII characters.
+            return Ok((unsafe { SharedStr::from_bytes_unchecked(value) },));
+        }
+        let decoded = String::from_encoded_str(unsafe { EncodedStr::new_unchecked(segment) })?;
+        Ok((unsafe { SharedStr::from_bytes_unchecked(decoded.into()) },))
+    }
+}
+
 // ==== Remains ====
 
 /// Create an endpoint which parses the remaining path segments into the specified type.
 ///
 /// This endpoint will skip the current request if the conversion is failed.
+///
+/// The remaining path is passed to `FromEncodedStr` as a whole, and hence
+/// the encoded slashes (`%2F`) are indistinguishable from the separators
+/// after being fully decoded. Use `DecodeExceptSlash` or `SafePathBuf` in
+/// `encoded` if the distinction matters.
 #[inline]
 pub fn remains<T>() -> Remains<T>
 where
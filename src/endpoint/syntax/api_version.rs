@@ -0,0 +1,353 @@
+use {
+    crate::{
+        endpoint::{
+            Endpoint, //
+            IsEndpoint,
+            Oneshot,
+            OneshotAction,
+            PreflightContext,
+        },
+        error::{self, Error, HttpError},
+    },
+    http::{
+        header::{self, HeaderName},
+        StatusCode,
+    },
+    std::{fmt, str::FromStr, sync::Arc},
+};
+
+/// A version of the API, such as `v2`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ApiVersion(pub u32);
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = std::num::ParseIntError;
+
+    /// Parses a version in the form of `2` or `v2` (case-insensitive).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = if s.starts_with('v') || s.starts_with('V') {
+            &s[1..]
+        } else {
+            s
+        };
+        s.parse().map(ApiVersion)
+    }
+}
+
+/// The error which indicates that the API version requested by the client
+/// is not supported, or is not specified.
+///
+/// When all the routes of `or` have been rejected, this error takes precedence
+/// over `404 Not Found` from the other routes, so that the client is told the
+/// supported versions.
+#[derive(Debug, failure::Fail)]
+#[fail(display = "{} (supported versions: {})", reason, supported)]
+pub struct UnsupportedApiVersion {
+    reason: String,
+    supported: String,
+    status: StatusCode,
+}
+
+impl HttpError for UnsupportedApiVersion {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+}
+
+/// Create an endpoint which determines the version of the API requested by the client.
+///
+/// The version is looked up from the following sources in order:
+///
+/// * The path prefix such as `/v2/`, which is consumed if matched.
+/// * The custom header, if specified by `header()`.
+/// * The parameter of the media types in `Accept`, such as
+///   `application/vnd.example+json; version=2`.
+///
+/// If the requested version is not supported, the endpoint reports
+/// `406 Not Acceptable` (or `404 Not Found` if specified in the path),
+/// with the message listing the supported versions. If the version is not
+/// specified, the default version (if any) is used.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax::{self, ApiVersion};
+/// # use finchers::test;
+/// # use http::Request;
+/// let version = syntax::api_version(&[1, 2]).header("x-api-version");
+///
+/// let endpoint = version
+///     .only(1)
+///     .map(|| "old")
+///     .or_strict(version.only(2).map(|| "new"));
+///
+/// let mut runner = test::runner(endpoint);
+/// assert_eq!(runner.apply("/v1").unwrap(), "old");
+/// assert_eq!(runner.apply(Request::get("/").header("x-api-version", "2")).unwrap(), "new");
+///
+/// let mut runner = test::runner(version);
+/// assert_eq!(runner.apply("/v2").unwrap(), ApiVersion(2));
+/// ```
+pub fn api_version(supported: &[u32]) -> MatchApiVersion {
+    assert!(
+        !supported.is_empty(),
+        "at least one version must be supported"
+    );
+    MatchApiVersion {
+        config: Arc::new(Config {
+            supported: supported.iter().cloned().map(ApiVersion).collect(),
+            path: true,
+            header: None,
+            accept_param: Some("version".into()),
+            default: None,
+        }),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    supported: Vec<ApiVersion>,
+    path: bool,
+    header: Option<HeaderName>,
+    accept_param: Option<String>,
+    default: Option<ApiVersion>,
+}
+
+impl Config {
+    fn supported_list(&self) -> String {
+        self.supported
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn unsupported(&self, status: StatusCode, reason: impl fmt::Display) -> Error {
+        UnsupportedApiVersion {
+            reason: reason.to_string(),
+            supported: self.supported_list(),
+            status,
+        }
+        .into()
+    }
+
+    fn lookup_path(&self, cx: &mut PreflightContext<'_>) -> Option<ApiVersion> {
+        if !self.path {
+            return None;
+        }
+        let mut orig_cx = cx.clone();
+        let version = {
+            let segment = orig_cx.cursor().next()?.percent_decode().ok()?;
+            if !(segment.starts_with('v') || segment.starts_with('V')) {
+                return None;
+            }
+            segment.parse().ok()?
+        };
+        *cx = orig_cx;
+        Some(version)
+    }
+
+    fn lookup_header(&self, cx: &PreflightContext<'_>) -> Result<Option<ApiVersion>, Error> {
+        let name = match self.header {
+            Some(ref name) => name,
+            None => return Ok(None),
+        };
+        match cx.headers().get(name) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .map(Some)
+                .ok_or_else(|| error::bad_request(format!("invalid header: `{}'", name))),
+            None => Ok(None),
+        }
+    }
+
+    fn lookup_accept(&self, cx: &PreflightContext<'_>) -> Option<ApiVersion> {
+        let param = self.accept_param.as_ref()?;
+        cx.headers()
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .flat_map(|media_type| media_type.split(';').skip(1))
+            .filter_map(|kv| {
+                let mut kv = kv.splitn(2, '=');
+                let key = kv.next()?.trim();
+                let value = kv.next()?.trim().trim_matches('"');
+                if key.eq_ignore_ascii_case(param) {
+                    value.parse().ok()
+                } else {
+                    None
+                }
+            })
+            .next()
+    }
+
+    fn negotiate(&self, cx: &mut PreflightContext<'_>) -> Result<ApiVersion, Error> {
+        if let Some(version) = self.lookup_path(cx) {
+            return if self.supported.contains(&version) {
+                Ok(version)
+            } else {
+                Err(self.unsupported(
+                    StatusCode::NOT_FOUND,
+                    format_args!("unsupported API version: {}", version),
+                ))
+            };
+        }
+
+        let requested = match self.lookup_header(cx)? {
+            Some(version) => Some(version),
+            None => self.lookup_accept(cx),
+        };
+        match requested.or(self.default) {
+            Some(version) if self.supported.contains(&version) => Ok(version),
+            Some(version) => Err(self.unsupported(
+                StatusCode::NOT_ACCEPTABLE,
+                format_args!("unsupported API version: {}", version),
+            )),
+            None => Err(self.unsupported(StatusCode::NOT_FOUND, "missing API version")),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct MatchApiVersion {
+    config: Arc<Config>,
+}
+
+impl MatchApiVersion {
+    fn configure(self, f: impl FnOnce(&mut Config)) -> Self {
+        let mut config = Arc::try_unwrap(self.config).unwrap_or_else(|config| (*config).clone());
+        f(&mut config);
+        MatchApiVersion {
+            config: Arc::new(config),
+        }
+    }
+
+    /// Sets whether to look up the version from the path prefix.
+    ///
+    /// The default value is `true`.
+    pub fn path(self, enabled: bool) -> Self {
+        self.configure(|config| config.path = enabled)
+    }
+
+    /// Sets the name of the custom header which specifies the version.
+    ///
+    /// # Panics
+    /// This method panics if the specified name is not a valid header name.
+    pub fn header(self, name: &str) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes()).expect("invalid header name");
+        self.configure(|config| config.header = Some(name))
+    }
+
+    /// Sets the name of the media type parameter in `Accept` which specifies
+    /// the version, or disables the lookup if `None` is given.
+    ///
+    /// The default value is `Some("version")`.
+    pub fn accept_param(self, name: Option<&str>) -> Self {
+        self.configure(|config| config.accept_param = name.map(Into::into))
+    }
+
+    /// Sets the version used if the client does not specify any version.
+    pub fn default_version(self, version: u32) -> Self {
+        self.configure(|config| config.default = Some(ApiVersion(version)))
+    }
+
+    /// Creates an endpoint which matches only if the negotiated version is
+    /// equal to the specified one.
+    pub fn only(&self, version: u32) -> OnlyApiVersion {
+        OnlyApiVersion {
+            config: self.config.clone(),
+            version: ApiVersion(version),
+        }
+    }
+}
+
+mod match_api_version {
+    use super::*;
+
+    impl IsEndpoint for MatchApiVersion {}
+
+    impl<Bd> Endpoint<Bd> for MatchApiVersion {
+        type Output = (ApiVersion,);
+        type Action = Oneshot<MatchApiVersionAction>;
+
+        fn action(&self) -> Self::Action {
+            MatchApiVersionAction {
+                config: self.config.clone(),
+            }
+            .into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct MatchApiVersionAction {
+        config: Arc<Config>,
+    }
+
+    impl OneshotAction for MatchApiVersionAction {
+        type Output = (ApiVersion,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            self.config.negotiate(cx).map(|version| (version,))
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct OnlyApiVersion {
+    config: Arc<Config>,
+    version: ApiVersion,
+}
+
+mod only_api_version {
+    use super::*;
+
+    impl IsEndpoint for OnlyApiVersion {}
+
+    impl<Bd> Endpoint<Bd> for OnlyApiVersion {
+        type Output = ();
+        type Action = Oneshot<OnlyApiVersionAction>;
+
+        fn action(&self) -> Self::Action {
+            OnlyApiVersionAction {
+                config: self.config.clone(),
+                version: self.version,
+            }
+            .into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct OnlyApiVersionAction {
+        config: Arc<Config>,
+        version: ApiVersion,
+    }
+
+    impl OneshotAction for OnlyApiVersionAction {
+        type Output = ();
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            let version = self.config.negotiate(cx)?;
+            if version == self.version {
+                Ok(())
+            } else {
+                Err(error::not_found(format!(
+                    "not matched (requested {}, expected {})",
+                    version, self.version
+                )))
+            }
+        }
+    }
+}
@@ -1,14 +1,18 @@
 #![allow(missing_docs)]
 
 use {
-    crate::error::{Error, HttpError},
+    crate::{
+        error::{Error, HttpError},
+        util::scan,
+    },
+    bytes::Bytes,
     failure::Fail,
     http::StatusCode,
-    percent_encoding::percent_decode,
     std::{
         borrow::Cow,
         fmt, net,
-        path::PathBuf,
+        ops::Deref,
+        path::{Component, Path, PathBuf},
         str::{self, FromStr, Utf8Error},
     },
 };
@@ -79,7 +83,12 @@ impl EncodedStr {
     /// Decode this encoded string as an UTF-8 string.
     #[inline]
     pub fn percent_decode(&self) -> Result<Cow<'_, str>, Utf8Error> {
-        percent_decode(&self.0).decode_utf8()
+        match scan::percent_decode(&self.0) {
+            Cow::Borrowed(bytes) => str::from_utf8(bytes).map(Cow::Borrowed),
+            Cow::Owned(bytes) => String::from_utf8(bytes)
+                .map(Cow::Owned)
+                .map_err(|e| e.utf8_error()),
+        }
     }
 
     /// Decode this encoded string as an UTF-8 string.
@@ -88,7 +97,13 @@ impl EncodedStr {
     /// replaced to � (U+FFFD).
     #[inline]
     pub fn percent_decode_lossy(&self) -> Cow<'_, str> {
-        percent_decode(&self.0).decode_utf8_lossy()
+        match scan::percent_decode(&self.0) {
+            Cow::Borrowed(bytes) => String::from_utf8_lossy(bytes),
+            Cow::Owned(bytes) => match String::from_utf8(bytes) {
+                Ok(s) => Cow::Owned(s),
+                Err(e) => Cow::Owned(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+            },
+        }
     }
 }
 
@@ -149,6 +164,369 @@ impl FromEncodedStr for PathBuf {
     }
 }
 
+// ==== Decode policies ====
+//
+// The implementations of `FromEncodedStr` for the primitive types and `String`
+// fully decode the input. The following wrappers select another policy.
+
+/// A value parsed from the raw, still percent-encoded string.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::endpoint::syntax::{self, encoded::Raw};
+/// # use finchers::test;
+/// let mut runner = test::runner(syntax::param::<Raw>());
+/// assert_eq!(runner.apply("/a%20b").unwrap().0, "a%20b");
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Raw<T = String>(pub T);
+
+impl<T> FromEncodedStr for Raw<T>
+where
+    T: FromStr + 'static,
+    T::Err: Fail,
+{
+    type Error = Error;
+
+    fn from_encoded_str(s: &EncodedStr) -> Result<Self, Self::Error> {
+        let s = str::from_utf8(s.as_bytes()).map_err(|cause| DecodeEncodedStrError { cause })?;
+        Ok(Raw(s
+            .parse()
+            .map_err(|cause| ParseEncodedStrError { cause })?))
+    }
+}
+
+/// A value parsed from the decoded string, in which the encoded slashes
+/// (`%2F`) and percent signs (`%25`) are left as they are.
+///
+/// This is useful with `remains`, so that the encoded slashes can be
+/// distinguished from the separators of the segments. The percent signs
+/// are also kept encoded so that the result is decoded unambiguously
+/// (e.g. `%252F` is not confused with `%2F`).
+///
+/// # Example
+///
+/// ```
+/// # use finchers::endpoint::syntax::{self, encoded::DecodeExceptSlash};
+/// # use finchers::test;
+/// let mut runner = test::runner(syntax::remains::<DecodeExceptSlash>());
+/// assert_eq!(runner.apply("/a%20b/c%2Fd").unwrap().0, "a b/c%2Fd");
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct DecodeExceptSlash<T = String>(pub T);
+
+impl<T> FromEncodedStr for DecodeExceptSlash<T>
+where
+    T: FromStr + 'static,
+    T::Err: Fail,
+{
+    type Error = Error;
+
+    fn from_encoded_str(s: &EncodedStr) -> Result<Self, Self::Error> {
+        let s =
+            decode_except_slash(s.as_bytes()).map_err(|cause| DecodeEncodedStrError { cause })?;
+        Ok(DecodeExceptSlash(
+            s.parse().map_err(|cause| ParseEncodedStrError { cause })?,
+        ))
+    }
+}
+
+fn decode_except_slash(input: &[u8]) -> Result<String, Utf8Error> {
+    fn hex(b: u8) -> Option<u8> {
+        (b as char).to_digit(16).map(|d| d as u8)
+    }
+
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let escaped = match input.get(i..i + 3) {
+            Some(&[b'%', h, l]) => hex(h).and_then(|h| hex(l).map(|l| h << 4 | l)),
+            _ => None,
+        };
+        match escaped {
+            Some(b) if b != b'/' && b != b'%' => {
+                decoded.push(b);
+                i += 3;
+            }
+            _ => {
+                decoded.push(input[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|e| e.utf8_error())
+}
+
+/// A decoded value along with the original percent-encoded string.
+///
+/// The inner value is converted by its own `FromEncodedStr` implementation,
+/// so this wrapper can be combined with the other policies.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::endpoint::syntax::{self, encoded::PercentDecoded};
+/// # use finchers::test;
+/// let mut runner = test::runner(syntax::param::<PercentDecoded<String>>());
+/// let param = runner.apply("/a%20b").unwrap();
+/// assert_eq!(param.raw(), "a%20b");
+/// assert_eq!(param.value(), "a b");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PercentDecoded<T = String> {
+    raw: String,
+    value: T,
+}
+
+impl<T> PercentDecoded<T> {
+    /// Returns the original percent-encoded string.
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// Returns the reference to the decoded value.
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    /// Converts this value into the decoded value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    /// Converts this value into the pair of the original string and the decoded value.
+    pub fn into_parts(self) -> (String, T) {
+        (self.raw, self.value)
+    }
+}
+
+impl<T> Deref for PercentDecoded<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T> FromEncodedStr for PercentDecoded<T>
+where
+    T: FromEncodedStr,
+{
+    type Error = Error;
+
+    fn from_encoded_str(s: &EncodedStr) -> Result<Self, Self::Error> {
+        let raw = str::from_utf8(s.as_bytes())
+            .map_err(|cause| DecodeEncodedStrError { cause })?
+            .to_owned();
+        let value = T::from_encoded_str(s).map_err(Into::into)?;
+        Ok(PercentDecoded { raw, value })
+    }
+}
+
+/// A relative path decoded from the percent-encoded segments, which is
+/// guaranteed not to escape from the directory it is joined to.
+///
+/// The conversion from `EncodedStr` decodes each segment individually and
+/// rejects the following paths with `400 Bad Request`:
+///
+/// * The segments which are `..`, or contain a NUL character.
+/// * The segments which contain the encoded separators (`%2F` or `%5C`),
+///   or which are interpreted as the absolute paths or the drive prefixes
+///   on the current platform.
+///
+/// The empty segments and `.` are skipped.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax::{self, encoded::SafePathBuf};
+/// # use finchers::test;
+/// let endpoint = syntax::segment("files")
+///     .and(syntax::remains::<SafePathBuf>())
+///     .map(|path: SafePathBuf| format!("{}", path.display()));
+///
+/// let mut runner = test::runner(endpoint);
+/// assert_eq!(runner.apply("/files/a/b%20c.txt").unwrap(), "a/b c.txt");
+/// assert!(runner.apply("/files/a/../../etc/passwd").is_err());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SafePathBuf(PathBuf);
+
+impl SafePathBuf {
+    /// Converts this value into the inner `PathBuf`.
+    pub fn into_path_buf(self) -> PathBuf {
+        self.0
+    }
+}
+
+impl Deref for SafePathBuf {
+    type Target = Path;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for SafePathBuf {
+    #[inline]
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl From<SafePathBuf> for PathBuf {
+    fn from(path: SafePathBuf) -> Self {
+        path.0
+    }
+}
+
+impl FromEncodedStr for SafePathBuf {
+    type Error = Error;
+
+    fn from_encoded_str(s: &EncodedStr) -> Result<Self, Self::Error> {
+        let mut path = PathBuf::new();
+        for segment in s.as_bytes().split(|&b| b == b'/') {
+            let segment = unsafe { EncodedStr::new_unchecked(segment) }
+                .percent_decode()
+                .map_err(|cause| DecodeEncodedStrError { cause })?;
+            match &*segment {
+                "" | "." => continue,
+                ".." => {
+                    return Err(UnsafePathError {
+                        reason: "contains a parent directory",
+                    }
+                    .into())
+                }
+                s if s.contains('\0') => {
+                    return Err(UnsafePathError {
+                        reason: "contains a NUL character",
+                    }
+                    .into());
+                }
+                s if s.contains('/') || s.contains('\\') => {
+                    return Err(UnsafePathError {
+                        reason: "contains an encoded separator",
+                    }
+                    .into());
+                }
+                _ => {}
+            }
+            let mut components = Path::new(&*segment).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(..)), None) => path.push(&*segment),
+                _ => {
+                    return Err(UnsafePathError {
+                        reason: "contains an absolute component",
+                    }
+                    .into())
+                }
+            }
+        }
+        Ok(SafePathBuf(path))
+    }
+}
+
+/// A decoded string which shares the buffer of the request path.
+///
+/// This type is returned from `syntax::param_str`. If the segment does not
+/// contain any percent-encoded characters, the value is a slice of the buffer
+/// returned from `Context::shared_path` and hence the extraction does not
+/// allocate. Otherwise, the decoded string is stored in a new buffer.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SharedStr(Bytes);
+
+impl SharedStr {
+    /// Creates a `SharedStr` from the specified bytes.
+    ///
+    /// # Safety
+    /// The given bytes must be a valid UTF-8 sequence.
+    #[inline]
+    pub unsafe fn from_bytes_unchecked(bytes: Bytes) -> Self {
+        SharedStr(bytes)
+    }
+
+    /// Returns the reference to the string slice.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        unsafe { str::from_utf8_unchecked(&self.0) }
+    }
+
+    /// Converts this value into the underlying buffer.
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+}
+
+impl fmt::Debug for SharedStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for SharedStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl Deref for SharedStr {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for SharedStr {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl std::borrow::Borrow<str> for SharedStr {
+    #[inline]
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<str> for SharedStr {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for SharedStr {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl From<SharedStr> for String {
+    fn from(s: SharedStr) -> Self {
+        s.as_str().to_owned()
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Fail)]
+#[fail(display = "unsafe path: {}", reason)]
+pub struct UnsafePathError {
+    reason: &'static str,
+}
+
+impl HttpError for UnsafePathError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
 #[allow(missing_docs)]
 #[derive(Debug, Fail)]
 #[fail(display = "failed to decode a percent encoded string to UTF-8")]
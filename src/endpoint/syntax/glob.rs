@@ -0,0 +1,427 @@
+use {
+    crate::{
+        endpoint::{
+            Endpoint, //
+            IsEndpoint,
+            Oneshot,
+            OneshotAction,
+            PreflightContext,
+        },
+        error::{self, Error},
+    },
+    std::{
+        borrow::Cow,
+        ops::{Deref, Range},
+        sync::Arc,
+    },
+};
+
+/// Create an endpoint which matches the remaining path to a glob pattern.
+///
+/// The pattern consists of the segments separated by `/`, and each segment
+/// may contain the following wildcards:
+///
+/// * `*` - matches zero or more characters within a segment.
+/// * `?` - matches exactly one character within a segment.
+/// * `**` - matches zero or more segments. It must be a whole segment.
+///
+/// The other characters are matched literally against the percent-decoded
+/// segments of the request. The endpoint consumes all of the remaining
+/// segments, and outputs the portions matched by the wildcards, in order of
+/// appearance, as `GlobCaptures`. The segments matched by `**` are joined
+/// with `/`.
+///
+/// The pattern is compiled when this function is called, and the matching
+/// is skipped early if the number of segments cannot satisfy the pattern.
+///
+/// # Panics
+/// This function panics if `**` is used as a part of segment.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax::{self, GlobCaptures};
+/// # use finchers::test;
+/// let mut runner = test::runner(
+///     syntax::glob("static/**/*.js")
+///         .map(|captures: GlobCaptures| format!("{}|{}", captures[0], captures[1])),
+/// );
+/// assert_eq!(runner.apply("/static/js/vendor/app.js").unwrap(), "js/vendor|app");
+/// assert_eq!(runner.apply("/static/app.js").unwrap(), "|app");
+/// assert!(runner.apply("/static/app.css").is_err());
+/// ```
+pub fn glob(pattern: &str) -> MatchGlob {
+    MatchGlob {
+        pattern: Arc::new(Pattern::compile(pattern)),
+    }
+}
+
+/// The portions of path captured by the wildcards in `syntax::glob`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GlobCaptures(Vec<String>);
+
+impl GlobCaptures {
+    /// Consumes itself and returns the captured values.
+    pub fn into_vec(self) -> Vec<String> {
+        self.0
+    }
+}
+
+impl Deref for GlobCaptures {
+    type Target = [String];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(Debug)]
+struct Pattern {
+    source: String,
+    segments: Vec<Segment>,
+    min_segments: usize,
+    has_any_segments: bool,
+}
+
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    Wildcard(Vec<Part>),
+    AnySegments,
+}
+
+#[derive(Debug)]
+enum Part {
+    Literal(String),
+    Star,
+    Question,
+}
+
+impl Pattern {
+    fn compile(source: &str) -> Self {
+        let trimmed = source.trim_start_matches('/');
+        let segments: Vec<Segment> = if trimmed.is_empty() {
+            vec![]
+        } else {
+            trimmed.split('/').map(Segment::compile).collect()
+        };
+        let num_any_segments = segments
+            .iter()
+            .filter(|segment| segment.is_any_segments())
+            .count();
+        let min_segments = segments.len() - num_any_segments;
+        let has_any_segments = num_any_segments > 0;
+        Pattern {
+            source: source.into(),
+            segments,
+            min_segments,
+            has_any_segments,
+        }
+    }
+
+    fn matches(&self, path: &[Cow<'_, str>]) -> Option<Vec<String>> {
+        if path.len() < self.min_segments
+            || (!self.has_any_segments && path.len() != self.min_segments)
+        {
+            return None;
+        }
+        // The captures are recorded as ranges while backtracking, and
+        // the strings are built only once the whole pattern has matched.
+        let mut captures = vec![];
+        let mut failed = vec![false; (self.segments.len() + 1) * (path.len() + 1)];
+        if !match_segments(&self.segments, path, 0, 0, &mut captures, &mut failed) {
+            return None;
+        }
+        Some(
+            captures
+                .into_iter()
+                .map(|capture| match capture {
+                    Capture::Segments(range) => path[range].join("/"),
+                    Capture::Chars(i, range) => path[i][range].to_owned(),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// The position of a portion captured by a wildcard.
+#[derive(Debug)]
+enum Capture {
+    /// The range of segments matched by `**`.
+    Segments(Range<usize>),
+    /// The range of bytes in a segment matched by `*` or `?`.
+    Chars(usize, Range<usize>),
+}
+
+impl Segment {
+    fn is_any_segments(&self) -> bool {
+        match self {
+            Segment::AnySegments => true,
+            _ => false,
+        }
+    }
+
+    fn compile(segment: &str) -> Self {
+        if segment == "**" {
+            return Segment::AnySegments;
+        }
+        assert!(
+            !segment.contains("**"),
+            "`**` must be a whole segment of the glob pattern"
+        );
+        if !segment.contains(&['*', '?'][..]) {
+            return Segment::Literal(segment.into());
+        }
+
+        let mut parts = vec![];
+        let mut literal = String::new();
+        for c in segment.chars() {
+            let part = match c {
+                '*' => Part::Star,
+                '?' => Part::Question,
+                c => {
+                    literal.push(c);
+                    continue;
+                }
+            };
+            if !literal.is_empty() {
+                parts.push(Part::Literal(literal.clone()));
+                literal.clear();
+            }
+            parts.push(part);
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Segment::Wildcard(parts)
+    }
+}
+
+/// Matches the pattern from the `i`-th segment against `path[pos..]`.
+///
+/// The result depends only on `(i, pos)`, so the pairs which have failed to
+/// match are recorded in `failed` and are not tried again. This bounds the
+/// backtracking of `**` to a polynomial time.
+fn match_segments(
+    pattern: &[Segment],
+    path: &[Cow<'_, str>],
+    i: usize,
+    pos: usize,
+    captures: &mut Vec<Capture>,
+    failed: &mut [bool],
+) -> bool {
+    let segment = match pattern.get(i) {
+        Some(segment) => segment,
+        None => return pos == path.len(),
+    };
+    let key = i * (path.len() + 1) + pos;
+    if failed[key] {
+        return false;
+    }
+    let len = captures.len();
+    let matched = match segment {
+        Segment::AnySegments => {
+            // The shortest match is tried first.
+            let mut matched = false;
+            for end in pos..=path.len() {
+                captures.push(Capture::Segments(pos..end));
+                if match_segments(pattern, path, i + 1, end, captures, failed) {
+                    matched = true;
+                    break;
+                }
+                captures.truncate(len);
+            }
+            matched
+        }
+        Segment::Literal(literal) => match path.get(pos) {
+            Some(s) if s == literal => {
+                match_segments(pattern, path, i + 1, pos + 1, captures, failed)
+            }
+            _ => false,
+        },
+        Segment::Wildcard(parts) => match path.get(pos) {
+            Some(s) => {
+                let mut failed_parts = vec![false; (parts.len() + 1) * (s.len() + 1)];
+                match_parts(parts, s, 0, 0, pos, captures, &mut failed_parts)
+                    && match_segments(pattern, path, i + 1, pos + 1, captures, failed)
+            }
+            None => false,
+        },
+    };
+    if !matched {
+        captures.truncate(len);
+        failed[key] = true;
+    }
+    matched
+}
+
+/// Matches the parts from the `i`-th one against `s[start..]`, where `s` is
+/// the `index`-th segment of the path.
+///
+/// As in `match_segments`, the failed pairs of `(i, start)` are memoized.
+fn match_parts(
+    parts: &[Part],
+    s: &str,
+    i: usize,
+    start: usize,
+    index: usize,
+    captures: &mut Vec<Capture>,
+    failed: &mut [bool],
+) -> bool {
+    let part = match parts.get(i) {
+        Some(part) => part,
+        None => return start == s.len(),
+    };
+    let key = i * (s.len() + 1) + start;
+    if failed[key] {
+        return false;
+    }
+    let len = captures.len();
+    let matched = match part {
+        Part::Literal(literal) => {
+            s[start..].starts_with(&**literal)
+                && match_parts(
+                    parts,
+                    s,
+                    i + 1,
+                    start + literal.len(),
+                    index,
+                    captures,
+                    failed,
+                )
+        }
+        Part::Question => match s[start..].chars().next() {
+            Some(c) => {
+                let end = start + c.len_utf8();
+                captures.push(Capture::Chars(index, start..end));
+                match_parts(parts, s, i + 1, end, index, captures, failed)
+            }
+            None => false,
+        },
+        Part::Star => {
+            let boundaries = s[start..]
+                .char_indices()
+                .map(|(offset, _)| start + offset)
+                .chain(std::iter::once(s.len()));
+            let mut matched = false;
+            for end in boundaries {
+                captures.push(Capture::Chars(index, start..end));
+                if match_parts(parts, s, i + 1, end, index, captures, failed) {
+                    matched = true;
+                    break;
+                }
+                captures.truncate(len);
+            }
+            matched
+        }
+    };
+    if !matched {
+        captures.truncate(len);
+        failed[key] = true;
+    }
+    matched
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct MatchGlob {
+    pattern: Arc<Pattern>,
+}
+
+impl IsEndpoint for MatchGlob {}
+
+impl<Bd> Endpoint<Bd> for MatchGlob {
+    type Output = (GlobCaptures,);
+    type Action = Oneshot<MatchGlobAction>;
+
+    fn action(&self) -> Self::Action {
+        MatchGlobAction {
+            pattern: self.pattern.clone(),
+        }
+        .into_action()
+    }
+}
+
+#[doc(hidden)]
+#[allow(missing_debug_implementations)]
+pub struct MatchGlobAction {
+    pattern: Arc<Pattern>,
+}
+
+impl OneshotAction for MatchGlobAction {
+    type Output = (GlobCaptures,);
+
+    fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+        let segments = cx
+            .cursor()
+            .map(|segment| segment.percent_decode())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| error::not_found("not matched"))?;
+        match self.pattern.matches(&segments) {
+            Some(captures) => Ok((GlobCaptures(captures),)),
+            None => Err(error::not_found(format!(
+                "not matched to the pattern `{}'",
+                self.pattern.source
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captures(pattern: &str, path: &str) -> Option<Vec<String>> {
+        let segments: Vec<Cow<'_, str>> = path.split('/').map(Cow::Borrowed).collect();
+        Pattern::compile(pattern).matches(&segments)
+    }
+
+    #[test]
+    fn test_literal() {
+        assert_eq!(captures("/foo/bar", "foo/bar"), Some(vec![]));
+        assert_eq!(captures("/foo/bar", "foo/baz"), None);
+        assert_eq!(captures("/foo/bar", "foo/bar/baz"), None);
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert_eq!(
+            captures("img/*.??g", "img/logo.png"),
+            Some(vec!["logo".into(), "p".into(), "n".into()])
+        );
+        assert_eq!(
+            captures("*-*", "a-b-c"),
+            Some(vec!["a".into(), "b-c".into()])
+        );
+        assert_eq!(captures("img/*.png", "img/logo.svg"), None);
+        assert_eq!(captures("?", "あ"), Some(vec!["あ".into()]));
+    }
+
+    #[test]
+    fn test_any_segments() {
+        assert_eq!(
+            captures("a/**/b/**", "a/x/b/y/b/z"),
+            Some(vec!["x".into(), "y/b/z".into()])
+        );
+        assert_eq!(captures("**", "x/y"), Some(vec!["x/y".into()]));
+        assert_eq!(captures("a/**/c", "a/b"), None);
+    }
+
+    #[test]
+    fn test_adversarial_path() {
+        let path = vec!["a"; 64].join("/");
+        assert_eq!(captures(&format!("{}b", "**/a/".repeat(16)), &path), None);
+        assert_eq!(captures("/**/a/**/a/**/b", &path), None);
+
+        let segment = "a".repeat(64);
+        assert_eq!(captures(&format!("{}b", "*a".repeat(16)), &segment), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_any_segments() {
+        let _ = glob("static/**.js");
+    }
+}
@@ -0,0 +1,263 @@
+//! Endpoints for the path segments with the matrix parameters.
+//!
+//! The matrix parameters are the `;`-separated key-value pairs appended to
+//! the path segments, such as `/cars;color=red/doors;count=4`.
+//! The endpoints in `syntax` compare the whole segment including them, and
+//! the endpoints in this module need to be used instead in order to accept them.
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::endpoint::syntax::matrix::{self, MatrixParams};
+//! # use finchers::test;
+//! let endpoint = matrix::segment("cars")
+//!     .and(matrix::param::<u32>())
+//!     .map(|cars: MatrixParams, id: u32, _: MatrixParams| {
+//!         let color: Option<String> = cars.get("color").unwrap();
+//!         format!("{}: {}", id, color.unwrap_or_else(|| "any".into()))
+//!     });
+//!
+//! let mut runner = test::runner(endpoint);
+//! assert_eq!(runner.apply("/cars;color=red/42").unwrap(), "42: red");
+//! assert_eq!(runner.apply("/cars/42;doors=4").unwrap(), "42: any");
+//! ```
+
+use {
+    super::{encoded::EncodedStr, FromEncodedStr, SEGMENT_ENCODE_SET},
+    crate::{
+        endpoint::{
+            Endpoint,
+            IsEndpoint,
+            Oneshot,
+            OneshotAction,
+            PreflightContext, //
+        },
+        error::Error,
+    },
+    percent_encoding::percent_encode,
+    std::{fmt, marker::PhantomData, sync::Arc},
+};
+
+/// Splits the matrix parameters off the specified segment.
+///
+/// The parameters without `=` are regarded as the ones with an empty value.
+pub fn split(segment: &EncodedStr) -> (&EncodedStr, MatrixParams) {
+    let mut parts = segment.as_bytes().split(|&b| b == b';');
+    let path = parts.next().unwrap_or(&[]);
+    let params = parts
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let mut kv = param.splitn(2, |&b| b == b'=');
+            let key = kv.next().unwrap_or(&[]);
+            let value = kv.next().unwrap_or(&[]);
+            (key.to_owned(), value.to_owned())
+        })
+        .collect();
+    let path = unsafe { EncodedStr::new_unchecked(path) };
+    (path, MatrixParams { params })
+}
+
+/// A collection of the matrix parameters extracted from a segment.
+///
+/// The keys and values are kept percent-encoded and are decoded on access.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct MatrixParams {
+    params: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl fmt::Debug for MatrixParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.iter()
+                    .map(|(k, v)| (k.percent_decode_lossy(), v.percent_decode_lossy())),
+            )
+            .finish()
+    }
+}
+
+impl MatrixParams {
+    /// Returns `true` if no parameters are specified.
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// Returns the number of parameters.
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Returns `true` if the parameter with the specified key exists.
+    pub fn contains(&self, key: &str) -> bool {
+        self.values(key).next().is_some()
+    }
+
+    /// Returns an iterator over the pairs of the encoded keys and values.
+    pub fn iter(&self) -> impl Iterator<Item = (&EncodedStr, &EncodedStr)> {
+        self.params
+            .iter()
+            .map(|(k, v)| unsafe { (EncodedStr::new_unchecked(k), EncodedStr::new_unchecked(v)) })
+    }
+
+    fn values<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a EncodedStr> + 'a {
+        self.iter()
+            .filter(move |(k, _)| k.percent_decode_lossy() == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Parses the first value of the specified key into the type `T`.
+    ///
+    /// This method returns `Ok(None)` if the key does not exist.
+    pub fn get<T>(&self, key: &str) -> Result<Option<T>, Error>
+    where
+        T: FromEncodedStr,
+    {
+        match self.values(key).next() {
+            Some(value) => T::from_encoded_str(value).map(Some).map_err(Into::into),
+            None => Ok(None),
+        }
+    }
+
+    /// Parses all of the values of the specified key into the type `T`.
+    pub fn get_all<T>(&self, key: &str) -> Result<Vec<T>, Error>
+    where
+        T: FromEncodedStr,
+    {
+        self.values(key)
+            .map(|value| T::from_encoded_str(value).map_err(Into::into))
+            .collect()
+    }
+}
+
+// ==== MatchSegment ====
+
+/// Create an endpoint which validates a path segment, ignoring its matrix parameters.
+///
+/// The matrix parameters of the segment are returned from the endpoint.
+pub fn segment(s: impl AsRef<str>) -> MatchSegment {
+    let s = s.as_ref();
+    debug_assert!(!s.is_empty());
+    MatchSegment {
+        encoded: Arc::new(percent_encode(s.as_bytes(), SEGMENT_ENCODE_SET).to_string()),
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct MatchSegment {
+    encoded: Arc<String>,
+}
+
+impl IsEndpoint for MatchSegment {}
+
+impl<Bd> Endpoint<Bd> for MatchSegment {
+    type Output = (MatrixParams,);
+    type Action = Oneshot<MatchSegmentAction>;
+
+    fn action(&self) -> Self::Action {
+        MatchSegmentAction {
+            encoded: self.encoded.clone(),
+        }
+        .into_action()
+    }
+}
+
+#[doc(hidden)]
+#[allow(missing_debug_implementations)]
+pub struct MatchSegmentAction {
+    encoded: Arc<String>,
+}
+
+impl OneshotAction for MatchSegmentAction {
+    type Output = (MatrixParams,);
+
+    fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+        let s = cx
+            .cursor()
+            .next()
+            .ok_or_else(|| crate::error::not_found("not matched"))?;
+        let (path, params) = split(s);
+        if path == *self.encoded {
+            Ok((params,))
+        } else {
+            Err(crate::error::not_found("not matched"))
+        }
+    }
+}
+
+// ==== Param ====
+
+/// Create an endpoint which parses a path segment into the specified type,
+/// along with its matrix parameters.
+///
+/// The matrix parameters are split off before the conversion.
+#[inline]
+pub fn param<T>() -> Param<T>
+where
+    T: FromEncodedStr,
+{
+    Param {
+        _marker: PhantomData,
+    }
+}
+
+#[allow(missing_docs)]
+pub struct Param<T> {
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Copy for Param<T> {}
+
+impl<T> Clone for Param<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> fmt::Debug for Param<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Param").finish()
+    }
+}
+
+impl<T: FromEncodedStr> IsEndpoint for Param<T> {}
+
+impl<T, Bd> Endpoint<Bd> for Param<T>
+where
+    T: FromEncodedStr,
+{
+    type Output = (T, MatrixParams);
+    type Action = Oneshot<ParamAction<T>>;
+
+    fn action(&self) -> Self::Action {
+        ParamAction {
+            _marker: PhantomData,
+        }
+        .into_action()
+    }
+}
+
+#[doc(hidden)]
+#[allow(missing_debug_implementations)]
+pub struct ParamAction<T> {
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> OneshotAction for ParamAction<T>
+where
+    T: FromEncodedStr,
+{
+    type Output = (T, MatrixParams);
+
+    fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+        let s = cx
+            .cursor()
+            .next()
+            .ok_or_else(|| crate::error::not_found("not matched"))?;
+        let (path, params) = split(s);
+        let x = T::from_encoded_str(path).map_err(Into::into)?;
+        Ok((x, params))
+    }
+}
@@ -3,6 +3,7 @@
 use {
     crate::{
         endpoint::{
+            ext::{LiteralPattern, RoutePattern},
             Endpoint,
             IsEndpoint,
             Oneshot,
@@ -29,6 +30,12 @@ pub struct MatchVerbs {
 
 impl IsEndpoint for MatchVerbs {}
 
+impl LiteralPattern for MatchVerbs {
+    fn literal_pattern(&self, pattern: &mut RoutePattern) {
+        pattern.restrict_verbs(self.allowed);
+    }
+}
+
 impl<Bd> Endpoint<Bd> for MatchVerbs {
     type Output = ();
     type Action = Oneshot<MatchVerbsAction>;
@@ -54,7 +61,84 @@ impl OneshotAction for MatchVerbsAction {
         if self.allowed.contains(cx.method()) {
             Ok(())
         } else {
-            Err(crate::error::method_not_allowed("invalid method"))
+            let allowed: Vec<_> = self.allowed.into_iter().map(Method::as_str).collect();
+            Err(crate::error::method_not_allowed(format!(
+                "invalid method (expected {})",
+                allowed.join(", ")
+            )))
+        }
+    }
+}
+
+/// Create an endpoint which checks if the verb of current request is
+/// equal to the specified extension method, such as `PROPFIND`.
+///
+/// # Panics
+/// This function panics if the specified name is not a valid method.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax::verb;
+/// # use finchers::test;
+/// # use http::Request;
+/// let propfind = verb::custom("PROPFIND").map(|| "propfind");
+/// let get = verb::get().map(|| "get");
+///
+/// let mut runner = test::runner(propfind.or_strict(get));
+/// assert_eq!(runner.apply(Request::builder().method("PROPFIND").uri("/")).unwrap(), "propfind");
+/// assert_eq!(runner.apply("/").unwrap(), "get");
+/// ```
+pub fn custom(method: &str) -> MatchCustomVerb {
+    MatchCustomVerb {
+        method: Method::from_bytes(method.as_bytes()).expect("invalid method"),
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct MatchCustomVerb {
+    method: Method,
+}
+
+impl IsEndpoint for MatchCustomVerb {}
+
+impl LiteralPattern for MatchCustomVerb {
+    fn literal_pattern(&self, pattern: &mut RoutePattern) {
+        pattern.restrict_method(self.method.clone());
+    }
+}
+
+impl<Bd> Endpoint<Bd> for MatchCustomVerb {
+    type Output = ();
+    type Action = Oneshot<MatchCustomVerbAction>;
+
+    fn action(&self) -> Self::Action {
+        MatchCustomVerbAction {
+            method: self.method.clone(),
+        }
+        .into_action()
+    }
+}
+
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct MatchCustomVerbAction {
+    method: Method,
+}
+
+impl OneshotAction for MatchCustomVerbAction {
+    type Output = ();
+
+    fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+        if *cx.method() == self.method {
+            Ok(())
+        } else {
+            Err(crate::error::method_not_allowed(format!(
+                "invalid method (expected {})",
+                self.method
+            )))
         }
     }
 }
@@ -80,6 +164,12 @@ macro_rules! define_verbs {
 
         impl IsEndpoint for $Endpoint {}
 
+        impl LiteralPattern for $Endpoint {
+            fn literal_pattern(&self, pattern: &mut RoutePattern) {
+                pattern.restrict_verbs(Verbs::$METHOD);
+            }
+        }
+
         impl<Bd> Endpoint<Bd> for $Endpoint {
             type Output = ();
             type Action = Oneshot<$Action>;
@@ -102,7 +192,11 @@ macro_rules! define_verbs {
                 if *cx.method() == Method::$METHOD {
                     Ok(())
                 } else {
-                    Err(crate::error::method_not_allowed("invalid method"))
+                    Err(crate::error::method_not_allowed(concat!(
+                        "invalid method (expected ",
+                        stringify!($METHOD),
+                        ")"
+                    )))
                 }
             }
         }
@@ -0,0 +1,324 @@
+//! Components for wrapping endpoints.
+//!
+//! A `Wrapper` abstracts a transformation from an endpoint into another one,
+//! and is used for writing the cross-cutting concerns that can be applied
+//! to arbitrary endpoints with `EndpointExt::wrap`.
+
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, EndpointExt, IsEndpoint},
+        error::{self, Error},
+        output::IntoResponse,
+    },
+    futures::{Async, Future, Poll},
+    http::{Response, StatusCode},
+    std::time::{Duration, Instant},
+    tokio::timer::Delay,
+};
+
+mod diagnostics;
+mod feature_flag;
+mod flight_recorder;
+mod latency_budget;
+mod pipeline;
+mod rewrite;
+mod security_headers;
+mod tarpit;
+mod trace;
+mod trace_context;
+#[cfg(all(feature = "diesel", feature = "r2d2"))]
+mod transactional;
+mod when;
+
+pub use {
+    self::diagnostics::{
+        route_diagnostics, RouteDiagnostics, RouteDiagnosticsAction, RouteDiagnosticsEndpoint,
+    },
+    self::feature_flag::{
+        feature_flag, CachedFlags, EnvFlags, FeatureFlag, FeatureFlagAction, FeatureFlagEndpoint,
+        FileFlags, FlagProvider, FlagRule, StaticFlags,
+    },
+    self::flight_recorder::{
+        FlightRecorder, FlightRecorderAction, FlightRecorderEndpoint, FlightRecorderWrapper,
+        InFlightRequest, RequestPhase,
+    },
+    self::latency_budget::{
+        latency_budget, BudgetMetrics, BudgetStats, BudgetViolation, LatencyBudget,
+        LatencyBudgetAction, LatencyBudgetEndpoint, PhaseTimings,
+    },
+    self::pipeline::{pipeline, Identity, Pipeline, Stack},
+    self::rewrite::{
+        rewrite_request, RequestMut, RewriteRequest, RewriteRequestAction, RewriteRequestEndpoint,
+    },
+    self::security_headers::{
+        security_headers, ContentSecurityPolicy, FrameOptions, SecurityHeaders,
+        SecurityHeadersAction, SecurityHeadersEndpoint,
+    },
+    self::tarpit::{is_suspicious_path, tarpit, Tarpit, TarpitAction, TarpitEndpoint},
+    self::trace::{trace, Trace, TraceAction, TraceEndpoint},
+    self::trace_context::{
+        propagate_trace_context, PropagateTraceContext, PropagateTraceContextAction,
+        PropagateTraceContextEndpoint, TraceContext,
+    },
+    self::when::{
+        env_flag, header_present, path_prefix, when, EnvFlag, HeaderPresent, PathPrefix,
+        RequestPredicate, When, WhenAction, WhenEndpoint,
+    },
+    crate::endpoint::ext::Map,
+};
+
+#[cfg(all(feature = "diesel", feature = "r2d2"))]
+pub use self::transactional::{
+    transactional, Transactional, TransactionalAction, TransactionalEndpoint,
+};
+
+/// A trait representing a transformation of an endpoint into another one.
+pub trait Wrapper<E> {
+    /// The type of endpoint returned from `wrap`.
+    type Endpoint;
+
+    /// Wraps the specified endpoint.
+    fn wrap(self, endpoint: E) -> Self::Endpoint;
+}
+
+/// Creates a `Wrapper` which maps the output of the wrapped endpoint
+/// using the specified function.
+///
+/// The wrapped endpoint behaves the same as the one created by `EndpointExt::map`.
+pub fn map_output<F>(f: F) -> MapOutput<F> {
+    MapOutput { f }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct MapOutput<F> {
+    f: F,
+}
+
+impl<E, F> Wrapper<E> for MapOutput<F>
+where
+    E: IsEndpoint,
+{
+    type Endpoint = Map<E, F>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        endpoint.map(self.f)
+    }
+}
+
+/// Creates a `Wrapper` which converts the output of the wrapped endpoint
+/// into an HTTP response and then maps it using the specified function.
+///
+/// This wrapper is useful for the post-processing of responses, such as
+/// injecting the headers or rewriting the response bodies.
+/// Note that the errors returned from the wrapped endpoint are passed through
+/// without applying the function.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::wrapper;
+/// # use finchers::test;
+/// let endpoint = endpoint::unit()
+///     .map(|| "Hello")
+///     .wrap(wrapper::map_response(|mut response: http::Response<_>| {
+///         response
+///             .headers_mut()
+///             .insert("x-powered-by", "finchers".parse().unwrap());
+///         response
+///     }));
+///
+/// let mut runner = test::runner(endpoint);
+/// runner
+///     .perform("/")
+///     .unwrap()
+///     .assert_header("x-powered-by", "finchers")
+///     .assert_body("Hello");
+/// ```
+pub fn map_response<F>(f: F) -> MapResponse<F> {
+    MapResponse { f }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct MapResponse<F> {
+    f: F,
+}
+
+impl<E, F> Wrapper<E> for MapResponse<F>
+where
+    E: IsEndpoint,
+{
+    type Endpoint = MapResponseEndpoint<E, F>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        MapResponseEndpoint {
+            endpoint,
+            f: self.f,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct MapResponseEndpoint<E, F> {
+    endpoint: E,
+    f: F,
+}
+
+impl<E: IsEndpoint, F> IsEndpoint for MapResponseEndpoint<E, F> {}
+
+impl<E, F, Bd, T> Endpoint<Bd> for MapResponseEndpoint<E, F>
+where
+    E: Endpoint<Bd>,
+    E::Output: IntoResponse,
+    F: Fn(Response<<E::Output as IntoResponse>::Body>) -> Response<T> + Clone,
+{
+    type Output = (Response<T>,);
+    type Action = MapResponseAction<E::Action, F>;
+
+    fn action(&self) -> Self::Action {
+        MapResponseAction {
+            action: self.endpoint.action(),
+            f: self.f.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct MapResponseAction<A, F> {
+    action: A,
+    f: F,
+}
+
+impl<A, F, Bd, T> EndpointAction<Bd> for MapResponseAction<A, F>
+where
+    A: EndpointAction<Bd>,
+    A::Output: IntoResponse,
+    F: Fn(Response<<A::Output as IntoResponse>::Body>) -> Response<T>,
+{
+    type Output = (Response<T>,);
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let preflight = self.action.preflight(cx)?;
+        let f = &self.f;
+        Ok(preflight.map(|output| (f(output.into_response(cx.request())),)))
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        let output = futures::try_ready!(self.action.poll_action(cx));
+        let response = output.into_response(cx.request());
+        Ok(((self.f)(response),).into())
+    }
+}
+
+/// Creates a `Wrapper` which limits the processing time of the wrapped endpoint.
+///
+/// The deadline is set to the cancellation token of the request when the
+/// endpoint is applied, so that the background tasks observing the token
+/// (or `endpoints::on_disconnect`) are also stopped.
+/// If the wrapped endpoint does not complete before the deadline, the token
+/// is cancelled and the request is rejected with `503 Service Unavailable`.
+pub fn timeout(duration: Duration) -> Timeout {
+    Timeout { duration }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct Timeout {
+    duration: Duration,
+}
+
+impl<E> Wrapper<E> for Timeout
+where
+    E: IsEndpoint,
+{
+    type Endpoint = TimeoutEndpoint<E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        TimeoutEndpoint {
+            endpoint,
+            duration: self.duration,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct TimeoutEndpoint<E> {
+    endpoint: E,
+    duration: Duration,
+}
+
+impl<E: IsEndpoint> IsEndpoint for TimeoutEndpoint<E> {}
+
+impl<E, Bd> Endpoint<Bd> for TimeoutEndpoint<E>
+where
+    E: Endpoint<Bd>,
+{
+    type Output = E::Output;
+    type Action = TimeoutAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        TimeoutAction {
+            action: self.endpoint.action(),
+            duration: self.duration,
+            deadline: None,
+            delay: None,
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct TimeoutAction<A> {
+    action: A,
+    duration: Duration,
+    deadline: Option<Instant>,
+    delay: Option<Delay>,
+}
+
+impl<A, Bd> EndpointAction<Bd> for TimeoutAction<A>
+where
+    A: EndpointAction<Bd>,
+{
+    type Output = A::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let deadline = tokio::clock::now() + self.duration;
+        cx.cancellation().set_deadline(deadline);
+        self.deadline = Some(deadline);
+        self.action.preflight(cx)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        if let Async::Ready(output) = self.action.poll_action(cx)? {
+            return Ok(Async::Ready(output));
+        }
+
+        let deadline = self.deadline.expect("the action has not been preflighted");
+        let delay = self.delay.get_or_insert_with(|| Delay::new(deadline));
+        match delay.poll().map_err(error::internal_server_error)? {
+            Async::Ready(()) => {
+                cx.cancellation().cancel();
+                Err(error::err_msg(
+                    "the request has timed out",
+                    StatusCode::SERVICE_UNAVAILABLE,
+                ))
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
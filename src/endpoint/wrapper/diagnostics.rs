@@ -0,0 +1,130 @@
+use {
+    super::Wrapper,
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{ext::NotMatched, Endpoint, IsEndpoint},
+        error::{self, Error},
+    },
+    futures::Poll,
+    std::fmt::Write,
+};
+
+/// Creates a `Wrapper` which enumerates the near-miss routes in the body of
+/// the error response, when the wrapped endpoint could not determine the route.
+///
+/// The candidates are collected by `NotMatched::candidates`, i.e. the routes
+/// rejected for a reason other than `404 Not Found`, such as the wrong method
+/// or the wrong content type. The status code of the response is not changed.
+///
+/// This wrapper is intended for the use during the development of the large
+/// `or` trees, and should not be enabled in production since it exposes the
+/// details of the routes.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::{syntax, wrapper};
+/// # use finchers::test;
+/// let endpoint = syntax::verb::get()
+///     .and(syntax::segment("foo"))
+///     .map(|| "foo")
+///     .or(syntax::segment("bar").map(|| "bar"))
+///     .wrap(wrapper::route_diagnostics());
+///
+/// let mut runner = test::runner(endpoint);
+/// let response = runner.perform(http::Request::post("/foo")).unwrap();
+/// assert_eq!(response.status().as_u16(), 404);
+/// assert!(response
+///     .to_utf8_lossy()
+///     .contains("405 Method Not Allowed: invalid method (expected GET)"));
+/// ```
+pub fn route_diagnostics() -> RouteDiagnostics {
+    RouteDiagnostics(())
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct RouteDiagnostics(());
+
+impl<E> Wrapper<E> for RouteDiagnostics
+where
+    E: IsEndpoint,
+{
+    type Endpoint = RouteDiagnosticsEndpoint<E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        RouteDiagnosticsEndpoint { endpoint }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct RouteDiagnosticsEndpoint<E> {
+    endpoint: E,
+}
+
+impl<E: IsEndpoint> IsEndpoint for RouteDiagnosticsEndpoint<E> {}
+
+impl<E, Bd> Endpoint<Bd> for RouteDiagnosticsEndpoint<E>
+where
+    E: Endpoint<Bd>,
+{
+    type Output = E::Output;
+    type Action = RouteDiagnosticsAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        RouteDiagnosticsAction {
+            action: self.endpoint.action(),
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct RouteDiagnosticsAction<A> {
+    action: A,
+}
+
+impl<A, Bd> EndpointAction<Bd> for RouteDiagnosticsAction<A>
+where
+    A: EndpointAction<Bd>,
+{
+    type Output = A::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        self.action.preflight(cx).map_err(|err| {
+            let report = match err.downcast_ref::<NotMatched>() {
+                Some(not_matched) => report(not_matched),
+                None => return err,
+            };
+            error::err_msg(report, err.status_code())
+        })
+    }
+
+    #[inline]
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        self.action.poll_action(cx)
+    }
+}
+
+fn report(not_matched: &NotMatched) -> String {
+    let mut report = not_matched.to_string();
+    let candidates = not_matched.candidates();
+    if candidates.is_empty() {
+        report.push_str("\n\nno candidate routes");
+    } else {
+        report.push_str("\n\ncandidate routes:");
+        for candidate in candidates {
+            let _ = write!(report, "\n- {}: {}", candidate.status_code(), candidate);
+        }
+    }
+    report
+}
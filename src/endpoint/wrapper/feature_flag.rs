@@ -0,0 +1,706 @@
+use {
+    super::Wrapper,
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+        service::Context,
+        util::stable_position,
+    },
+    futures::Poll,
+    http::StatusCode,
+    serde_json::Value,
+    std::{
+        borrow::Cow,
+        collections::{HashMap, HashSet},
+        fmt, fs, io,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex, RwLock},
+        time::{Duration, Instant, SystemTime},
+    },
+};
+
+/// Creates a `Wrapper` which enables the wrapped endpoint only if the
+/// specified flag is enabled by the `FlagProvider`.
+///
+/// The flag is evaluated for each request, before the wrapped endpoint is
+/// applied. When the flag is disabled, the request is rejected with
+/// `404 Not Found` by default, and hence it falls through to the other
+/// endpoint if the wrapped endpoint is combined with `or`. The rollout can
+/// be keyed by the user (or any other value taken from the request) with
+/// `FeatureFlag::key_by`, which is used by the percentage rollouts and the
+/// allow lists (see `FlagRule`).
+///
+/// The provider is called synchronously during routing, so the providers
+/// backed by a remote service should be wrapped in `CachedFlags`.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::{syntax, wrapper::{self, StaticFlags}};
+/// # use finchers::test;
+/// # use http::Request;
+/// #[derive(Clone)]
+/// struct UserId(String);
+///
+/// let flags = StaticFlags::new().rule("new_checkout", "alice,bob".parse().unwrap());
+///
+/// let endpoint = syntax::segment("checkout")
+///     .map(|| "new checkout")
+///     .wrap(
+///         wrapper::feature_flag(flags, "new_checkout").key_by(|cx| {
+///             cx.extensions().get::<UserId>().map(|user| user.0.clone())
+///         }),
+///     )
+///     .or(syntax::segment("checkout").map(|| "old checkout"));
+///
+/// let mut runner = test::runner(endpoint);
+/// let mut request = Request::get("/checkout").body("").unwrap();
+/// request.extensions_mut().insert(UserId("alice".into()));
+/// assert_eq!(runner.perform(request).unwrap().to_utf8_lossy(), "new checkout");
+/// assert_eq!(runner.perform("/checkout").unwrap().to_utf8_lossy(), "old checkout");
+/// ```
+pub fn feature_flag<P>(provider: P, flag: impl Into<Cow<'static, str>>) -> FeatureFlag<P>
+where
+    P: FlagProvider,
+{
+    FeatureFlag {
+        provider: Arc::new(provider),
+        flag: flag.into(),
+        key_fn: None,
+        status: StatusCode::NOT_FOUND,
+    }
+}
+
+/// A trait representing the source of the feature flags.
+pub trait FlagProvider: Send + Sync + 'static {
+    /// Returns whether the flag is enabled for the specified key.
+    fn is_enabled(&self, flag: &str, key: Option<&str>) -> bool;
+}
+
+impl<P: FlagProvider + ?Sized> FlagProvider for Arc<P> {
+    fn is_enabled(&self, flag: &str, key: Option<&str>) -> bool {
+        (**self).is_enabled(flag, key)
+    }
+}
+
+impl<P: FlagProvider + ?Sized> FlagProvider for Box<P> {
+    fn is_enabled(&self, flag: &str, key: Option<&str>) -> bool {
+        (**self).is_enabled(flag, key)
+    }
+}
+
+// ==== FlagRule ====
+
+/// The rule which determines whether a flag is enabled.
+///
+/// A rule is parsed from the following strings:
+///
+/// * `true`, `on`, `yes` or `1` - enabled for all requests.
+/// * `false`, `off`, `no`, `0` or an empty string - disabled for all requests.
+/// * a percentage such as `25%` - enabled for the specified ratio of the keys.
+/// * a comma separated list such as `alice,bob` - enabled only for the listed keys.
+///
+/// The requests without the key are regarded as disabled by the percentage
+/// rollouts (except `100%`) and the allow lists. The percentage rollouts
+/// assign each key to a stable bucket, so a key keeps the same decision
+/// while the percentage is increased.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlagRule {
+    /// Enabled or disabled for all requests.
+    All(bool),
+    /// Enabled for the specified percentage of the keys.
+    Percentage(f64),
+    /// Enabled only for the listed keys.
+    Keys(HashSet<String>),
+}
+
+impl FlagRule {
+    /// Evaluates the rule for the specified flag and key.
+    pub fn evaluate(&self, flag: &str, key: Option<&str>) -> bool {
+        match *self {
+            FlagRule::All(enabled) => enabled,
+            FlagRule::Percentage(percentage) if percentage >= 100.0 => true,
+            FlagRule::Percentage(percentage) => key.map_or(false, |key| {
+                stable_position(&[flag.as_bytes(), key.as_bytes()]) * 100.0 < percentage
+            }),
+            FlagRule::Keys(ref keys) => key.map_or(false, |key| keys.contains(key)),
+        }
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        match *value {
+            Value::Bool(enabled) => Some(FlagRule::All(enabled)),
+            Value::Number(ref n) => n.as_f64().map(FlagRule::Percentage),
+            Value::String(ref s) => s.parse().ok(),
+            Value::Array(ref keys) => keys
+                .iter()
+                .map(|key| key.as_str().map(ToOwned::to_owned))
+                .collect::<Option<_>>()
+                .map(FlagRule::Keys),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for FlagRule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match &*s.to_ascii_lowercase() {
+            "true" | "on" | "yes" | "1" => return Ok(FlagRule::All(true)),
+            "false" | "off" | "no" | "0" | "" => return Ok(FlagRule::All(false)),
+            _ => {}
+        }
+        if s.ends_with('%') {
+            return s[..s.len() - 1]
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|&percentage| percentage >= 0.0 && percentage <= 100.0)
+                .map(FlagRule::Percentage)
+                .ok_or_else(|| error::bad_request(format!("invalid percentage: `{}'", s)));
+        }
+        Ok(FlagRule::Keys(
+            s.split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(ToOwned::to_owned)
+                .collect(),
+        ))
+    }
+}
+
+// ==== StaticFlags ====
+
+/// A `FlagProvider` with the fixed set of rules.
+///
+/// The flags which are not registered are disabled.
+#[derive(Debug, Clone, Default)]
+pub struct StaticFlags {
+    rules: HashMap<String, FlagRule>,
+}
+
+impl StaticFlags {
+    /// Creates an empty `StaticFlags`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the rule of a flag.
+    pub fn rule(mut self, flag: impl Into<String>, rule: FlagRule) -> Self {
+        self.rules.insert(flag.into(), rule);
+        self
+    }
+}
+
+impl FlagProvider for StaticFlags {
+    fn is_enabled(&self, flag: &str, key: Option<&str>) -> bool {
+        self.rules
+            .get(flag)
+            .map_or(false, |rule| rule.evaluate(flag, key))
+    }
+}
+
+// ==== EnvFlags ====
+
+/// A `FlagProvider` which reads the rules from the environment variables.
+///
+/// The rule of a flag is read from the variable named by the prefix
+/// (`FEATURE_` by default) and the upper-cased name of the flag, in which
+/// `-` and `.` are replaced with `_`; e.g. `FEATURE_NEW_CHECKOUT` for the
+/// flag `new_checkout`. The variables are read at each evaluation.
+#[derive(Debug, Clone)]
+pub struct EnvFlags {
+    prefix: Cow<'static, str>,
+}
+
+impl Default for EnvFlags {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EnvFlags {
+    /// Creates an `EnvFlags` with the default prefix.
+    pub fn new() -> Self {
+        Self::with_prefix("FEATURE_")
+    }
+
+    /// Creates an `EnvFlags` with the specified prefix.
+    pub fn with_prefix(prefix: impl Into<Cow<'static, str>>) -> Self {
+        EnvFlags {
+            prefix: prefix.into(),
+        }
+    }
+
+    fn var_name(&self, flag: &str) -> String {
+        let mut name = self.prefix.to_string();
+        name.extend(flag.chars().map(|c| match c {
+            '-' | '.' => '_',
+            c => c.to_ascii_uppercase(),
+        }));
+        name
+    }
+}
+
+impl FlagProvider for EnvFlags {
+    fn is_enabled(&self, flag: &str, key: Option<&str>) -> bool {
+        std::env::var(self.var_name(flag))
+            .ok()
+            .and_then(|value| value.parse::<FlagRule>().ok())
+            .map_or(false, |rule| rule.evaluate(flag, key))
+    }
+}
+
+// ==== FileFlags ====
+
+/// A `FlagProvider` which reads the rules from a JSON file.
+///
+/// The file consists of an object which maps the name of flags to their rules,
+/// each of which is a boolean, a percentage (as a number), a list of keys or
+/// a string described in `FlagRule`:
+///
+/// ```json
+/// { "new_checkout": "25%", "dark_mode": true, "beta": ["alice", "bob"] }
+/// ```
+///
+/// The file is reloaded when its modification time has been changed, which
+/// is checked at most once per the reload interval (1 second by default).
+/// If the modified file is invalid, the previous rules are kept.
+#[derive(Debug)]
+pub struct FileFlags {
+    path: PathBuf,
+    interval: Duration,
+    state: RwLock<FileState>,
+}
+
+#[derive(Debug)]
+struct FileState {
+    rules: HashMap<String, FlagRule>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl FileFlags {
+    /// Loads the rules from the specified file.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let modified = fs::metadata(&path)?.modified().ok();
+        let rules = Self::load(&path)?;
+        Ok(FileFlags {
+            path,
+            interval: Duration::from_secs(1),
+            state: RwLock::new(FileState {
+                rules,
+                modified,
+                checked: Instant::now(),
+            }),
+        })
+    }
+
+    /// Sets the interval to check whether the file has been modified.
+    pub fn reload_interval(self, interval: Duration) -> Self {
+        FileFlags { interval, ..self }
+    }
+
+    fn load(path: &Path) -> io::Result<HashMap<String, FlagRule>> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let content = fs::read(path)?;
+        let value: HashMap<String, Value> =
+            serde_json::from_slice(&content).map_err(|e| invalid(e.to_string()))?;
+        value
+            .into_iter()
+            .map(|(flag, value)| match FlagRule::from_json(&value) {
+                Some(rule) => Ok((flag, rule)),
+                None => Err(invalid(format!("invalid rule of `{}': {}", flag, value))),
+            })
+            .collect()
+    }
+
+    fn reload_if_modified(&self) {
+        {
+            let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+            if state.checked.elapsed() < self.interval {
+                return;
+            }
+        }
+
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        if state.checked.elapsed() < self.interval {
+            return;
+        }
+        state.checked = Instant::now();
+
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified == state.modified {
+            return;
+        }
+        match Self::load(&self.path) {
+            Ok(rules) => {
+                state.rules = rules;
+                state.modified = modified;
+            }
+            Err(err) => log::warn!(
+                target: "finchers::feature_flag",
+                "failed to reload the feature flags from {}: {}",
+                self.path.display(),
+                err
+            ),
+        }
+    }
+}
+
+impl FlagProvider for FileFlags {
+    fn is_enabled(&self, flag: &str, key: Option<&str>) -> bool {
+        self.reload_if_modified();
+        let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+        state
+            .rules
+            .get(flag)
+            .map_or(false, |rule| rule.evaluate(flag, key))
+    }
+}
+
+// ==== CachedFlags ====
+
+type CacheKey = (String, Option<String>);
+
+/// A `FlagProvider` which caches the decisions of another provider for a while.
+///
+/// This is intended for the providers which query a remote service, so that
+/// the service is queried at most once per the TTL for each pair of the flag
+/// and key. The cache is cleared when the number of the entries exceeds the
+/// capacity (4096 by default).
+pub struct CachedFlags<P> {
+    provider: P,
+    ttl: Duration,
+    capacity: usize,
+    cache: Mutex<HashMap<CacheKey, (bool, Instant)>>,
+}
+
+impl<P> fmt::Debug for CachedFlags<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedFlags")
+            .field("ttl", &self.ttl)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl<P: FlagProvider> CachedFlags<P> {
+    /// Creates a `CachedFlags` which caches the decisions for the specified duration.
+    pub fn new(provider: P, ttl: Duration) -> Self {
+        CachedFlags {
+            provider,
+            ttl,
+            capacity: 4096,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the maximum number of the cached decisions.
+    pub fn capacity(self, capacity: usize) -> Self {
+        CachedFlags { capacity, ..self }
+    }
+}
+
+impl<P: FlagProvider> FlagProvider for CachedFlags<P> {
+    fn is_enabled(&self, flag: &str, key: Option<&str>) -> bool {
+        let cache_key = (flag.to_owned(), key.map(ToOwned::to_owned));
+        {
+            let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(&(enabled, at)) = cache.get(&cache_key) {
+                if at.elapsed() < self.ttl {
+                    return enabled;
+                }
+            }
+        }
+
+        // The provider is called without the lock, since it may take a while.
+        let enabled = self.provider.is_enabled(flag, key);
+
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if cache.len() >= self.capacity {
+            cache.clear();
+        }
+        cache.insert(cache_key, (enabled, Instant::now()));
+        enabled
+    }
+}
+
+// ==== FeatureFlag ====
+
+type KeyFn = dyn Fn(&Context) -> Option<String> + Send + Sync + 'static;
+
+/// A `Wrapper` which gates the wrapped endpoint by a feature flag.
+///
+/// See the documentation of `feature_flag` for details.
+pub struct FeatureFlag<P> {
+    provider: Arc<P>,
+    flag: Cow<'static, str>,
+    key_fn: Option<Arc<KeyFn>>,
+    status: StatusCode,
+}
+
+impl<P> Clone for FeatureFlag<P> {
+    fn clone(&self) -> Self {
+        FeatureFlag {
+            provider: self.provider.clone(),
+            flag: self.flag.clone(),
+            key_fn: self.key_fn.clone(),
+            status: self.status,
+        }
+    }
+}
+
+impl<P> fmt::Debug for FeatureFlag<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FeatureFlag")
+            .field("flag", &self.flag)
+            .field("status", &self.status)
+            .finish()
+    }
+}
+
+impl<P> FeatureFlag<P> {
+    /// Sets the function which extracts the key of the rollout from the request,
+    /// such as the ID of the user stored in the extensions.
+    pub fn key_by<F>(self, f: F) -> Self
+    where
+        F: Fn(&Context) -> Option<String> + Send + Sync + 'static,
+    {
+        FeatureFlag {
+            key_fn: Some(Arc::new(f)),
+            ..self
+        }
+    }
+
+    /// Sets the status code of the rejection when the flag is disabled.
+    ///
+    /// The default value is `404 Not Found`.
+    pub fn reject_with(self, status: StatusCode) -> Self {
+        FeatureFlag { status, ..self }
+    }
+}
+
+impl<P, E> Wrapper<E> for FeatureFlag<P>
+where
+    P: FlagProvider,
+    E: IsEndpoint,
+{
+    type Endpoint = FeatureFlagEndpoint<P, E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        FeatureFlagEndpoint {
+            endpoint,
+            flag: self,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct FeatureFlagEndpoint<P, E> {
+    endpoint: E,
+    flag: FeatureFlag<P>,
+}
+
+impl<P, E> IsEndpoint for FeatureFlagEndpoint<P, E>
+where
+    P: FlagProvider,
+    E: IsEndpoint,
+{
+}
+
+impl<P, E, Bd> Endpoint<Bd> for FeatureFlagEndpoint<P, E>
+where
+    P: FlagProvider,
+    E: Endpoint<Bd>,
+{
+    type Output = E::Output;
+    type Action = FeatureFlagAction<P, E::Action>;
+
+    fn action(&self) -> Self::Action {
+        FeatureFlagAction {
+            action: self.endpoint.action(),
+            flag: self.flag.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct FeatureFlagAction<P, A> {
+    action: A,
+    flag: FeatureFlag<P>,
+}
+
+impl<P, A, Bd> EndpointAction<Bd> for FeatureFlagAction<P, A>
+where
+    P: FlagProvider,
+    A: EndpointAction<Bd>,
+{
+    type Output = A::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let flag = &self.flag;
+        let key = flag.key_fn.as_ref().and_then(|key_fn| key_fn(cx));
+        if !flag
+            .provider
+            .is_enabled(&flag.flag, key.as_ref().map(String::as_str))
+        {
+            return Err(error::err_msg(
+                format!("the feature `{}' is disabled", flag.flag),
+                flag.status,
+            ));
+        }
+        self.action.preflight(cx)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        self.action.poll_action(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            endpoint::{syntax, EndpointExt},
+            test,
+        },
+        http::Request,
+    };
+
+    #[test]
+    fn test_parse_rule() {
+        assert_eq!("on".parse::<FlagRule>().unwrap(), FlagRule::All(true));
+        assert_eq!("".parse::<FlagRule>().unwrap(), FlagRule::All(false));
+        assert_eq!(
+            "12.5%".parse::<FlagRule>().unwrap(),
+            FlagRule::Percentage(12.5)
+        );
+        assert!("120%".parse::<FlagRule>().is_err());
+        assert_eq!(
+            " alice, bob ".parse::<FlagRule>().unwrap(),
+            FlagRule::Keys(vec!["alice".into(), "bob".into()].into_iter().collect())
+        );
+    }
+
+    #[test]
+    fn test_percentage() {
+        let rule = FlagRule::Percentage(25.0);
+        let enabled = (0..10_000)
+            .filter(|i| rule.evaluate("flag", Some(&i.to_string())))
+            .count();
+        assert!(2_000 < enabled && enabled < 3_000, "{}", enabled);
+        assert!(!rule.evaluate("flag", None));
+
+        // the enabled keys are kept when the percentage is increased.
+        let wider = FlagRule::Percentage(50.0);
+        assert!((0..1_000)
+            .map(|i| i.to_string())
+            .filter(|key| rule.evaluate("flag", Some(key)))
+            .all(|key| wider.evaluate("flag", Some(&key))));
+    }
+
+    #[test]
+    fn test_env_flags() {
+        let flags = EnvFlags::with_prefix("FINCHERS_TEST_FEATURE_");
+        std::env::set_var("FINCHERS_TEST_FEATURE_NEW_CHECKOUT", "true");
+        std::env::set_var("FINCHERS_TEST_FEATURE_BETA", "alice");
+        assert!(flags.is_enabled("new-checkout", None));
+        assert!(flags.is_enabled("beta", Some("alice")));
+        assert!(!flags.is_enabled("beta", Some("bob")));
+        assert!(!flags.is_enabled("missing", Some("alice")));
+    }
+
+    #[test]
+    fn test_file_flags() {
+        let path = std::env::temp_dir().join(format!("finchers-flags-{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"{ "dark_mode": true, "beta": ["alice"], "half": 50 }"#,
+        )
+        .unwrap();
+
+        let flags = FileFlags::open(&path)
+            .unwrap()
+            .reload_interval(Duration::from_millis(0));
+        assert!(flags.is_enabled("dark_mode", None));
+        assert!(flags.is_enabled("beta", Some("alice")));
+        assert!(!flags.is_enabled("beta", None));
+
+        // the invalid file does not overwrite the previous rules.
+        fs::write(&path, r#"{ "dark_mode": {} }"#).unwrap();
+        flags.state.write().unwrap().modified = None;
+        assert!(flags.is_enabled("dark_mode", None));
+
+        fs::write(&path, r#"{ "dark_mode": "off" }"#).unwrap();
+        flags.state.write().unwrap().modified = None;
+        assert!(!flags.is_enabled("dark_mode", None));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cached_flags() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        struct Remote(Arc<std::sync::atomic::AtomicUsize>);
+        impl FlagProvider for Remote {
+            fn is_enabled(&self, _: &str, _: Option<&str>) -> bool {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                true
+            }
+        }
+
+        let flags = CachedFlags::new(Remote(calls.clone()), Duration::from_secs(60));
+        assert!(flags.is_enabled("flag", Some("alice")));
+        assert!(flags.is_enabled("flag", Some("alice")));
+        assert!(flags.is_enabled("flag", Some("bob")));
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_feature_flag() {
+        let flags = StaticFlags::new()
+            .rule("enabled", FlagRule::All(true))
+            .rule("disabled", FlagRule::All(false));
+
+        let mut runner = test::runner(
+            syntax::segment("a")
+                .map(|| "new")
+                .wrap(feature_flag(flags.clone(), "enabled"))
+                .or(syntax::segment("a").map(|| "old")),
+        );
+        assert_eq!(runner.perform("/a").unwrap().to_utf8_lossy(), "new");
+
+        // the disabled endpoint falls through to the other one.
+        let mut runner = test::runner(
+            syntax::segment("a")
+                .map(|| "new")
+                .wrap(feature_flag(flags.clone(), "disabled"))
+                .or(syntax::segment("a").map(|| "old")),
+        );
+        assert_eq!(runner.perform("/a").unwrap().to_utf8_lossy(), "old");
+
+        let mut runner = test::runner(
+            syntax::segment("a")
+                .map(|| "new")
+                .wrap(feature_flag(flags, "disabled").reject_with(StatusCode::FORBIDDEN)),
+        );
+        let response = runner.perform(Request::get("/a")).unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,475 @@
+use {
+    super::Wrapper,
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+        util::millis,
+    },
+    futures::{Async, Future, Poll},
+    http::{header, Method, Uri},
+    serde::Serialize,
+    std::{
+        borrow::Cow,
+        collections::HashMap,
+        fmt,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    },
+    tokio::timer::Delay,
+};
+
+/// A recorder of the requests in flight, which detects the slow requests.
+///
+/// The wrapper created by `FlightRecorder::wrapper` registers each request
+/// while it is being processed by the wrapped endpoint. When a request is not
+/// completed within the threshold, a `InFlightRequest` is logged at the `WARN`
+/// level and passed to the callback registered by `on_slow`, *while* the
+/// request is still in flight, so that the stuck requests are reported even
+/// if they never complete. The currently stuck requests are also available
+/// from `stuck`, which is served by the admin suite
+/// (see `endpoints::admin::Admin::flight_recorder`).
+///
+/// The time spent in each phase (`routing` and `handler`, as in
+/// `wrapper::latency_budget`) can also be recorded to the `Server-Timing`
+/// response header through `Context::timings`.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::{syntax, wrapper::{FlightRecorder, InFlightRequest}};
+/// # use finchers::test;
+/// # use std::time::Duration;
+/// let recorder = FlightRecorder::new(Duration::from_secs(5))
+///     .on_slow(|request: &InFlightRequest| eprintln!("{}", request));
+///
+/// let endpoint = syntax::segment("search")
+///     .map(|| "results")
+///     .wrap(recorder.wrapper().route("search").server_timing(true));
+/// # let mut runner = test::runner(endpoint);
+/// # assert_eq!(runner.apply("/search").unwrap(), "results");
+/// # assert!(recorder.stuck().is_empty());
+/// ```
+#[derive(Clone)]
+pub struct FlightRecorder {
+    inner: Arc<Inner>,
+}
+
+type SlowFn = dyn Fn(&InFlightRequest) + Send + Sync + 'static;
+
+struct Inner {
+    threshold: Duration,
+    on_slow: Option<Box<SlowFn>>,
+    next_id: AtomicUsize,
+    in_flight: Mutex<HashMap<u64, Flight>>,
+}
+
+struct Flight {
+    route: Option<Arc<Cow<'static, str>>>,
+    method: Method,
+    uri: Uri,
+    user_agent: Option<String>,
+    started: Instant,
+    phase: RequestPhase,
+}
+
+impl fmt::Debug for FlightRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FlightRecorder")
+            .field("threshold", &self.inner.threshold)
+            .finish()
+    }
+}
+
+impl FlightRecorder {
+    /// Creates a `FlightRecorder` which regards the requests taking longer
+    /// than the specified duration as slow.
+    pub fn new(threshold: Duration) -> Self {
+        FlightRecorder {
+            inner: Arc::new(Inner {
+                threshold,
+                on_slow: None,
+                next_id: AtomicUsize::new(0),
+                in_flight: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Registers a callback which is called when a request exceeds the threshold.
+    ///
+    /// # Panics
+    /// This method panics if the `FlightRecorder` has already been cloned.
+    pub fn on_slow<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&InFlightRequest) + Send + Sync + 'static,
+    {
+        Arc::get_mut(&mut self.inner)
+            .expect("the recorder has already been shared")
+            .on_slow = Some(Box::new(f));
+        self
+    }
+
+    /// Creates a `Wrapper` which records the requests to this recorder.
+    pub fn wrapper(&self) -> FlightRecorderWrapper {
+        FlightRecorderWrapper {
+            recorder: self.clone(),
+            route: None,
+            server_timing: false,
+        }
+    }
+
+    /// Returns the requests in flight, from the oldest.
+    pub fn in_flight(&self) -> Vec<InFlightRequest> {
+        let now = Instant::now();
+        let in_flight = self.inner.lock();
+        let mut requests: Vec<_> = in_flight
+            .iter()
+            .map(|(&id, flight)| flight.to_record(id, now))
+            .collect();
+        requests.sort_by(|a, b| b.elapsed_ms.partial_cmp(&a.elapsed_ms).unwrap());
+        requests
+    }
+
+    /// Returns the requests in flight which have exceeded the threshold, from the oldest.
+    pub fn stuck(&self) -> Vec<InFlightRequest> {
+        let threshold = millis(self.inner.threshold);
+        let mut requests = self.in_flight();
+        requests.retain(|request| request.elapsed_ms > threshold);
+        requests
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Flight>> {
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn report(&self, id: u64) {
+        let record = match self.lock().get(&id) {
+            Some(flight) => flight.to_record(id, Instant::now()),
+            None => return,
+        };
+        log::warn!(target: "finchers::flight_recorder", "{}", record);
+        if let Some(ref on_slow) = self.on_slow {
+            on_slow(&record);
+        }
+    }
+}
+
+impl Flight {
+    fn to_record(&self, id: u64, now: Instant) -> InFlightRequest {
+        InFlightRequest {
+            id,
+            route: self.route.as_ref().map(|route| route.to_string()),
+            method: self.method.to_string(),
+            uri: self.uri.to_string(),
+            user_agent: self.user_agent.clone(),
+            phase: self.phase,
+            elapsed_ms: millis(now - self.started),
+        }
+    }
+}
+
+/// The phase of an endpoint in which a request is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestPhase {
+    /// The synchronous part of the endpoint (`EndpointAction::preflight`).
+    Routing,
+    /// The asynchronous part of the endpoint.
+    Handler,
+}
+
+/// A record of a request in flight.
+#[derive(Debug, Clone, Serialize)]
+pub struct InFlightRequest {
+    /// The ID of the request, unique within the recorder.
+    pub id: u64,
+    /// The name of the route, if specified.
+    pub route: Option<String>,
+    /// The method of the request.
+    pub method: String,
+    /// The URI of the request.
+    pub uri: String,
+    /// The value of `User-Agent`.
+    pub user_agent: Option<String>,
+    /// The phase in which the request is.
+    pub phase: RequestPhase,
+    /// The time elapsed since the request has reached the wrapper, in milliseconds.
+    pub elapsed_ms: f64,
+}
+
+impl fmt::Display for InFlightRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] slow request #{}: {} {} ({:?}, {:.3}ms elapsed, user-agent: {})",
+            self.route.as_ref().map_or("<unnamed>", |route| &**route),
+            self.id,
+            self.method,
+            self.uri,
+            self.phase,
+            self.elapsed_ms,
+            self.user_agent
+                .as_ref()
+                .map_or("-", |user_agent| &**user_agent),
+        )
+    }
+}
+
+// ==== FlightRecorderWrapper ====
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct FlightRecorderWrapper {
+    recorder: FlightRecorder,
+    route: Option<Arc<Cow<'static, str>>>,
+    server_timing: bool,
+}
+
+impl FlightRecorderWrapper {
+    /// Sets the name of the route, which is reported with the requests.
+    pub fn route(self, route: impl Into<Cow<'static, str>>) -> Self {
+        FlightRecorderWrapper {
+            route: Some(Arc::new(route.into())),
+            ..self
+        }
+    }
+
+    /// Sets whether to record the time spent in each phase to `Context::timings`,
+    /// which is sent as the `Server-Timing` header.
+    ///
+    /// The timings are not recorded if the wrapped endpoint completes
+    /// without the asynchronous part.
+    ///
+    /// The default value is `false`.
+    pub fn server_timing(self, enabled: bool) -> Self {
+        FlightRecorderWrapper {
+            server_timing: enabled,
+            ..self
+        }
+    }
+}
+
+impl<E> Wrapper<E> for FlightRecorderWrapper
+where
+    E: IsEndpoint,
+{
+    type Endpoint = FlightRecorderEndpoint<E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        FlightRecorderEndpoint {
+            endpoint,
+            wrapper: self,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct FlightRecorderEndpoint<E> {
+    endpoint: E,
+    wrapper: FlightRecorderWrapper,
+}
+
+impl<E: IsEndpoint> IsEndpoint for FlightRecorderEndpoint<E> {}
+
+impl<E, Bd> Endpoint<Bd> for FlightRecorderEndpoint<E>
+where
+    E: Endpoint<Bd>,
+{
+    type Output = E::Output;
+    type Action = FlightRecorderAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        FlightRecorderAction {
+            action: self.endpoint.action(),
+            wrapper: self.wrapper.clone(),
+            id: None,
+            started: None,
+            routing: Duration::default(),
+            deadline: None,
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct FlightRecorderAction<A> {
+    action: A,
+    wrapper: FlightRecorderWrapper,
+    id: Option<u64>,
+    started: Option<Instant>,
+    routing: Duration,
+    deadline: Option<Delay>,
+}
+
+impl<A> FlightRecorderAction<A> {
+    fn inner(&self) -> &Inner {
+        &self.wrapper.recorder.inner
+    }
+}
+
+impl<A> Drop for FlightRecorderAction<A> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            self.inner().lock().remove(&id);
+        }
+    }
+}
+
+impl<A, Bd> EndpointAction<Bd> for FlightRecorderAction<A>
+where
+    A: EndpointAction<Bd>,
+{
+    type Output = A::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let started = Instant::now();
+        let id = self.inner().next_id.fetch_add(1, Ordering::Relaxed) as u64;
+        self.inner().lock().insert(
+            id,
+            Flight {
+                route: self.wrapper.route.clone(),
+                method: cx.method().clone(),
+                uri: cx.uri().clone(),
+                user_agent: cx
+                    .headers()
+                    .get(header::USER_AGENT)
+                    .and_then(|value| value.to_str().ok())
+                    .map(ToOwned::to_owned),
+                started,
+                phase: RequestPhase::Routing,
+            },
+        );
+        self.id = Some(id);
+        self.started = Some(started);
+
+        let preflight = self.action.preflight(cx);
+        self.routing = started.elapsed();
+        let threshold = self.inner().threshold;
+        if self.routing > threshold {
+            // The synchronous part cannot be interrupted, so it is reported afterwards.
+            self.inner().report(id);
+        }
+
+        match preflight {
+            Ok(Preflight::Incomplete) => {
+                if let Some(flight) = self.inner().lock().get_mut(&id) {
+                    flight.phase = RequestPhase::Handler;
+                }
+                if self.routing <= threshold {
+                    self.deadline =
+                        Some(Delay::new(tokio::clock::now() + threshold - self.routing));
+                }
+                Ok(Preflight::Incomplete)
+            }
+            completed => {
+                self.inner().lock().remove(&id);
+                self.id = None;
+                completed
+            }
+        }
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        let polled = self.action.poll_action(cx);
+
+        if let Ok(Async::NotReady) = polled {
+            if let Some(Ok(Async::Ready(()))) = self.deadline.as_mut().map(Delay::poll) {
+                self.deadline = None;
+                if let Some(id) = self.id {
+                    self.inner().report(id);
+                }
+            }
+            return Ok(Async::NotReady);
+        }
+
+        if let Some(id) = self.id.take() {
+            self.inner().lock().remove(&id);
+        }
+        if self.wrapper.server_timing {
+            let started = self.started.expect("the action has not been preflighted");
+            let handler = started.elapsed() - self.routing;
+            cx.timings()
+                .record("routing", self.routing)
+                .record("handler", handler);
+        }
+        polled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{endpoint::EndpointExt, test},
+        futures::future,
+    };
+
+    #[test]
+    fn test_slow_request() {
+        let slow = Arc::new(Mutex::new(vec![]));
+        let recorder = FlightRecorder::new(Duration::from_millis(10)).on_slow({
+            let slow = slow.clone();
+            move |request: &InFlightRequest| slow.lock().unwrap().push(request.clone())
+        });
+
+        let mut runner = test::runner({
+            let wrapper = recorder.wrapper().route("sleep").server_timing(true);
+            let recorder = recorder.clone();
+            crate::endpoint::unit()
+                .and_then(move || {
+                    let stuck = recorder.clone();
+                    Delay::new(tokio::clock::now() + Duration::from_millis(50))
+                        .map_err(crate::error::internal_server_error)
+                        .and_then(move |()| {
+                            // the request is reported before completing.
+                            let stuck = stuck.stuck();
+                            assert_eq!(stuck.len(), 1);
+                            assert_eq!(stuck[0].phase, RequestPhase::Handler);
+                            future::ok::<_, Error>("done")
+                        })
+                })
+                .wrap(wrapper)
+        });
+        let response = runner.perform("/sleep?q=1").unwrap();
+        assert!(response.headers()["server-timing"]
+            .to_str()
+            .unwrap()
+            .starts_with("routing;dur="));
+
+        let slow = slow.lock().unwrap();
+        assert_eq!(slow.len(), 1);
+        assert_eq!(slow[0].route.as_ref().map(String::as_str), Some("sleep"));
+        assert_eq!(slow[0].uri, "/sleep?q=1");
+        assert_eq!(slow[0].phase, RequestPhase::Handler);
+        assert!(slow[0].elapsed_ms >= 10.0);
+
+        assert!(recorder.in_flight().is_empty());
+    }
+
+    #[test]
+    fn test_fast_request() {
+        let recorder = FlightRecorder::new(Duration::from_secs(10))
+            .on_slow(|_: &InFlightRequest| panic!("should not be reported"));
+        let mut runner = test::runner(
+            crate::endpoint::unit()
+                .map(|| "fast")
+                .wrap(recorder.wrapper()),
+        );
+        assert_eq!(runner.apply("/").unwrap(), "fast");
+        assert!(recorder.in_flight().is_empty());
+    }
+}
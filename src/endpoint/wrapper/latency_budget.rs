@@ -0,0 +1,397 @@
+use {
+    super::Wrapper,
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+        output::IntoResponse,
+        service::ServerTimings,
+        util::millis,
+    },
+    futures::{Async, Poll},
+    http::Response,
+    std::{
+        borrow::Cow,
+        collections::HashMap,
+        fmt,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+/// Creates a `Wrapper` which checks whether the wrapped endpoint responds
+/// within the specified latency budget.
+///
+/// The time spent by the wrapped endpoint is measured in the following phases:
+///
+/// * `routing` - the synchronous part of the endpoint (`EndpointAction::preflight`).
+/// * `handler` - the asynchronous part of the endpoint, until its output is ready.
+/// * `serialization` - the conversion of the output into an HTTP response.
+///
+/// When the total exceeds the budget, a `BudgetViolation` is logged at the `WARN`
+/// level and passed to the callback registered by `LatencyBudget::on_violation`.
+/// The timings and the number of violations of each route are also recorded
+/// into the `BudgetMetrics` registered by `LatencyBudget::metrics`.
+/// The request is processed as usual even if the budget is exceeded.
+///
+/// Since the phase timings include the serialization, the wrapped endpoint
+/// converts its output into `Response` as in `wrapper::map_response`.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::{syntax, wrapper};
+/// # use finchers::test;
+/// # use std::time::Duration;
+/// let metrics = wrapper::BudgetMetrics::new();
+///
+/// let endpoint = syntax::segment("search")
+///     .map(|| "results")
+///     .wrap(
+///         wrapper::latency_budget(Duration::from_millis(100))
+///             .name("search")
+///             .server_timing(true)
+///             .metrics(&metrics)
+///             .on_violation(|violation: &wrapper::BudgetViolation| {
+///                 eprintln!("{}", violation);
+///             }),
+///     );
+/// # let mut runner = test::runner(endpoint);
+/// # let response = runner.perform("/search").unwrap();
+/// # assert!(response.headers().contains_key("server-timing"));
+/// # assert_eq!(metrics.get("search").unwrap().requests, 1);
+/// ```
+pub fn latency_budget(budget: Duration) -> LatencyBudget {
+    LatencyBudget {
+        budget,
+        name: None,
+        server_timing: false,
+        metrics: None,
+        on_violation: None,
+    }
+}
+
+type ViolationFn = dyn Fn(&BudgetViolation) + Send + Sync + 'static;
+
+#[allow(missing_docs)]
+#[derive(Clone)]
+pub struct LatencyBudget {
+    budget: Duration,
+    name: Option<Arc<Cow<'static, str>>>,
+    server_timing: bool,
+    metrics: Option<BudgetMetrics>,
+    on_violation: Option<Arc<ViolationFn>>,
+}
+
+impl fmt::Debug for LatencyBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LatencyBudget")
+            .field("budget", &self.budget)
+            .field("name", &self.name)
+            .field("server_timing", &self.server_timing)
+            .field("metrics", &self.metrics)
+            .finish()
+    }
+}
+
+impl LatencyBudget {
+    /// Sets the name of the route, which is reported with the violations.
+    pub fn name(self, name: impl Into<Cow<'static, str>>) -> Self {
+        LatencyBudget {
+            name: Some(Arc::new(name.into())),
+            ..self
+        }
+    }
+
+    /// Sets whether to add the phase timings to the response as the
+    /// `Server-Timing` header.
+    ///
+    /// The timings are sent together with the ones recorded in
+    /// `Context::timings`.
+    ///
+    /// The default value is `false`.
+    pub fn server_timing(self, enabled: bool) -> Self {
+        LatencyBudget {
+            server_timing: enabled,
+            ..self
+        }
+    }
+
+    /// Sets the `BudgetMetrics` into which the timings of the route are recorded.
+    ///
+    /// A `BudgetMetrics` can be shared among the routes, which are
+    /// distinguished by the name specified by `LatencyBudget::name`.
+    pub fn metrics(self, metrics: &BudgetMetrics) -> Self {
+        LatencyBudget {
+            metrics: Some(metrics.clone()),
+            ..self
+        }
+    }
+
+    /// Registers a callback which is called when the budget is exceeded.
+    pub fn on_violation<F>(self, f: F) -> Self
+    where
+        F: Fn(&BudgetViolation) + Send + Sync + 'static,
+    {
+        LatencyBudget {
+            on_violation: Some(Arc::new(f)),
+            ..self
+        }
+    }
+
+    fn check(&self, timings: PhaseTimings) {
+        let exceeded = timings.total() > self.budget;
+        if let Some(ref metrics) = self.metrics {
+            metrics.record(self.name.as_ref().map(|name| &**name), timings, exceeded);
+        }
+        if !exceeded {
+            return;
+        }
+        let violation = BudgetViolation {
+            name: self.name.as_ref().map(|name| (**name).clone()),
+            budget: self.budget,
+            timings,
+        };
+        log::warn!(target: "finchers::latency_budget", "{}", violation);
+        if let Some(ref on_violation) = self.on_violation {
+            on_violation(&violation);
+        }
+    }
+}
+
+/// The time spent in each phase of an endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PhaseTimings {
+    /// The time spent in the synchronous part of the endpoint.
+    pub routing: Duration,
+    /// The time spent in the asynchronous part of the endpoint.
+    pub handler: Duration,
+    /// The time spent in converting the output into the response.
+    pub serialization: Duration,
+}
+
+impl PhaseTimings {
+    /// Returns the total time of the phases.
+    pub fn total(&self) -> Duration {
+        self.routing + self.handler + self.serialization
+    }
+}
+
+/// A collection of the timings of the routes wrapped by `latency_budget`.
+///
+/// The values are shared among the clones, so that a clone can be held by
+/// the metrics exporter, e.g. an endpoint rendering them.
+#[derive(Debug, Clone, Default)]
+pub struct BudgetMetrics {
+    routes: Arc<Mutex<HashMap<Cow<'static, str>, BudgetStats>>>,
+}
+
+/// The statistics of a route, recorded in `BudgetMetrics`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BudgetStats {
+    /// The number of the completed requests.
+    pub requests: u64,
+    /// The number of the requests which exceeded the budget.
+    pub violations: u64,
+    /// The sum of the timings of the requests.
+    pub total: PhaseTimings,
+    /// The maximum of the total time of a request.
+    pub max: Duration,
+}
+
+impl BudgetMetrics {
+    /// Creates an empty `BudgetMetrics`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the statistics of the specified route.
+    ///
+    /// The routes without the name are recorded as `<unnamed>`.
+    pub fn get(&self, name: &str) -> Option<BudgetStats> {
+        self.routes.lock().unwrap().get(name).cloned()
+    }
+
+    /// Returns the statistics of all the routes, sorted by their names.
+    pub fn snapshot(&self) -> Vec<(String, BudgetStats)> {
+        let mut routes: Vec<_> = self
+            .routes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, stats)| (name.to_string(), *stats))
+            .collect();
+        routes.sort_by(|a, b| a.0.cmp(&b.0));
+        routes
+    }
+
+    fn record(&self, name: Option<&Cow<'static, str>>, timings: PhaseTimings, exceeded: bool) {
+        let name = name.cloned().unwrap_or(Cow::Borrowed("<unnamed>"));
+        let mut routes = self.routes.lock().unwrap();
+        let stats = routes.entry(name).or_default();
+        stats.requests += 1;
+        if exceeded {
+            stats.violations += 1;
+        }
+        stats.total.routing += timings.routing;
+        stats.total.handler += timings.handler;
+        stats.total.serialization += timings.serialization;
+        stats.max = std::cmp::max(stats.max, timings.total());
+    }
+}
+
+/// A record of the request which exceeded the latency budget.
+#[derive(Debug, Clone)]
+pub struct BudgetViolation {
+    /// The name of the route, if specified.
+    pub name: Option<Cow<'static, str>>,
+    /// The latency budget of the route.
+    pub budget: Duration,
+    /// The time spent in each phase.
+    pub timings: PhaseTimings,
+}
+
+impl fmt::Display for BudgetViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] the latency budget ({:.3}ms) has been exceeded: {:.3}ms (routing: {:.3}ms, handler: {:.3}ms, serialization: {:.3}ms)",
+            self.name.as_ref().map_or("<unnamed>", |name| &**name),
+            millis(self.budget),
+            millis(self.timings.total()),
+            millis(self.timings.routing),
+            millis(self.timings.handler),
+            millis(self.timings.serialization),
+        )
+    }
+}
+
+impl<E> Wrapper<E> for LatencyBudget
+where
+    E: IsEndpoint,
+{
+    type Endpoint = LatencyBudgetEndpoint<E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        LatencyBudgetEndpoint {
+            endpoint,
+            budget: self,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct LatencyBudgetEndpoint<E> {
+    endpoint: E,
+    budget: LatencyBudget,
+}
+
+impl<E: IsEndpoint> IsEndpoint for LatencyBudgetEndpoint<E> {}
+
+impl<E, Bd> Endpoint<Bd> for LatencyBudgetEndpoint<E>
+where
+    E: Endpoint<Bd>,
+    E::Output: IntoResponse,
+{
+    type Output = (Response<<E::Output as IntoResponse>::Body>,);
+    type Action = LatencyBudgetAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        LatencyBudgetAction {
+            action: self.endpoint.action(),
+            budget: self.budget.clone(),
+            timings: PhaseTimings::default(),
+            handler_start: None,
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct LatencyBudgetAction<A> {
+    action: A,
+    budget: LatencyBudget,
+    timings: PhaseTimings,
+    handler_start: Option<Instant>,
+}
+
+impl<A> LatencyBudgetAction<A> {
+    fn finish<T: IntoResponse>(
+        &mut self,
+        output: T,
+        request: &http::Request<()>,
+    ) -> Response<T::Body> {
+        let start = tokio::clock::now();
+        let mut response = output.into_response(request);
+        self.timings.serialization = tokio::clock::now() - start;
+
+        if self.budget.server_timing {
+            // The timings are passed through the response since the context
+            // cannot be modified within `preflight`.
+            let extensions = response.extensions_mut();
+            if extensions.get::<ServerTimings>().is_none() {
+                extensions.insert(ServerTimings::default());
+            }
+            extensions
+                .get_mut::<ServerTimings>()
+                .expect("should be inserted")
+                .record("routing", self.timings.routing)
+                .record("handler", self.timings.handler)
+                .record("serialization", self.timings.serialization);
+        }
+        self.budget.check(self.timings);
+        response
+    }
+}
+
+impl<A, Bd> EndpointAction<Bd> for LatencyBudgetAction<A>
+where
+    A: EndpointAction<Bd>,
+    A::Output: IntoResponse,
+{
+    type Output = (Response<<A::Output as IntoResponse>::Body>,);
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let start = tokio::clock::now();
+        let preflight = self.action.preflight(cx)?;
+        let now = tokio::clock::now();
+        self.timings.routing = now - start;
+        match preflight {
+            Preflight::Completed(output) => {
+                Ok(Preflight::Completed((self.finish(output, cx.request()),)))
+            }
+            Preflight::Incomplete => {
+                self.handler_start = Some(now);
+                Ok(Preflight::Incomplete)
+            }
+        }
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        let result = self.action.poll_action(cx);
+        if let Ok(Async::NotReady) = result {
+            return Ok(Async::NotReady);
+        }
+        let start = self
+            .handler_start
+            .expect("the action has not been preflighted");
+        self.timings.handler = tokio::clock::now() - start;
+        match result {
+            Ok(Async::Ready(output)) => Ok((self.finish(output, cx.request()),).into()),
+            Ok(Async::NotReady) => unreachable!(),
+            Err(err) => {
+                self.budget.check(self.timings);
+                Err(err)
+            }
+        }
+    }
+}
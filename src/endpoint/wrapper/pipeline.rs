@@ -0,0 +1,114 @@
+use super::Wrapper;
+
+/// Creates an empty `Pipeline`, which composes the wrappers into a single one.
+///
+/// The wrappers added by `Pipeline::with` are applied in order, i.e. the
+/// first one wraps the endpoint directly, the same as chaining `wrap()`.
+/// Since the composed wrapper has a nameable type, a standard stack of the
+/// wrappers can be defined once and shared between the route modules.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::wrapper::{self, Pipeline, Stack, Timeout, SecurityHeaders};
+/// # use finchers::endpoint::syntax;
+/// # use finchers::test;
+/// # use std::time::Duration;
+/// type Standard = Pipeline<Stack<Stack<wrapper::Identity, Timeout>, SecurityHeaders>>;
+///
+/// fn standard() -> Standard {
+///     wrapper::pipeline()
+///         .with(wrapper::timeout(Duration::from_secs(30)))
+///         .with(wrapper::security_headers())
+/// }
+///
+/// let users = syntax::segment("users").map(|| "users").wrap(standard());
+/// let posts = syntax::segment("posts").map(|| "posts").wrap(standard());
+///
+/// let mut runner = test::runner(users.or(posts));
+/// runner
+///     .perform("/posts")
+///     .unwrap()
+///     .assert_header("x-content-type-options", "nosniff")
+///     .assert_body("posts");
+/// ```
+pub fn pipeline() -> Pipeline<Identity> {
+    Pipeline {
+        wrapper: Identity(()),
+    }
+}
+
+/// A `Wrapper` which returns the endpoint as it is.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Identity(());
+
+impl<E> Wrapper<E> for Identity {
+    type Endpoint = E;
+
+    #[inline]
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        endpoint
+    }
+}
+
+/// A `Wrapper` which applies `Inner` and then `Outer`.
+#[derive(Debug, Copy, Clone)]
+pub struct Stack<Inner, Outer> {
+    inner: Inner,
+    outer: Outer,
+}
+
+impl<E, Inner, Outer> Wrapper<E> for Stack<Inner, Outer>
+where
+    Inner: Wrapper<E>,
+    Outer: Wrapper<Inner::Endpoint>,
+{
+    type Endpoint = Outer::Endpoint;
+
+    #[inline]
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        self.outer.wrap(self.inner.wrap(endpoint))
+    }
+}
+
+/// A composition of the wrappers created by `pipeline`.
+#[derive(Debug, Copy, Clone)]
+pub struct Pipeline<W> {
+    wrapper: W,
+}
+
+impl<W> Pipeline<W> {
+    /// Appends the specified wrapper to the pipeline, which is applied
+    /// after the wrappers already added.
+    pub fn with<Outer>(self, wrapper: Outer) -> Pipeline<Stack<W, Outer>> {
+        Pipeline {
+            wrapper: Stack {
+                inner: self.wrapper,
+                outer: wrapper,
+            },
+        }
+    }
+
+    /// Appends all wrappers in the specified pipeline to this one.
+    pub fn then<Outer>(self, pipeline: Pipeline<Outer>) -> Pipeline<Stack<W, Outer>> {
+        self.with(pipeline.wrapper)
+    }
+
+    /// Consumes itself and returns the composed wrapper.
+    pub fn into_inner(self) -> W {
+        self.wrapper
+    }
+}
+
+impl<E, W> Wrapper<E> for Pipeline<W>
+where
+    W: Wrapper<E>,
+{
+    type Endpoint = W::Endpoint;
+
+    #[inline]
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        self.wrapper.wrap(endpoint)
+    }
+}
@@ -0,0 +1,366 @@
+use {
+    super::Wrapper,
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+    },
+    futures::{Async, Poll},
+    http::{
+        header::HeaderMap,
+        uri::{PathAndQuery, Uri},
+        Extensions, Method,
+    },
+    std::{fmt, marker::PhantomData},
+};
+
+/// Creates a `Wrapper` which modifies the request seen by the wrapped endpoint.
+///
+/// The specified function receives a `RequestMut`, through which the method,
+/// the path, the query, the headers and the body of the request can be
+/// rewritten. It is used for writing the middlewares such as the method
+/// override, the path normalization and the decompression of request bodies.
+///
+/// Since the routing of the wrapped endpoint depends on the rewritten request,
+/// the wrapped endpoint is applied after the function is called, at the first
+/// poll of the action. As a result, the rejections from the wrapped endpoint
+/// are not recovered by the enclosing `or` combinators, and this wrapper
+/// should be applied to the entire route tree (or to a subtree mounted by
+/// `endpoint::scope`). Note also that the modifications are kept in the
+/// request after the wrapped endpoint is completed.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::{syntax, wrapper};
+/// # use finchers::test;
+/// # use http::{Method, Request};
+/// let endpoint = syntax::verb::put()
+///     .map(|| "updated")
+///     .or(syntax::verb::post().map(|| "created"))
+///     .wrap(wrapper::rewrite_request(|request| {
+///         let method = match request.headers_mut().remove("x-http-method-override") {
+///             Some(ref value) if request.method() == Method::POST => value.as_bytes().to_owned(),
+///             _ => return Ok(()),
+///         };
+///         let method = Method::from_bytes(&method).map_err(finchers::error::bad_request)?;
+///         request.set_method(method);
+///         Ok(())
+///     }));
+///
+/// let mut runner = test::runner(endpoint);
+/// let request = Request::post("/")
+///     .header("x-http-method-override", "PUT")
+///     .body("")
+///     .unwrap();
+/// assert_eq!(runner.perform(request).unwrap().to_utf8_lossy(), "updated");
+/// assert_eq!(runner.perform(Request::post("/")).unwrap().to_utf8_lossy(), "created");
+/// ```
+pub fn rewrite_request<F, Bd>(f: F) -> RewriteRequest<F, Bd>
+where
+    F: Fn(&mut RequestMut<'_, '_, Bd>) -> Result<(), Error> + Clone,
+{
+    RewriteRequest {
+        f,
+        _marker: PhantomData,
+    }
+}
+
+// ==== RequestMut ====
+
+/// A proxy type which provides the mutable access to the request,
+/// passed to the function of `rewrite_request`.
+pub struct RequestMut<'a, 'cx, Bd> {
+    cx: &'a mut ActionContext<'cx, Bd>,
+    position: &'a mut (usize, usize),
+}
+
+impl<'a, 'cx, Bd> fmt::Debug for RequestMut<'a, 'cx, Bd> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestMut")
+            .field("method", self.cx.method())
+            .field("uri", self.cx.uri())
+            .field("headers", self.cx.headers())
+            .finish()
+    }
+}
+
+impl<'a, 'cx, Bd> RequestMut<'a, 'cx, Bd> {
+    /// Returns the method of the request.
+    pub fn method(&self) -> &Method {
+        self.cx.method()
+    }
+
+    /// Replaces the method of the request.
+    pub fn set_method(&mut self, method: Method) {
+        *self.cx.method_mut() = method;
+    }
+
+    /// Returns the part of the path which is not consumed by the enclosing
+    /// endpoints, without the leading slash.
+    pub fn path(&self) -> &str {
+        &self.cx.uri().path()[self.position.0..]
+    }
+
+    /// Replaces the part of the path returned from `path`.
+    ///
+    /// The segments already consumed by the enclosing endpoints, such as
+    /// the prefix of `endpoint::scope`, are kept as they are.
+    pub fn set_path(&mut self, path: &str) -> Result<(), Error> {
+        let path = path.trim_start_matches('/');
+        let mut new_path = self.cx.uri().path()[..self.position.0].to_owned();
+        if !new_path.ends_with('/') && !path.is_empty() {
+            new_path.push('/');
+        }
+        let pos = new_path.len();
+        new_path += path;
+        let query = self.cx.uri().query().map(ToOwned::to_owned);
+        self.set_path_and_query(new_path, query.as_ref().map(String::as_str))?;
+        self.position.0 = pos;
+        Ok(())
+    }
+
+    /// Returns the query string of the request, if exists.
+    pub fn query(&self) -> Option<&str> {
+        self.cx.uri().query()
+    }
+
+    /// Replaces the query string of the request.
+    pub fn set_query(&mut self, query: Option<&str>) -> Result<(), Error> {
+        let path = self.cx.uri().path().to_owned();
+        self.set_path_and_query(path, query)
+    }
+
+    fn set_path_and_query(&mut self, mut path: String, query: Option<&str>) -> Result<(), Error> {
+        if let Some(query) = query {
+            path.push('?');
+            path += query;
+        }
+        let mut parts = self.cx.uri().clone().into_parts();
+        parts.path_and_query = Some(path.parse::<PathAndQuery>().map_err(error::bad_request)?);
+        *self.cx.uri_mut() = Uri::from_parts(parts).map_err(error::bad_request)?;
+        Ok(())
+    }
+
+    /// Returns a reference to the header map of the request.
+    pub fn headers(&self) -> &HeaderMap {
+        self.cx.headers()
+    }
+
+    /// Returns a mutable reference to the header map of the request.
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.cx.headers_mut()
+    }
+
+    /// Returns a reference to the extension map of the request.
+    pub fn extensions(&self) -> &Extensions {
+        self.cx.extensions()
+    }
+
+    /// Returns a mutable reference to the extension map of the request.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        self.cx.extensions_mut()
+    }
+
+    /// Returns a mutable reference to the request body if exists.
+    pub fn body_mut(&mut self) -> Option<&mut Bd> {
+        self.cx.body_mut()
+    }
+
+    /// Takes the request body in order to replace it with `set_body`.
+    pub fn take_body(&mut self) -> Result<Bd, Error> {
+        self.cx.take_body()
+    }
+
+    /// Replaces the request body seen by the wrapped endpoint.
+    pub fn set_body(&mut self, body: Bd) {
+        self.cx.replace_body(body);
+    }
+}
+
+// ==== RewriteRequest ====
+
+#[allow(missing_docs)]
+pub struct RewriteRequest<F, Bd> {
+    f: F,
+    _marker: PhantomData<fn(Bd)>,
+}
+
+impl<F: Clone, Bd> Clone for RewriteRequest<F, Bd> {
+    fn clone(&self) -> Self {
+        RewriteRequest {
+            f: self.f.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<F, Bd> fmt::Debug for RewriteRequest<F, Bd> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RewriteRequest").finish()
+    }
+}
+
+impl<E, F, Bd> Wrapper<E> for RewriteRequest<F, Bd>
+where
+    E: IsEndpoint,
+{
+    type Endpoint = RewriteRequestEndpoint<E, F, Bd>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        RewriteRequestEndpoint {
+            endpoint,
+            f: self.f,
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+pub struct RewriteRequestEndpoint<E, F, Bd> {
+    endpoint: E,
+    f: F,
+    _marker: PhantomData<fn(Bd)>,
+}
+
+impl<E: Clone, F: Clone, Bd> Clone for RewriteRequestEndpoint<E, F, Bd> {
+    fn clone(&self) -> Self {
+        RewriteRequestEndpoint {
+            endpoint: self.endpoint.clone(),
+            f: self.f.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<E: fmt::Debug, F, Bd> fmt::Debug for RewriteRequestEndpoint<E, F, Bd> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RewriteRequestEndpoint")
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+impl<E: IsEndpoint, F, Bd> IsEndpoint for RewriteRequestEndpoint<E, F, Bd> {}
+
+impl<E, F, Bd> Endpoint<Bd> for RewriteRequestEndpoint<E, F, Bd>
+where
+    E: Endpoint<Bd>,
+    F: Fn(&mut RequestMut<'_, '_, Bd>) -> Result<(), Error> + Clone,
+{
+    type Output = E::Output;
+    type Action = RewriteRequestAction<E::Action, F>;
+
+    fn action(&self) -> Self::Action {
+        RewriteRequestAction {
+            action: self.endpoint.action(),
+            f: self.f.clone(),
+            position: None,
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct RewriteRequestAction<A, F> {
+    action: A,
+    f: F,
+    position: Option<(usize, usize)>,
+}
+
+impl<A, F, Bd> EndpointAction<Bd> for RewriteRequestAction<A, F>
+where
+    A: EndpointAction<Bd>,
+    F: Fn(&mut RequestMut<'_, '_, Bd>) -> Result<(), Error>,
+{
+    type Output = A::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        // The wrapped action is applied in `poll_action`, where the request
+        // can be modified.
+        self.position = Some(cx.position());
+        Ok(Preflight::Incomplete)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        if let Some(mut position) = self.position.take() {
+            (self.f)(&mut RequestMut {
+                cx,
+                position: &mut position,
+            })?;
+            let mut pcx = PreflightContext::resume(cx.context(), position);
+            if let Preflight::Completed(output) = self.action.preflight(&mut pcx)? {
+                return Ok(Async::Ready(output));
+            }
+        }
+        self.action.poll_action(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            endpoint::{syntax, EndpointExt},
+            endpoints,
+            test::{self, ReqBody},
+        },
+        http::Request,
+    };
+
+    #[test]
+    fn test_rewrite_path_in_scope() {
+        let endpoint = crate::endpoint::scope(
+            "/api",
+            syntax::segment("users")
+                .and(syntax::param::<String>())
+                .map(|name: String| name)
+                .wrap(rewrite_request(
+                    |request: &mut RequestMut<'_, '_, ReqBody>| {
+                        let path = request.path().to_lowercase();
+                        request.set_path(&path)
+                    },
+                )),
+        );
+        let mut runner = test::runner(endpoint);
+
+        assert_eq!(
+            runner.apply("/api/USERS/Alice?x=1").ok(),
+            Some("alice".into())
+        );
+        assert!(runner.apply("/API/users/alice").is_err());
+    }
+
+    #[test]
+    fn test_rewrite_headers_and_body() {
+        let endpoint = endpoints::header::optional::<String>("x-decoded")
+            .and(endpoints::body::text())
+            .map(|decoded: Option<String>, body: String| (decoded, body))
+            .wrap(rewrite_request(
+                |request: &mut RequestMut<'_, '_, ReqBody>| {
+                    request.take_body()?;
+                    request.set_body(ReqBody::new("decoded"));
+                    request
+                        .headers_mut()
+                        .insert("x-decoded", "1".parse().unwrap());
+                    Ok(())
+                },
+            ));
+        let mut runner = test::runner(endpoint);
+
+        let request = Request::post("/").body("encoded").unwrap();
+        assert_eq!(
+            runner.apply(request).ok(),
+            Some((Some("1".into()), "decoded".into()))
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use {
+    super::Wrapper,
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+        output::IntoResponse,
+        service::Context,
+    },
+    futures::{Async, Poll},
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        Response,
+    },
+    std::{fmt, sync::Arc, time::Duration},
+};
+
+/// Creates a `Wrapper` which sets the security-related headers to all of
+/// the responses returned from the wrapped endpoint.
+///
+/// By default, the following headers are set:
+///
+/// * `Strict-Transport-Security: max-age=31536000; includeSubDomains`
+/// * `X-Content-Type-Options: nosniff`
+/// * `X-Frame-Options: DENY`
+/// * `Referrer-Policy: strict-origin-when-cross-origin`
+///
+/// The headers are also added to the error responses returned from the
+/// wrapped endpoint, including the ones returned before it is matched
+/// (e.g. `404 Not Found`). The headers already set by the wrapped endpoint
+/// are not overwritten.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::wrapper::{self, ContentSecurityPolicy};
+/// # use finchers::test;
+/// let csp = ContentSecurityPolicy::new()
+///     .default_src(&["'self'"])
+///     .img_src(&["'self'", "https://images.example.com"]);
+///
+/// let endpoint = endpoint::unit()
+///     .map(|| "Hello")
+///     .wrap(wrapper::security_headers().content_security_policy(csp));
+///
+/// let mut runner = test::runner(endpoint);
+/// runner
+///     .perform("/")
+///     .unwrap()
+///     .assert_header("x-content-type-options", "nosniff")
+///     .assert_header(
+///         "content-security-policy",
+///         "default-src 'self'; img-src 'self' https://images.example.com",
+///     );
+/// ```
+pub fn security_headers() -> SecurityHeaders {
+    SecurityHeaders::default()
+}
+
+/// A `Wrapper` which sets the security-related headers.
+///
+/// See the documentation of `security_headers` for details.
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    headers: HeaderMap,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        SecurityHeaders {
+            headers: HeaderMap::new(),
+        }
+        .hsts(Duration::from_secs(365 * 24 * 60 * 60), true)
+        .content_type_options(true)
+        .frame_options(FrameOptions::Deny)
+        .referrer_policy("strict-origin-when-cross-origin")
+    }
+}
+
+impl SecurityHeaders {
+    fn set(mut self, name: HeaderName, value: Option<String>) -> Self {
+        match value {
+            Some(value) => {
+                let value = HeaderValue::from_str(&value)
+                    .unwrap_or_else(|_| panic!("invalid header value for {}: {:?}", name, value));
+                self.headers.insert(name, value);
+            }
+            None => {
+                self.headers.remove(name);
+            }
+        }
+        self
+    }
+
+    /// Sets `Strict-Transport-Security` with the specified `max-age`.
+    pub fn hsts(self, max_age: Duration, include_subdomains: bool) -> Self {
+        let mut value = format!("max-age={}", max_age.as_secs());
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        self.set(header::STRICT_TRANSPORT_SECURITY, Some(value))
+    }
+
+    /// Disables `Strict-Transport-Security`.
+    ///
+    /// This is useful when the application is served only in plaintext.
+    pub fn without_hsts(self) -> Self {
+        self.set(header::STRICT_TRANSPORT_SECURITY, None)
+    }
+
+    /// Sets whether to add `X-Content-Type-Options: nosniff`.
+    pub fn content_type_options(self, nosniff: bool) -> Self {
+        let value = if nosniff {
+            Some("nosniff".into())
+        } else {
+            None
+        };
+        self.set(header::X_CONTENT_TYPE_OPTIONS, value)
+    }
+
+    /// Sets `X-Frame-Options`.
+    pub fn frame_options(self, options: FrameOptions) -> Self {
+        let value = match options {
+            FrameOptions::Deny => Some("DENY".into()),
+            FrameOptions::SameOrigin => Some("SAMEORIGIN".into()),
+            FrameOptions::Disabled => None,
+        };
+        self.set(header::X_FRAME_OPTIONS, value)
+    }
+
+    /// Sets `Referrer-Policy`.
+    pub fn referrer_policy(self, policy: &str) -> Self {
+        self.set(header::REFERRER_POLICY, Some(policy.into()))
+    }
+
+    /// Sets `Content-Security-Policy` (or `Content-Security-Policy-Report-Only`).
+    pub fn content_security_policy(self, policy: ContentSecurityPolicy) -> Self {
+        let (name, other) = if policy.report_only {
+            (
+                header::CONTENT_SECURITY_POLICY_REPORT_ONLY,
+                header::CONTENT_SECURITY_POLICY,
+            )
+        } else {
+            (
+                header::CONTENT_SECURITY_POLICY,
+                header::CONTENT_SECURITY_POLICY_REPORT_ONLY,
+            )
+        };
+        self.set(other, None).set(name, Some(policy.to_string()))
+    }
+
+    /// Sets an arbitrary header in addition to the predefined ones.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+}
+
+/// The value of `X-Frame-Options`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOptions {
+    /// `DENY`
+    Deny,
+    /// `SAMEORIGIN`
+    SameOrigin,
+    /// Does not set the header.
+    Disabled,
+}
+
+/// A builder of the value of `Content-Security-Policy`.
+///
+/// The sources given to the same directive multiple times are merged,
+/// so that the policies provided by separate modules can be composed.
+#[derive(Debug, Clone, Default)]
+pub struct ContentSecurityPolicy {
+    directives: Vec<(String, Vec<String>)>,
+    report_only: bool,
+}
+
+impl ContentSecurityPolicy {
+    /// Creates an empty policy.
+    pub fn new() -> Self {
+        ContentSecurityPolicy::default()
+    }
+
+    /// Adds the sources to the specified directive.
+    pub fn directive<I>(mut self, name: &str, sources: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let pos = match self.directives.iter().position(|(n, _)| n == name) {
+            Some(pos) => pos,
+            None => {
+                self.directives.push((name.to_owned(), vec![]));
+                self.directives.len() - 1
+            }
+        };
+        let values = &mut self.directives[pos].1;
+        for source in sources {
+            let source = source.as_ref();
+            if !values.iter().any(|v| v == source) {
+                values.push(source.to_owned());
+            }
+        }
+        self
+    }
+
+    /// Adds the sources to `default-src`.
+    pub fn default_src<I>(self, sources: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.directive("default-src", sources)
+    }
+
+    /// Adds the sources to `script-src`.
+    pub fn script_src<I>(self, sources: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.directive("script-src", sources)
+    }
+
+    /// Adds the sources to `style-src`.
+    pub fn style_src<I>(self, sources: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.directive("style-src", sources)
+    }
+
+    /// Adds the sources to `img-src`.
+    pub fn img_src<I>(self, sources: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.directive("img-src", sources)
+    }
+
+    /// Adds the sources to `connect-src`.
+    pub fn connect_src<I>(self, sources: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.directive("connect-src", sources)
+    }
+
+    /// Adds the sources to `frame-ancestors`.
+    pub fn frame_ancestors<I>(self, sources: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.directive("frame-ancestors", sources)
+    }
+
+    /// Sets `report-uri`, replacing the previously set one.
+    pub fn report_uri(mut self, uri: &str) -> Self {
+        self.directives.retain(|(name, _)| name != "report-uri");
+        self.directive("report-uri", Some(uri))
+    }
+
+    /// Sets whether to send the policy as `Content-Security-Policy-Report-Only`.
+    pub fn report_only(self, enabled: bool) -> Self {
+        ContentSecurityPolicy {
+            report_only: enabled,
+            ..self
+        }
+    }
+
+    /// Merges the directives of another policy into this one.
+    ///
+    /// The `report-uri` of `other`, if any, replaces the one of this policy.
+    pub fn merge(self, other: ContentSecurityPolicy) -> Self {
+        other
+            .directives
+            .into_iter()
+            .fold(self, |csp, (name, sources)| match &*name {
+                "report-uri" => sources.iter().fold(csp, |csp, uri| csp.report_uri(uri)),
+                _ => csp.directive(&name, sources),
+            })
+    }
+}
+
+impl fmt::Display for ContentSecurityPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, sources)) in self.directives.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            f.write_str(name)?;
+            for source in sources {
+                write!(f, " {}", source)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<E> Wrapper<E> for SecurityHeaders
+where
+    E: IsEndpoint,
+{
+    type Endpoint = SecurityHeadersEndpoint<E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        SecurityHeadersEndpoint {
+            endpoint,
+            headers: Arc::new(self.headers),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct SecurityHeadersEndpoint<E> {
+    endpoint: E,
+    headers: Arc<HeaderMap>,
+}
+
+impl<E: IsEndpoint> IsEndpoint for SecurityHeadersEndpoint<E> {}
+
+impl<E, Bd> Endpoint<Bd> for SecurityHeadersEndpoint<E>
+where
+    E: Endpoint<Bd>,
+    E::Output: IntoResponse,
+{
+    type Output = (Response<<E::Output as IntoResponse>::Body>,);
+    type Action = SecurityHeadersAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        SecurityHeadersAction {
+            action: self.endpoint.action(),
+            headers: self.headers.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct SecurityHeadersAction<A> {
+    action: A,
+    headers: Arc<HeaderMap>,
+}
+
+impl<A> SecurityHeadersAction<A> {
+    fn apply<T>(&self, mut response: Response<T>) -> Response<T> {
+        insert_missing(response.headers_mut(), &self.headers);
+        response
+    }
+}
+
+fn insert_missing(dst: &mut HeaderMap, src: &HeaderMap) {
+    for (name, value) in src {
+        if !dst.contains_key(name) {
+            dst.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+// The headers are registered without allocating a `HeaderMap` in `Context`.
+fn insert_missing_into_context(cx: &mut Context, src: &HeaderMap) {
+    for (name, value) in src {
+        let exists = cx
+            .try_response_headers()
+            .map_or(false, |headers| headers.contains_key(name));
+        if !exists {
+            cx.insert_response_header(name.clone(), value.clone());
+        }
+    }
+}
+
+impl<A, Bd> EndpointAction<Bd> for SecurityHeadersAction<A>
+where
+    A: EndpointAction<Bd>,
+    A::Output: IntoResponse,
+{
+    type Output = (Response<<A::Output as IntoResponse>::Body>,);
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let preflight = self.action.preflight(cx).map_err(|mut err| {
+            // The context cannot be modified during the preflight, and hence
+            // the headers are carried by the error itself.
+            insert_missing(err.headers_mut(), &self.headers);
+            err
+        })?;
+        Ok(preflight.map(|output| (self.apply(output.into_response(cx.request())),)))
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        match self.action.poll_action(cx) {
+            Ok(Async::Ready(output)) => {
+                let response = output.into_response(cx.request());
+                Ok(Async::Ready((self.apply(response),)))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => {
+                insert_missing_into_context(cx, &self.headers);
+                Err(err)
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use {
+    super::Wrapper,
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+        server::DropConnection,
+    },
+    futures::{Async, Future, Poll},
+    http::StatusCode,
+    std::{fmt, sync::Arc, time::Duration},
+    tokio::timer::Delay,
+};
+
+/// Creates a `Wrapper` which traps the requests to the paths typically probed
+/// by the bots and the vulnerability scanners.
+///
+/// The trapped requests never reach the wrapped endpoint, and are rejected
+/// with `404 Not Found` (or by dropping the connection, see `close_connection`)
+/// after a delay (10 seconds by default) in order to slow down the scanners.
+///
+/// The matcher receives the raw, percent-encoded path of the request so that
+/// the encoded traversal attempts (such as `%2e%2e`) are also caught.
+/// See `is_suspicious_path` for the paths matched by default.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::wrapper;
+/// # use std::time::Duration;
+/// let endpoint = endpoint::unit()
+///     .map(|| "Hello")
+///     .wrap(
+///         wrapper::tarpit()
+///             .matcher(|path: &str| {
+///                 wrapper::is_suspicious_path(path) || path.starts_with("/admin.php")
+///             })
+///             .delay(Duration::from_secs(5)),
+///     );
+/// # drop(finchers::test::runner(endpoint));
+/// ```
+pub fn tarpit() -> Tarpit {
+    Tarpit {
+        matcher: Arc::new(is_suspicious_path),
+        delay: Duration::from_secs(10),
+        close: false,
+    }
+}
+
+/// Returns whether the specified raw path is an obviously malicious one.
+///
+/// This function matches the following paths:
+///
+/// * The paths containing the segments of the popular administration pages
+///   or the sensitive files, such as `wp-admin`, `.env` and `.git`.
+/// * The traversal attempts, including the percent-encoded ones such as
+///   `%2e%2e/` and `..%2f`. The encoded separators (`%2f` and `%5c`) are
+///   matched only when they form a `..` segment, since they also appear in
+///   the legitimate paths (e.g. the encoded identifiers).
+/// * The null byte injections, i.e. `%00` at the end of the path or before
+///   an extension (e.g. `/download.php%00.jpg`).
+pub fn is_suspicious_path(path: &str) -> bool {
+    const SEGMENTS: &[&str] = &[
+        "wp-admin",
+        "wp-login.php",
+        "wp-content",
+        "xmlrpc.php",
+        "phpmyadmin",
+        "cgi-bin",
+        ".env",
+        ".git",
+        ".htaccess",
+        ".ds_store",
+    ];
+
+    let path = path.to_ascii_lowercase();
+    if path.ends_with("%00") || path.contains("%00.") {
+        return true;
+    }
+    // Decode the dots and the separators so that the encoded traversal
+    // attempts are split into the segments.
+    let path = path
+        .replace("%2e", ".")
+        .replace("%2f", "/")
+        .replace("%5c", "/")
+        .replace('\\', "/");
+    path.split('/')
+        .any(|segment| segment == ".." || SEGMENTS.contains(&segment))
+}
+
+/// A `Wrapper` which traps the suspicious requests.
+///
+/// See the documentation of `tarpit` for details.
+#[derive(Clone)]
+pub struct Tarpit {
+    matcher: Arc<dyn Fn(&str) -> bool + Send + Sync + 'static>,
+    delay: Duration,
+    close: bool,
+}
+
+impl fmt::Debug for Tarpit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tarpit")
+            .field("delay", &self.delay)
+            .field("close", &self.close)
+            .finish()
+    }
+}
+
+impl Tarpit {
+    /// Sets the function which determines whether to trap the request,
+    /// from the raw path of the request.
+    pub fn matcher<F>(self, matcher: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        Tarpit {
+            matcher: Arc::new(matcher),
+            ..self
+        }
+    }
+
+    /// Sets the duration to wait before responding to the trapped requests.
+    pub fn delay(self, delay: Duration) -> Self {
+        Tarpit { delay, ..self }
+    }
+
+    /// Drops the connection after the delay, instead of responding to
+    /// the trapped requests.
+    ///
+    /// When served by `server::Server`, the HTTP/1 connection is closed without
+    /// sending any response and the HTTP/2 stream is reset. Other runners,
+    /// such as the test runner, receive `404 Not Found` as usual.
+    pub fn close_connection(self) -> Self {
+        Tarpit {
+            close: true,
+            ..self
+        }
+    }
+}
+
+impl<E> Wrapper<E> for Tarpit
+where
+    E: IsEndpoint,
+{
+    type Endpoint = TarpitEndpoint<E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        TarpitEndpoint {
+            endpoint,
+            tarpit: self,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct TarpitEndpoint<E> {
+    endpoint: E,
+    tarpit: Tarpit,
+}
+
+impl<E: IsEndpoint> IsEndpoint for TarpitEndpoint<E> {}
+
+impl<E, Bd> Endpoint<Bd> for TarpitEndpoint<E>
+where
+    E: Endpoint<Bd>,
+{
+    type Output = E::Output;
+    type Action = TarpitAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        TarpitAction {
+            action: self.endpoint.action(),
+            tarpit: self.tarpit.clone(),
+            delay: None,
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct TarpitAction<A> {
+    action: A,
+    tarpit: Tarpit,
+    delay: Option<Delay>,
+}
+
+impl<A, Bd> EndpointAction<Bd> for TarpitAction<A>
+where
+    A: EndpointAction<Bd>,
+{
+    type Output = A::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        if (self.tarpit.matcher)(cx.uri().path()) {
+            let deadline = tokio::clock::now() + self.tarpit.delay;
+            self.delay = Some(Delay::new(deadline));
+            return Ok(Preflight::Incomplete);
+        }
+        self.action.preflight(cx)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        match self.delay {
+            Some(ref mut delay) => {
+                if let Async::NotReady = delay.poll().map_err(error::internal_server_error)? {
+                    return Ok(Async::NotReady);
+                }
+                if self.tarpit.close {
+                    return Err(DropConnection::new(StatusCode::NOT_FOUND).into());
+                }
+                Err(error::not_found("not found"))
+            }
+            None => self.action.poll_action(cx),
+        }
+    }
+}
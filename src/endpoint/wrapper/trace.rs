@@ -0,0 +1,240 @@
+use {
+    super::Wrapper,
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+    },
+    futures::{Async, Poll},
+    std::{borrow::Cow, fmt, sync::Arc, time::Instant},
+};
+
+/// Creates a `Wrapper` which logs the decisions of the wrapped endpoint.
+///
+/// The following events are logged with the specified name, at the `DEBUG`
+/// level by default:
+///
+/// * Whether the wrapped endpoint has matched, and the path segments consumed by it.
+/// * The outcome of the asynchronous part of the endpoint and the time it took.
+///
+/// The events are passed to the global logger, unless another logger is
+/// specified by `Trace::logger`. When the level is disabled, the wrapper does
+/// nothing except delegating to the wrapped endpoint.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::{syntax, wrapper};
+/// # use finchers::test;
+/// let endpoint = syntax::segment("foo")
+///     .map(|| "foo")
+///     .wrap(wrapper::trace("foo"))
+///     .or(syntax::segment("bar")
+///         .map(|| "bar")
+///         .wrap(wrapper::trace("bar")));
+/// // GET /bar:
+/// // DEBUG finchers::trace: [foo] not matched: not matched (404 Not Found)
+/// // DEBUG finchers::trace: [bar] matched (consumed: "bar") and completed
+/// # let mut runner = test::runner(endpoint);
+/// # runner.perform("/bar").unwrap().assert_body("bar");
+/// ```
+pub fn trace(name: impl Into<Cow<'static, str>>) -> Trace {
+    Trace {
+        name: Arc::new(name.into()),
+        level: log::Level::Debug,
+        logger: None,
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Clone)]
+pub struct Trace {
+    name: Arc<Cow<'static, str>>,
+    level: log::Level,
+    logger: Option<Arc<dyn log::Log>>,
+}
+
+impl fmt::Debug for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Trace")
+            .field("name", &self.name)
+            .field("level", &self.level)
+            .finish()
+    }
+}
+
+impl Trace {
+    /// Sets the level at which the events are logged.
+    pub fn level(self, level: log::Level) -> Self {
+        Trace { level, ..self }
+    }
+
+    /// Sets the logger to which the events are passed instead of the global one.
+    pub fn logger(self, logger: impl log::Log + 'static) -> Self {
+        Trace {
+            logger: Some(Arc::new(logger)),
+            ..self
+        }
+    }
+
+    fn metadata(&self) -> log::Metadata<'_> {
+        log::Metadata::builder()
+            .level(self.level)
+            .target(TARGET)
+            .build()
+    }
+
+    fn enabled(&self) -> bool {
+        match self.logger {
+            Some(ref logger) => logger.enabled(&self.metadata()),
+            None => {
+                self.level <= log::STATIC_MAX_LEVEL
+                    && self.level <= log::max_level()
+                    && log::logger().enabled(&self.metadata())
+            }
+        }
+    }
+
+    fn log(&self, args: fmt::Arguments<'_>) {
+        let record = log::Record::builder()
+            .args(args)
+            .metadata(self.metadata())
+            .module_path(Some(module_path!()))
+            .file(Some(file!()))
+            .line(Some(line!()))
+            .build();
+        match self.logger {
+            Some(ref logger) => logger.log(&record),
+            None => log::logger().log(&record),
+        }
+    }
+}
+
+impl<E> Wrapper<E> for Trace
+where
+    E: IsEndpoint,
+{
+    type Endpoint = TraceEndpoint<E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        TraceEndpoint {
+            endpoint,
+            trace: self,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct TraceEndpoint<E> {
+    endpoint: E,
+    trace: Trace,
+}
+
+impl<E: IsEndpoint> IsEndpoint for TraceEndpoint<E> {}
+
+impl<E, Bd> Endpoint<Bd> for TraceEndpoint<E>
+where
+    E: Endpoint<Bd>,
+{
+    type Output = E::Output;
+    type Action = TraceAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        TraceAction {
+            action: self.endpoint.action(),
+            trace: self.trace.clone(),
+            start: None,
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct TraceAction<A> {
+    action: A,
+    trace: Trace,
+    start: Option<Instant>,
+}
+
+const TARGET: &str = "finchers::trace";
+
+impl<A, Bd> EndpointAction<Bd> for TraceAction<A>
+where
+    A: EndpointAction<Bd>,
+{
+    type Output = A::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let (trace, name) = (&self.trace, &self.trace.name);
+        if !trace.enabled() {
+            return self.action.preflight(cx);
+        }
+
+        let before = cx.cursor().remaining_path().as_bytes().len();
+        let result = self.action.preflight(cx);
+        let after = cx.cursor().remaining_path().as_bytes().len();
+        let path = cx.uri().path();
+        let consumed = path[path.len() - before..path.len() - after].trim_end_matches('/');
+
+        match result {
+            Ok(Preflight::Completed(..)) => {
+                trace.log(format_args!(
+                    "[{}] matched (consumed: {:?}) and completed",
+                    name, consumed
+                ));
+            }
+            Ok(Preflight::Incomplete) => {
+                trace.log(format_args!(
+                    "[{}] matched (consumed: {:?})",
+                    name, consumed
+                ));
+                self.start = Some(Instant::now());
+            }
+            Err(ref err) => {
+                trace.log(format_args!(
+                    "[{}] not matched: {} ({})",
+                    name,
+                    err,
+                    err.status_code()
+                ));
+            }
+        }
+        result
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        let result = self.action.poll_action(cx);
+        if let Some(start) = self.start {
+            let (trace, name) = (&self.trace, &self.trace.name);
+            match result {
+                Ok(Async::NotReady) => {}
+                Ok(Async::Ready(..)) => {
+                    trace.log(format_args!(
+                        "[{}] completed in {:?}",
+                        name,
+                        start.elapsed()
+                    ));
+                }
+                Err(ref err) => {
+                    trace.log(format_args!(
+                        "[{}] failed in {:?}: {} ({})",
+                        name,
+                        start.elapsed(),
+                        err,
+                        err.status_code()
+                    ));
+                }
+            }
+        }
+        result
+    }
+}
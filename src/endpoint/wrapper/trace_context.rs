@@ -0,0 +1,625 @@
+use {
+    super::Wrapper,
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+    },
+    futures::Poll,
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    std::{
+        cell::RefCell,
+        collections::hash_map::RandomState,
+        fmt,
+        hash::{BuildHasher, Hasher},
+        sync::atomic::{AtomicUsize, Ordering},
+        time::SystemTime,
+    },
+};
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+const B3: &str = "b3";
+const X_B3_TRACE_ID: &str = "x-b3-traceid";
+const X_B3_SPAN_ID: &str = "x-b3-spanid";
+const X_B3_SAMPLED: &str = "x-b3-sampled";
+const X_B3_FLAGS: &str = "x-b3-flags";
+
+/// Creates a `Wrapper` which propagates the distributed trace context.
+///
+/// The wrapper extracts the context of the caller from the request headers,
+/// in the following order of preference:
+///
+/// * `traceparent` and `tracestate` ([W3C Trace Context])
+/// * `b3` (B3 single header)
+/// * `X-B3-TraceId`, `X-B3-SpanId`, `X-B3-Sampled` and `X-B3-Flags` (B3 multiple headers)
+///
+/// and then starts a new span as a child of it (or a new trace, if the
+/// request has no valid context). The span is entered while the wrapped
+/// endpoint is running, so that `TraceContext::current` returns it and the
+/// requests sent by `Client` carry it to the downstream services.
+///
+/// [W3C Trace Context]: https://www.w3.org/TR/trace-context/
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::wrapper::{self, TraceContext};
+/// # use finchers::test;
+/// let endpoint = endpoint::unit()
+///     .map(|| match TraceContext::current() {
+///         Some(cx) => format!("{:032x}", cx.trace_id()),
+///         None => String::new(),
+///     })
+///     .wrap(wrapper::propagate_trace_context());
+///
+/// let mut runner = test::runner(endpoint);
+/// let response = runner
+///     .perform(
+///         http::Request::get("/")
+///             .header("traceparent", "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"),
+///     )
+///     .unwrap();
+/// assert_eq!(response.to_utf8_lossy(), "0af7651916cd43dd8448eb211c80319c");
+/// ```
+pub fn propagate_trace_context() -> PropagateTraceContext {
+    PropagateTraceContext { sample_new: true }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct PropagateTraceContext {
+    sample_new: bool,
+}
+
+impl PropagateTraceContext {
+    /// Sets whether the traces started by this wrapper are sampled.
+    ///
+    /// The sampling decision of the caller is always respected.
+    pub fn sample_new(self, sampled: bool) -> Self {
+        PropagateTraceContext {
+            sample_new: sampled,
+        }
+    }
+}
+
+impl<E> Wrapper<E> for PropagateTraceContext
+where
+    E: IsEndpoint,
+{
+    type Endpoint = PropagateTraceContextEndpoint<E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        PropagateTraceContextEndpoint {
+            endpoint,
+            sample_new: self.sample_new,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct PropagateTraceContextEndpoint<E> {
+    endpoint: E,
+    sample_new: bool,
+}
+
+impl<E: IsEndpoint> IsEndpoint for PropagateTraceContextEndpoint<E> {}
+
+impl<E, Bd> Endpoint<Bd> for PropagateTraceContextEndpoint<E>
+where
+    E: Endpoint<Bd>,
+{
+    type Output = E::Output;
+    type Action = PropagateTraceContextAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        PropagateTraceContextAction {
+            action: self.endpoint.action(),
+            sample_new: self.sample_new,
+            context: None,
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct PropagateTraceContextAction<A> {
+    action: A,
+    sample_new: bool,
+    context: Option<TraceContext>,
+}
+
+impl<A, Bd> EndpointAction<Bd> for PropagateTraceContextAction<A>
+where
+    A: EndpointAction<Bd>,
+{
+    type Output = A::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let context = match TraceContext::extract(cx.headers()) {
+            Some(parent) => parent.child(),
+            None => TraceContext::new_root(self.sample_new),
+        };
+        let action = &mut self.action;
+        let context = self.context.get_or_insert(context);
+        context.enter(|| action.preflight(cx))
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        let action = &mut self.action;
+        match self.context {
+            Some(ref context) => context.enter(|| action.poll_action(cx)),
+            None => action.poll_action(cx),
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<TraceContext>> = RefCell::new(None);
+}
+
+// ==== TraceContext ====
+
+/// The context of a span in a distributed trace.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    sampled: bool,
+    trace_state: Option<String>,
+    format: Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    W3C,
+    B3,
+}
+
+impl fmt::Debug for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceContext")
+            .field("trace_id", &format_args!("{:032x}", self.trace_id))
+            .field("span_id", &format_args!("{:016x}", self.span_id))
+            .field(
+                "parent_id",
+                &self.parent_id.map(|id| format!("{:016x}", id)),
+            )
+            .field("sampled", &self.sampled)
+            .field("trace_state", &self.trace_state)
+            .finish()
+    }
+}
+
+impl TraceContext {
+    /// Creates the context of a new trace.
+    pub fn new_root(sampled: bool) -> Self {
+        TraceContext {
+            trace_id: u128::from(random_id()) << 64 | u128::from(random_id()),
+            span_id: random_id(),
+            parent_id: None,
+            sampled,
+            trace_state: None,
+            format: Format::W3C,
+        }
+    }
+
+    /// Returns the context of the span entered by the wrapper created with
+    /// `propagate_trace_context`.
+    ///
+    /// This function returns `None` if called outside of the wrapped endpoint,
+    /// including the futures spawned onto another task.
+    pub fn current() -> Option<Self> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Runs the specified function with this span entered.
+    fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Reset(Option<TraceContext>);
+
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                let prev = self.0.take();
+                CURRENT.with(|current| *current.borrow_mut() = prev);
+            }
+        }
+
+        let prev = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let _reset = Reset(prev);
+        f()
+    }
+
+    /// Extracts the context of the remote span from the request headers.
+    ///
+    /// The headers are tried in the order described in `propagate_trace_context`,
+    /// and the invalid ones are ignored.
+    pub fn extract(headers: &HeaderMap) -> Option<Self> {
+        extract_w3c(headers)
+            .or_else(|| extract_b3_single(headers))
+            .or_else(|| extract_b3_multi(headers))
+    }
+
+    /// Creates the context of a new span, as a child of this span.
+    pub fn child(&self) -> Self {
+        TraceContext {
+            span_id: random_id(),
+            parent_id: Some(self.span_id),
+            trace_state: self.trace_state.clone(),
+            ..*self
+        }
+    }
+
+    /// Returns the trace ID.
+    pub fn trace_id(&self) -> u128 {
+        self.trace_id
+    }
+
+    /// Returns the ID of this span.
+    pub fn span_id(&self) -> u64 {
+        self.span_id
+    }
+
+    /// Returns the ID of the parent span, if any.
+    pub fn parent_id(&self) -> Option<u64> {
+        self.parent_id
+    }
+
+    /// Returns whether the trace is sampled.
+    pub fn is_sampled(&self) -> bool {
+        self.sampled
+    }
+
+    /// Returns the vendor-specific data received in `tracestate`, if any.
+    pub fn trace_state(&self) -> Option<&str> {
+        self.trace_state.as_ref().map(String::as_str)
+    }
+
+    /// Returns the value of `traceparent` which represents this span.
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id, self.span_id, self.sampled as u8
+        )
+    }
+
+    /// Inserts the headers which propagate this span to the downstream service.
+    ///
+    /// `traceparent` and `tracestate` are always inserted, and `b3` is also
+    /// inserted if the context has been received in the B3 format.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        headers.insert(
+            HeaderName::from_static(TRACEPARENT),
+            HeaderValue::from_shared(self.traceparent().into()).expect("should be a valid value"),
+        );
+        if let Some(value) = self
+            .trace_state
+            .as_ref()
+            .and_then(|state| HeaderValue::from_str(state).ok())
+        {
+            headers.insert(HeaderName::from_static(TRACESTATE), value);
+        }
+        if self.format == Format::B3 {
+            let value = format!(
+                "{:032x}-{:016x}-{}",
+                self.trace_id, self.span_id, self.sampled as u8
+            );
+            headers.insert(
+                HeaderName::from_static(B3),
+                HeaderValue::from_shared(value.into()).expect("should be a valid value"),
+            );
+        }
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+}
+
+fn parse_hex(s: &str, max_len: usize) -> Option<u128> {
+    if s.is_empty() || s.len() > max_len || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u128::from_str_radix(s, 16).ok()
+}
+
+fn parse_ids(trace_id: &str, span_id: &str) -> Option<(u128, u64)> {
+    let trace_id = parse_hex(trace_id, 32).filter(|&id| id != 0)?;
+    let span_id = parse_hex(span_id, 16).filter(|&id| id != 0)? as u64;
+    Some((trace_id, span_id))
+}
+
+fn extract_w3c(headers: &HeaderMap) -> Option<TraceContext> {
+    let traceparent = header_str(headers, TRACEPARENT)?;
+    let mut parts = traceparent.split('-');
+    let version = parts.next().filter(|v| v.len() == 2)?;
+    let (trace_id, span_id, flags) = (parts.next()?, parts.next()?, parts.next()?);
+    let version = parse_hex(version, 2)?;
+    // the version 00 has exactly four fields, and the future versions may have more.
+    if version == 0xff || (version == 0 && parts.next().is_some()) {
+        return None;
+    }
+    if trace_id.len() != 32 || span_id.len() != 16 || flags.len() != 2 {
+        return None;
+    }
+    let (trace_id, span_id) = parse_ids(trace_id, span_id)?;
+    let flags = parse_hex(flags, 2)?;
+
+    let trace_state: Vec<&str> = headers
+        .get_all(TRACESTATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect();
+
+    Some(TraceContext {
+        trace_id,
+        span_id,
+        parent_id: None,
+        sampled: flags & 0x01 != 0,
+        trace_state: if trace_state.is_empty() {
+            None
+        } else {
+            Some(trace_state.join(","))
+        },
+        format: Format::W3C,
+    })
+}
+
+fn extract_b3_single(headers: &HeaderMap) -> Option<TraceContext> {
+    // `{TraceId}-{SpanId}-{SamplingState}-{ParentSpanId}`, where the last two are optional.
+    let b3 = header_str(headers, B3)?;
+    let mut parts = b3.split('-');
+    let (trace_id, span_id) = parse_ids(parts.next()?, parts.next()?)?;
+    let sampled = match parts.next() {
+        None | Some("1") | Some("d") => true,
+        Some("0") => false,
+        Some(..) => return None,
+    };
+    Some(TraceContext {
+        trace_id,
+        span_id,
+        parent_id: None,
+        sampled,
+        trace_state: None,
+        format: Format::B3,
+    })
+}
+
+fn extract_b3_multi(headers: &HeaderMap) -> Option<TraceContext> {
+    let (trace_id, span_id) = parse_ids(
+        header_str(headers, X_B3_TRACE_ID)?,
+        header_str(headers, X_B3_SPAN_ID)?,
+    )?;
+    let debug = header_str(headers, X_B3_FLAGS) == Some("1");
+    let sampled = match header_str(headers, X_B3_SAMPLED) {
+        Some("0") | Some("false") => debug,
+        _ => true,
+    };
+    Some(TraceContext {
+        trace_id,
+        span_id,
+        parent_id: None,
+        sampled,
+        trace_state: None,
+        format: Format::B3,
+    })
+}
+
+/// Generates a random non-zero ID.
+fn random_id() -> u64 {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        // `RandomState` is seeded randomly, and its keys are changed for each instance.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+        if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.write_u64(elapsed.as_secs());
+            hasher.write_u32(elapsed.subsec_nanos());
+        }
+        let id = hasher.finish();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            client::{self, Client},
+            endpoint::EndpointExt,
+            test,
+        },
+        http::Request,
+        std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+            thread,
+        },
+    };
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|&(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_w3c() {
+        let cx = TraceContext::extract(&headers(&[
+            (
+                "traceparent",
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            ),
+            ("tracestate", "congo=t61rcWkgMzE"),
+        ]))
+        .unwrap();
+        assert_eq!(cx.trace_id(), 0x0af7651916cd43dd8448eb211c80319c);
+        assert_eq!(cx.span_id(), 0xb7ad6b7169203331);
+        assert!(cx.is_sampled());
+        assert_eq!(cx.trace_state(), Some("congo=t61rcWkgMzE"));
+
+        let child = cx.child();
+        assert_eq!(child.trace_id(), cx.trace_id());
+        assert_eq!(child.parent_id(), Some(cx.span_id()));
+        assert_ne!(child.span_id(), cx.span_id());
+
+        let mut injected = HeaderMap::new();
+        child.inject(&mut injected);
+        assert_eq!(injected["traceparent"], child.traceparent());
+        assert_eq!(injected["tracestate"], "congo=t61rcWkgMzE");
+        assert!(!injected.contains_key("b3"));
+    }
+
+    #[test]
+    fn test_extract_w3c_invalid() {
+        for traceparent in &[
+            "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b716920333-01",
+            "00-0af7651916cd43dd8448eb211c80319x-b7ad6b7169203331-01",
+        ] {
+            let mut headers = HeaderMap::new();
+            headers.insert("traceparent", HeaderValue::from_static(traceparent));
+            assert!(TraceContext::extract(&headers).is_none(), "{}", traceparent);
+        }
+
+        // the future version may have additional fields.
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_static("01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00-ext"),
+        );
+        assert!(!TraceContext::extract(&headers).unwrap().is_sampled());
+    }
+
+    #[test]
+    fn test_extract_b3() {
+        let cx = TraceContext::extract(&headers(&[(
+            "b3",
+            "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-0-05e3ac9a4f6e3b90",
+        )]))
+        .unwrap();
+        assert_eq!(cx.trace_id(), 0x80f198ee56343ba864fe8b2a57d3eff7);
+        assert_eq!(cx.span_id(), 0xe457b5a2e4d86bd1);
+        assert!(!cx.is_sampled());
+
+        let mut injected = HeaderMap::new();
+        cx.inject(&mut injected);
+        assert_eq!(
+            injected["b3"],
+            "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-0"
+        );
+
+        // 64-bit trace ID in the multiple headers.
+        let cx = TraceContext::extract(&headers(&[
+            ("x-b3-traceid", "a3ce929d0e0e4736"),
+            ("x-b3-spanid", "00f067aa0ba902b7"),
+            ("x-b3-sampled", "1"),
+        ]))
+        .unwrap();
+        assert_eq!(cx.trace_id(), 0xa3ce929d0e0e4736);
+        assert_eq!(cx.span_id(), 0x00f067aa0ba902b7);
+        assert!(cx.is_sampled());
+
+        // the sampling-only header does not carry the context.
+        assert!(TraceContext::extract(&headers(&[("b3", "0")])).is_none());
+    }
+
+    #[test]
+    fn test_prefer_w3c() {
+        let cx = TraceContext::extract(&headers(&[
+            (
+                "traceparent",
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            ),
+            ("b3", "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1"),
+        ]))
+        .unwrap();
+        assert_eq!(cx.trace_id(), 0x0af7651916cd43dd8448eb211c80319c);
+    }
+
+    #[test]
+    fn test_new_root() {
+        let (a, b) = (TraceContext::new_root(true), TraceContext::new_root(false));
+        assert_ne!(a.trace_id(), b.trace_id());
+        assert_eq!(a.parent_id(), None);
+        assert!(a.traceparent().ends_with("-01"));
+        assert!(b.traceparent().ends_with("-00"));
+    }
+
+    #[test]
+    fn test_client_propagation() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let upstream = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = vec![];
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                head.push(line.trim_end().to_owned());
+            }
+            (&stream)
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                .unwrap();
+            head
+        });
+
+        let uri: http::Uri = format!("http://{}/", addr).parse().unwrap();
+        let mut runner = test::runner(
+            client::client()
+                .and_then(move |client: Client| client.get_bytes(uri.clone()))
+                .map(|body: bytes::Bytes| body.to_vec())
+                .wrap(propagate_trace_context()),
+        );
+        let body = runner
+            .apply(
+                Request::get("/")
+                    .header("b3", "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1"),
+            )
+            .unwrap();
+        assert_eq!(body, b"ok");
+
+        let head = upstream.join().unwrap();
+        let traceparent = head
+            .iter()
+            .find(|line| line.starts_with("traceparent: "))
+            .map(|line| &line["traceparent: ".len()..])
+            .unwrap();
+        assert!(traceparent.starts_with("00-80f198ee56343ba864fe8b2a57d3eff7-"));
+        assert!(!traceparent.contains("-e457b5a2e4d86bd1-"));
+        assert!(traceparent.ends_with("-01"));
+        assert!(head
+            .iter()
+            .any(|line| line.starts_with("b3: 80f198ee56343ba864fe8b2a57d3eff7-")));
+    }
+}
@@ -0,0 +1,398 @@
+use {
+    super::Wrapper,
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        contrib::diesel::{Pool, Transaction},
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+        util::blocking,
+    },
+    diesel::Connection,
+    futures::{Async, Future, Poll},
+    http::StatusCode,
+    std::{fmt, mem},
+    tokio::executor::{DefaultExecutor, Executor},
+};
+
+/// Creates a `Wrapper` which runs the wrapped endpoint within a database transaction.
+///
+/// After the wrapped endpoint has been matched, a connection is checked out from
+/// the pool and a transaction is started on it. The connection is exposed to the
+/// wrapped endpoint as a `Transaction`, which can be extracted with
+/// `contrib::diesel::transaction`. The transaction is committed if the wrapped
+/// endpoint completes successfully, and rolled back if it returns an error,
+/// panics or is cancelled.
+///
+/// Beginning, committing and rolling back the transaction are run within the
+/// blocking section of the threadpool, and the rollback on cancellation is run
+/// on a spawned task. Since the queries are blocking, the handlers should also
+/// run them within the blocking section by using `util::blocking`.
+///
+/// Note that the outcome of the transaction is determined by the result of the
+/// wrapped endpoint, *not* by the status code of the response converted from it.
+/// The handlers should return an `Err` in order to roll back the transaction.
+///
+/// # Example
+///
+/// ```no_run
+/// # use finchers::prelude::*;
+/// # use finchers::contrib::{diesel::{self, Transaction}, PoolConfig};
+/// # use finchers::endpoint::{syntax::path, wrapper};
+/// # use finchers::error::Error;
+/// # use finchers::util::blocking;
+/// # use serde::Deserialize;
+/// use ::diesel::sqlite::SqliteConnection;
+/// # #[derive(Debug, Deserialize)]
+/// # struct Transfer { from: i32, to: i32, amount: i64 }
+/// # fn withdraw(_: &SqliteConnection, _: i32, _: i64) -> Result<(), Error> { Ok(()) }
+/// # fn deposit(_: &SqliteConnection, _: i32, _: i64) -> Result<(), Error> { Ok(()) }
+/// # let pool = diesel::pool::<SqliteConnection>("app.db", &PoolConfig::default()).unwrap();
+///
+/// let transfer = path!(@post "/transfer")
+///     .and(endpoints::body::json::<Transfer>())
+///     .and(diesel::transaction::<SqliteConnection>())
+///     .and_then(|transfer: Transfer, tx: Transaction<SqliteConnection>| {
+///         blocking(move || {
+///             let conn = tx.connection();
+///             withdraw(&*conn, transfer.from, transfer.amount)?;
+///             deposit(&*conn, transfer.to, transfer.amount)?;
+///             Ok::<_, Error>("transferred")
+///         })
+///     })
+///     .wrap(wrapper::transactional(pool.clone()));
+/// # drop(transfer);
+/// ```
+pub fn transactional<C>(pool: Pool<C>) -> Transactional<C>
+where
+    C: Connection + 'static,
+{
+    Transactional { pool }
+}
+
+/// A `Wrapper` which runs the wrapped endpoint within a database transaction.
+///
+/// See the documentation of `transactional` for details.
+pub struct Transactional<C>
+where
+    C: Connection + 'static,
+{
+    pool: Pool<C>,
+}
+
+impl<C> Clone for Transactional<C>
+where
+    C: Connection + 'static,
+{
+    fn clone(&self) -> Self {
+        Transactional {
+            pool: self.pool.clone(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for Transactional<C>
+where
+    C: Connection + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transactional").finish()
+    }
+}
+
+impl<E, C> Wrapper<E> for Transactional<C>
+where
+    E: IsEndpoint,
+    C: Connection + 'static,
+{
+    type Endpoint = TransactionalEndpoint<E, C>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        TransactionalEndpoint {
+            endpoint,
+            pool: self.pool,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+pub struct TransactionalEndpoint<E, C>
+where
+    C: Connection + 'static,
+{
+    endpoint: E,
+    pool: Pool<C>,
+}
+
+impl<E, C> fmt::Debug for TransactionalEndpoint<E, C>
+where
+    E: fmt::Debug,
+    C: Connection + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransactionalEndpoint")
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+impl<E, C> IsEndpoint for TransactionalEndpoint<E, C>
+where
+    E: IsEndpoint,
+    C: Connection + 'static,
+{
+}
+
+impl<E, C, Bd> Endpoint<Bd> for TransactionalEndpoint<E, C>
+where
+    E: Endpoint<Bd>,
+    C: Connection + 'static,
+{
+    type Output = E::Output;
+    type Action = TransactionalAction<E::Action, C, E::Output>;
+
+    fn action(&self) -> Self::Action {
+        TransactionalAction {
+            action: self.endpoint.action(),
+            pool: self.pool.clone(),
+            state: State::Begin,
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct TransactionalAction<A, C, T>
+where
+    C: Connection + 'static,
+{
+    action: A,
+    pool: Pool<C>,
+    state: State<C, T>,
+}
+
+enum State<C, T>
+where
+    C: Connection + 'static,
+{
+    Begin,
+    Running(Transaction<C>),
+    Committing(Transaction<C>, Option<T>),
+    RollingBack(Transaction<C>, Option<Error>),
+    Done,
+}
+
+impl<A, C, Bd> EndpointAction<Bd> for TransactionalAction<A, C, A::Output>
+where
+    A: EndpointAction<Bd>,
+    C: Connection + 'static,
+{
+    type Output = A::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        // The output completed without polling does not touch the database,
+        // so no transaction is started for it.
+        let preflight = self.action.preflight(cx)?;
+        if let Preflight::Completed(..) = preflight {
+            self.state = State::Done;
+        }
+        Ok(preflight)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        loop {
+            self.state = match mem::replace(&mut self.state, State::Done) {
+                State::Begin => {
+                    let pool = &self.pool;
+                    let polled = blocking(|| {
+                        let conn = pool
+                            .get()
+                            .map_err(|e| error::fail(e, StatusCode::SERVICE_UNAVAILABLE))?;
+                        Transaction::begin(conn)
+                            .map_err(|e| error::fail(e, StatusCode::INTERNAL_SERVER_ERROR))
+                    })
+                    .poll();
+                    match polled {
+                        Ok(Async::Ready(tx)) => {
+                            cx.extensions_mut().insert(tx.clone());
+                            State::Running(tx)
+                        }
+                        Ok(Async::NotReady) => {
+                            self.state = State::Begin;
+                            return Ok(Async::NotReady);
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                State::Running(tx) => match self.action.poll_action(cx) {
+                    Ok(Async::Ready(output)) => State::Committing(tx, Some(output)),
+                    Ok(Async::NotReady) => {
+                        self.state = State::Running(tx);
+                        return Ok(Async::NotReady);
+                    }
+                    Err(err) => State::RollingBack(tx, Some(err)),
+                },
+
+                State::Committing(tx, mut output) => match blocking(|| tx.commit()).poll() {
+                    Ok(Async::Ready(())) => {
+                        cx.extensions_mut().remove::<Transaction<C>>();
+                        let output = output.take().expect("the output has already been taken");
+                        return Ok(Async::Ready(output));
+                    }
+                    Ok(Async::NotReady) => {
+                        self.state = State::Committing(tx, output);
+                        return Ok(Async::NotReady);
+                    }
+                    Err(err) => State::RollingBack(
+                        tx,
+                        Some(error::fail(err, StatusCode::INTERNAL_SERVER_ERROR)),
+                    ),
+                },
+
+                State::RollingBack(tx, mut err) => match blocking(|| tx.rollback()).poll() {
+                    Ok(Async::NotReady) => {
+                        self.state = State::RollingBack(tx, err);
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(())) | Err(..) => {
+                        cx.extensions_mut().remove::<Transaction<C>>();
+                        return Err(err.take().expect("the error has already been taken"));
+                    }
+                },
+
+                State::Done => panic!("the action has already been polled"),
+            };
+        }
+    }
+}
+
+impl<A, C, T> Drop for TransactionalAction<A, C, T>
+where
+    C: Connection + 'static,
+{
+    fn drop(&mut self) {
+        // The action is dropped before completion when the wrapped endpoint
+        // has panicked or the request has been cancelled.
+        let tx = match mem::replace(&mut self.state, State::Done) {
+            State::Running(tx) | State::Committing(tx, ..) | State::RollingBack(tx, ..) => tx,
+            State::Begin | State::Done => return,
+        };
+        let rollback = {
+            let tx = tx.clone();
+            blocking(move || tx.rollback()).map_err(|err| {
+                log::error!("failed to roll back the cancelled transaction: {}", err)
+            })
+        };
+        // The connection must not be returned to the pool within the
+        // transaction, so it is rolled back in place if the task cannot be spawned.
+        if DefaultExecutor::current()
+            .spawn(Box::new(rollback))
+            .is_err()
+        {
+            if let Err(err) = tx.rollback() {
+                log::error!("failed to roll back the cancelled transaction: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            contrib::{diesel as contrib_diesel, PoolConfig},
+            endpoint::syntax,
+            prelude::*,
+            test,
+        },
+        diesel::{dsl::sql, sql_query, sql_types::BigInt, sqlite::SqliteConnection, RunQueryDsl},
+    };
+
+    fn count(pool: &Pool<SqliteConnection>) -> i64 {
+        diesel::select(sql::<BigInt>("COUNT(*) FROM items"))
+            .get_result(&*pool.get().unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_commit_and_rollback() {
+        // The in-memory database is private to the connection, so that
+        // the pool holds only one connection.
+        let config = PoolConfig {
+            max_size: 1,
+            ..PoolConfig::default()
+        };
+        let pool = contrib_diesel::pool::<SqliteConnection>(":memory:", &config).unwrap();
+        sql_query("CREATE TABLE items (name TEXT NOT NULL)")
+            .execute(&*pool.get().unwrap())
+            .unwrap();
+
+        let endpoint = syntax::param::<String>()
+            .and(contrib_diesel::transaction::<SqliteConnection>())
+            .and_then(|name: String, tx: Transaction<SqliteConnection>| {
+                sql_query("INSERT INTO items (name) VALUES ('item')")
+                    .execute(&*tx.connection())
+                    .map_err(|e| error::fail(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+                if name == "fail" {
+                    return Err(error::bad_request("rolled back"));
+                }
+                Ok(name)
+            })
+            .wrap(transactional(pool.clone()));
+        let mut runner = test::runner(endpoint);
+
+        runner.perform("/ok").unwrap().assert_status(200);
+        assert_eq!(count(&pool), 1);
+
+        runner.perform("/fail").unwrap().assert_status(400);
+        assert_eq!(count(&pool), 1);
+    }
+
+    fn cancelled_action(pool: &Pool<SqliteConnection>) -> impl Drop {
+        let endpoint = syntax::param::<String>().wrap(transactional(pool.clone()));
+        let mut action = Endpoint::<()>::action(&endpoint);
+        let tx = Transaction::begin(pool.get().unwrap()).unwrap();
+        sql_query("INSERT INTO items (name) VALUES ('item')")
+            .execute(&*tx.connection())
+            .unwrap();
+        action.state = State::Running(tx);
+        action
+    }
+
+    #[test]
+    fn test_rollback_on_cancel() {
+        use {futures::future, tokio::runtime::current_thread::Runtime};
+
+        let config = PoolConfig {
+            max_size: 1,
+            ..PoolConfig::default()
+        };
+        let pool = contrib_diesel::pool::<SqliteConnection>(":memory:", &config).unwrap();
+        sql_query("CREATE TABLE items (name TEXT NOT NULL)")
+            .execute(&*pool.get().unwrap())
+            .unwrap();
+
+        // rolled back on the spawned task.
+        let mut rt = Runtime::new().unwrap();
+        let action = cancelled_action(&pool);
+        rt.block_on(future::lazy(move || {
+            drop(action);
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+        rt.run().unwrap();
+        assert_eq!(count(&pool), 0);
+
+        // rolled back in place, since no executor is available.
+        drop(cancelled_action(&pool));
+        assert_eq!(count(&pool), 0);
+    }
+}
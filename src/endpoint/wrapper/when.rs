@@ -0,0 +1,305 @@
+use {
+    super::{feature_flag::FlagRule, Wrapper},
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+        service::Context,
+    },
+    either::Either,
+    futures::Poll,
+    http::header::HeaderName,
+    std::{borrow::Cow, fmt, sync::Arc},
+};
+
+/// Creates a `Wrapper` which applies `wrapper` only to the requests
+/// matching the specified predicate.
+///
+/// The predicate is evaluated for each request before routing. The other
+/// requests are passed to the original endpoint as it is, so that the same
+/// route tree can be shared between both cases. Since the wrapped and the
+/// original endpoint may have different outputs, the output of the returned
+/// endpoint is unified into `Either`, as with `EndpointExt::or`.
+///
+/// The predicate is either a closure `Fn(&Context) -> bool` or one of the
+/// helpers `path_prefix`, `header_present` and `env_flag`.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::{syntax, wrapper};
+/// # use finchers::test;
+/// let endpoint = syntax::segment("admin")
+///     .map(|| "admin")
+///     .or(syntax::segment("public").map(|| "public"))
+///     .wrap(wrapper::when(
+///         wrapper::path_prefix("/admin"),
+///         wrapper::security_headers(),
+///     ));
+///
+/// let mut runner = test::runner(endpoint);
+/// runner
+///     .perform("/admin")
+///     .unwrap()
+///     .assert_header("x-content-type-options", "nosniff");
+/// runner
+///     .perform("/public")
+///     .unwrap()
+///     .assert_no_header("x-content-type-options");
+/// ```
+pub fn when<P, W>(predicate: P, wrapper: W) -> When<P, W>
+where
+    P: RequestPredicate,
+{
+    When {
+        predicate: Arc::new(predicate),
+        wrapper,
+    }
+}
+
+// ==== RequestPredicate ====
+
+/// A trait representing the condition on the incoming requests.
+pub trait RequestPredicate: Send + Sync + 'static {
+    /// Returns whether the request matches the condition.
+    fn matches(&self, cx: &Context) -> bool;
+}
+
+impl<F> RequestPredicate for F
+where
+    F: Fn(&Context) -> bool + Send + Sync + 'static,
+{
+    fn matches(&self, cx: &Context) -> bool {
+        (*self)(cx)
+    }
+}
+
+/// Creates a `RequestPredicate` which matches the requests whose path
+/// starts with the specified segments.
+///
+/// The prefix is compared per segment against the whole path of the request,
+/// i.e. `/admin` matches `/admin` and `/admin/users` but not `/administrator`.
+pub fn path_prefix(prefix: &str) -> PathPrefix {
+    PathPrefix {
+        segments: prefix
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(ToOwned::to_owned)
+            .collect(),
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct PathPrefix {
+    segments: Vec<String>,
+}
+
+impl RequestPredicate for PathPrefix {
+    fn matches(&self, cx: &Context) -> bool {
+        let mut path = cx.uri().path().split('/').filter(|s| !s.is_empty());
+        self.segments
+            .iter()
+            .all(|expected| path.next() == Some(expected.as_str()))
+    }
+}
+
+/// Creates a `RequestPredicate` which matches the requests with the
+/// specified header.
+pub fn header_present(name: HeaderName) -> HeaderPresent {
+    HeaderPresent { name }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct HeaderPresent {
+    name: HeaderName,
+}
+
+impl RequestPredicate for HeaderPresent {
+    fn matches(&self, cx: &Context) -> bool {
+        cx.headers().contains_key(&self.name)
+    }
+}
+
+/// Creates a `RequestPredicate` which matches all requests while the
+/// specified environment variable is enabled.
+///
+/// The variable is read at each evaluation, and is regarded as enabled if
+/// its value is `true`, `on`, `yes`, `1` or `100%` (see `FlagRule`).
+pub fn env_flag(name: impl Into<Cow<'static, str>>) -> EnvFlag {
+    EnvFlag { name: name.into() }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct EnvFlag {
+    name: Cow<'static, str>,
+}
+
+impl RequestPredicate for EnvFlag {
+    fn matches(&self, _: &Context) -> bool {
+        std::env::var(&*self.name)
+            .ok()
+            .and_then(|value| value.parse::<FlagRule>().ok())
+            .map_or(false, |rule| rule.evaluate(&self.name, None))
+    }
+}
+
+// ==== When ====
+
+/// A `Wrapper` which applies the inner wrapper conditionally.
+///
+/// See the documentation of `when` for details.
+pub struct When<P, W> {
+    predicate: Arc<P>,
+    wrapper: W,
+}
+
+impl<P, W: Clone> Clone for When<P, W> {
+    fn clone(&self) -> Self {
+        When {
+            predicate: self.predicate.clone(),
+            wrapper: self.wrapper.clone(),
+        }
+    }
+}
+
+impl<P, W: fmt::Debug> fmt::Debug for When<P, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("When")
+            .field("wrapper", &self.wrapper)
+            .finish()
+    }
+}
+
+impl<P, W, E> Wrapper<E> for When<P, W>
+where
+    P: RequestPredicate,
+    W: Wrapper<E>,
+    E: IsEndpoint + Clone,
+{
+    type Endpoint = WhenEndpoint<P, W::Endpoint, E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        WhenEndpoint {
+            predicate: self.predicate,
+            wrapped: self.wrapper.wrap(endpoint.clone()),
+            endpoint,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+pub struct WhenEndpoint<P, W, E> {
+    predicate: Arc<P>,
+    wrapped: W,
+    endpoint: E,
+}
+
+impl<P, W: Clone, E: Clone> Clone for WhenEndpoint<P, W, E> {
+    fn clone(&self) -> Self {
+        WhenEndpoint {
+            predicate: self.predicate.clone(),
+            wrapped: self.wrapped.clone(),
+            endpoint: self.endpoint.clone(),
+        }
+    }
+}
+
+impl<P, W: fmt::Debug, E: fmt::Debug> fmt::Debug for WhenEndpoint<P, W, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WhenEndpoint")
+            .field("wrapped", &self.wrapped)
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+impl<P, W, E> IsEndpoint for WhenEndpoint<P, W, E>
+where
+    P: RequestPredicate,
+    W: IsEndpoint,
+    E: IsEndpoint,
+{
+}
+
+impl<P, W, E, T1, T2, Bd> Endpoint<Bd> for WhenEndpoint<P, W, E>
+where
+    P: RequestPredicate,
+    W: Endpoint<Bd, Output = (T1,)>,
+    E: Endpoint<Bd, Output = (T2,)>,
+{
+    type Output = (Either<T1, T2>,);
+    type Action = WhenAction<P, W::Action, E::Action>;
+
+    fn action(&self) -> Self::Action {
+        WhenAction {
+            predicate: self.predicate.clone(),
+            state: State::Init(self.wrapped.action(), self.endpoint.action()),
+        }
+    }
+}
+
+#[allow(missing_debug_implementations)]
+enum State<L, R> {
+    Init(L, R),
+    Left(L),
+    Right(R),
+    Done,
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct WhenAction<P, L, R> {
+    predicate: Arc<P>,
+    state: State<L, R>,
+}
+
+impl<P, L, R, T1, T2, Bd> EndpointAction<Bd> for WhenAction<P, L, R>
+where
+    P: RequestPredicate,
+    L: EndpointAction<Bd, Output = (T1,)>,
+    R: EndpointAction<Bd, Output = (T2,)>,
+{
+    type Output = (Either<T1, T2>,);
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        self.state = match std::mem::replace(&mut self.state, State::Done) {
+            State::Init(mut left, _) if self.predicate.matches(cx) => {
+                if let Preflight::Completed((output,)) = left.preflight(cx)? {
+                    return Ok(Preflight::Completed((Either::Left(output),)));
+                }
+                State::Left(left)
+            }
+            State::Init(_, mut right) => {
+                if let Preflight::Completed((output,)) = right.preflight(cx)? {
+                    return Ok(Preflight::Completed((Either::Right(output),)));
+                }
+                State::Right(right)
+            }
+            _ => panic!("unexpected condition"),
+        };
+        Ok(Preflight::Incomplete)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        match self.state {
+            State::Left(ref mut t) => t
+                .poll_action(cx)
+                .map(|x| x.map(|(out,)| (Either::Left(out),))),
+            State::Right(ref mut t) => t
+                .poll_action(cx)
+                .map(|x| x.map(|(out,)| (Either::Right(out),))),
+            _ => panic!("unexpected condition"),
+        }
+    }
+}
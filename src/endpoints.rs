@@ -1,6 +1,25 @@
 //! Built-in endpoints.
 
+pub mod admin;
+pub mod auth;
 pub mod body;
+pub mod cancel;
+pub mod expect;
+pub mod form;
 pub mod fs;
+#[cfg(feature = "grpc-web")]
+pub mod grpc_web;
 pub mod header;
+pub mod health;
+pub mod lang;
+pub mod long_poll;
+pub mod pagination;
 pub mod query;
+pub mod tower;
+#[cfg(feature = "tus")]
+pub mod tus;
+pub mod webdav;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+pub use self::{cancel::on_disconnect, long_poll::long_poll};
@@ -0,0 +1,663 @@
+//! Endpoints for inspecting the running application.
+//!
+//! The endpoint created by `suite` serves the following resources as JSON,
+//! relative to the path where it is mounted:
+//!
+//! * `GET /` - the list of the resources below.
+//! * `GET /build` - the name and version of the application and Finchers.
+//! * `GET /config` - the configuration registered by `Admin::config`.
+//! * `GET /routes` - the route table registered by `Admin::route`.
+//! * `GET /errors` - the recent errors recorded by `Monitor`.
+//! * `GET /runtime` - the uptime and the request counters recorded by `Monitor`.
+//! * `GET /stuck` - the requests exceeding the threshold of `FlightRecorder`.
+//!
+//! The routes are not collected from the endpoints automatically, and hence
+//! they must be registered along with the endpoints. Tokio 0.1 does not expose
+//! the statistics of its scheduler, so the runtime stats are measured at the
+//! request level by `Monitor`, along with the metrics of the tasks spawned by
+//! the server if `Admin::runtime_metrics` is set (requires the feature
+//! `runtime-metrics`).
+//!
+//! Since these resources expose the internals of the application, `suite`
+//! requires a `Wrapper` which protects them, such as `bearer_token`.
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::endpoint::syntax::{self, path};
+//! # use finchers::endpoints::admin::{self, Admin, Monitor};
+//! # use finchers::test;
+//! # use http::{Method, Request};
+//! let monitor = Monitor::new(64);
+//! let admin = Admin::new()
+//!     .build_info(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+//!     .config(serde_json::json!({ "workers": 4 }))
+//!     .route(Method::GET, "/hello")
+//!     .monitor(&monitor);
+//!
+//! let endpoint = path!(@get "/hello")
+//!     .map(|| "Hello")
+//!     .or(syntax::segment("admin").and(admin::suite(&admin, admin::bearer_token("s3cr3t"))))
+//!     .wrap(monitor.wrapper());
+//!
+//! let mut runner = test::runner(endpoint);
+//! let response = runner
+//!     .perform(Request::get("/admin/routes").header("authorization", "Bearer s3cr3t"))
+//!     .unwrap();
+//! assert_eq!(response.to_utf8_lossy(), r#"[{"method":"GET","path":"/hello"}]"#);
+//!
+//! // the requests without the token are rejected.
+//! let response = runner.perform("/admin/routes").unwrap();
+//! assert!(response.status().is_client_error());
+//! ```
+
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Oneshot,
+            OneshotAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{
+            wrapper::{FlightRecorder, Wrapper},
+            Endpoint, IsEndpoint,
+        },
+        error::{self, Error},
+        output::Json,
+        util::constant_time_eq,
+    },
+    futures::Poll,
+    http::{header, Method, StatusCode},
+    serde::Serialize,
+    serde_json::{json, Value},
+    std::{
+        collections::VecDeque,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::{Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+// ==== Admin ====
+
+/// The information served by the endpoint created by `suite`.
+#[derive(Debug, Clone, Default)]
+pub struct Admin {
+    inner: Arc<AdminInner>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct AdminInner {
+    build: Option<(String, String)>,
+    config: Value,
+    routes: Vec<(Method, String)>,
+    monitor: Option<Monitor>,
+    flight_recorder: Option<FlightRecorder>,
+    #[cfg(feature = "runtime-metrics")]
+    runtime_metrics: Option<crate::server::RuntimeMetrics>,
+}
+
+impl Admin {
+    /// Creates an empty `Admin`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn configure(mut self, f: impl FnOnce(&mut AdminInner)) -> Self {
+        f(Arc::make_mut(&mut self.inner));
+        self
+    }
+
+    /// Sets the name and version of the application.
+    pub fn build_info(self, name: impl Into<String>, version: impl Into<String>) -> Self {
+        self.configure(|inner| inner.build = Some((name.into(), version.into())))
+    }
+
+    /// Sets the configuration of the application.
+    ///
+    /// The value is serialized immediately, so the secrets should be removed
+    /// before passing it.
+    ///
+    /// # Panics
+    /// This method panics if the value could not be serialized into JSON.
+    pub fn config(self, config: impl Serialize) -> Self {
+        let config = serde_json::to_value(config).expect("failed to serialize the configuration");
+        self.configure(|inner| inner.config = config)
+    }
+
+    /// Registers a route to the route table.
+    pub fn route(self, method: Method, path: impl Into<String>) -> Self {
+        self.configure(|inner| inner.routes.push((method, path.into())))
+    }
+
+    /// Sets the `Monitor` which provides the recent errors and the runtime stats.
+    pub fn monitor(self, monitor: &Monitor) -> Self {
+        let monitor = monitor.clone();
+        self.configure(|inner| inner.monitor = Some(monitor))
+    }
+
+    /// Sets the `FlightRecorder` which provides the stuck requests.
+    pub fn flight_recorder(self, recorder: &FlightRecorder) -> Self {
+        let recorder = recorder.clone();
+        self.configure(|inner| inner.flight_recorder = Some(recorder))
+    }
+
+    /// Sets the `RuntimeMetrics` whose snapshot is served as `executor`
+    /// in the runtime stats.
+    #[cfg(feature = "runtime-metrics")]
+    pub fn runtime_metrics(self, metrics: &crate::server::RuntimeMetrics) -> Self {
+        let metrics = metrics.clone();
+        self.configure(|inner| inner.runtime_metrics = Some(metrics))
+    }
+
+    fn runtime(&self) -> Value {
+        let inner = &*self.inner;
+        #[allow(unused_mut)]
+        let mut value = match inner.monitor {
+            Some(ref monitor) => json!(monitor.stats()),
+            None => Value::Null,
+        };
+        #[cfg(feature = "runtime-metrics")]
+        {
+            if let Some(ref metrics) = inner.runtime_metrics {
+                if !value.is_object() {
+                    value = json!({});
+                }
+                value["executor"] = json!(metrics.snapshot());
+            }
+        }
+        value
+    }
+
+    fn resource(&self, name: &str) -> Option<Value> {
+        let inner = &*self.inner;
+        let value = match name {
+            "" => json!(["build", "config", "routes", "errors", "runtime", "stuck"]),
+            "build" => {
+                let (name, version) = match inner.build {
+                    Some((ref name, ref version)) => (Some(name), Some(version)),
+                    None => (None, None),
+                };
+                json!({
+                    "name": name,
+                    "version": version,
+                    "finchers": env!("CARGO_PKG_VERSION"),
+                })
+            }
+            "config" => inner.config.clone(),
+            "routes" => inner
+                .routes
+                .iter()
+                .map(|(method, path)| json!({ "method": method.as_str(), "path": path }))
+                .collect(),
+            "errors" => match inner.monitor {
+                Some(ref monitor) => json!(monitor.recent_errors()),
+                None => json!([]),
+            },
+            "runtime" => self.runtime(),
+            "stuck" => match inner.flight_recorder {
+                Some(ref recorder) => json!(recorder.stuck()),
+                None => json!([]),
+            },
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+/// Create an endpoint which serves the information of `Admin`, protected by
+/// the specified `Wrapper`.
+pub fn suite<W>(admin: &Admin, auth: W) -> W::Endpoint
+where
+    W: Wrapper<AdminEndpoint>,
+{
+    auth.wrap(AdminEndpoint {
+        admin: admin.clone(),
+    })
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct AdminEndpoint {
+    admin: Admin,
+}
+
+impl IsEndpoint for AdminEndpoint {}
+
+impl<Bd> Endpoint<Bd> for AdminEndpoint {
+    type Output = (Json<Value>,);
+    type Action = Oneshot<AdminAction>;
+
+    fn action(&self) -> Self::Action {
+        AdminAction {
+            admin: self.admin.clone(),
+        }
+        .into_action()
+    }
+}
+
+#[doc(hidden)]
+#[allow(missing_debug_implementations)]
+pub struct AdminAction {
+    admin: Admin,
+}
+
+impl OneshotAction for AdminAction {
+    type Output = (Json<Value>,);
+
+    fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+        let name = cx
+            .cursor()
+            .next()
+            .map(|segment| segment.percent_decode_lossy().into_owned())
+            .unwrap_or_default();
+        if cx.cursor().next().is_some() {
+            return Err(error::not_found("not matched"));
+        }
+        let value = self
+            .admin
+            .resource(&name)
+            .ok_or_else(|| error::not_found("not matched"))?;
+        if *cx.method() != Method::GET {
+            return Err(error::method_not_allowed("invalid method (expected GET)"));
+        }
+        Ok((Json(value),))
+    }
+}
+
+// ==== Monitor ====
+
+/// A recorder of the requests handled by the wrapped endpoint.
+///
+/// It counts the requests and keeps the recent errors in a ring buffer of
+/// the specified capacity. By default, only the server errors (`5xx`) are
+/// kept in the buffer.
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    inner: Arc<MonitorInner>,
+}
+
+#[derive(Debug)]
+struct MonitorInner {
+    started: Instant,
+    capacity: usize,
+    client_errors: bool,
+    requests: AtomicUsize,
+    in_flight: AtomicUsize,
+    errors: AtomicUsize,
+    recent: Mutex<VecDeque<ErrorRecord>>,
+}
+
+/// An error recorded by `Monitor`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorRecord {
+    /// The time when the error occurred, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+    /// The method of the request.
+    pub method: String,
+    /// The path of the request.
+    pub path: String,
+    /// The status code of the error.
+    pub status: u16,
+    /// The message of the error.
+    pub message: String,
+}
+
+/// The statistics of the requests recorded by `Monitor`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeStats {
+    /// The number of seconds since the `Monitor` has been created.
+    pub uptime_secs: u64,
+    /// The total number of requests.
+    pub requests_total: u64,
+    /// The number of requests in progress.
+    pub requests_in_flight: usize,
+    /// The total number of recorded errors.
+    pub errors_total: u64,
+}
+
+impl Monitor {
+    /// Creates a `Monitor` which keeps the specified number of recent errors.
+    pub fn new(capacity: usize) -> Self {
+        Monitor {
+            inner: Arc::new(MonitorInner {
+                started: Instant::now(),
+                capacity,
+                client_errors: false,
+                requests: AtomicUsize::new(0),
+                in_flight: AtomicUsize::new(0),
+                errors: AtomicUsize::new(0),
+                recent: Mutex::new(VecDeque::with_capacity(capacity)),
+            }),
+        }
+    }
+
+    /// Sets whether to record the client errors (`4xx`) as well.
+    ///
+    /// # Panics
+    /// This method panics if the `Monitor` has already been cloned.
+    pub fn record_client_errors(mut self, enabled: bool) -> Self {
+        Arc::get_mut(&mut self.inner)
+            .expect("the monitor has already been shared")
+            .client_errors = enabled;
+        self
+    }
+
+    /// Creates a `Wrapper` which records the requests to this monitor.
+    ///
+    /// It should be applied to the whole of the application, so that the
+    /// requests which have not been routed are also recorded.
+    pub fn wrapper(&self) -> MonitorWrapper {
+        MonitorWrapper {
+            monitor: self.clone(),
+        }
+    }
+
+    /// Returns the recorded errors, from the oldest.
+    pub fn recent_errors(&self) -> Vec<ErrorRecord> {
+        let recent = self.inner.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent.iter().cloned().collect()
+    }
+
+    /// Returns the current statistics of the requests.
+    pub fn stats(&self) -> RuntimeStats {
+        RuntimeStats {
+            uptime_secs: self.inner.started.elapsed().as_secs(),
+            requests_total: self.inner.requests.load(Ordering::Relaxed) as u64,
+            requests_in_flight: self.inner.in_flight.load(Ordering::Relaxed),
+            errors_total: self.inner.errors.load(Ordering::Relaxed) as u64,
+        }
+    }
+
+    fn record(&self, method: &Method, path: &str, err: &Error) {
+        let status = err.status_code();
+        if !(status.is_server_error() || (self.inner.client_errors && status.is_client_error())) {
+            return;
+        }
+        self.inner.errors.fetch_add(1, Ordering::Relaxed);
+        if self.inner.capacity == 0 {
+            return;
+        }
+        let record = ErrorRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            method: method.to_string(),
+            path: path.to_owned(),
+            status: status.as_u16(),
+            message: err.to_string(),
+        };
+        let mut recent = self.inner.recent.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() == self.inner.capacity {
+            recent.pop_front();
+        }
+        recent.push_back(record);
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct MonitorWrapper {
+    monitor: Monitor,
+}
+
+impl<E> Wrapper<E> for MonitorWrapper
+where
+    E: IsEndpoint,
+{
+    type Endpoint = MonitorEndpoint<E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        MonitorEndpoint {
+            endpoint,
+            monitor: self.monitor,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct MonitorEndpoint<E> {
+    endpoint: E,
+    monitor: Monitor,
+}
+
+impl<E: IsEndpoint> IsEndpoint for MonitorEndpoint<E> {}
+
+impl<E, Bd> Endpoint<Bd> for MonitorEndpoint<E>
+where
+    E: Endpoint<Bd>,
+{
+    type Output = E::Output;
+    type Action = MonitorAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        self.monitor.inner.requests.fetch_add(1, Ordering::Relaxed);
+        self.monitor.inner.in_flight.fetch_add(1, Ordering::Relaxed);
+        MonitorAction {
+            action: self.endpoint.action(),
+            monitor: self.monitor.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct MonitorAction<A> {
+    action: A,
+    monitor: Monitor,
+}
+
+impl<A> Drop for MonitorAction<A> {
+    fn drop(&mut self) {
+        self.monitor.inner.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<A, Bd> EndpointAction<Bd> for MonitorAction<A>
+where
+    A: EndpointAction<Bd>,
+{
+    type Output = A::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        self.action.preflight(cx).map_err(|err| {
+            self.monitor.record(cx.method(), cx.uri().path(), &err);
+            err
+        })
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        self.action.poll_action(cx).map_err(|err| {
+            self.monitor.record(cx.method(), cx.uri().path(), &err);
+            err
+        })
+    }
+}
+
+// ==== BearerToken ====
+
+/// Creates a `Wrapper` which requires the requests to have the specified
+/// token in the `Authorization` header, using the `Bearer` scheme.
+///
+/// The requests without the valid token are rejected with `401 Unauthorized`.
+/// The token is compared in constant time.
+pub fn bearer_token(token: impl Into<String>) -> BearerToken {
+    BearerToken {
+        token: Arc::new(token.into()),
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct BearerToken {
+    token: Arc<String>,
+}
+
+impl<E> Wrapper<E> for BearerToken
+where
+    E: IsEndpoint,
+{
+    type Endpoint = BearerTokenEndpoint<E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        BearerTokenEndpoint {
+            endpoint,
+            token: self.token,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct BearerTokenEndpoint<E> {
+    endpoint: E,
+    token: Arc<String>,
+}
+
+impl<E: IsEndpoint> IsEndpoint for BearerTokenEndpoint<E> {}
+
+impl<E, Bd> Endpoint<Bd> for BearerTokenEndpoint<E>
+where
+    E: Endpoint<Bd>,
+{
+    type Output = E::Output;
+    type Action = BearerTokenAction<E::Action>;
+
+    fn action(&self) -> Self::Action {
+        BearerTokenAction {
+            action: self.endpoint.action(),
+            token: self.token.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct BearerTokenAction<A> {
+    action: A,
+    token: Arc<String>,
+}
+
+impl<A, Bd> EndpointAction<Bd> for BearerTokenAction<A>
+where
+    A: EndpointAction<Bd>,
+{
+    type Output = A::Output;
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        let authorized = cx
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                let mut parts = value.splitn(2, ' ');
+                match (parts.next(), parts.next()) {
+                    (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => {
+                        Some(token.trim())
+                    }
+                    _ => None,
+                }
+            })
+            .map_or(false, |token| {
+                constant_time_eq(token.as_bytes(), self.token.as_bytes())
+            });
+        if !authorized {
+            return Err(error::err_msg(
+                "missing or invalid bearer token",
+                StatusCode::UNAUTHORIZED,
+            ));
+        }
+        self.action.preflight(cx)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        self.action.poll_action(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            endpoint::{syntax, EndpointExt},
+            test,
+        },
+        http::Request,
+        std::time::Duration,
+    };
+
+    #[test]
+    fn test_monitor() {
+        let monitor = Monitor::new(2);
+        let admin = Admin::new().monitor(&monitor);
+        let mut runner = test::runner(
+            syntax::segment("fail")
+                .and_then(|| Err::<&'static str, _>(error::internal_server_error("oops")))
+                .or(suite(&admin, bearer_token("token")))
+                .wrap(monitor.wrapper()),
+        );
+
+        for _ in 0..3 {
+            let _ = runner.perform("/fail");
+        }
+        let _ = runner.perform("/errors");
+
+        let errors = monitor.recent_errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].status, 500);
+        assert_eq!(errors[0].path, "/fail");
+        assert_eq!(errors[0].message, "oops");
+
+        let stats = monitor.stats();
+        assert_eq!(stats.requests_total, 4);
+        assert_eq!(stats.requests_in_flight, 0);
+        assert_eq!(stats.errors_total, 3);
+
+        let response = runner
+            .perform(Request::get("/runtime").header("authorization", "bearer token"))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.to_utf8_lossy().contains(r#""requests_total":5"#));
+    }
+
+    #[test]
+    fn test_flight_recorder() {
+        let recorder = FlightRecorder::new(Duration::from_millis(0));
+        let admin = Admin::new().flight_recorder(&recorder);
+        let mut runner = test::runner(
+            suite(&admin, bearer_token("token")).wrap(recorder.wrapper().route("admin")),
+        );
+
+        // the request serving the resource is itself in flight.
+        let response = runner
+            .perform(Request::get("/stuck").header("authorization", "bearer token"))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.to_utf8_lossy();
+        assert!(body.contains(r#""route":"admin""#), "{}", body);
+        assert!(body.contains(r#""uri":"/stuck""#), "{}", body);
+        assert!(body.contains(r#""phase":"routing""#), "{}", body);
+
+        assert!(recorder.stuck().is_empty());
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
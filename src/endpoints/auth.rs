@@ -0,0 +1,146 @@
+//! Components for authenticating clients.
+
+use {
+    crate::{
+        action::{
+            Oneshot,
+            OneshotAction,
+            PreflightContext, //
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+    },
+    std::sync::Arc,
+};
+
+#[cfg(feature = "oauth2")]
+pub mod oauth2;
+
+// ==== ClientCertChain ====
+
+/// The chain of certificates presented by the client and verified during the
+/// TLS handshake.
+///
+/// Finchers itself does not terminate TLS. The listeners registered by
+/// `Server::bind_tls` store the value of this type into the extensions of each
+/// request when the acceptor reports the certificates of the client, which is
+/// done by wrapping it with `server::ClientAuth`:
+///
+/// ```no_run
+/// # use finchers::server::{self, ClientAuth};
+/// # use tokio::net::TcpStream;
+/// # // stand-ins for the acceptor and the connection of a TLS library
+/// # let tls_acceptor = |stream: TcpStream| Ok::<_, std::io::Error>(stream);
+/// # fn peer_cert_chain(_: &TcpStream) -> Option<Vec<Vec<u8>>> { None }
+/// # use finchers::prelude::*;
+/// # use finchers::endpoints::auth::{self, ClientCertChain};
+/// # let endpoint = auth::client_cert().map(|chain: ClientCertChain| chain.len().to_string());
+/// server::start(endpoint)
+///     .bind_tls("0.0.0.0:443", ClientAuth::new(tls_acceptor, peer_cert_chain))
+///     .serve()
+///     .expect("failed to start the server");
+/// ```
+///
+/// Other servers may store it into the extension map of the request or the
+/// connection by themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCertChain {
+    certs: Arc<Vec<Vec<u8>>>,
+}
+
+impl ClientCertChain {
+    /// Creates a new `ClientCertChain` from the DER-encoded certificates.
+    ///
+    /// The certificates must be ordered from the end-entity (leaf) one to
+    /// the ones closer to the root.
+    pub fn new<I>(certs: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Vec<u8>>,
+    {
+        ClientCertChain {
+            certs: Arc::new(certs.into_iter().map(Into::into).collect()),
+        }
+    }
+
+    /// Returns the DER-encoded end-entity certificate of the client.
+    pub fn leaf(&self) -> Option<&[u8]> {
+        self.certs.first().map(|cert| &cert[..])
+    }
+
+    /// Returns an iterator over the DER-encoded certificates in the chain.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.certs.iter().map(|cert| &cert[..])
+    }
+
+    /// Returns the number of certificates in the chain.
+    pub fn len(&self) -> usize {
+        self.certs.len()
+    }
+
+    /// Returns `true` if the chain contains no certificates.
+    pub fn is_empty(&self) -> bool {
+        self.certs.is_empty()
+    }
+}
+
+// ==== ClientCert ====
+
+/// Create an endpoint which extracts the verified certificate chain of the client.
+///
+/// This endpoint rejects the request with `401 Unauthorized` if the client
+/// did not present any certificate.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoints::auth::{self, ClientCertChain};
+/// let endpoint = auth::client_cert()
+///     .map(|chain: ClientCertChain| format!("{} certificate(s)", chain.len()));
+/// # drop(endpoint);
+/// ```
+#[inline]
+pub fn client_cert() -> ClientCert {
+    ClientCert(())
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct ClientCert(());
+
+mod client_cert {
+    use super::*;
+
+    impl IsEndpoint for ClientCert {}
+
+    impl<Bd> Endpoint<Bd> for ClientCert {
+        type Output = (ClientCertChain,);
+        type Action = Oneshot<ClientCertAction>;
+
+        fn action(&self) -> Self::Action {
+            ClientCertAction(()).into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct ClientCertAction(());
+
+    impl OneshotAction for ClientCertAction {
+        type Output = (ClientCertChain,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            cx.request()
+                .extensions()
+                .get::<ClientCertChain>()
+                .or_else(|| {
+                    cx.connection_extensions()
+                        .and_then(|ext| ext.get::<ClientCertChain>())
+                })
+                .filter(|chain| !chain.is_empty())
+                .cloned()
+                .map(|chain| (chain,))
+                .ok_or_else(|| error::unauthorized("missing client certificate"))
+        }
+    }
+}
@@ -0,0 +1,707 @@
+//! Helpers for the OAuth 2.0 authorization code flow (and OpenID Connect).
+//!
+//! `OAuth2` provides two endpoints corresponding to the legs of the flow:
+//!
+//! * `authorize()` redirects the user agent to the authorization server,
+//!   after generating the `state`, the `nonce` (if OpenID Connect is enabled)
+//!   and the PKCE code verifier.
+//! * `callback()` handles the redirection back from the authorization server,
+//!   validates the `state` and exchanges the authorization code for the tokens
+//!   via the built-in HTTP client (`finchers::client`).
+//!
+//! If OpenID Connect is enabled by `OAuth2::openid_connect`, the ID token is
+//! required in the token response. Its signature is verified with the JWK set
+//! published by the provider, and its claims `iss`, `aud`, `exp` and `nonce`
+//! are validated before the token is returned from `callback()`.
+//!
+//! The values generated in the first leg are kept in a short-lived cookie
+//! (`HttpOnly`, `SameSite=Lax`) until the second leg, so that no server-side
+//! storage is required. The token returned from `callback()` is typically
+//! stored into the session by the application.
+//!
+//! Note that the built-in client currently supports only plain HTTP, so the
+//! token endpoint must be reachable without TLS (e.g. through a local proxy),
+//! or the requests should be sent by the `Client` configured by the application
+//! and stored in the extensions of the request.
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::endpoint::syntax;
+//! # use finchers::endpoints::auth::oauth2::{OAuth2, Token};
+//! let oauth2 = OAuth2::new(
+//!     "my-client-id",
+//!     "https://accounts.example.com/authorize",
+//!     "http://accounts.example.com/token",
+//!     "https://app.example.com/auth/callback",
+//! )
+//! .client_secret("my-client-secret")
+//! .openid_connect(
+//!     "https://accounts.example.com",
+//!     "http://accounts.example.com/jwks",
+//! )
+//! .scopes(&["email"]);
+//!
+//! let login = syntax::segment("login").and(oauth2.authorize());
+//! let callback = syntax::segment("callback")
+//!     .and(oauth2.callback())
+//!     .map(|token: Token| format!("logged in (token type: {})", token.token_type));
+//! # drop((login, callback));
+//! ```
+
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        client::{self, Client},
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+        output::Redirect,
+    },
+    cookie::{Cookie, SameSite},
+    futures::{Async, Future, Poll},
+    http::{
+        header::{self, HeaderValue},
+        Request, StatusCode, Uri,
+    },
+    rand::{thread_rng, Rng},
+    serde::{de::DeserializeOwned, Deserialize},
+    sha2::{Digest, Sha256},
+    std::{
+        sync::{Arc, Mutex},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    url::{form_urlencoded, Url},
+};
+
+mod jws;
+
+use self::jws::{Jws, KeySet};
+
+#[derive(Debug, Clone)]
+struct Config {
+    client_id: String,
+    client_secret: Option<String>,
+    authorize_url: Url,
+    token_url: Uri,
+    redirect_uri: String,
+    scopes: Vec<String>,
+    cookie_name: String,
+    pkce: bool,
+    openid: Option<OpenId>,
+}
+
+/// The configuration of OpenID Connect.
+#[derive(Debug, Clone)]
+struct OpenId {
+    issuer: String,
+    jwks_url: Uri,
+    /// The JWK set fetched most recently, shared among the clones.
+    keys: Arc<Mutex<Option<Arc<KeySet>>>>,
+}
+
+impl Config {
+    fn scope(&self) -> Option<String> {
+        let mut scopes: Vec<&str> = self.scopes.iter().map(String::as_str).collect();
+        if self.openid.is_some() && !scopes.contains(&"openid") {
+            scopes.insert(0, "openid");
+        }
+        if scopes.is_empty() {
+            None
+        } else {
+            Some(scopes.join(" "))
+        }
+    }
+
+    fn check_openid(&self) {
+        if self.openid.is_none() && self.scopes.iter().any(|scope| scope == "openid") {
+            panic!(
+                "the scope `openid' requires `OAuth2::openid_connect' \
+                 to validate the ID token"
+            );
+        }
+    }
+}
+
+/// The configuration of an OAuth 2.0 client, which creates the endpoints
+/// for the authorization code flow.
+#[derive(Debug, Clone)]
+pub struct OAuth2 {
+    config: Arc<Config>,
+}
+
+impl OAuth2 {
+    /// Creates a new `OAuth2` with the specified client ID and endpoints.
+    ///
+    /// # Panics
+    /// This method panics if the specified URLs are invalid.
+    pub fn new(client_id: &str, authorize_url: &str, token_url: &str, redirect_uri: &str) -> Self {
+        OAuth2 {
+            config: Arc::new(Config {
+                client_id: client_id.into(),
+                client_secret: None,
+                authorize_url: Url::parse(authorize_url).expect("invalid authorization URL"),
+                token_url: token_url.parse().expect("invalid token URL"),
+                redirect_uri: redirect_uri.into(),
+                scopes: vec![],
+                cookie_name: "finchers-oauth2".into(),
+                pkce: true,
+                openid: None,
+            }),
+        }
+    }
+
+    fn configure(self, f: impl FnOnce(&mut Config)) -> Self {
+        let mut config = Arc::try_unwrap(self.config).unwrap_or_else(|config| (*config).clone());
+        f(&mut config);
+        OAuth2 {
+            config: Arc::new(config),
+        }
+    }
+
+    /// Sets the client secret, which is sent to the token endpoint.
+    pub fn client_secret(self, secret: &str) -> Self {
+        self.configure(|config| config.client_secret = Some(secret.into()))
+    }
+
+    /// Sets the requested scopes.
+    ///
+    /// The scope `openid` must not be specified unless OpenID Connect is
+    /// enabled by `openid_connect`, since the ID token cannot be validated
+    /// otherwise.
+    pub fn scopes(self, scopes: &[&str]) -> Self {
+        self.configure(|config| config.scopes = scopes.iter().map(|&s| s.into()).collect())
+    }
+
+    /// Sets the name of the cookie which holds the values between the legs.
+    ///
+    /// The default value is `finchers-oauth2`.
+    pub fn cookie_name(self, name: &str) -> Self {
+        self.configure(|config| config.cookie_name = name.into())
+    }
+
+    /// Sets whether to use PKCE (RFC 7636) with the `S256` method.
+    ///
+    /// The default value is `true`.
+    pub fn pkce(self, enabled: bool) -> Self {
+        self.configure(|config| config.pkce = enabled)
+    }
+
+    /// Enables OpenID Connect with the specified issuer identifier and the
+    /// URL of its JWK set.
+    ///
+    /// The scope `openid` is requested along with the `nonce`. The ID tokens
+    /// must be signed with `RS256` or `ES256`, and the JWK set is fetched
+    /// again when the key of an ID token is not found in the cached one.
+    ///
+    /// # Panics
+    /// This method panics if the specified URL is invalid.
+    pub fn openid_connect(self, issuer: &str, jwks_url: &str) -> Self {
+        let jwks_url = jwks_url.parse().expect("invalid JWK set URL");
+        self.configure(|config| {
+            config.openid = Some(OpenId {
+                issuer: issuer.into(),
+                jwks_url,
+                keys: Arc::new(Mutex::new(None)),
+            })
+        })
+    }
+
+    /// Creates an endpoint which starts the flow by redirecting to the
+    /// authorization server.
+    ///
+    /// # Panics
+    /// This method panics if the scope `openid` is requested without
+    /// enabling OpenID Connect.
+    pub fn authorize(&self) -> Authorize {
+        self.config.check_openid();
+        Authorize {
+            config: self.config.clone(),
+        }
+    }
+
+    /// Creates an endpoint which handles the redirection from the
+    /// authorization server and exchanges the code for the tokens.
+    ///
+    /// This endpoint reports `400 Bad Request` if the state is missing or
+    /// mismatched, `401 Unauthorized` if the authorization server returns
+    /// an error or the ID token is invalid, and `502 Bad Gateway` if the
+    /// token exchange fails.
+    ///
+    /// # Panics
+    /// This method panics if the scope `openid` is requested without
+    /// enabling OpenID Connect.
+    pub fn callback(&self) -> Callback {
+        self.config.check_openid();
+        Callback {
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// The values kept between the legs of the flow.
+#[derive(Debug)]
+struct FlowState {
+    state: String,
+    nonce: Option<String>,
+    verifier: Option<String>,
+}
+
+impl FlowState {
+    fn generate(config: &Config) -> Self {
+        FlowState {
+            state: random_token(),
+            nonce: if config.openid.is_some() {
+                Some(random_token())
+            } else {
+                None
+            },
+            verifier: if config.pkce {
+                Some(random_token())
+            } else {
+                None
+            },
+        }
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "{}.{}.{}",
+            self.state,
+            self.nonce.as_ref().map_or("", |s| &**s),
+            self.verifier.as_ref().map_or("", |s| &**s)
+        )
+    }
+
+    fn decode(s: &str) -> Option<Self> {
+        let non_empty = |s: &str| if s.is_empty() { None } else { Some(s.into()) };
+        let mut parts = s.split('.');
+        let state = non_empty(parts.next()?)?;
+        let nonce = non_empty(parts.next()?);
+        let verifier = non_empty(parts.next()?);
+        Some(FlowState {
+            state,
+            nonce,
+            verifier,
+        })
+    }
+}
+
+fn random_token() -> String {
+    let mut buf = [0u8; 32];
+    thread_rng().fill(&mut buf[..]);
+    base64::encode_config(&buf, base64::URL_SAFE_NO_PAD)
+}
+
+/// Compares the secrets in constant time.
+fn secure_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && openssl::memcmp::eq(a.as_bytes(), b.as_bytes())
+}
+
+fn code_challenge(verifier: &str) -> String {
+    base64::encode_config(
+        &Sha256::digest(verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+// ==== Token ====
+
+/// The response from the token endpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Token {
+    /// The access token.
+    pub access_token: String,
+    /// The type of the access token, typically `Bearer`.
+    pub token_type: String,
+    /// The lifetime of the access token in seconds.
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    /// The refresh token.
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// The granted scopes, if different from the requested ones.
+    #[serde(default)]
+    pub scope: Option<String>,
+    /// The ID token issued by the OpenID Connect provider.
+    #[serde(default)]
+    pub id_token: Option<String>,
+    /// The claims in the ID token, set only after its validation.
+    #[serde(skip)]
+    claims: Option<serde_json::Value>,
+}
+
+impl Token {
+    /// Decodes the claims in the ID token into the specified type.
+    ///
+    /// Only the ID token validated by `OAuth2::callback` is decoded. The
+    /// other values, including the tokens deserialized by the application,
+    /// are reported as `401 Unauthorized`.
+    pub fn claims<T>(&self) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let claims = self
+            .claims
+            .clone()
+            .ok_or_else(|| error::unauthorized("missing validated ID token"))?;
+        serde_json::from_value(claims).map_err(error::bad_request)
+    }
+
+    /// Verifies the signature of the ID token and validates its claims.
+    fn validate(&mut self, config: &Config, keys: &KeySet, nonce: &str) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Audience {
+            One(String),
+            Many(Vec<String>),
+        }
+
+        #[derive(Deserialize)]
+        struct Claims {
+            iss: String,
+            aud: Audience,
+            exp: u64,
+            #[serde(default)]
+            nonce: Option<String>,
+        }
+
+        let openid = config
+            .openid
+            .as_ref()
+            .expect("OpenID Connect is not enabled");
+        let invalid = |msg: &str| error::unauthorized(format!("invalid ID token: {}", msg));
+        let claims = {
+            let id_token = self
+                .id_token
+                .as_ref()
+                .ok_or_else(|| error::unauthorized("missing ID token"))?;
+            let jws = Jws::parse(id_token)?;
+            keys.verify(&jws)?;
+            serde_json::from_slice::<serde_json::Value>(jws.payload())
+                .map_err(|_| invalid("malformed"))?
+        };
+        let validated: Claims =
+            serde_json::from_value(claims.clone()).map_err(|_| invalid("missing claims"))?;
+
+        if validated.iss != openid.issuer {
+            return Err(invalid("issuer mismatch"));
+        }
+        let audience_matched = match validated.aud {
+            Audience::One(ref aud) => *aud == config.client_id,
+            Audience::Many(ref aud) => aud.contains(&config.client_id),
+        };
+        if !audience_matched {
+            return Err(invalid("audience mismatch"));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        if validated.exp <= now {
+            return Err(invalid("expired"));
+        }
+        match validated.nonce {
+            Some(ref claimed) if secure_eq(claimed, nonce) => {}
+            _ => return Err(invalid("nonce mismatch")),
+        }
+
+        self.claims = Some(claims);
+        Ok(())
+    }
+}
+
+// ==== Authorize ====
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct Authorize {
+    config: Arc<Config>,
+}
+
+mod authorize {
+    use super::*;
+
+    impl IsEndpoint for Authorize {}
+
+    impl<Bd> Endpoint<Bd> for Authorize {
+        type Output = (Redirect,);
+        type Action = AuthorizeAction;
+
+        fn action(&self) -> Self::Action {
+            AuthorizeAction {
+                config: self.config.clone(),
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct AuthorizeAction {
+        config: Arc<Config>,
+    }
+
+    impl<Bd> EndpointAction<Bd> for AuthorizeAction {
+        type Output = (Redirect,);
+
+        fn preflight(
+            &mut self,
+            _: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            // The cookie jar is only available in `poll_action`.
+            Ok(Preflight::Incomplete)
+        }
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            let config = &*self.config;
+            let flow = FlowState::generate(config);
+
+            let mut url = config.authorize_url.clone();
+            {
+                let mut query = url.query_pairs_mut();
+                query
+                    .append_pair("response_type", "code")
+                    .append_pair("client_id", &config.client_id)
+                    .append_pair("redirect_uri", &config.redirect_uri)
+                    .append_pair("state", &flow.state);
+                if let Some(scope) = config.scope() {
+                    query.append_pair("scope", &scope);
+                }
+                if let Some(ref nonce) = flow.nonce {
+                    query.append_pair("nonce", nonce);
+                }
+                if let Some(ref verifier) = flow.verifier {
+                    query
+                        .append_pair("code_challenge", &code_challenge(verifier))
+                        .append_pair("code_challenge_method", "S256");
+                }
+            }
+            let location =
+                HeaderValue::from_str(url.as_str()).map_err(error::internal_server_error)?;
+
+            let cookie = Cookie::build(config.cookie_name.clone(), flow.encode())
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .secure(config.redirect_uri.starts_with("https:"))
+                .finish();
+            cx.cookies()?.add(cookie);
+
+            Ok(Async::Ready((
+                Redirect::new(StatusCode::FOUND).location_value(location),
+            )))
+        }
+    }
+}
+
+// ==== Callback ====
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct Callback {
+    config: Arc<Config>,
+}
+
+mod callback {
+    use super::*;
+
+    impl IsEndpoint for Callback {}
+
+    impl<Bd> Endpoint<Bd> for Callback {
+        type Output = (Token,);
+        type Action = CallbackAction;
+
+        fn action(&self) -> Self::Action {
+            CallbackAction {
+                config: self.config.clone(),
+                state: State::Init,
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct CallbackAction {
+        config: Arc<Config>,
+        state: State,
+    }
+
+    enum State {
+        Init,
+        Received {
+            code: String,
+            state: String,
+        },
+        Exchanging {
+            future: Box<dyn Future<Item = Token, Error = Error> + Send>,
+            nonce: Option<String>,
+        },
+        FetchingKeys {
+            future: Box<dyn Future<Item = KeySet, Error = Error> + Send>,
+            token: Token,
+            nonce: String,
+        },
+        Done,
+    }
+
+    impl<Bd> EndpointAction<Bd> for CallbackAction {
+        type Output = (Token,);
+
+        fn preflight(
+            &mut self,
+            cx: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            let param = |name: &str| {
+                cx.query_pairs()
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.clone())
+            };
+            if let Some(err) = param("error") {
+                return Err(error::unauthorized(format!(
+                    "the authorization server returned an error: {}",
+                    err
+                )));
+            }
+            let code = param("code").ok_or_else(|| error::bad_request("missing code"))?;
+            let state = param("state").ok_or_else(|| error::bad_request("missing state"))?;
+            self.state = State::Received { code, state };
+            Ok(Preflight::Incomplete)
+        }
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            loop {
+                self.state = match std::mem::replace(&mut self.state, State::Done) {
+                    State::Received { code, state } => {
+                        let config = &*self.config;
+                        let flow = {
+                            let cookies = cx.cookies()?;
+                            let flow = cookies
+                                .get(&config.cookie_name)
+                                .and_then(|cookie| FlowState::decode(cookie.value()));
+                            cookies.remove(
+                                Cookie::build(config.cookie_name.clone(), "")
+                                    .path("/")
+                                    .finish(),
+                            );
+                            flow.ok_or_else(|| error::bad_request("missing OAuth2 state"))?
+                        };
+                        if !secure_eq(&flow.state, &state) {
+                            return Err(error::bad_request("OAuth2 state mismatch"));
+                        }
+                        if config.openid.is_some() && flow.nonce.is_none() {
+                            return Err(error::bad_request("missing OAuth2 nonce"));
+                        }
+                        State::Exchanging {
+                            future: exchange(&client(cx), config, &code, flow.verifier.as_ref()),
+                            nonce: flow.nonce,
+                        }
+                    }
+                    State::Exchanging { mut future, nonce } => {
+                        let mut token = match future.poll()? {
+                            Async::Ready(token) => token,
+                            Async::NotReady => {
+                                self.state = State::Exchanging { future, nonce };
+                                return Ok(Async::NotReady);
+                            }
+                        };
+                        let (openid, nonce) = match (&self.config.openid, nonce) {
+                            (Some(openid), Some(nonce)) => (openid, nonce),
+                            _ => return Ok(Async::Ready((token,))),
+                        };
+                        let keys = openid.keys.lock().unwrap().clone();
+                        match keys {
+                            Some(ref keys) if has_key(&token, keys) => {
+                                token.validate(&self.config, keys, &nonce)?;
+                                return Ok(Async::Ready((token,)));
+                            }
+                            _ => State::FetchingKeys {
+                                future: Box::new(client(cx).get_json(openid.jwks_url.clone())),
+                                token,
+                                nonce,
+                            },
+                        }
+                    }
+                    State::FetchingKeys {
+                        mut future,
+                        mut token,
+                        nonce,
+                    } => {
+                        let keys = match future.poll()? {
+                            Async::Ready(keys) => Arc::new(keys),
+                            Async::NotReady => {
+                                self.state = State::FetchingKeys {
+                                    future,
+                                    token,
+                                    nonce,
+                                };
+                                return Ok(Async::NotReady);
+                            }
+                        };
+                        if let Some(ref openid) = self.config.openid {
+                            *openid.keys.lock().unwrap() = Some(keys.clone());
+                        }
+                        token.validate(&self.config, &keys, &nonce)?;
+                        return Ok(Async::Ready((token,)));
+                    }
+                    State::Init | State::Done => panic!("unexpected condition"),
+                };
+            }
+        }
+    }
+
+    fn client<Bd>(cx: &ActionContext<'_, Bd>) -> Client {
+        cx.extensions()
+            .get::<Client>()
+            .cloned()
+            .unwrap_or_else(client::shared)
+    }
+
+    /// Returns whether the cached JWK set may be used for the ID token.
+    ///
+    /// The malformed tokens are rejected later without fetching the JWK set.
+    fn has_key(token: &Token, keys: &KeySet) -> bool {
+        match token.id_token.as_ref().map(|id_token| Jws::parse(id_token)) {
+            Some(Ok(jws)) => keys.contains(&jws),
+            _ => true,
+        }
+    }
+
+    fn exchange(
+        client: &Client,
+        config: &Config,
+        code: &str,
+        verifier: Option<&String>,
+    ) -> Box<dyn Future<Item = Token, Error = Error> + Send> {
+        let mut body = form_urlencoded::Serializer::new(String::new());
+        body.append_pair("grant_type", "authorization_code")
+            .append_pair("code", code)
+            .append_pair("redirect_uri", &config.redirect_uri)
+            .append_pair("client_id", &config.client_id);
+        if let Some(ref secret) = config.client_secret {
+            body.append_pair("client_secret", secret);
+        }
+        if let Some(verifier) = verifier {
+            body.append_pair("code_verifier", verifier);
+        }
+
+        let request = Request::post(config.token_url.clone())
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::ACCEPT, "application/json")
+            .body(body.finish().into())
+            .expect("should be a valid request");
+
+        Box::new(
+            client
+                .request(request)
+                .and_then(client::receive_bytes)
+                .and_then(|body| {
+                    serde_json::from_slice(&body)
+                        .map_err(|err| error::fail(err, StatusCode::BAD_GATEWAY))
+                }),
+        )
+    }
+}
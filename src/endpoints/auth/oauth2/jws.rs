@@ -0,0 +1,219 @@
+//! Verification of the ID tokens signed by the keys in a JWK set (RFC 7517).
+
+use {
+    crate::error::{self, Error},
+    openssl::{
+        bn::BigNum,
+        ec::{EcGroup, EcKey},
+        ecdsa::EcdsaSig,
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::PKey,
+        rsa::Rsa,
+        sha::sha256,
+        sign::Verifier,
+    },
+    serde::Deserialize,
+};
+
+/// A set of the public keys published by the OpenID provider.
+#[derive(Debug, Deserialize)]
+pub(super) struct KeySet {
+    keys: Vec<Key>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Key {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default, rename = "use")]
+    use_: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+impl KeySet {
+    /// Returns the keys which can be used to verify the signature of
+    /// the specified token.
+    fn candidates<'a>(&'a self, jws: &'a Jws<'_>) -> impl Iterator<Item = &'a Key> + 'a {
+        self.keys.iter().filter(move |key| {
+            key.use_.as_ref().map_or(true, |use_| use_ == "sig")
+                && match (&jws.header.kid, &key.kid) {
+                    (Some(expected), Some(kid)) => expected == kid,
+                    _ => true,
+                }
+        })
+    }
+
+    /// Returns whether this set has the key for the specified token.
+    ///
+    /// If not, the provider may have rotated its keys since the set was fetched.
+    pub(super) fn contains(&self, jws: &Jws<'_>) -> bool {
+        self.candidates(jws).next().is_some()
+    }
+
+    /// Verifies the signature of the specified token.
+    pub(super) fn verify(&self, jws: &Jws<'_>) -> Result<(), Error> {
+        for key in self.candidates(jws) {
+            if verify_with(key, jws)? {
+                return Ok(());
+            }
+        }
+        Err(invalid("signature mismatch"))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+/// A token in the JWS compact serialization.
+#[derive(Debug)]
+pub(super) struct Jws<'a> {
+    header: Header,
+    signing_input: &'a str,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl<'a> Jws<'a> {
+    pub(super) fn parse(token: &'a str) -> Result<Self, Error> {
+        let mut parts = token.rsplitn(2, '.');
+        let signature = parts.next().ok_or_else(|| invalid("malformed"))?;
+        let signing_input = parts.next().ok_or_else(|| invalid("malformed"))?;
+        let mut parts = signing_input.splitn(2, '.');
+        let header = parts.next().ok_or_else(|| invalid("malformed"))?;
+        let payload = parts.next().ok_or_else(|| invalid("malformed"))?;
+        Ok(Jws {
+            header: serde_json::from_slice(&decode(header)?).map_err(|_| invalid("malformed"))?,
+            signing_input,
+            payload: decode(payload)?,
+            signature: decode(signature)?,
+        })
+    }
+
+    pub(super) fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+fn verify_with(key: &Key, jws: &Jws<'_>) -> Result<bool, Error> {
+    match (&*jws.header.alg, &*key.kty) {
+        ("RS256", "RSA") => {
+            let (n, e) = match (&key.n, &key.e) {
+                (Some(n), Some(e)) => (decode(n)?, decode(e)?),
+                _ => return Ok(false),
+            };
+            let rsa = Rsa::from_public_components(
+                BigNum::from_slice(&n).map_err(crypto_error)?,
+                BigNum::from_slice(&e).map_err(crypto_error)?,
+            )
+            .map_err(crypto_error)?;
+            let pkey = PKey::from_rsa(rsa).map_err(crypto_error)?;
+            let mut verifier =
+                Verifier::new(MessageDigest::sha256(), &pkey).map_err(crypto_error)?;
+            verifier
+                .update(jws.signing_input.as_bytes())
+                .map_err(crypto_error)?;
+            Ok(verifier.verify(&jws.signature).unwrap_or(false))
+        }
+        ("ES256", "EC") => {
+            let (x, y) = match (key.crv.as_ref().map(String::as_str), &key.x, &key.y) {
+                (Some("P-256"), Some(x), Some(y)) => (decode(x)?, decode(y)?),
+                _ => return Ok(false),
+            };
+            // The signature is the concatenation of R and S (RFC 7518, section 3.4).
+            if jws.signature.len() != 64 {
+                return Ok(false);
+            }
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).map_err(crypto_error)?;
+            let key = EcKey::from_public_key_affine_coordinates(
+                &group,
+                &*BigNum::from_slice(&x).map_err(crypto_error)?,
+                &*BigNum::from_slice(&y).map_err(crypto_error)?,
+            )
+            .map_err(crypto_error)?;
+            let signature = EcdsaSig::from_private_components(
+                BigNum::from_slice(&jws.signature[..32]).map_err(crypto_error)?,
+                BigNum::from_slice(&jws.signature[32..]).map_err(crypto_error)?,
+            )
+            .map_err(crypto_error)?;
+            Ok(signature
+                .verify(&sha256(jws.signing_input.as_bytes()), &key)
+                .unwrap_or(false))
+        }
+        ("RS256", _) | ("ES256", _) => Ok(false),
+        // `none` and the symmetric algorithms are never accepted.
+        (alg, _) => Err(invalid(format!("unsupported algorithm `{}'", alg))),
+    }
+}
+
+fn decode(s: &str) -> Result<Vec<u8>, Error> {
+    base64::decode_config(s, base64::URL_SAFE_NO_PAD).map_err(|_| invalid("malformed"))
+}
+
+fn invalid(msg: impl std::fmt::Display) -> Error {
+    error::unauthorized(format!("invalid ID token: {}", msg))
+}
+
+fn crypto_error(err: openssl::error::ErrorStack) -> Error {
+    invalid(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::{bn::BigNumContext, sign::Signer};
+
+    fn encode(data: &[u8]) -> String {
+        base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+    }
+
+    #[test]
+    fn test_es256() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let (mut x, mut y) = (BigNum::new().unwrap(), BigNum::new().unwrap());
+        key.public_key()
+            .affine_coordinates_gfp(&group, &mut x, &mut y, &mut BigNumContext::new().unwrap())
+            .unwrap();
+        let keys: KeySet = serde_json::from_str(&format!(
+            r#"{{"keys":[{{"kty":"EC","crv":"P-256","x":"{}","y":"{}"}}]}}"#,
+            encode(&x.to_vec_padded(32).unwrap()),
+            encode(&y.to_vec_padded(32).unwrap()),
+        ))
+        .unwrap();
+
+        let signing_input = format!("{}.{}", encode(br#"{"alg":"ES256"}"#), encode(b"{}"));
+        let signature = {
+            let pkey = PKey::from_ec_key(key).unwrap();
+            let mut signer = Signer::new(MessageDigest::sha256(), &pkey).unwrap();
+            signer.update(signing_input.as_bytes()).unwrap();
+            let der = EcdsaSig::from_der(&signer.sign_to_vec().unwrap()).unwrap();
+            let mut signature = der.r().to_vec_padded(32).unwrap();
+            signature.extend(der.s().to_vec_padded(32).unwrap());
+            signature
+        };
+
+        let token = format!("{}.{}", signing_input, encode(&signature));
+        assert!(keys.verify(&Jws::parse(&token).unwrap()).is_ok());
+
+        let forged = format!("{}.{}", signing_input, encode(&[0; 64]));
+        assert!(keys.verify(&Jws::parse(&forged).unwrap()).is_err());
+
+        let none = format!("{}.{}.", encode(br#"{"alg":"none"}"#), encode(b"{}"));
+        assert!(keys.verify(&Jws::parse(&none).unwrap()).is_err());
+    }
+}
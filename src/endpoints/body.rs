@@ -19,7 +19,7 @@ use {
     std::{cell::UnsafeCell, marker::PhantomData},
 };
 
-fn content_type<T>(request: &Request<T>) -> crate::error::Result<Option<Mime>> {
+pub(crate) fn content_type<T>(request: &Request<T>) -> crate::error::Result<Option<Mime>> {
     if let Some(h) = request.headers().get(http::header::CONTENT_TYPE) {
         let mime = h
             .to_str()
@@ -90,8 +90,13 @@ pub struct ReceiveAll(());
 
 mod receive_all {
     use super::*;
+    use crate::service::PooledBuf;
     use bytes::Buf;
 
+    /// The maximum size of the buffer allocated in advance from the size hint,
+    /// which protects from the requests with a bogus `Content-Length`.
+    const MAX_PREALLOCATION: u64 = 256 * 1024;
+
     impl IsEndpoint for ReceiveAll {}
 
     impl<Bd> Endpoint<Bd> for ReceiveAll
@@ -115,38 +120,73 @@ mod receive_all {
     #[allow(missing_debug_implementations)]
     enum State<Bd> {
         Start,
-        Receiving(Bd, Vec<u8>),
+        Receiving(Bd, Option<PooledBuf>),
     }
 
-    impl<Bd> EndpointAction<Bd> for ReceiveAllAction<Bd>
+    impl<Bd> ReceiveAllAction<Bd>
     where
         Bd: BufStream,
         Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
     {
-        type Output = (Vec<u8>,);
+        /// Receives the whole body into a buffer taken from the buffer pool,
+        /// which is returned to the pool when the buffer is dropped.
+        pub(super) fn poll_buf(
+            &mut self,
+            cx: &mut ActionContext<'_, Bd>,
+        ) -> Poll<PooledBuf, Error> {
+            self.poll_buf_with(cx, |_| ())
+        }
 
-        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        /// Receives the whole body as `poll_buf`, passing each chunk to the
+        /// specified function as it arrives.
+        pub(super) fn poll_buf_with(
+            &mut self,
+            cx: &mut ActionContext<'_, Bd>,
+            mut inspect: impl FnMut(&[u8]),
+        ) -> Poll<PooledBuf, Error> {
             loop {
                 self.state = match self.state {
                     State::Start => {
-                        let payload = cx.take_body()?;
-                        State::Receiving(payload, Vec::new())
+                        let body = cx.take_body()?;
+                        let size_hint =
+                            std::cmp::min(body.size_hint().lower(), MAX_PREALLOCATION) as usize;
+                        let buf = match cx.buffer_pool() {
+                            Some(pool) => pool.get(size_hint),
+                            None => PooledBuf::unpooled(size_hint),
+                        };
+                        State::Receiving(body, Some(buf))
                     }
                     State::Receiving(ref mut body, ref mut buf) => {
+                        let buf_mut = buf.as_mut().expect("the action has already completed");
                         while let Some(data) = futures::try_ready!(body
                             .poll_buf()
                             .map_err(|e| failure::Error::from_boxed_compat(e.into())))
                         {
-                            buf.extend_from_slice(data.bytes());
+                            inspect(data.bytes());
+                            buf_mut.extend_from_slice(data.bytes());
                         }
-                        let buf = std::mem::replace(buf, Vec::new());
-                        return Ok((buf,).into());
+                        return Ok(buf.take().unwrap().into());
                     }
                 };
             }
         }
     }
 
+    impl<Bd> EndpointAction<Bd> for ReceiveAllAction<Bd>
+    where
+        Bd: BufStream,
+        Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        type Output = (Vec<u8>,);
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            // The received data is passed to the caller, so the buffer
+            // cannot be returned to the pool.
+            let buf = futures::try_ready!(self.poll_buf(cx));
+            Ok((buf.into_vec(),).into())
+        }
+    }
+
     pub(super) fn new_action<Bd>() -> ReceiveAllAction<Bd>
     where
         Bd: BufStream,
@@ -160,6 +200,17 @@ mod receive_all {
 // ==== Text ====
 
 /// Create an endpoint which parses a request body into `String`.
+///
+/// The body is decoded according to the `charset` parameter of `Content-Type`,
+/// defaulting to UTF-8. Without the `encoding` feature, only UTF-8 (and its
+/// subset US-ASCII) is supported. With the `encoding` feature, any encoding
+/// defined in the WHATWG Encoding Standard (e.g. `iso-8859-1` or `utf-16`)
+/// is supported, and the byte order mark takes precedence over the specified
+/// charset.
+///
+/// The requests with an unsupported charset are rejected with
+/// `415 Unsupported Media Type`, and the ones whose body cannot be decoded
+/// are rejected with `400 Bad Request`.
 #[inline]
 pub fn text() -> Text {
     Text {
@@ -189,6 +240,7 @@ mod text {
         fn action(&self) -> Self::Action {
             TextAction {
                 receive_all: super::receive_all::new_action(),
+                charset: Charset::Utf8,
             }
         }
     }
@@ -200,6 +252,49 @@ mod text {
         Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
     {
         receive_all: super::receive_all::ReceiveAllAction<Bd>,
+        charset: Charset,
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    enum Charset {
+        Utf8,
+        #[cfg(feature = "encoding")]
+        Encoding(&'static encoding_rs::Encoding),
+    }
+
+    impl Charset {
+        fn from_label(label: &str) -> Option<Self> {
+            if label.eq_ignore_ascii_case("utf-8") || label.eq_ignore_ascii_case("us-ascii") {
+                return Some(Charset::Utf8);
+            }
+            #[cfg(feature = "encoding")]
+            {
+                if let Some(encoding) = encoding_rs::Encoding::for_label(label.as_bytes()) {
+                    return Some(Charset::Encoding(encoding));
+                }
+            }
+            None
+        }
+
+        #[cfg(not(feature = "encoding"))]
+        fn decode(self, data: &[u8]) -> Result<String, Error> {
+            String::from_utf8(data.to_vec()).map_err(error::bad_request)
+        }
+
+        #[cfg(feature = "encoding")]
+        fn decode(self, data: &[u8]) -> Result<String, Error> {
+            let encoding = match self {
+                Charset::Utf8 => encoding_rs::UTF_8,
+                Charset::Encoding(encoding) => encoding,
+            };
+            let (encoding, bom_len) = encoding_rs::Encoding::for_bom(data).unwrap_or((encoding, 0));
+            encoding
+                .decode_without_bom_handling_and_without_replacement(&data[bom_len..])
+                .map(|s| s.into_owned())
+                .ok_or_else(|| {
+                    error::bad_request(format!("the request body is not valid {}", encoding.name()))
+                })
+        }
     }
 
     impl<Bd> EndpointAction<Bd> for TextAction<Bd>
@@ -217,24 +312,23 @@ mod text {
                 .as_ref()
                 .and_then(|m| m.get_param("charset"))
             {
-                if param != "utf-8" {
-                    return Err(error::bad_request("Only the UTF-8 charset is supported."));
-                }
+                self.charset = Charset::from_label(param.as_str()).ok_or_else(|| {
+                    error::unsupported_media_type(format!("unsupported charset: {}", param))
+                })?;
             }
 
             Ok(Preflight::Incomplete)
         }
 
         fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
-            let (data,) = futures::try_ready!(self.receive_all.poll_action(cx));
-            String::from_utf8(data.to_vec())
-                .map(|x| (x,).into())
-                .map_err(error::bad_request)
-                .map_err(Into::into)
+            let data = futures::try_ready!(self.receive_all.poll_buf(cx));
+            self.charset.decode(&data).map(|x| (x,).into())
         }
     }
 }
 
+// ==== Json ====
+
 /// Create an endpoint which parses a request body into a JSON data.
 #[inline]
 pub fn json<T>() -> Json<T>
@@ -303,9 +397,9 @@ mod json {
             cx: &mut PreflightContext<'_>,
         ) -> Result<Preflight<Self::Output>, Error> {
             let mime = content_type(&*cx)? //
-                .ok_or_else(|| error::bad_request("missing content type"))?;
+                .ok_or_else(|| error::unsupported_media_type("missing content type"))?;
             if mime != mime::APPLICATION_JSON {
-                return Err(error::bad_request(
+                return Err(error::unsupported_media_type(
                     "The value of `Content-type` must be `application/json`.",
                 ));
             }
@@ -314,8 +408,8 @@ mod json {
         }
 
         fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
-            let (data,) = futures::try_ready!(self.receive_all.poll_action(cx));
-            serde_json::from_slice(&*data)
+            let data = futures::try_ready!(self.receive_all.poll_buf(cx));
+            serde_json::from_slice(&data)
                 .map(|x| (x,).into())
                 .map_err(error::bad_request)
         }
@@ -392,9 +486,9 @@ mod urlencoded {
             cx: &mut PreflightContext<'_>,
         ) -> Result<Preflight<Self::Output>, Error> {
             let mime = content_type(&*cx)? //
-                .ok_or_else(|| error::bad_request("missing content type"))?;
+                .ok_or_else(|| error::unsupported_media_type("missing content type"))?;
             if mime != mime::APPLICATION_WWW_FORM_URLENCODED {
-                return Err(error::bad_request(
+                return Err(error::unsupported_media_type(
                     "The value of `Content-type` must be `application-x-www-form-urlencoded`.",
                 ));
             }
@@ -403,11 +497,283 @@ mod urlencoded {
         }
 
         fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
-            let (data,) = futures::try_ready!(self.receive_all.poll_action(cx));
-            let s = std::str::from_utf8(&*data).map_err(error::bad_request)?;
+            let data = futures::try_ready!(self.receive_all.poll_buf(cx));
+            let s = std::str::from_utf8(&data).map_err(error::bad_request)?;
             serde_qs::from_str(s)
                 .map(|x| (x,).into())
                 .map_err(|err| error::bad_request(SyncFailure::new(err)))
         }
     }
 }
+
+// ==== Verified ====
+
+/// The hash algorithms used by `verified`.
+#[cfg(feature = "integrity")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// SHA-256, checked against `Digest: sha-256=..`, `Content-Digest: sha-256=:..:`
+    /// and `x-amz-content-sha256`.
+    Sha256,
+
+    /// MD5, checked against `Content-MD5`, `Digest: md5=..` and `Content-Digest: md5=:..:`.
+    Md5,
+}
+
+/// Create an endpoint which receives all of request body while computing
+/// its digest, and verifies it against the request headers.
+///
+/// The digest is computed incrementally as each chunk of the body arrives,
+/// so the body is not scanned again after it has been received. All of the
+/// expected values found in the headers must match the computed digest,
+/// otherwise the request is rejected with `400 Bad Request`. The requests
+/// without any expected value are also rejected unless `allow_missing` is set.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::endpoints::body::{self, Algorithm, VerifiedBody};
+/// # use finchers::test;
+/// # use http::Request;
+/// let mut runner = test::runner(body::verified(Algorithm::Md5));
+///
+/// let body: VerifiedBody = runner
+///     .apply(
+///         Request::put("/")
+///             .header("content-md5", "XUFAKrxLKna5cZ2REBfFkg==")
+///             .body("hello"),
+///     )
+///     .unwrap();
+/// assert_eq!(body.data(), b"hello");
+///
+/// let err = runner
+///     .apply(
+///         Request::put("/")
+///             .header("content-md5", "XUFAKrxLKna5cZ2REBfFkg==")
+///             .body("hello!"),
+///     )
+///     .unwrap_err();
+/// assert_eq!(err.status_code().as_u16(), 400);
+/// ```
+#[cfg(feature = "integrity")]
+#[inline]
+pub fn verified(algorithm: Algorithm) -> Verified {
+    Verified {
+        algorithm,
+        allow_missing: false,
+    }
+}
+
+#[cfg(feature = "integrity")]
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy)]
+pub struct Verified {
+    algorithm: Algorithm,
+    allow_missing: bool,
+}
+
+#[cfg(feature = "integrity")]
+impl Verified {
+    /// Sets whether to accept the requests without any expected digest.
+    ///
+    /// The computed digest is still returned in that case.
+    pub fn allow_missing(self, allow_missing: bool) -> Self {
+        Verified {
+            allow_missing,
+            ..self
+        }
+    }
+}
+
+/// The request body verified by `verified`, and its digest.
+#[cfg(feature = "integrity")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedBody {
+    data: Vec<u8>,
+    digest: Vec<u8>,
+    algorithm: Algorithm,
+}
+
+#[cfg(feature = "integrity")]
+impl VerifiedBody {
+    /// Returns the received data.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the digest of the received data.
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+
+    /// Returns the algorithm used for computing the digest.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Consumes itself and returns the received data and its digest.
+    pub fn into_parts(self) -> (Vec<u8>, Vec<u8>) {
+        (self.data, self.digest)
+    }
+}
+
+#[cfg(feature = "integrity")]
+mod verified {
+    use super::*;
+    use {
+        http::HeaderMap,
+        md5::Md5,
+        sha2::{Digest, Sha256},
+    };
+
+    impl IsEndpoint for Verified {}
+
+    impl<Bd> Endpoint<Bd> for Verified
+    where
+        Bd: BufStream,
+        Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        type Output = (VerifiedBody,);
+        type Action = VerifiedAction<Bd>;
+
+        fn action(&self) -> Self::Action {
+            VerifiedAction {
+                receive_all: super::receive_all::new_action(),
+                algorithm: self.algorithm,
+                allow_missing: self.allow_missing,
+                hasher: match self.algorithm {
+                    Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+                    Algorithm::Md5 => Hasher::Md5(Md5::new()),
+                },
+                expected: vec![],
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct VerifiedAction<Bd>
+    where
+        Bd: BufStream,
+        Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        receive_all: super::receive_all::ReceiveAllAction<Bd>,
+        algorithm: Algorithm,
+        allow_missing: bool,
+        hasher: Hasher,
+        expected: Vec<Vec<u8>>,
+    }
+
+    enum Hasher {
+        Sha256(Sha256),
+        Md5(Md5),
+    }
+
+    impl Hasher {
+        fn input(&mut self, data: &[u8]) {
+            match self {
+                Hasher::Sha256(hasher) => hasher.input(data),
+                Hasher::Md5(hasher) => hasher.input(data),
+            }
+        }
+
+        fn result(&mut self) -> Vec<u8> {
+            match self {
+                Hasher::Sha256(hasher) => hasher.result_reset().to_vec(),
+                Hasher::Md5(hasher) => hasher.result_reset().to_vec(),
+            }
+        }
+    }
+
+    impl<Bd> EndpointAction<Bd> for VerifiedAction<Bd>
+    where
+        Bd: BufStream,
+        Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        type Output = (VerifiedBody,);
+
+        fn preflight(
+            &mut self,
+            cx: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            self.expected = expected_digests(cx.headers(), self.algorithm)?;
+            if self.expected.is_empty() && !self.allow_missing {
+                return Err(error::bad_request("missing the digest of request body"));
+            }
+            Ok(Preflight::Incomplete)
+        }
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            let hasher = &mut self.hasher;
+            let data = futures::try_ready!(self
+                .receive_all
+                .poll_buf_with(cx, |chunk| hasher.input(chunk)));
+            let digest = hasher.result();
+            if self.expected.iter().any(|expected| *expected != digest) {
+                return Err(error::bad_request("the digest of request body mismatched"));
+            }
+            Ok((VerifiedBody {
+                data: data.into_vec(),
+                digest,
+                algorithm: self.algorithm,
+            },)
+                .into())
+        }
+    }
+
+    /// Collects the expected digests of the specified algorithm from the headers.
+    fn expected_digests(headers: &HeaderMap, algorithm: Algorithm) -> Result<Vec<Vec<u8>>, Error> {
+        let name = match algorithm {
+            Algorithm::Sha256 => "sha-256",
+            Algorithm::Md5 => "md5",
+        };
+        let invalid = |header: &str| error::bad_request(format!("invalid header: {}", header));
+
+        let mut expected = vec![];
+        if algorithm == Algorithm::Md5 {
+            for value in headers.get_all("content-md5") {
+                let value = value.to_str().map_err(|_| invalid("content-md5"))?;
+                expected.push(base64::decode(value.trim()).map_err(|_| invalid("content-md5"))?);
+            }
+        }
+
+        // RFC 3230 (`Digest`) and RFC 9530 (`Content-Digest`, in the form of
+        // the structured fields where the byte sequences are wrapped with `:`).
+        for header in &["digest", "content-digest"] {
+            for value in headers.get_all(*header) {
+                let value = value.to_str().map_err(|_| invalid(header))?;
+                for item in value.split(',') {
+                    let mut parts = item.trim().splitn(2, '=');
+                    match (parts.next(), parts.next()) {
+                        (Some(key), Some(digest)) if key.eq_ignore_ascii_case(name) => {
+                            let digest = digest.trim().trim_matches(':');
+                            expected.push(base64::decode(digest).map_err(|_| invalid(header))?);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        if algorithm == Algorithm::Sha256 {
+            if let Some(value) = headers.get("x-amz-content-sha256") {
+                let value = value
+                    .to_str()
+                    .map_err(|_| invalid("x-amz-content-sha256"))?;
+                // the special values such as `UNSIGNED-PAYLOAD` are not digests.
+                if value.len() == 64 {
+                    expected
+                        .push(decode_hex(value).ok_or_else(|| invalid("x-amz-content-sha256"))?);
+                }
+            }
+        }
+
+        Ok(expected)
+    }
+
+    fn decode_hex(s: &str) -> Option<Vec<u8>> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+            .collect()
+    }
+}
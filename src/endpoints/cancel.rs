@@ -0,0 +1,77 @@
+//! Components for observing the cancellation of requests.
+
+use crate::{
+    action::{
+        Oneshot,
+        OneshotAction,
+        PreflightContext, //
+    },
+    endpoint::{Endpoint, IsEndpoint},
+    error::Error,
+    service::Cancelled,
+};
+
+/// Create an endpoint which returns a future resolved when the current request
+/// is cancelled.
+///
+/// The request is cancelled when the client disconnects before the response
+/// is sent, or when its deadline set by the timeout wrappers expires.
+/// The returned future is intended to be selected against the long-running
+/// background tasks, so that they stop the work that no one will receive.
+/// Note that the endpoint itself is no longer polled after the cancellation.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoints;
+/// # use finchers::service::Cancelled;
+/// # use futures::Future;
+/// # fn long_running_task() -> impl Future<Item = (), Error = ()> + Send + 'static {
+/// #     futures::future::ok(())
+/// # }
+/// let endpoint = endpoints::on_disconnect()
+///     .map(|disconnected: Cancelled| {
+///         tokio::spawn(
+///             long_running_task()
+///                 .select2(disconnected)
+///                 .then(|_| Ok(())),
+///         );
+///         "accepted"
+///     });
+/// # drop(endpoint);
+/// ```
+#[inline]
+pub fn on_disconnect() -> OnDisconnect {
+    OnDisconnect(())
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct OnDisconnect(());
+
+mod on_disconnect {
+    use super::*;
+
+    impl IsEndpoint for OnDisconnect {}
+
+    impl<Bd> Endpoint<Bd> for OnDisconnect {
+        type Output = (Cancelled,);
+        type Action = Oneshot<OnDisconnectAction>;
+
+        fn action(&self) -> Self::Action {
+            OnDisconnectAction(()).into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct OnDisconnectAction(());
+
+    impl OneshotAction for OnDisconnectAction {
+        type Output = (Cancelled,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            Ok((cx.cancellation().cancelled(),))
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Components for controlling the handling of `Expect: 100-continue`.
+//!
+//! When a client sends a request with the header `Expect: 100-continue`, it waits
+//! for the interim response `100 Continue` before uploading the message body.
+//! `Server` sends the interim response automatically when the request body is
+//! read for the first time (for example, by the endpoints in `endpoints::body`),
+//! and does not send it if the response is returned before that. Hence, an
+//! endpoint can reject the request before the client uploads the body by
+//! returning an error *before* reading the body.
+//!
+//! The endpoints in this module make such early rejection explicit, and are
+//! intended to be placed in front of the body extractors on each route, so that
+//! the handling of the expectation can be chosen per route. They check the
+//! request after the route has been determined, and thus the errors are
+//! responded as they are, instead of trying the other routes:
+//!
+//! * Routes without any of them send `100 Continue` automatically when the
+//!   body extractor starts reading the body.
+//! * `continue_if` and `max_length` validate the request first, and respond
+//!   the error instead of `100 Continue` if the validation fails.
+//! * `reject` never sends `100 Continue`, and responds `417 Expectation Failed`
+//!   so that the client retries the request without the expectation.
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::endpoints::{body, expect};
+//! # use finchers::endpoint::syntax::path;
+//! let upload = path!(@post "/upload")
+//!     .and(expect::max_length(1024 * 1024))
+//!     .and(body::text())
+//!     .map(|text: String| format!("received {} bytes", text.len()));
+//!
+//! let comment = path!(@post "/comment")
+//!     .and(expect::reject())
+//!     .and(body::text())
+//!     .map(|text: String| format!("received {} bytes", text.len()));
+//! # drop((upload, comment));
+//! ```
+
+use {
+    crate::{
+        action::{ActionContext, EndpointAction},
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+    },
+    futures::Poll,
+    http::{header, Request, StatusCode},
+    std::sync::Arc,
+};
+
+/// Returns whether the request has the header `Expect: 100-continue`.
+pub fn is_expect_continue(request: &Request<()>) -> bool {
+    request
+        .headers()
+        .get(header::EXPECT)
+        .map(|value| value.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+        .unwrap_or(false)
+}
+
+fn check_expectation(request: &Request<()>) -> Result<bool, Error> {
+    match request.headers().get(header::EXPECT) {
+        Some(..) if is_expect_continue(request) => Ok(true),
+        Some(value) => Err(error::err_msg(
+            format!("unsupported expectation: {:?}", value),
+            StatusCode::EXPECTATION_FAILED,
+        )),
+        None => Ok(false),
+    }
+}
+
+// ==== ContinueIf ====
+
+/// Create an endpoint which validates the request by using the specified function
+/// before the client uploads the message body.
+///
+/// If the request has the header `Expect: 100-continue`, the function is called
+/// and the returned error is responded instead of `100 Continue`.
+/// The requests without `Expect` are passed through without calling the function,
+/// and the ones with an unsupported expectation are rejected with
+/// `417 Expectation Failed`.
+pub fn continue_if<F>(f: F) -> ContinueIf<F>
+where
+    F: Fn(&Request<()>) -> Result<(), Error>,
+{
+    ContinueIf { f: Arc::new(f) }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct ContinueIf<F> {
+    f: Arc<F>,
+}
+
+mod continue_if {
+    use super::*;
+
+    impl<F> IsEndpoint for ContinueIf<F> {}
+
+    impl<F, Bd> Endpoint<Bd> for ContinueIf<F>
+    where
+        F: Fn(&Request<()>) -> Result<(), Error>,
+    {
+        type Output = ();
+        type Action = ContinueIfAction<F>;
+
+        fn action(&self) -> Self::Action {
+            ContinueIfAction { f: self.f.clone() }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct ContinueIfAction<F> {
+        f: Arc<F>,
+    }
+
+    impl<F, Bd> EndpointAction<Bd> for ContinueIfAction<F>
+    where
+        F: Fn(&Request<()>) -> Result<(), Error>,
+    {
+        type Output = ();
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            if check_expectation(cx.request())? {
+                (self.f)(cx.request())?;
+            }
+            Ok(().into())
+        }
+    }
+}
+
+// ==== MaxLength ====
+
+/// Create an endpoint which rejects the request with `413 Payload Too Large`
+/// if the value of `Content-Length` exceeds the specified limit.
+///
+/// Unlike `continue_if`, the limit is checked regardless of the presence of
+/// `Expect: 100-continue`, since the request body is not read by this endpoint.
+/// The requests with an unsupported expectation are rejected with
+/// `417 Expectation Failed`.
+pub fn max_length(limit: u64) -> MaxLength {
+    MaxLength { limit }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct MaxLength {
+    limit: u64,
+}
+
+mod max_length {
+    use super::*;
+
+    impl IsEndpoint for MaxLength {}
+
+    impl<Bd> Endpoint<Bd> for MaxLength {
+        type Output = ();
+        type Action = MaxLengthAction;
+
+        fn action(&self) -> Self::Action {
+            MaxLengthAction { limit: self.limit }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct MaxLengthAction {
+        limit: u64,
+    }
+
+    impl<Bd> EndpointAction<Bd> for MaxLengthAction {
+        type Output = ();
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            check_expectation(cx.request())?;
+            let content_length = match cx.headers().get(header::CONTENT_LENGTH) {
+                Some(value) => value
+                    .to_str()
+                    .map_err(error::bad_request)?
+                    .parse::<u64>()
+                    .map_err(error::bad_request)?,
+                None => return Ok(().into()),
+            };
+            if content_length > self.limit {
+                return Err(error::err_msg(
+                    format!(
+                        "the length of payload exceeds the limit ({} > {})",
+                        content_length, self.limit
+                    ),
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                ));
+            }
+            Ok(().into())
+        }
+    }
+}
+
+// ==== Reject ====
+
+/// Create an endpoint which rejects the requests with `Expect: 100-continue`
+/// with `417 Expectation Failed`, without sending `100 Continue`.
+///
+/// This is useful for the routes which do not want to wait for the upload
+/// of the message body, such as the ones receiving small payloads. The client
+/// is expected to retry the request without the expectation.
+pub fn reject() -> Reject {
+    Reject(())
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct Reject(());
+
+mod reject {
+    use super::*;
+
+    impl IsEndpoint for Reject {}
+
+    impl<Bd> Endpoint<Bd> for Reject {
+        type Output = ();
+        type Action = RejectAction;
+
+        fn action(&self) -> Self::Action {
+            RejectAction(())
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct RejectAction(());
+
+    impl<Bd> EndpointAction<Bd> for RejectAction {
+        type Output = ();
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            if check_expectation(cx.request())? {
+                return Err(error::err_msg(
+                    "the expectation is not supported on this route",
+                    StatusCode::EXPECTATION_FAILED,
+                ));
+            }
+            Ok(().into())
+        }
+    }
+}
@@ -0,0 +1,444 @@
+//! Endpoints for parsing the HTML forms with validation.
+//!
+//! The form is deserialized from the `application/x-www-form-urlencoded`
+//! body in the same way as `body::urlencoded()`, and then checked by
+//! the implementation of `Validate`. If the validation fails, the endpoint
+//! reports `422 Unprocessable Entity` with the JSON body enumerating
+//! the errors of each field:
+//!
+//! ```json
+//! {"errors":{"email":["must contain `@`"],"name":["must not be empty"]}}
+//! ```
+//!
+//! # CSRF protection
+//!
+//! The forms are protected from the cross-site request forgery by the
+//! double-submit cookie. `csrf_token()` returns the token stored in the
+//! cookie `finchers-csrf` (issuing a new one if missing), which is embedded
+//! into the form as the hidden field `_csrf`. `validated()` rejects the form
+//! with `403 Forbidden` unless the field (or the header `X-CSRF-Token`)
+//! matches the cookie, before deserializing it. The field `_csrf` is
+//! removed from the form passed to the deserializer.
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::endpoint::syntax;
+//! # use finchers::endpoints::form::{self, CsrfToken, Validate, ValidationErrors};
+//! # use finchers::test;
+//! # use http::Request;
+//! # use serde::Deserialize;
+//! #[derive(Debug, Deserialize)]
+//! struct SignUp {
+//!     name: String,
+//!     email: String,
+//! }
+//!
+//! impl Validate for SignUp {
+//!     fn validate(&self) -> Result<(), ValidationErrors> {
+//!         let mut errors = ValidationErrors::new();
+//!         if self.name.is_empty() {
+//!             errors.add("name", "must not be empty");
+//!         }
+//!         if !self.email.contains('@') {
+//!             errors.add("email", "must contain `@`");
+//!         }
+//!         errors.into_result()
+//!     }
+//! }
+//!
+//! let new_form = syntax::verb::get()
+//!     .and(form::csrf_token())
+//!     .map(|token: CsrfToken| {
+//!         format!(r#"<input type="hidden" name="_csrf" value="{}">"#, token)
+//!     });
+//! let sign_up = syntax::verb::post()
+//!     .and(form::validated())
+//!     .map(|form: SignUp| format!("Welcome, {}!", form.name));
+//!
+//! let mut runner = test::runner(new_form.or(sign_up));
+//! let mut session = runner.session();
+//! let response = session.perform("/").unwrap();
+//! let token = response.to_utf8().unwrap().split('"').nth(5).unwrap().to_owned();
+//!
+//! let submit = |body: String| {
+//!     Request::post("/")
+//!         .header("content-type", "application/x-www-form-urlencoded")
+//!         .body(body)
+//! };
+//! let response = session
+//!     .perform(submit(format!("name=&email=alice&_csrf={}", token)))
+//!     .unwrap();
+//! assert_eq!(response.status().as_u16(), 422);
+//!
+//! // the forms without the token are rejected
+//! let response = session
+//!     .perform(submit("name=alice&email=alice%40example.com".into()))
+//!     .unwrap();
+//! assert_eq!(response.status().as_u16(), 403);
+//! ```
+
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        endpoints::body::{content_type, receive_all, ReceiveAll},
+        error::{self, Error, HttpError},
+        util::constant_time_eq,
+    },
+    cookie::{Cookie, SameSite},
+    failure::{Fail, SyncFailure},
+    futures::{Async, Poll},
+    http::{
+        header::{self, HeaderValue},
+        Request, Response, StatusCode,
+    },
+    izanami_util::buf_stream::BufStream,
+    rand::{thread_rng, Rng},
+    serde::{de::DeserializeOwned, Serialize},
+    std::{collections::BTreeMap, fmt, marker::PhantomData},
+    url::form_urlencoded,
+};
+
+const CSRF_COOKIE: &str = "finchers-csrf";
+const CSRF_FIELD: &str = "_csrf";
+const CSRF_HEADER: &str = "x-csrf-token";
+
+// ==== Validate ====
+
+/// A trait representing the types whose values can be validated after
+/// deserialization.
+pub trait Validate {
+    /// Checks whether the value is valid, and returns the errors of each
+    /// field if not.
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+/// A collection of validation errors keyed by the field names.
+///
+/// This type is also used as an error value of `Validated`, whose response
+/// is `422 Unprocessable Entity` with the errors serialized as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ValidationErrors {
+    errors: BTreeMap<String, Vec<String>>,
+}
+
+impl ValidationErrors {
+    /// Creates an empty `ValidationErrors`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an error message to the specified field.
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors
+            .entry(field.into())
+            .or_default()
+            .push(message.into());
+    }
+
+    /// Returns `true` if no errors have been added.
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Returns the error messages of the specified field.
+    pub fn get(&self, field: &str) -> &[String] {
+        self.errors.get(field).map_or(&[], |messages| &messages[..])
+    }
+
+    /// Returns an iterator over the fields and their error messages.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.errors
+            .iter()
+            .map(|(field, messages)| (&**field, &messages[..]))
+    }
+
+    /// Converts itself into a `Result`, which is `Ok` if no errors have been added.
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The message is used as the response body.
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        f.write_str(&json)
+    }
+}
+
+impl Fail for ValidationErrors {}
+
+impl HttpError for ValidationErrors {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNPROCESSABLE_ENTITY
+    }
+
+    fn to_response(&self, _: &Request<()>) -> Response<()> {
+        let mut response = Response::new(());
+        *response.status_mut() = self.status_code();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        response
+    }
+}
+
+// ==== CsrfToken ====
+
+/// A token for protecting the forms from the cross-site request forgery.
+///
+/// The value is a hex string safe to embed into HTML as it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    fn generate() -> Self {
+        let mut buf = [0u8; 32];
+        thread_rng().fill(&mut buf[..]);
+        CsrfToken(buf.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    fn is_valid(token: &str) -> bool {
+        token.len() == 64 && token.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+
+    /// Returns the token as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for CsrfToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Create an endpoint which returns the CSRF token to be embedded into the
+/// forms as the field `_csrf`.
+///
+/// The token is taken from the cookie `finchers-csrf`. If the cookie is
+/// missing or malformed, a new token is generated and the cookie is set
+/// (`HttpOnly`, `SameSite=Lax`).
+#[inline]
+pub fn csrf_token() -> CsrfTokenEndpoint {
+    CsrfTokenEndpoint(())
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct CsrfTokenEndpoint(());
+
+mod csrf_token {
+    use super::*;
+
+    impl IsEndpoint for CsrfTokenEndpoint {}
+
+    impl<Bd> Endpoint<Bd> for CsrfTokenEndpoint {
+        type Output = (CsrfToken,);
+        type Action = CsrfTokenAction;
+
+        fn action(&self) -> Self::Action {
+            CsrfTokenAction(())
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct CsrfTokenAction(());
+
+    impl<Bd> EndpointAction<Bd> for CsrfTokenAction {
+        type Output = (CsrfToken,);
+
+        fn preflight(
+            &mut self,
+            _: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            // The cookie jar is only available in `poll_action`.
+            Ok(Preflight::Incomplete)
+        }
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            let cookies = cx.cookies()?;
+            if let Some(cookie) = cookies.get(CSRF_COOKIE) {
+                if CsrfToken::is_valid(cookie.value()) {
+                    return Ok(Async::Ready((CsrfToken(cookie.value().to_owned()),)));
+                }
+            }
+            let token = CsrfToken::generate();
+            cookies.add(
+                Cookie::build(CSRF_COOKIE, token.0.clone())
+                    .path("/")
+                    .http_only(true)
+                    .same_site(SameSite::Lax)
+                    .finish(),
+            );
+            Ok(Async::Ready((token,)))
+        }
+    }
+}
+
+// ==== Validated ====
+
+/// Create an endpoint which parses an urlencoded form and validates it.
+///
+/// The form must contain the CSRF token issued by `csrf_token()` as the
+/// field `_csrf` (or in the header `X-CSRF-Token`), otherwise the endpoint
+/// reports `403 Forbidden`. The check can be disabled by `without_csrf`.
+///
+/// The errors during parsing the form are reported as in `body::urlencoded()`,
+/// and the validation errors are reported as `ValidationErrors`.
+#[inline]
+pub fn validated<T>() -> Validated<T>
+where
+    T: DeserializeOwned + Validate,
+{
+    Validated {
+        csrf: true,
+        _marker: PhantomData,
+    }
+}
+
+#[allow(missing_docs)]
+pub struct Validated<T> {
+    csrf: bool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Validated<T> {
+    /// Disables the check of the CSRF token.
+    ///
+    /// This is intended for the forms which are protected by other means,
+    /// e.g. the authentication headers which the browsers never send
+    /// automatically.
+    pub fn without_csrf(self) -> Self {
+        Validated {
+            csrf: false,
+            ..self
+        }
+    }
+}
+
+mod validated {
+    use super::*;
+
+    impl<T> fmt::Debug for Validated<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Validated")
+                .field("csrf", &self.csrf)
+                .finish()
+        }
+    }
+
+    impl<T: DeserializeOwned + Validate> IsEndpoint for Validated<T> {}
+
+    impl<T, Bd> Endpoint<Bd> for Validated<T>
+    where
+        Bd: BufStream,
+        Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+        T: DeserializeOwned + Validate,
+    {
+        type Output = (T,);
+        type Action = ValidatedAction<<ReceiveAll as Endpoint<Bd>>::Action, T>;
+
+        fn action(&self) -> Self::Action {
+            ValidatedAction {
+                receive_all: receive_all().action(),
+                csrf: self.csrf,
+                _marker: PhantomData,
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct ValidatedAction<A, T> {
+        receive_all: A,
+        csrf: bool,
+        _marker: PhantomData<fn() -> T>,
+    }
+
+    impl<A, T, Bd> EndpointAction<Bd> for ValidatedAction<A, T>
+    where
+        A: EndpointAction<Bd, Output = (Vec<u8>,)>,
+        T: DeserializeOwned + Validate,
+    {
+        type Output = (T,);
+
+        fn preflight(
+            &mut self,
+            cx: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            let mime = content_type(&*cx)? //
+                .ok_or_else(|| error::unsupported_media_type("missing content type"))?;
+            if mime != mime::APPLICATION_WWW_FORM_URLENCODED {
+                return Err(error::unsupported_media_type(
+                    "The value of `Content-type` must be `application-x-www-form-urlencoded`.",
+                ));
+            }
+            Ok(Preflight::Incomplete)
+        }
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            let (data,) = futures::try_ready!(self.receive_all.poll_action(cx));
+            let s = std::str::from_utf8(&data).map_err(error::bad_request)?;
+
+            let form = if self.csrf {
+                check_csrf_token(cx, s)?;
+                s.split('&')
+                    .filter(|pair| !is_csrf_field(pair))
+                    .collect::<Vec<_>>()
+                    .join("&")
+            } else {
+                s.to_owned()
+            };
+
+            let form: T = serde_qs::from_str(&form)
+                .map_err(|err| error::bad_request(SyncFailure::new(err)))?;
+            form.validate()?;
+            Ok((form,).into())
+        }
+    }
+
+    fn is_csrf_field(pair: &str) -> bool {
+        form_urlencoded::parse(pair.as_bytes())
+            .next()
+            .map_or(false, |(name, _)| name == CSRF_FIELD)
+    }
+
+    fn check_csrf_token<Bd>(cx: &mut ActionContext<'_, Bd>, form: &str) -> Result<(), Error> {
+        let submitted = match cx
+            .headers()
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(token) => Some(token.to_owned()),
+            None => form_urlencoded::parse(form.as_bytes())
+                .find(|(name, _)| name == CSRF_FIELD)
+                .map(|(_, value)| value.into_owned()),
+        };
+        let submitted = submitted.ok_or_else(|| error::forbidden("missing CSRF token"))?;
+        let expected = cx
+            .cookies()?
+            .get(CSRF_COOKIE)
+            .map(|cookie| cookie.value().to_owned())
+            .ok_or_else(|| error::forbidden("missing CSRF cookie"))?;
+        if !CsrfToken::is_valid(&expected)
+            || !constant_time_eq(submitted.as_bytes(), expected.as_bytes())
+        {
+            return Err(error::forbidden("CSRF token mismatch"));
+        }
+        Ok(())
+    }
+}
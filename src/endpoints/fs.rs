@@ -8,8 +8,11 @@ use {
             Preflight,
             PreflightContext,
         },
-        endpoint::{Endpoint, IsEndpoint},
-        error::{self, Error},
+        endpoint::{
+            syntax::encoded::{FromEncodedStr, SafePathBuf},
+            Endpoint, IsEndpoint,
+        },
+        error::Error,
         output::fs::{NamedFile, OpenNamedFile},
     },
     futures::Poll,
@@ -70,6 +73,9 @@ mod file {
 }
 
 /// Create an endpoint which serves files in the specified directory.
+///
+/// The remaining path is decoded as `SafePathBuf`, and the requests which
+/// attempt to escape from the root directory are rejected.
 #[inline]
 pub fn dir(root: impl Into<PathBuf>) -> Dir {
     Dir { root: root.into() }
@@ -117,13 +123,9 @@ mod dir {
             &mut self,
             cx: &mut PreflightContext<'_>,
         ) -> Result<Preflight<Self::Output>, Error> {
-            let path = cx
-                .cursor()
-                .remaining_path()
-                .percent_decode()
-                .map(|path| PathBuf::from(path.into_owned()));
+            let path = SafePathBuf::from_encoded_str(cx.cursor().remaining_path());
             let _ = cx.cursor().count();
-            let path = path.map_err(error::bad_request)?;
+            let path = path?;
 
             let mut path = self.root.join(path);
             if path.is_dir() {
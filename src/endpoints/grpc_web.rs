@@ -0,0 +1,484 @@
+//! A gateway for the [gRPC-Web] unary calls.
+//!
+//! This module translates the gRPC-Web requests sent from the browser clients
+//! into the calls of the user-supplied handlers, so that they can talk to
+//! a finchers service without a proxy such as Envoy. The messages are
+//! encoded with [`prost`]. Both the binary (`application/grpc-web(+proto)`)
+//! and the base64 (`application/grpc-web-text(+proto)`) variants are supported,
+//! but streaming calls are not.
+//!
+//! [gRPC-Web]: https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-WEB.md
+//! [`prost`]: https://docs.rs/prost
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::endpoints::grpc_web::{self, Status};
+//! #[derive(Clone, PartialEq, prost::Message)]
+//! struct HelloRequest {
+//!     #[prost(string, tag = "1")]
+//!     name: String,
+//! }
+//!
+//! #[derive(Clone, PartialEq, prost::Message)]
+//! struct HelloReply {
+//!     #[prost(string, tag = "1")]
+//!     message: String,
+//! }
+//!
+//! let say_hello = grpc_web::unary("/helloworld.Greeter/SayHello", |req: HelloRequest| {
+//!     Ok::<_, Status>(HelloReply {
+//!         message: format!("Hello, {}", req.name),
+//!     })
+//! });
+//! # drop(say_hello);
+//! ```
+
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+        output::IntoResponse,
+    },
+    bytes::Buf,
+    futures::{Async, Future, IntoFuture, Poll},
+    http::{
+        header::{self, HeaderValue},
+        Method, Request, Response,
+    },
+    izanami_util::buf_stream::BufStream,
+    prost::Message,
+    std::{fmt, marker::PhantomData, sync::Arc},
+};
+
+const FLAG_COMPRESSED: u8 = 0x01;
+const FLAG_TRAILERS: u8 = 0x80;
+
+/// The default limit of the request body size, same as the default maximum
+/// size of the received messages in gRPC.
+const DEFAULT_MAX_REQUEST_SIZE: usize = 4 * 1024 * 1024;
+
+mod encode_set {
+    use percent_encoding::SIMPLE_ENCODE_SET;
+
+    percent_encoding::define_encode_set! {
+        /// The encode set for `grpc-message`, which percent-encodes the bytes
+        /// other than the printable ASCII characters and `%` itself.
+        pub GRPC_MESSAGE_ENCODE_SET = [SIMPLE_ENCODE_SET] | {'%'}
+    }
+}
+
+// ==== Status ====
+
+/// The status codes of gRPC.
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Code {
+    Ok = 0,
+    Cancelled = 1,
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Aborted = 10,
+    OutOfRange = 11,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    DataLoss = 15,
+    Unauthenticated = 16,
+}
+
+/// An error status returned from the handlers of gRPC calls.
+#[derive(Debug, Clone)]
+pub struct Status {
+    code: Code,
+    message: String,
+}
+
+impl Status {
+    /// Creates a new `Status` from the specified code and message.
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Status {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Returns the status code.
+    pub fn code(&self) -> Code {
+        self.code
+    }
+
+    /// Returns the message of this status.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+// ==== Frames ====
+
+/// Decodes the frames in the request body, and returns the payload of the
+/// first data frame.
+fn decode_request(body: &[u8], text: bool) -> Result<Vec<u8>, Status> {
+    let decoded;
+    let mut body = if text {
+        decoded = base64::decode(body)
+            .map_err(|_| Status::new(Code::InvalidArgument, "invalid base64 payload"))?;
+        &decoded[..]
+    } else {
+        body
+    };
+
+    loop {
+        if body.len() < 5 {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                "missing request message",
+            ));
+        }
+        let flag = body[0];
+        let len = (u32::from(body[1]) << 24
+            | u32::from(body[2]) << 16
+            | u32::from(body[3]) << 8
+            | u32::from(body[4])) as usize;
+        if body.len() - 5 < len {
+            return Err(Status::new(Code::InvalidArgument, "incomplete frame"));
+        }
+        let (payload, rest) = body[5..].split_at(len);
+        if flag & FLAG_TRAILERS != 0 {
+            body = rest;
+            continue;
+        }
+        if flag & FLAG_COMPRESSED != 0 {
+            return Err(Status::new(
+                Code::Unimplemented,
+                "compressed messages are not supported",
+            ));
+        }
+        return Ok(payload.to_owned());
+    }
+}
+
+fn encode_frame(buf: &mut Vec<u8>, flag: u8, payload: &[u8]) {
+    let len = payload.len() as u32;
+    buf.push(flag);
+    buf.extend_from_slice(&[
+        (len >> 24) as u8,
+        (len >> 16) as u8,
+        (len >> 8) as u8,
+        len as u8,
+    ]);
+    buf.extend_from_slice(payload);
+}
+
+// ==== GrpcWebResponse ====
+
+/// The response of a gRPC-Web unary call.
+///
+/// The body consists of the response message (if succeeded) followed by
+/// the trailer frame containing `grpc-status` and `grpc-message`.
+#[derive(Debug)]
+pub struct GrpcWebResponse {
+    message: Result<Vec<u8>, Status>,
+    text: bool,
+}
+
+impl GrpcWebResponse {
+    /// Returns the status of this call, or `None` if succeeded.
+    pub fn status(&self) -> Option<&Status> {
+        self.message.as_ref().err()
+    }
+}
+
+impl IntoResponse for GrpcWebResponse {
+    type Body = Vec<u8>;
+
+    fn into_response(self, _: &Request<()>) -> Response<Self::Body> {
+        let mut body = vec![];
+        let (code, message) = match self.message {
+            Ok(ref message) => {
+                encode_frame(&mut body, 0, message);
+                (Code::Ok, String::new())
+            }
+            Err(status) => (status.code, status.message),
+        };
+        let mut trailers = format!("grpc-status:{}\r\n", code as u32);
+        if !message.is_empty() {
+            let encoded: String = percent_encoding::utf8_percent_encode(
+                &message,
+                encode_set::GRPC_MESSAGE_ENCODE_SET,
+            )
+            .collect();
+            trailers += &format!("grpc-message:{}\r\n", encoded);
+        }
+        encode_frame(&mut body, FLAG_TRAILERS, trailers.as_bytes());
+
+        let (body, content_type) = if self.text {
+            (
+                base64::encode(&body).into_bytes(),
+                "application/grpc-web-text+proto",
+            )
+        } else {
+            (body, "application/grpc-web+proto")
+        };
+        let mut response = Response::new(body);
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        response
+    }
+}
+
+// ==== Unary ====
+
+/// Create an endpoint which handles the gRPC-Web unary calls of the
+/// specified method path (e.g. `/helloworld.Greeter/SayHello`) with `f`.
+///
+/// The request must be a `POST` with one of the gRPC-Web content types,
+/// otherwise this endpoint reports `405` or `415`. Since the failures of
+/// the call (including the malformed messages) are reported through
+/// the trailer frame as in gRPC, the response status is always `200 OK`
+/// once the request matches.
+///
+/// The request body larger than 4 MiB is rejected with `RESOURCE_EXHAUSTED`
+/// without being received entirely. The limit can be changed by
+/// `Unary::max_request_size`.
+pub fn unary<F, Req, R>(path: &str, f: F) -> Unary<F, Req>
+where
+    F: Fn(Req) -> R,
+    Req: Message + Default,
+    R: IntoFuture<Error = Status>,
+    R::Item: Message,
+{
+    Unary {
+        path: path.trim_start_matches('/').into(),
+        f: Arc::new(f),
+        max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+        _marker: PhantomData,
+    }
+}
+
+#[allow(missing_docs)]
+pub struct Unary<F, Req> {
+    path: Arc<str>,
+    f: Arc<F>,
+    max_request_size: usize,
+    _marker: PhantomData<fn(Req)>,
+}
+
+impl<F, Req> Unary<F, Req> {
+    /// Sets the maximum size of the request body in bytes, including the
+    /// frame headers and the base64 encoding.
+    pub fn max_request_size(self, max_request_size: usize) -> Self {
+        Unary {
+            max_request_size,
+            ..self
+        }
+    }
+}
+
+impl<F, Req> Clone for Unary<F, Req> {
+    fn clone(&self) -> Self {
+        Unary {
+            path: self.path.clone(),
+            f: self.f.clone(),
+            max_request_size: self.max_request_size,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<F, Req> fmt::Debug for Unary<F, Req> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Unary")
+            .field("path", &self.path)
+            .field("max_request_size", &self.max_request_size)
+            .finish()
+    }
+}
+
+mod unary {
+    use super::*;
+
+    impl<F, Req> IsEndpoint for Unary<F, Req> {}
+
+    impl<F, Req, R, Bd> Endpoint<Bd> for Unary<F, Req>
+    where
+        F: Fn(Req) -> R,
+        Req: Message + Default,
+        R: IntoFuture<Error = Status>,
+        R::Item: Message,
+        Bd: BufStream,
+        Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        type Output = (GrpcWebResponse,);
+        type Action = UnaryAction<F, Req, R::Future, Bd>;
+
+        fn action(&self) -> Self::Action {
+            UnaryAction {
+                path: self.path.clone(),
+                f: self.f.clone(),
+                max_request_size: self.max_request_size,
+                text: false,
+                state: State::Start,
+                _marker: PhantomData,
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct UnaryAction<F, Req, Fut, Bd> {
+        path: Arc<str>,
+        f: Arc<F>,
+        max_request_size: usize,
+        text: bool,
+        state: State<Fut, Bd>,
+        _marker: PhantomData<fn(Req)>,
+    }
+
+    enum State<Fut, Bd> {
+        Start,
+        Receiving(Bd, Vec<u8>),
+        Calling(Fut),
+        Done,
+    }
+
+    impl<F, Req, R, Bd> EndpointAction<Bd> for UnaryAction<F, Req, R::Future, Bd>
+    where
+        F: Fn(Req) -> R,
+        Req: Message + Default,
+        R: IntoFuture<Error = Status>,
+        R::Item: Message,
+        Bd: BufStream,
+        Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        type Output = (GrpcWebResponse,);
+
+        fn preflight(
+            &mut self,
+            cx: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            if *cx.cursor().remaining_path() != *self.path {
+                return Err(error::not_found("not matched"));
+            }
+            while cx.cursor().next().is_some() {}
+
+            if *cx.method() != Method::POST {
+                return Err(error::method_not_allowed("invalid method (expected POST)"));
+            }
+
+            let content_type = cx
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.split(';').next().unwrap_or("").trim())
+                .unwrap_or("");
+            self.text = match content_type {
+                "application/grpc-web" | "application/grpc-web+proto" => false,
+                "application/grpc-web-text" | "application/grpc-web-text+proto" => true,
+                _ => {
+                    return Err(error::unsupported_media_type(
+                        "the content type must be `application/grpc-web(-text)(+proto)`",
+                    ));
+                }
+            };
+
+            Ok(Preflight::Incomplete)
+        }
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            loop {
+                let message = match self.state {
+                    State::Start => {
+                        self.state = State::Receiving(cx.take_body()?, Vec::new());
+                        continue;
+                    }
+                    State::Receiving(ref mut body, ref mut buf) => {
+                        let request =
+                            match futures::try_ready!(receive(body, buf, self.max_request_size)) {
+                                true => decode_request(buf, self.text).and_then(|payload| {
+                                    Req::decode(&payload[..]).map_err(|err| {
+                                        Status::new(Code::InvalidArgument, err.to_string())
+                                    })
+                                }),
+                                false => Err(Status::new(
+                                    Code::ResourceExhausted,
+                                    format!(
+                                        "the request body is larger than {} bytes",
+                                        self.max_request_size
+                                    ),
+                                )),
+                            };
+                        match request {
+                            Ok(request) => {
+                                self.state = State::Calling((*self.f)(request).into_future());
+                                continue;
+                            }
+                            Err(status) => Err(status),
+                        }
+                    }
+                    State::Calling(ref mut future) => match future.poll() {
+                        Ok(Async::Ready(reply)) => {
+                            let mut buf = Vec::with_capacity(reply.encoded_len());
+                            reply
+                                .encode(&mut buf)
+                                .expect("the buffer should have the sufficient capacity");
+                            Ok(buf)
+                        }
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(status) => Err(status),
+                    },
+                    State::Done => panic!("the action has already been polled"),
+                };
+                self.state = State::Done;
+                return Ok(Async::Ready((GrpcWebResponse {
+                    message,
+                    text: self.text,
+                },)));
+            }
+        }
+    }
+
+    /// Receives the request body into `buf`, and returns `false` if its size
+    /// exceeds `max_size`.
+    fn receive<Bd>(body: &mut Bd, buf: &mut Vec<u8>, max_size: usize) -> Poll<bool, Error>
+    where
+        Bd: BufStream,
+        Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        while let Some(mut data) = futures::try_ready!(body
+            .poll_buf()
+            .map_err(|e| failure::Error::from_boxed_compat(e.into())))
+        {
+            while data.has_remaining() {
+                let n = {
+                    let chunk = data.bytes();
+                    if buf.len() + chunk.len() > max_size {
+                        return Ok(Async::Ready(false));
+                    }
+                    buf.extend_from_slice(chunk);
+                    chunk.len()
+                };
+                data.advance(n);
+            }
+        }
+        Ok(Async::Ready(true))
+    }
+}
@@ -61,6 +61,92 @@ impl FromHeaderValue for Url {
     }
 }
 
+/// A header value which is guaranteed to be a visible ASCII string.
+///
+/// The extraction of this type only clones the `HeaderValue`, which shares
+/// its buffer with the request headers, and hence it can be used instead of
+/// `String` in order to avoid allocating for each request.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoints::header::{self, HeaderStr};
+/// # use finchers::test;
+/// # use http::Request;
+/// let endpoint = header::parse::<HeaderStr>("x-api-key")
+///     .map(|key: HeaderStr| key.len());
+///
+/// let mut runner = test::runner(endpoint);
+/// let request = Request::get("/").header("x-api-key", "secret").body("").unwrap();
+/// assert_eq!(runner.apply(request).unwrap(), 6);
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct HeaderStr(HeaderValue);
+
+impl HeaderStr {
+    /// Returns the reference to the string slice.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        // The value has been checked at the construction.
+        unsafe { std::str::from_utf8_unchecked(self.0.as_bytes()) }
+    }
+
+    /// Converts this value into the underlying `HeaderValue`.
+    pub fn into_header_value(self) -> HeaderValue {
+        self.0
+    }
+}
+
+impl fmt::Debug for HeaderStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for HeaderStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl std::ops::Deref for HeaderStr {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for HeaderStr {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<str> for HeaderStr {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for HeaderStr {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl FromHeaderValue for HeaderStr {
+    type Error = ToStrError;
+
+    fn from_header_value(value: &HeaderValue) -> Result<Self, Self::Error> {
+        value.to_str()?;
+        Ok(HeaderStr(value.clone()))
+    }
+}
+
 // ==== Parse ====
 
 /// Create an endpoint which parses an entry in the HTTP header.
@@ -149,6 +235,23 @@ mod parse {
     }
 }
 
+// ==== Header ====
+
+/// A header value extracted by the handler attribute macros such as `#[finchers::get]`.
+///
+/// The header name is derived from the name of the argument.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header<T>(pub T);
+
+impl<T> std::ops::Deref for Header<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 // ==== Optional ====
 
 /// Create an endpoint which parses an entry in the HTTP header.
@@ -0,0 +1,300 @@
+//! Endpoints for the health checking of the application.
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::endpoints::health::{self, Registry};
+//! # use finchers::endpoint::syntax::path;
+//! let registry = Registry::new();
+//! registry.register("database", || {
+//!     // ping to the database server.
+//!     Ok::<(), String>(())
+//! });
+//!
+//! let endpoint = path!(@get "/healthz")
+//!     .and(health::liveness())
+//!     .or(path!(@get "/readyz").and(health::readiness(&registry)));
+//! # drop(endpoint);
+//! ```
+
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Oneshot,
+            OneshotAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+        output::{IntoResponse, Json},
+    },
+    futures::{
+        future::{self, JoinAll},
+        Async, Future, IntoFuture, Poll,
+    },
+    http::{Request, Response, StatusCode},
+    serde::Serialize,
+    std::{
+        collections::BTreeMap,
+        fmt,
+        sync::{Arc, RwLock},
+    },
+};
+
+type CheckFuture = Box<dyn Future<Item = (), Error = String> + Send + 'static>;
+type CheckFn = Arc<dyn Fn() -> CheckFuture + Send + Sync + 'static>;
+
+/// A registry of the checks which determine the readiness of the application.
+///
+/// The registry is shared between its clones, and hence the separate modules
+/// can contribute their own checks after the endpoint has been constructed.
+#[derive(Clone, Default)]
+pub struct Registry {
+    checks: Arc<RwLock<Vec<(String, CheckFn)>>>,
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let checks = self.checks.read().unwrap();
+        f.debug_list()
+            .entries(checks.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl Registry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Registers a check with the specified name.
+    ///
+    /// The function is called on every readiness request, and the check
+    /// is considered to be failed if the returned future resolves to an error.
+    /// Since the readiness endpoint waits for all of the checks, the check
+    /// that may not complete (e.g. a network request) should have its own timeout.
+    pub fn register<F, R>(&self, name: impl Into<String>, check: F)
+    where
+        F: Fn() -> R + Send + Sync + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: Send + 'static,
+        R::Error: fmt::Display,
+    {
+        let check: CheckFn = Arc::new(move || {
+            Box::new(check().into_future().map_err(|e| e.to_string())) as CheckFuture
+        });
+        self.checks.write().unwrap().push((name.into(), check));
+    }
+}
+
+/// The status of the application or an individual check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    #[allow(missing_docs)]
+    Up,
+    #[allow(missing_docs)]
+    Down,
+}
+
+/// The result of an individual check.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    /// Returns the status of this check.
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Returns the error message if this check has failed.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_ref().map(String::as_str)
+    }
+}
+
+/// The report of health checking.
+///
+/// This value is converted into a JSON response whose status code is
+/// `200 OK` if all of the checks have passed, and otherwise
+/// `503 Service Unavailable`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    status: Status,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    checks: BTreeMap<String, Check>,
+}
+
+impl Health {
+    fn from_checks(checks: BTreeMap<String, Check>) -> Self {
+        let status = if checks.values().all(|c| c.status == Status::Up) {
+            Status::Up
+        } else {
+            Status::Down
+        };
+        Health { status, checks }
+    }
+
+    /// Returns the overall status.
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Returns the result of the check with the specified name.
+    pub fn check(&self, name: &str) -> Option<&Check> {
+        self.checks.get(name)
+    }
+}
+
+impl IntoResponse for Health {
+    type Body = String;
+
+    fn into_response(self, request: &Request<()>) -> Response<Self::Body> {
+        let status = match self.status {
+            Status::Up => StatusCode::OK,
+            Status::Down => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        let mut response = Json(self).into_response(request);
+        if response.status() == StatusCode::OK {
+            *response.status_mut() = status;
+        }
+        response
+    }
+}
+
+// ==== Liveness ====
+
+/// Creates an endpoint which always reports that the application is alive.
+#[inline]
+pub fn liveness() -> Liveness {
+    Liveness(())
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct Liveness(());
+
+mod liveness {
+    use super::*;
+
+    impl IsEndpoint for Liveness {}
+
+    impl<Bd> Endpoint<Bd> for Liveness {
+        type Output = (Health,);
+        type Action = Oneshot<LivenessAction>;
+
+        fn action(&self) -> Self::Action {
+            LivenessAction(()).into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct LivenessAction(());
+
+    impl OneshotAction for LivenessAction {
+        type Output = (Health,);
+
+        fn preflight(self, _: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            Ok((Health::from_checks(BTreeMap::new()),))
+        }
+    }
+}
+
+// ==== Readiness ====
+
+/// Creates an endpoint which runs all of the checks in the registry concurrently
+/// and reports the aggregated result.
+#[inline]
+pub fn readiness(registry: &Registry) -> Readiness {
+    Readiness {
+        registry: registry.clone(),
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct Readiness {
+    registry: Registry,
+}
+
+mod readiness {
+    use super::*;
+
+    type CheckResult = Box<dyn Future<Item = (String, Check), Error = ()> + Send + 'static>;
+
+    impl IsEndpoint for Readiness {}
+
+    impl<Bd> Endpoint<Bd> for Readiness {
+        type Output = (Health,);
+        type Action = ReadinessAction;
+
+        fn action(&self) -> Self::Action {
+            ReadinessAction {
+                registry: self.registry.clone(),
+                pending: None,
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct ReadinessAction {
+        registry: Registry,
+        pending: Option<JoinAll<Vec<CheckResult>>>,
+    }
+
+    impl<Bd> EndpointAction<Bd> for ReadinessAction {
+        type Output = (Health,);
+
+        fn preflight(
+            &mut self,
+            _: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            let checks = self.registry.checks.read().unwrap();
+            let futures: Vec<CheckResult> = checks
+                .iter()
+                .map(|(name, check)| {
+                    let name = name.clone();
+                    Box::new(check().then(move |result| {
+                        let check = match result {
+                            Ok(()) => Check {
+                                status: Status::Up,
+                                error: None,
+                            },
+                            Err(err) => Check {
+                                status: Status::Down,
+                                error: Some(err),
+                            },
+                        };
+                        Ok((name, check))
+                    })) as CheckResult
+                })
+                .collect();
+            self.pending = Some(future::join_all(futures));
+            Ok(Preflight::Incomplete)
+        }
+
+        fn poll_action(&mut self, _: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            let pending = self
+                .pending
+                .as_mut()
+                .expect("the action has not been preflighted");
+            match pending.poll() {
+                Ok(Async::Ready(results)) => {
+                    let checks = results.into_iter().collect();
+                    Ok(Async::Ready((Health::from_checks(checks),)))
+                }
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+                Err(()) => unreachable!(),
+            }
+        }
+    }
+}
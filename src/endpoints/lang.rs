@@ -0,0 +1,394 @@
+//! Endpoints for negotiating the language of the response with `Accept-Language`.
+
+use {
+    crate::{
+        action::{
+            ActionContext,
+            EndpointAction,
+            Oneshot,
+            OneshotAction,
+            Preflight,
+            PreflightContext, //
+        },
+        endpoint::{wrapper::Wrapper, Endpoint, IsEndpoint},
+        error::Error,
+        service::Context,
+    },
+    futures::{Async, Poll},
+    http::header,
+    std::{cmp::Ordering, fmt, str::FromStr, sync::Arc},
+};
+
+/// A language tag, such as `en`, `en-US` or `zh-Hant`.
+///
+/// The comparison of the tags is case-insensitive.
+#[derive(Debug, Clone, Eq)]
+pub struct LanguageTag(String);
+
+impl LanguageTag {
+    /// Creates a new `LanguageTag` from the specified string.
+    ///
+    /// # Panics
+    /// This function panics if the specified string is not a valid tag.
+    pub fn new(tag: &str) -> Self {
+        tag.parse().expect("invalid language tag")
+    }
+
+    /// Returns the string representation of this tag.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns whether this tag is the wildcard (`*`).
+    pub fn is_wildcard(&self) -> bool {
+        self.0 == "*"
+    }
+
+    /// Returns whether this tag, regarded as a language range, matches
+    /// the specified tag.
+    ///
+    /// A range matches the tags which are equal to it or start with it followed
+    /// by `-`, e.g. `en` matches `en` and `en-US`, but not `eng`.
+    pub fn matches(&self, tag: &LanguageTag) -> bool {
+        if self.is_wildcard() {
+            return true;
+        }
+        let (range, tag) = (self.0.as_bytes(), tag.0.as_bytes());
+        tag.len() >= range.len()
+            && tag[..range.len()].eq_ignore_ascii_case(range)
+            && (tag.len() == range.len() || tag[range.len()] == b'-')
+    }
+}
+
+impl PartialEq for LanguageTag {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl fmt::Display for LanguageTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for LanguageTag {
+    type Err = InvalidLanguageTag;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = s == "*"
+            || (!s.is_empty()
+                && s.split('-').all(|subtag| {
+                    !subtag.is_empty()
+                        && subtag.len() <= 8
+                        && subtag.bytes().all(|b| b.is_ascii_alphanumeric())
+                }));
+        if valid {
+            Ok(LanguageTag(s.to_owned()))
+        } else {
+            Err(InvalidLanguageTag(()))
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, failure::Fail)]
+#[fail(display = "invalid language tag")]
+pub struct InvalidLanguageTag(());
+
+/// Parses the value of `Accept-Language` into the list of the language ranges
+/// and their weights, sorted in the descending order of the weight.
+///
+/// The malformed entries are ignored.
+pub fn parse_accept_language(value: &str) -> Vec<(LanguageTag, f32)> {
+    let mut ranges: Vec<_> = value
+        .split(',')
+        .filter_map(|entry| {
+            let mut params = entry.split(';');
+            let tag = params.next()?.trim().parse::<LanguageTag>().ok()?;
+            let mut q = 1.0;
+            for param in params {
+                let mut kv = param.splitn(2, '=');
+                if kv.next()?.trim().eq_ignore_ascii_case("q") {
+                    q = kv.next()?.trim().parse::<f32>().ok()?;
+                    if q < 0.0 || q > 1.0 {
+                        return None;
+                    }
+                }
+            }
+            Some((tag, q))
+        })
+        .collect();
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+    ranges
+}
+
+fn accepted_ranges(cx: &Context) -> Vec<(LanguageTag, f32)> {
+    let mut ranges: Vec<_> = cx
+        .headers()
+        .get_all(header::ACCEPT_LANGUAGE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(parse_accept_language)
+        .collect();
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+    ranges
+}
+
+/// Selects the best-matching tag in `supported` for the specified ranges.
+///
+/// For each range in the order of the weight, the supported tags matched by
+/// the range are tried first, and then the ones which match a prefix of
+/// the range (e.g. `en` for `en-US`). The ranges with `q=0` are excluded.
+/// If no supported tag is matched, the first one is returned as the default.
+fn select(supported: &[LanguageTag], ranges: &[(LanguageTag, f32)]) -> LanguageTag {
+    let excluded = |tag: &LanguageTag| {
+        ranges
+            .iter()
+            .any(|(range, q)| *q == 0.0 && !range.is_wildcard() && range.matches(tag))
+    };
+    ranges
+        .iter()
+        .filter(|(_, q)| *q > 0.0)
+        .filter_map(|(range, _)| {
+            supported
+                .iter()
+                .filter(|tag| !excluded(tag))
+                .find(|tag| range.matches(tag))
+                .or_else(|| {
+                    supported
+                        .iter()
+                        .filter(|tag| !excluded(tag))
+                        .find(|tag| tag.matches(range))
+                })
+        })
+        .next()
+        .unwrap_or(&supported[0])
+        .clone()
+}
+
+// ==== Accepted ====
+
+/// Create an endpoint which returns the parsed list of `Accept-Language`,
+/// sorted in the descending order of the weight.
+///
+/// This endpoint always matches and returns an empty list if the header is missing.
+pub fn accepted() -> Accepted {
+    Accepted(())
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct Accepted(());
+
+mod accepted {
+    use super::*;
+
+    impl IsEndpoint for Accepted {}
+
+    impl<Bd> Endpoint<Bd> for Accepted {
+        type Output = (Vec<(LanguageTag, f32)>,);
+        type Action = Oneshot<AcceptedAction>;
+
+        fn action(&self) -> Self::Action {
+            AcceptedAction(()).into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct AcceptedAction(());
+
+    impl OneshotAction for AcceptedAction {
+        type Output = (Vec<(LanguageTag, f32)>,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            Ok((accepted_ranges(cx),))
+        }
+    }
+}
+
+// ==== Preferred ====
+
+/// Create an endpoint which returns the best-matching language in `supported`
+/// for `Accept-Language`.
+///
+/// This endpoint always matches, and returns the first element of `supported`
+/// if the header is missing or none of the supported languages are acceptable.
+///
+/// # Panics
+/// This function panics if `supported` is empty.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoints::lang::{self, LanguageTag};
+/// # use finchers::test;
+/// # use http::Request;
+/// let endpoint = lang::preferred(&[LanguageTag::new("en"), LanguageTag::new("ja")])
+///     .map(|lang: LanguageTag| match lang.as_str() {
+///         "ja" => "こんにちは",
+///         _ => "Hello",
+///     });
+///
+/// let mut runner = test::runner(endpoint);
+/// let request = Request::get("/")
+///     .header("accept-language", "fr;q=0.9, ja;q=0.8, en;q=0.1")
+///     .body("")
+///     .unwrap();
+/// assert_eq!(runner.apply(request).unwrap(), "こんにちは");
+/// ```
+pub fn preferred(supported: &[LanguageTag]) -> Preferred {
+    assert!(
+        !supported.is_empty(),
+        "at least one language must be supported"
+    );
+    Preferred {
+        supported: supported.into(),
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct Preferred {
+    supported: Arc<[LanguageTag]>,
+}
+
+mod preferred {
+    use super::*;
+
+    impl IsEndpoint for Preferred {}
+
+    impl<Bd> Endpoint<Bd> for Preferred {
+        type Output = (LanguageTag,);
+        type Action = Oneshot<PreferredAction>;
+
+        fn action(&self) -> Self::Action {
+            PreferredAction {
+                supported: self.supported.clone(),
+            }
+            .into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct PreferredAction {
+        supported: Arc<[LanguageTag]>,
+    }
+
+    impl OneshotAction for PreferredAction {
+        type Output = (LanguageTag,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            Ok((select(&self.supported, &accepted_ranges(cx)),))
+        }
+    }
+}
+
+// ==== Negotiate ====
+
+/// Creates a `Wrapper` which negotiates the language with `Accept-Language`
+/// and stores the result in the extensions of the request as a `LanguageTag`.
+///
+/// The negotiated language is stored before the wrapped endpoint is polled,
+/// so that it can be retrieved from the `ActionContext`, or from the futures
+/// driven by `endpoint::endpoint` via
+/// `Context::with(|cx| cx.extensions().get::<LanguageTag>().cloned())`.
+/// Note that it is *not* visible from the synchronous part of the wrapped
+/// endpoint (i.e. `preflight`), which should use `preferred` instead.
+///
+/// # Panics
+/// This function panics if `supported` is empty.
+pub fn negotiate(supported: &[LanguageTag]) -> Negotiate {
+    Negotiate {
+        preferred: preferred(supported),
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct Negotiate {
+    preferred: Preferred,
+}
+
+impl<E> Wrapper<E> for Negotiate
+where
+    E: IsEndpoint,
+{
+    type Endpoint = NegotiateEndpoint<E>;
+
+    fn wrap(self, endpoint: E) -> Self::Endpoint {
+        NegotiateEndpoint {
+            endpoint,
+            supported: self.preferred.supported,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct NegotiateEndpoint<E> {
+    endpoint: E,
+    supported: Arc<[LanguageTag]>,
+}
+
+mod negotiate {
+    use super::*;
+
+    impl<E: IsEndpoint> IsEndpoint for NegotiateEndpoint<E> {}
+
+    impl<E, Bd> Endpoint<Bd> for NegotiateEndpoint<E>
+    where
+        E: Endpoint<Bd>,
+    {
+        type Output = E::Output;
+        type Action = NegotiateAction<E::Action, E::Output>;
+
+        fn action(&self) -> Self::Action {
+            NegotiateAction {
+                action: self.endpoint.action(),
+                supported: self.supported.clone(),
+                lang: None,
+                output: None,
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct NegotiateAction<A, T> {
+        action: A,
+        supported: Arc<[LanguageTag]>,
+        lang: Option<LanguageTag>,
+        output: Option<T>,
+    }
+
+    impl<A, Bd> EndpointAction<Bd> for NegotiateAction<A, A::Output>
+    where
+        A: EndpointAction<Bd>,
+    {
+        type Output = A::Output;
+
+        fn preflight(
+            &mut self,
+            cx: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            self.lang = Some(select(&self.supported, &accepted_ranges(cx)));
+            // The completed output is held until `poll_action` in order to
+            // store the negotiated language into the context.
+            if let Preflight::Completed(output) = self.action.preflight(cx)? {
+                self.output = Some(output);
+            }
+            Ok(Preflight::Incomplete)
+        }
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            if let Some(lang) = self.lang.take() {
+                cx.extensions_mut().insert(lang);
+            }
+            match self.output.take() {
+                Some(output) => Ok(Async::Ready(output)),
+                None => self.action.poll_action(cx),
+            }
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Components for serving the long-polling requests.
+
+use {
+    crate::{
+        action::{ActionContext, EndpointAction, Preflight, PreflightContext},
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+        output::IntoResponse,
+        service::Cancelled,
+    },
+    futures::{Async, Future, Poll, Stream},
+    http::{Request, Response, StatusCode},
+    izanami_util::buf_stream::Either,
+    std::{fmt, sync::Arc, time::Duration},
+    tokio::timer::Delay,
+};
+
+/// Create an endpoint which waits for the first item from a stream, such as
+/// the `Subscriber` of `broadcast::Hub`.
+///
+/// The closure is called for each request after the routing has been
+/// completed, and the stream returned from it is polled until the first item
+/// arrives. If no item arrives until the timeout expires, the stream ends,
+/// or the server starts shutting down gracefully, the endpoint completes
+/// without an item and the client receives `204 No Content`, so that it can
+/// poll again (possibly to another instance). Completing early on shutdown
+/// lets the server drain the connections without waiting for the timeout.
+///
+/// The shutdown is observed through `Context::shutdown`, and hence it
+/// requires that the `App` is served by the server created with `Server::from`.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::broadcast::Hub;
+/// # use finchers::endpoint::syntax;
+/// # use finchers::endpoints::long_poll;
+/// # use std::time::Duration;
+/// let hub = Hub::<String>::new(16);
+///
+/// // responds with the first message published after the request,
+/// // or `204 No Content` after 30 seconds.
+/// let endpoint = syntax::segment("events").and({
+///     let hub = hub.clone();
+///     long_poll(move || hub.subscribe(), Duration::from_secs(30))
+/// });
+/// # drop(endpoint);
+/// ```
+pub fn long_poll<F, S>(source: F, timeout: Duration) -> LongPoll<F>
+where
+    F: Fn() -> S,
+    S: Stream,
+    S::Error: Into<Error>,
+{
+    LongPoll {
+        source: Arc::new(source),
+        timeout,
+    }
+}
+
+/// The result of waiting for an item in `long_poll`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Polled<T> {
+    /// The item has arrived.
+    Item(T),
+
+    /// The timeout has expired before any item arrived.
+    TimedOut,
+
+    /// The stream has ended before any item arrived.
+    Closed,
+
+    /// The server has started shutting down before any item arrived.
+    ShuttingDown,
+}
+
+impl<T> Polled<T> {
+    /// Returns the arrived item, if any.
+    pub fn into_item(self) -> Option<T> {
+        match self {
+            Polled::Item(item) => Some(item),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the item has arrived.
+    pub fn is_item(&self) -> bool {
+        match self {
+            Polled::Item(..) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Polled<T> {
+    type Body = Either<T::Body, &'static [u8]>;
+
+    fn into_response(self, request: &Request<()>) -> Response<Self::Body> {
+        match self {
+            Polled::Item(item) => item.into_response(request).map(Either::Left),
+            _ => {
+                let mut response = Response::new(Either::Right(&[] as &[u8]));
+                *response.status_mut() = StatusCode::NO_CONTENT;
+                response
+            }
+        }
+    }
+}
+
+#[allow(missing_docs)]
+pub struct LongPoll<F> {
+    source: Arc<F>,
+    timeout: Duration,
+}
+
+impl<F> Clone for LongPoll<F> {
+    fn clone(&self) -> Self {
+        LongPoll {
+            source: self.source.clone(),
+            timeout: self.timeout,
+        }
+    }
+}
+
+impl<F> fmt::Debug for LongPoll<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LongPoll")
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl<F> IsEndpoint for LongPoll<F> {}
+
+impl<F, S, Bd> Endpoint<Bd> for LongPoll<F>
+where
+    F: Fn() -> S,
+    S: Stream,
+    S::Error: Into<Error>,
+{
+    type Output = (Polled<S::Item>,);
+    type Action = LongPollAction<F, S>;
+
+    fn action(&self) -> Self::Action {
+        LongPollAction {
+            source: self.source.clone(),
+            timeout: self.timeout,
+            state: None,
+        }
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct LongPollAction<F, S> {
+    source: Arc<F>,
+    timeout: Duration,
+    state: Option<Waiting<S>>,
+}
+
+struct Waiting<S> {
+    stream: S,
+    timer: Delay,
+    shutdown: Option<Cancelled>,
+}
+
+impl<F, S, Bd> EndpointAction<Bd> for LongPollAction<F, S>
+where
+    F: Fn() -> S,
+    S: Stream,
+    S::Error: Into<Error>,
+{
+    type Output = (Polled<S::Item>,);
+
+    fn preflight(
+        &mut self,
+        _: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        Ok(Preflight::Incomplete)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        let (source, timeout) = (&self.source, self.timeout);
+        let waiting = self.state.get_or_insert_with(|| Waiting {
+            stream: source(),
+            timer: Delay::new(tokio::clock::now() + timeout),
+            shutdown: cx.shutdown().map(|token| token.cancelled()),
+        });
+
+        if let Some(ref mut shutdown) = waiting.shutdown {
+            if let Ok(Async::Ready(())) | Err(()) = shutdown.poll() {
+                return Ok(Async::Ready((Polled::ShuttingDown,)));
+            }
+        }
+
+        match cx.context_mut().set(|| waiting.stream.poll()) {
+            Ok(Async::Ready(Some(item))) => return Ok(Async::Ready((Polled::Item(item),))),
+            Ok(Async::Ready(None)) => return Ok(Async::Ready((Polled::Closed,))),
+            Ok(Async::NotReady) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        match waiting.timer.poll() {
+            Ok(Async::Ready(())) => Ok(Async::Ready((Polled::TimedOut,))),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => Err(crate::error::fail(err, StatusCode::INTERNAL_SERVER_ERROR)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{broadcast::Hub, endpoint::EndpointExt, test},
+        futures::future,
+    };
+
+    #[test]
+    fn test_item() {
+        let hub = Hub::<&'static str>::new(4);
+        let mut runner = test::runner({
+            let hub = hub.clone();
+            long_poll(
+                move || {
+                    let subscriber = hub.subscribe();
+                    hub.send("hello");
+                    subscriber
+                },
+                Duration::from_secs(10),
+            )
+        });
+        runner.perform("/").unwrap().assert_status(200);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let hub = Hub::<&'static str>::new(4);
+        let mut runner = test::runner({
+            let hub = hub.clone();
+            long_poll(move || hub.subscribe(), Duration::from_secs(30))
+                .map(|polled: Polled<&'static str>| polled == Polled::TimedOut)
+        });
+
+        let clock = runner.clock().clone();
+        runner.runtime().spawn(future::lazy(move || {
+            clock.advance(Duration::from_secs(30));
+            Ok(())
+        }));
+        assert!(runner.apply("/").unwrap());
+    }
+
+    #[test]
+    fn test_closed() {
+        let hub = Hub::<&'static str>::new(4);
+        let mut runner = test::runner({
+            let hub = hub.clone();
+            long_poll(
+                move || {
+                    let subscriber = hub.subscribe();
+                    hub.close();
+                    subscriber
+                },
+                Duration::from_secs(10),
+            )
+        });
+        runner.perform("/").unwrap().assert_status(204);
+    }
+}
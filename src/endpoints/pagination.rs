@@ -0,0 +1,320 @@
+//! Components for paginating the collections.
+//!
+//! The endpoint `page` extracts the page to be returned from the query
+//! string, and `Page::paginate` wraps the response to advertise the other
+//! pages with the `Link` header ([RFC 5988]).
+//!
+//! [RFC 5988]: https://tools.ietf.org/html/rfc5988
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::endpoint::syntax::path;
+//! # use finchers::endpoints::pagination::{self, Page};
+//! # use finchers::output::Json;
+//! # use finchers::test;
+//! let items: Vec<u32> = (0..95).collect();
+//!
+//! let endpoint = path!(@get "/items")
+//!     .and(pagination::page().max_per_page(50))
+//!     .map(move |page: Page| {
+//!         let offset = page.offset() as usize;
+//!         let end = std::cmp::min(offset + page.per_page() as usize, items.len());
+//!         let body = items.get(offset..end).unwrap_or(&[]).to_vec();
+//!         page.paginate(Json(body), items.len() as u64)
+//!     });
+//!
+//! let mut runner = test::runner(endpoint);
+//! let response = runner.perform("/items?page=2&per_page=20").unwrap();
+//! assert_eq!(
+//!     response.headers()["link"],
+//!     "</items?page=3&per_page=20>; rel=\"next\", \
+//!      </items?page=1&per_page=20>; rel=\"prev\", \
+//!      </items?page=1&per_page=20>; rel=\"first\", \
+//!      </items?page=5&per_page=20>; rel=\"last\""
+//! );
+//! assert_eq!(response.headers()["x-total-count"], "95");
+//! ```
+
+use {
+    crate::{
+        action::{
+            Oneshot,
+            OneshotAction,
+            PreflightContext, //
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+        output::IntoResponse,
+    },
+    http::{
+        header::{self, HeaderName, HeaderValue},
+        Request, Response,
+    },
+    std::sync::Arc,
+    url::form_urlencoded,
+};
+
+const PAGE: &str = "page";
+const PER_PAGE: &str = "per_page";
+const CURSOR: &str = "cursor";
+
+/// Create an endpoint which extracts the page from the query string.
+///
+/// The page is specified by the following parameters, and all of them are optional:
+///
+/// * `page` - the 1-based page number (default: `1`).
+/// * `per_page` - the number of items in a page. The value larger than the
+///   maximum is capped to the maximum (default: `20`, maximum: `100`).
+/// * `cursor` - the opaque cursor for the cursor-based pagination.
+///
+/// The requests with an invalid value (e.g. `page=0`) are rejected with
+/// `400 Bad Request`.
+#[inline]
+pub fn page() -> PageParams {
+    PageParams {
+        config: Arc::new(Config {
+            default_per_page: 20,
+            max_per_page: 100,
+        }),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    default_per_page: u32,
+    max_per_page: u32,
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct PageParams {
+    config: Arc<Config>,
+}
+
+impl PageParams {
+    fn configure(mut self, f: impl FnOnce(&mut Config)) -> Self {
+        f(Arc::make_mut(&mut self.config));
+        self
+    }
+
+    /// Sets the number of items in a page used if `per_page` is not specified.
+    ///
+    /// # Panics
+    /// This method panics if the value is zero.
+    pub fn default_per_page(self, per_page: u32) -> Self {
+        assert!(
+            per_page > 0,
+            "the number of items in a page must be positive"
+        );
+        self.configure(|config| config.default_per_page = per_page)
+    }
+
+    /// Sets the maximum number of items in a page.
+    ///
+    /// # Panics
+    /// This method panics if the value is zero.
+    pub fn max_per_page(self, max_per_page: u32) -> Self {
+        assert!(
+            max_per_page > 0,
+            "the number of items in a page must be positive"
+        );
+        self.configure(|config| config.max_per_page = max_per_page)
+    }
+}
+
+impl IsEndpoint for PageParams {}
+
+impl<Bd> Endpoint<Bd> for PageParams {
+    type Output = (Page,);
+    type Action = Oneshot<PageAction>;
+
+    fn action(&self) -> Self::Action {
+        PageAction {
+            config: self.config.clone(),
+        }
+        .into_action()
+    }
+}
+
+#[doc(hidden)]
+#[allow(missing_debug_implementations)]
+pub struct PageAction {
+    config: Arc<Config>,
+}
+
+impl OneshotAction for PageAction {
+    type Output = (Page,);
+
+    fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+        let mut page = Page {
+            number: 1,
+            per_page: std::cmp::min(self.config.default_per_page, self.config.max_per_page),
+            cursor: None,
+        };
+        let query = cx.uri().query().unwrap_or("");
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            match &*name {
+                PAGE => page.number = parse_positive(PAGE, &value)?,
+                PER_PAGE => {
+                    page.per_page =
+                        std::cmp::min(parse_positive(PER_PAGE, &value)?, self.config.max_per_page)
+                }
+                CURSOR => page.cursor = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+        Ok((page,))
+    }
+}
+
+fn parse_positive(name: &str, value: &str) -> Result<u32, Error> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(error::bad_request(format!(
+            "the query parameter `{}' must be a positive integer",
+            name
+        ))),
+    }
+}
+
+/// The page extracted by `page`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    number: u32,
+    per_page: u32,
+    cursor: Option<String>,
+}
+
+impl Page {
+    /// Returns the 1-based page number.
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// Returns the number of items in a page.
+    pub fn per_page(&self) -> u32 {
+        self.per_page
+    }
+
+    /// Returns the number of items preceding this page.
+    pub fn offset(&self) -> u64 {
+        u64::from(self.number - 1) * u64::from(self.per_page)
+    }
+
+    /// Returns the cursor specified by the client, if any.
+    pub fn cursor(&self) -> Option<&str> {
+        self.cursor.as_ref().map(String::as_str)
+    }
+
+    /// Wraps the response to emit the `Link` header with `next`, `prev`,
+    /// `first` and `last`, computed from the total number of items.
+    ///
+    /// The total number is also reported as `X-Total-Count`.
+    pub fn paginate<T>(&self, body: T, total: u64) -> Paginated<T> {
+        Paginated {
+            body,
+            page: self.clone(),
+            links: Links::Total(total),
+        }
+    }
+
+    /// Wraps the response to emit the `Link` header with `next` for the
+    /// cursor-based pagination, where `next` is the cursor of the next page,
+    /// or `None` if this page is the last.
+    pub fn paginate_cursor<T>(&self, body: T, next: Option<String>) -> Paginated<T> {
+        Paginated {
+            body,
+            page: self.clone(),
+            links: Links::Cursor(next),
+        }
+    }
+}
+
+/// A responder created by `Page::paginate` and `Page::paginate_cursor`.
+#[derive(Debug)]
+pub struct Paginated<T> {
+    body: T,
+    page: Page,
+    links: Links,
+}
+
+#[derive(Debug)]
+enum Links {
+    Total(u64),
+    Cursor(Option<String>),
+}
+
+impl<T> Paginated<T> {
+    fn link_values(&self, request: &Request<()>) -> Vec<String> {
+        // the parameters other than pagination are kept as they are.
+        let params: Vec<(String, String)> =
+            form_urlencoded::parse(request.uri().query().unwrap_or("").as_bytes())
+                .filter(|(name, _)| ![PAGE, PER_PAGE, CURSOR].contains(&&**name))
+                .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                .collect();
+        let link = |rel: &str, extra: &[(&str, &str)]| {
+            let mut query = form_urlencoded::Serializer::new(String::new());
+            for (name, value) in &params {
+                query.append_pair(name, value);
+            }
+            for (name, value) in extra {
+                query.append_pair(name, value);
+            }
+            format!(
+                "<{}?{}>; rel=\"{}\"",
+                request.uri().path(),
+                query.finish(),
+                rel
+            )
+        };
+
+        let per_page = self.page.per_page.to_string();
+        let page_link = |rel: &str, number: u64| {
+            link(rel, &[(PAGE, &number.to_string()), (PER_PAGE, &per_page)])
+        };
+
+        let mut links = vec![];
+        match self.links {
+            Links::Total(total) => {
+                let number = u64::from(self.page.number);
+                let last = std::cmp::max(total.div_ceil(u64::from(self.page.per_page)), 1);
+                if number < last {
+                    links.push(page_link("next", number + 1));
+                }
+                if number > 1 {
+                    links.push(page_link("prev", std::cmp::min(number - 1, last)));
+                }
+                links.push(page_link("first", 1));
+                links.push(page_link("last", last));
+            }
+            Links::Cursor(Some(ref next)) => {
+                links.push(link("next", &[(CURSOR, next), (PER_PAGE, &per_page)]));
+            }
+            Links::Cursor(None) => {}
+        }
+        links
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Paginated<T> {
+    type Body = T::Body;
+
+    fn into_response(self, request: &Request<()>) -> Response<Self::Body> {
+        let links = self.link_values(request);
+        let mut response = self.body.into_response(request);
+        if !links.is_empty() {
+            let value = HeaderValue::from_shared(links.join(", ").into())
+                .expect("should be a valid header value");
+            response.headers_mut().insert(header::LINK, value);
+        }
+        if let Links::Total(total) = self.links {
+            response.headers_mut().insert(
+                HeaderName::from_static("x-total-count"),
+                HeaderValue::from(total),
+            );
+        }
+        response
+    }
+}
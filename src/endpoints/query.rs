@@ -12,7 +12,7 @@ use {
     },
     failure::SyncFailure,
     serde::de::DeserializeOwned,
-    std::marker::PhantomData,
+    std::{fmt, marker::PhantomData, str::FromStr, sync::Arc},
 };
 
 // ==== Required ====
@@ -189,7 +189,6 @@ mod optional {
             }
         }
     }
-
 }
 
 /// Create an endpoint which extracts the query string from a request.
@@ -227,3 +226,261 @@ mod raw {
         }
     }
 }
+
+// ==== Param ====
+
+fn values<'a>(pairs: &'a [(String, String)], key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    pairs
+        .iter()
+        .filter(move |(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+fn parse_value<T>(key: &str, value: &str) -> Result<T, Error>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|err| error::bad_request(format!("invalid query parameter `{}`: {}", key, err)))
+}
+
+/// Create an endpoint which parses the value of the specified query parameter.
+///
+/// If the parameter is specified more than once, the first one is used.
+/// If the parameter is missing, this endpoint returns a `400 Bad Request`.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::endpoints::query;
+/// # use finchers::prelude::*;
+/// # use finchers::test;
+/// let endpoint = query::param::<u32>("page")
+///     .map(|page: u32| format!("page = {}", page));
+///
+/// let mut runner = test::runner(endpoint);
+/// assert_eq!(runner.apply("/?page=3").unwrap(), "page = 3");
+/// assert!(runner.apply("/?page=three").is_err());
+/// ```
+#[inline]
+pub fn param<T>(key: impl Into<String>) -> Param<T>
+where
+    T: FromStr + 'static,
+    T::Err: fmt::Display,
+{
+    Param {
+        key: key.into().into(),
+        _marker: PhantomData,
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct Param<T> {
+    key: Arc<str>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+mod param {
+    use super::*;
+
+    impl<T> IsEndpoint for Param<T>
+    where
+        T: FromStr + 'static,
+        T::Err: fmt::Display,
+    {
+    }
+
+    impl<T, Bd> Endpoint<Bd> for Param<T>
+    where
+        T: FromStr + 'static,
+        T::Err: fmt::Display,
+    {
+        type Output = (T,);
+        type Action = Oneshot<ParamAction<T>>;
+
+        fn action(&self) -> Self::Action {
+            ParamAction {
+                key: self.key.clone(),
+                _marker: PhantomData,
+            }
+            .into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct ParamAction<T> {
+        key: Arc<str>,
+        _marker: PhantomData<fn() -> T>,
+    }
+
+    impl<T> OneshotAction for ParamAction<T>
+    where
+        T: FromStr + 'static,
+        T::Err: fmt::Display,
+    {
+        type Output = (T,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            let pairs = cx.query_pairs();
+            let value = values(&pairs, &self.key).next().ok_or_else(|| {
+                error::bad_request(format!("missing query parameter `{}`", self.key))
+            })?;
+            parse_value(&self.key, value).map(|x| (x,))
+        }
+    }
+}
+
+// ==== OptionalParam ====
+
+/// Create an endpoint which parses the value of the specified query parameter
+/// if it exists.
+///
+/// This endpoint returns a `None` if the parameter is missing, and
+/// a `400 Bad Request` if the value cannot be parsed.
+#[inline]
+pub fn optional_param<T>(key: impl Into<String>) -> OptionalParam<T>
+where
+    T: FromStr + 'static,
+    T::Err: fmt::Display,
+{
+    OptionalParam {
+        key: key.into().into(),
+        _marker: PhantomData,
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct OptionalParam<T> {
+    key: Arc<str>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+mod optional_param {
+    use super::*;
+
+    impl<T> IsEndpoint for OptionalParam<T>
+    where
+        T: FromStr + 'static,
+        T::Err: fmt::Display,
+    {
+    }
+
+    impl<T, Bd> Endpoint<Bd> for OptionalParam<T>
+    where
+        T: FromStr + 'static,
+        T::Err: fmt::Display,
+    {
+        type Output = (Option<T>,);
+        type Action = Oneshot<OptionalParamAction<T>>;
+
+        fn action(&self) -> Self::Action {
+            OptionalParamAction {
+                key: self.key.clone(),
+                _marker: PhantomData,
+            }
+            .into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct OptionalParamAction<T> {
+        key: Arc<str>,
+        _marker: PhantomData<fn() -> T>,
+    }
+
+    impl<T> OneshotAction for OptionalParamAction<T>
+    where
+        T: FromStr + 'static,
+        T::Err: fmt::Display,
+    {
+        type Output = (Option<T>,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            let pairs = cx.query_pairs();
+            let value = values(&pairs, &self.key).next();
+            match value {
+                Some(value) => parse_value(&self.key, value).map(|x| (Some(x),)),
+                None => Ok((None,)),
+            }
+        }
+    }
+}
+
+// ==== Params ====
+
+/// Create an endpoint which parses all of the values of the specified
+/// query parameter, such as `?tag=a&tag=b`.
+///
+/// This endpoint returns an empty `Vec` if the parameter is missing.
+#[inline]
+pub fn params<T>(key: impl Into<String>) -> Params<T>
+where
+    T: FromStr + 'static,
+    T::Err: fmt::Display,
+{
+    Params {
+        key: key.into().into(),
+        _marker: PhantomData,
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct Params<T> {
+    key: Arc<str>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+mod params {
+    use super::*;
+
+    impl<T> IsEndpoint for Params<T>
+    where
+        T: FromStr + 'static,
+        T::Err: fmt::Display,
+    {
+    }
+
+    impl<T, Bd> Endpoint<Bd> for Params<T>
+    where
+        T: FromStr + 'static,
+        T::Err: fmt::Display,
+    {
+        type Output = (Vec<T>,);
+        type Action = Oneshot<ParamsAction<T>>;
+
+        fn action(&self) -> Self::Action {
+            ParamsAction {
+                key: self.key.clone(),
+                _marker: PhantomData,
+            }
+            .into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct ParamsAction<T> {
+        key: Arc<str>,
+        _marker: PhantomData<fn() -> T>,
+    }
+
+    impl<T> OneshotAction for ParamsAction<T>
+    where
+        T: FromStr + 'static,
+        T::Err: fmt::Display,
+    {
+        type Output = (Vec<T>,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            let pairs = cx.query_pairs();
+            values(&pairs, &self.key)
+                .map(|value| parse_value(&self.key, value))
+                .collect::<Result<_, _>>()
+                .map(|x| (x,))
+        }
+    }
+}
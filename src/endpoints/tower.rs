@@ -0,0 +1,102 @@
+//! Components for embedding the implementors of `tower_service::Service` into endpoints.
+
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+    },
+    futures::{Async, Future, Poll},
+    http::Request,
+    tower_service::Service,
+};
+
+/// Create an endpoint which forwards the incoming request to the specified `Service`.
+///
+/// The inner service is cloned for each request, and the request body is taken
+/// from the context when the service becomes ready. The response returned from
+/// the service is used as the output of this endpoint.
+///
+/// Note that the request extensions are not forwarded to the inner service.
+#[inline]
+pub fn service<S>(service: S) -> TowerService<S> {
+    TowerService { service }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct TowerService<S> {
+    service: S,
+}
+
+mod tower_service_endpoint {
+    use super::*;
+
+    impl<S> IsEndpoint for TowerService<S> {}
+
+    impl<S, Bd> Endpoint<Bd> for TowerService<S>
+    where
+        S: Service<Request<Bd>> + Clone,
+        S::Error: Into<Error>,
+    {
+        type Output = (S::Response,);
+        type Action = TowerServiceAction<S, Bd>;
+
+        fn action(&self) -> Self::Action {
+            TowerServiceAction {
+                state: State::Ready(self.service.clone()),
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct TowerServiceAction<S, Bd>
+    where
+        S: Service<Request<Bd>>,
+    {
+        state: State<S, S::Future>,
+    }
+
+    #[allow(missing_debug_implementations)]
+    enum State<S, F> {
+        Ready(S),
+        InFlight(F),
+    }
+
+    impl<S, Bd> EndpointAction<Bd> for TowerServiceAction<S, Bd>
+    where
+        S: Service<Request<Bd>>,
+        S::Error: Into<Error>,
+    {
+        type Output = (S::Response,);
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            loop {
+                self.state = match self.state {
+                    State::Ready(ref mut service) => {
+                        futures::try_ready!(service.poll_ready().map_err(Into::into));
+                        let request = forward_request(cx)?;
+                        State::InFlight(service.call(request))
+                    }
+                    State::InFlight(ref mut future) => {
+                        let response = futures::try_ready!(future.poll().map_err(Into::into));
+                        return Ok(Async::Ready((response,)));
+                    }
+                };
+            }
+        }
+    }
+
+    fn forward_request<Bd>(cx: &mut ActionContext<'_, Bd>) -> Result<Request<Bd>, Error> {
+        let body = cx.take_body()?;
+        let mut request = Request::new(body);
+        *request.method_mut() = cx.method().clone();
+        *request.uri_mut() = cx.uri().clone();
+        *request.version_mut() = cx.version();
+        *request.headers_mut() = cx.headers().clone();
+        Ok(request)
+    }
+}
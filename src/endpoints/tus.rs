@@ -0,0 +1,982 @@
+//! Components for serving resumable uploads with the [tus] protocol.
+//!
+//! The endpoint created by `server` handles the core protocol and the
+//! `creation`, `expiration` and `termination` extensions, and stores the
+//! uploaded data into a `Storage`. The uploads are addressed by the segment
+//! following the path where the endpoint is mounted.
+//!
+//! [tus]: https://tus.io/protocols/resumable-upload.html
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::endpoint::syntax;
+//! # use finchers::endpoints::tus::{self, FileStorage, TusResponse};
+//! # use std::time::Duration;
+//! # fn main() -> std::io::Result<()> {
+//! # let dir = std::env::temp_dir().join("finchers-tus-doctest");
+//! let storage = FileStorage::new(&dir)?;
+//! let endpoint = syntax::segment("files").and(
+//!     tus::server(storage)
+//!         .max_size(1024 * 1024 * 1024)
+//!         .expiration(Duration::from_secs(24 * 60 * 60))
+//!         .map(|response: TusResponse| {
+//!             if let Some(upload) = response.completed() {
+//!                 log::info!("upload completed: {}", upload.id);
+//!             }
+//!             response
+//!         }),
+//! );
+//! # drop(endpoint);
+//! # std::fs::remove_dir_all(dir)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use {
+    crate::{
+        action::{ActionContext, EndpointAction, Preflight, PreflightContext},
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+        output::IntoResponse,
+        util::{blocking, civil_from_days},
+    },
+    bytes::Buf,
+    futures::{Async, Future, Poll},
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        Method, Request, Response, StatusCode,
+    },
+    izanami_util::buf_stream::BufStream,
+    rand::{thread_rng, Rng},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        fmt::{self, Write as _},
+        fs::{self, OpenOptions},
+        io::{self, Write as _},
+        path::PathBuf,
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// The version of the tus protocol supported by this module.
+pub const TUS_VERSION: &str = "1.0.0";
+
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+fn tus_header(name: &'static str) -> HeaderName {
+    HeaderName::from_static(name)
+}
+
+// ==== Upload ====
+
+/// The state of an upload.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Upload {
+    /// The identifier of the upload.
+    pub id: String,
+
+    /// The total size of the upload in bytes.
+    pub length: u64,
+
+    /// The number of bytes which have been received.
+    pub offset: u64,
+
+    /// The raw value of `Upload-Metadata` specified at the creation.
+    pub metadata: Option<String>,
+
+    /// The time when the incomplete upload expires.
+    pub expires: Option<SystemTime>,
+}
+
+impl Upload {
+    /// Returns whether all of the bytes have been received.
+    pub fn is_complete(&self) -> bool {
+        self.offset >= self.length
+    }
+
+    /// Returns whether the upload is incomplete and has expired at the specified time.
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        !self.is_complete() && self.expires.map_or(false, |expires| expires <= now)
+    }
+
+    /// Returns the decoded value of the metadata associated with the specified key.
+    ///
+    /// The keys without a value are reported as an empty value.
+    pub fn metadata(&self, key: &str) -> Option<Vec<u8>> {
+        self.metadata
+            .as_ref()?
+            .split(',')
+            .filter_map(|pair| {
+                let mut parts = pair.trim().splitn(2, ' ');
+                match (parts.next(), parts.next()) {
+                    (Some(k), value) if k == key => {
+                        value.map_or(Some(vec![]), |v| base64::decode(v.trim()).ok())
+                    }
+                    _ => None,
+                }
+            })
+            .next()
+    }
+}
+
+// ==== Storage ====
+
+/// A trait representing the storage of uploads used by `server`.
+///
+/// The methods are called inside of `util::blocking`, and hence they are
+/// allowed to block the current thread.
+pub trait Storage: Send + Sync + 'static {
+    /// Registers a new upload whose offset is zero.
+    fn create(&self, upload: &Upload) -> io::Result<()>;
+
+    /// Returns the current state of the upload, or `None` if not exist.
+    fn get(&self, id: &str) -> io::Result<Option<Upload>>;
+
+    /// Appends the data to the upload.
+    ///
+    /// The implementation should fail with `io::ErrorKind::InvalidInput` if
+    /// the specified offset is not equal to the current one. The check and
+    /// the write must be atomic with respect to the other appends to the same
+    /// upload, since the concurrent requests may resume it at the same offset.
+    fn append(&self, id: &str, offset: u64, data: &[u8]) -> io::Result<()>;
+
+    /// Removes the upload and its data.
+    fn remove(&self, id: &str) -> io::Result<()>;
+
+    /// Removes the incomplete uploads expired at the specified time, and
+    /// returns the number of removed uploads.
+    ///
+    /// The expired uploads are also removed when they are accessed, so the
+    /// default implementation does nothing.
+    fn purge_expired(&self, now: SystemTime) -> io::Result<usize> {
+        let _ = now;
+        Ok(0)
+    }
+}
+
+impl<S: Storage> Storage for Arc<S> {
+    fn create(&self, upload: &Upload) -> io::Result<()> {
+        (**self).create(upload)
+    }
+
+    fn get(&self, id: &str) -> io::Result<Option<Upload>> {
+        (**self).get(id)
+    }
+
+    fn append(&self, id: &str, offset: u64, data: &[u8]) -> io::Result<()> {
+        (**self).append(id, offset, data)
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        (**self).remove(id)
+    }
+
+    fn purge_expired(&self, now: SystemTime) -> io::Result<usize> {
+        (**self).purge_expired(now)
+    }
+}
+
+/// A `Storage` which stores the uploads into a directory.
+///
+/// Each upload is stored as two files: `<id>` containing the received data,
+/// and `<id>.info` containing the state of upload as JSON.
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    root: PathBuf,
+    locks: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+}
+
+impl FileStorage {
+    /// Creates a `FileStorage` using the specified directory.
+    ///
+    /// The directory is created if not exist.
+    pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(FileStorage {
+            root,
+            locks: Arc::default(),
+        })
+    }
+
+    /// Returns the path to the file containing the data of the upload.
+    ///
+    /// It returns `None` if the identifier contains any characters unsafe as
+    /// a file name.
+    pub fn data_path(&self, id: &str) -> Option<PathBuf> {
+        if id.is_empty()
+            || !id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return None;
+        }
+        Some(self.root.join(id))
+    }
+
+    fn info_path(&self, id: &str) -> Option<PathBuf> {
+        self.data_path(id).map(|path| path.with_extension("info"))
+    }
+
+    /// Calls the function while holding the lock of the specified upload.
+    fn with_lock<R>(&self, id: &str, f: impl FnOnce() -> R) -> R {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(id.to_owned())
+            .or_default()
+            .clone();
+        let result = {
+            let _guard = lock.lock().unwrap();
+            f()
+        };
+        let mut locks = self.locks.lock().unwrap();
+        // the entry is shared only by the map and this call.
+        if Arc::strong_count(&lock) == 2 {
+            locks.remove(id);
+        }
+        result
+    }
+}
+
+impl Storage for FileStorage {
+    fn create(&self, upload: &Upload) -> io::Result<()> {
+        let (data_path, info_path) = match (self.data_path(&upload.id), self.info_path(&upload.id))
+        {
+            (Some(data_path), Some(info_path)) => (data_path, info_path),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid upload id",
+                ))
+            }
+        };
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(data_path)?;
+        fs::write(info_path, serde_json::to_vec(upload)?)
+    }
+
+    fn get(&self, id: &str) -> io::Result<Option<Upload>> {
+        let (data_path, info_path) = match (self.data_path(id), self.info_path(id)) {
+            (Some(data_path), Some(info_path)) => (data_path, info_path),
+            _ => return Ok(None),
+        };
+        let info = match fs::read(info_path) {
+            Ok(info) => info,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut upload: Upload = serde_json::from_slice(&info)?;
+        // the offset is always derived from the size of the data.
+        upload.offset = fs::metadata(data_path)?.len();
+        Ok(Some(upload))
+    }
+
+    fn append(&self, id: &str, offset: u64, data: &[u8]) -> io::Result<()> {
+        let data_path = self
+            .data_path(id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "invalid upload id"))?;
+        self.with_lock(id, || {
+            let mut file = OpenOptions::new().append(true).open(data_path)?;
+            if file.metadata()?.len() != offset {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "mismatched upload offset",
+                ));
+            }
+            file.write_all(data)
+        })
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        for path in self.data_path(id).into_iter().chain(self.info_path(id)) {
+            match fs::remove_file(path) {
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    fn purge_expired(&self, now: SystemTime) -> io::Result<usize> {
+        let mut count = 0;
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.extension() != Some("info".as_ref()) {
+                continue;
+            }
+            let id = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(id) => id,
+                None => continue,
+            };
+            if let Some(upload) = self.get(id)? {
+                if upload.is_expired(now) {
+                    self.remove(id)?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+// ==== Server ====
+
+/// Create an endpoint which serves the resumable uploads with the tus protocol.
+///
+/// The endpoint handles the following requests, and the other requests are
+/// rejected with `404 Not Found` so that it can be combined with other routes:
+///
+/// * `OPTIONS /` - reports the capabilities of the server.
+/// * `POST /` - creates a new upload with `Upload-Length`, and responds
+///   with its location.
+/// * `HEAD /<id>` - reports the current offset of the upload.
+/// * `PATCH /<id>` - appends the request body at `Upload-Offset`. The body
+///   is written to the storage as it arrives, so the data received before
+///   the connection is interrupted is kept.
+/// * `DELETE /<id>` - terminates the upload.
+///
+/// The requests other than `OPTIONS` must have `Tus-Resumable: 1.0.0`, or
+/// they are rejected with `412 Precondition Failed`. A `PATCH` request is
+/// rejected with `409 Conflict` if `Upload-Offset` is not equal to the
+/// current offset, and with `413 Payload Too Large` if the body exceeds the
+/// length of the upload. The incomplete uploads past their expiration are
+/// removed on access and reported as `410 Gone`.
+pub fn server<S>(storage: S) -> TusServer<S>
+where
+    S: Storage,
+{
+    TusServer {
+        storage: Arc::new(storage),
+        config: Arc::new(Config {
+            max_size: None,
+            expiration: None,
+        }),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    max_size: Option<u64>,
+    expiration: Option<Duration>,
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct TusServer<S> {
+    storage: Arc<S>,
+    config: Arc<Config>,
+}
+
+impl<S> Clone for TusServer<S> {
+    fn clone(&self) -> Self {
+        TusServer {
+            storage: self.storage.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<S> TusServer<S> {
+    fn configure(mut self, f: impl FnOnce(&mut Config)) -> Self {
+        f(Arc::make_mut(&mut self.config));
+        self
+    }
+
+    /// Sets the maximum length of an upload, reported as `Tus-Max-Size`.
+    ///
+    /// The creation of larger uploads is rejected with `413 Payload Too Large`.
+    pub fn max_size(self, max_size: u64) -> Self {
+        self.configure(|config| config.max_size = Some(max_size))
+    }
+
+    /// Sets the duration until an incomplete upload expires after its creation.
+    ///
+    /// By default, the uploads never expire.
+    pub fn expiration(self, expiration: Duration) -> Self {
+        self.configure(|config| config.expiration = Some(expiration))
+    }
+
+    /// Returns a reference to the storage.
+    pub fn storage(&self) -> &Arc<S> {
+        &self.storage
+    }
+}
+
+impl<S> IsEndpoint for TusServer<S> {}
+
+impl<S, Bd> Endpoint<Bd> for TusServer<S>
+where
+    S: Storage,
+    Bd: BufStream,
+    Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+    type Output = (TusResponse,);
+    type Action = TusAction<S, Bd>;
+
+    fn action(&self) -> Self::Action {
+        TusAction {
+            storage: self.storage.clone(),
+            config: self.config.clone(),
+            state: State::Init,
+        }
+    }
+}
+
+/// The response from the endpoint created by `server`.
+#[derive(Debug)]
+pub struct TusResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    completed: Option<Upload>,
+}
+
+impl TusResponse {
+    fn new(status: StatusCode) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            tus_header("tus-resumable"),
+            HeaderValue::from_static(TUS_VERSION),
+        );
+        TusResponse {
+            status,
+            headers,
+            completed: None,
+        }
+    }
+
+    fn header(mut self, name: &'static str, value: impl fmt::Display) -> Self {
+        let value = HeaderValue::from_shared(value.to_string().into())
+            .expect("should be a valid header value");
+        self.headers.insert(tus_header(name), value);
+        self
+    }
+
+    /// Returns the status code of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the header map of the response.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Returns the upload completed by the request, if any.
+    ///
+    /// It is returned only once for each upload, from the response to the
+    /// `PATCH` request which has received the last bytes.
+    pub fn completed(&self) -> Option<&Upload> {
+        self.completed.as_ref()
+    }
+}
+
+impl IntoResponse for TusResponse {
+    type Body = &'static [u8];
+
+    fn into_response(self, _: &Request<()>) -> Response<Self::Body> {
+        let mut response = Response::new(&[] as &[u8]);
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+        response
+    }
+}
+
+#[allow(missing_docs, missing_debug_implementations)]
+pub struct TusAction<S, Bd: BufStream> {
+    storage: Arc<S>,
+    config: Arc<Config>,
+    state: State<Bd>,
+}
+
+enum State<Bd: BufStream> {
+    Init,
+    Create(Upload, String),
+    Head(String),
+    Delete(String),
+    Patch(String, u64),
+    Receiving {
+        upload: Upload,
+        body: Bd,
+        chunk: Option<Bd::Item>,
+    },
+    Done,
+}
+
+impl<S, Bd> EndpointAction<Bd> for TusAction<S, Bd>
+where
+    S: Storage,
+    Bd: BufStream,
+    Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+    type Output = (TusResponse,);
+
+    fn preflight(
+        &mut self,
+        cx: &mut PreflightContext<'_>,
+    ) -> Result<Preflight<Self::Output>, Error> {
+        // the trailing slash is ignored (e.g. `POST /files/`).
+        let id = match cx.cursor().next() {
+            Some(segment) if !segment.as_bytes().is_empty() => {
+                Some(segment.percent_decode_lossy().into_owned())
+            }
+            _ => None,
+        };
+        if cx.cursor().next().is_some() {
+            return Err(error::not_found("not matched"));
+        }
+
+        if *cx.method() == Method::OPTIONS {
+            let mut response = TusResponse::new(StatusCode::NO_CONTENT)
+                .header("tus-version", TUS_VERSION)
+                .header("tus-extension", TUS_EXTENSIONS);
+            if let Some(max_size) = self.config.max_size {
+                response = response.header("tus-max-size", max_size);
+            }
+            return Ok(Preflight::Completed((response,)));
+        }
+
+        let id = match (cx.method(), id) {
+            (&Method::POST, None) => None,
+            (&Method::HEAD, Some(id))
+            | (&Method::PATCH, Some(id))
+            | (&Method::DELETE, Some(id)) => Some(id),
+            _ => return Err(error::not_found("not matched")),
+        };
+
+        match cx.headers().get("tus-resumable") {
+            Some(version) if version == TUS_VERSION => {}
+            _ => {
+                return Err(error::err_msg(
+                    format!("unsupported protocol version (expected {})", TUS_VERSION),
+                    StatusCode::PRECONDITION_FAILED,
+                ))
+            }
+        }
+
+        self.state = match (cx.method(), id) {
+            (&Method::POST, _) => {
+                let length = parse_u64_header(cx.headers(), "upload-length")?;
+                if self.config.max_size.map_or(false, |max| length > max) {
+                    return Err(error::err_msg(
+                        "the upload is too large",
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                    ));
+                }
+                let metadata = match cx.headers().get("upload-metadata") {
+                    Some(value) => Some(
+                        value
+                            .to_str()
+                            .map_err(|err| error::fail(err, StatusCode::BAD_REQUEST))?
+                            .to_owned(),
+                    ),
+                    None => None,
+                };
+                let upload = Upload {
+                    id: generate_id(),
+                    length,
+                    offset: 0,
+                    metadata,
+                    expires: self
+                        .config
+                        .expiration
+                        .map(|expiration| SystemTime::now() + expiration),
+                };
+                let location = format!("{}/{}", cx.uri().path().trim_end_matches('/'), upload.id);
+                State::Create(upload, location)
+            }
+            (&Method::HEAD, Some(id)) => State::Head(id),
+            (&Method::DELETE, Some(id)) => State::Delete(id),
+            (_, Some(id)) => {
+                match cx.headers().get(header::CONTENT_TYPE) {
+                    Some(content_type) if content_type == OFFSET_OCTET_STREAM => {}
+                    _ => {
+                        return Err(error::unsupported_media_type(format!(
+                            "the content type must be {}",
+                            OFFSET_OCTET_STREAM
+                        )))
+                    }
+                }
+                State::Patch(id, parse_u64_header(cx.headers(), "upload-offset")?)
+            }
+            (_, None) => unreachable!(),
+        };
+
+        Ok(Preflight::Incomplete)
+    }
+
+    fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+        let storage = &*self.storage;
+        loop {
+            self.state = match self.state {
+                State::Init | State::Done => panic!("the action has already been polled"),
+
+                State::Create(ref upload, ref location) => {
+                    futures::try_ready!(blocking(|| storage.create(upload))
+                        .poll()
+                        .map_err(io_error));
+                    let mut response =
+                        TusResponse::new(StatusCode::CREATED).header("location", location);
+                    if let Some(expires) = upload.expires {
+                        response = response.header("upload-expires", HttpDate(expires));
+                    }
+                    self.state = State::Done;
+                    return Ok(Async::Ready((response,)));
+                }
+
+                State::Head(ref id) => {
+                    let upload = futures::try_ready!(poll_upload(storage, id));
+                    let mut response = TusResponse::new(StatusCode::OK)
+                        .header("upload-offset", upload.offset)
+                        .header("upload-length", upload.length)
+                        .header("cache-control", "no-store");
+                    if let Some(ref metadata) = upload.metadata {
+                        response = response.header("upload-metadata", metadata);
+                    }
+                    if let Some(expires) = upload.expires {
+                        response = response.header("upload-expires", HttpDate(expires));
+                    }
+                    self.state = State::Done;
+                    return Ok(Async::Ready((response,)));
+                }
+
+                State::Delete(ref id) => {
+                    futures::try_ready!(poll_upload(storage, id));
+                    futures::try_ready!(blocking(|| storage.remove(id)).poll().map_err(io_error));
+                    self.state = State::Done;
+                    return Ok(Async::Ready((TusResponse::new(StatusCode::NO_CONTENT),)));
+                }
+
+                State::Patch(ref id, offset) => {
+                    let upload = futures::try_ready!(poll_upload(storage, id));
+                    if upload.offset != offset {
+                        return Err(error::err_msg(
+                            format!("mismatched upload offset (current: {})", upload.offset),
+                            StatusCode::CONFLICT,
+                        ));
+                    }
+                    State::Receiving {
+                        upload,
+                        body: cx.take_body()?,
+                        chunk: None,
+                    }
+                }
+
+                State::Receiving {
+                    ref mut upload,
+                    ref mut body,
+                    ref mut chunk,
+                } => {
+                    loop {
+                        if let Some(ref mut data) = *chunk {
+                            // the chunk may consist of several contiguous slices.
+                            futures::try_ready!(blocking(|| {
+                                while data.has_remaining() {
+                                    let len = data.bytes().len();
+                                    storage.append(&upload.id, upload.offset, data.bytes())?;
+                                    upload.offset += len as u64;
+                                    data.advance(len);
+                                }
+                                Ok(())
+                            })
+                            .poll()
+                            .map_err(io_error));
+                        }
+                        *chunk = None;
+
+                        match futures::try_ready!(body
+                            .poll_buf()
+                            .map_err(|e| failure::Error::from_boxed_compat(e.into())))
+                        {
+                            Some(data) => {
+                                if upload.offset + data.remaining() as u64 > upload.length {
+                                    return Err(error::err_msg(
+                                        "the body exceeds the length of the upload",
+                                        StatusCode::PAYLOAD_TOO_LARGE,
+                                    ));
+                                }
+                                *chunk = Some(data);
+                            }
+                            None => break,
+                        }
+                    }
+
+                    let mut response = TusResponse::new(StatusCode::NO_CONTENT)
+                        .header("upload-offset", upload.offset);
+                    if let Some(expires) = upload.expires {
+                        response = response.header("upload-expires", HttpDate(expires));
+                    }
+                    if upload.is_complete() {
+                        response.completed = Some(upload.clone());
+                    }
+                    self.state = State::Done;
+                    return Ok(Async::Ready((response,)));
+                }
+            };
+        }
+    }
+}
+
+/// Retrieves the upload from the storage, removing it if expired.
+fn poll_upload<S: Storage>(storage: &S, id: &str) -> Poll<Upload, Error> {
+    let upload = futures::try_ready!(blocking(|| storage.get(id)).poll().map_err(io_error))
+        .ok_or_else(|| error::not_found("the upload does not exist"))?;
+    if upload.is_expired(SystemTime::now()) {
+        futures::try_ready!(blocking(|| storage.remove(id)).poll().map_err(io_error));
+        return Err(error::err_msg("the upload has expired", StatusCode::GONE));
+    }
+    Ok(Async::Ready(upload))
+}
+
+fn io_error(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::InvalidInput => error::fail(err, StatusCode::CONFLICT),
+        io::ErrorKind::NotFound => error::fail(err, StatusCode::NOT_FOUND),
+        _ => error::fail(err, StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+fn parse_u64_header(headers: &HeaderMap, name: &str) -> Result<u64, Error> {
+    headers
+        .get(name)
+        .ok_or_else(|| error::bad_request(format!("missing header: {}", name)))?
+        .to_str()
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| error::bad_request(format!("invalid header: {}", name)))
+}
+
+fn generate_id() -> String {
+    let mut buf = [0u8; 16];
+    thread_rng().fill(&mut buf[..]);
+    let mut id = String::with_capacity(32);
+    for b in &buf {
+        let _ = write!(id, "{:02x}", b);
+    }
+    id
+}
+
+/// Formats a `SystemTime` as an IMF-fixdate (e.g. `Sun, 06 Nov 1994 08:49:37 GMT`).
+struct HttpDate(SystemTime);
+
+impl fmt::Display for HttpDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+        let secs = self
+            .0
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0) as i64;
+        let (days, secs) = (secs / 86400, secs % 86400);
+        let (year, month, day) = civil_from_days(days);
+        write!(
+            f,
+            "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[(days % 7) as usize],
+            day,
+            MONTHS[month as usize - 1],
+            year,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            endpoint::EndpointExt,
+            test::{self, TestRunner},
+        },
+        http::Request,
+    };
+
+    /// A temporary directory removed at the end of test.
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn temp_storage(name: &str) -> (FileStorage, TempDir) {
+        let dir =
+            std::env::temp_dir().join(format!("finchers-tus-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        (FileStorage::new(&dir).unwrap(), TempDir(dir))
+    }
+
+    fn mount<S: Storage>(
+        server: TusServer<S>,
+    ) -> impl Endpoint<test::ReqBody, Output = (TusResponse,)> {
+        crate::endpoint::syntax::segment("files").and(server)
+    }
+
+    fn create<E>(runner: &mut TestRunner<E>, length: u64) -> String
+    where
+        E: Endpoint<test::ReqBody, Output = (TusResponse,)>,
+    {
+        let response = runner
+            .perform(
+                Request::post("/files/")
+                    .header("tus-resumable", TUS_VERSION)
+                    .header("upload-length", length.to_string().as_str())
+                    .header("upload-metadata", "filename aGVsbG8udHh0,is_confidential"),
+            )
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers()["location"].to_str().unwrap();
+        assert!(location.starts_with("/files/"));
+        location.to_owned()
+    }
+
+    fn patch<'a>(location: &str, offset: u64, data: &'a str) -> http::Result<Request<&'a str>> {
+        Request::patch(location)
+            .header("tus-resumable", TUS_VERSION)
+            .header("content-type", OFFSET_OCTET_STREAM)
+            .header("upload-offset", offset.to_string().as_str())
+            .body(data)
+    }
+
+    #[test]
+    fn test_upload() {
+        let (storage, _dir) = temp_storage("upload");
+        let storage = Arc::new(storage);
+        let mut runner = test::runner(mount(server(storage.clone())));
+        let location = create(&mut runner, 11);
+        let id = location.trim_start_matches("/files/");
+
+        let response = runner.perform(patch(&location, 0, "hello ")).unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        response.assert_header("upload-offset", "6");
+
+        let response = runner
+            .perform(
+                Request::head(location.as_str())
+                    .header("tus-resumable", TUS_VERSION)
+                    .body(()),
+            )
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.assert_header("upload-offset", "6");
+        response.assert_header("upload-length", "11");
+
+        let response = runner.apply(patch(&location, 6, "world")).unwrap();
+        let completed = response.completed().expect("should be completed");
+        assert_eq!(completed.offset, 11);
+        assert_eq!(completed.metadata("filename"), Some(b"hello.txt".to_vec()));
+        assert_eq!(completed.metadata("is_confidential"), Some(vec![]));
+
+        let data = fs::read(storage.data_path(id).unwrap()).unwrap();
+        assert_eq!(data, b"hello world");
+    }
+
+    #[test]
+    fn test_offset_mismatch() {
+        let (storage, _dir) = temp_storage("mismatch");
+        let mut runner = test::runner(mount(server(storage)));
+        let location = create(&mut runner, 11);
+        let response = runner.perform(patch(&location, 3, "hello")).unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = runner.perform(patch(&location, 0, "hello world!")).unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn test_protocol_version() {
+        let (storage, _dir) = temp_storage("version");
+        let mut runner = test::runner(server(storage).max_size(100));
+        let response = runner
+            .perform(Request::post("/").header("upload-length", "10"))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+        let response = runner.perform(Request::options("/")).unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        response.assert_header("tus-version", TUS_VERSION);
+        response.assert_header("tus-max-size", "100");
+    }
+
+    #[test]
+    fn test_expiration() {
+        let (storage, _dir) = temp_storage("expiration");
+        let storage = Arc::new(storage);
+        let mut runner = test::runner(mount(
+            server(storage.clone()).expiration(Duration::from_secs(0)),
+        ));
+        let location = create(&mut runner, 11);
+        let response = runner.perform(patch(&location, 0, "hello")).unwrap();
+        assert_eq!(response.status(), StatusCode::GONE);
+        let id = location.trim_start_matches("/files/");
+        assert_eq!(storage.get(id).unwrap(), None);
+    }
+
+    #[test]
+    fn test_termination() {
+        let (storage, _dir) = temp_storage("termination");
+        let mut runner = test::runner(mount(server(storage)));
+        let location = create(&mut runner, 11);
+        let delete = || {
+            Request::delete(location.as_str())
+                .header("tus-resumable", TUS_VERSION)
+                .body(())
+        };
+        let response = runner.perform(delete()).unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = runner.perform(delete()).unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_concurrent_append() {
+        let (storage, _dir) = temp_storage("concurrent");
+        let storage = Arc::new(storage);
+        let upload = Upload {
+            id: generate_id(),
+            length: 100,
+            offset: 0,
+            metadata: None,
+            expires: None,
+        };
+        storage.create(&upload).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let storage = storage.clone();
+                let id = upload.id.clone();
+                std::thread::spawn(move || storage.append(&id, 0, b"hello").is_ok())
+            })
+            .collect();
+        let succeeded = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|&ok| ok)
+            .count();
+        assert_eq!(succeeded, 1);
+        assert_eq!(storage.get(&upload.id).unwrap().unwrap().offset, 5);
+        assert!(storage.locks.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_http_date() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(HttpDate(time).to_string(), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+}
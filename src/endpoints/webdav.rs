@@ -0,0 +1,523 @@
+//! Components for implementing WebDAV ([RFC 4918]) servers.
+//!
+//! This module provides the matchers for the WebDAV methods, the extractors
+//! for the `Depth`, `Destination` and `Overwrite` headers, and the builder
+//! of the `207 Multi-Status` responses.
+//!
+//! [RFC 4918]: https://tools.ietf.org/html/rfc4918
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::endpoints::webdav::{self, Depth, Multistatus, PropStat};
+//! # use finchers::test;
+//! # use http::{Request, StatusCode};
+//! let endpoint = webdav::propfind()
+//!     .and(webdav::depth())
+//!     .map(|depth: Depth| {
+//!         let mut multistatus = Multistatus::new();
+//!         multistatus.response(
+//!             "/files/",
+//!             vec![PropStat::new(StatusCode::OK)
+//!                 .prop("displayname", "files")
+//!                 .prop_xml("resourcetype", "<D:collection/>")],
+//!         );
+//!         if depth != Depth::Zero {
+//!             multistatus.status("/files/secret.txt", StatusCode::FORBIDDEN);
+//!         }
+//!         multistatus
+//!     });
+//!
+//! let mut runner = test::runner(endpoint);
+//! let response = runner
+//!     .perform(Request::builder().method("PROPFIND").uri("/files/").header("depth", "1"))
+//!     .unwrap();
+//! assert_eq!(response.status().as_u16(), 207);
+//! assert!(response.to_utf8_lossy().contains("<D:href>/files/secret.txt</D:href>"));
+//! ```
+
+use {
+    crate::{
+        action::{
+            Oneshot,
+            OneshotAction,
+            PreflightContext, //
+        },
+        endpoint::{syntax::verb, Endpoint, IsEndpoint},
+        endpoints::header::FromHeaderValue,
+        error::{self, Error},
+        output::IntoResponse,
+    },
+    http::{
+        header::{self, HeaderName, HeaderValue},
+        Request, Response, StatusCode, Uri,
+    },
+    std::{fmt, fmt::Write, marker::PhantomData, str::FromStr},
+};
+
+// ==== Methods ====
+
+macro_rules! define_webdav_verbs {
+    ($( $(#[$m:meta])* $name:ident => $METHOD:expr, )*) => {$(
+        $(#[$m])*
+        #[inline]
+        pub fn $name() -> verb::MatchCustomVerb {
+            verb::custom($METHOD)
+        }
+    )*};
+}
+
+define_webdav_verbs! {
+    /// Create an endpoint which matches the `PROPFIND` requests.
+    propfind => "PROPFIND",
+    /// Create an endpoint which matches the `PROPPATCH` requests.
+    proppatch => "PROPPATCH",
+    /// Create an endpoint which matches the `MKCOL` requests.
+    mkcol => "MKCOL",
+    /// Create an endpoint which matches the `COPY` requests.
+    copy => "COPY",
+    /// Create an endpoint which matches the `MOVE` requests.
+    move_ => "MOVE",
+    /// Create an endpoint which matches the `LOCK` requests.
+    lock => "LOCK",
+    /// Create an endpoint which matches the `UNLOCK` requests.
+    unlock => "UNLOCK",
+}
+
+// ==== Headers ====
+
+/// The value of `Depth` header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Depth {
+    /// `Depth: 0`
+    Zero,
+    /// `Depth: 1`
+    One,
+    /// `Depth: infinity`
+    Infinity,
+}
+
+impl FromStr for Depth {
+    type Err = InvalidHeader;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "0" => Ok(Depth::Zero),
+            "1" => Ok(Depth::One),
+            s if s.eq_ignore_ascii_case("infinity") => Ok(Depth::Infinity),
+            _ => Err(InvalidHeader("Depth")),
+        }
+    }
+}
+
+impl FromHeaderValue for Depth {
+    type Error = InvalidHeader;
+
+    fn from_header_value(value: &HeaderValue) -> Result<Self, Self::Error> {
+        value.to_str().map_err(|_| InvalidHeader("Depth"))?.parse()
+    }
+}
+
+/// The value of `Overwrite` header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Overwrite(pub bool);
+
+impl FromHeaderValue for Overwrite {
+    type Error = InvalidHeader;
+
+    fn from_header_value(value: &HeaderValue) -> Result<Self, Self::Error> {
+        match value.as_bytes() {
+            b"T" | b"t" => Ok(Overwrite(true)),
+            b"F" | b"f" => Ok(Overwrite(false)),
+            _ => Err(InvalidHeader("Overwrite")),
+        }
+    }
+}
+
+/// The value of `Destination` header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Destination(pub Uri);
+
+impl Destination {
+    /// Returns the path component of the destination URI.
+    pub fn path(&self) -> &str {
+        self.0.path()
+    }
+}
+
+impl FromHeaderValue for Destination {
+    type Error = InvalidHeader;
+
+    fn from_header_value(value: &HeaderValue) -> Result<Self, Self::Error> {
+        Uri::from_shared(value.as_bytes().into())
+            .map(Destination)
+            .map_err(|_| InvalidHeader("Destination"))
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, failure::Fail)]
+#[fail(display = "invalid header: `{}'", _0)]
+pub struct InvalidHeader(&'static str);
+
+/// Create an endpoint which extracts the value of `Depth` header.
+///
+/// If the header is missing, `Depth::Infinity` is returned as specified in RFC 4918.
+pub fn depth() -> ParseHeader<Depth> {
+    ParseHeader::new("depth", Some(Depth::Infinity))
+}
+
+/// Create an endpoint which extracts the value of `Overwrite` header.
+///
+/// If the header is missing, `Overwrite(true)` is returned as specified in RFC 4918.
+pub fn overwrite() -> ParseHeader<Overwrite> {
+    ParseHeader::new("overwrite", Some(Overwrite(true)))
+}
+
+/// Create an endpoint which extracts the value of `Destination` header.
+///
+/// This endpoint reports `400 Bad Request` if the header is missing.
+pub fn destination() -> ParseHeader<Destination> {
+    ParseHeader::new("destination", None)
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct ParseHeader<T> {
+    name: HeaderName,
+    default: Option<T>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> ParseHeader<T> {
+    fn new(name: &'static str, default: Option<T>) -> Self {
+        ParseHeader {
+            name: HeaderName::from_static(name),
+            default,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Clone> Clone for ParseHeader<T> {
+    fn clone(&self) -> Self {
+        ParseHeader {
+            name: self.name.clone(),
+            default: self.default.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+mod parse_header {
+    use super::*;
+
+    impl<T: FromHeaderValue> IsEndpoint for ParseHeader<T> {}
+
+    impl<T, Bd> Endpoint<Bd> for ParseHeader<T>
+    where
+        T: FromHeaderValue + Clone,
+    {
+        type Output = (T,);
+        type Action = Oneshot<ParseHeaderAction<T>>;
+
+        fn action(&self) -> Self::Action {
+            ParseHeaderAction {
+                name: self.name.clone(),
+                default: self.default.clone(),
+            }
+            .into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct ParseHeaderAction<T> {
+        name: HeaderName,
+        default: Option<T>,
+    }
+
+    impl<T> OneshotAction for ParseHeaderAction<T>
+    where
+        T: FromHeaderValue,
+    {
+        type Output = (T,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            match (cx.headers().get(&self.name), self.default) {
+                (Some(value), _) => T::from_header_value(value)
+                    .map(|parsed| (parsed,))
+                    .map_err(error::bad_request),
+                (None, Some(default)) => Ok((default,)),
+                (None, None) => Err(error::bad_request(format!(
+                    "missing header: `{}'",
+                    self.name.as_str()
+                ))),
+            }
+        }
+    }
+}
+
+// ==== Multistatus ====
+
+/// A builder of the `207 Multi-Status` response.
+///
+/// The elements are written in the `DAV:` namespace with the prefix `D:`.
+/// The other prefixes used by the properties must be declared by `namespace`.
+#[derive(Debug, Clone, Default)]
+pub struct Multistatus {
+    namespaces: Vec<(String, String)>,
+    responses: Vec<MultistatusResponse>,
+    description: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct MultistatusResponse {
+    href: String,
+    body: ResponseBody,
+}
+
+#[derive(Debug, Clone)]
+enum ResponseBody {
+    Status(StatusCode),
+    PropStats(Vec<PropStat>),
+}
+
+impl Multistatus {
+    /// Creates an empty `Multistatus`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares the namespace with the specified prefix on the root element,
+    /// so that it can be used in the names of the properties such as `Z:author`.
+    ///
+    /// # Panics
+    /// This method panics if the prefix is `D` or is not a valid name.
+    pub fn namespace(&mut self, prefix: impl Into<String>, uri: impl Into<String>) -> &mut Self {
+        let prefix = prefix.into();
+        assert!(
+            is_ncname(&prefix) && prefix != "D",
+            "invalid namespace prefix: {}",
+            prefix
+        );
+        self.namespaces.retain(|(p, _)| *p != prefix);
+        self.namespaces.push((prefix, uri.into()));
+        self
+    }
+
+    /// Appends a response element which contains the properties of the resource.
+    pub fn response(&mut self, href: impl Into<String>, propstats: Vec<PropStat>) -> &mut Self {
+        self.responses.push(MultistatusResponse {
+            href: href.into(),
+            body: ResponseBody::PropStats(propstats),
+        });
+        self
+    }
+
+    /// Appends a response element which contains only the status of the resource.
+    pub fn status(&mut self, href: impl Into<String>, status: StatusCode) -> &mut Self {
+        self.responses.push(MultistatusResponse {
+            href: href.into(),
+            body: ResponseBody::Status(status),
+        });
+        self
+    }
+
+    /// Sets the description of the whole response.
+    pub fn description(&mut self, description: impl Into<String>) -> &mut Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Returns the XML representation of this value.
+    ///
+    /// # Panics
+    /// This method panics if a property uses the prefix which has not been
+    /// declared by `namespace`.
+    pub fn to_xml(&self) -> String {
+        for response in &self.responses {
+            if let ResponseBody::PropStats(ref propstats) = response.body {
+                for (name, _) in propstats.iter().flat_map(|propstat| &propstat.props) {
+                    if let Some(pos) = name.find(':') {
+                        let prefix = &name[..pos];
+                        assert!(
+                            prefix == "D" || self.namespaces.iter().any(|(p, _)| p == prefix),
+                            "undeclared namespace prefix: {}",
+                            prefix
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut xml = String::new();
+        xml.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push_str(r#"<D:multistatus xmlns:D="DAV:""#);
+        for (prefix, uri) in &self.namespaces {
+            let _ = write!(xml, r#" xmlns:{}="{}""#, prefix, Escaped(uri));
+        }
+        xml.push('>');
+        for response in &self.responses {
+            xml.push_str("<D:response>");
+            let _ = write!(xml, "<D:href>{}</D:href>", Escaped(&response.href));
+            match response.body {
+                ResponseBody::Status(status) => write_status(&mut xml, status),
+                ResponseBody::PropStats(ref propstats) => {
+                    for propstat in propstats {
+                        propstat.write_xml(&mut xml);
+                    }
+                }
+            }
+            xml.push_str("</D:response>");
+        }
+        if let Some(ref description) = self.description {
+            let _ = write!(
+                xml,
+                "<D:responsedescription>{}</D:responsedescription>",
+                Escaped(description)
+            );
+        }
+        xml.push_str("</D:multistatus>");
+        xml
+    }
+}
+
+impl IntoResponse for Multistatus {
+    type Body = String;
+
+    fn into_response(self, _: &Request<()>) -> Response<Self::Body> {
+        let mut response = Response::new(self.to_xml());
+        *response.status_mut() = StatusCode::MULTI_STATUS;
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/xml; charset=utf-8"),
+        );
+        response
+    }
+}
+
+/// A set of the properties which share the same status, used in `Multistatus`.
+///
+/// The names of the properties without a prefix are regarded as the ones
+/// in the `DAV:` namespace.
+///
+/// # Panics
+/// The methods appending a property panic if the name is not a valid
+/// (optionally prefixed) XML name.
+#[derive(Debug, Clone)]
+pub struct PropStat {
+    status: StatusCode,
+    props: Vec<(String, Option<PropValue>)>,
+}
+
+#[derive(Debug, Clone)]
+enum PropValue {
+    Text(String),
+    Xml(String),
+}
+
+impl PropStat {
+    /// Creates an empty `PropStat` with the specified status.
+    pub fn new(status: StatusCode) -> Self {
+        PropStat {
+            status,
+            props: vec![],
+        }
+    }
+
+    /// Appends a property with the specified text value, which is escaped.
+    pub fn prop(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.props
+            .push((prop_name(name), Some(PropValue::Text(value.into()))));
+        self
+    }
+
+    /// Appends a property with the specified XML fragment, which is written as it is.
+    pub fn prop_xml(mut self, name: impl Into<String>, xml: impl Into<String>) -> Self {
+        self.props
+            .push((prop_name(name), Some(PropValue::Xml(xml.into()))));
+        self
+    }
+
+    /// Appends a property without the value, e.g. the one in the response
+    /// of `<D:propname/>` request or the one which was not found.
+    pub fn empty_prop(mut self, name: impl Into<String>) -> Self {
+        self.props.push((prop_name(name), None));
+        self
+    }
+
+    fn write_xml(&self, xml: &mut String) {
+        xml.push_str("<D:propstat><D:prop>");
+        for (name, value) in &self.props {
+            let name = if name.contains(':') {
+                name.clone()
+            } else {
+                format!("D:{}", name)
+            };
+            let _ = match value {
+                Some(PropValue::Text(text)) => write!(xml, "<{0}>{1}</{0}>", name, Escaped(text)),
+                Some(PropValue::Xml(fragment)) => write!(xml, "<{0}>{1}</{0}>", name, fragment),
+                None => write!(xml, "<{}/>", name),
+            };
+        }
+        xml.push_str("</D:prop>");
+        write_status(xml, self.status);
+        xml.push_str("</D:propstat>");
+    }
+}
+
+fn prop_name(name: impl Into<String>) -> String {
+    let name = name.into();
+    let valid = match name.find(':') {
+        Some(pos) => is_ncname(&name[..pos]) && is_ncname(&name[pos + 1..]),
+        None => is_ncname(&name),
+    };
+    assert!(valid, "invalid property name: {}", name);
+    name
+}
+
+/// Returns whether `s` is a valid XML name without the colon.
+fn is_ncname(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+fn write_status(xml: &mut String, status: StatusCode) {
+    let _ = write!(
+        xml,
+        "<D:status>HTTP/1.1 {} {}</D:status>",
+        status.as_str(),
+        status.canonical_reason().unwrap_or("")
+    );
+}
+
+/// A wrapper which escapes the special characters in XML.
+///
+/// The characters which are not allowed in XML 1.0 even as the character
+/// references (e.g. `U+0000`) are replaced with `U+FFFD`.
+struct Escaped<'a>(&'a str);
+
+impl<'a> fmt::Display for Escaped<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '&' => f.write_str("&amp;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&apos;")?,
+                // Escaped so that they are not normalized by the XML parsers.
+                '\r' => f.write_str("&#xD;")?,
+                '\t' | '\n' => f.write_char(c)?,
+                '\u{0}'..='\u{1F}' | '\u{FFFE}' | '\u{FFFF}' => f.write_char('\u{FFFD}')?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,553 @@
+//! Endpoints for verifying the signatures of webhook requests.
+//!
+//! The endpoints in this module receive the entire request body and verify
+//! its HMAC signature before returning it, so that the handlers never see
+//! the unauthenticated payloads. The comparison of the signatures is performed
+//! in constant time. Presets are provided for the conventions of GitHub,
+//! Stripe and Slack.
+//!
+//! The request body larger than 1 MiB is rejected with `413 Payload Too Large`
+//! without being received entirely. The limit can be changed by
+//! `Verify::max_body_size`.
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::endpoints::webhook;
+//! #[derive(Debug, serde::Deserialize)]
+//! struct PushEvent {
+//!     #[serde(rename = "ref")]
+//!     git_ref: String,
+//! }
+//!
+//! let endpoint = webhook::github("my-secret")
+//!     .json::<PushEvent>()
+//!     .map(|_raw: Vec<u8>, event: PushEvent| format!("pushed to {}", event.git_ref));
+//! # drop(endpoint);
+//! ```
+
+use {
+    crate::{
+        action::{
+            ActionContext, //
+            EndpointAction,
+            Preflight,
+            PreflightContext,
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::{self, Error},
+    },
+    bytes::Buf,
+    futures::{Async, Poll},
+    hmac::{Hmac, Mac},
+    http::{
+        header::{self, HeaderName},
+        StatusCode,
+    },
+    izanami_util::buf_stream::BufStream,
+    serde::de::DeserializeOwned,
+    std::{
+        fmt,
+        marker::PhantomData,
+        sync::Arc,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// The hash algorithm used in the HMAC signatures.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Algorithm {
+    /// HMAC-SHA1.
+    Sha1,
+    /// HMAC-SHA256.
+    Sha256,
+}
+
+/// The convention of the signed content and the signature header.
+#[derive(Debug, Clone)]
+enum Scheme {
+    /// The signature of the body is stored in the header, with an optional prefix.
+    Plain { prefix: String },
+    /// `Stripe-Signature: t=<timestamp>,v1=<signature>`, signing `<timestamp>.<body>`.
+    Stripe,
+    /// `X-Slack-Signature: v0=<signature>` and `X-Slack-Request-Timestamp`,
+    /// signing `v0:<timestamp>:<body>`.
+    Slack,
+}
+
+/// The default value of the maximum size of the request body.
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+#[derive(Clone)]
+struct Config {
+    secret: Vec<u8>,
+    algorithm: Algorithm,
+    header: HeaderName,
+    scheme: Scheme,
+    tolerance: Option<Duration>,
+    max_body_size: usize,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("secret", &"[REDACTED]")
+            .field("algorithm", &self.algorithm)
+            .field("header", &self.header)
+            .field("scheme", &self.scheme)
+            .field("tolerance", &self.tolerance)
+            .field("max_body_size", &self.max_body_size)
+            .finish()
+    }
+}
+
+/// The signature and the content to be signed, extracted from the request headers.
+#[derive(Debug)]
+struct Signed {
+    signatures: Vec<Vec<u8>>,
+    prefix: Vec<u8>,
+}
+
+impl Config {
+    fn extract(&self, cx: &PreflightContext<'_>) -> Result<Signed, Error> {
+        let value = cx
+            .headers()
+            .get(&self.header)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| error::unauthorized(format!("missing header: `{}'", self.header)))?;
+        let invalid = || error::unauthorized(format!("invalid header: `{}'", self.header));
+
+        match self.scheme {
+            Scheme::Plain { ref prefix } => {
+                if !value.starts_with(&**prefix) {
+                    return Err(invalid());
+                }
+                let signature = decode_hex(&value[prefix.len()..]).ok_or_else(invalid)?;
+                Ok(Signed {
+                    signatures: vec![signature],
+                    prefix: vec![],
+                })
+            }
+            Scheme::Stripe => {
+                let mut timestamp = None;
+                let mut signatures = vec![];
+                for kv in value.split(',') {
+                    let mut kv = kv.trim().splitn(2, '=');
+                    match (kv.next(), kv.next()) {
+                        (Some("t"), Some(t)) => timestamp = Some(t),
+                        (Some("v1"), Some(sig)) => signatures.extend(decode_hex(sig)),
+                        _ => {}
+                    }
+                }
+                let timestamp = timestamp.ok_or_else(invalid)?;
+                self.check_timestamp(timestamp)?;
+                if signatures.is_empty() {
+                    return Err(invalid());
+                }
+                Ok(Signed {
+                    signatures,
+                    prefix: format!("{}.", timestamp).into_bytes(),
+                })
+            }
+            Scheme::Slack => {
+                if !value.starts_with("v0=") {
+                    return Err(invalid());
+                }
+                let signature = decode_hex(&value[3..]).ok_or_else(invalid)?;
+                let timestamp = cx
+                    .headers()
+                    .get("x-slack-request-timestamp")
+                    .and_then(|value| value.to_str().ok())
+                    .ok_or_else(|| {
+                        error::unauthorized("missing header: `x-slack-request-timestamp'")
+                    })?;
+                self.check_timestamp(timestamp)?;
+                Ok(Signed {
+                    signatures: vec![signature],
+                    prefix: format!("v0:{}:", timestamp).into_bytes(),
+                })
+            }
+        }
+    }
+
+    fn check_timestamp(&self, timestamp: &str) -> Result<(), Error> {
+        let tolerance = match self.tolerance {
+            Some(tolerance) => tolerance,
+            None => return Ok(()),
+        };
+        let timestamp = timestamp
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| error::unauthorized("invalid timestamp"))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let diff = if now > timestamp {
+            now - timestamp
+        } else {
+            timestamp - now
+        };
+        if diff > tolerance.as_secs() {
+            return Err(error::unauthorized("the timestamp is out of the tolerance"));
+        }
+        Ok(())
+    }
+
+    fn payload_too_large(&self) -> Error {
+        error::err_msg(
+            format!(
+                "the request body is larger than {} bytes",
+                self.max_body_size
+            ),
+            StatusCode::PAYLOAD_TOO_LARGE,
+        )
+    }
+
+    fn verify(&self, signed: &Signed, body: &[u8]) -> Result<(), Error> {
+        let verified = signed.signatures.iter().any(|signature| {
+            // `Mac::verify` compares the codes in constant time.
+            match self.algorithm {
+                Algorithm::Sha1 => {
+                    let mut mac = Hmac::<sha1::Sha1>::new_varkey(&self.secret)
+                        .expect("HMAC accepts keys of any length");
+                    mac.input(&signed.prefix);
+                    mac.input(body);
+                    mac.verify(signature).is_ok()
+                }
+                Algorithm::Sha256 => {
+                    let mut mac = Hmac::<sha2::Sha256>::new_varkey(&self.secret)
+                        .expect("HMAC accepts keys of any length");
+                    mac.input(&signed.prefix);
+                    mac.input(body);
+                    mac.verify(signature).is_ok()
+                }
+            }
+        });
+        if verified {
+            Ok(())
+        } else {
+            Err(error::unauthorized("signature mismatch"))
+        }
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [hi, lo] => Some((hex_digit(*hi)? << 4) | hex_digit(*lo)?),
+            _ => None,
+        })
+        .collect()
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Create an endpoint which verifies the HMAC signature of the request body,
+/// stored in the specified header as a hex string.
+///
+/// # Panics
+/// This function panics if the specified name is not a valid header name.
+pub fn hmac(algorithm: Algorithm, secret: impl AsRef<[u8]>, header: &str) -> Verify {
+    Verify::new(Config {
+        secret: secret.as_ref().to_owned(),
+        algorithm,
+        header: HeaderName::from_bytes(header.as_bytes()).expect("invalid header name"),
+        scheme: Scheme::Plain {
+            prefix: String::new(),
+        },
+        tolerance: None,
+        max_body_size: DEFAULT_MAX_BODY_SIZE,
+    })
+}
+
+/// Create an endpoint which verifies the webhook requests from GitHub,
+/// signed with HMAC-SHA256 in `X-Hub-Signature-256`.
+pub fn github(secret: impl AsRef<[u8]>) -> Verify {
+    hmac(Algorithm::Sha256, secret, "x-hub-signature-256").prefix("sha256=")
+}
+
+/// Create an endpoint which verifies the webhook requests from Stripe,
+/// signed in `Stripe-Signature`.
+///
+/// The requests whose timestamp differs from the current time by more than
+/// 5 minutes are rejected.
+pub fn stripe(secret: impl AsRef<[u8]>) -> Verify {
+    Verify::new(Config {
+        secret: secret.as_ref().to_owned(),
+        algorithm: Algorithm::Sha256,
+        header: HeaderName::from_static("stripe-signature"),
+        scheme: Scheme::Stripe,
+        tolerance: Some(Duration::from_secs(300)),
+        max_body_size: DEFAULT_MAX_BODY_SIZE,
+    })
+}
+
+/// Create an endpoint which verifies the requests from Slack, signed in
+/// `X-Slack-Signature` with `X-Slack-Request-Timestamp`.
+///
+/// The requests whose timestamp differs from the current time by more than
+/// 5 minutes are rejected.
+pub fn slack(signing_secret: impl AsRef<[u8]>) -> Verify {
+    Verify::new(Config {
+        secret: signing_secret.as_ref().to_owned(),
+        algorithm: Algorithm::Sha256,
+        header: HeaderName::from_static("x-slack-signature"),
+        scheme: Scheme::Slack,
+        tolerance: Some(Duration::from_secs(300)),
+        max_body_size: DEFAULT_MAX_BODY_SIZE,
+    })
+}
+
+// ==== Verify ====
+
+/// An endpoint which verifies the signature and returns the raw request body.
+///
+/// The errors are reported as `401 Unauthorized`.
+#[derive(Clone)]
+pub struct Verify {
+    config: Arc<Config>,
+}
+
+impl fmt::Debug for Verify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The secret is intentionally omitted.
+        f.debug_struct("Verify")
+            .field("algorithm", &self.config.algorithm)
+            .field("header", &self.config.header)
+            .field("max_body_size", &self.config.max_body_size)
+            .finish()
+    }
+}
+
+impl Verify {
+    fn new(config: Config) -> Self {
+        Verify {
+            config: Arc::new(config),
+        }
+    }
+
+    fn configure(self, f: impl FnOnce(&mut Config)) -> Self {
+        let mut config = Arc::try_unwrap(self.config).unwrap_or_else(|config| (*config).clone());
+        f(&mut config);
+        Verify::new(config)
+    }
+
+    /// Sets the prefix of the header value preceding the signature, such as `sha256=`.
+    ///
+    /// This setting has no effect on the presets for Stripe and Slack.
+    pub fn prefix(self, prefix: &str) -> Self {
+        self.configure(|config| {
+            if let Scheme::Plain { .. } = config.scheme {
+                config.scheme = Scheme::Plain {
+                    prefix: prefix.into(),
+                };
+            }
+        })
+    }
+
+    /// Sets the tolerance of the timestamp, or disables the check if `None`
+    /// is given.
+    ///
+    /// This setting has an effect only on the schemes which sign the
+    /// timestamps, i.e. Stripe and Slack.
+    pub fn tolerance(self, tolerance: Option<Duration>) -> Self {
+        self.configure(|config| config.tolerance = tolerance)
+    }
+
+    /// Sets the maximum size of the request body in bytes.
+    ///
+    /// The default value is 1 MiB.
+    pub fn max_body_size(self, max_body_size: usize) -> Self {
+        self.configure(|config| config.max_body_size = max_body_size)
+    }
+
+    /// Converts this endpoint into the one which additionally parses the
+    /// verified body as JSON.
+    pub fn json<T>(self) -> VerifyJson<T>
+    where
+        T: DeserializeOwned,
+    {
+        VerifyJson {
+            verify: self,
+            _marker: PhantomData,
+        }
+    }
+}
+
+mod verify {
+    use super::*;
+
+    impl IsEndpoint for Verify {}
+
+    impl<Bd> Endpoint<Bd> for Verify
+    where
+        Bd: BufStream,
+        Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        type Output = (Vec<u8>,);
+        type Action = VerifyAction<Bd>;
+
+        fn action(&self) -> Self::Action {
+            VerifyAction {
+                config: self.config.clone(),
+                signed: None,
+                state: State::Start,
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct VerifyAction<Bd> {
+        config: Arc<Config>,
+        signed: Option<Signed>,
+        state: State<Bd>,
+    }
+
+    enum State<Bd> {
+        Start,
+        Receiving(Bd, Vec<u8>),
+    }
+
+    impl<Bd> EndpointAction<Bd> for VerifyAction<Bd>
+    where
+        Bd: BufStream,
+        Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        type Output = (Vec<u8>,);
+
+        fn preflight(
+            &mut self,
+            cx: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            // The headers are checked before receiving the body.
+            let content_length = cx
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok());
+            if let Some(content_length) = content_length {
+                if content_length > self.config.max_body_size as u64 {
+                    return Err(self.config.payload_too_large());
+                }
+            }
+            self.signed = Some(self.config.extract(cx)?);
+            Ok(Preflight::Incomplete)
+        }
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            loop {
+                self.state = match self.state {
+                    State::Start => State::Receiving(cx.take_body()?, Vec::new()),
+                    State::Receiving(ref mut body, ref mut buf) => {
+                        while let Some(mut data) = futures::try_ready!(body
+                            .poll_buf()
+                            .map_err(|e| failure::Error::from_boxed_compat(e.into())))
+                        {
+                            while data.has_remaining() {
+                                let n = {
+                                    let chunk = data.bytes();
+                                    if buf.len() + chunk.len() > self.config.max_body_size {
+                                        return Err(self.config.payload_too_large());
+                                    }
+                                    buf.extend_from_slice(chunk);
+                                    chunk.len()
+                                };
+                                data.advance(n);
+                            }
+                        }
+                        let body = std::mem::replace(buf, Vec::new());
+                        let signed = self
+                            .signed
+                            .take()
+                            .expect("the action has already been polled");
+                        self.config.verify(&signed, &body)?;
+                        return Ok(Async::Ready((body,)));
+                    }
+                };
+            }
+        }
+    }
+}
+
+// ==== VerifyJson ====
+
+/// An endpoint which verifies the signature and returns the raw request body
+/// along with the parsed JSON payload.
+///
+/// The payload which cannot be parsed is reported as `400 Bad Request`.
+pub struct VerifyJson<T> {
+    verify: Verify,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for VerifyJson<T> {
+    fn clone(&self) -> Self {
+        VerifyJson {
+            verify: self.verify.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for VerifyJson<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerifyJson")
+            .field("verify", &self.verify)
+            .finish()
+    }
+}
+
+mod verify_json {
+    use super::*;
+
+    impl<T: DeserializeOwned> IsEndpoint for VerifyJson<T> {}
+
+    impl<T, Bd> Endpoint<Bd> for VerifyJson<T>
+    where
+        T: DeserializeOwned,
+        Bd: BufStream,
+        Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        type Output = (Vec<u8>, T);
+        type Action = VerifyJsonAction<<Verify as Endpoint<Bd>>::Action, T>;
+
+        fn action(&self) -> Self::Action {
+            VerifyJsonAction {
+                verify: self.verify.action(),
+                _marker: PhantomData,
+            }
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct VerifyJsonAction<A, T> {
+        verify: A,
+        _marker: PhantomData<fn() -> T>,
+    }
+
+    impl<A, T, Bd> EndpointAction<Bd> for VerifyJsonAction<A, T>
+    where
+        A: EndpointAction<Bd, Output = (Vec<u8>,)>,
+        T: DeserializeOwned,
+    {
+        type Output = (Vec<u8>, T);
+
+        fn preflight(
+            &mut self,
+            cx: &mut PreflightContext<'_>,
+        ) -> Result<Preflight<Self::Output>, Error> {
+            self.verify.preflight(cx).map(|_| Preflight::Incomplete)
+        }
+
+        fn poll_action(&mut self, cx: &mut ActionContext<'_, Bd>) -> Poll<Self::Output, Error> {
+            let (body,) = futures::try_ready!(self.verify.poll_action(cx));
+            let payload = serde_json::from_slice(&body).map_err(error::bad_request)?;
+            Ok(Async::Ready((body, payload)))
+        }
+    }
+}
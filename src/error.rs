@@ -3,7 +3,7 @@
 use {
     crate::{output::IntoResponse, util::Never},
     failure::{AsFail, Fail},
-    http::{Request, Response, StatusCode},
+    http::{HeaderMap, Request, Response, StatusCode},
     std::{any::TypeId, fmt, io},
 };
 
@@ -177,6 +177,7 @@ define_error_fn! {
     forbidden => FORBIDDEN,
     not_found => NOT_FOUND,
     method_not_allowed => METHOD_NOT_ALLOWED,
+    unsupported_media_type => UNSUPPORTED_MEDIA_TYPE,
     internal_server_error => INTERNAL_SERVER_ERROR,
 }
 
@@ -230,6 +231,7 @@ where
 #[derive(Debug)]
 pub struct Error {
     inner: Box<dyn HttpError>,
+    headers: Option<Box<HeaderMap>>,
 }
 
 impl AsRef<dyn HttpError> for Error {
@@ -286,23 +288,39 @@ impl Error {
     {
         Self {
             inner: Box::new(err),
+            headers: None,
         }
     }
 
     /// Attempts to downcast the boxed value to a conrete type.
+    ///
+    /// Note that the headers added by `headers_mut` are dropped on success.
     pub fn downcast<T: HttpError>(self) -> Result<T> {
+        let headers = self.headers;
         self.inner
             .downcast::<T>()
             .map(|e| *e)
-            .map_err(|inner| Self { inner })
+            .map_err(|inner| Self { inner, headers })
+    }
+
+    /// Returns a mutable reference to the headers added to the response
+    /// created from this error.
+    ///
+    /// The headers are set after `HttpError::to_response` is called, and
+    /// replace the values with the same name.
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.headers.get_or_insert_with(Default::default)
     }
 
     #[allow(missing_docs)]
-    pub fn into_response_with<F, T>(self, request: &Request<()>, f: F) -> Response<T>
+    pub fn into_response_with<F, T>(mut self, request: &Request<()>, f: F) -> Response<T>
     where
         F: Fn(&Self, &Request<()>, &mut Response<()>) -> T,
     {
         let mut response = self.inner.to_response(request);
+        if let Some(headers) = self.headers.take() {
+            response.headers_mut().extend(*headers);
+        }
         let body = f(&self, request, &mut response);
         response.extensions_mut().insert(self);
         response.map(|_| body)
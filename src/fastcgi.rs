@@ -0,0 +1,678 @@
+//! A FastCGI front-end for running the services behind a web server like nginx.
+//!
+//! `FastCgi` maps the parameters and the standard input of the FastCGI
+//! requests into `Request<RequestBody>`, so that the services can be deployed
+//! without an extra reverse-proxied TCP hop. It is served by the same launcher
+//! as the HTTP listeners, using `Server::bind_with`.
+//! The plain CGI invocation is also supported by the submodule `cgi`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use finchers::prelude::*;
+//! # use finchers::endpoint::syntax::path;
+//! # use finchers::fastcgi::FastCgi;
+//! let endpoint = path!(@get "/").map(|| "Hello, world!");
+//!
+//! finchers::server::start(endpoint)
+//!     .bind_with("127.0.0.1:9000", FastCgi::new())
+//!     .serve()
+//!     .expect("failed to start the server");
+//! ```
+//!
+//! Note that the multiplexing of requests over a connection is not supported.
+
+pub mod cgi;
+mod record;
+
+use {
+    self::record::{Record, RecordCodec},
+    crate::server::{error_response, Connection, RequestBody, RequestSource},
+    bytes::{Bytes, BytesMut},
+    futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream},
+    http::{
+        header::{HeaderName, HeaderValue},
+        Method, Request, Response, StatusCode, Version,
+    },
+    std::{collections::VecDeque, io},
+    tokio::{
+        codec::Framed,
+        io::{AsyncRead, AsyncWrite},
+    },
+};
+
+/// The default value of `FastCgi::max_params_size`.
+const DEFAULT_MAX_PARAMS_SIZE: usize = 64 * 1024;
+
+/// The default value of `FastCgi::max_body_size`.
+const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Constructs an HTTP request from the CGI meta-variables and the standard input.
+fn build_request<I, K, V>(params: I, body: Bytes) -> Result<Request<RequestBody>, failure::Error>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    let mut request = Request::new(RequestBody::from(body));
+    let mut request_uri = None;
+    let mut script_name = String::new();
+    let mut path_info = String::new();
+    let mut query_string = String::new();
+
+    for (name, value) in params {
+        let (name, value) = (name.as_ref(), value.as_ref());
+        match name {
+            b"REQUEST_METHOD" => *request.method_mut() = Method::from_bytes(value)?,
+            b"REQUEST_URI" => request_uri = Some(String::from_utf8(value.to_owned())?),
+            b"SCRIPT_NAME" => script_name = String::from_utf8(value.to_owned())?,
+            b"PATH_INFO" => path_info = String::from_utf8(value.to_owned())?,
+            b"QUERY_STRING" => query_string = String::from_utf8(value.to_owned())?,
+            b"SERVER_PROTOCOL" => {
+                *request.version_mut() = match value {
+                    b"HTTP/0.9" => Version::HTTP_09,
+                    b"HTTP/1.0" => Version::HTTP_10,
+                    b"HTTP/2.0" | b"HTTP/2" => Version::HTTP_2,
+                    _ => Version::HTTP_11,
+                }
+            }
+            b"CONTENT_TYPE" | b"CONTENT_LENGTH" if !value.is_empty() => {
+                let name = name.to_ascii_lowercase().replace_underscores();
+                request.headers_mut().insert(
+                    HeaderName::from_bytes(&name)?,
+                    HeaderValue::from_bytes(value)?,
+                );
+            }
+            name if name.starts_with(b"HTTP_") => {
+                let name = name[5..].to_ascii_lowercase().replace_underscores();
+                request.headers_mut().append(
+                    HeaderName::from_bytes(&name)?,
+                    HeaderValue::from_bytes(value)?,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    *request.uri_mut() = match request_uri {
+        Some(uri) => uri.parse()?,
+        None => {
+            let mut uri = script_name;
+            uri += &path_info;
+            if uri.is_empty() {
+                uri.push('/');
+            }
+            if !query_string.is_empty() {
+                uri.push('?');
+                uri += &query_string;
+            }
+            uri.parse()?
+        }
+    };
+
+    Ok(request)
+}
+
+trait ReplaceUnderscores {
+    fn replace_underscores(self) -> Self;
+}
+
+impl ReplaceUnderscores for Vec<u8> {
+    fn replace_underscores(mut self) -> Self {
+        for b in &mut self {
+            if *b == b'_' {
+                *b = b'-';
+            }
+        }
+        self
+    }
+}
+
+/// Encodes an HTTP response into the format of CGI response.
+fn encode_response(response: Response<Bytes>) -> Bytes {
+    let (parts, body) = response.into_parts();
+
+    let mut buf = BytesMut::with_capacity(body.len() + 128);
+    buf.extend_from_slice(b"Status: ");
+    buf.extend_from_slice(parts.status.as_str().as_bytes());
+    if let Some(reason) = parts.status.canonical_reason() {
+        buf.extend_from_slice(b" ");
+        buf.extend_from_slice(reason.as_bytes());
+    }
+    buf.extend_from_slice(b"\r\n");
+    for (name, value) in &parts.headers {
+        buf.extend_from_slice(name.as_str().as_bytes());
+        buf.extend_from_slice(b": ");
+        buf.extend_from_slice(value.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf.extend_from_slice(b"\r\n");
+    buf.extend_from_slice(&body);
+    buf.freeze()
+}
+
+// ==== FastCgi ====
+
+/// A `RequestSource` which serves the FastCGI connections from the web server.
+///
+/// The parameters and the standard input of each request are buffered before
+/// the request is dispatched, and the requests exceeding the limits are
+/// rejected without being passed to the service.
+#[derive(Debug, Clone)]
+pub struct FastCgi {
+    max_params_size: usize,
+    max_body_size: usize,
+}
+
+impl Default for FastCgi {
+    fn default() -> Self {
+        FastCgi {
+            max_params_size: DEFAULT_MAX_PARAMS_SIZE,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+impl FastCgi {
+    /// Creates a `FastCgi` with the default configuration.
+    pub fn new() -> Self {
+        FastCgi::default()
+    }
+
+    /// Sets the maximum size of the encoded parameters of a request.
+    ///
+    /// The requests exceeding this limit are responded with
+    /// `431 Request Header Fields Too Large`. The default value is 64 KiB.
+    pub fn max_params_size(self, max: usize) -> Self {
+        FastCgi {
+            max_params_size: max,
+            ..self
+        }
+    }
+
+    /// Sets the maximum size of the standard input of a request.
+    ///
+    /// The requests exceeding this limit are responded with
+    /// `413 Payload Too Large`. The default value is 16 MiB.
+    pub fn max_body_size(self, max: usize) -> Self {
+        FastCgi {
+            max_body_size: max,
+            ..self
+        }
+    }
+}
+
+impl RequestSource for FastCgi {
+    type Transport = Transport<Connection>;
+
+    fn transport(&self, conn: Connection) -> Self::Transport {
+        Transport::new(conn, self.clone())
+    }
+}
+
+// ==== Transport ====
+
+/// A transport which exchanges the requests and the responses over
+/// a FastCGI connection.
+#[allow(missing_debug_implementations)]
+pub struct Transport<Io> {
+    framed: Framed<Io, RecordCodec>,
+    config: FastCgi,
+    incoming: Option<Incoming>,
+    in_flight: Option<u16>,
+    pending: VecDeque<Record>,
+    keep_conn: bool,
+    closing: bool,
+}
+
+#[allow(missing_debug_implementations)]
+struct Incoming {
+    request_id: u16,
+    params: Vec<u8>,
+    stdin: BytesMut,
+    rejected: Option<StatusCode>,
+}
+
+impl Incoming {
+    fn reject(&mut self, status: StatusCode) {
+        if self.rejected.is_none() {
+            self.rejected = Some(status);
+        }
+        self.params = Vec::new();
+        self.stdin = BytesMut::new();
+    }
+}
+
+impl<Io> Transport<Io>
+where
+    Io: AsyncRead + AsyncWrite,
+{
+    fn new(io: Io, config: FastCgi) -> Self {
+        Transport {
+            framed: Framed::new(io, RecordCodec::default()),
+            config,
+            incoming: None,
+            in_flight: None,
+            pending: VecDeque::new(),
+            keep_conn: false,
+            closing: false,
+        }
+    }
+
+    fn poll_write(&mut self) -> Poll<(), io::Error> {
+        while let Some(record) = self.pending.pop_front() {
+            if let AsyncSink::NotReady(record) = self.framed.start_send(record)? {
+                self.pending.push_front(record);
+                return Ok(Async::NotReady);
+            }
+        }
+        self.framed.poll_complete()
+    }
+
+    fn respond(&mut self, request_id: u16, response: Response<Bytes>) {
+        let data = encode_response(response);
+        self.pending
+            .extend(record::stream_records(record::STDOUT, request_id, data));
+        self.end_request(request_id, record::REQUEST_COMPLETE);
+    }
+
+    fn end_request(&mut self, request_id: u16, protocol_status: u8) {
+        self.pending
+            .push_back(Record::end_request(request_id, 0, protocol_status));
+        if !self.keep_conn {
+            self.closing = true;
+        }
+    }
+
+    fn on_record(&mut self, record: Record) -> io::Result<Option<Request<RequestBody>>> {
+        if record.request_id == 0 {
+            self.on_management_record(record)?;
+            return Ok(None);
+        }
+
+        let mut incoming = match self.incoming.take() {
+            Some(incoming) => {
+                if record.request_id != incoming.request_id {
+                    if record.kind == record::BEGIN_REQUEST {
+                        self.pending.push_back(Record::end_request(
+                            record.request_id,
+                            0,
+                            record::CANT_MPX_CONN,
+                        ));
+                    }
+                    self.incoming = Some(incoming);
+                    return Ok(None);
+                }
+                incoming
+            }
+            None if record.kind == record::BEGIN_REQUEST => {
+                let content = &record.content[..];
+                if content.len() < 3 {
+                    return Err(invalid_data("malformed FCGI_BEGIN_REQUEST record"));
+                }
+                let role = u16::from(content[0]) << 8 | u16::from(content[1]);
+                if role != record::ROLE_RESPONDER {
+                    self.pending.push_back(Record::end_request(
+                        record.request_id,
+                        0,
+                        record::UNKNOWN_ROLE,
+                    ));
+                    return Ok(None);
+                }
+                self.keep_conn = content[2] & record::FLAG_KEEP_CONN != 0;
+                self.incoming = Some(Incoming {
+                    request_id: record.request_id,
+                    params: vec![],
+                    stdin: BytesMut::new(),
+                    rejected: None,
+                });
+                return Ok(None);
+            }
+            None => return Ok(None),
+        };
+
+        match record.kind {
+            record::PARAMS => {
+                if incoming.params.len() + record.content.len() > self.config.max_params_size {
+                    incoming.reject(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+                } else if incoming.rejected.is_none() {
+                    incoming.params.extend_from_slice(&record.content);
+                }
+            }
+            record::STDIN if !record.content.is_empty() => {
+                if incoming.stdin.len() + record.content.len() > self.config.max_body_size {
+                    incoming.reject(StatusCode::PAYLOAD_TOO_LARGE);
+                } else if incoming.rejected.is_none() {
+                    incoming.stdin.extend_from_slice(&record.content);
+                }
+            }
+            record::STDIN => {
+                let request_id = incoming.request_id;
+                if let Some(status) = incoming.rejected {
+                    log::debug!("the request has been rejected: {}", status);
+                    self.respond(request_id, error_response(status));
+                    return Ok(None);
+                }
+                let params = record::parse_params(&incoming.params)?;
+                return Ok(match build_request(params, incoming.stdin.freeze()) {
+                    Ok(request) => {
+                        self.in_flight = Some(request_id);
+                        Some(request)
+                    }
+                    Err(err) => {
+                        log::debug!("failed to construct the request: {}", err);
+                        self.respond(request_id, error_response(StatusCode::BAD_REQUEST));
+                        None
+                    }
+                });
+            }
+            record::ABORT_REQUEST => {
+                self.end_request(incoming.request_id, record::REQUEST_COMPLETE);
+                return Ok(None);
+            }
+            record::DATA => {}
+            kind => self.pending.push_back(Record::unknown_type(kind)),
+        }
+
+        self.incoming = Some(incoming);
+        Ok(None)
+    }
+
+    fn on_management_record(&mut self, record: Record) -> io::Result<()> {
+        match record.kind {
+            record::GET_VALUES => {
+                let names = record::parse_params(&record.content)?;
+                let values = names.iter().filter_map(|(name, _)| {
+                    let value: &[u8] = match &name[..] {
+                        b"FCGI_MAX_CONNS" => b"1024",
+                        b"FCGI_MAX_REQS" => b"1024",
+                        b"FCGI_MPXS_CONNS" => b"0",
+                        _ => return None,
+                    };
+                    Some((&name[..], value))
+                });
+                let content = record::encode_params(values);
+                self.pending
+                    .push_back(Record::new(record::GET_VALUES_RESULT, 0, content));
+            }
+            kind => self.pending.push_back(Record::unknown_type(kind)),
+        }
+        Ok(())
+    }
+}
+
+impl<Io> Stream for Transport<Io>
+where
+    Io: AsyncRead + AsyncWrite,
+{
+    type Item = Request<RequestBody>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let flushed = self.poll_write()?.is_ready();
+            if self.closing {
+                return Ok(if flushed {
+                    Async::Ready(None)
+                } else {
+                    Async::NotReady
+                });
+            }
+            match futures::try_ready!(self.framed.poll()) {
+                Some(record) => {
+                    if let Some(request) = self.on_record(record)? {
+                        return Ok(Async::Ready(Some(request)));
+                    }
+                }
+                None => self.closing = true,
+            }
+        }
+    }
+}
+
+impl<Io> Sink for Transport<Io>
+where
+    Io: AsyncRead + AsyncWrite,
+{
+    type SinkItem = Response<Bytes>;
+    type SinkError = io::Error;
+
+    fn start_send(
+        &mut self,
+        response: Self::SinkItem,
+    ) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let request_id = self.in_flight.take().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no request is waiting for the response",
+            )
+        })?;
+        self.respond(request_id, response);
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.poll_write()
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        futures::try_ready!(self.poll_write());
+        self.framed.close()
+    }
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            record::{encode_params, stream_records},
+            *,
+        },
+        crate::{endpoint::syntax, endpoints, prelude::*, server},
+        futures::{future, sync::oneshot, Future},
+        std::{
+            io::{Read, Write},
+            net::TcpStream,
+            thread,
+        },
+        tokio::{
+            codec::{Decoder, Encoder},
+            runtime::current_thread::Runtime,
+        },
+    };
+
+    #[derive(Debug)]
+    struct MockIo {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockIo {
+        fn new(input: Vec<u8>) -> Self {
+            MockIo {
+                input: io::Cursor::new(input),
+                output: vec![],
+            }
+        }
+    }
+
+    impl io::Read for MockIo {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl io::Write for MockIo {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for MockIo {}
+
+    impl AsyncWrite for MockIo {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn encode_request(params: Vec<(&str, &str)>, stdin: &[u8]) -> Vec<u8> {
+        let mut records = vec![Record::new(
+            record::BEGIN_REQUEST,
+            1,
+            vec![0, 1, 0, 0, 0, 0, 0, 0],
+        )];
+        records.extend(stream_records(
+            record::PARAMS,
+            1,
+            encode_params(
+                params
+                    .iter()
+                    .map(|&(name, value)| (name.as_bytes(), value.as_bytes())),
+            )
+            .into(),
+        ));
+        records.extend(stream_records(record::STDIN, 1, Bytes::from(stdin)));
+
+        let mut codec = RecordCodec::default();
+        let mut input = BytesMut::new();
+        for record in records {
+            codec.encode(record, &mut input).unwrap();
+        }
+        input.to_vec()
+    }
+
+    /// Decodes the records sent to the web server, and returns the standard output.
+    fn decode_response(output: &[u8]) -> String {
+        let mut codec = RecordCodec::default();
+        let mut output = BytesMut::from(output);
+        let mut stdout = vec![];
+        let mut ended = false;
+        while let Some(record) = codec.decode(&mut output).unwrap() {
+            match record.kind {
+                record::STDOUT => stdout.extend_from_slice(&record.content),
+                record::END_REQUEST => ended = true,
+                kind => panic!("unexpected record type: {}", kind),
+            }
+        }
+        assert!(ended);
+        String::from_utf8(stdout).unwrap()
+    }
+
+    #[test]
+    fn test_build_request() {
+        let request = build_request(
+            vec![
+                ("REQUEST_METHOD", "POST"),
+                ("SCRIPT_NAME", "/app"),
+                ("PATH_INFO", "/posts"),
+                ("QUERY_STRING", "page=2"),
+                ("CONTENT_TYPE", "text/plain"),
+                ("HTTP_X_FORWARDED_FOR", "127.0.0.1"),
+            ],
+            Bytes::from("hello"),
+        )
+        .unwrap();
+        assert_eq!(request.method(), http::Method::POST);
+        assert_eq!(request.uri(), "/app/posts?page=2");
+        assert_eq!(request.headers()["content-type"], "text/plain");
+        assert_eq!(request.headers()["x-forwarded-for"], "127.0.0.1");
+    }
+
+    #[test]
+    fn test_transport() {
+        let input = encode_request(
+            vec![("REQUEST_METHOD", "POST"), ("REQUEST_URI", "/echo")],
+            b"hello",
+        );
+        let mut transport = Transport::new(MockIo::new(input), FastCgi::new());
+        let mut rt = Runtime::new().unwrap();
+
+        let request = rt
+            .block_on(future::poll_fn(|| transport.poll()))
+            .unwrap()
+            .expect("the request should be received");
+        assert_eq!(request.method(), http::Method::POST);
+        assert_eq!(request.uri(), "/echo");
+
+        assert!(transport
+            .start_send(Response::new(Bytes::from("hello")))
+            .unwrap()
+            .is_ready());
+        rt.block_on(future::poll_fn(|| transport.close())).unwrap();
+
+        let stdout = decode_response(&transport.framed.get_ref().output);
+        assert!(stdout.starts_with("Status: 200 OK\r\n"));
+        assert!(stdout.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_transport_limits() {
+        let long_value = "a".repeat(128);
+        let cases = vec![
+            (
+                FastCgi::new().max_params_size(64),
+                encode_request(vec![("HTTP_X_LONG", &long_value)], b""),
+                "Status: 431 Request Header Fields Too Large\r\n",
+            ),
+            (
+                FastCgi::new().max_body_size(4),
+                encode_request(vec![("REQUEST_METHOD", "POST")], b"hello"),
+                "Status: 413 Payload Too Large\r\n",
+            ),
+        ];
+
+        for (config, input, status) in cases {
+            let mut transport = Transport::new(MockIo::new(input), config);
+            let request = Runtime::new()
+                .unwrap()
+                .block_on(future::poll_fn(|| transport.poll()))
+                .unwrap();
+            assert!(request.is_none());
+
+            let stdout = decode_response(&transport.framed.get_ref().output);
+            assert!(stdout.starts_with(status), "{}", stdout);
+        }
+    }
+
+    #[test]
+    fn test_bind_with() {
+        let endpoint = syntax::segment("echo")
+            .and(endpoints::body::text())
+            .map(|body: String| format!("echo: {}", body));
+
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = server::start(endpoint).bind_with("127.0.0.1:0", FastCgi::new());
+        let addr = server.local_addrs().next().unwrap();
+        let handle = thread::spawn(move || {
+            server
+                .serve_with_graceful_shutdown(rx_shutdown.map_err(|_| ()))
+                .unwrap();
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(&encode_request(
+                vec![("REQUEST_METHOD", "POST"), ("REQUEST_URI", "/echo")],
+                b"hello",
+            ))
+            .unwrap();
+        let mut output = vec![];
+        stream.read_to_end(&mut output).unwrap();
+
+        let stdout = decode_response(&output);
+        assert!(stdout.starts_with("Status: 200 OK\r\n"));
+        assert!(stdout.ends_with("\r\n\r\necho: hello"));
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+//! Support for the plain CGI invocation.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use finchers::prelude::*;
+//! # use finchers::endpoint::syntax::path;
+//! # fn main() -> std::io::Result<()> {
+//! let endpoint = path!(@get "/").map(|| "Hello, world!");
+//!
+//! finchers::fastcgi::cgi::run(endpoint.into_service())
+//! # }
+//! ```
+
+use {
+    super::{build_request, encode_response, DEFAULT_MAX_BODY_SIZE},
+    crate::server::{error_response, ReadAll, RequestBody},
+    futures::{future, Future},
+    http::{Request, Response, StatusCode},
+    izanami_service::{MakeService, Service},
+    izanami_util::buf_stream::BufStream,
+    std::{
+        error::Error as StdError,
+        io::{self, Read, Write},
+    },
+    tokio::runtime::current_thread::Runtime,
+};
+
+type CritError = Box<dyn StdError + Send + Sync + 'static>;
+
+/// Handles a CGI request using the specified `MakeService`.
+///
+/// The request is constructed from the environment variables and the standard
+/// input of the current process, and the response is written to the standard output.
+/// The requests whose body exceeds 16 MiB are responded with `413 Payload Too Large`.
+pub fn run<S, Bd>(make_service: S) -> io::Result<()>
+where
+    S: MakeService<(), Request<RequestBody>, Response = Response<Bd>>,
+    S::Error: Into<CritError>,
+    S::MakeError: Into<CritError>,
+    Bd: BufStream,
+    Bd::Error: Into<CritError>,
+{
+    let max_body_size = DEFAULT_MAX_BODY_SIZE as u64;
+    let content_length = std::env::var("CONTENT_LENGTH")
+        .ok()
+        .and_then(|len| len.parse::<u64>().ok());
+    if content_length.unwrap_or(0) > max_body_size {
+        return respond(error_response(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+    let mut body = vec![];
+    io::stdin()
+        .take(content_length.unwrap_or(max_body_size + 1))
+        .read_to_end(&mut body)?;
+    if body.len() as u64 > max_body_size {
+        return respond(error_response(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    let params = std::env::vars_os().map(|(name, value)| {
+        (
+            name.to_string_lossy().into_owned(),
+            value.to_string_lossy().into_owned(),
+        )
+    });
+
+    let response = match build_request(params, body.into()) {
+        Ok(request) => {
+            let mut rt = Runtime::new()?;
+            let future = make_service
+                .make_service(())
+                .map_err(Into::into)
+                .and_then(|service| {
+                    let mut service = Some(service);
+                    future::poll_fn(move || {
+                        futures::try_ready!(service
+                            .as_mut()
+                            .expect("the future has already polled")
+                            .poll_ready()
+                            .map_err(Into::into));
+                        Ok(service
+                            .take()
+                            .expect("the future has already polled")
+                            .into())
+                    })
+                })
+                .and_then(move |mut service| service.call(request).map_err(Into::into))
+                .and_then(|response| {
+                    let (parts, body) = response.into_parts();
+                    ReadAll::new(body).map(|body| Response::from_parts(parts, body))
+                });
+            rt.block_on(future).unwrap_or_else(|err| {
+                log::error!("service error: {}", err);
+                error_response(StatusCode::INTERNAL_SERVER_ERROR)
+            })
+        }
+        Err(err) => {
+            log::debug!("failed to construct the request: {}", err);
+            error_response(StatusCode::BAD_REQUEST)
+        }
+    };
+
+    respond(response)
+}
+
+fn respond(response: Response<bytes::Bytes>) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    stdout.write_all(&encode_response(response))?;
+    stdout.flush()
+}
@@ -0,0 +1,227 @@
+//! The record layer of the FastCGI protocol.
+
+use {
+    bytes::{BufMut, Bytes, BytesMut},
+    std::io,
+    tokio::codec::{Decoder, Encoder},
+};
+
+const VERSION_1: u8 = 1;
+const HEADER_LEN: usize = 8;
+
+/// The maximum length of the content in a record.
+pub(super) const MAX_CONTENT_LEN: usize = 0xFFF8;
+
+pub(super) const BEGIN_REQUEST: u8 = 1;
+pub(super) const ABORT_REQUEST: u8 = 2;
+pub(super) const END_REQUEST: u8 = 3;
+pub(super) const PARAMS: u8 = 4;
+pub(super) const STDIN: u8 = 5;
+pub(super) const STDOUT: u8 = 6;
+pub(super) const DATA: u8 = 8;
+pub(super) const GET_VALUES: u8 = 9;
+pub(super) const GET_VALUES_RESULT: u8 = 10;
+pub(super) const UNKNOWN_TYPE: u8 = 11;
+
+pub(super) const ROLE_RESPONDER: u16 = 1;
+pub(super) const FLAG_KEEP_CONN: u8 = 1;
+
+pub(super) const REQUEST_COMPLETE: u8 = 0;
+pub(super) const CANT_MPX_CONN: u8 = 1;
+pub(super) const UNKNOWN_ROLE: u8 = 3;
+
+#[derive(Debug, Clone)]
+pub(super) struct Record {
+    pub(super) kind: u8,
+    pub(super) request_id: u16,
+    pub(super) content: Bytes,
+}
+
+impl Record {
+    pub(super) fn new(kind: u8, request_id: u16, content: impl Into<Bytes>) -> Self {
+        Record {
+            kind,
+            request_id,
+            content: content.into(),
+        }
+    }
+
+    pub(super) fn end_request(request_id: u16, app_status: u32, protocol_status: u8) -> Self {
+        let mut content = Vec::with_capacity(8);
+        content.put_u32_be(app_status);
+        content.put_u8(protocol_status);
+        content.put_slice(&[0; 3]);
+        Record::new(END_REQUEST, request_id, content)
+    }
+
+    pub(super) fn unknown_type(kind: u8) -> Self {
+        Record::new(UNKNOWN_TYPE, 0, vec![kind, 0, 0, 0, 0, 0, 0, 0])
+    }
+}
+
+/// Splits the data into a sequence of stream records, terminated by an empty one.
+pub(super) fn stream_records(kind: u8, request_id: u16, data: Bytes) -> Vec<Record> {
+    let mut records: Vec<Record> = (0..data.len())
+        .step_by(MAX_CONTENT_LEN)
+        .map(|start| {
+            let end = std::cmp::min(start + MAX_CONTENT_LEN, data.len());
+            Record::new(kind, request_id, data.slice(start, end))
+        })
+        .collect();
+    records.push(Record::new(kind, request_id, Bytes::new()));
+    records
+}
+
+#[derive(Debug, Default)]
+pub(super) struct RecordCodec(());
+
+impl Decoder for RecordCodec {
+    type Item = Record;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Self::Item>> {
+        if src.len() < HEADER_LEN {
+            return Ok(None);
+        }
+
+        if src[0] != VERSION_1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported FastCGI version: {}", src[0]),
+            ));
+        }
+        let kind = src[1];
+        let request_id = u16::from(src[2]) << 8 | u16::from(src[3]);
+        let content_len = (usize::from(src[4]) << 8) | usize::from(src[5]);
+        let padding_len = usize::from(src[6]);
+
+        let total_len = HEADER_LEN + content_len + padding_len;
+        if src.len() < total_len {
+            src.reserve(total_len - src.len());
+            return Ok(None);
+        }
+
+        let record = src.split_to(total_len).freeze();
+        Ok(Some(Record {
+            kind,
+            request_id,
+            content: record.slice(HEADER_LEN, HEADER_LEN + content_len),
+        }))
+    }
+}
+
+impl Encoder for RecordCodec {
+    type Item = Record;
+    type Error = io::Error;
+
+    fn encode(&mut self, record: Self::Item, dst: &mut BytesMut) -> io::Result<()> {
+        debug_assert!(record.content.len() <= MAX_CONTENT_LEN);
+        let content_len = record.content.len();
+        let padding_len = (8 - content_len % 8) % 8;
+
+        dst.reserve(HEADER_LEN + content_len + padding_len);
+        dst.put_u8(VERSION_1);
+        dst.put_u8(record.kind);
+        dst.put_u16_be(record.request_id);
+        dst.put_u16_be(content_len as u16);
+        dst.put_u8(padding_len as u8);
+        dst.put_u8(0);
+        dst.put_slice(&record.content);
+        dst.put_slice(&[0; 8][..padding_len]);
+        Ok(())
+    }
+}
+
+/// Parses a sequence of name-value pairs.
+pub(super) fn parse_params(mut buf: &[u8]) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    fn read_len(buf: &mut &[u8]) -> io::Result<usize> {
+        match buf.first() {
+            Some(&b) if b & 0x80 == 0 => {
+                *buf = &buf[1..];
+                Ok(usize::from(b))
+            }
+            Some(..) if buf.len() >= 4 => {
+                let len = (usize::from(buf[0] & 0x7F) << 24)
+                    | (usize::from(buf[1]) << 16)
+                    | (usize::from(buf[2]) << 8)
+                    | usize::from(buf[3]);
+                *buf = &buf[4..];
+                Ok(len)
+            }
+            _ => Err(invalid_params()),
+        }
+    }
+
+    let mut params = vec![];
+    while !buf.is_empty() {
+        let name_len = read_len(&mut buf)?;
+        let value_len = read_len(&mut buf)?;
+        if buf.len() < name_len + value_len {
+            return Err(invalid_params());
+        }
+        let (name, rest) = buf.split_at(name_len);
+        let (value, rest) = rest.split_at(value_len);
+        params.push((name.to_owned(), value.to_owned()));
+        buf = rest;
+    }
+    Ok(params)
+}
+
+/// Encodes a sequence of name-value pairs.
+pub(super) fn encode_params<'a>(params: impl IntoIterator<Item = (&'a [u8], &'a [u8])>) -> Vec<u8> {
+    fn write_len(buf: &mut Vec<u8>, len: usize) {
+        if len < 0x80 {
+            buf.put_u8(len as u8);
+        } else {
+            buf.put_u32_be(len as u32 | 0x8000_0000);
+        }
+    }
+
+    let mut buf = vec![];
+    for (name, value) in params {
+        write_len(&mut buf, name.len());
+        write_len(&mut buf, value.len());
+        buf.put_slice(name);
+        buf.put_slice(value);
+    }
+    buf
+}
+
+fn invalid_params() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "malformed FastCGI name-value pairs",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_record() {
+        let mut buf = BytesMut::new();
+        RecordCodec::default()
+            .encode(Record::new(STDIN, 1, &b"hello"[..]), &mut buf)
+            .unwrap();
+        assert_eq!(buf.len(), 16);
+
+        let record = RecordCodec::default().decode(&mut buf).unwrap().unwrap();
+        assert_eq!(record.kind, STDIN);
+        assert_eq!(record.request_id, 1);
+        assert_eq!(record.content, &b"hello"[..]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_roundtrip_params() {
+        let long_value = vec![b'x'; 200];
+        let encoded = encode_params(vec![
+            (&b"REQUEST_METHOD"[..], &b"GET"[..]),
+            (&b"HTTP_X_LONG"[..], &long_value[..]),
+        ]);
+        let params = parse_params(&encoded).unwrap();
+        assert_eq!(params[0], (b"REQUEST_METHOD".to_vec(), b"GET".to_vec()));
+        assert_eq!(params[1], (b"HTTP_X_LONG".to_vec(), long_value));
+    }
+}
@@ -0,0 +1,575 @@
+//! A queue of the background jobs run after responding.
+//!
+//! A `JobQueue` is shared by the handlers (typically as the state attached
+//! by `Scope::with_state`), and the jobs enqueued into it are run by the
+//! workers driven on the same runtime as the server. The failed jobs are
+//! retried according to the `RetryPolicy`. When registered to a `Server` with
+//! `Server::with_jobs`, the workers are started with the server, and the queue
+//! is drained after the server has shut down gracefully.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use finchers::prelude::*;
+//! # use finchers::endpoint::syntax;
+//! # use finchers::jobs::{JobQueue, RetryPolicy};
+//! # use finchers::server;
+//! # use std::time::Duration;
+//! # fn send_welcome_mail(_: &str) -> Result<(), failure::Error> { Ok(()) }
+//! let queue = JobQueue::new()
+//!     .concurrency(4)
+//!     .retry(RetryPolicy::exponential(5, Duration::from_millis(100), Duration::from_secs(10)));
+//!
+//! let sign_up = syntax::segment("sign_up")
+//!     .and(endpoint::state::<JobQueue>())
+//!     .and_then(|queue: JobQueue| {
+//!         let address = "alice@example.com".to_owned();
+//!         queue.enqueue(move || send_welcome_mail(&address))?;
+//!         Ok::<_, finchers::error::Error>("accepted")
+//!     });
+//! let endpoint = endpoint::scope("/", sign_up).with_state(queue.clone());
+//!
+//! server::start(endpoint)
+//!     .with_jobs(&queue)
+//!     .bind("127.0.0.1:4000")
+//!     .serve()
+//!     .expect("failed to start the server");
+//! ```
+
+use {
+    crate::error::HttpError,
+    failure::Fail,
+    futures::{
+        future,
+        stream::FuturesUnordered,
+        sync::{mpsc, oneshot},
+        Async, Future, IntoFuture, Poll, Stream,
+    },
+    http::StatusCode,
+    std::{
+        cmp, fmt,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+    tokio_timer::{Delay, Timeout},
+};
+
+type JobFuture = Box<dyn Future<Item = (), Error = failure::Error> + Send + 'static>;
+type JobFn = Box<dyn FnMut() -> JobFuture + Send + 'static>;
+
+// ==== RetryPolicy ====
+
+/// The policy for retrying the failed jobs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Backoff,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backoff {
+    Fixed(Duration),
+    Exponential { initial: Duration, max: Duration },
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::never()
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a policy which never retries the failed jobs.
+    pub fn never() -> Self {
+        RetryPolicy::fixed(1, Duration::from_secs(0))
+    }
+
+    /// Creates a policy which runs the job at most `max_attempts` times,
+    /// waiting for `delay` between the attempts.
+    ///
+    /// # Panics
+    /// This method panics if `max_attempts` is zero.
+    pub fn fixed(max_attempts: u32, delay: Duration) -> Self {
+        assert!(max_attempts > 0, "max_attempts must be positive");
+        RetryPolicy {
+            max_attempts,
+            backoff: Backoff::Fixed(delay),
+        }
+    }
+
+    /// Creates a policy which runs the job at most `max_attempts` times,
+    /// doubling the delay between the attempts from `initial` up to `max`.
+    ///
+    /// # Panics
+    /// This method panics if `max_attempts` is zero.
+    pub fn exponential(max_attempts: u32, initial: Duration, max: Duration) -> Self {
+        assert!(max_attempts > 0, "max_attempts must be positive");
+        RetryPolicy {
+            max_attempts,
+            backoff: Backoff::Exponential { initial, max },
+        }
+    }
+
+    /// Returns the maximum number of attempts.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns the delay before the next attempt, after the job has failed
+    /// `failures` times.
+    pub fn delay(&self, failures: u32) -> Duration {
+        match self.backoff {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => {
+                let factor = 1u32
+                    .checked_shl(failures.saturating_sub(1))
+                    .unwrap_or(std::u32::MAX);
+                initial
+                    .checked_mul(factor)
+                    .map_or(max, |delay| cmp::min(delay, max))
+            }
+        }
+    }
+}
+
+// ==== EnqueueError ====
+
+/// An error returned when the job is enqueued after the queue has been shut down.
+///
+/// This error is reported as `503 Service Unavailable`.
+#[derive(Debug, Fail)]
+#[fail(display = "the job queue has been shut down")]
+pub struct EnqueueError(());
+
+impl HttpError for EnqueueError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+// ==== JobQueue ====
+
+#[derive(Debug, Clone)]
+struct Config {
+    concurrency: usize,
+    retry: RetryPolicy,
+    shutdown_timeout: Option<Duration>,
+}
+
+struct Entry {
+    job: JobFn,
+    retry: RetryPolicy,
+}
+
+struct Shared {
+    tx: Mutex<Option<mpsc::UnboundedSender<Entry>>>,
+    rx: Mutex<Option<mpsc::UnboundedReceiver<Entry>>>,
+    done: Mutex<Option<oneshot::Receiver<()>>>,
+}
+
+/// A queue of the background jobs.
+///
+/// The values of this type are cheaply cloneable, and all of the clones
+/// refer to the same queue.
+#[derive(Clone)]
+pub struct JobQueue {
+    config: Arc<Config>,
+    shared: Arc<Shared>,
+}
+
+impl fmt::Debug for JobQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JobQueue")
+            .field("config", &self.config)
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        JobQueue::new()
+    }
+}
+
+impl JobQueue {
+    /// Creates a new `JobQueue` with the default configuration.
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded();
+        JobQueue {
+            config: Arc::new(Config {
+                concurrency: 1,
+                retry: RetryPolicy::never(),
+                shutdown_timeout: None,
+            }),
+            shared: Arc::new(Shared {
+                tx: Mutex::new(Some(tx)),
+                rx: Mutex::new(Some(rx)),
+                done: Mutex::new(None),
+            }),
+        }
+    }
+
+    fn configure(self, f: impl FnOnce(&mut Config)) -> Self {
+        let mut config = Arc::try_unwrap(self.config).unwrap_or_else(|config| (*config).clone());
+        f(&mut config);
+        JobQueue {
+            config: Arc::new(config),
+            shared: self.shared,
+        }
+    }
+
+    /// Sets the maximum number of the jobs run concurrently.
+    ///
+    /// The default value is `1`.
+    ///
+    /// # Panics
+    /// This method panics if `n` is zero.
+    pub fn concurrency(self, n: usize) -> Self {
+        assert!(n > 0, "the concurrency must be positive");
+        self.configure(|config| config.concurrency = n)
+    }
+
+    /// Sets the default retry policy of the jobs.
+    ///
+    /// The default value is `RetryPolicy::never()`.
+    pub fn retry(self, policy: RetryPolicy) -> Self {
+        self.configure(|config| config.retry = policy)
+    }
+
+    /// Sets the maximum duration for draining the queue on shutdown.
+    ///
+    /// The remaining jobs are dropped when the timeout expires.
+    /// The default value is `None`, which waits for all of the jobs.
+    pub fn shutdown_timeout(self, timeout: Option<Duration>) -> Self {
+        self.configure(|config| config.shutdown_timeout = timeout)
+    }
+
+    /// Enqueues a job with the default retry policy.
+    ///
+    /// The job is a function which returns a future, and is called again
+    /// when the job is retried.
+    pub fn enqueue<F, R>(&self, job: F) -> Result<(), EnqueueError>
+    where
+        F: FnMut() -> R + Send + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: Send + 'static,
+        R::Error: Into<failure::Error> + 'static,
+    {
+        self.enqueue_with(self.config.retry, job)
+    }
+
+    /// Enqueues a job with the specified retry policy.
+    pub fn enqueue_with<F, R>(&self, retry: RetryPolicy, mut job: F) -> Result<(), EnqueueError>
+    where
+        F: FnMut() -> R + Send + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: Send + 'static,
+        R::Error: Into<failure::Error> + 'static,
+    {
+        let entry = Entry {
+            job: Box::new(move || Box::new(job().into_future().map_err(Into::into)) as JobFuture),
+            retry,
+        };
+        let tx = self.shared.tx.lock().unwrap();
+        match *tx {
+            Some(ref tx) => tx.unbounded_send(entry).map_err(|_| EnqueueError(())),
+            None => Err(EnqueueError(())),
+        }
+    }
+
+    /// Returns whether the queue has been shut down.
+    pub fn is_closed(&self) -> bool {
+        self.shared.tx.lock().unwrap().is_none()
+    }
+
+    /// Creates a future which runs the enqueued jobs until the queue is
+    /// shut down and drained.
+    ///
+    /// The returned future must be spawned onto the runtime which has a timer.
+    /// This method returns `None` if the workers have already been created,
+    /// or the queue has been shut down before creating them.
+    pub fn workers(&self) -> Option<Workers> {
+        let (workers, done) = self.new_workers()?;
+        *self.shared.done.lock().unwrap() = Some(done);
+        Some(workers)
+    }
+
+    fn new_workers(&self) -> Option<(Workers, oneshot::Receiver<()>)> {
+        let rx = self.shared.rx.lock().unwrap().take()?;
+        let (tx_done, rx_done) = oneshot::channel();
+        let workers = Workers {
+            rx: Some(rx),
+            running: FuturesUnordered::new(),
+            concurrency: self.config.concurrency,
+            _done: tx_done,
+        };
+        Some((workers, rx_done))
+    }
+
+    /// Shuts down the queue, and returns a future which completes when all of
+    /// the enqueued jobs are finished.
+    ///
+    /// The jobs can no longer be enqueued after calling this method.
+    /// If the workers have not been created yet, the returned future runs
+    /// the remaining jobs by itself.
+    pub fn shutdown(&self) -> Shutdown {
+        self.shared.tx.lock().unwrap().take();
+        let done = self.shared.done.lock().unwrap().take();
+        let drain: Box<dyn Future<Item = (), Error = ()> + Send> = match done {
+            // `Canceled` means that the workers have finished.
+            Some(done) => Box::new(done.then(|_| Ok(()))),
+            None => match self.new_workers() {
+                Some((workers, _)) => Box::new(workers),
+                None => Box::new(future::ok(())),
+            },
+        };
+        Shutdown {
+            drain: match self.config.shutdown_timeout {
+                Some(timeout) => Box::new(Timeout::new(drain, timeout).or_else(|_| {
+                    log::warn!("the job queue has not been drained within the timeout");
+                    Ok(())
+                })),
+                None => drain,
+            },
+        }
+    }
+}
+
+/// A future which drives the workers of a `JobQueue`.
+///
+/// This future completes when the queue is shut down and all of the jobs
+/// are finished.
+#[must_use = "futures do nothing unless polled"]
+pub struct Workers {
+    rx: Option<mpsc::UnboundedReceiver<Entry>>,
+    running: FuturesUnordered<RunJob>,
+    concurrency: usize,
+    _done: oneshot::Sender<()>,
+}
+
+impl fmt::Debug for Workers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Workers")
+            .field("running", &self.running.len())
+            .field("concurrency", &self.concurrency)
+            .finish()
+    }
+}
+
+impl Future for Workers {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            while self.running.len() < self.concurrency {
+                let entry = match self.rx {
+                    Some(ref mut rx) => match rx.poll()? {
+                        Async::Ready(Some(entry)) => entry,
+                        Async::Ready(None) => {
+                            self.rx = None;
+                            break;
+                        }
+                        Async::NotReady => break,
+                    },
+                    None => break,
+                };
+                self.running.push(RunJob::new(entry));
+            }
+
+            match self.running.poll()? {
+                Async::Ready(Some(())) => continue,
+                Async::Ready(None) if self.rx.is_none() => return Ok(Async::Ready(())),
+                Async::Ready(None) | Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+struct RunJob {
+    entry: Entry,
+    failures: u32,
+    state: RunJobState,
+}
+
+enum RunJobState {
+    Running(JobFuture),
+    Waiting(Delay),
+}
+
+impl RunJob {
+    fn new(mut entry: Entry) -> Self {
+        let future = (entry.job)();
+        RunJob {
+            entry,
+            failures: 0,
+            state: RunJobState::Running(future),
+        }
+    }
+}
+
+impl Future for RunJob {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                RunJobState::Running(ref mut future) => match future.poll() {
+                    Ok(Async::Ready(())) => return Ok(Async::Ready(())),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        self.failures += 1;
+                        if self.failures >= self.entry.retry.max_attempts() {
+                            log::error!(
+                                "the job failed after {} attempt(s): {}",
+                                self.failures,
+                                err
+                            );
+                            return Ok(Async::Ready(()));
+                        }
+                        let delay = self.entry.retry.delay(self.failures);
+                        log::warn!("the job failed (retrying after {:?}): {}", delay, err);
+                        RunJobState::Waiting(Delay::new(Instant::now() + delay))
+                    }
+                },
+                RunJobState::Waiting(ref mut delay) => match delay.poll() {
+                    Ok(Async::Ready(())) => RunJobState::Running((self.entry.job)()),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        log::error!("timer error: {}", err);
+                        return Ok(Async::Ready(()));
+                    }
+                },
+            };
+        }
+    }
+}
+
+/// A future returned from `JobQueue::shutdown`.
+#[must_use = "futures do nothing unless polled"]
+pub struct Shutdown {
+    drain: Box<dyn Future<Item = (), Error = ()> + Send>,
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shutdown").finish()
+    }
+}
+
+impl Future for Shutdown {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.drain.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::sync::atomic::{AtomicUsize, Ordering},
+        tokio::runtime::current_thread::Runtime,
+    };
+
+    #[test]
+    fn test_retry_policy() {
+        let policy =
+            RetryPolicy::exponential(5, Duration::from_millis(100), Duration::from_secs(1));
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+        assert_eq!(policy.delay(5), Duration::from_secs(1));
+        assert_eq!(policy.delay(100), Duration::from_secs(1));
+
+        let policy = RetryPolicy::fixed(3, Duration::from_millis(10));
+        assert_eq!(policy.delay(2), Duration::from_millis(10));
+        assert_eq!(RetryPolicy::default().max_attempts(), 1);
+    }
+
+    #[test]
+    fn test_run_and_drain() {
+        let queue = JobQueue::new()
+            .concurrency(2)
+            .retry(RetryPolicy::fixed(3, Duration::from_millis(1)));
+        let completed = Arc::new(AtomicUsize::new(0));
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let failures = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..5 {
+            let completed = completed.clone();
+            queue
+                .enqueue(move || {
+                    completed.fetch_add(1, Ordering::SeqCst);
+                    Ok::<(), failure::Error>(())
+                })
+                .unwrap();
+        }
+        {
+            let attempts = attempts.clone();
+            queue
+                .enqueue(move || {
+                    if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                        Err(failure::err_msg("temporary failure"))
+                    } else {
+                        Ok(())
+                    }
+                })
+                .unwrap();
+        }
+        {
+            let failures = failures.clone();
+            queue
+                .enqueue_with(RetryPolicy::never(), move || {
+                    failures.fetch_add(1, Ordering::SeqCst);
+                    Err::<(), _>(failure::err_msg("permanent failure"))
+                })
+                .unwrap();
+        }
+
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(queue.workers().unwrap());
+        assert!(queue.workers().is_none());
+        rt.block_on(queue.shutdown()).unwrap();
+
+        assert_eq!(completed.load(Ordering::SeqCst), 5);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(failures.load(Ordering::SeqCst), 1);
+        assert!(queue.is_closed());
+        assert!(queue.enqueue(|| Ok::<(), failure::Error>(())).is_err());
+    }
+
+    #[test]
+    fn test_shutdown_timeout() {
+        let queue = JobQueue::new().shutdown_timeout(Some(Duration::from_millis(10)));
+        queue.enqueue(future::empty::<(), failure::Error>).unwrap();
+
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(queue.workers().unwrap());
+        rt.block_on(queue.shutdown()).unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_before_workers() {
+        let queue = JobQueue::new();
+        let completed = Arc::new(AtomicUsize::new(0));
+        for _ in 0..3 {
+            let completed = completed.clone();
+            queue
+                .enqueue(move || {
+                    completed.fetch_add(1, Ordering::SeqCst);
+                    Ok::<(), failure::Error>(())
+                })
+                .unwrap();
+        }
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(queue.shutdown()).unwrap();
+        assert_eq!(completed.load(Ordering::SeqCst), 3);
+        assert!(queue.workers().is_none());
+    }
+}
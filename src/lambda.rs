@@ -0,0 +1,505 @@
+//! An adapter for running the endpoints on AWS Lambda behind Amazon API Gateway.
+//!
+//! This module provides the conversion between the events sent from API Gateway
+//! (both of the REST API (payload format version 1.0) and the HTTP API (version 2.0))
+//! and the HTTP request/response, so that the same route tree used in the server
+//! can be deployed to the serverless environment.
+//!
+//! The connection with the Lambda runtime API is out of the scope of this module.
+//! The runtime loop receives the event payload, passes it to `handle` together
+//! with the service (e.g. `App`), and sends back the serialized `LambdaResponse`.
+//!
+//! This module is available only if the feature `lambda` is enabled.
+
+use {
+    bytes::{Buf, Bytes},
+    futures::{Async, Future, Poll},
+    http::{
+        header::{HeaderMap, HeaderName, HeaderValue},
+        Request, Response,
+    },
+    izanami_service::Service,
+    izanami_util::buf_stream::BufStream,
+    serde::{Deserialize, Serialize},
+    std::{collections::HashMap, io},
+    url::form_urlencoded,
+};
+
+/// An event received from Amazon API Gateway.
+#[derive(Debug, Clone)]
+pub enum LambdaEvent {
+    /// An event with the payload format version 1.0 (REST API).
+    V1(ApiGatewayV1Request),
+    /// An event with the payload format version 2.0 (HTTP API).
+    V2(ApiGatewayV2Request),
+}
+
+impl LambdaEvent {
+    /// Parses the event payload.
+    ///
+    /// The payload format is detected by the value of `version` field.
+    pub fn from_slice(payload: &[u8]) -> serde_json::Result<Self> {
+        let value: serde_json::Value = serde_json::from_slice(payload)?;
+        match value.get("version").and_then(|v| v.as_str()) {
+            Some("2.0") => serde_json::from_value(value).map(LambdaEvent::V2),
+            _ => serde_json::from_value(value).map(LambdaEvent::V1),
+        }
+    }
+
+    fn is_v2(&self) -> bool {
+        match self {
+            LambdaEvent::V1(..) => false,
+            LambdaEvent::V2(..) => true,
+        }
+    }
+
+    /// Converts this event into an HTTP request.
+    pub fn into_request(self) -> Result<Request<LambdaBody>, failure::Error> {
+        match self {
+            LambdaEvent::V1(event) => event.into_request(),
+            LambdaEvent::V2(event) => event.into_request(),
+        }
+    }
+}
+
+/// The payload of events with the format version 1.0.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiGatewayV1Request {
+    pub http_method: String,
+    pub path: String,
+    #[serde(default)]
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub multi_value_headers: Option<HashMap<String, Vec<String>>>,
+    #[serde(default)]
+    pub query_string_parameters: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub multi_value_query_string_parameters: Option<HashMap<String, Vec<String>>>,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub is_base64_encoded: bool,
+}
+
+impl ApiGatewayV1Request {
+    fn into_request(self) -> Result<Request<LambdaBody>, failure::Error> {
+        let mut uri = self.path;
+        let query = match (
+            self.multi_value_query_string_parameters,
+            self.query_string_parameters,
+        ) {
+            (Some(params), _) => encode_query(
+                params
+                    .iter()
+                    .flat_map(|(k, vs)| vs.iter().map(move |v| (k, v))),
+            ),
+            (None, Some(params)) => encode_query(params.iter()),
+            (None, None) => String::new(),
+        };
+        if !query.is_empty() {
+            uri.push('?');
+            uri.push_str(&query);
+        }
+
+        let mut request = Request::builder()
+            .method(self.http_method.as_str())
+            .uri(uri)
+            .body(LambdaBody::decode(self.body, self.is_base64_encoded)?)?;
+
+        match (self.multi_value_headers, self.headers) {
+            (Some(headers), _) => {
+                for (name, values) in headers {
+                    for value in values {
+                        append_header(request.headers_mut(), &name, &value)?;
+                    }
+                }
+            }
+            (None, Some(headers)) => {
+                for (name, value) in headers {
+                    append_header(request.headers_mut(), &name, &value)?;
+                }
+            }
+            (None, None) => {}
+        }
+
+        Ok(request)
+    }
+}
+
+/// The payload of events with the format version 2.0.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiGatewayV2Request {
+    pub version: String,
+    pub raw_path: String,
+    #[serde(default)]
+    pub raw_query_string: String,
+    #[serde(default)]
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub cookies: Option<Vec<String>>,
+    pub request_context: ApiGatewayV2RequestContext,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub is_base64_encoded: bool,
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ApiGatewayV2RequestContext {
+    pub http: ApiGatewayV2Http,
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ApiGatewayV2Http {
+    pub method: String,
+}
+
+impl ApiGatewayV2Request {
+    fn into_request(self) -> Result<Request<LambdaBody>, failure::Error> {
+        let mut uri = self.raw_path;
+        if !self.raw_query_string.is_empty() {
+            uri.push('?');
+            uri.push_str(&self.raw_query_string);
+        }
+
+        let mut request = Request::builder()
+            .method(self.request_context.http.method.as_str())
+            .uri(uri)
+            .body(LambdaBody::decode(self.body, self.is_base64_encoded)?)?;
+
+        if let Some(headers) = self.headers {
+            // The values of duplicated headers are combined with commas by API Gateway.
+            for (name, value) in headers {
+                append_header(request.headers_mut(), &name, &value)?;
+            }
+        }
+
+        if let Some(cookies) = self.cookies {
+            if !cookies.is_empty() {
+                request.headers_mut().insert(
+                    http::header::COOKIE,
+                    HeaderValue::from_str(&cookies.join("; "))?,
+                );
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+fn encode_query<'a>(params: impl Iterator<Item = (&'a String, &'a String)>) -> String {
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    for (key, value) in params {
+        serializer.append_pair(key, value);
+    }
+    serializer.finish()
+}
+
+fn append_header(headers: &mut HeaderMap, name: &str, value: &str) -> Result<(), failure::Error> {
+    headers.append(
+        HeaderName::from_bytes(name.as_bytes())?,
+        HeaderValue::from_str(value)?,
+    );
+    Ok(())
+}
+
+/// The type of request body used in the Lambda adapter.
+#[derive(Debug)]
+pub struct LambdaBody(Option<Bytes>);
+
+impl LambdaBody {
+    fn decode(body: Option<String>, is_base64_encoded: bool) -> Result<Self, failure::Error> {
+        let body = match body {
+            Some(ref body) if is_base64_encoded => Some(base64::decode(body)?.into()),
+            Some(body) => Some(body.into()),
+            None => None,
+        };
+        Ok(LambdaBody(body.filter(|body: &Bytes| !body.is_empty())))
+    }
+}
+
+impl BufStream for LambdaBody {
+    type Item = io::Cursor<Bytes>;
+    type Error = io::Error;
+
+    #[inline]
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        Ok(self.0.take().map(io::Cursor::new).into())
+    }
+}
+
+/// The response payload sent back to Amazon API Gateway.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LambdaResponse {
+    pub status_code: u16,
+    pub headers: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multi_value_headers: Option<HashMap<String, Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cookies: Option<Vec<String>>,
+    pub body: String,
+    pub is_base64_encoded: bool,
+}
+
+impl LambdaResponse {
+    fn from_parts(response: Response<Vec<u8>>, is_v2: bool) -> Self {
+        let (parts, body) = response.into_parts();
+
+        let mut headers = HashMap::new();
+        let mut multi_value_headers = HashMap::new();
+        let mut cookies = vec![];
+        for name in parts.headers.keys() {
+            let values: Vec<String> = parts
+                .headers
+                .get_all(name)
+                .iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+                .collect();
+            if is_v2 && *name == http::header::SET_COOKIE {
+                cookies.extend(values);
+            } else if is_v2 {
+                headers.insert(name.as_str().to_owned(), values.join(","));
+            } else {
+                if let Some(last) = values.last() {
+                    headers.insert(name.as_str().to_owned(), last.clone());
+                }
+                multi_value_headers.insert(name.as_str().to_owned(), values);
+            }
+        }
+
+        let is_text = parts
+            .headers
+            .get(http::header::CONTENT_TYPE)
+            .map(|value| is_textual_content_type(value.as_bytes()))
+            .unwrap_or(true);
+        let (body, is_base64_encoded) = match String::from_utf8(body) {
+            Ok(body) if is_text => (body, false),
+            Ok(body) => (base64::encode(&body), true),
+            Err(err) => (base64::encode(err.as_bytes()), true),
+        };
+
+        LambdaResponse {
+            status_code: parts.status.as_u16(),
+            headers,
+            multi_value_headers: if is_v2 {
+                None
+            } else {
+                Some(multi_value_headers)
+            },
+            cookies: if is_v2 { Some(cookies) } else { None },
+            body,
+            is_base64_encoded,
+        }
+    }
+}
+
+fn is_textual_content_type(value: &[u8]) -> bool {
+    let value = String::from_utf8_lossy(value).to_ascii_lowercase();
+    value.starts_with("text/")
+        || value.contains("json")
+        || value.contains("xml")
+        || value.contains("javascript")
+        || value.starts_with("application/x-www-form-urlencoded")
+}
+
+/// Handles an event from API Gateway with the specified service.
+///
+/// The service is typically an `App` created by `EndpointServiceExt::into_service`.
+/// The request is passed to the service after it has reported its readiness
+/// by `poll_ready`.
+pub fn handle<'a, S, Bd>(
+    service: &'a mut S,
+    event: LambdaEvent,
+) -> impl Future<Item = LambdaResponse, Error = failure::Error> + 'a
+where
+    S: Service<Request<LambdaBody>, Response = Response<Bd>> + 'a,
+    S::Error: Into<failure::Error>,
+    Bd: BufStream + 'a,
+    Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+    let is_v2 = event.is_v2();
+    let state = match event.into_request() {
+        Ok(request) => State::Ready(Some(request)),
+        Err(err) => State::Failed(Some(err)),
+    };
+    HandleFuture {
+        service,
+        state,
+        is_v2,
+    }
+}
+
+#[allow(missing_debug_implementations)]
+struct HandleFuture<'a, S, Bd>
+where
+    S: Service<Request<LambdaBody>>,
+{
+    service: &'a mut S,
+    state: State<S::Future, Bd>,
+    is_v2: bool,
+}
+
+#[allow(missing_debug_implementations)]
+enum State<F, Bd> {
+    Failed(Option<failure::Error>),
+    Ready(Option<Request<LambdaBody>>),
+    InFlight(F),
+    Receiving(Option<(http::response::Parts, Vec<u8>)>, Bd),
+}
+
+impl<'a, S, Bd> Future for HandleFuture<'a, S, Bd>
+where
+    S: Service<Request<LambdaBody>, Response = Response<Bd>>,
+    S::Error: Into<failure::Error>,
+    Bd: BufStream,
+    Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+    type Item = LambdaResponse;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                State::Failed(ref mut err) => {
+                    return Err(err.take().expect("the future has already polled"));
+                }
+                State::Ready(ref mut request) => {
+                    futures::try_ready!(self.service.poll_ready().map_err(Into::into));
+                    let request = request.take().expect("the future has already polled");
+                    State::InFlight(self.service.call(request))
+                }
+                State::InFlight(ref mut future) => {
+                    let response = futures::try_ready!(future.poll().map_err(Into::into));
+                    let (parts, body) = response.into_parts();
+                    State::Receiving(Some((parts, Vec::new())), body)
+                }
+                State::Receiving(ref mut received, ref mut body) => {
+                    while let Some(mut data) = futures::try_ready!(body
+                        .poll_buf()
+                        .map_err(|e| failure::Error::from_boxed_compat(e.into())))
+                    {
+                        let buf = &mut received.as_mut().expect("the future has already polled").1;
+                        while data.has_remaining() {
+                            let n = {
+                                let chunk = data.bytes();
+                                buf.extend_from_slice(chunk);
+                                chunk.len()
+                            };
+                            data.advance(n);
+                        }
+                    }
+                    let (parts, buf) = received.take().expect("the future has already polled");
+                    return Ok(Async::Ready(LambdaResponse::from_parts(
+                        Response::from_parts(parts, buf),
+                        self.is_v2,
+                    )));
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{endpoint::syntax, endpoints, prelude::*},
+        tokio::runtime::current_thread::Runtime,
+    };
+
+    fn run(payload: &str) -> LambdaResponse {
+        let endpoint = syntax::segment("echo")
+            .and(endpoints::query::raw())
+            .and(endpoints::body::text())
+            .map(|query: Option<String>, body: String| {
+                format!("query={}, body={}", query.unwrap_or_default(), body)
+            });
+        let mut app = endpoint.into_service();
+        let event = LambdaEvent::from_slice(payload.as_bytes()).unwrap();
+        Runtime::new()
+            .unwrap()
+            .block_on(handle(&mut app, event))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_v1_event() {
+        let response = run(r#"{
+                "httpMethod": "POST",
+                "path": "/echo",
+                "multiValueHeaders": { "content-type": ["text/plain"] },
+                "multiValueQueryStringParameters": { "q": ["a b"] },
+                "body": "SGVsbG8=",
+                "isBase64Encoded": true
+            }"#);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, "query=q=a+b, body=Hello");
+        assert!(!response.is_base64_encoded);
+        assert!(response.multi_value_headers.is_some());
+    }
+
+    #[test]
+    fn test_v2_event() {
+        let response = run(r#"{
+                "version": "2.0",
+                "rawPath": "/echo",
+                "rawQueryString": "q=1",
+                "headers": { "content-type": "text/plain" },
+                "requestContext": { "http": { "method": "POST" } },
+                "body": "Hello",
+                "isBase64Encoded": false
+            }"#);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, "query=q=1, body=Hello");
+        assert_eq!(response.cookies, Some(vec![]));
+    }
+
+    #[test]
+    fn test_not_found() {
+        let response = run(r#"{ "httpMethod": "GET", "path": "/" }"#);
+        assert_eq!(response.status_code, 404);
+    }
+
+    #[test]
+    fn test_wait_for_readiness() {
+        struct Lazy {
+            ready: bool,
+        }
+
+        impl Service<Request<LambdaBody>> for Lazy {
+            type Response = Response<String>;
+            type Error = io::Error;
+            type Future = futures::future::FutureResult<Response<String>, io::Error>;
+
+            fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+                if self.ready {
+                    return Ok(Async::Ready(()));
+                }
+                self.ready = true;
+                futures::task::current().notify();
+                Ok(Async::NotReady)
+            }
+
+            fn call(&mut self, _: Request<LambdaBody>) -> Self::Future {
+                assert!(self.ready, "called before the service is ready");
+                futures::future::ok(Response::new("ready".into()))
+            }
+        }
+
+        let mut service = Lazy { ready: false };
+        let event = LambdaEvent::from_slice(br#"{ "httpMethod": "GET", "path": "/" }"#).unwrap();
+        let response = Runtime::new()
+            .unwrap()
+            .block_on(handle(&mut service, event))
+            .unwrap();
+        assert_eq!(response.body, "ready");
+    }
+}
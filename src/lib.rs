@@ -22,7 +22,7 @@
 //!     .and(endpoints::body::text())
 //!     .map(|data: String| format!("POST: body={}", data));
 //!
-//! let endpoint = path!("/posts")
+//! let endpoint = path!("/posts/...")
 //!     .and(get_post.or(create_post));
 //!
 //! # drop(move || -> izanami::Result<_> {
@@ -48,14 +48,75 @@
 mod common;
 
 pub mod action;
+pub mod broadcast;
+pub mod client;
+pub mod contrib;
 pub mod endpoint;
 pub mod endpoints;
 pub mod error;
+pub mod fastcgi;
+pub mod jobs;
+#[cfg(feature = "lambda")]
+pub mod lambda;
+pub mod middleware;
 pub mod output;
+pub mod server;
 pub mod service;
 pub mod test;
 pub mod util;
 
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::test;
+/// use finchers::endpoints::header::Header;
+/// use finchers::error::Error;
+/// use finchers::output::Json;
+/// # use serde::Deserialize;
+///
+/// #[finchers::get("/users/{id}/posts")]
+/// fn user_posts(id: u32, x_client_id: Option<Header<String>>) -> String {
+///     match x_client_id {
+///         Some(Header(client)) => format!("posts of user {} for {}", id, client),
+///         None => format!("posts of user {}", id),
+///     }
+/// }
+///
+/// #[derive(Deserialize)]
+/// struct NewPost {
+///     title: String,
+/// }
+///
+/// #[finchers::post("/users/{id}/posts")]
+/// fn create_post(id: u32, post: Json<NewPost>) -> Result<String, Error> {
+///     Ok(format!("user {} posted {}", id, post.0.title))
+/// }
+///
+/// let mut runner = test::runner(user_posts().or(create_post()));
+/// runner
+///     .perform("/users/42/posts")
+///     .unwrap()
+///     .assert_body("posts of user 42");
+/// let request = http::Request::post("/users/42/posts")
+///     .header("content-type", "application/json")
+///     .body(r#"{"title":"hello"}"#)
+///     .unwrap();
+/// runner
+///     .perform(request)
+///     .unwrap()
+///     .assert_body("user 42 posted hello");
+/// ```
+pub use finchers_macros::get;
+pub use finchers_macros::{delete, head, options, patch, post, put};
+
+#[doc(hidden)]
+pub mod __private {
+    pub use futures::Future;
+    pub use http::Uri;
+    pub use izanami_util::buf_stream::BufStream;
+}
+
 /// A prelude for crates using the `finchers` crate.
 pub mod prelude {
     pub use crate::endpoint;
@@ -0,0 +1,1287 @@
+//! Middlewares at the service level.
+//!
+//! A `Middleware` wraps the `Service` created for each connection and can
+//! modify the incoming requests, return responses without calling the inner
+//! service, modify the outgoing responses and observe the errors returned
+//! from the inner service.
+//!
+//! # Ordering
+//!
+//! Middlewares are applied with `App::with_middleware` (or `WithMiddleware::with_middleware`),
+//! and a middleware added later *wraps* the ones added earlier.
+//! Therefore, the last added middleware sees the requests first and the
+//! responses last:
+//!
+//! ```text
+//! app.with_middleware(a).with_middleware(b)
+//!
+//!   request  --> b --> a --> endpoint
+//!   response <-- b <-- a <--
+//! ```
+//!
+//! # Short-circuiting
+//!
+//! A middleware can respond without calling the inner service, e.g. for
+//! rejecting the requests before routing. `short_circuit` creates such a
+//! middleware from a function; the inner service is called only if the
+//! function returns `None`.
+//!
+//! # Errors
+//!
+//! The errors occurred in endpoints are converted into HTTP responses before
+//! reaching the middlewares, and hence they are observed as the responses with
+//! the corresponding status codes. The errors returned from `Service::call`
+//! are the fatal ones, which cause the connection to be closed. They can be
+//! observed with `observe_error` before they reach the server.
+//!
+//! # Interoperability with Tower
+//!
+//! The services produced by middlewares are `tower_service::Service`s.
+//! A Tower layer can be applied as a `Middleware` by `from_layer`, and
+//! conversely a `Middleware` can be used as a Tower layer by `into_layer`:
+//!
+//! ```no_run
+//! # use finchers::prelude::*;
+//! # use finchers::middleware;
+//! # use finchers::server::RequestBody;
+//! # use finchers::service::App;
+//! # let endpoint = endpoint::unit().map(|| "Hello");
+//! let set_request_id = middleware::map_request(|request: &mut http::Request<RequestBody>| {
+//!     request.headers_mut().insert("x-request-id", "42".parse().unwrap());
+//! });
+//!
+//! // a `Middleware` used as a Tower layer...
+//! let layer = middleware::into_layer(set_request_id);
+//!
+//! // ...and a Tower layer used as a `Middleware`.
+//! let app = App::new(endpoint).with_middleware(middleware::from_layer(layer));
+//! # finchers::server::Server::new(app).bind("127.0.0.1:4000").serve().unwrap();
+//! ```
+
+pub mod access_log;
+pub mod audit;
+pub mod har;
+pub mod normalize;
+pub mod shadow;
+
+use {
+    bytes::{Buf, Bytes, BytesMut},
+    futures::{Async, Future, Poll},
+    http::{
+        header::{self, HeaderName},
+        Method, Request, Response,
+    },
+    izanami_service::{MakeService, Service},
+    izanami_util::buf_stream::{BufStream, Either},
+    std::{
+        io,
+        sync::{Arc, Mutex},
+    },
+    tower_layer::Layer,
+};
+
+/// A trait representing a middleware which wraps a `Service`.
+pub trait Middleware<S> {
+    /// The type of service returned from `wrap`.
+    type Service;
+
+    /// Wraps the specified service.
+    fn wrap(&self, inner: S) -> Self::Service;
+}
+
+impl<M, S> Middleware<S> for &M
+where
+    M: Middleware<S> + ?Sized,
+{
+    type Service = M::Service;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        (**self).wrap(inner)
+    }
+}
+
+/// A `Middleware` that returns the inner service as it is.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Identity(());
+
+impl Identity {
+    /// Creates a new `Identity`.
+    pub fn new() -> Self {
+        Identity(())
+    }
+}
+
+impl<S> Middleware<S> for Identity {
+    type Service = S;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        inner
+    }
+}
+
+/// A `Middleware` composed of two middlewares.
+///
+/// The service created by `inner` is wrapped by `outer`.
+#[derive(Debug, Copy, Clone)]
+pub struct Chain<M1, M2> {
+    inner: M1,
+    outer: M2,
+}
+
+impl<M1, M2> Chain<M1, M2> {
+    /// Creates a new `Chain` from the specified middlewares.
+    pub fn new(inner: M1, outer: M2) -> Self {
+        Chain { inner, outer }
+    }
+}
+
+impl<S, M1, M2> Middleware<S> for Chain<M1, M2>
+where
+    M1: Middleware<S>,
+    M2: Middleware<M1::Service>,
+{
+    type Service = M2::Service;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        self.outer.wrap(self.inner.wrap(inner))
+    }
+}
+
+/// Creates a `Middleware` from the specified function.
+pub fn from_fn<F>(f: F) -> FromFn<F> {
+    FromFn { f }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct FromFn<F> {
+    f: F,
+}
+
+impl<S, F, T> Middleware<S> for FromFn<F>
+where
+    F: Fn(S) -> T,
+{
+    type Service = T;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        (self.f)(inner)
+    }
+}
+
+/// Creates a `Middleware` from the specified Tower layer.
+pub fn from_layer<L>(layer: L) -> FromLayer<L> {
+    FromLayer { layer }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct FromLayer<L> {
+    layer: L,
+}
+
+impl<S, L> Middleware<S> for FromLayer<L>
+where
+    L: Layer<S>,
+{
+    type Service = L::Service;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        self.layer.layer(inner)
+    }
+}
+
+/// Converts the specified `Middleware` into a Tower layer.
+pub fn into_layer<M>(middleware: M) -> IntoLayer<M> {
+    IntoLayer { middleware }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct IntoLayer<M> {
+    middleware: M,
+}
+
+impl<S, M> Layer<S> for IntoLayer<M>
+where
+    M: Middleware<S>,
+{
+    type Service = M::Service;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        self.middleware.wrap(inner)
+    }
+}
+
+/// Creates a `Middleware` which modifies the incoming requests.
+pub fn map_request<F>(f: F) -> MapRequest<F> {
+    MapRequest { f }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct MapRequest<F> {
+    f: F,
+}
+
+impl<S, F> Middleware<S> for MapRequest<F>
+where
+    F: Clone,
+{
+    type Service = MapRequestService<S, F>;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        MapRequestService {
+            inner,
+            f: self.f.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct MapRequestService<S, F> {
+    inner: S,
+    f: F,
+}
+
+impl<S, F, Bd> Service<Request<Bd>> for MapRequestService<S, F>
+where
+    S: Service<Request<Bd>>,
+    F: Fn(&mut Request<Bd>),
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Request<Bd>) -> Self::Future {
+        (self.f)(&mut request);
+        self.inner.call(request)
+    }
+}
+
+/// Creates a `Middleware` which modifies the outgoing responses.
+pub fn map_response<F>(f: F) -> MapResponse<F> {
+    MapResponse { f }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct MapResponse<F> {
+    f: F,
+}
+
+impl<S, F> Middleware<S> for MapResponse<F>
+where
+    F: Clone,
+{
+    type Service = MapResponseService<S, F>;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        MapResponseService {
+            inner,
+            f: self.f.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct MapResponseService<S, F> {
+    inner: S,
+    f: F,
+}
+
+impl<S, F, Req, Bd, T> Service<Req> for MapResponseService<S, F>
+where
+    S: Service<Req, Response = Response<Bd>>,
+    F: Fn(Response<Bd>) -> Response<T> + Clone,
+{
+    type Response = Response<T>;
+    type Error = S::Error;
+    type Future = MapResponseFuture<S::Future, F>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        MapResponseFuture {
+            future: self.inner.call(request),
+            f: Some(self.f.clone()),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct MapResponseFuture<Fut, F> {
+    future: Fut,
+    f: Option<F>,
+}
+
+impl<Fut, F, Bd, T> Future for MapResponseFuture<Fut, F>
+where
+    Fut: Future<Item = Response<Bd>>,
+    F: Fn(Response<Bd>) -> Response<T>,
+{
+    type Item = Response<T>;
+    type Error = Fut::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = futures::try_ready!(self.future.poll());
+        let f = self.f.take().expect("the future has already polled");
+        Ok(f(response).into())
+    }
+}
+
+/// Creates a `Middleware` which responds without calling the inner service
+/// if the specified function returns a response.
+///
+/// ```no_run
+/// # use finchers::prelude::*;
+/// # use finchers::middleware;
+/// # use finchers::server::RequestBody;
+/// # use finchers::service::App;
+/// # use http::{Response, StatusCode};
+/// # let endpoint = endpoint::unit().map(|| "Hello");
+/// let app = App::new(endpoint).with_middleware(middleware::short_circuit(
+///     |request: &http::Request<RequestBody>| {
+///         if request.headers().contains_key("authorization") {
+///             return None;
+///         }
+///         let mut response = Response::new("unauthorized");
+///         *response.status_mut() = StatusCode::UNAUTHORIZED;
+///         Some(response)
+///     },
+/// ));
+/// # finchers::server::Server::new(app).bind("127.0.0.1:4000").serve().unwrap();
+/// ```
+///
+/// The body of the responses is `Either<T, Bd>`, where `T` is the type of body
+/// returned from the function and `Bd` is the one of the inner service.
+pub fn short_circuit<F>(f: F) -> ShortCircuit<F> {
+    ShortCircuit { f }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct ShortCircuit<F> {
+    f: F,
+}
+
+impl<S, F> Middleware<S> for ShortCircuit<F>
+where
+    F: Clone,
+{
+    type Service = ShortCircuitService<S, F>;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        ShortCircuitService {
+            inner,
+            f: self.f.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct ShortCircuitService<S, F> {
+    inner: S,
+    f: F,
+}
+
+impl<S, F, Bd, RespBd, T> Service<Request<Bd>> for ShortCircuitService<S, F>
+where
+    S: Service<Request<Bd>, Response = Response<RespBd>>,
+    F: Fn(&Request<Bd>) -> Option<Response<T>>,
+{
+    type Response = Response<Either<T, RespBd>>;
+    type Error = S::Error;
+    type Future = ShortCircuitFuture<S::Future, T>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Request<Bd>) -> Self::Future {
+        match (self.f)(&request) {
+            Some(response) => ShortCircuitFuture {
+                inner: None,
+                response: Some(response),
+            },
+            None => ShortCircuitFuture {
+                inner: Some(self.inner.call(request)),
+                response: None,
+            },
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct ShortCircuitFuture<Fut, T> {
+    inner: Option<Fut>,
+    response: Option<Response<T>>,
+}
+
+impl<Fut, Bd, T> Future for ShortCircuitFuture<Fut, T>
+where
+    Fut: Future<Item = Response<Bd>>,
+{
+    type Item = Response<Either<T, Bd>>;
+    type Error = Fut::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref mut inner) = self.inner {
+            let response = futures::try_ready!(inner.poll());
+            return Ok(Async::Ready(response.map(Either::Right)));
+        }
+        let response = self.response.take().expect("the future has already polled");
+        Ok(Async::Ready(response.map(Either::Left)))
+    }
+}
+
+/// Creates a `Middleware` which observes the errors returned from the inner service.
+///
+/// The errors are passed to the specified function by reference, and then
+/// returned to the server as they are.
+pub fn observe_error<F>(f: F) -> ObserveError<F> {
+    ObserveError { f }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Copy, Clone)]
+pub struct ObserveError<F> {
+    f: F,
+}
+
+impl<S, F> Middleware<S> for ObserveError<F>
+where
+    F: Clone,
+{
+    type Service = ObserveErrorService<S, F>;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        ObserveErrorService {
+            inner,
+            f: self.f.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct ObserveErrorService<S, F> {
+    inner: S,
+    f: F,
+}
+
+impl<S, F, Req> Service<Req> for ObserveErrorService<S, F>
+where
+    S: Service<Req>,
+    F: Fn(&S::Error) + Clone,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ObserveErrorFuture<S::Future, F>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(|err| {
+            (self.f)(&err);
+            err
+        })
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        ObserveErrorFuture {
+            future: self.inner.call(request),
+            f: self.f.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct ObserveErrorFuture<Fut, F> {
+    future: Fut,
+    f: F,
+}
+
+impl<Fut, F> Future for ObserveErrorFuture<Fut, F>
+where
+    Fut: Future,
+    F: Fn(&Fut::Error),
+{
+    type Item = Fut::Item;
+    type Error = Fut::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.future.poll().map_err(|err| {
+            (self.f)(&err);
+            err
+        })
+    }
+}
+
+/// Creates a `Middleware` which overrides the method of `POST` requests
+/// with the one specified by the client.
+///
+/// This is intended for clients which can only send `GET` and `POST`, such as
+/// HTML forms. The method is taken from the `X-HTTP-Method-Override` header,
+/// the `_method` parameter in the query string or the `_method` field in
+/// the `application/x-www-form-urlencoded` request body, and is applied only
+/// if it is contained in the allowlist (`PUT`, `PATCH` and `DELETE` by default).
+/// Since the rewrite happens before routing, the matchers in
+/// `endpoint::syntax::verb` see the overridden method.
+///
+/// In order to look up the form field, the request body is buffered before
+/// calling the inner service, and then passed to it as the body created from
+/// the buffered bytes. The body is buffered only if the other lookups did not
+/// find the method and the `Content-Length` does not exceed the limit
+/// (8 KiB by default); the method in the larger or chunked bodies is ignored.
+///
+/// The original method is stored in the extensions of the request as
+/// `OriginalMethod`.
+pub fn method_override() -> MethodOverride {
+    MethodOverride {
+        config: Arc::new(MethodOverrideConfig {
+            allowed: vec![Method::PUT, Method::PATCH, Method::DELETE],
+            header: Some(HeaderName::from_static("x-http-method-override")),
+            query_param: Some("_method".into()),
+            form_field: Some("_method".into()),
+            max_body_size: 8 * 1024,
+        }),
+    }
+}
+
+/// The method of the request before overridden by `MethodOverride`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OriginalMethod(pub Method);
+
+#[derive(Debug, Clone)]
+struct MethodOverrideConfig {
+    allowed: Vec<Method>,
+    header: Option<HeaderName>,
+    query_param: Option<String>,
+    form_field: Option<String>,
+    max_body_size: usize,
+}
+
+impl MethodOverrideConfig {
+    fn parse_method(method: &str) -> Option<Method> {
+        Method::from_bytes(method.trim().to_ascii_uppercase().as_bytes()).ok()
+    }
+
+    fn requested<Bd>(&self, request: &Request<Bd>) -> Option<Method> {
+        let from_header = self.header.as_ref().and_then(|name| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned)
+        });
+        let from_query = || {
+            let param = self.query_param.as_ref()?;
+            url::form_urlencoded::parse(request.uri().query()?.as_bytes())
+                .find(|(key, _)| key == param)
+                .map(|(_, value)| value.into_owned())
+        };
+        let method = from_header.or_else(from_query)?;
+        Self::parse_method(&method)
+    }
+
+    fn requested_in_body(&self, body: &[u8]) -> Option<Method> {
+        let field = self.form_field.as_ref()?;
+        let (_, method) = url::form_urlencoded::parse(body).find(|(key, _)| key == field)?;
+        Self::parse_method(&method)
+    }
+
+    /// Returns whether the request body should be buffered to look up the form field.
+    fn buffers_body<Bd>(&self, request: &Request<Bd>) -> bool {
+        if self.form_field.is_none() {
+            return false;
+        }
+        let is_form = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<mime::Mime>().ok())
+            .map_or(false, |mime| {
+                mime.essence_str() == "application/x-www-form-urlencoded"
+            });
+        let content_length = request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        is_form && content_length.map_or(false, |len| len <= self.max_body_size)
+    }
+
+    fn apply<Bd>(&self, request: &mut Request<Bd>, method: Method) {
+        if !self.allowed.contains(&method) {
+            log::debug!("the method override to {} is not allowed", method);
+            return;
+        }
+        if let Some(ref name) = self.header {
+            request.headers_mut().remove(name);
+        }
+        let original = std::mem::replace(request.method_mut(), method);
+        request.extensions_mut().insert(OriginalMethod(original));
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct MethodOverride {
+    config: Arc<MethodOverrideConfig>,
+}
+
+impl MethodOverride {
+    fn configure(self, f: impl FnOnce(&mut MethodOverrideConfig)) -> Self {
+        let mut config = Arc::try_unwrap(self.config).unwrap_or_else(|config| (*config).clone());
+        f(&mut config);
+        MethodOverride {
+            config: Arc::new(config),
+        }
+    }
+
+    /// Sets the list of the methods which the requests can be overridden to.
+    pub fn allow(self, methods: &[Method]) -> Self {
+        self.configure(|config| config.allowed = methods.to_vec())
+    }
+
+    /// Sets the name of the header which specifies the method, or disables
+    /// the lookup if `None` is given.
+    ///
+    /// The default value is `Some("x-http-method-override")`.
+    ///
+    /// # Panics
+    /// This method panics if the specified name is not a valid header name.
+    pub fn header(self, name: Option<&str>) -> Self {
+        let name =
+            name.map(|name| HeaderName::from_bytes(name.as_bytes()).expect("invalid header name"));
+        self.configure(|config| config.header = name)
+    }
+
+    /// Sets the name of the query parameter which specifies the method,
+    /// or disables the lookup if `None` is given.
+    ///
+    /// The default value is `Some("_method")`.
+    pub fn query_param(self, name: Option<&str>) -> Self {
+        self.configure(|config| config.query_param = name.map(Into::into))
+    }
+
+    /// Sets the name of the field in the form body which specifies the method,
+    /// or disables the lookup (and the buffering of the body) if `None` is given.
+    ///
+    /// The default value is `Some("_method")`.
+    pub fn form_field(self, name: Option<&str>) -> Self {
+        self.configure(|config| config.form_field = name.map(Into::into))
+    }
+
+    /// Sets the maximum size of the request body buffered to look up the form field.
+    ///
+    /// The default value is 8 KiB.
+    pub fn max_body_size(self, max_body_size: usize) -> Self {
+        self.configure(|config| config.max_body_size = max_body_size)
+    }
+}
+
+impl<S> Middleware<S> for MethodOverride {
+    type Service = MethodOverrideService<S>;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        MethodOverrideService {
+            inner: Arc::new(Mutex::new(inner)),
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct MethodOverrideService<S> {
+    // shared with the futures, which call it after buffering the request body.
+    inner: Arc<Mutex<S>>,
+    config: Arc<MethodOverrideConfig>,
+}
+
+impl<S, Bd> Service<Request<Bd>> for MethodOverrideService<S>
+where
+    S: Service<Request<Bd>>,
+    S::Error: From<io::Error>,
+    Bd: BufStream + From<Bytes>,
+    Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = MethodOverrideFuture<S, Bd>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Request<Bd>) -> Self::Future {
+        if request.method() == Method::POST {
+            match self.config.requested(&request) {
+                Some(method) => self.config.apply(&mut request, method),
+                None if self.config.buffers_body(&request) => {
+                    let (parts, body) = request.into_parts();
+                    return MethodOverrideFuture {
+                        state: MethodOverrideState::Buffering {
+                            parts: Some(parts),
+                            body,
+                            buf: BytesMut::new(),
+                        },
+                        inner: self.inner.clone(),
+                        config: self.config.clone(),
+                    };
+                }
+                None => {}
+            }
+        }
+
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        MethodOverrideFuture {
+            state: MethodOverrideState::Calling(inner.call(request)),
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[allow(missing_debug_implementations)]
+pub struct MethodOverrideFuture<S, Bd>
+where
+    S: Service<Request<Bd>>,
+{
+    state: MethodOverrideState<S::Future, Bd>,
+    inner: Arc<Mutex<S>>,
+    config: Arc<MethodOverrideConfig>,
+}
+
+enum MethodOverrideState<Fut, Bd> {
+    Buffering {
+        parts: Option<http::request::Parts>,
+        body: Bd,
+        buf: BytesMut,
+    },
+    Pending(Option<Request<Bd>>),
+    Calling(Fut),
+}
+
+impl<S, Bd> Future for MethodOverrideFuture<S, Bd>
+where
+    S: Service<Request<Bd>>,
+    S::Error: From<io::Error>,
+    Bd: BufStream + From<Bytes>,
+    Bd::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+    type Item = S::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                MethodOverrideState::Buffering {
+                    ref mut parts,
+                    ref mut body,
+                    ref mut buf,
+                } => {
+                    while let Some(data) = futures::try_ready!(body
+                        .poll_buf()
+                        .map_err(|err| io::Error::new(io::ErrorKind::Other, err)))
+                    {
+                        buf.extend_from_slice(data.bytes());
+                        if buf.len() > self.config.max_body_size {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "the request body is too large",
+                            )
+                            .into());
+                        }
+                    }
+                    let body = buf.take().freeze();
+                    let method = self.config.requested_in_body(&body);
+                    let parts = parts.take().expect("the future has already polled");
+                    let mut request = Request::from_parts(parts, Bd::from(body));
+                    if let Some(method) = method {
+                        self.config.apply(&mut request, method);
+                    }
+                    MethodOverrideState::Pending(Some(request))
+                }
+                MethodOverrideState::Pending(ref mut request) => {
+                    let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+                    futures::try_ready!(inner.poll_ready());
+                    let request = request.take().expect("the future has already polled");
+                    MethodOverrideState::Calling(inner.call(request))
+                }
+                MethodOverrideState::Calling(ref mut future) => return future.poll(),
+            };
+        }
+    }
+}
+
+/// A `MakeService` which applies a `Middleware` to the services created by
+/// the inner `MakeService`.
+#[derive(Debug)]
+pub struct WithMiddleware<S, M> {
+    make_service: S,
+    middleware: M,
+}
+
+impl<S, M> WithMiddleware<S, M> {
+    /// Creates a new `WithMiddleware` from the specified components.
+    pub fn new(make_service: S, middleware: M) -> Self {
+        WithMiddleware {
+            make_service,
+            middleware,
+        }
+    }
+
+    /// Appends a middleware which wraps the current ones.
+    pub fn with_middleware<M2>(self, middleware: M2) -> WithMiddleware<S, Chain<M, M2>> {
+        WithMiddleware {
+            make_service: self.make_service,
+            middleware: Chain::new(self.middleware, middleware),
+        }
+    }
+}
+
+impl<S, M, Ctx, Req> MakeService<Ctx, Req> for WithMiddleware<S, M>
+where
+    S: MakeService<Ctx, Req>,
+    M: Middleware<S::Service> + Clone,
+    M::Service: Service<Req>,
+{
+    type Response = <M::Service as Service<Req>>::Response;
+    type Error = <M::Service as Service<Req>>::Error;
+    type Service = M::Service;
+    type MakeError = S::MakeError;
+    type Future = WithMiddlewareFuture<S::Future, M>;
+
+    fn make_service(&self, ctx: Ctx) -> Self::Future {
+        WithMiddlewareFuture {
+            future: self.make_service.make_service(ctx),
+            middleware: Some(self.middleware.clone()),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct WithMiddlewareFuture<Fut, M> {
+    future: Fut,
+    middleware: Option<M>,
+}
+
+impl<Fut, M> Future for WithMiddlewareFuture<Fut, M>
+where
+    Fut: Future,
+    M: Middleware<Fut::Item>,
+{
+    type Item = M::Service;
+    type Error = Fut::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let service = futures::try_ready!(self.future.poll());
+        let middleware = self
+            .middleware
+            .take()
+            .expect("the future has already polled");
+        Ok(middleware.wrap(service).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            endpoint::{self, EndpointExt},
+            service::EndpointServiceExt,
+        },
+        futures::future,
+        http::{header::HeaderValue, StatusCode},
+        izanami_util::buf_stream::BufStream,
+        tokio::runtime::current_thread::Runtime,
+    };
+
+    pub(super) fn call<S, ReqBd, Bd>(make_service: &S, request: Request<ReqBd>) -> Response<String>
+    where
+        S: MakeService<(), Request<ReqBd>, Response = Response<Bd>>,
+        S::Error: std::fmt::Debug,
+        S::MakeError: std::fmt::Debug,
+        Bd: BufStream,
+        Bd::Error: std::fmt::Debug,
+    {
+        let mut rt = Runtime::new().unwrap();
+        let mut service = rt.block_on(make_service.make_service(())).unwrap();
+        let response = rt.block_on(service.call(request)).unwrap();
+        let (parts, mut body) = response.into_parts();
+        let mut buf = vec![];
+        rt.block_on(future::poll_fn(|| {
+            while let Some(chunk) = futures::try_ready!(body.poll_buf()) {
+                buf.extend_from_slice(bytes::Buf::bytes(&chunk));
+            }
+            Ok::<_, Bd::Error>(().into())
+        }))
+        .unwrap();
+        Response::from_parts(parts, String::from_utf8(buf).unwrap())
+    }
+
+    #[test]
+    fn test_ordering() {
+        let append = |value: &'static str| {
+            move |request: &mut Request<()>| {
+                request
+                    .headers_mut()
+                    .append("x-trace", HeaderValue::from_static(value));
+            }
+        };
+
+        let app = endpoint::endpoint(|| {
+            use crate::action::{OneshotAction, PreflightContext};
+
+            struct TraceAction;
+
+            impl OneshotAction for TraceAction {
+                type Output = (String,);
+
+                fn preflight(
+                    self,
+                    cx: &mut PreflightContext<'_>,
+                ) -> crate::error::Result<Self::Output> {
+                    let trace = cx
+                        .request()
+                        .headers()
+                        .get_all("x-trace")
+                        .iter()
+                        .map(|v| v.to_str().unwrap())
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    Ok((trace,))
+                }
+            }
+
+            TraceAction.into_action()
+        })
+        .into_service()
+        .with_middleware(map_request(append("a")))
+        .with_middleware(map_request(append("b")));
+
+        let response = call(&app, Request::new(()));
+        assert_eq!(response.body(), "b,a");
+    }
+
+    #[test]
+    fn test_map_response() {
+        let app = endpoint::syntax::segment("foo")
+            .map(|| "foo")
+            .into_service()
+            .with_middleware(map_response(|mut response: Response<_>| {
+                let status = response.status().as_str().parse().unwrap();
+                response.headers_mut().insert("x-status", status);
+                response
+            }));
+
+        let response = call(&app, Request::get("/foo").body(()).unwrap());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-status"], "200");
+        assert_eq!(response.body(), "foo");
+
+        let response = call(&app, Request::get("/bar").body(()).unwrap());
+        assert_eq!(response.headers()["x-status"], "404");
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let app = endpoint::syntax::segment("foo")
+            .map(|| "foo")
+            .into_service()
+            .with_middleware(short_circuit(|request: &Request<()>| {
+                if request.headers().contains_key("authorization") {
+                    return None;
+                }
+                let mut response = Response::new("unauthorized");
+                *response.status_mut() = StatusCode::UNAUTHORIZED;
+                Some(response)
+            }));
+
+        let response = call(&app, Request::get("/foo").body(()).unwrap());
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.body(), "unauthorized");
+
+        let response = call(
+            &app,
+            Request::get("/foo")
+                .header("authorization", "Bearer xxx")
+                .body(())
+                .unwrap(),
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "foo");
+    }
+
+    #[test]
+    fn test_observe_error() {
+        struct Failing;
+
+        impl Service<Request<()>> for Failing {
+            type Response = Response<()>;
+            type Error = &'static str;
+            type Future = future::FutureResult<Self::Response, Self::Error>;
+
+            fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+                Ok(Async::Ready(()))
+            }
+
+            fn call(&mut self, _: Request<()>) -> Self::Future {
+                future::err("failed")
+            }
+        }
+
+        let observed = Arc::new(std::sync::Mutex::new(vec![]));
+        let middleware = observe_error({
+            let observed = observed.clone();
+            move |err: &&'static str| observed.lock().unwrap().push(*err)
+        });
+        let mut service = into_layer(middleware).layer(Failing);
+
+        let result = service.call(Request::new(())).wait();
+        assert_eq!(result.err(), Some("failed"));
+        assert_eq!(*observed.lock().unwrap(), vec!["failed"]);
+    }
+
+    #[test]
+    fn test_method_override() {
+        use crate::server::RequestBody;
+
+        let app = endpoint::syntax::verb::delete()
+            .and(crate::endpoints::body::text())
+            .map(|body: String| format!("deleted: {}", body))
+            .or(endpoint::syntax::verb::post().map(|| "posted"))
+            .into_service()
+            .with_middleware(method_override().allow(&[http::Method::DELETE]));
+
+        let empty = || RequestBody::from(Bytes::new());
+        let form = |body: &'static str| {
+            Request::post("/")
+                .header("content-type", "application/x-www-form-urlencoded")
+                .header("content-length", body.len().to_string())
+                .body(RequestBody::from(Bytes::from_static(body.as_bytes())))
+                .unwrap()
+        };
+
+        let response = call(
+            &app,
+            Request::post("/")
+                .header("x-http-method-override", "DELETE")
+                .body(empty())
+                .unwrap(),
+        );
+        assert_eq!(response.body(), "deleted: ");
+
+        let response = call(
+            &app,
+            Request::post("/?_method=delete").body(empty()).unwrap(),
+        );
+        assert_eq!(response.body(), "deleted: ");
+
+        // the buffered body is passed to the endpoint
+        let response = call(&app, form("name=alice&_method=DELETE"));
+        assert_eq!(response.body(), "deleted: name=alice&_method=DELETE");
+
+        // not in the allowlist
+        let response = call(&app, Request::post("/?_method=PUT").body(empty()).unwrap());
+        assert_eq!(response.body(), "posted");
+
+        // only POST requests are overridden
+        let response = call(
+            &app,
+            Request::get("/?_method=DELETE").body(empty()).unwrap(),
+        );
+        assert!(response.status().is_client_error());
+
+        // the bodies larger than the limit are not buffered
+        let app = endpoint::syntax::verb::delete()
+            .map(|| "deleted")
+            .or(endpoint::syntax::verb::post().map(|| "posted"))
+            .into_service()
+            .with_middleware(method_override().max_body_size(8));
+        let response = call(&app, form("name=alice&_method=DELETE"));
+        assert_eq!(response.body(), "posted");
+    }
+
+    #[test]
+    fn test_har() {
+        use self::{audit::audit, har::HarRecorder};
+
+        let recorder = HarRecorder::new(10);
+        let app = audit(recorder.clone()).wrap_make_service(
+            crate::endpoint::syntax::segment("har")
+                .and(recorder.endpoint())
+                .or(crate::endpoint::unit().map(|| "Hello"))
+                .into_service(),
+        );
+
+        let response = call(
+            &app,
+            Request::get("/hello?name=alice&access_token=secret")
+                .header("host", "example.com")
+                .body("")
+                .unwrap(),
+        );
+        assert_eq!(response.body(), "Hello");
+
+        let response = call(&app, Request::get("/har").body("").unwrap());
+        let har: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+        let entries = har["log"]["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0]["request"]["url"],
+            "http://example.com/hello?name=alice&access_token=%5BREDACTED%5D"
+        );
+        assert_eq!(
+            entries[0]["request"]["queryString"][1]["value"],
+            "[REDACTED]"
+        );
+        assert_eq!(entries[0]["response"]["content"]["text"], "Hello");
+        assert_eq!(recorder.len(), 2);
+    }
+
+    #[test]
+    fn test_access_log() {
+        use self::access_log::{access_log, Format};
+
+        let lines = Arc::new(std::sync::Mutex::new(vec![]));
+        let app = crate::endpoint::syntax::segment("missing")
+            .and_then(|| Err::<&'static str, _>(crate::error::not_found("missing")))
+            .or(endpoint::unit().map(|| "Hello"))
+            .into_service()
+            .with_middleware(
+                access_log()
+                    .format(Format::Json)
+                    .sample_class(2, 0.5)
+                    .sink({
+                        let lines = lines.clone();
+                        move |line: String| lines.lock().unwrap().push(line)
+                    }),
+            );
+
+        for _ in 0..4 {
+            let response = call(&app, Request::get("/").body(()).unwrap());
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = call(
+            &app,
+            Request::get("/missing")
+                .header("user-agent", "curl/7.61.0")
+                .body(())
+                .unwrap(),
+        );
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let lines = lines.lock().unwrap();
+        let entries: Vec<serde_json::Value> = lines
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["status"], 200);
+        assert_eq!(entries[0]["sample_rate"], 0.5);
+        assert_eq!(entries[0]["size"], 5);
+        assert_eq!(entries[2]["status"], 404);
+        assert_eq!(entries[2]["uri"], "/missing");
+        assert_eq!(entries[2]["user_agent"], "curl/7.61.0");
+        assert_eq!(entries[2]["sample_rate"], 1.0);
+    }
+
+    #[test]
+    fn test_shadow() {
+        use {self::shadow::shadow, bytes::Bytes};
+
+        let mirrored = Arc::new(std::sync::Mutex::new(vec![]));
+        let app = shadow({
+            let mirrored = mirrored.clone();
+            move |request: Request<Bytes>| {
+                mirrored.lock().unwrap().push(request);
+                Err(failure::err_msg("the shadow errors are not leaked"))
+            }
+        })
+        .sample_rate(0.5)
+        .max_body_size(16)
+        .wrap_make_service(
+            crate::endpoints::body::text()
+                .map(|body: String| format!("echo: {}", body))
+                .into_service(),
+        );
+
+        for i in 0..4 {
+            let response = call(
+                &app,
+                Request::post("/echo")
+                    .header("connection", "keep-alive")
+                    .body(format!("body {}", i))
+                    .unwrap(),
+            );
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.body(), &format!("echo: body {}", i));
+        }
+        // the requests with too large body are not mirrored.
+        for _ in 0..2 {
+            let response = call(&app, Request::post("/echo").body("x".repeat(32)).unwrap());
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let mirrored = mirrored.lock().unwrap();
+        assert_eq!(mirrored.len(), 2);
+        assert_eq!(mirrored[0].method(), Method::POST);
+        assert_eq!(mirrored[0].uri(), "/echo");
+        assert_eq!(mirrored[0].headers()["x-shadow-request"], "1");
+        assert!(!mirrored[0].headers().contains_key("connection"));
+        assert_eq!(mirrored[0].body(), "body 1");
+        assert_eq!(mirrored[1].body(), "body 3");
+    }
+
+    #[test]
+    fn test_shadow_service() {
+        use self::shadow::{self, shadow};
+
+        let received = Arc::new(std::sync::Mutex::new(vec![]));
+        let target = shadow::service(
+            crate::endpoints::body::text()
+                .map({
+                    let received = received.clone();
+                    move |body: String| {
+                        received.lock().unwrap().push(body);
+                        "ignored"
+                    }
+                })
+                .into_service(),
+        );
+        let app = shadow(target).wrap_make_service(
+            crate::endpoints::body::text()
+                .map(|body: String| format!("echo: {}", body))
+                .into_service(),
+        );
+
+        let mut rt = Runtime::new().unwrap();
+        let mut service = rt.block_on(app.make_service(())).unwrap();
+        let response = rt
+            .block_on(service.call(Request::post("/").body("Hello").unwrap()))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        drop((service, response));
+        rt.run().unwrap();
+
+        assert_eq!(*received.lock().unwrap(), vec!["Hello".to_owned()]);
+    }
+}
@@ -0,0 +1,674 @@
+//! A middleware which writes an access log line for each request.
+//!
+//! The lines are formatted in either the Common Log Format or JSON (one object
+//! per request), and passed to a `LogSink`. The default sink writes them via
+//! the `log` crate at the `INFO` level, with the target `finchers::access_log`.
+//!
+//! The line is written when the response body has been sent (or dropped),
+//! so that it records the number of bytes reported by `BodyLength`.
+//!
+//! # Sampling
+//!
+//! The services handling a large amount of traffic can reduce the number of
+//! lines by sampling them according to the status code. The sampling is
+//! systematic, i.e. with the rate `0.01`, every 100th request of the matching
+//! status is logged. The rate is also recorded in the JSON lines, so that the
+//! consumers can weight the sampled entries.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use finchers::prelude::*;
+//! # use finchers::middleware::access_log::{self, Format};
+//! # use finchers::server::Server;
+//! # use finchers::service::App;
+//! # let endpoint = endpoint::unit().map(|| "Hello");
+//! // the lines are received by another task, e.g. the one which ships them.
+//! let (tx, rx) = futures::sync::mpsc::unbounded::<String>();
+//! # drop(rx);
+//!
+//! let app = App::new(endpoint).with_middleware(
+//!     access_log::access_log()
+//!         .format(Format::Json)
+//!         .sample_class(5, 1.0) // all of 5xx
+//!         .sample_class(2, 0.01) // 1% of 2xx
+//!         .sink(tx),
+//! );
+//!
+//! Server::new(app)
+//!     .bind("127.0.0.1:4000")
+//!     .serve()
+//!     .expect("failed to start the server");
+//! ```
+
+use {
+    crate::{
+        output::BodyLength,
+        util::{civil_from_days, millis},
+    },
+    futures::{sync::mpsc::UnboundedSender, Async, Future, Poll},
+    http::{
+        header::{self, HeaderMap},
+        Method, Request, Response, StatusCode, Uri, Version,
+    },
+    izanami_service::Service,
+    izanami_util::{
+        buf_stream::{BufStream, SizeHint},
+        http::{HasTrailers, Upgrade},
+    },
+    serde::Serialize,
+    std::{
+        fmt,
+        io::Write,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+// ==== LogSink ====
+
+/// A trait representing the destination of the access log lines.
+///
+/// This trait is implemented for the functions which take a line, and for
+/// `UnboundedSender<String>` so that the lines can be shipped to an
+/// asynchronous writer. The sink is called on the task handling the request,
+/// so it should not block.
+pub trait LogSink: Send + Sync + 'static {
+    /// Writes a line, without the trailing newline.
+    fn write(&self, line: String);
+}
+
+impl<F> LogSink for F
+where
+    F: Fn(String) + Send + Sync + 'static,
+{
+    fn write(&self, line: String) {
+        (*self)(line)
+    }
+}
+
+impl LogSink for UnboundedSender<String> {
+    fn write(&self, line: String) {
+        // The lines are dropped after the receiver has been closed.
+        let _ = self.unbounded_send(line);
+    }
+}
+
+/// A `LogSink` which writes the lines via the `log` crate.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Logger(());
+
+impl LogSink for Logger {
+    fn write(&self, line: String) {
+        log::info!(target: "finchers::access_log", "{}", line);
+    }
+}
+
+/// A `LogSink` which writes the lines to the standard output.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Stdout(());
+
+impl LogSink for Stdout {
+    fn write(&self, line: String) {
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        let _ = writeln!(stdout, "{}", line);
+    }
+}
+
+// ==== AccessLogEntry ====
+
+/// The format of the access log lines.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// The Common Log Format, e.g. `- - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1" 200 2326`.
+    ///
+    /// Since the middleware does not know the address of the client, the
+    /// first field is always `-`.
+    Common,
+
+    /// A JSON object per line, which has the fields of `AccessLogEntry`.
+    Json,
+}
+
+/// An entry of the access log.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogEntry {
+    /// The time when the request was received, in ISO 8601.
+    pub timestamp: String,
+    /// The method of the request.
+    pub method: String,
+    /// The URI of the request.
+    pub uri: String,
+    /// The HTTP version of the request.
+    pub version: String,
+    /// The status code of the response.
+    pub status: u16,
+    /// The time elapsed until the response head was ready, in milliseconds.
+    pub duration_ms: f64,
+    /// The number of bytes of the response body which have been sent.
+    ///
+    /// If the inner service does not report it via `BodyLength`, the size
+    /// known in advance is used instead.
+    pub size: Option<u64>,
+    /// The value of `User-Agent`.
+    pub user_agent: Option<String>,
+    /// The value of `Referer`.
+    pub referer: Option<String>,
+    /// The rate at which the entries with the same status are sampled.
+    pub sample_rate: f64,
+}
+
+impl AccessLogEntry {
+    fn new(request: &RequestInfo, status: StatusCode, size: Option<u64>, rate: f64) -> Self {
+        let header = |name: header::HeaderName| {
+            request
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned)
+        };
+        AccessLogEntry {
+            timestamp: format_iso8601(request.timestamp),
+            method: request.method.to_string(),
+            uri: request.uri.to_string(),
+            version: format!("{:?}", request.version),
+            status: status.as_u16(),
+            duration_ms: millis(request.started.elapsed()),
+            size,
+            user_agent: header(header::USER_AGENT),
+            referer: header(header::REFERER),
+            sample_rate: rate,
+        }
+    }
+
+    /// Formats this entry into a line.
+    pub fn format(&self, format: Format) -> String {
+        match format {
+            Format::Common => format!(
+                "- - - [{}] \"{} {} {}\" {} {}",
+                format_clf_date(&self.timestamp),
+                self.method,
+                self.uri,
+                self.version,
+                self.status,
+                self.size
+                    .map_or_else(|| "-".into(), |size| size.to_string()),
+            ),
+            Format::Json => serde_json::to_string(self).expect("should be serializable"),
+        }
+    }
+}
+
+/// Formats the time in ISO 8601, e.g. `2000-10-10T13:55:36.123Z`.
+fn format_iso8601(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        elapsed.subsec_millis(),
+    )
+}
+
+/// Converts the timestamp in ISO 8601 into the one used in the Common Log
+/// Format, e.g. `10/Oct/2000:13:55:36 +0000`.
+fn format_clf_date(timestamp: &str) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let month: usize = timestamp[5..7].parse().unwrap_or(1);
+    format!(
+        "{}/{}/{}:{} +0000",
+        &timestamp[8..10],
+        MONTHS[month - 1],
+        &timestamp[0..4],
+        &timestamp[11..19],
+    )
+}
+
+// ==== AccessLog ====
+
+/// Creates a `Middleware` which writes the access logs.
+///
+/// By default, the lines are formatted in the Common Log Format, all of the
+/// requests are logged, and the lines are written via the `log` crate.
+pub fn access_log() -> AccessLog<Logger> {
+    AccessLog {
+        sink: Arc::new(Logger(())),
+        config: Arc::new(Config {
+            format: Format::Common,
+            rules: vec![],
+            default_rate: Sampler::new(1.0),
+        }),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    format: Format,
+    rules: Vec<(Statuses, Sampler)>,
+    default_rate: Sampler,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Statuses {
+    Exact(u16),
+    Class(u16),
+}
+
+impl Statuses {
+    fn matches(self, status: StatusCode) -> bool {
+        match self {
+            Statuses::Exact(code) => status.as_u16() == code,
+            Statuses::Class(class) => status.as_u16() / 100 == class,
+        }
+    }
+}
+
+/// The systematic sampler shared among the services.
+#[derive(Debug, Clone)]
+pub(super) struct Sampler {
+    rate: f64,
+    count: Arc<AtomicUsize>,
+}
+
+impl Sampler {
+    pub(super) fn new(rate: f64) -> Self {
+        assert!(
+            rate >= 0.0 && rate <= 1.0,
+            "the sampling rate must be between 0 and 1"
+        );
+        Sampler {
+            rate,
+            count: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub(super) fn sample(&self) -> bool {
+        let n = self.count.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * self.rate).floor() > (n * self.rate).floor()
+    }
+}
+
+impl Config {
+    fn sampler(&self, status: StatusCode) -> &Sampler {
+        // The exact rules take precedence over the class ones.
+        let exact = self.rules.iter().find(|(statuses, _)| match statuses {
+            Statuses::Exact(..) => statuses.matches(status),
+            _ => false,
+        });
+        exact
+            .or_else(|| {
+                self.rules
+                    .iter()
+                    .find(|(statuses, _)| statuses.matches(status))
+            })
+            .map_or(&self.default_rate, |(_, sampler)| sampler)
+    }
+}
+
+#[allow(missing_docs)]
+pub struct AccessLog<S> {
+    sink: Arc<S>,
+    config: Arc<Config>,
+}
+
+impl<S> Clone for AccessLog<S> {
+    fn clone(&self) -> Self {
+        AccessLog {
+            sink: self.sink.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for AccessLog<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl<S> AccessLog<S> {
+    fn configure(mut self, f: impl FnOnce(&mut Config)) -> Self {
+        f(Arc::make_mut(&mut self.config));
+        self
+    }
+
+    /// Sets the format of the lines.
+    pub fn format(self, format: Format) -> Self {
+        self.configure(|config| config.format = format)
+    }
+
+    /// Sets the sampling rate of the responses with the specified status code.
+    ///
+    /// # Panics
+    /// This method panics if the rate is not between `0.0` and `1.0`.
+    pub fn sample_status(self, status: StatusCode, rate: f64) -> Self {
+        let sampler = Sampler::new(rate);
+        self.configure(|config| {
+            config
+                .rules
+                .push((Statuses::Exact(status.as_u16()), sampler))
+        })
+    }
+
+    /// Sets the sampling rate of the responses with the specified class of
+    /// status code, e.g. `2` for `2xx`.
+    ///
+    /// The rates set by `sample_status` take precedence over this one.
+    ///
+    /// # Panics
+    /// This method panics if the class is not between `1` and `5`, or the
+    /// rate is not between `0.0` and `1.0`.
+    pub fn sample_class(self, class: u16, rate: f64) -> Self {
+        assert!(
+            class >= 1 && class <= 5,
+            "the class of status code must be between 1 and 5"
+        );
+        let sampler = Sampler::new(rate);
+        self.configure(|config| config.rules.push((Statuses::Class(class), sampler)))
+    }
+
+    /// Sets the sampling rate of the responses not matched by the other rules.
+    ///
+    /// The default value is `1.0`.
+    ///
+    /// # Panics
+    /// This method panics if the rate is not between `0.0` and `1.0`.
+    pub fn default_sample_rate(self, rate: f64) -> Self {
+        let sampler = Sampler::new(rate);
+        self.configure(|config| config.default_rate = sampler)
+    }
+
+    /// Sets the sink to which the lines are written.
+    pub fn sink<T>(self, sink: T) -> AccessLog<T>
+    where
+        T: LogSink,
+    {
+        AccessLog {
+            sink: Arc::new(sink),
+            config: self.config,
+        }
+    }
+}
+
+impl<S, Snk> super::Middleware<S> for AccessLog<Snk> {
+    type Service = AccessLogService<S, Snk>;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        AccessLogService {
+            inner,
+            access_log: self.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct AccessLogService<S, Snk> {
+    inner: S,
+    access_log: AccessLog<Snk>,
+}
+
+impl<S, Snk, Bd, ResBd> Service<Request<Bd>> for AccessLogService<S, Snk>
+where
+    S: Service<Request<Bd>, Response = Response<ResBd>>,
+    ResBd: BufStream,
+    Snk: LogSink,
+{
+    type Response = Response<AccessLogBody<ResBd, Snk>>;
+    type Error = S::Error;
+    type Future = AccessLogFuture<S::Future, Snk>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Request<Bd>) -> Self::Future {
+        let info = RequestInfo {
+            timestamp: SystemTime::now(),
+            started: Instant::now(),
+            method: request.method().clone(),
+            uri: request.uri().clone(),
+            version: request.version(),
+            headers: request.headers().clone(),
+        };
+        AccessLogFuture {
+            future: self.inner.call(request),
+            request: Some(info),
+            access_log: self.access_log.clone(),
+        }
+    }
+}
+
+struct RequestInfo {
+    timestamp: SystemTime,
+    started: Instant,
+    method: Method,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+}
+
+#[allow(missing_docs)]
+pub struct AccessLogFuture<Fut, Snk> {
+    future: Fut,
+    request: Option<RequestInfo>,
+    access_log: AccessLog<Snk>,
+}
+
+impl<Fut, Snk> fmt::Debug for AccessLogFuture<Fut, Snk> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessLogFuture").finish()
+    }
+}
+
+impl<Fut, Snk, Bd> Future for AccessLogFuture<Fut, Snk>
+where
+    Fut: Future<Item = Response<Bd>>,
+    Bd: BufStream,
+    Snk: LogSink,
+{
+    type Item = Response<AccessLogBody<Bd, Snk>>;
+    type Error = Fut::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = futures::try_ready!(self.future.poll());
+        let request = self.request.take().expect("the future has already polled");
+
+        let config = &*self.access_log.config;
+        let sampler = config.sampler(response.status());
+        let pending = if sampler.sample() {
+            let size_hint = response.body().size_hint();
+            let size = response
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok()?.parse().ok())
+                .or_else(|| match size_hint.upper() {
+                    Some(upper) if upper == size_hint.lower() => Some(upper),
+                    _ => None,
+                });
+            Some(PendingEntry {
+                entry: AccessLogEntry::new(&request, response.status(), size, sampler.rate),
+                length: response.extensions().get::<BodyLength>().cloned(),
+            })
+        } else {
+            None
+        };
+
+        let access_log = self.access_log.clone();
+        Ok(Async::Ready(response.map(|inner| AccessLogBody {
+            inner,
+            pending,
+            access_log,
+        })))
+    }
+}
+
+// ==== AccessLogBody ====
+
+struct PendingEntry {
+    entry: AccessLogEntry,
+    length: Option<BodyLength>,
+}
+
+/// A message body which writes the access log line when it is finished,
+/// so that the line has the number of bytes actually sent.
+pub struct AccessLogBody<Bd, Snk: LogSink> {
+    inner: Bd,
+    pending: Option<PendingEntry>,
+    access_log: AccessLog<Snk>,
+}
+
+impl<Bd: fmt::Debug, Snk: LogSink> fmt::Debug for AccessLogBody<Bd, Snk> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessLogBody")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<Bd, Snk: LogSink> AccessLogBody<Bd, Snk> {
+    fn finish(&mut self) {
+        if let Some(PendingEntry { mut entry, length }) = self.pending.take() {
+            if let Some(length) = length {
+                entry.size = Some(length.bytes_written());
+            }
+            self.access_log
+                .sink
+                .write(entry.format(self.access_log.config.format));
+        }
+    }
+}
+
+impl<Bd, Snk> BufStream for AccessLogBody<Bd, Snk>
+where
+    Bd: BufStream,
+    Snk: LogSink,
+{
+    type Item = Bd::Item;
+    type Error = Bd::Error;
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.inner.poll_buf() {
+            Ok(Async::Ready(None)) => {
+                self.finish();
+                Ok(Async::Ready(None))
+            }
+            Err(err) => {
+                self.finish();
+                Err(err)
+            }
+            polled => polled,
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+
+    fn consume_hint(&mut self, amount: usize) {
+        self.inner.consume_hint(amount)
+    }
+}
+
+impl<Bd, Snk> HasTrailers for AccessLogBody<Bd, Snk>
+where
+    Bd: HasTrailers,
+    Snk: LogSink,
+{
+    type TrailersError = Bd::TrailersError;
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::TrailersError> {
+        self.inner.poll_trailers()
+    }
+}
+
+impl<Bd, Snk> Upgrade for AccessLogBody<Bd, Snk>
+where
+    Bd: Upgrade,
+    Snk: LogSink,
+{
+    type Upgraded = Bd::Upgraded;
+    type Error = Bd::Error;
+
+    fn poll_upgrade(&mut self) -> Poll<Self::Upgraded, Self::Error> {
+        self.inner.poll_upgrade()
+    }
+}
+
+impl<Bd, Snk: LogSink> Drop for AccessLogBody<Bd, Snk> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::time::Duration};
+
+    #[test]
+    fn test_sampler() {
+        let sampler = Sampler::new(0.25);
+        let sampled: Vec<bool> = (0..8).map(|_| sampler.sample()).collect();
+        assert_eq!(
+            sampled,
+            [false, false, false, true, false, false, false, true]
+        );
+
+        let sampler = Sampler::new(0.0);
+        assert!((0..100).all(|_| !sampler.sample()));
+    }
+
+    #[test]
+    fn test_rules() {
+        let config = access_log()
+            .sample_class(2, 0.0)
+            .sample_status(StatusCode::CREATED, 0.5)
+            .config;
+        assert_eq!(config.sampler(StatusCode::OK).rate, 0.0);
+        assert_eq!(config.sampler(StatusCode::CREATED).rate, 0.5);
+        assert_eq!(config.sampler(StatusCode::NOT_FOUND).rate, 1.0);
+    }
+
+    #[test]
+    fn test_format() {
+        let entry = AccessLogEntry {
+            timestamp: format_iso8601(UNIX_EPOCH + Duration::from_millis(971_186_136_123)),
+            method: "GET".into(),
+            uri: "/apache_pb.gif".into(),
+            version: "HTTP/1.0".into(),
+            status: 200,
+            duration_ms: 1.5,
+            size: Some(2326),
+            user_agent: None,
+            referer: None,
+            sample_rate: 1.0,
+        };
+        assert_eq!(entry.timestamp, "2000-10-10T13:55:36.123Z");
+        assert_eq!(
+            entry.format(Format::Common),
+            "- - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326"
+        );
+        assert_eq!(
+            entry.format(Format::Json),
+            "{\"timestamp\":\"2000-10-10T13:55:36.123Z\",\"method\":\"GET\",\
+             \"uri\":\"/apache_pb.gif\",\"version\":\"HTTP/1.0\",\"status\":200,\
+             \"duration_ms\":1.5,\"size\":2326,\"user_agent\":null,\"referer\":null,\
+             \"sample_rate\":1.0}"
+        );
+    }
+}
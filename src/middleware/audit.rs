@@ -0,0 +1,836 @@
+//! A middleware which records the requests and responses for audit logs.
+//!
+//! The `Audit` middleware tees the request and response bodies (up to
+//! the configured size) while they are streamed, and passes an `AuditRecord`
+//! to the `AuditSink` after the response body has been sent. The bodies are
+//! never buffered before being passed through, so the streaming responses
+//! are recorded without delaying them.
+//!
+//! The sensitive headers (`Authorization`, `Cookie`, `Set-Cookie` and
+//! `Proxy-Authorization` by default) and query parameters (`access_token`
+//! by default) are redacted before recording, and the fields in the query,
+//! the JSON or urlencoded bodies can also be redacted by name.
+//!
+//! Since the request body passed to the inner service is wrapped by
+//! `AuditBody`, this middleware cannot be added by `App::with_middleware`,
+//! which keeps the type of request body. Use `Audit::wrap_make_service`
+//! instead, which wraps the `MakeService` (such as `App`) itself.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use finchers::prelude::*;
+//! # use finchers::middleware::audit::{audit, AuditRecord};
+//! # use finchers::server::Server;
+//! # use finchers::service::App;
+//! # let endpoint = endpoint::unit().map(|| "Hello");
+//! let audit = audit(|record: AuditRecord| {
+//!     log::info!(target: "audit", "{} {} -> {}", record.method, record.uri, record.status);
+//!     Ok(())
+//! })
+//! .max_body_size(16 * 1024)
+//! .redact_fields(&["password", "card_number"]);
+//!
+//! Server::new(audit.wrap_make_service(App::new(endpoint)))
+//!     .bind("127.0.0.1:4000")
+//!     .serve()
+//!     .expect("failed to start the server");
+//! ```
+
+use {
+    bytes::{Buf, Bytes},
+    either::Either,
+    futures::{Async, Future, IntoFuture, Poll},
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        Method, Request, Response, StatusCode, Uri, Version,
+    },
+    izanami_service::{MakeService, Service},
+    izanami_util::{
+        buf_stream::{BufStream, SizeHint},
+        http::{HasTrailers, Upgrade},
+    },
+    std::{
+        fmt, io,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant, SystemTime},
+    },
+    tokio::executor::{DefaultExecutor, Executor},
+};
+
+pub(crate) const REDACTED: &str = "[REDACTED]";
+
+// ==== AuditSink ====
+
+/// A trait representing the destination of the audit records.
+///
+/// This trait is implemented for the functions which take an `AuditRecord`
+/// and return an `IntoFuture`. The returned future is spawned onto the
+/// default executor, and its failure is logged.
+pub trait AuditSink: Send + Sync + 'static {
+    /// The type of future returned from `record`.
+    type Future: Future<Item = (), Error = failure::Error> + Send + 'static;
+
+    /// Records an audit record.
+    fn record(&self, record: AuditRecord) -> Self::Future;
+}
+
+impl<F, R> AuditSink for F
+where
+    F: Fn(AuditRecord) -> R + Send + Sync + 'static,
+    R: IntoFuture<Item = (), Error = failure::Error>,
+    R::Future: Send + 'static,
+{
+    type Future = R::Future;
+
+    fn record(&self, record: AuditRecord) -> Self::Future {
+        (*self)(record).into_future()
+    }
+}
+
+// ==== AuditRecord ====
+
+/// The captured part of a message body.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CapturedBody {
+    /// The captured data, at most the configured size.
+    pub data: Vec<u8>,
+    /// The total size of the body which has been read.
+    pub size: u64,
+    /// Whether the data has been truncated due to the size limit.
+    pub truncated: bool,
+}
+
+impl CapturedBody {
+    fn push(&mut self, chunk: &[u8], max_size: usize) {
+        self.size += chunk.len() as u64;
+        let rest = max_size.saturating_sub(self.data.len());
+        if chunk.len() > rest {
+            self.truncated = true;
+        }
+        self.data
+            .extend_from_slice(&chunk[..std::cmp::min(rest, chunk.len())]);
+    }
+
+    /// Captures the whole of the specified chunk, and returns the chunk to
+    /// be passed through.
+    ///
+    /// Since the chunk cannot be read without consuming it, a non-contiguous
+    /// chunk is copied into a contiguous buffer unless the captured data has
+    /// already reached the limit.
+    fn capture<B: Buf>(&mut self, chunk: B, max_size: usize) -> Either<B, io::Cursor<Bytes>> {
+        let remaining = chunk.remaining();
+        if chunk.bytes().len() == remaining {
+            self.push(chunk.bytes(), max_size);
+            return Either::Left(chunk);
+        }
+        if self.data.len() >= max_size {
+            self.size += remaining as u64;
+            self.truncated = true;
+            return Either::Left(chunk);
+        }
+        let data = Bytes::from(chunk.collect::<Vec<u8>>());
+        self.push(&data, max_size);
+        Either::Right(io::Cursor::new(data))
+    }
+}
+
+/// A record of a pair of request and response.
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    /// The time when the request was received.
+    pub timestamp: SystemTime,
+    /// The duration until the response body was sent.
+    pub elapsed: Duration,
+    /// The method of the request.
+    pub method: Method,
+    /// The URI of the request, whose query parameters are redacted.
+    pub uri: Uri,
+    /// The HTTP version of the request.
+    pub version: Version,
+    /// The request headers, after redaction.
+    pub request_headers: HeaderMap,
+    /// The part of the request body read by the application.
+    pub request_body: CapturedBody,
+    /// The status code of the response.
+    pub status: StatusCode,
+    /// The response headers, after redaction.
+    pub response_headers: HeaderMap,
+    /// The part of the response body sent to the client.
+    pub response_body: CapturedBody,
+    /// Whether the response body has been sent completely.
+    ///
+    /// This is `false` if the connection was closed or an error occurred
+    /// while sending the response body.
+    pub completed: bool,
+}
+
+// ==== Audit ====
+
+/// Create a middleware which records the requests and responses into
+/// the specified `AuditSink`.
+pub fn audit<S>(sink: S) -> Audit<S>
+where
+    S: AuditSink,
+{
+    Audit {
+        sink: Arc::new(sink),
+        config: Arc::new(Config {
+            max_body_size: 64 * 1024,
+            redact_headers: vec![
+                header::AUTHORIZATION,
+                header::COOKIE,
+                header::SET_COOKIE,
+                header::PROXY_AUTHORIZATION,
+            ],
+            redact_query: vec!["access_token".into()],
+            redact_fields: vec![],
+            redact: None,
+        }),
+    }
+}
+
+type RedactFn = dyn Fn(&mut AuditRecord) + Send + Sync + 'static;
+
+#[derive(Clone)]
+struct Config {
+    max_body_size: usize,
+    redact_headers: Vec<HeaderName>,
+    redact_query: Vec<String>,
+    redact_fields: Vec<String>,
+    redact: Option<Arc<RedactFn>>,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("max_body_size", &self.max_body_size)
+            .field("redact_headers", &self.redact_headers)
+            .field("redact_query", &self.redact_query)
+            .field("redact_fields", &self.redact_fields)
+            .finish()
+    }
+}
+
+impl Config {
+    fn redact_uri(&self, uri: &Uri) -> Uri {
+        let names = self.redact_query.iter().chain(&self.redact_fields);
+        redact_query(uri, |name| names.clone().any(|field| field == name))
+    }
+
+    fn redact_headers(&self, headers: &HeaderMap) -> HeaderMap {
+        let mut headers = headers.clone();
+        for name in &self.redact_headers {
+            if headers.contains_key(name) {
+                headers.insert(name, HeaderValue::from_static(REDACTED));
+            }
+        }
+        headers
+    }
+
+    fn redact_body(&self, headers: &HeaderMap, body: &mut CapturedBody) {
+        if self.redact_fields.is_empty() || body.data.is_empty() {
+            return;
+        }
+        let mime: mime::Mime = match headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+        {
+            Some(mime) => mime,
+            None => return,
+        };
+        let redacted = if mime.subtype() == mime::JSON || mime.suffix() == Some(mime::JSON) {
+            serde_json::from_slice(&body.data).ok().map(|mut value| {
+                redact_json(&mut value, &self.redact_fields);
+                serde_json::to_vec(&value).expect("should be serializable")
+            })
+        } else if mime.essence_str() == "application/x-www-form-urlencoded" {
+            if body.truncated {
+                None
+            } else {
+                let pairs = url::form_urlencoded::parse(&body.data).map(|(key, value)| {
+                    if self.redact_fields.iter().any(|field| *field == key) {
+                        (key, REDACTED.into())
+                    } else {
+                        (key, value)
+                    }
+                });
+                let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+                serializer.extend_pairs(pairs);
+                Some(serializer.finish().into_bytes())
+            }
+        } else {
+            return;
+        };
+        // The body which cannot be parsed (e.g. truncated one) is dropped,
+        // since it may contain the sensitive fields.
+        body.data = redacted.unwrap_or_else(|| REDACTED.into());
+    }
+}
+
+/// Replaces the values of the query parameters whose names satisfy `redact`.
+///
+/// The URI is returned as it is if no parameters are redacted.
+pub(crate) fn redact_query(uri: &Uri, redact: impl Fn(&str) -> bool) -> Uri {
+    let query = match uri.query() {
+        Some(query) => query,
+        None => return uri.clone(),
+    };
+    let mut redacted = false;
+    let pairs: Vec<String> = query
+        .split('&')
+        .map(|pair| {
+            let name = pair.split('=').next().unwrap_or("");
+            let decoded = url::form_urlencoded::parse(name.as_bytes())
+                .next()
+                .map(|(name, _)| name);
+            match decoded {
+                Some(ref decoded) if redact(decoded) => {
+                    redacted = true;
+                    format!("{}={}", name, REDACTED)
+                }
+                _ => pair.to_owned(),
+            }
+        })
+        .collect();
+    if !redacted {
+        return uri.clone();
+    }
+    let path_and_query = format!("{}?{}", uri.path(), pairs.join("&"));
+    let mut parts = uri.clone().into_parts();
+    // `[` and `]` are not allowed in the query by `http`, so they are
+    // percent-encoded.
+    parts.path_and_query = Some(
+        path_and_query
+            .replace('[', "%5B")
+            .replace(']', "%5D")
+            .parse()
+            .expect("should be a valid path and query"),
+    );
+    Uri::from_parts(parts).expect("should be a valid URI")
+}
+
+fn redact_json(value: &mut serde_json::Value, fields: &[String]) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if fields.iter().any(|field| field == key) {
+                    *value = serde_json::Value::String(REDACTED.into());
+                } else {
+                    redact_json(value, fields);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                redact_json(value, fields);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[allow(missing_docs)]
+pub struct Audit<S> {
+    sink: Arc<S>,
+    config: Arc<Config>,
+}
+
+impl<S> Clone for Audit<S> {
+    fn clone(&self) -> Self {
+        Audit {
+            sink: self.sink.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for Audit<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Audit")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl<S> Audit<S> {
+    fn configure(self, f: impl FnOnce(&mut Config)) -> Self {
+        let mut config = Arc::try_unwrap(self.config).unwrap_or_else(|config| (*config).clone());
+        f(&mut config);
+        Audit {
+            sink: self.sink,
+            config: Arc::new(config),
+        }
+    }
+
+    /// Sets the maximum size of the captured part of each body.
+    ///
+    /// The default value is 64 KiB.
+    pub fn max_body_size(self, size: usize) -> Self {
+        self.configure(|config| config.max_body_size = size)
+    }
+
+    /// Sets the names of the headers whose values are redacted.
+    ///
+    /// # Panics
+    /// This method panics if any of the names is not a valid header name.
+    pub fn redact_headers(self, names: &[&str]) -> Self {
+        let names = names
+            .iter()
+            .map(|name| HeaderName::from_bytes(name.as_bytes()).expect("invalid header name"))
+            .collect();
+        self.configure(|config| config.redact_headers = names)
+    }
+
+    /// Sets the names of the query parameters whose values are redacted.
+    ///
+    /// The default value is `["access_token"]` (RFC 6750).
+    pub fn redact_query(self, names: &[&str]) -> Self {
+        let names = names.iter().map(|&name| name.into()).collect();
+        self.configure(|config| config.redact_query = names)
+    }
+
+    /// Sets the names of the fields whose values are redacted in the query,
+    /// the JSON or urlencoded bodies.
+    ///
+    /// The fields in the nested JSON objects are also redacted. If a body
+    /// with these content types cannot be parsed (e.g. since it is truncated),
+    /// the whole of the captured data is redacted.
+    pub fn redact_fields(self, fields: &[&str]) -> Self {
+        let fields = fields.iter().map(|&field| field.into()).collect();
+        self.configure(|config| config.redact_fields = fields)
+    }
+
+    /// Sets a function which modifies the records before they are passed
+    /// to the sink, after the built-in redactions are applied.
+    pub fn redact<F>(self, f: F) -> Self
+    where
+        F: Fn(&mut AuditRecord) + Send + Sync + 'static,
+    {
+        self.configure(|config| config.redact = Some(Arc::new(f)))
+    }
+}
+
+impl<Snk> Audit<Snk> {
+    /// Wraps the specified `MakeService` so that all of the services created
+    /// by it are wrapped by this middleware.
+    pub fn wrap_make_service<S>(self, make_service: S) -> WithAudit<S, Snk> {
+        WithAudit {
+            make_service,
+            audit: self,
+        }
+    }
+}
+
+impl<S, Snk> super::Middleware<S> for Audit<Snk> {
+    type Service = AuditService<S, Snk>;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        AuditService {
+            inner,
+            sink: self.sink.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+pub struct AuditService<S, Snk> {
+    inner: S,
+    sink: Arc<Snk>,
+    config: Arc<Config>,
+}
+
+impl<S, Snk> fmt::Debug for AuditService<S, Snk>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditService")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl<S, Snk, Bd, ResBd> Service<Request<Bd>> for AuditService<S, Snk>
+where
+    S: Service<Request<AuditBody<Bd>>, Response = Response<ResBd>>,
+    Bd: BufStream,
+    ResBd: BufStream,
+    Snk: AuditSink,
+{
+    type Response = Response<AuditBody<ResBd>>;
+    type Error = S::Error;
+    type Future = AuditFuture<S::Future, Snk>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Request<Bd>) -> Self::Future {
+        let captured = Arc::new(Mutex::new(CapturedBody::default()));
+        let pending = Pending {
+            timestamp: SystemTime::now(),
+            started: Instant::now(),
+            method: request.method().clone(),
+            uri: request.uri().clone(),
+            version: request.version(),
+            headers: request.headers().clone(),
+            body: captured.clone(),
+        };
+        let max_body_size = self.config.max_body_size;
+        let request = request.map(|inner| AuditBody {
+            inner,
+            captured,
+            max_body_size,
+            on_finish: None,
+        });
+        AuditFuture {
+            future: self.inner.call(request),
+            pending: Some(pending),
+            sink: self.sink.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// A `MakeService` created by `Audit::wrap_make_service`.
+#[derive(Debug)]
+pub struct WithAudit<S, Snk> {
+    make_service: S,
+    audit: Audit<Snk>,
+}
+
+impl<S, Snk, Ctx, Bd, ResBd> MakeService<Ctx, Request<Bd>> for WithAudit<S, Snk>
+where
+    S: MakeService<Ctx, Request<AuditBody<Bd>>, Response = Response<ResBd>>,
+    Bd: BufStream,
+    ResBd: BufStream,
+    Snk: AuditSink,
+{
+    type Response = Response<AuditBody<ResBd>>;
+    type Error = S::Error;
+    type Service = AuditService<S::Service, Snk>;
+    type MakeError = S::MakeError;
+    type Future = super::WithMiddlewareFuture<S::Future, Audit<Snk>>;
+
+    fn make_service(&self, ctx: Ctx) -> Self::Future {
+        super::WithMiddlewareFuture {
+            future: self.make_service.make_service(ctx),
+            middleware: Some(self.audit.clone()),
+        }
+    }
+}
+
+struct Pending {
+    timestamp: SystemTime,
+    started: Instant,
+    method: Method,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+    body: Arc<Mutex<CapturedBody>>,
+}
+
+#[allow(missing_docs)]
+pub struct AuditFuture<Fut, Snk> {
+    future: Fut,
+    pending: Option<Pending>,
+    sink: Arc<Snk>,
+    config: Arc<Config>,
+}
+
+impl<Fut, Snk> fmt::Debug for AuditFuture<Fut, Snk> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditFuture").finish()
+    }
+}
+
+impl<Fut, Snk, Bd> Future for AuditFuture<Fut, Snk>
+where
+    Fut: Future<Item = Response<Bd>>,
+    Snk: AuditSink,
+{
+    type Item = Response<AuditBody<Bd>>;
+    type Error = Fut::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = futures::try_ready!(self.future.poll());
+        let pending = self.pending.take().expect("the future has already polled");
+        let status = response.status();
+        let response_headers = response.headers().clone();
+        let captured = Arc::new(Mutex::new(CapturedBody::default()));
+
+        let sink = self.sink.clone();
+        let config = self.config.clone();
+        let on_finish = {
+            let captured = captured.clone();
+            move |completed: bool| {
+                let mut request_body = pending.body.lock().unwrap().clone();
+                let mut response_body = captured.lock().unwrap().clone();
+                config.redact_body(&pending.headers, &mut request_body);
+                config.redact_body(&response_headers, &mut response_body);
+                let mut record = AuditRecord {
+                    timestamp: pending.timestamp,
+                    elapsed: pending.started.elapsed(),
+                    method: pending.method,
+                    uri: config.redact_uri(&pending.uri),
+                    version: pending.version,
+                    request_headers: config.redact_headers(&pending.headers),
+                    request_body,
+                    status,
+                    response_headers: config.redact_headers(&response_headers),
+                    response_body,
+                    completed,
+                };
+                if let Some(ref redact) = config.redact {
+                    redact(&mut record);
+                }
+                let future = sink
+                    .record(record)
+                    .map_err(|err| log::error!("failed to record the audit log: {}", err));
+                if let Err(err) = DefaultExecutor::current().spawn(Box::new(future)) {
+                    log::error!("failed to spawn the task recording the audit log: {}", err);
+                }
+            }
+        };
+
+        let max_body_size = self.config.max_body_size;
+        Ok(Async::Ready(response.map(|inner| AuditBody {
+            inner,
+            captured,
+            max_body_size,
+            on_finish: Some(Box::new(on_finish)),
+        })))
+    }
+}
+
+// ==== AuditBody ====
+
+trait FnBox {
+    fn call_box(self: Box<Self>, completed: bool);
+}
+
+impl<F> FnBox for F
+where
+    F: FnOnce(bool),
+{
+    fn call_box(self: Box<Self>, completed: bool) {
+        (*self)(completed)
+    }
+}
+
+/// A message body which captures the data passing through it.
+pub struct AuditBody<Bd> {
+    inner: Bd,
+    captured: Arc<Mutex<CapturedBody>>,
+    max_body_size: usize,
+    on_finish: Option<Box<dyn FnBox + Send + 'static>>,
+}
+
+impl<Bd: fmt::Debug> fmt::Debug for AuditBody<Bd> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditBody")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<Bd> AuditBody<Bd> {
+    fn finish(&mut self, completed: bool) {
+        if let Some(on_finish) = self.on_finish.take() {
+            on_finish.call_box(completed);
+        }
+    }
+}
+
+impl<Bd> BufStream for AuditBody<Bd>
+where
+    Bd: BufStream,
+{
+    type Item = Either<Bd::Item, io::Cursor<Bytes>>;
+    type Error = Bd::Error;
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.inner.poll_buf() {
+            Ok(Async::Ready(Some(chunk))) => {
+                let chunk = self
+                    .captured
+                    .lock()
+                    .unwrap()
+                    .capture(chunk, self.max_body_size);
+                Ok(Async::Ready(Some(chunk)))
+            }
+            Ok(Async::Ready(None)) => {
+                self.finish(true);
+                Ok(Async::Ready(None))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => {
+                self.finish(false);
+                Err(err)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+
+    fn consume_hint(&mut self, amount: usize) {
+        self.inner.consume_hint(amount)
+    }
+}
+
+impl<Bd> HasTrailers for AuditBody<Bd>
+where
+    Bd: HasTrailers,
+{
+    type TrailersError = Bd::TrailersError;
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::TrailersError> {
+        self.inner.poll_trailers()
+    }
+}
+
+impl<Bd> Upgrade for AuditBody<Bd>
+where
+    Bd: Upgrade,
+{
+    type Upgraded = Bd::Upgraded;
+    type Error = Bd::Error;
+
+    fn poll_upgrade(&mut self) -> Poll<Self::Upgraded, Self::Error> {
+        self.inner.poll_upgrade()
+    }
+}
+
+impl<Bd> Drop for AuditBody<Bd> {
+    fn drop(&mut self) {
+        self.finish(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{endpoint::EndpointExt, middleware::tests::call, service::EndpointServiceExt},
+        http::{Method, Request, StatusCode},
+    };
+
+    #[test]
+    fn test_audit() {
+        let records = Arc::new(std::sync::Mutex::new(vec![]));
+        let app = audit({
+            let records = records.clone();
+            move |record: AuditRecord| {
+                records.lock().unwrap().push(record);
+                Ok(())
+            }
+        })
+        .max_body_size(40)
+        .redact_fields(&["password"])
+        .wrap_make_service(
+            crate::endpoints::body::text()
+                .map(|body: String| format!("{{\"echo\":{}}}", body))
+                .into_service(),
+        );
+
+        let response = call(
+            &app,
+            Request::post("/login")
+                .header("authorization", "Bearer secret")
+                .header("content-type", "application/json")
+                .body(r#"{"user":"alice","password":"hunter2"}"#)
+                .unwrap(),
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.body().contains("hunter2"));
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.method, Method::POST);
+        assert_eq!(record.uri, "/login");
+        assert_eq!(record.status, StatusCode::OK);
+        assert!(record.completed);
+        assert_eq!(record.request_headers["authorization"], "[REDACTED]");
+        assert_eq!(
+            record.request_body.data,
+            br#"{"password":"[REDACTED]","user":"alice"}"#.to_vec()
+        );
+        assert!(!record.request_body.truncated);
+        // the fields in the plain text are not redacted
+        assert!(record.response_body.truncated);
+        assert_eq!(record.response_body.size, response.body().len() as u64);
+        assert_eq!(
+            record.response_body.data,
+            response.body().as_bytes()[..40].to_vec()
+        );
+    }
+
+    #[test]
+    fn test_audit_redact_query() {
+        let records = Arc::new(std::sync::Mutex::new(vec![]));
+        let app = audit({
+            let records = records.clone();
+            move |record: AuditRecord| {
+                records.lock().unwrap().push(record);
+                Ok(())
+            }
+        })
+        .redact_fields(&["password"])
+        .wrap_make_service(crate::endpoint::unit().map(|| "Hello").into_service());
+
+        let response = call(
+            &app,
+            Request::get("/login?user=alice&access_token=abc&password=hunter2")
+                .body("")
+                .unwrap(),
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let records = records.lock().unwrap();
+        assert_eq!(
+            records[0].uri,
+            "/login?user=alice&access_token=%5BREDACTED%5D&password=%5BREDACTED%5D"
+        );
+    }
+
+    #[test]
+    fn test_redact_query() {
+        let uri: Uri = "/?a=1&b=2".parse().unwrap();
+        assert_eq!(redact_query(&uri, |_| false), uri);
+        assert_eq!(
+            redact_query(&uri, |name| name == "b"),
+            "/?a=1&b=%5BREDACTED%5D"
+        );
+        assert_eq!(
+            redact_query(&"/?acc%65ss_token=x&flag".parse().unwrap(), |name| name
+                == "access_token"),
+            "/?acc%65ss_token=%5BREDACTED%5D&flag"
+        );
+    }
+
+    #[test]
+    fn test_capture_non_contiguous() {
+        use std::io::Cursor;
+
+        let mut captured = CapturedBody::default();
+        let chunk = Cursor::new(&b"Hello, "[..]).chain(Cursor::new(&b"world"[..]));
+        let chunk = captured.capture(chunk, 8);
+        assert_eq!(chunk.remaining(), 12);
+        assert_eq!(chunk.collect::<Vec<u8>>(), b"Hello, world".to_vec());
+        assert_eq!(captured.data, b"Hello, w".to_vec());
+        assert_eq!(captured.size, 12);
+        assert!(captured.truncated);
+
+        // the chunks are passed through as they are once the limit is reached.
+        let chunk = Cursor::new(&b"foo"[..]).chain(Cursor::new(&b"bar"[..]));
+        assert!(captured.capture(chunk, 8).is_left());
+        assert_eq!(captured.size, 18);
+    }
+}
@@ -0,0 +1,603 @@
+//! Recording of the HTTP exchanges in the HAR (HTTP Archive) 1.2 format.
+//!
+//! `HarRecorder` is an `AuditSink` which keeps the records passed from the
+//! `Audit` middleware, and exports them as a HAR document, which can be
+//! imported into the browser developer tools or replayed by the API consumers.
+//! The recorded exchanges are exposed via `HarRecorder::endpoint`, or written
+//! into the files in a directory each time the buffer is full.
+//!
+//! Since the records are taken from the audit middleware, the redaction of
+//! headers, query parameters and body fields and the size limit of the bodies
+//! configured on `Audit` are also applied to the HAR entries.
+//!
+//! The files are written within the blocking section of the threadpool (if
+//! the feature `tokio-threadpool` is enabled), and
+//! the exchanges are kept in the buffer if the file could not be written so
+//! that they are written together with the next ones.
+//!
+//! This is intended for debugging, and should not be enabled in production
+//! without restricting the access to the endpoint.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use finchers::prelude::*;
+//! # use finchers::endpoint::syntax;
+//! # use finchers::middleware::{audit, har::HarRecorder};
+//! # use finchers::server::Server;
+//! # use finchers::service::App;
+//! # let app_endpoint = endpoint::unit().map(|| "Hello");
+//! let recorder = HarRecorder::new(100);
+//!
+//! let endpoint = syntax::segment("_debug")
+//!     .and(syntax::segment("har"))
+//!     .and(recorder.endpoint())
+//!     .or(app_endpoint);
+//! let app = audit::audit(recorder.clone())
+//!     .redact_fields(&["password"])
+//!     .wrap_make_service(App::new(endpoint));
+//!
+//! Server::new(app)
+//!     .bind("127.0.0.1:4000")
+//!     .serve()
+//!     .expect("failed to start the server");
+//! ```
+
+use {
+    super::audit::{AuditRecord, AuditSink, CapturedBody},
+    crate::{
+        action::{
+            Oneshot,
+            OneshotAction,
+            PreflightContext, //
+        },
+        endpoint::{Endpoint, IsEndpoint},
+        error::Error,
+        output::Json,
+        util::{blocking, civil_from_days, millis},
+    },
+    futures::{Async, Future, Poll},
+    http::{header, HeaderMap, Uri},
+    serde_json::{json, Value},
+    std::{
+        collections::VecDeque,
+        fmt, fs,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// An `AuditSink` which keeps the recorded exchanges for exporting as HAR.
+#[derive(Clone)]
+pub struct HarRecorder {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    capacity: usize,
+    records: Mutex<VecDeque<AuditRecord>>,
+    rotation: Option<Rotation>,
+}
+
+struct Rotation {
+    dir: PathBuf,
+    sequence: Mutex<u64>,
+}
+
+impl fmt::Debug for HarRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HarRecorder")
+            .field("capacity", &self.inner.capacity)
+            .field("len", &self.len())
+            .field(
+                "rotation",
+                &self.inner.rotation.as_ref().map(|rotation| &rotation.dir),
+            )
+            .finish()
+    }
+}
+
+impl HarRecorder {
+    /// Creates a new `HarRecorder` which keeps the latest `capacity` exchanges.
+    ///
+    /// # Panics
+    /// This method panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_rotation(capacity, None)
+    }
+
+    /// Creates a new `HarRecorder` which writes the recorded exchanges into
+    /// a new file in `dir` each time `capacity` exchanges have been recorded.
+    ///
+    /// The files are named as `finchers-<unix time in millis>-<sequence>.har`.
+    ///
+    /// # Panics
+    /// This method panics if `capacity` is zero.
+    pub fn rotate_into(capacity: usize, dir: impl Into<PathBuf>) -> Self {
+        Self::with_rotation(
+            capacity,
+            Some(Rotation {
+                dir: dir.into(),
+                sequence: Mutex::new(0),
+            }),
+        )
+    }
+
+    fn with_rotation(capacity: usize, rotation: Option<Rotation>) -> Self {
+        assert!(capacity > 0, "the capacity must be positive");
+        HarRecorder {
+            inner: Arc::new(Inner {
+                capacity,
+                records: Mutex::new(VecDeque::with_capacity(capacity)),
+                rotation,
+            }),
+        }
+    }
+
+    /// Returns the number of the exchanges currently kept.
+    pub fn len(&self) -> usize {
+        self.inner.records.lock().unwrap().len()
+    }
+
+    /// Returns `true` if no exchanges are kept.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Discards the exchanges currently kept.
+    pub fn clear(&self) {
+        self.inner.records.lock().unwrap().clear();
+    }
+
+    /// Returns the HAR document of the exchanges currently kept.
+    pub fn to_har(&self) -> Value {
+        let records = self.inner.records.lock().unwrap();
+        to_har(records.iter())
+    }
+
+    /// Writes the exchanges currently kept into a new file and discards them,
+    /// and returns the path of the written file.
+    ///
+    /// This method returns `Ok(None)` if no exchanges are kept or the recorder
+    /// has not been created by `rotate_into`. If the file could not be written,
+    /// the exchanges are kept.
+    pub fn rotate(&self) -> std::io::Result<Option<PathBuf>> {
+        if self.inner.rotation.is_none() {
+            return Ok(None);
+        }
+        let records: Vec<_> = self.inner.records.lock().unwrap().drain(..).collect();
+        if records.is_empty() {
+            return Ok(None);
+        }
+        self.inner.write(records).map(Some)
+    }
+
+    /// Creates an endpoint which returns the HAR document of the exchanges
+    /// currently kept, as `application/json`.
+    pub fn endpoint(&self) -> HarEndpoint {
+        HarEndpoint {
+            recorder: self.clone(),
+        }
+    }
+}
+
+impl Inner {
+    /// Writes the specified exchanges into a new file, or puts them back
+    /// to the buffer if failed.
+    fn write(&self, records: Vec<AuditRecord>) -> std::io::Result<PathBuf> {
+        let rotation = self.rotation.as_ref().expect("should be rotated");
+        rotation.write(&records).map_err(|err| {
+            let mut buffer = self.records.lock().unwrap();
+            for record in records.into_iter().rev() {
+                buffer.push_front(record);
+            }
+            err
+        })
+    }
+}
+
+impl Rotation {
+    fn write(&self, records: &[AuditRecord]) -> std::io::Result<PathBuf> {
+        let sequence = {
+            let mut sequence = self.sequence.lock().unwrap();
+            *sequence += 1;
+            *sequence
+        };
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()))
+            .unwrap_or(0);
+        let path = self
+            .dir
+            .join(format!("finchers-{}-{}.har", millis, sequence));
+        let har = serde_json::to_vec_pretty(&to_har(records.iter()))?;
+        write_file(&path, &har)?;
+        Ok(path)
+    }
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    fs::create_dir_all(path.parent().unwrap_or_else(|| Path::new(".")))?;
+    fs::write(path, contents)
+}
+
+impl AuditSink for HarRecorder {
+    type Future = RecordFuture;
+
+    fn record(&self, record: AuditRecord) -> Self::Future {
+        let mut records = self.inner.records.lock().unwrap();
+        records.push_back(record);
+        if records.len() < self.inner.capacity {
+            return RecordFuture { write: None };
+        }
+        if self.inner.rotation.is_some() {
+            RecordFuture {
+                write: Some((self.inner.clone(), records.drain(..).collect())),
+            }
+        } else {
+            while records.len() > self.inner.capacity {
+                records.pop_front();
+            }
+            RecordFuture { write: None }
+        }
+    }
+}
+
+/// The future returned from `HarRecorder::record`.
+#[allow(missing_debug_implementations)]
+#[must_use = "futures do nothing unless polled"]
+pub struct RecordFuture {
+    write: Option<(Arc<Inner>, Vec<AuditRecord>)>,
+}
+
+impl Future for RecordFuture {
+    type Item = ();
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.write.is_none() {
+            return Ok(Async::Ready(()));
+        }
+        futures::try_ready!(blocking(|| {
+            let (inner, records) = self.write.take().expect("should be Some");
+            inner.write(records)
+        })
+        .poll());
+        Ok(Async::Ready(()))
+    }
+}
+
+// ==== HAR ====
+
+fn to_har<'a>(records: impl Iterator<Item = &'a AuditRecord>) -> Value {
+    json!({
+        "log": {
+            "version": "1.2",
+            "creator": {
+                "name": "finchers",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "entries": records.map(to_entry).collect::<Vec<_>>(),
+        }
+    })
+}
+
+fn to_entry(record: &AuditRecord) -> Value {
+    let http_version = format!("{:?}", record.version);
+    let wait = millis(record.elapsed);
+    let mut entry = json!({
+        "startedDateTime": format_date_time(record.timestamp),
+        "time": wait,
+        "request": {
+            "method": record.method.as_str(),
+            "url": request_url(&record.uri, &record.request_headers),
+            "httpVersion": http_version,
+            "cookies": [],
+            "headers": to_headers(&record.request_headers),
+            "queryString": to_query_string(&record.uri),
+            "headersSize": -1,
+            "bodySize": record.request_body.size,
+        },
+        "response": {
+            "status": record.status.as_u16(),
+            "statusText": record.status.canonical_reason().unwrap_or(""),
+            "httpVersion": http_version,
+            "cookies": [],
+            "headers": to_headers(&record.response_headers),
+            "content": to_content(&record.response_headers, &record.response_body),
+            "redirectURL": header_str(&record.response_headers, header::LOCATION),
+            "headersSize": -1,
+            "bodySize": record.response_body.size,
+            "_completed": record.completed,
+        },
+        "cache": {},
+        "timings": {
+            "send": 0,
+            "wait": wait,
+            "receive": 0,
+        },
+    });
+    if let Some(post_data) = to_post_data(&record.request_headers, &record.request_body) {
+        entry["request"]["postData"] = post_data;
+    }
+    entry
+}
+
+/// Formats the time in ISO 8601, e.g. `2009-07-24T19:20:30.450Z`.
+fn format_date_time(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs() as i64;
+    let (year, month, day) = civil_from_days(secs / 86400);
+    let secs_of_day = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        elapsed.subsec_millis(),
+    )
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> &str {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+}
+
+fn request_url(uri: &Uri, headers: &HeaderMap) -> String {
+    if uri.authority_part().is_some() {
+        return uri.to_string();
+    }
+    let path_and_query = uri.path_and_query().map_or("/", |p| p.as_str());
+    match header_str(headers, header::HOST) {
+        "" => path_and_query.to_owned(),
+        host => format!("http://{}{}", host, path_and_query),
+    }
+}
+
+fn to_headers(headers: &HeaderMap) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            json!({
+                "name": name.as_str(),
+                "value": String::from_utf8_lossy(value.as_bytes()),
+            })
+        })
+        .collect()
+}
+
+fn to_query_string(uri: &Uri) -> Vec<Value> {
+    url::form_urlencoded::parse(uri.query().unwrap_or("").as_bytes())
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect()
+}
+
+fn to_post_data(headers: &HeaderMap, body: &CapturedBody) -> Option<Value> {
+    if body.size == 0 {
+        return None;
+    }
+    let mut post_data = to_content(headers, body);
+    post_data
+        .as_object_mut()
+        .expect("should be an object")
+        .remove("size");
+    Some(post_data)
+}
+
+fn to_content(headers: &HeaderMap, body: &CapturedBody) -> Value {
+    let mut content = json!({
+        "size": body.size,
+        "mimeType": header_str(headers, header::CONTENT_TYPE),
+    });
+    let fields = content.as_object_mut().expect("should be an object");
+    match std::str::from_utf8(&body.data) {
+        Ok(text) => {
+            fields.insert("text".into(), text.into());
+        }
+        Err(..) => {
+            fields.insert(
+                "comment".into(),
+                "the binary content has been omitted".into(),
+            );
+        }
+    }
+    if body.truncated {
+        fields.insert("_truncated".into(), true.into());
+    }
+    content
+}
+
+// ==== HarEndpoint ====
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct HarEndpoint {
+    recorder: HarRecorder,
+}
+
+mod har_endpoint {
+    use super::*;
+
+    impl IsEndpoint for HarEndpoint {}
+
+    impl<Bd> Endpoint<Bd> for HarEndpoint {
+        type Output = (Json<Value>,);
+        type Action = Oneshot<HarAction>;
+
+        fn action(&self) -> Self::Action {
+            HarAction {
+                recorder: self.recorder.clone(),
+            }
+            .into_action()
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct HarAction {
+        recorder: HarRecorder,
+    }
+
+    impl OneshotAction for HarAction {
+        type Output = (Json<Value>,);
+
+        fn preflight(self, _: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            Ok((Json(self.recorder.to_har()),))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        futures::Future,
+        http::{Method, StatusCode, Version},
+        std::time::Duration,
+    };
+
+    fn record(path: &str) -> AuditRecord {
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(header::HOST, "example.com".parse().unwrap());
+        request_headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert(header::CONTENT_TYPE, "text/plain".parse().unwrap());
+        AuditRecord {
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_234_567_890_123),
+            elapsed: Duration::from_micros(1500),
+            method: Method::POST,
+            uri: path.parse().unwrap(),
+            version: Version::HTTP_11,
+            request_headers,
+            request_body: CapturedBody {
+                data: b"{\"id\":1}".to_vec(),
+                size: 8,
+                truncated: false,
+            },
+            status: StatusCode::CREATED,
+            response_headers,
+            response_body: CapturedBody {
+                data: b"created".to_vec(),
+                size: 7,
+                truncated: false,
+            },
+            completed: true,
+        }
+    }
+
+    #[test]
+    fn test_to_har() {
+        let recorder = HarRecorder::new(2);
+        for path in &["/a", "/b?x=1&y=2", "/c"] {
+            recorder.record(record(path)).wait().unwrap();
+        }
+        assert_eq!(recorder.len(), 2);
+
+        let har = recorder.to_har();
+        assert_eq!(har["log"]["version"], "1.2");
+        let entries = har["log"]["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let entry = &entries[0];
+        assert_eq!(entry["startedDateTime"], "2009-02-13T23:31:30.123Z");
+        assert_eq!(entry["time"], 1.5);
+        assert_eq!(entry["request"]["method"], "POST");
+        assert_eq!(entry["request"]["url"], "http://example.com/b?x=1&y=2");
+        assert_eq!(entry["request"]["httpVersion"], "HTTP/1.1");
+        assert_eq!(
+            entry["request"]["queryString"],
+            json!([{ "name": "x", "value": "1" }, { "name": "y", "value": "2" }])
+        );
+        assert_eq!(
+            entry["request"]["postData"],
+            json!({ "mimeType": "application/json", "text": "{\"id\":1}" })
+        );
+        assert_eq!(entry["response"]["status"], 201);
+        assert_eq!(entry["response"]["statusText"], "Created");
+        assert_eq!(
+            entry["response"]["content"],
+            json!({ "size": 7, "mimeType": "text/plain", "text": "created" })
+        );
+
+        recorder.clear();
+        assert!(recorder.is_empty());
+    }
+
+    #[test]
+    fn test_rotate_into() {
+        let dir = std::env::temp_dir().join(format!("finchers-har-{}", std::process::id()));
+        let recorder = HarRecorder::rotate_into(2, &dir);
+
+        recorder.record(record("/a")).wait().unwrap();
+        assert_eq!(recorder.len(), 1);
+        recorder.record(record("/b")).wait().unwrap();
+        assert!(recorder.is_empty());
+
+        recorder.record(record("/c")).wait().unwrap();
+        let path = recorder.rotate().unwrap().expect("should be rotated");
+        assert!(recorder.rotate().unwrap().is_none());
+
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        assert!(files.contains(&path));
+
+        let har: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(har["log"]["entries"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            har["log"]["entries"][0]["request"]["url"],
+            "http://example.com/c"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotate_failed() {
+        let dir = std::env::temp_dir().join(format!("finchers-har-failed-{}", std::process::id()));
+        // the directory cannot be created, since a file exists at the path.
+        fs::write(&dir, b"").unwrap();
+        let recorder = HarRecorder::rotate_into(2, dir.join("har"));
+
+        recorder.record(record("/a")).wait().unwrap();
+        assert!(recorder.record(record("/b")).wait().is_err());
+        assert_eq!(recorder.len(), 2);
+        assert!(recorder.rotate().is_err());
+        assert_eq!(recorder.len(), 2);
+
+        fs::remove_file(&dir).unwrap();
+        recorder.record(record("/c")).wait().unwrap();
+        assert!(recorder.is_empty());
+
+        let entries: Vec<_> = fs::read_dir(dir.join("har"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        let har: Value = serde_json::from_slice(&fs::read(&entries[0]).unwrap()).unwrap();
+        let urls: Vec<_> = har["log"]["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["request"]["url"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                "http://example.com/a",
+                "http://example.com/b",
+                "http://example.com/c"
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,466 @@
+//! A middleware which normalizes the path of request URIs before routing.
+//!
+//! The endpoints match the path segment by segment, so the requests to
+//! `//foo`, `/./foo` or `/%66oo` do not reach the endpoint for `/foo` unless
+//! a duplicate route is added for each of them. The `NormalizePath`
+//! middleware rewrites such paths to the canonical form before they are
+//! passed to the endpoints, or redirects the client to the canonical URI.
+//!
+//! The following normalizations are applied in order (each of them can be
+//! disabled individually):
+//!
+//! * The percent-encoded unreserved characters (`ALPHA`, `DIGIT`, `-`, `.`,
+//!   `_` and `~`) are decoded, and the hexadecimal digits of the remaining
+//!   percent-encodings are uppercased (RFC 3986, section 6.2.2.2).
+//! * The sequences of slashes are merged into a single slash.
+//! * The dot segments (`.` and `..`) are resolved (RFC 3986, section 5.2.4).
+//!   The `..` segments never go above the root.
+//! * Optionally, the path is converted to lowercase. The percent-encodings
+//!   are kept uppercased.
+//!
+//! The query is preserved as it is.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use finchers::prelude::*;
+//! # use finchers::server::Server;
+//! # use finchers::service::App;
+//! # use http::StatusCode;
+//! use finchers::middleware::normalize::{normalize_path, NormalizePolicy};
+//!
+//! # let endpoint = endpoint::unit().map(|| "Hello");
+//! let app = App::new(endpoint).with_middleware(
+//!     normalize_path()
+//!         .lowercase(true)
+//!         .policy(NormalizePolicy::Redirect(StatusCode::PERMANENT_REDIRECT)),
+//! );
+//!
+//! Server::new(app)
+//!     .bind("127.0.0.1:4000")
+//!     .serve()
+//!     .expect("failed to start the server");
+//! ```
+
+use {
+    futures::{Async, Future, Poll},
+    http::{
+        header::{self, HeaderValue},
+        uri::PathAndQuery,
+        Request, Response, StatusCode, Uri,
+    },
+    izanami_service::Service,
+    izanami_util::buf_stream::Either,
+    std::sync::Arc,
+};
+
+/// Creates a `Middleware` which normalizes the path of the request URIs.
+///
+/// By default, all of the normalizations except lowercasing are enabled,
+/// and the normalized path is passed to the inner service by rewriting the
+/// request URI. The original URI is stored in the extensions of the request
+/// as `OriginalUri`.
+pub fn normalize_path() -> NormalizePath {
+    NormalizePath {
+        config: Arc::new(NormalizePathConfig {
+            merge_slashes: true,
+            resolve_dot_segments: true,
+            lowercase: false,
+            normalize_percent_encoding: true,
+            policy: NormalizePolicy::Rewrite,
+        }),
+    }
+}
+
+/// The URI of the request before rewritten by `NormalizePath`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OriginalUri(pub Uri);
+
+/// The policy which specifies how the non-normalized requests are handled.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NormalizePolicy {
+    /// Rewrites the request URI and passes the request to the inner service.
+    Rewrite,
+
+    /// Returns a redirect response with the specified status code, without
+    /// calling the inner service.
+    ///
+    /// `308 Permanent Redirect` or `307 Temporary Redirect` should be used
+    /// in order to keep the method and body of the non-`GET` requests.
+    Redirect(StatusCode),
+}
+
+#[derive(Debug, Clone)]
+struct NormalizePathConfig {
+    merge_slashes: bool,
+    resolve_dot_segments: bool,
+    lowercase: bool,
+    normalize_percent_encoding: bool,
+    policy: NormalizePolicy,
+}
+
+impl NormalizePathConfig {
+    /// Returns the normalized path, or `None` if the path is already normalized.
+    fn normalize(&self, path: &str) -> Option<String> {
+        // The asterisk-form (`OPTIONS *`) and the authority-form are left untouched.
+        if !path.starts_with('/') {
+            return None;
+        }
+
+        let decoded = self.normalize_chars(path);
+        let normalized = self.normalize_segments(&decoded);
+        if normalized == path {
+            None
+        } else {
+            Some(normalized)
+        }
+    }
+
+    fn normalize_chars(&self, path: &str) -> String {
+        let bytes = path.as_bytes();
+        let mut buf = String::with_capacity(path.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let b = bytes[i];
+            if b == b'%' && i + 2 < bytes.len() {
+                if let (Some(hi), Some(lo)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                    let decoded = hi << 4 | lo;
+                    if self.normalize_percent_encoding && is_unreserved(decoded) {
+                        buf.push(self.convert_case(decoded) as char);
+                    } else if self.normalize_percent_encoding {
+                        buf.push('%');
+                        buf.push(bytes[i + 1].to_ascii_uppercase() as char);
+                        buf.push(bytes[i + 2].to_ascii_uppercase() as char);
+                    } else {
+                        buf.push_str(&path[i..i + 3]);
+                    }
+                    i += 3;
+                    continue;
+                }
+            }
+            buf.push(self.convert_case(b) as char);
+            i += 1;
+        }
+        buf
+    }
+
+    fn convert_case(&self, b: u8) -> u8 {
+        if self.lowercase {
+            b.to_ascii_lowercase()
+        } else {
+            b
+        }
+    }
+
+    fn normalize_segments(&self, path: &str) -> String {
+        let mut segments: Vec<&str> = vec![];
+        let mut trailing_slash = false;
+
+        let mut iter = path[1..].split('/').peekable();
+        while let Some(segment) = iter.next() {
+            let is_last = iter.peek().is_none();
+            trailing_slash = false;
+            match segment {
+                "" if self.merge_slashes && !is_last => {}
+                "." if self.resolve_dot_segments => trailing_slash = true,
+                ".." if self.resolve_dot_segments => {
+                    segments.pop();
+                    trailing_slash = true;
+                }
+                segment => segments.push(segment),
+            }
+        }
+
+        let mut normalized = String::with_capacity(path.len());
+        normalized.push('/');
+        normalized.push_str(&segments.join("/"));
+        if trailing_slash && !segments.is_empty() {
+            normalized.push('/');
+        }
+        normalized
+    }
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_' || b == b'~'
+}
+
+fn replace_path(uri: &Uri, path: &str) -> Option<Uri> {
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_owned(),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse::<PathAndQuery>().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct NormalizePath {
+    config: Arc<NormalizePathConfig>,
+}
+
+impl NormalizePath {
+    fn configure(self, f: impl FnOnce(&mut NormalizePathConfig)) -> Self {
+        let mut config = Arc::try_unwrap(self.config).unwrap_or_else(|config| (*config).clone());
+        f(&mut config);
+        NormalizePath {
+            config: Arc::new(config),
+        }
+    }
+
+    /// Sets whether to merge the sequences of slashes into a single slash.
+    ///
+    /// The default value is `true`.
+    pub fn merge_slashes(self, enabled: bool) -> Self {
+        self.configure(|config| config.merge_slashes = enabled)
+    }
+
+    /// Sets whether to resolve the dot segments (`.` and `..`).
+    ///
+    /// The default value is `true`.
+    pub fn resolve_dot_segments(self, enabled: bool) -> Self {
+        self.configure(|config| config.resolve_dot_segments = enabled)
+    }
+
+    /// Sets whether to convert the path to lowercase.
+    ///
+    /// The default value is `false`.
+    pub fn lowercase(self, enabled: bool) -> Self {
+        self.configure(|config| config.lowercase = enabled)
+    }
+
+    /// Sets whether to decode the percent-encoded unreserved characters and
+    /// uppercase the remaining percent-encodings.
+    ///
+    /// The default value is `true`.
+    pub fn normalize_percent_encoding(self, enabled: bool) -> Self {
+        self.configure(|config| config.normalize_percent_encoding = enabled)
+    }
+
+    /// Sets the policy for handling the non-normalized requests.
+    ///
+    /// The default value is `NormalizePolicy::Rewrite`.
+    ///
+    /// # Panics
+    /// This method panics if the status code of `NormalizePolicy::Redirect`
+    /// is not a redirection.
+    pub fn policy(self, policy: NormalizePolicy) -> Self {
+        if let NormalizePolicy::Redirect(status) = policy {
+            assert!(
+                status.is_redirection(),
+                "the status code must be a redirection"
+            );
+        }
+        self.configure(|config| config.policy = policy)
+    }
+}
+
+impl<S> super::Middleware<S> for NormalizePath {
+    type Service = NormalizePathService<S>;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        NormalizePathService {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct NormalizePathService<S> {
+    inner: S,
+    config: Arc<NormalizePathConfig>,
+}
+
+impl<S, Bd, RespBd> Service<Request<Bd>> for NormalizePathService<S>
+where
+    S: Service<Request<Bd>, Response = Response<RespBd>>,
+{
+    type Response = Response<Either<&'static str, RespBd>>;
+    type Error = S::Error;
+    type Future = NormalizePathFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Request<Bd>) -> Self::Future {
+        let normalized = self
+            .config
+            .normalize(request.uri().path())
+            .and_then(|path| replace_path(request.uri(), &path));
+
+        if let Some(uri) = normalized {
+            match self.config.policy {
+                NormalizePolicy::Rewrite => {
+                    let original = std::mem::replace(request.uri_mut(), uri);
+                    request.extensions_mut().insert(OriginalUri(original));
+                }
+                NormalizePolicy::Redirect(status) => {
+                    let location = uri
+                        .path_and_query()
+                        .map_or_else(|| uri.path().to_owned(), |pq| pq.as_str().to_owned());
+                    if let Ok(location) = HeaderValue::from_shared(location.into()) {
+                        let mut response = Response::new("");
+                        *response.status_mut() = status;
+                        response.headers_mut().insert(header::LOCATION, location);
+                        return NormalizePathFuture {
+                            inner: None,
+                            redirect: Some(response),
+                        };
+                    }
+                }
+            }
+        }
+
+        NormalizePathFuture {
+            inner: Some(self.inner.call(request)),
+            redirect: None,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct NormalizePathFuture<Fut> {
+    inner: Option<Fut>,
+    redirect: Option<Response<&'static str>>,
+}
+
+impl<Fut, Bd> Future for NormalizePathFuture<Fut>
+where
+    Fut: Future<Item = Response<Bd>>,
+{
+    type Item = Response<Either<&'static str, Bd>>;
+    type Error = Fut::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref mut inner) = self.inner {
+            let response = futures::try_ready!(inner.poll());
+            return Ok(Async::Ready(response.map(Either::Right)));
+        }
+        let response = self.redirect.take().expect("the future has already polled");
+        Ok(Async::Ready(response.map(Either::Left)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            endpoint::{self, EndpointExt},
+            middleware::tests::call,
+            service::EndpointServiceExt,
+        },
+    };
+
+    fn normalize(path: &str) -> String {
+        let config = normalize_path().config;
+        config.normalize(path).unwrap_or_else(|| path.to_owned())
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("/foo/bar"), "/foo/bar");
+        assert_eq!(normalize("//foo///bar/"), "/foo/bar/");
+        assert_eq!(normalize("/./foo/../bar/."), "/bar/");
+        assert_eq!(normalize("/../../foo"), "/foo");
+        assert_eq!(normalize("/foo/.."), "/");
+        assert_eq!(normalize("/%66%6F%6f/%2e%2E/bar"), "/bar");
+        assert_eq!(normalize("/foo%2fbar%e3%81%82"), "/foo%2Fbar%E3%81%82");
+        assert_eq!(normalize("/foo%zz%4"), "/foo%zz%4");
+        assert_eq!(normalize("*"), "*");
+    }
+
+    #[test]
+    fn test_normalize_disabled() {
+        let config = normalize_path()
+            .merge_slashes(false)
+            .resolve_dot_segments(false)
+            .normalize_percent_encoding(false)
+            .lowercase(true)
+            .config;
+        assert_eq!(
+            config.normalize("//Foo/./%2e%2E/%4A").unwrap(),
+            "//foo/./%2e%2E/%4A"
+        );
+    }
+
+    #[test]
+    fn test_lowercase() {
+        let config = normalize_path().lowercase(true).config;
+        assert_eq!(config.normalize("/Foo/%42ar%2f").unwrap(), "/foo/bar%2F");
+        assert_eq!(config.normalize("/foo"), None);
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        let endpoint = || {
+            endpoint::syntax::segment("foo")
+                .and(endpoint::syntax::segment("bar"))
+                .and(endpoint::syntax::eos())
+                .and(endpoint::endpoint(|| {
+                    use crate::action::{OneshotAction, PreflightContext};
+
+                    struct OriginalAction;
+
+                    impl OneshotAction for OriginalAction {
+                        type Output = (String,);
+
+                        fn preflight(
+                            self,
+                            cx: &mut PreflightContext<'_>,
+                        ) -> crate::error::Result<Self::Output> {
+                            let original = cx
+                                .request()
+                                .extensions()
+                                .get::<OriginalUri>()
+                                .map(|OriginalUri(uri)| uri.to_string())
+                                .unwrap_or_default();
+                            Ok((original,))
+                        }
+                    }
+
+                    OriginalAction.into_action()
+                }))
+                .into_service()
+        };
+
+        let app = endpoint().with_middleware(normalize_path());
+
+        let response = call(
+            &app,
+            Request::get("//foo/./baz/../bar?q=1").body(()).unwrap(),
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "//foo/./baz/../bar?q=1");
+
+        let response = call(&app, Request::get("/foo/bar").body(()).unwrap());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "");
+
+        let app = endpoint().with_middleware(
+            normalize_path().policy(NormalizePolicy::Redirect(StatusCode::PERMANENT_REDIRECT)),
+        );
+
+        let response = call(&app, Request::get("/%66oo//bar?q=1").body(()).unwrap());
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()["location"], "/foo/bar?q=1");
+
+        let response = call(&app, Request::get("/foo/bar").body(()).unwrap());
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
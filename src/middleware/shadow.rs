@@ -0,0 +1,593 @@
+//! A middleware which mirrors the requests to a secondary target.
+//!
+//! The `Shadow` middleware copies a sampled part of the requests (the head
+//! and the body, up to the configured size) and sends them to a
+//! `ShadowTarget`, such as another service or an upstream server, in order to
+//! validate a new implementation against the production traffic. The shadow
+//! requests are sent from the tasks spawned onto the default executor, and
+//! their responses are discarded.
+//!
+//! The primary request is never delayed nor affected by the shadow one:
+//!
+//! * The request body is teed while the primary service reads it, rather than
+//!   buffered in advance. The request is mirrored after the primary service
+//!   has read the whole body, and it is not mirrored if the body exceeds the
+//!   size limit or is not read to the end.
+//! * The errors and the timeouts of the shadow requests are only logged at
+//!   the `DEBUG` level with the target `finchers::shadow`.
+//! * The number of concurrent shadow requests is limited, and the requests
+//!   exceeding the limit are not mirrored.
+//!
+//! The mirrored requests are marked with the header `x-shadow-request: 1`
+//! by default, so that the target can suppress the side effects.
+//!
+//! Since the request body passed to the inner service is wrapped by
+//! `ShadowBody`, this middleware cannot be added by `App::with_middleware`.
+//! Use `Shadow::wrap_make_service` instead.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let shadow = middleware::shadow::shadow(shadow::upstream("http://10.0.0.2:4000".parse()?))
+//!     .sample_rate(0.05)
+//!     .max_body_size(16 * 1024);
+//!
+//! Server::new(shadow.wrap_make_service(App::new(endpoint)))
+//!     .bind("127.0.0.1:4000")
+//!     .serve()?;
+//! ```
+
+use {
+    super::access_log::Sampler,
+    bytes::{Bytes, BytesMut},
+    futures::{future::Either, Async, Future, IntoFuture, Poll, Stream},
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        uri::{self, Uri},
+        Request,
+    },
+    izanami_service::{MakeService, Service},
+    izanami_util::{
+        buf_stream::{BufStream, SizeHint},
+        http::{HasTrailers, Upgrade},
+    },
+    std::{
+        fmt,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    },
+    tokio::{
+        executor::{DefaultExecutor, Executor},
+        timer::Timeout,
+    },
+};
+
+// ==== ShadowTarget ====
+
+/// A trait representing the destination of the shadow requests.
+///
+/// `send` is called within the task of the primary request, so it should
+/// only construct the future, which is spawned onto the default executor.
+///
+/// This trait is implemented for the functions which take a `Request<Bytes>`
+/// and return an `IntoFuture`.
+pub trait ShadowTarget: Send + Sync + 'static {
+    /// The type of future returned from `send`.
+    type Future: Future<Item = (), Error = failure::Error> + Send + 'static;
+
+    /// Sends a shadow request.
+    fn send(&self, request: Request<Bytes>) -> Self::Future;
+}
+
+impl<F, R> ShadowTarget for F
+where
+    F: Fn(Request<Bytes>) -> R + Send + Sync + 'static,
+    R: IntoFuture<Item = (), Error = failure::Error>,
+    R::Future: Send + 'static,
+{
+    type Future = R::Future;
+
+    fn send(&self, request: Request<Bytes>) -> Self::Future {
+        (*self)(request).into_future()
+    }
+}
+
+/// Creates a `ShadowTarget` which sends the requests to the upstream server
+/// by using the `Client` shared within the current thread.
+///
+/// The scheme and the authority of the request URI are replaced with the
+/// ones of `base`, and the `Host` header is removed. The response body
+/// is read to the end (and discarded) so that the connection can be reused.
+///
+/// # Panics
+/// This function panics if `base` does not have the scheme or the authority.
+pub fn upstream(base: Uri) -> Upstream {
+    let parts = base.into_parts();
+    Upstream {
+        scheme: parts.scheme.expect("the base URI must have the scheme"),
+        authority: parts
+            .authority
+            .expect("the base URI must have the authority"),
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    scheme: uri::Scheme,
+    authority: uri::Authority,
+}
+
+impl ShadowTarget for Upstream {
+    type Future = Box<dyn Future<Item = (), Error = failure::Error> + Send + 'static>;
+
+    fn send(&self, request: Request<Bytes>) -> Self::Future {
+        let (mut parts, body) = request.into_parts();
+
+        let mut uri = std::mem::replace(&mut parts.uri, Uri::default()).into_parts();
+        uri.scheme = Some(self.scheme.clone());
+        uri.authority = Some(self.authority.clone());
+        if uri.path_and_query.is_none() {
+            uri.path_and_query = Some(uri::PathAndQuery::from_static("/"));
+        }
+        parts.uri = match Uri::from_parts(uri) {
+            Ok(uri) => uri,
+            Err(err) => return Box::new(futures::future::err(err.into())),
+        };
+        parts.headers.remove(header::HOST);
+
+        let request = Request::from_parts(parts, hyper::Body::from(body));
+        Box::new(
+            crate::client::shared()
+                .request(request)
+                .map_err(|err| failure::err_msg(err.to_string()))
+                .and_then(|response| response.into_body().concat2().map_err(Into::into))
+                .map(|_| ()),
+        )
+    }
+}
+
+/// Creates a `ShadowTarget` which calls the specified `Service`, such as
+/// an `App` serving the new implementation of the endpoint.
+///
+/// The request is not mirrored if the service is not ready.
+pub fn service<S>(service: S) -> ServiceTarget<S> {
+    ServiceTarget {
+        service: Mutex::new(service),
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct ServiceTarget<S> {
+    service: Mutex<S>,
+}
+
+impl<S> ShadowTarget for ServiceTarget<S>
+where
+    S: Service<Request<Bytes>> + Send + 'static,
+    S::Error: Into<failure::Error>,
+    S::Future: Send + 'static,
+{
+    type Future = Either<
+        futures::future::MapErr<
+            futures::future::Map<S::Future, fn(S::Response)>,
+            fn(S::Error) -> failure::Error,
+        >,
+        futures::future::FutureResult<(), failure::Error>,
+    >;
+
+    fn send(&self, request: Request<Bytes>) -> Self::Future {
+        let mut service = self.service.lock().unwrap_or_else(|e| e.into_inner());
+        match service.poll_ready() {
+            Ok(Async::Ready(())) => Either::A(
+                service
+                    .call(request)
+                    .map(drop as fn(S::Response))
+                    .map_err(Into::into as fn(S::Error) -> failure::Error),
+            ),
+            Ok(Async::NotReady) => Either::B(futures::future::err(failure::err_msg(
+                "the shadow service is not ready",
+            ))),
+            Err(err) => Either::B(futures::future::err(err.into())),
+        }
+    }
+}
+
+// ==== Shadow ====
+
+/// Create a middleware which mirrors the requests to the specified `ShadowTarget`.
+pub fn shadow<T>(target: T) -> Shadow<T>
+where
+    T: ShadowTarget,
+{
+    Shadow {
+        target: Arc::new(target),
+        config: Arc::new(Config {
+            sampler: Sampler::new(1.0),
+            max_body_size: 64 * 1024,
+            timeout: Duration::from_secs(10),
+            max_in_flight: 64,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            marker: Some((
+                HeaderName::from_static("x-shadow-request"),
+                HeaderValue::from_static("1"),
+            )),
+        }),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    sampler: Sampler,
+    max_body_size: usize,
+    timeout: Duration,
+    max_in_flight: usize,
+    in_flight: Arc<AtomicUsize>,
+    marker: Option<(HeaderName, HeaderValue)>,
+}
+
+#[allow(missing_docs)]
+pub struct Shadow<T> {
+    target: Arc<T>,
+    config: Arc<Config>,
+}
+
+impl<T> Clone for Shadow<T> {
+    fn clone(&self) -> Self {
+        Shadow {
+            target: self.target.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Shadow<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shadow")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl<T> Shadow<T> {
+    fn configure(self, f: impl FnOnce(&mut Config)) -> Self {
+        let mut config = Arc::try_unwrap(self.config).unwrap_or_else(|config| (*config).clone());
+        f(&mut config);
+        Shadow {
+            target: self.target,
+            config: Arc::new(config),
+        }
+    }
+
+    /// Sets the ratio of the mirrored requests, between `0.0` and `1.0`.
+    ///
+    /// The sampling is systematic, i.e. with the rate `0.05`, every 20th
+    /// request is mirrored. The default value is `1.0`.
+    ///
+    /// # Panics
+    /// This method panics if the rate is not between `0.0` and `1.0`.
+    pub fn sample_rate(self, rate: f64) -> Self {
+        let sampler = Sampler::new(rate);
+        self.configure(|config| config.sampler = sampler)
+    }
+
+    /// Sets the maximum size of the request body to be mirrored.
+    ///
+    /// The requests with the larger body are not mirrored.
+    /// The default value is 64 KiB.
+    pub fn max_body_size(self, size: usize) -> Self {
+        self.configure(|config| config.max_body_size = size)
+    }
+
+    /// Sets the timeout of each shadow request.
+    ///
+    /// The default value is 10 seconds.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.configure(|config| config.timeout = timeout)
+    }
+
+    /// Sets the maximum number of the concurrent shadow requests.
+    ///
+    /// The default value is 64.
+    pub fn max_in_flight(self, max: usize) -> Self {
+        self.configure(|config| config.max_in_flight = max)
+    }
+
+    /// Sets the header which marks the shadow requests, or removes the mark with `None`.
+    ///
+    /// The default value is `x-shadow-request: 1`.
+    pub fn marker(self, marker: Option<(HeaderName, HeaderValue)>) -> Self {
+        self.configure(|config| config.marker = marker)
+    }
+
+    /// Returns the number of the shadow requests in flight.
+    pub fn in_flight(&self) -> usize {
+        self.config.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Wraps the specified `MakeService` so that all of the services created
+    /// by it are wrapped by this middleware.
+    pub fn wrap_make_service<S>(self, make_service: S) -> WithShadow<S, T> {
+        WithShadow {
+            make_service,
+            shadow: self,
+        }
+    }
+}
+
+impl<S, T> super::Middleware<S> for Shadow<T> {
+    type Service = ShadowService<S, T>;
+
+    fn wrap(&self, inner: S) -> Self::Service {
+        ShadowService {
+            inner,
+            target: self.target.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+pub struct ShadowService<S, T> {
+    inner: S,
+    target: Arc<T>,
+    config: Arc<Config>,
+}
+
+impl<S, T> fmt::Debug for ShadowService<S, T>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShadowService")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl<S, T, Bd> Service<Request<Bd>> for ShadowService<S, T>
+where
+    S: Service<Request<ShadowBody<Bd>>>,
+    Bd: BufStream,
+    T: ShadowTarget,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Request<Bd>) -> Self::Future {
+        if !self.config.sampler.sample() {
+            return self.inner.call(request.map(|inner| ShadowBody {
+                inner,
+                capture: None,
+            }));
+        }
+
+        let mut head = Request::new(());
+        *head.method_mut() = request.method().clone();
+        *head.uri_mut() = request.uri().clone();
+        *head.version_mut() = request.version();
+        *head.headers_mut() = request.headers().clone();
+        remove_hop_by_hop_headers(head.headers_mut());
+        if let Some((ref name, ref value)) = self.config.marker {
+            head.headers_mut().insert(name.clone(), value.clone());
+        }
+
+        let (target, config) = (self.target.clone(), self.config.clone());
+        let dispatch = move |body: Bytes| dispatch(&*target, &config, head.map(|()| body));
+
+        let body = request.body();
+        if body.size_hint().upper() == Some(0) {
+            dispatch(Bytes::new());
+            return self.inner.call(request.map(|inner| ShadowBody {
+                inner,
+                capture: None,
+            }));
+        }
+
+        let max_body_size = self.config.max_body_size;
+        self.inner.call(request.map(|inner| ShadowBody {
+            inner,
+            capture: Some(Capture {
+                buf: BytesMut::new(),
+                max_body_size,
+                on_complete: Box::new(dispatch),
+            }),
+        }))
+    }
+}
+
+fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+    for name in &[
+        header::CONNECTION,
+        header::TRANSFER_ENCODING,
+        header::TE,
+        header::UPGRADE,
+        header::PROXY_AUTHORIZATION,
+    ] {
+        headers.remove(name);
+    }
+    headers.remove("keep-alive");
+}
+
+fn dispatch<T>(target: &T, config: &Config, request: Request<Bytes>)
+where
+    T: ShadowTarget,
+{
+    let in_flight = config.in_flight.clone();
+    if in_flight.fetch_add(1, Ordering::AcqRel) >= config.max_in_flight {
+        in_flight.fetch_sub(1, Ordering::AcqRel);
+        log::debug!(
+            target: "finchers::shadow",
+            "too many shadow requests in flight; {} {} is not mirrored",
+            request.method(),
+            request.uri()
+        );
+        return;
+    }
+
+    let (method, uri) = (request.method().clone(), request.uri().clone());
+    let future = Timeout::new(target.send(request), config.timeout).then(move |result| {
+        in_flight.fetch_sub(1, Ordering::AcqRel);
+        if let Err(err) = result {
+            let err = match err.into_inner() {
+                Some(err) => err.to_string(),
+                None => "timed out".to_owned(),
+            };
+            log::debug!(
+                target: "finchers::shadow",
+                "the shadow request {} {} failed: {}",
+                method,
+                uri,
+                err
+            );
+        }
+        Ok(())
+    });
+    if let Err(err) = DefaultExecutor::current().spawn(Box::new(future)) {
+        log::debug!(
+            target: "finchers::shadow",
+            "failed to spawn the shadow request: {}",
+            err
+        );
+    }
+}
+
+/// A `MakeService` created by `Shadow::wrap_make_service`.
+#[derive(Debug)]
+pub struct WithShadow<S, T> {
+    make_service: S,
+    shadow: Shadow<T>,
+}
+
+impl<S, T, Ctx, Bd> MakeService<Ctx, Request<Bd>> for WithShadow<S, T>
+where
+    S: MakeService<Ctx, Request<ShadowBody<Bd>>>,
+    Bd: BufStream,
+    T: ShadowTarget,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Service = ShadowService<S::Service, T>;
+    type MakeError = S::MakeError;
+    type Future = super::WithMiddlewareFuture<S::Future, Shadow<T>>;
+
+    fn make_service(&self, ctx: Ctx) -> Self::Future {
+        super::WithMiddlewareFuture {
+            future: self.make_service.make_service(ctx),
+            middleware: Some(self.shadow.clone()),
+        }
+    }
+}
+
+// ==== ShadowBody ====
+
+/// A request body which copies the data passing through it for the shadow request.
+pub struct ShadowBody<Bd> {
+    inner: Bd,
+    capture: Option<Capture>,
+}
+
+struct Capture {
+    buf: BytesMut,
+    max_body_size: usize,
+    on_complete: Box<dyn FnBox + Send + 'static>,
+}
+
+trait FnBox {
+    fn call_box(self: Box<Self>, data: Bytes);
+}
+
+impl<F> FnBox for F
+where
+    F: FnOnce(Bytes),
+{
+    fn call_box(self: Box<Self>, data: Bytes) {
+        (*self)(data)
+    }
+}
+
+impl<Bd: fmt::Debug> fmt::Debug for ShadowBody<Bd> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShadowBody")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<Bd> BufStream for ShadowBody<Bd>
+where
+    Bd: BufStream,
+{
+    type Item = Bd::Item;
+    type Error = Bd::Error;
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let polled = self.inner.poll_buf();
+        match polled {
+            Ok(Async::Ready(Some(ref chunk))) => {
+                if let Some(ref mut capture) = self.capture {
+                    // Only the contiguous part of each chunk is copied, as in `audit`.
+                    let data = bytes::Buf::bytes(chunk);
+                    if capture.buf.len() + data.len() > capture.max_body_size {
+                        log::debug!(
+                            target: "finchers::shadow",
+                            "the request body is too large to be mirrored"
+                        );
+                        self.capture = None;
+                    } else {
+                        capture.buf.extend_from_slice(data);
+                    }
+                }
+            }
+            Ok(Async::Ready(None)) => {
+                if let Some(capture) = self.capture.take() {
+                    capture.on_complete.call_box(capture.buf.freeze());
+                }
+            }
+            Ok(Async::NotReady) => {}
+            Err(..) => self.capture = None,
+        }
+        polled
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+
+    fn consume_hint(&mut self, amount: usize) {
+        self.inner.consume_hint(amount)
+    }
+}
+
+impl<Bd> HasTrailers for ShadowBody<Bd>
+where
+    Bd: HasTrailers,
+{
+    type TrailersError = Bd::TrailersError;
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::TrailersError> {
+        self.inner.poll_trailers()
+    }
+}
+
+impl<Bd> Upgrade for ShadowBody<Bd>
+where
+    Bd: Upgrade,
+{
+    type Upgraded = Bd::Upgraded;
+    type Error = Bd::Error;
+
+    fn poll_upgrade(&mut self) -> Poll<Self::Upgraded, Self::Error> {
+        self.inner.poll_upgrade()
+    }
+}
@@ -1,20 +1,25 @@
 //! Components for constructing HTTP responses.
 
 pub mod fs;
+pub mod payloads;
 pub mod status;
 
+mod any;
 mod binary;
 mod debug;
 mod json;
+mod length;
 mod redirect;
 mod text;
 
 use either::Either;
 use http::{Request, Response, StatusCode};
 
+pub use self::any::{AnyBody, AnyResponse};
 pub use self::debug::Debug;
 pub use self::fs::NamedFile;
-pub use self::json::Json;
+pub use self::json::{etag_json, CachePolicy, ETagJson, Json};
+pub use self::length::{BodyLength, CheckedBody};
 pub use self::redirect::Redirect;
 
 /// A trait representing the value to be converted into an HTTP response.
@@ -0,0 +1,135 @@
+use {
+    super::IntoResponse,
+    bytes::Buf,
+    futures::Poll,
+    http::{Request, Response},
+    izanami_util::buf_stream::{BufStream, SizeHint},
+    std::{error, fmt},
+};
+
+type BoxedError = Box<dyn error::Error + Send + Sync + 'static>;
+type BoxedFn = Box<dyn FnBox + Send + 'static>;
+
+trait FnBox {
+    fn call_box(self: Box<Self>, request: &Request<()>) -> Response<AnyBody>;
+}
+
+impl<F> FnBox for F
+where
+    F: FnOnce(&Request<()>) -> Response<AnyBody>,
+{
+    fn call_box(self: Box<Self>, request: &Request<()>) -> Response<AnyBody> {
+        (*self)(request)
+    }
+}
+
+/// A type-erased value which will be converted into an HTTP response.
+///
+/// This type is used for composing the endpoints whose output types are
+/// different from each other, e.g. in `routes!`.
+pub struct AnyResponse {
+    inner: BoxedFn,
+}
+
+impl fmt::Debug for AnyResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnyResponse").finish()
+    }
+}
+
+impl AnyResponse {
+    /// Erases the type of the specified value.
+    pub fn new<T>(output: T) -> Self
+    where
+        T: IntoResponse + Send + 'static,
+        T::Body: BufStream + Send + 'static,
+        <T::Body as BufStream>::Item: Send + 'static,
+        <T::Body as BufStream>::Error: Into<BoxedError>,
+    {
+        AnyResponse {
+            inner: Box::new(move |request: &Request<()>| {
+                output.into_response(request).map(AnyBody::new)
+            }),
+        }
+    }
+}
+
+impl IntoResponse for AnyResponse {
+    type Body = AnyBody;
+
+    #[inline]
+    fn into_response(self, request: &Request<()>) -> Response<Self::Body> {
+        self.inner.call_box(request)
+    }
+}
+
+/// The type-erased response body used in `AnyResponse`.
+pub struct AnyBody {
+    inner: Box<dyn BufStream<Item = Box<dyn Buf + Send>, Error = BoxedError> + Send + 'static>,
+}
+
+impl fmt::Debug for AnyBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnyBody").finish()
+    }
+}
+
+impl AnyBody {
+    pub(crate) fn new<Bd>(body: Bd) -> Self
+    where
+        Bd: BufStream + Send + 'static,
+        Bd::Item: Send + 'static,
+        Bd::Error: Into<BoxedError>,
+    {
+        struct Erased<Bd>(Bd);
+
+        impl<Bd> BufStream for Erased<Bd>
+        where
+            Bd: BufStream,
+            Bd::Item: Send + 'static,
+            Bd::Error: Into<BoxedError>,
+        {
+            type Item = Box<dyn Buf + Send>;
+            type Error = BoxedError;
+
+            fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+                self.0
+                    .poll_buf()
+                    .map(|x| x.map(|buf| buf.map(|buf| Box::new(buf) as Box<dyn Buf + Send>)))
+                    .map_err(Into::into)
+            }
+
+            fn size_hint(&self) -> SizeHint {
+                self.0.size_hint()
+            }
+
+            fn consume_hint(&mut self, amount: usize) {
+                self.0.consume_hint(amount)
+            }
+        }
+
+        AnyBody {
+            inner: Box::new(Erased(body)),
+        }
+    }
+}
+
+impl BufStream for AnyBody {
+    type Item = Box<dyn Buf + Send>;
+    type Error = BoxedError;
+
+    #[inline]
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.inner.poll_buf()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+
+    #[inline]
+    fn consume_hint(&mut self, amount: usize) {
+        self.inner.consume_hint(amount)
+    }
+}
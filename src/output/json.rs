@@ -1,8 +1,12 @@
+use bytes::Bytes;
 use http::header::HeaderValue;
-use http::{header, Request, Response, StatusCode};
+use http::{header, Method, Request, Response, StatusCode};
 use serde::Serialize;
 use serde_json;
 use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::time::Duration;
 
 use super::IntoResponse;
 
@@ -17,20 +21,24 @@ impl<T> From<T> for Json<T> {
     }
 }
 
+/// Serializes the value, or constructs the error message if failed.
+fn to_body<T: Serialize + ?Sized>(value: &T) -> Result<String, String> {
+    serde_json::to_string(value).map_err(|err| {
+        serde_json::json!({
+            "code": 500,
+            "message": format!("failed to construct JSON response: {}", err),
+        })
+        .to_string()
+    })
+}
+
 impl<T: Serialize> IntoResponse for Json<T> {
     type Body = String;
 
     fn into_response(self, _: &Request<()>) -> Response<Self::Body> {
-        let (status, body) = match serde_json::to_string(&self.0) {
+        let (status, body) = match to_body(&self.0) {
             Ok(body) => (StatusCode::OK, body),
-            Err(err) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                serde_json::json!({
-                    "code": 500,
-                    "message": format!("failed to construct JSON response: {}", err),
-                })
-                .to_string(),
-            ),
+            Err(body) => (StatusCode::INTERNAL_SERVER_ERROR, body),
         };
 
         let mut response = Response::new(body);
@@ -57,3 +65,194 @@ impl IntoResponse for Value {
         response
     }
 }
+
+/// The policy of caching used for constructing the value of `Cache-Control`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CachePolicy {
+    /// `no-cache` - the caches must revalidate the response before reusing it.
+    NoCache,
+
+    /// `private, max-age=<secs>` - only the browser may cache the response.
+    Private(Duration),
+
+    /// `public, max-age=<secs>` - the shared caches may also store the response.
+    Public(Duration),
+
+    /// An arbitrary value of `Cache-Control`.
+    Custom(HeaderValue),
+}
+
+impl CachePolicy {
+    fn to_header_value(&self) -> HeaderValue {
+        match self {
+            CachePolicy::NoCache => HeaderValue::from_static("no-cache"),
+            CachePolicy::Private(max_age) => {
+                HeaderValue::from_shared(format!("private, max-age={}", max_age.as_secs()).into())
+                    .expect("should be a valid header value")
+            }
+            CachePolicy::Public(max_age) => {
+                HeaderValue::from_shared(format!("public, max-age={}", max_age.as_secs()).into())
+                    .expect("should be a valid header value")
+            }
+            CachePolicy::Custom(value) => value.clone(),
+        }
+    }
+}
+
+/// Creates a JSON responder which supports the conditional `GET` requests.
+///
+/// The value is serialized once when the response is constructed, and a
+/// strong `ETag` is computed from the serialized bytes. If any of the entity
+/// tags in `If-None-Match` matches, the responder returns `304 Not Modified`
+/// without the body for `GET` and `HEAD` requests, and `412 Precondition
+/// Failed` for the other methods. `Cache-Control` is set from the specified
+/// policy in both cases.
+///
+/// The entity tag is derived from a 64-bit hash of the body and its length,
+/// which is stable across the processes built from the same binary.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax::path;
+/// # use finchers::output::{etag_json, CachePolicy};
+/// # use finchers::test;
+/// # use http::Request;
+/// # use std::time::Duration;
+/// let mut runner = test::runner(path!(@get "/users").map(|| {
+///     etag_json(vec!["alice", "bob"], CachePolicy::Private(Duration::from_secs(60)))
+/// }));
+///
+/// let response = runner.perform("/users").unwrap();
+/// assert_eq!(response.status().as_u16(), 200);
+/// let etag = response.headers()["etag"].clone();
+///
+/// let response = runner
+///     .perform(Request::get("/users").header("if-none-match", etag))
+///     .unwrap();
+/// assert_eq!(response.status().as_u16(), 304);
+/// ```
+pub fn etag_json<T: Serialize>(value: T, policy: CachePolicy) -> ETagJson<T> {
+    ETagJson { value, policy }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct ETagJson<T> {
+    value: T,
+    policy: CachePolicy,
+}
+
+/// Computes a strong entity tag from the serialized bytes.
+fn entity_tag(body: &[u8]) -> HeaderValue {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(body);
+    HeaderValue::from_shared(format!("\"{:016x}-{:x}\"", hasher.finish(), body.len()).into())
+        .expect("should be a valid header value")
+}
+
+/// Returns whether any entity tag in `If-None-Match` matches, using the weak comparison.
+fn if_none_match(request: &Request<()>, etag: &HeaderValue) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+    let etag = opaque(etag.to_str().expect("generated from ASCII"));
+    request
+        .headers()
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+impl<T: Serialize> IntoResponse for ETagJson<T> {
+    type Body = Bytes;
+
+    fn into_response(self, request: &Request<()>) -> Response<Self::Body> {
+        let body = match to_body(&self.value) {
+            Ok(body) => Bytes::from(body),
+            Err(body) => {
+                let mut response = Response::new(Bytes::from(body));
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                response.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/json"),
+                );
+                return response;
+            }
+        };
+        let etag = entity_tag(&body);
+
+        let mut response = if if_none_match(request, &etag) {
+            let mut response = Response::new(Bytes::new());
+            *response.status_mut() = match *request.method() {
+                Method::GET | Method::HEAD => StatusCode::NOT_MODIFIED,
+                _ => StatusCode::PRECONDITION_FAILED,
+            };
+            response
+        } else {
+            let mut response = Response::new(body);
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            );
+            response
+        };
+        response.headers_mut().insert(header::ETAG, etag);
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, self.policy.to_header_value());
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn respond(method: &str, if_none_match: Option<&str>) -> Response<Bytes> {
+        let mut request = Request::builder();
+        request.method(method).uri("/");
+        if let Some(value) = if_none_match {
+            request.header(header::IF_NONE_MATCH, value);
+        }
+        let request = request.body(()).unwrap();
+        etag_json(vec![1, 2, 3], CachePolicy::NoCache).into_response(&request)
+    }
+
+    #[test]
+    fn test_etag_json() {
+        let response = respond("GET", None);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "[1,2,3]");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        assert!(etag.starts_with('"') && etag.ends_with("-7\""));
+
+        let response = respond("GET", Some(&format!("\"foo\", W/{}", etag)));
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert!(response.body().is_empty());
+        assert_eq!(response.headers()[header::ETAG], *etag);
+
+        let response = respond("PUT", Some("*"));
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+        let response = respond("GET", Some("\"foo\""));
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_cache_policy() {
+        assert_eq!(
+            CachePolicy::Public(Duration::from_secs(3600)).to_header_value(),
+            "public, max-age=3600"
+        );
+        assert_eq!(
+            CachePolicy::Private(Duration::from_secs(60)).to_header_value(),
+            "private, max-age=60"
+        );
+    }
+}
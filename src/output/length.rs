@@ -0,0 +1,271 @@
+use {
+    bytes::{buf::Take, Buf},
+    futures::{Async, Poll},
+    http::{header, Method, Request, Response, StatusCode},
+    izanami_util::buf_stream::{BufStream, SizeHint},
+    std::{
+        error, fmt,
+        sync::{Arc, Mutex},
+    },
+};
+
+type BoxedError = Box<dyn error::Error + Send + Sync + 'static>;
+
+/// A handle for retrieving the number of bytes written to the response body.
+///
+/// The handle is stored in the extensions of the responses returned from
+/// `AppService`, and the value is updated while the body is streamed, so
+/// that the middlewares can record the size of body after it has been sent.
+#[derive(Debug, Clone, Default)]
+pub struct BodyLength {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    written: u64,
+    completed: bool,
+}
+
+impl BodyLength {
+    /// Returns the number of bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.inner.lock().unwrap().written
+    }
+
+    /// Returns whether the whole of body has been written.
+    pub fn is_complete(&self) -> bool {
+        self.inner.lock().unwrap().completed
+    }
+
+    fn add(&self, amount: u64) {
+        self.inner.lock().unwrap().written += amount;
+    }
+}
+
+/// A response body which enforces that the length of streamed data matches
+/// the `Content-Length` declared in the response header.
+///
+/// If the body yields more data than declared, the excess is discarded.
+/// If the body ends before the declared length, an error is returned so that
+/// the connection is closed instead of being reused with a broken message
+/// framing. Both cases are reported to the log.
+pub struct CheckedBody<Bd> {
+    inner: Bd,
+    expected: Option<u64>,
+    length: BodyLength,
+}
+
+impl<Bd: fmt::Debug> fmt::Debug for CheckedBody<Bd> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CheckedBody")
+            .field("inner", &self.inner)
+            .field("expected", &self.expected)
+            .field("written", &self.length.bytes_written())
+            .finish()
+    }
+}
+
+impl<Bd> CheckedBody<Bd> {
+    /// Wraps the body of the specified response.
+    ///
+    /// The declared length is ignored if the response must not have
+    /// a message body, i.e. the response to a `HEAD` request or with the
+    /// status code `1xx`, `204 No Content` or `304 Not Modified`.
+    pub(crate) fn wrap(request: &Request<()>, response: Response<Bd>) -> Response<Self> {
+        let status = response.status();
+        let expected = if request.method() == Method::HEAD
+            || status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+        {
+            None
+        } else {
+            response
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+        };
+
+        let length = BodyLength::default();
+        let mut response = response.map(|inner| CheckedBody {
+            inner,
+            expected,
+            length: length.clone(),
+        });
+        response.extensions_mut().insert(length);
+        response
+    }
+
+    /// Returns the handle for retrieving the number of written bytes.
+    pub fn length(&self) -> &BodyLength {
+        &self.length
+    }
+
+    fn remaining(&self) -> Option<u64> {
+        self.expected
+            .map(|expected| expected.saturating_sub(self.length.bytes_written()))
+    }
+
+    fn complete(&mut self) {
+        self.length.inner.lock().unwrap().completed = true;
+    }
+}
+
+impl<Bd> BufStream for CheckedBody<Bd>
+where
+    Bd: BufStream,
+    Bd::Error: Into<BoxedError>,
+{
+    type Item = Take<Bd::Item>;
+    type Error = BoxedError;
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.length.is_complete() {
+            return Ok(Async::Ready(None));
+        }
+
+        match futures::try_ready!(self.inner.poll_buf().map_err(Into::into)) {
+            Some(chunk) => {
+                let len = chunk.remaining() as u64;
+                let limit = match self.remaining() {
+                    Some(remaining) if len > remaining => {
+                        log::warn!(
+                            "the response body is longer than its Content-Length (expected = {}); the excess is truncated",
+                            self.expected.unwrap_or(0),
+                        );
+                        self.complete();
+                        remaining
+                    }
+                    _ => len,
+                };
+                self.length.add(limit);
+                if self.remaining() == Some(0) {
+                    self.complete();
+                }
+                Ok(Async::Ready(Some(chunk.take(limit as usize))))
+            }
+            None => {
+                if let Some(expected) = self.expected {
+                    let actual = self.length.bytes_written();
+                    if actual < expected {
+                        log::error!(
+                            "the response body is shorter than its Content-Length (expected = {}, actual = {})",
+                            expected,
+                            actual,
+                        );
+                        return Err(Box::new(LengthMismatch { expected, actual }));
+                    }
+                }
+                self.complete();
+                Ok(Async::Ready(None))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.remaining() {
+            Some(remaining) => {
+                let mut hint = SizeHint::new();
+                hint.set_upper(remaining);
+                hint.set_lower(remaining);
+                hint
+            }
+            None => self.inner.size_hint(),
+        }
+    }
+
+    fn consume_hint(&mut self, amount: usize) {
+        self.inner.consume_hint(amount)
+    }
+}
+
+#[derive(Debug)]
+struct LengthMismatch {
+    expected: u64,
+    actual: u64,
+}
+
+impl fmt::Display for LengthMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the response body ended before its Content-Length (expected = {}, actual = {})",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl error::Error for LengthMismatch {}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, futures::Future};
+
+    fn collect<Bd>(mut body: CheckedBody<Bd>) -> Result<Vec<u8>, BoxedError>
+    where
+        Bd: BufStream,
+        Bd::Error: Into<BoxedError>,
+    {
+        let mut buf = vec![];
+        futures::future::poll_fn(|| -> Poll<(), BoxedError> {
+            while let Some(chunk) = futures::try_ready!(body.poll_buf()) {
+                buf.extend_from_slice(chunk.bytes());
+            }
+            Ok(().into())
+        })
+        .wait()?;
+        Ok(buf)
+    }
+
+    fn response(body: &'static str, content_length: &str) -> Response<&'static str> {
+        Response::builder()
+            .header(header::CONTENT_LENGTH, content_length)
+            .body(body)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_matched() {
+        let request = Request::new(());
+        let response = CheckedBody::wrap(&request, response("hello", "5"));
+        let length = response.extensions().get::<BodyLength>().cloned().unwrap();
+
+        let body = collect(response.into_body()).unwrap();
+        assert_eq!(body, b"hello");
+        assert_eq!(length.bytes_written(), 5);
+        assert!(length.is_complete());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let request = Request::new(());
+        let response = CheckedBody::wrap(&request, response("hello, world", "5"));
+        let length = response.extensions().get::<BodyLength>().cloned().unwrap();
+
+        let body = collect(response.into_body()).unwrap();
+        assert_eq!(body, b"hello");
+        assert_eq!(length.bytes_written(), 5);
+    }
+
+    #[test]
+    fn test_too_short() {
+        let request = Request::new(());
+        let response = CheckedBody::wrap(&request, response("hi", "5"));
+        let length = response.extensions().get::<BodyLength>().cloned().unwrap();
+
+        assert!(collect(response.into_body()).is_err());
+        assert_eq!(length.bytes_written(), 2);
+        assert!(!length.is_complete());
+    }
+
+    #[test]
+    fn test_head() {
+        let request = Request::head("/").body(()).unwrap();
+        let response = CheckedBody::wrap(&request, response("", "5"));
+
+        let body = collect(response.into_body()).unwrap();
+        assert!(body.is_empty());
+    }
+}
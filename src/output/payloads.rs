@@ -0,0 +1,299 @@
+//! Response bodies which are sent without copying the underlying buffers.
+//!
+//! The contents shared by `Arc` (e.g. the responses held in an in-memory
+//! cache) and the memory-mapped files are passed to the transport as they
+//! are, instead of being copied into a fresh buffer for each response.
+
+use {
+    super::IntoResponse,
+    futures::{Async, Poll},
+    http::{header, header::HeaderValue, Request, Response},
+    izanami_util::buf_stream::{BufStream, SizeHint},
+    std::{fmt, io, sync::Arc},
+};
+
+#[cfg(unix)]
+pub use self::mapped::{MappedFile, Mapping};
+
+/// A reference-counted byte sequence, which is used as the chunk of `SharedBody`.
+pub struct SharedBytes<T: ?Sized>(Arc<T>);
+
+impl<T: ?Sized> Clone for SharedBytes<T> {
+    fn clone(&self) -> Self {
+        SharedBytes(self.0.clone())
+    }
+}
+
+impl<T: ?Sized + AsRef<[u8]>> AsRef<[u8]> for SharedBytes<T> {
+    fn as_ref(&self) -> &[u8] {
+        (*self.0).as_ref()
+    }
+}
+
+impl<T: ?Sized + AsRef<[u8]>> fmt::Debug for SharedBytes<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedBytes")
+            .field("len", &self.as_ref().len())
+            .finish()
+    }
+}
+
+/// A response body which sends the contents shared by `Arc` without copying.
+pub struct SharedBody<T: ?Sized> {
+    data: Option<SharedBytes<T>>,
+}
+
+impl<T: ?Sized + AsRef<[u8]>> fmt::Debug for SharedBody<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedBody")
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
+impl<T: ?Sized + AsRef<[u8]>> SharedBody<T> {
+    /// Creates a new `SharedBody` from the specified contents.
+    pub fn new(data: Arc<T>) -> Self {
+        SharedBody {
+            data: Some(SharedBytes(data)),
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.data
+            .as_ref()
+            .map_or(0, |data| data.as_ref().len() as u64)
+    }
+}
+
+impl<T: ?Sized + AsRef<[u8]>> BufStream for SharedBody<T> {
+    type Item = io::Cursor<SharedBytes<T>>;
+    type Error = io::Error;
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.data.take() {
+            Some(ref data) if data.as_ref().is_empty() => Ok(Async::Ready(None)),
+            Some(data) => Ok(Async::Ready(Some(io::Cursor::new(data)))),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let mut hint = SizeHint::new();
+        hint.set_upper(self.len());
+        hint.set_lower(self.len());
+        hint
+    }
+}
+
+impl IntoResponse for Arc<[u8]> {
+    type Body = SharedBody<[u8]>;
+
+    fn into_response(self, _: &Request<()>) -> Response<Self::Body> {
+        make_shared_response(self, "application/octet-stream")
+    }
+}
+
+impl IntoResponse for Arc<str> {
+    type Body = SharedBody<str>;
+
+    fn into_response(self, _: &Request<()>) -> Response<Self::Body> {
+        make_shared_response(self, "text/plain; charset=utf-8")
+    }
+}
+
+fn make_shared_response<T>(data: Arc<T>, content_type: &'static str) -> Response<SharedBody<T>>
+where
+    T: ?Sized + AsRef<[u8]>,
+{
+    let mut response = Response::new(SharedBody::new(data));
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response
+}
+
+#[cfg(unix)]
+mod mapped {
+    use {
+        super::*,
+        mime_guess::from_path,
+        std::{
+            fs::File,
+            os::unix::io::AsRawFd,
+            path::{Path, PathBuf},
+            ptr, slice,
+        },
+    };
+
+    /// A read-only memory mapping of a file.
+    pub struct Mapping {
+        ptr: *mut libc::c_void,
+        len: usize,
+    }
+
+    // The mapping is read-only and never changes its address.
+    unsafe impl Send for Mapping {}
+    unsafe impl Sync for Mapping {}
+
+    impl Mapping {
+        fn new(file: &File) -> io::Result<Mapping> {
+            let len = file.metadata()?.len() as usize;
+            if len == 0 {
+                // mmap(2) rejects the empty mappings.
+                return Ok(Mapping {
+                    ptr: ptr::null_mut(),
+                    len: 0,
+                });
+            }
+            let ptr = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    len,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Mapping { ptr, len })
+        }
+    }
+
+    impl AsRef<[u8]> for Mapping {
+        fn as_ref(&self) -> &[u8] {
+            if self.len == 0 {
+                return &[];
+            }
+            unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            if self.len > 0 {
+                unsafe {
+                    libc::munmap(self.ptr, self.len);
+                }
+            }
+        }
+    }
+
+    impl fmt::Debug for Mapping {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Mapping").field("len", &self.len).finish()
+        }
+    }
+
+    /// An instance of `Output` representing a memory-mapped file.
+    ///
+    /// Unlike `NamedFile`, the contents are sent directly from the page cache
+    /// without being read into the intermediate buffers, and the mapping can be
+    /// cloned cheaply and kept for serving the same file repeatedly.
+    ///
+    /// Since the contents are not copied, the file must not be modified while
+    /// it is mapped. See the safety section of `MappedFile::open`.
+    #[derive(Debug, Clone)]
+    pub struct MappedFile {
+        mapping: Arc<Mapping>,
+        path: PathBuf,
+    }
+
+    impl MappedFile {
+        /// Maps the specified file into the memory.
+        ///
+        /// This function blocks the current thread while opening the file.
+        ///
+        /// # Safety
+        ///
+        /// The caller must ensure that the file is not modified or truncated
+        /// by this or any other process while the returned value (or its
+        /// clones) are alive. Modifying the file changes the contents seen
+        /// through the `&[u8]` returned from `as_bytes`, which is undefined
+        /// behavior, and accessing the truncated part of the mapping
+        /// terminates the process with `SIGBUS`.
+        pub unsafe fn open(path: impl AsRef<Path>) -> io::Result<MappedFile> {
+            let path = path.as_ref();
+            let file = File::open(path)?;
+            Ok(MappedFile {
+                mapping: Arc::new(Mapping::new(&file)?),
+                path: path.to_owned(),
+            })
+        }
+
+        /// Returns the path of the mapped file.
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+
+        /// Returns the contents of the mapped file.
+        pub fn as_bytes(&self) -> &[u8] {
+            (*self.mapping).as_ref()
+        }
+    }
+
+    impl IntoResponse for MappedFile {
+        type Body = SharedBody<Mapping>;
+
+        fn into_response(self, _: &Request<()>) -> Response<Self::Body> {
+            let content_type = from_path(&self.path).first_or_octet_stream();
+            Response::builder()
+                .header(header::CONTENT_LENGTH, self.mapping.len)
+                .header(header::CONTENT_TYPE, content_type.as_ref())
+                .body(SharedBody::new(self.mapping))
+                .unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, bytes::Buf};
+
+    fn collect<T: ?Sized + AsRef<[u8]>>(mut body: SharedBody<T>) -> Vec<u8> {
+        let mut data = vec![];
+        while let Async::Ready(Some(chunk)) = body.poll_buf().unwrap() {
+            data.extend_from_slice(chunk.bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_shared_body() {
+        let data: Arc<str> = Arc::from("Hello");
+        let response = data.clone().into_response(&Request::new(()));
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+        let body = response.into_body();
+        assert_eq!(body.size_hint().upper(), Some(5));
+        assert_eq!(Arc::strong_count(&data), 2);
+        assert_eq!(collect(body), b"Hello");
+
+        let empty: Arc<[u8]> = Arc::from(&[][..]);
+        assert!(collect(SharedBody::new(empty)).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mapped_file() {
+        let path = std::env::temp_dir().join(format!("finchers-mapped-{}.txt", std::process::id()));
+        std::fs::write(&path, "mapped contents").unwrap();
+
+        // The file is only written by this test.
+        let file = unsafe { MappedFile::open(&path) }.unwrap();
+        assert_eq!(file.as_bytes(), b"mapped contents");
+
+        let response = file.clone().into_response(&Request::new(()));
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(response.headers()["content-length"], "15");
+        assert_eq!(collect(response.into_body()), b"mapped contents");
+
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
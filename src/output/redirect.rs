@@ -26,6 +26,14 @@ impl Redirect {
             ..self
         }
     }
+
+    /// Sets the value of header field `Location` to a dynamically created value.
+    pub fn location_value(self, location: HeaderValue) -> Redirect {
+        Redirect {
+            location: Some(location),
+            ..self
+        }
+    }
 }
 
 macro_rules! impl_constructors {
@@ -55,10 +63,11 @@ impl Redirect {
 }
 
 impl IntoResponse for Redirect {
-    type Body = ();
+    // `()` cannot be used as the response body since it does not implement `BufStream`.
+    type Body = &'static [u8];
 
     fn into_response(self, _: &Request<()>) -> Response<Self::Body> {
-        let mut response = Response::new(());
+        let mut response = Response::new(&[] as &[u8]);
         *response.status_mut() = self.status;
         if let Some(location) = self.location {
             response.headers_mut().insert(LOCATION, location);
@@ -66,3 +75,18 @@ impl IntoResponse for Redirect {
         response
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{endpoint, test};
+
+    #[test]
+    fn test_redirect() {
+        let mut runner = test::runner(endpoint::value(Redirect::see_other("/login")));
+        let response = runner.perform("/").unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()["location"], "/login");
+        assert!(response.body().is_empty());
+    }
+}
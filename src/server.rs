@@ -0,0 +1,2058 @@
+//! A lightweight HTTP server for serving endpoints, based on Hyper.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use finchers::prelude::*;
+//! # use finchers::server;
+//! let endpoint = endpoint::unit().map(|| "Hello, world");
+//!
+//! server::start(endpoint)
+//!     .bind("127.0.0.1:4000")
+//!     .serve()
+//!     .expect("failed to start the server");
+//! ```
+//!
+//! # Execution modes
+//!
+//! The server started by `start` runs on the multi-threaded Tokio runtime,
+//! and hence the endpoint, its actions and the responses must be `Send`.
+//!
+//! The server started by `start_local` runs on the single-threaded runtime
+//! instead. In this mode, the endpoint and its actions are not required to be
+//! `Send`, which allows the endpoints to share the state via `Rc` and
+//! `RefCell` without any synchronization:
+//!
+//! ```no_run
+//! # use finchers::prelude::*;
+//! # use finchers::server;
+//! # use std::{cell::Cell, rc::Rc};
+//! let counter = Rc::new(Cell::new(0));
+//! let endpoint = endpoint::unit().map(move || {
+//!     counter.set(counter.get() + 1);
+//!     format!("visitor #{}", counter.get())
+//! });
+//!
+//! server::start_local(endpoint)
+//!     .bind("127.0.0.1:4000")
+//!     .serve()
+//!     .expect("failed to start the server");
+//! ```
+
+#[cfg(feature = "acme")]
+pub mod acme;
+mod config;
+mod conn;
+mod error;
+mod expect;
+mod lifecycle;
+mod metrics;
+mod reload;
+mod schedule;
+mod source;
+mod strict;
+
+pub use self::{
+    config::{Config, H2cConfig, TlsConfig},
+    conn::{Acceptor, Alpn, ClientAuth, Connection},
+    error::{ServerError, ServerResult},
+    reload::{Reloadable, Watch},
+    schedule::{InvalidSchedule, Schedule},
+    source::RequestSource,
+    strict::StrictParsing,
+};
+
+pub(crate) use self::source::{error_response, ReadAll};
+
+#[cfg(feature = "runtime-metrics")]
+pub use self::metrics::{RuntimeMetrics, RuntimeSnapshot};
+
+use {
+    self::{
+        conn::Listener,
+        expect::ExpectContinue,
+        lifecycle::Lifecycle,
+        metrics::{Instrumented, InstrumentedExecutor},
+        source::Source,
+    },
+    crate::{
+        endpoints::auth::ClientCertChain,
+        error::{Error, HttpError},
+        jobs::JobQueue,
+        service::App,
+    },
+    bytes::Bytes,
+    futures::{future, Future, IntoFuture, Poll},
+    http::{
+        header::{self, HeaderValue},
+        HeaderMap, Request, Response, StatusCode, Version,
+    },
+    hyper::{
+        body::Payload,
+        server::conn::{AddrIncoming, Http},
+    },
+    izanami_service::{MakeServiceRef, Service},
+    izanami_util::{
+        buf_stream::{BufStream, SizeHint},
+        http::{HasTrailers, Upgrade},
+    },
+    std::{
+        error::Error as StdError,
+        fmt, io,
+        marker::PhantomData,
+        mem,
+        net::{SocketAddr, ToSocketAddrs},
+        sync::Arc,
+        time::Duration,
+    },
+    tokio::{executor::DefaultExecutor, runtime::current_thread},
+};
+
+type CritError = Box<dyn StdError + Send + Sync + 'static>;
+
+type Signal = Box<dyn Future<Item = (), Error = ()> + Send + 'static>;
+
+/// Create a `Server` which serves the specified endpoint on the
+/// multi-threaded runtime.
+pub fn start<E>(endpoint: E) -> Server<App<E>> {
+    Server::from(App::new(endpoint))
+}
+
+/// Create a `Server` which serves the specified endpoint on the
+/// single-threaded runtime.
+///
+/// Unlike `start`, the endpoint and its actions are not required to be `Send`.
+pub fn start_local<E>(endpoint: E) -> Server<App<E>, CurrentThread> {
+    start(endpoint).current_thread()
+}
+
+// ==== DropConnection ====
+
+/// An error which makes the server drop the connection instead of sending
+/// the error response.
+///
+/// When this error is returned from the endpoint, the HTTP/1 connection is
+/// closed without sending any response and the HTTP/2 stream is reset.
+/// The other runners (e.g. `test::TestRunner`) respond with the status code
+/// specified at construction.
+#[derive(Debug, failure::Fail)]
+#[fail(display = "the connection is dropped")]
+pub struct DropConnection {
+    status: StatusCode,
+}
+
+impl DropConnection {
+    /// Creates a new `DropConnection` with the status code used by the runners
+    /// which do not drop the connection.
+    pub fn new(status: StatusCode) -> Self {
+        DropConnection { status }
+    }
+}
+
+impl HttpError for DropConnection {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+}
+
+// ==== RequestBody ====
+
+/// The type of request body used in `Server`.
+///
+/// This type wraps the message body received by Hyper's HTTP server.
+#[derive(Debug)]
+pub struct RequestBody {
+    inner: Inner,
+    expect_continue: Option<Arc<ExpectContinue>>,
+}
+
+#[derive(Debug)]
+enum Inner {
+    Raw(hyper::Body),
+    OnUpgrade(hyper::upgrade::OnUpgrade),
+}
+
+impl RequestBody {
+    pub(crate) fn from_hyp(body: hyper::Body) -> Self {
+        RequestBody {
+            inner: Inner::Raw(body),
+            expect_continue: None,
+        }
+    }
+}
+
+impl From<Bytes> for RequestBody {
+    /// Creates a `RequestBody` from the entirely buffered content,
+    /// e.g. the standard input received by a `RequestSource`.
+    fn from(data: Bytes) -> Self {
+        RequestBody::from_hyp(hyper::Body::from(data))
+    }
+}
+
+impl BufStream for RequestBody {
+    type Item = hyper::Chunk;
+    type Error = hyper::Error;
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(expect_continue) = self.expect_continue.take() {
+            expect_continue.release();
+        }
+        match &mut self.inner {
+            Inner::Raw(body) => body.poll_data(),
+            Inner::OnUpgrade(..) => panic!("the request body has already been upgraded"),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.inner {
+            Inner::Raw(body) => {
+                let mut hint = SizeHint::new();
+                if let Some(len) = body.content_length() {
+                    hint.set_upper(len);
+                    hint.set_lower(len);
+                }
+                hint
+            }
+            Inner::OnUpgrade(..) => panic!("the request body has already been upgraded"),
+        }
+    }
+}
+
+impl HasTrailers for RequestBody {
+    type TrailersError = hyper::Error;
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::TrailersError> {
+        match &mut self.inner {
+            Inner::Raw(body) => body.poll_trailers(),
+            Inner::OnUpgrade(..) => panic!("the request body has already been upgraded"),
+        }
+    }
+}
+
+impl Upgrade for RequestBody {
+    type Upgraded = hyper::upgrade::Upgraded;
+    type Error = hyper::Error;
+
+    fn poll_upgrade(&mut self) -> Poll<Self::Upgraded, Self::Error> {
+        loop {
+            self.inner = match &mut self.inner {
+                Inner::Raw(body) => {
+                    let body = mem::replace(body, hyper::Body::empty());
+                    Inner::OnUpgrade(body.on_upgrade())
+                }
+                Inner::OnUpgrade(on_upgrade) => return on_upgrade.poll(),
+            };
+        }
+    }
+}
+
+// ==== Server ====
+
+/// An HTTP server which serves a `MakeService` on the bound addresses.
+///
+/// The type parameter `B` specifies the runtime on which the server runs,
+/// either `Threadpool` (the default) or `CurrentThread`.
+pub struct Server<S, B = Threadpool> {
+    make_service: S,
+    listeners: Vec<Listener>,
+    sources: Vec<Source>,
+    protocol: Http,
+    tcp_nodelay: bool,
+    tcp_keepalive: Option<Duration>,
+    tls_handshake_timeout: Duration,
+    max_tls_handshakes: usize,
+    h2c_max_concurrent_streams: Option<u32>,
+    h2_max_concurrent_streams: Option<u32>,
+    strict_parsing: Option<StrictParsing>,
+    signal: Option<Signal>,
+    lifecycle: Lifecycle,
+    runtime_metrics: Option<metrics::RuntimeMetrics>,
+    error: Option<ServerError>,
+    _marker: PhantomData<fn() -> B>,
+}
+
+impl<S, B> fmt::Debug for Server<S, B>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("make_service", &self.make_service)
+            .field("listeners", &self.listeners)
+            .field("sources", &self.sources)
+            .field("protocol", &self.protocol)
+            .field("lifecycle", &self.lifecycle)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl<S> Server<S> {
+    /// Create a new `Server` from the specified `MakeService`.
+    pub fn new(make_service: S) -> Self {
+        Server {
+            make_service,
+            listeners: vec![],
+            sources: vec![],
+            protocol: Http::new(),
+            tcp_nodelay: false,
+            tcp_keepalive: None,
+            tls_handshake_timeout: Duration::from_secs(conn::DEFAULT_HANDSHAKE_TIMEOUT_SECS),
+            max_tls_handshakes: conn::DEFAULT_MAX_HANDSHAKES,
+            h2c_max_concurrent_streams: None,
+            h2_max_concurrent_streams: None,
+            strict_parsing: None,
+            signal: None,
+            lifecycle: Lifecycle::new(),
+            runtime_metrics: None,
+            error: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<E, H> From<App<E, H>> for Server<App<E, H>> {
+    /// Create a new `Server` from the specified `App`.
+    ///
+    /// Unlike `Server::new`, the server created with this method notifies
+    /// the endpoints of the `App` when it starts shutting down gracefully
+    /// (see `Context::shutdown`).
+    fn from(app: App<E, H>) -> Self {
+        let mut lifecycle = Lifecycle::new();
+        lifecycle.set_shutdown_token(app.shutdown_token());
+        Server {
+            lifecycle,
+            ..Server::new(app)
+        }
+    }
+}
+
+impl<S, B> Server<S, B> {
+    /// Binds the server to the specified address.
+    ///
+    /// This method can be called multiple times in order to listen on
+    /// several addresses. The errors occurred while binding are reported
+    /// when the server starts.
+    pub fn bind(mut self, addr: impl ToSocketAddrs) -> Self {
+        if self.error.is_none() {
+            if let Err(err) = self.try_bind(addr, Listener::plain) {
+                self.error = Some(err);
+            }
+        }
+        self
+    }
+
+    /// Binds the server to the specified address, and performs the handshake
+    /// using the specified `Acceptor` on each accepted connection.
+    ///
+    /// The listeners registered by `bind` and `bind_tls` share the same
+    /// runtime and the same instance of `MakeService`, and hence a server
+    /// can serve both the plaintext and TLS connections:
+    ///
+    /// ```no_run
+    /// # use finchers::prelude::*;
+    /// # use finchers::server;
+    /// # use tokio::net::TcpStream;
+    /// # // a stand-in for the acceptor of a TLS library
+    /// # let tls_acceptor = |stream: TcpStream| Ok::<_, std::io::Error>(stream);
+    /// # let endpoint = finchers::endpoint::unit().map(|| "Hello");
+    /// server::start(endpoint)
+    ///     .bind("0.0.0.0:80")
+    ///     .bind_tls("0.0.0.0:443", tls_acceptor)
+    ///     .serve()
+    ///     .expect("failed to start the server");
+    /// ```
+    ///
+    /// The connections accepted by this listener are marked as secure
+    /// (see `Connection::is_secure`).
+    pub fn bind_tls<A>(mut self, addr: impl ToSocketAddrs, acceptor: A) -> Self
+    where
+        A: Acceptor + Send + Sync + 'static,
+    {
+        if self.error.is_none() {
+            let acceptor = Arc::new(acceptor);
+            let result = self.try_bind(addr, |incoming| {
+                Listener::with_acceptor(incoming, acceptor.clone())
+            });
+            if let Err(err) = result {
+                self.error = Some(err);
+            }
+        }
+        self
+    }
+
+    /// Binds the server to the specified address, and serves HTTP/2 with
+    /// prior knowledge (h2c) on the accepted connections.
+    ///
+    /// The connections accepted by this listener must start with the HTTP/2
+    /// connection preface, and the HTTP/1 requests are rejected. Note that
+    /// the listeners registered by `bind` also detect the preface and switch
+    /// to HTTP/2 unless `http1_only` is set, whereas the upgrade from HTTP/1.1
+    /// by the `Upgrade: h2c` header is not supported.
+    pub fn bind_h2c(mut self, addr: impl ToSocketAddrs) -> Self {
+        if self.error.is_none() {
+            if let Err(err) = self.try_bind(addr, Listener::h2c) {
+                self.error = Some(err);
+            }
+        }
+        self
+    }
+
+    /// Binds the server to the specified address, and serves the protocol
+    /// implemented by `source` on the accepted connections.
+    ///
+    /// The requests received on this listener are dispatched to the same
+    /// `MakeService` as the HTTP listeners. For example, a server can serve
+    /// both HTTP and FastCGI:
+    ///
+    /// ```no_run
+    /// # use finchers::prelude::*;
+    /// # use finchers::server;
+    /// # use finchers::fastcgi::FastCgi;
+    /// # let endpoint = endpoint::unit().map(|| "Hello, world");
+    /// server::start(endpoint)
+    ///     .bind("127.0.0.1:4000")
+    ///     .bind_with("127.0.0.1:9000", FastCgi::new())
+    ///     .serve()
+    ///     .expect("failed to start the server");
+    /// ```
+    pub fn bind_with<R>(mut self, addr: impl ToSocketAddrs, source: R) -> Self
+    where
+        R: RequestSource + Send + Sync + 'static,
+    {
+        if self.error.is_none() {
+            match bind_listeners(addr, Listener::plain) {
+                Ok(listeners) => {
+                    let source = Arc::new(source);
+                    self.sources.extend(
+                        listeners
+                            .into_iter()
+                            .map(|listener| Source::new(listener, source.clone())),
+                    );
+                }
+                Err(err) => self.error = Some(err),
+            }
+        }
+        self
+    }
+
+    fn try_bind(
+        &mut self,
+        addr: impl ToSocketAddrs,
+        f: impl Fn(AddrIncoming) -> Listener,
+    ) -> ServerResult<()> {
+        let listeners = bind_listeners(addr, f)?;
+        self.listeners.extend(listeners);
+        Ok(())
+    }
+
+    /// Returns an iterator over the local addresses which the server is bound to.
+    pub fn local_addrs(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.listeners
+            .iter()
+            .map(Listener::local_addr)
+            .chain(self.sources.iter().map(Source::local_addr))
+    }
+
+    /// Returns a mutable reference to the HTTP-level configuration.
+    pub fn protocol(&mut self) -> &mut Http {
+        &mut self.protocol
+    }
+
+    /// Sets whether to disable Nagle's algorithm on the accepted connections.
+    ///
+    /// The default value is `false`.
+    pub fn tcp_nodelay(self, enabled: bool) -> Self {
+        Server {
+            tcp_nodelay: enabled,
+            ..self
+        }
+    }
+
+    /// Sets the duration of TCP keepalive on the accepted connections.
+    ///
+    /// The default value is `None`, which disables TCP keepalive.
+    pub fn tcp_keepalive(self, keepalive: Option<Duration>) -> Self {
+        Server {
+            tcp_keepalive: keepalive,
+            ..self
+        }
+    }
+
+    /// Sets the duration of time allowed to complete the handshake on the
+    /// connections accepted by the listeners registered by `bind_tls`.
+    ///
+    /// The connections whose handshake has not completed within the duration
+    /// are closed. The default value is 10 seconds.
+    pub fn tls_handshake_timeout(self, timeout: Duration) -> Self {
+        Server {
+            tls_handshake_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of handshakes performed concurrently on each
+    /// listener registered by `bind_tls`.
+    ///
+    /// While the number of in-flight handshakes reaches the limit, the listener
+    /// stops accepting the new connections. The default value is 128, and
+    /// zero is treated as one.
+    pub fn max_tls_handshakes(self, max: usize) -> Self {
+        Server {
+            max_tls_handshakes: max,
+            ..self
+        }
+    }
+
+    /// Sets whether to enable HTTP/1 keep-alive.
+    ///
+    /// The default value is `true`.
+    pub fn keep_alive(mut self, enabled: bool) -> Self {
+        self.protocol.keep_alive(enabled);
+        self
+    }
+
+    /// Sets the maximum size of the buffer used for reading the HTTP/1 messages,
+    /// in bytes.
+    ///
+    /// The value must be at least 8192, and otherwise an error is reported
+    /// when the server starts.
+    pub fn max_buf_size(mut self, max: usize) -> Self {
+        if self.error.is_none() {
+            if let Err(err) = self.set_max_buf_size(max) {
+                self.error = Some(err);
+            }
+        }
+        self
+    }
+
+    fn set_max_buf_size(&mut self, max: usize) -> ServerResult<()> {
+        // Hyper panics if the buffer size is less than its initial size.
+        if max < 8192 {
+            return Err(ServerError::config(failure::err_msg(
+                "max_buf_size must be at least 8192",
+            )));
+        }
+        self.protocol.max_buf_size(max);
+        Ok(())
+    }
+
+    /// Sets whether to aggregate the flushes of the pipelined HTTP/1 responses.
+    ///
+    /// This is an experimental feature of Hyper, and the default value is `false`.
+    pub fn pipeline_flush(mut self, enabled: bool) -> Self {
+        self.protocol.pipeline_flush(enabled);
+        self
+    }
+
+    /// Sets whether to keep the HTTP/1 connections open after the client
+    /// has shut down the write half of the connection.
+    ///
+    /// The default value is `true`.
+    pub fn http1_half_close(mut self, enabled: bool) -> Self {
+        self.protocol.http1_half_close(enabled);
+        self
+    }
+
+    /// Sets whether to use the vectored writes for the HTTP/1 connections.
+    ///
+    /// The default value is `true`. Disabling it may improve the performance
+    /// with the transports which do not support the vectored writes efficiently,
+    /// such as some TLS implementations.
+    pub fn http1_writev(mut self, enabled: bool) -> Self {
+        self.protocol.http1_writev(enabled);
+        self
+    }
+
+    /// Sets whether to support only HTTP/1.
+    ///
+    /// This option and `http2_only` are mutually exclusive, and the one
+    /// called last takes effect.
+    pub fn http1_only(mut self, enabled: bool) -> Self {
+        self.protocol.http1_only(enabled);
+        self
+    }
+
+    /// Sets whether to support only HTTP/2, i.e. to assume that the clients
+    /// speak HTTP/2 with prior knowledge.
+    ///
+    /// This option and `http1_only` are mutually exclusive, and the one
+    /// called last takes effect.
+    pub fn http2_only(mut self, enabled: bool) -> Self {
+        self.protocol.http2_only(enabled);
+        self
+    }
+
+    /// Sets the maximum number of concurrent streams on each HTTP/2 connection
+    /// over the plaintext listeners (h2c).
+    ///
+    /// The default value is `None`, which uses the default of Hyper.
+    pub fn h2c_max_concurrent_streams(self, max: Option<u32>) -> Self {
+        Server {
+            h2c_max_concurrent_streams: max,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of concurrent streams on each HTTP/2 connection
+    /// over the TLS listeners.
+    ///
+    /// The clients select HTTP/2 by ALPN during the TLS handshake, and the
+    /// connections are switched to HTTP/2 by detecting the connection preface,
+    /// hence `http1_only` should not be set if the acceptor advertises `h2`.
+    ///
+    /// The default value is `None`, which uses the default of Hyper.
+    pub fn h2_max_concurrent_streams(self, max: Option<u32>) -> Self {
+        Server {
+            h2_max_concurrent_streams: max,
+            ..self
+        }
+    }
+
+    /// Enables the strict validation of request headers.
+    ///
+    /// See the documentation of `StrictParsing` for details.
+    pub fn strict_parsing(self, config: StrictParsing) -> Self {
+        Server {
+            strict_parsing: Some(config),
+            ..self
+        }
+    }
+
+    /// Registers a hook called before the server starts serving the connections.
+    ///
+    /// The hooks are run in the order of registration, and each of them is
+    /// awaited before the next one starts. This is typically used for running
+    /// the database migrations or warming up the caches. If any of the hooks
+    /// fails, the server does not start, the shutdown hooks are run and then
+    /// the error is returned from `serve`.
+    ///
+    /// ```no_run
+    /// # use finchers::prelude::*;
+    /// # use finchers::server;
+    /// # let endpoint = endpoint::unit();
+    /// server::start(endpoint)
+    ///     .bind("127.0.0.1:4000")
+    ///     .on_start(|| {
+    ///         println!("run the migrations");
+    ///         Ok::<(), failure::Error>(())
+    ///     })
+    ///     .on_shutdown(|| {
+    ///         println!("flush the buffers");
+    ///         Ok::<(), failure::Error>(())
+    ///     })
+    ///     .serve()
+    ///     .expect("failed to start the server");
+    /// ```
+    pub fn on_start<F, R>(mut self, f: F) -> Self
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: Send + 'static,
+        R::Error: Into<failure::Error> + 'static,
+    {
+        self.lifecycle.push_start(f);
+        self
+    }
+
+    /// Registers a hook called after the server has shut down gracefully.
+    ///
+    /// The hooks are run after all of the in-flight connections are closed,
+    /// in the **reverse** order of registration, so that the resources set up
+    /// by the earlier hooks are released last. The failures of the hooks are
+    /// logged and do not prevent the remaining hooks from running.
+    pub fn on_shutdown<F, R>(mut self, f: F) -> Self
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: Send + 'static,
+        R::Error: Into<failure::Error> + 'static,
+    {
+        self.lifecycle.push_shutdown(f);
+        self
+    }
+
+    /// Registers the hooks which start the workers of the specified `JobQueue`
+    /// with the server, and drain the queue after the server has shut down.
+    ///
+    /// The queue itself is typically shared with the handlers by attaching
+    /// it as a state (see `Scope::with_state`). The workers are not started
+    /// again if they have already been created by `JobQueue::workers`.
+    pub fn with_jobs(self, queue: &JobQueue) -> Self {
+        let workers = queue.clone();
+        let shutdown = queue.clone();
+        self.on_start(move || {
+            if let Some(workers) = workers.workers() {
+                tokio::spawn(workers);
+            }
+            Ok::<(), failure::Error>(())
+        })
+        .on_shutdown(move || {
+            shutdown
+                .shutdown()
+                .map_err(|()| failure::err_msg("failed to drain the job queue"))
+        })
+    }
+
+    /// Registers a task run periodically according to the specified schedule
+    /// in the cron syntax (see `Schedule` for details).
+    ///
+    /// The task is run on the same runtime as the server, from when the
+    /// server starts until it shuts down. A run is skipped if the previous
+    /// one is still running, and the server waits for the running task
+    /// before completing the shutdown. The failures of the task are logged.
+    ///
+    /// ```no_run
+    /// # use finchers::prelude::*;
+    /// # use finchers::server;
+    /// # let endpoint = endpoint::unit();
+    /// server::start(endpoint)
+    ///     .bind("127.0.0.1:4000")
+    ///     .schedule("*/5 * * * *", || {
+    ///         println!("purge the expired sessions");
+    ///         Ok::<(), failure::Error>(())
+    ///     })
+    ///     .serve()
+    ///     .expect("failed to start the server");
+    /// ```
+    ///
+    /// The error of an invalid schedule is reported when the server starts.
+    pub fn schedule<F, R>(mut self, schedule: &str, task: F) -> Self
+    where
+        F: FnMut() -> R + Send + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: Send + 'static,
+        R::Error: Into<failure::Error> + 'static,
+    {
+        let schedule: Schedule = match schedule.parse() {
+            Ok(schedule) => schedule,
+            Err(err) => {
+                if self.error.is_none() {
+                    self.error = Some(ServerError::config(err));
+                }
+                return self;
+            }
+        };
+        let handle = Arc::new(schedule::Handle::default());
+        let stop = handle.clone();
+        self.lifecycle.push_start(move || {
+            tokio::spawn(handle.start(schedule, task));
+            Ok::<(), failure::Error>(())
+        });
+        self.lifecycle.push_shutdown(move || stop.stop());
+        self
+    }
+
+    /// Registers the `RuntimeMetrics` which collects the metrics of the tasks
+    /// spawned by the server.
+    ///
+    /// ```no_run
+    /// # use finchers::prelude::*;
+    /// # use finchers::server::{self, RuntimeMetrics};
+    /// # let endpoint = endpoint::unit();
+    /// let metrics = RuntimeMetrics::new();
+    ///
+    /// let mut builder = tokio::runtime::Builder::new();
+    /// metrics.instrument_workers(&mut builder);
+    /// let rt = builder.build().expect("failed to build the runtime");
+    ///
+    /// server::start(endpoint)
+    ///     .bind("127.0.0.1:4000")
+    ///     .runtime_metrics(&metrics)
+    ///     .serve_with_runtime(rt)
+    ///     .expect("failed to start the server");
+    /// ```
+    #[cfg(feature = "runtime-metrics")]
+    pub fn runtime_metrics(self, metrics: &RuntimeMetrics) -> Self {
+        Server {
+            runtime_metrics: Some(metrics.clone()),
+            ..self
+        }
+    }
+
+    /// Switches the runtime to the single-threaded one.
+    pub fn current_thread(self) -> Server<S, CurrentThread> {
+        Server {
+            make_service: self.make_service,
+            listeners: self.listeners,
+            sources: self.sources,
+            protocol: self.protocol,
+            tcp_nodelay: self.tcp_nodelay,
+            tcp_keepalive: self.tcp_keepalive,
+            tls_handshake_timeout: self.tls_handshake_timeout,
+            max_tls_handshakes: self.max_tls_handshakes,
+            h2c_max_concurrent_streams: self.h2c_max_concurrent_streams,
+            h2_max_concurrent_streams: self.h2_max_concurrent_streams,
+            strict_parsing: self.strict_parsing,
+            signal: self.signal,
+            lifecycle: self.lifecycle,
+            runtime_metrics: self.runtime_metrics,
+            error: self.error,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the signal for shutting down the server gracefully.
+    ///
+    /// The server stops accepting new connections when the signal is completed,
+    /// and then completes after all of the in-flight connections are closed
+    /// and the shutdown hooks are run.
+    pub fn with_graceful_shutdown<F>(self, signal: F) -> Self
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        Server {
+            signal: Some(Box::new(signal)),
+            ..self
+        }
+    }
+
+    /// Converts this server into a `Future` which serves the connections
+    /// on all of the bound addresses.
+    ///
+    /// The returned future can be spawned onto a runtime owned by the application.
+    /// Note that the connections are spawned onto the default executor of the
+    /// context in which the future is polled.
+    ///
+    /// The future fails if any of the startup hooks fails, and hence the error
+    /// needs to be handled before spawning it (e.g. `map_err(|e| log::error!("{}", e))`).
+    pub fn into_future(self) -> ServerResult<B::Future>
+    where
+        B: Backend<S>,
+    {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        if self.listeners.is_empty() && self.sources.is_empty() {
+            return Err(ServerError::config(failure::err_msg(
+                "the server is not bound to any address",
+            )));
+        }
+        let mut listeners = Vec::with_capacity(self.listeners.len());
+        for mut listener in self.listeners {
+            listener.set_tcp_options(self.tcp_nodelay, self.tcp_keepalive);
+            listener.set_handshake_options(self.tls_handshake_timeout, self.max_tls_handshakes);
+
+            let mut protocol = self.protocol.clone();
+            let max_concurrent_streams = if listener.is_secure() {
+                self.h2_max_concurrent_streams
+            } else {
+                self.h2c_max_concurrent_streams
+            };
+            if max_concurrent_streams.is_some() {
+                protocol.http2_max_concurrent_streams(max_concurrent_streams);
+            }
+            if listener.is_h2c() {
+                protocol.http2_only(true);
+            }
+            listeners.push((listener, protocol));
+        }
+        let mut sources = self.sources;
+        for source in &mut sources {
+            source.set_tcp_options(self.tcp_nodelay, self.tcp_keepalive);
+        }
+        let signal = self
+            .signal
+            .unwrap_or_else(|| Box::new(future::empty::<(), ()>()));
+        // Notify the endpoints that the connections are being drained.
+        let signal: Signal = match self.lifecycle.shutdown_token() {
+            Some(token) => Box::new(signal.then(move |result| {
+                token.cancel();
+                result
+            })),
+            None => signal,
+        };
+        Ok(B::serve_all(
+            listeners,
+            sources,
+            self.make_service,
+            self.strict_parsing,
+            signal,
+            self.lifecycle,
+            self.runtime_metrics,
+        ))
+    }
+
+    /// Starts the server and blocks the current thread until it completes.
+    pub fn serve(self) -> ServerResult<()>
+    where
+        B: Backend<S>,
+    {
+        let rt = B::new_runtime().map_err(ServerError::custom)?;
+        self.serve_with_runtime(rt)
+    }
+
+    /// Starts the server with the specified shutdown signal.
+    ///
+    /// This method is a shortcut of `with_graceful_shutdown(signal).serve()`.
+    pub fn serve_with_graceful_shutdown<F>(self, signal: F) -> ServerResult<()>
+    where
+        B: Backend<S>,
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        self.with_graceful_shutdown(signal).serve()
+    }
+
+    /// Starts the server on the specified runtime and blocks the current thread
+    /// until it completes.
+    ///
+    /// This method is useful for customizing the runtime, such as the number of
+    /// worker threads, the prefix of the thread names and the hooks called when
+    /// starting or stopping the worker threads:
+    ///
+    /// ```no_run
+    /// # use finchers::prelude::*;
+    /// # use finchers::server;
+    /// # let endpoint = endpoint::unit();
+    /// let rt = tokio::runtime::Builder::new()
+    ///     .core_threads(4)
+    ///     .name_prefix("my-app-worker-")
+    ///     .build()
+    ///     .expect("failed to build the runtime");
+    ///
+    /// server::start(endpoint)
+    ///     .bind("127.0.0.1:4000")
+    ///     .serve_with_runtime(rt)
+    ///     .expect("failed to start the server");
+    /// ```
+    ///
+    /// The runtime is shut down after all of the tasks spawned on it are completed.
+    pub fn serve_with_runtime(self, rt: B::Runtime) -> ServerResult<()>
+    where
+        B: Backend<S>,
+    {
+        let future = self.into_future()?;
+        B::run(rt, future)
+    }
+}
+
+fn bind_listeners(
+    addr: impl ToSocketAddrs,
+    f: impl Fn(AddrIncoming) -> Listener,
+) -> ServerResult<Vec<Listener>> {
+    let mut addrs = addr
+        .to_socket_addrs()
+        .map_err(ServerError::config)?
+        .peekable();
+    if addrs.peek().is_none() {
+        return Err(ServerError::config(failure::err_msg(
+            "the address is not resolved",
+        )));
+    }
+    let mut listeners = vec![];
+    for addr in addrs {
+        let incoming = AddrIncoming::bind(&addr).map_err(ServerError::config)?;
+        listeners.push(f(incoming));
+    }
+    Ok(listeners)
+}
+
+// ==== Backend ====
+
+#[allow(missing_debug_implementations)]
+enum Never {}
+
+/// A `Backend` indicating that the server uses the multi-threaded Tokio runtime.
+#[allow(missing_debug_implementations)]
+pub struct Threadpool(Never);
+
+/// A `Backend` indicating that the server uses the single-threaded Tokio runtime.
+#[allow(missing_debug_implementations)]
+pub struct CurrentThread(Never);
+
+/// A trait for abstracting the runtime on which the server runs.
+///
+/// This trait is sealed and cannot be implemented outside of this crate.
+pub trait Backend<S>: self::imp::Sealed {
+    /// The type of runtime which drives the server.
+    type Runtime;
+
+    /// The type of future returned from `Server::into_future`.
+    type Future: Future<Item = (), Error = ServerError>;
+
+    #[doc(hidden)]
+    fn new_runtime() -> io::Result<Self::Runtime>;
+
+    #[doc(hidden)]
+    fn serve_all(
+        listeners: Vec<(Listener, Http)>,
+        sources: Vec<Source>,
+        make_service: S,
+        strict_parsing: Option<StrictParsing>,
+        signal: Signal,
+        lifecycle: Lifecycle,
+        runtime_metrics: Option<metrics::RuntimeMetrics>,
+    ) -> Self::Future;
+
+    #[doc(hidden)]
+    fn run(rt: Self::Runtime, future: Self::Future) -> ServerResult<()>;
+}
+
+mod imp {
+    use super::*;
+
+    pub trait Sealed {}
+
+    impl Sealed for Threadpool {}
+    impl Sealed for CurrentThread {}
+
+    impl<S, Bd> Backend<S> for Threadpool
+    where
+        S: MakeServiceRef<Connection, Request<RequestBody>, Response = Response<Bd>>
+            + Send
+            + Sync
+            + 'static,
+        S::Error: Into<CritError>,
+        S::MakeError: Into<CritError>,
+        S::Future: Send + 'static,
+        S::Service: Send + 'static,
+        <S::Service as Service<Request<RequestBody>>>::Future: Send + 'static,
+        Bd: BufStream + Send + 'static,
+        Bd::Item: Send,
+        Bd::Error: Into<CritError>,
+    {
+        type Runtime = tokio::runtime::Runtime;
+        type Future = Box<dyn Future<Item = (), Error = ServerError> + Send + 'static>;
+
+        fn new_runtime() -> io::Result<Self::Runtime> {
+            tokio::runtime::Runtime::new()
+        }
+
+        fn serve_all(
+            listeners: Vec<(Listener, Http)>,
+            sources: Vec<Source>,
+            make_service: S,
+            strict_parsing: Option<StrictParsing>,
+            signal: Signal,
+            lifecycle: Lifecycle,
+            runtime_metrics: Option<metrics::RuntimeMetrics>,
+        ) -> Self::Future {
+            let make_service = LiftedMakeHttpService {
+                make_service: Arc::new(make_service),
+                strict_parsing,
+            };
+            let signal = signal.shared();
+            let executor =
+                InstrumentedExecutor::new(DefaultExecutor::current(), runtime_metrics.clone());
+
+            let serves = listeners.into_iter().map(|(listener, protocol)| {
+                let protocol = protocol.with_executor(executor.clone());
+                hyper::server::Builder::new(listener.into_incoming(), protocol)
+                    .serve(make_service.clone())
+                    .with_graceful_shutdown(signal.clone().then(|_| Ok::<_, ()>(())))
+                    .map_err(|e| log::error!("server error: {}", e))
+            });
+            let mut serves: Vec<Box<dyn Future<Item = (), Error = ()> + Send>> =
+                serves.map(|serve| Box::new(serve) as _).collect();
+            serves.extend(sources.into_iter().map(|source| {
+                Box::new(source.serve(
+                    make_service.make_service.clone(),
+                    signal.clone(),
+                    executor.clone(),
+                )) as _
+            }));
+            let serve = lifecycle.run(future::join_all(serves).map(|_| ()));
+            Box::new(Instrumented::new(serve, runtime_metrics.as_ref()))
+        }
+
+        fn run(mut rt: Self::Runtime, future: Self::Future) -> ServerResult<()> {
+            let result = rt.block_on(future);
+            rt.shutdown_on_idle()
+                .wait()
+                .map_err(|()| ServerError::custom(failure::err_msg("runtime error")))?;
+            result
+        }
+    }
+
+    impl<S, Bd> Backend<S> for CurrentThread
+    where
+        S: MakeServiceRef<Connection, Request<RequestBody>, Response = Response<Bd>> + 'static,
+        S::Error: Into<CritError>,
+        S::MakeError: Into<CritError>,
+        S::Future: 'static,
+        S::Service: 'static,
+        <S::Service as Service<Request<RequestBody>>>::Future: 'static,
+        Bd: BufStream + Send + 'static,
+        Bd::Item: Send,
+        Bd::Error: Into<CritError>,
+    {
+        type Runtime = current_thread::Runtime;
+        type Future = Box<dyn Future<Item = (), Error = ServerError> + 'static>;
+
+        fn new_runtime() -> io::Result<Self::Runtime> {
+            current_thread::Runtime::new()
+        }
+
+        fn serve_all(
+            listeners: Vec<(Listener, Http)>,
+            sources: Vec<Source>,
+            make_service: S,
+            strict_parsing: Option<StrictParsing>,
+            signal: Signal,
+            lifecycle: Lifecycle,
+            runtime_metrics: Option<metrics::RuntimeMetrics>,
+        ) -> Self::Future {
+            let make_service = LiftedMakeHttpService {
+                make_service: Arc::new(make_service),
+                strict_parsing,
+            };
+            let signal = signal.shared();
+            let executor = InstrumentedExecutor::new(
+                current_thread::TaskExecutor::current(),
+                runtime_metrics.clone(),
+            );
+
+            let serves = listeners.into_iter().map(|(listener, protocol)| {
+                let protocol = protocol.with_executor(executor.clone());
+                hyper::server::Builder::new(listener.into_incoming(), protocol)
+                    .serve(make_service.clone())
+                    .with_graceful_shutdown(signal.clone().then(|_| Ok::<_, ()>(())))
+                    .map_err(|e| log::error!("server error: {}", e))
+            });
+            let mut serves: Vec<Box<dyn Future<Item = (), Error = ()>>> =
+                serves.map(|serve| Box::new(serve) as _).collect();
+            serves.extend(sources.into_iter().map(|source| {
+                Box::new(source.serve(
+                    make_service.make_service.clone(),
+                    signal.clone(),
+                    executor.clone(),
+                )) as _
+            }));
+            let serve = lifecycle.run(future::join_all(serves).map(|_| ()));
+            Box::new(Instrumented::new(serve, runtime_metrics.as_ref()))
+        }
+
+        fn run(mut rt: Self::Runtime, future: Self::Future) -> ServerResult<()> {
+            let result = rt.block_on(future);
+            rt.run().map_err(ServerError::custom)?;
+            result
+        }
+    }
+}
+
+#[allow(missing_debug_implementations)]
+struct LiftedMakeHttpService<S> {
+    make_service: Arc<S>,
+    strict_parsing: Option<StrictParsing>,
+}
+
+impl<S> Clone for LiftedMakeHttpService<S> {
+    fn clone(&self) -> Self {
+        LiftedMakeHttpService {
+            make_service: self.make_service.clone(),
+            strict_parsing: self.strict_parsing,
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+impl<'a, S, Bd> hyper::service::MakeService<&'a Connection> for LiftedMakeHttpService<S>
+where
+    S: MakeServiceRef<Connection, Request<RequestBody>, Response = Response<Bd>>,
+    S::Error: Into<CritError>,
+    S::MakeError: Into<CritError>,
+    Bd: BufStream + Send + 'static,
+    Bd::Item: Send,
+    Bd::Error: Into<CritError>,
+{
+    type ReqBody = hyper::Body;
+    type ResBody = WrappedBodyStream<Bd>;
+    type Error = CritError;
+    type Service = LiftedHttpService<S::Service>;
+    type MakeError = S::MakeError;
+    type Future = LiftedMakeHttpServiceFuture<S::Future>;
+
+    fn make_service(&mut self, ctx: &'a Connection) -> Self::Future {
+        LiftedMakeHttpServiceFuture {
+            inner: self.make_service.make_service_ref(ctx),
+            strict_parsing: self.strict_parsing,
+            remote_addr: ctx.remote_addr(),
+            client_cert_chain: ctx.client_cert_chain().cloned(),
+            expect_continue: ctx.expect_continue().clone(),
+        }
+    }
+}
+
+#[allow(missing_debug_implementations)]
+struct LiftedMakeHttpServiceFuture<Fut> {
+    inner: Fut,
+    strict_parsing: Option<StrictParsing>,
+    remote_addr: SocketAddr,
+    client_cert_chain: Option<ClientCertChain>,
+    expect_continue: Arc<ExpectContinue>,
+}
+
+impl<Fut> Future for LiftedMakeHttpServiceFuture<Fut>
+where
+    Fut: Future,
+{
+    type Item = LiftedHttpService<Fut::Item>;
+    type Error = Fut::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let service = futures::try_ready!(self.inner.poll());
+        Ok(LiftedHttpService {
+            service,
+            strict_parsing: self.strict_parsing,
+            remote_addr: self.remote_addr,
+            client_cert_chain: self.client_cert_chain.take(),
+            expect_continue: self.expect_continue.clone(),
+        }
+        .into())
+    }
+}
+
+#[allow(missing_debug_implementations)]
+struct LiftedHttpService<S> {
+    service: S,
+    strict_parsing: Option<StrictParsing>,
+    remote_addr: SocketAddr,
+    client_cert_chain: Option<ClientCertChain>,
+    expect_continue: Arc<ExpectContinue>,
+}
+
+impl<S, Bd> hyper::service::Service for LiftedHttpService<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<Bd>>,
+    S::Error: Into<CritError>,
+    Bd: BufStream + Send + 'static,
+    Bd::Item: Send,
+    Bd::Error: Into<CritError>,
+{
+    type ReqBody = hyper::Body;
+    type ResBody = WrappedBodyStream<Bd>;
+    type Error = CritError;
+    type Future = future::Either<
+        LiftedHttpServiceFuture<S::Future>,
+        future::FutureResult<Response<WrappedBodyStream<Bd>>, CritError>,
+    >;
+
+    fn call(&mut self, mut request: Request<hyper::Body>) -> Self::Future {
+        // Hyper has written `100 Continue` into its buffer if the request has
+        // the expectation. It is held back until the request body is polled.
+        let expect_continue = request.version() == Version::HTTP_11
+            && request
+                .headers()
+                .get(header::EXPECT)
+                .map_or(false, |value| value.as_bytes() == b"100-continue");
+        if expect_continue {
+            self.expect_continue.expect();
+        }
+        if let Some(ref strict_parsing) = self.strict_parsing {
+            if !strict_parsing.check(&request, self.remote_addr) {
+                let mut response = Response::new(WrappedBodyStream(None));
+                *response.status_mut() = StatusCode::BAD_REQUEST;
+                response
+                    .headers_mut()
+                    .insert(header::CONNECTION, HeaderValue::from_static("close"));
+                return future::Either::B(future::ok(response));
+            }
+        }
+        if let Some(ref chain) = self.client_cert_chain {
+            request.extensions_mut().insert(chain.clone());
+        }
+        let expect_continue = if expect_continue {
+            Some(self.expect_continue.clone())
+        } else {
+            None
+        };
+        let request = request.map(|body| RequestBody {
+            expect_continue,
+            ..RequestBody::from_hyp(body)
+        });
+        future::Either::A(LiftedHttpServiceFuture {
+            inner: self.service.call(request),
+        })
+    }
+}
+
+#[allow(missing_debug_implementations)]
+pub(crate) struct LiftedHttpServiceFuture<Fut> {
+    pub(crate) inner: Fut,
+}
+
+impl<Fut, Bd> Future for LiftedHttpServiceFuture<Fut>
+where
+    Fut: Future<Item = Response<Bd>>,
+    Fut::Error: Into<CritError>,
+    Bd: BufStream + Send + 'static,
+    Bd::Item: Send,
+    Bd::Error: Into<CritError>,
+{
+    type Item = Response<WrappedBodyStream<Bd>>;
+    type Error = CritError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = futures::try_ready!(self.inner.poll().map_err(Into::into));
+        let dropped = response
+            .extensions()
+            .get::<Error>()
+            .map_or(false, |err| err.is::<DropConnection>());
+        if dropped {
+            // Hyper closes the connection (or resets the stream) on the error of the service.
+            return Err("the connection is dropped".into());
+        }
+        Ok(response.map(|body| WrappedBodyStream(Some(body))).into())
+    }
+}
+
+#[allow(missing_debug_implementations)]
+pub(crate) struct WrappedBodyStream<Bd>(Option<Bd>);
+
+impl<Bd> Payload for WrappedBodyStream<Bd>
+where
+    Bd: BufStream + Send + 'static,
+    Bd::Item: Send,
+    Bd::Error: Into<CritError>,
+{
+    type Data = Bd::Item;
+    type Error = Bd::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        match self.0 {
+            Some(ref mut body) => body.poll_buf(),
+            None => Ok(None.into()),
+        }
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        match self.0 {
+            Some(ref body) => body.size_hint().upper(),
+            None => Some(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            action::{OneshotAction, PreflightContext},
+            endpoint::{self, EndpointExt},
+            service::{ConnectionHooks, EndpointServiceExt},
+        },
+        futures::sync::oneshot,
+        http::Extensions,
+        std::{
+            cell::Cell,
+            io::{Read, Write},
+            net::TcpStream,
+            rc::Rc,
+            sync::{mpsc, Mutex},
+            thread,
+        },
+    };
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_start() {
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = start(endpoint::unit().map(|| "Hello")).bind("127.0.0.1:0");
+        let addr = server.local_addrs().next().unwrap();
+        let handle = thread::spawn(move || {
+            server
+                .serve_with_graceful_shutdown(rx_shutdown.map_err(|_| ()))
+                .unwrap();
+        });
+
+        let response = get(addr, "/");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Hello"));
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[cfg(feature = "runtime-metrics")]
+    #[test]
+    fn test_runtime_metrics() {
+        let metrics = RuntimeMetrics::new();
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = start(endpoint::unit().map(|| "Hello"))
+            .bind("127.0.0.1:0")
+            .runtime_metrics(&metrics);
+        let addr = server.local_addrs().next().unwrap();
+        let handle = thread::spawn(move || {
+            server
+                .serve_with_graceful_shutdown(rx_shutdown.map_err(|_| ()))
+                .unwrap();
+        });
+
+        let response = get(addr, "/");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+
+        // the server itself and the connection.
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.tasks_spawned, 2);
+        assert_eq!(snapshot.tasks_alive, 0);
+        assert!(snapshot.polls_total >= 2);
+    }
+
+    #[test]
+    fn test_lifecycle_hooks() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let push = |event: &'static str| {
+            let events = events.clone();
+            move || {
+                events.lock().unwrap().push(event);
+                Ok::<(), failure::Error>(())
+            }
+        };
+        let endpoint = endpoint::unit().map({
+            let events = events.clone();
+            move || events.lock().unwrap().join(",")
+        });
+
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = start(endpoint)
+            .bind("127.0.0.1:0")
+            .on_start(push("start1"))
+            .on_start(push("start2"))
+            .on_shutdown(push("shutdown1"))
+            .on_shutdown(push("shutdown2"));
+        let addr = server.local_addrs().next().unwrap();
+        let handle = thread::spawn(move || {
+            server
+                .serve_with_graceful_shutdown(rx_shutdown.map_err(|_| ()))
+                .unwrap();
+        });
+
+        let response = get(addr, "/");
+        assert!(response.contains("start1,start2"));
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["start1", "start2", "shutdown2", "shutdown1"]
+        );
+    }
+
+    #[test]
+    fn test_lifecycle_startup_failure() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let err = Server::new(App::new(endpoint::unit()))
+            .bind("127.0.0.1:0")
+            .on_start(|| Err::<(), _>(failure::err_msg("migration failed")))
+            .on_shutdown({
+                let events = events.clone();
+                move || {
+                    events.lock().unwrap().push("shutdown");
+                    Ok::<(), failure::Error>(())
+                }
+            })
+            .current_thread()
+            .serve()
+            .unwrap_err();
+        assert!(err.to_string().contains("migration failed"));
+        assert_eq!(*events.lock().unwrap(), vec!["shutdown"]);
+    }
+
+    #[test]
+    fn test_drop_connection() {
+        use crate::endpoint::wrapper::{self, Wrapper};
+
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let endpoint = wrapper::tarpit()
+            .delay(Duration::from_millis(10))
+            .close_connection()
+            .wrap(endpoint::unit().map(|| "Hello"));
+        let server = start(endpoint).bind("127.0.0.1:0");
+        let addr = server.local_addrs().next().unwrap();
+        let handle = thread::spawn(move || {
+            server
+                .serve_with_graceful_shutdown(rx_shutdown.map_err(|_| ()))
+                .unwrap();
+        });
+
+        assert!(get(addr, "/").starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(get(addr, "/wp-admin/"), "");
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_long_poll_on_shutdown() {
+        use crate::{broadcast::Hub, endpoints::long_poll::long_poll};
+
+        let hub = Hub::<String>::new(4);
+        let (tx_polling, rx_polling) = std::sync::mpsc::channel();
+        let app = App::new({
+            let hub = hub.clone();
+            let tx_polling = Mutex::new(tx_polling);
+            long_poll(
+                move || {
+                    tx_polling.lock().unwrap().send(()).unwrap();
+                    hub.subscribe()
+                },
+                Duration::from_secs(60),
+            )
+        });
+
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = Server::from(app).bind("127.0.0.1:0");
+        let addr = server.local_addrs().next().unwrap();
+        let handle = thread::spawn(move || {
+            server
+                .serve_with_graceful_shutdown(rx_shutdown.map_err(|_| ()))
+                .unwrap();
+        });
+
+        let request = thread::spawn(move || get(addr, "/"));
+        // start shutting down after the request has started waiting.
+        rx_polling.recv().unwrap();
+        tx_shutdown.send(()).unwrap();
+
+        let response = request.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        handle.join().unwrap();
+        drop(hub);
+    }
+
+    #[test]
+    fn test_schedule() {
+        // The runs of the task are tested with the mock clock in `schedule`,
+        // so this test only checks that the task is stopped on shutdown.
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = start(endpoint::unit())
+            .bind("127.0.0.1:0")
+            .schedule("* * * * * *", || Ok::<(), failure::Error>(()));
+        let handle = thread::spawn(move || {
+            server
+                .serve_with_graceful_shutdown(rx_shutdown.map_err(|_| ()))
+                .unwrap();
+        });
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+
+        let err = start(endpoint::unit())
+            .bind("127.0.0.1:0")
+            .schedule("* * *", || Ok::<(), failure::Error>(()))
+            .serve()
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid schedule"));
+    }
+
+    #[test]
+    fn test_strict_parsing() {
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = start(endpoint::unit().map(|| "Hello"))
+            .bind("127.0.0.1:0")
+            .strict_parsing(StrictParsing::new().log_level(None));
+        let addr = server.local_addrs().next().unwrap();
+        let handle = thread::spawn(move || {
+            server
+                .serve_with_graceful_shutdown(rx_shutdown.map_err(|_| ()))
+                .unwrap();
+        });
+
+        let response = get(addr, "/");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"POST / HTTP/1.1\r\n\
+                  Host: localhost\r\n\
+                  Content-Length: 5\r\n\
+                  Transfer-Encoding: chunked\r\n\
+                  \r\n\
+                  0\r\n\r\n",
+            )
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_disable_keep_alive() {
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = start(endpoint::unit().map(|| "Hello"))
+            .bind("127.0.0.1:0")
+            .keep_alive(false)
+            .http1_only(true);
+        let addr = server.local_addrs().next().unwrap();
+        let handle = thread::spawn(move || {
+            server
+                .serve_with_graceful_shutdown(rx_shutdown.map_err(|_| ()))
+                .unwrap();
+        });
+
+        // The connection is closed by the server even if the client
+        // does not send `Connection: close`.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Hello"));
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_h2c() {
+        use futures::Stream;
+
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = start(endpoint::unit().map(|| "Hello"))
+            .bind_h2c("127.0.0.1:0")
+            .h2c_max_concurrent_streams(Some(10));
+        let addr = server.local_addrs().next().unwrap();
+        let handle = thread::spawn(move || {
+            server
+                .serve_with_graceful_shutdown(rx_shutdown.map_err(|_| ()))
+                .unwrap();
+        });
+
+        let client = hyper::Client::builder()
+            .http2_only(true)
+            .build_http::<hyper::Body>();
+        let uri: http::Uri = format!("http://{}/", addr).parse().unwrap();
+        let mut rt = tokio::runtime::current_thread::Runtime::new().unwrap();
+        let (version, body) = rt
+            .block_on(client.get(uri).and_then(|response| {
+                let version = response.version();
+                response
+                    .into_body()
+                    .concat2()
+                    .map(move |body| (version, body))
+            }))
+            .unwrap();
+        assert_eq!(version, http::Version::HTTP_2);
+        assert_eq!(&body[..], b"Hello");
+
+        // HTTP/1 requests are not accepted.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(!response.starts_with(b"HTTP/1.1 200 OK"));
+
+        drop(client);
+        drop(rt);
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    struct AlpnHooks;
+
+    impl<'a> ConnectionHooks<&'a Connection> for AlpnHooks {
+        fn on_open(&self, conn: &&'a Connection, extensions: &mut Extensions) {
+            extensions.insert(Alpn(conn.alpn_protocol().map(|p| p.to_vec())));
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct Alpn(Option<Vec<u8>>);
+
+    struct AlpnAction;
+
+    impl OneshotAction for AlpnAction {
+        type Output = (String,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> crate::error::Result<Self::Output> {
+            let alpn = cx
+                .connection_extensions()
+                .and_then(|ext| ext.get::<Alpn>().cloned())
+                .and_then(|alpn| alpn.0)
+                .map(|p| String::from_utf8(p).unwrap());
+            Ok((format!("{:?}", alpn),))
+        }
+    }
+
+    #[test]
+    fn test_alpn_protocol() {
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let app = endpoint::endpoint(|| AlpnAction.into_action())
+            .into_service()
+            .with_connection_hooks(AlpnHooks);
+        let server = Server::new(app)
+            .bind("127.0.0.1:0")
+            .bind_tls(
+                "127.0.0.1:0",
+                super::Alpn::new(Ok::<_, io::Error>, |_: &tokio::net::TcpStream| {
+                    Some(b"http/1.1".to_vec())
+                }),
+            )
+            .with_graceful_shutdown(rx_shutdown.map_err(|_| ()));
+        let addrs: Vec<_> = server.local_addrs().collect();
+        let handle = thread::spawn(move || server.serve().unwrap());
+
+        assert!(get(addrs[0], "/").contains("None"));
+        assert!(get(addrs[1], "/").contains("Some(\"http/1.1\")"));
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_client_cert_chain() {
+        use crate::endpoints::auth::{self, ClientCertChain};
+
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = start(
+            auth::client_cert()
+                .map(|chain: ClientCertChain| format!("{:?}", chain.iter().collect::<Vec<_>>())),
+        )
+        .bind("127.0.0.1:0")
+        .bind_tls(
+            "127.0.0.1:0",
+            super::ClientAuth::new(Ok::<_, io::Error>, |_: &tokio::net::TcpStream| {
+                Some(vec![b"leaf".to_vec(), b"root".to_vec()])
+            }),
+        )
+        .with_graceful_shutdown(rx_shutdown.map_err(|_| ()));
+        let addrs: Vec<_> = server.local_addrs().collect();
+        let handle = thread::spawn(move || server.serve().unwrap());
+
+        assert!(get(addrs[0], "/").starts_with("HTTP/1.1 401"));
+        assert!(get(addrs[1], "/").contains("[[108, 101, 97, 102], [114, 111, 111, 116]]"));
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_expect_continue_per_route() {
+        use crate::{
+            endpoint::syntax,
+            endpoints::{body, expect},
+            error,
+        };
+
+        // Returns the status line of the first response, and the rest of the
+        // responses if the server has sent `100 Continue` and received the body.
+        fn upload(addr: SocketAddr, path: &str) -> (String, Option<String>) {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "POST {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                 Expect: 100-continue\r\nContent-Length: 7\r\n\r\n",
+                path
+            )
+            .unwrap();
+            let mut status = [0; 12];
+            stream.read_exact(&mut status).unwrap();
+            let status = String::from_utf8_lossy(&status).into_owned();
+            if status != "HTTP/1.1 100" {
+                // The server may reset the connection since the body is not read.
+                return (status, None);
+            }
+            stream.write_all(b"payload").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            (status, Some(response))
+        }
+
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = start(
+            syntax::verb::post()
+                .and(syntax::segment("auto"))
+                .and(body::text())
+                .map(|text: String| text)
+                .or(syntax::verb::post()
+                    .and(syntax::segment("checked"))
+                    .and(expect::continue_if(|_| Err(error::unauthorized("denied"))))
+                    .and(body::text())
+                    .map(|text: String| text))
+                .or(syntax::verb::post()
+                    .and(syntax::segment("rejected"))
+                    .and(expect::reject())
+                    .and(body::text())
+                    .map(|text: String| text)),
+        )
+        .bind("127.0.0.1:0")
+        .with_graceful_shutdown(rx_shutdown.map_err(|_| ()));
+        let addr = server.local_addrs().next().unwrap();
+        let handle = thread::spawn(move || server.serve().unwrap());
+
+        let (status, response) = upload(addr, "/auto");
+        assert_eq!(status, "HTTP/1.1 100");
+        let response = response.unwrap();
+        assert!(response.contains("HTTP/1.1 200"));
+        assert!(response.contains("payload"));
+
+        let (status, response) = upload(addr, "/checked");
+        assert_eq!(status, "HTTP/1.1 401");
+        assert!(response.is_none());
+
+        let (status, response) = upload(addr, "/rejected");
+        assert_eq!(status, "HTTP/1.1 417");
+        assert!(response.is_none());
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_max_buf_size_error() {
+        let err = start(endpoint::unit())
+            .bind("127.0.0.1:0")
+            .max_buf_size(1024)
+            .serve()
+            .unwrap_err();
+        assert!(err.to_string().starts_with("failed to build server config"));
+    }
+
+    #[test]
+    fn test_serve_with_runtime() {
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server =
+            start(endpoint::unit().map(|| thread::current().name().unwrap_or_default().to_owned()))
+                .bind("127.0.0.1:0")
+                .with_graceful_shutdown(rx_shutdown.map_err(|_| ()));
+        let addr = server.local_addrs().next().unwrap();
+        let rt = tokio::runtime::Builder::new()
+            .core_threads(1)
+            .name_prefix("custom-worker-")
+            .build()
+            .unwrap();
+        let handle = thread::spawn(move || server.serve_with_runtime(rt).unwrap());
+
+        assert!(get(addr, "/").contains("custom-worker-"));
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_start_local() {
+        let (tx_addr, rx_addr) = mpsc::channel();
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let handle = thread::spawn(move || {
+            let counter = Rc::new(Cell::new(0));
+            let server = start_local(endpoint::unit().map(move || {
+                counter.set(counter.get() + 1);
+                format!("visitor #{}", counter.get())
+            }))
+            .bind("127.0.0.1:0");
+            tx_addr.send(server.local_addrs().next().unwrap()).unwrap();
+            server
+                .serve_with_graceful_shutdown(rx_shutdown.map_err(|_| ()))
+                .unwrap();
+        });
+
+        let addr = rx_addr.recv().unwrap();
+        assert!(get(addr, "/").contains("visitor #1"));
+        assert!(get(addr, "/").contains("visitor #2"));
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct Secure(bool);
+
+    struct SecureHooks;
+
+    impl<'a> ConnectionHooks<&'a Connection> for SecureHooks {
+        fn on_open(&self, conn: &&'a Connection, extensions: &mut Extensions) {
+            extensions.insert(Secure(conn.is_secure()));
+        }
+    }
+
+    struct SecureAction;
+
+    impl OneshotAction for SecureAction {
+        type Output = (String,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> crate::error::Result<Self::Output> {
+            let secure = cx
+                .connection_extensions()
+                .and_then(|ext| ext.get::<Secure>().map(|secure| secure.0));
+            Ok((format!("{:?}", secure),))
+        }
+    }
+
+    /// The I/O object established by `tls_acceptor`.
+    struct TlsConn(openssl::ssl::SslStream<tokio::net::TcpStream>);
+
+    impl Read for TlsConn {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for TlsConn {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl tokio::io::AsyncRead for TlsConn {}
+
+    impl tokio::io::AsyncWrite for TlsConn {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            self.0.get_mut().shutdown()
+        }
+    }
+
+    /// Creates an acceptor which performs the TLS handshake by using OpenSSL
+    /// with a self-signed certificate.
+    fn tls_acceptor() -> impl Acceptor + Send + Sync + 'static {
+        use openssl::{
+            asn1::Asn1Time,
+            hash::MessageDigest,
+            pkey::PKey,
+            rsa::Rsa,
+            ssl::{HandshakeError, SslAcceptor, SslMethod},
+            x509::{X509NameBuilder, X509},
+        };
+
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        acceptor.set_certificate(&cert.build()).unwrap();
+        let acceptor = Arc::new(acceptor.build());
+
+        move |stream: tokio::net::TcpStream| {
+            let acceptor = acceptor.clone();
+            let mut stream = Some(stream);
+            let mut mid_handshake = None;
+            future::poll_fn(move || {
+                let result = match mid_handshake.take() {
+                    Some(mid) => openssl::ssl::MidHandshakeSslStream::handshake(mid),
+                    None => acceptor.accept(stream.take().unwrap()),
+                };
+                match result {
+                    Ok(stream) => Ok(TlsConn(stream).into()),
+                    Err(HandshakeError::WouldBlock(mid)) => {
+                        mid_handshake = Some(mid);
+                        Ok(futures::Async::NotReady)
+                    }
+                    Err(err) => Err(io::Error::new(io::ErrorKind::Other, err.to_string())),
+                }
+            })
+        }
+    }
+
+    fn get_tls(addr: SocketAddr, path: &str) -> String {
+        use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut stream = connector.build().connect("localhost", stream).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        )
+        .unwrap();
+        // The server closes the connection without sending close_notify.
+        let mut response = vec![];
+        let mut buf = [0; 1024];
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8(response).unwrap()
+    }
+
+    #[test]
+    fn test_multiple_listeners() {
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let app = endpoint::endpoint(|| SecureAction.into_action())
+            .into_service()
+            .with_connection_hooks(SecureHooks);
+        let server = Server::new(app)
+            .bind("127.0.0.1:0")
+            .bind_tls("127.0.0.1:0", tls_acceptor())
+            .with_graceful_shutdown(rx_shutdown.map_err(|_| ()));
+        let addrs: Vec<_> = server.local_addrs().collect();
+        assert_eq!(addrs.len(), 2);
+        let handle = thread::spawn(move || server.serve().unwrap());
+
+        assert!(get(addrs[0], "/").contains("Some(false)"));
+        assert!(get_tls(addrs[1], "/").contains("Some(true)"));
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_tls_handshake_limits() {
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = start(endpoint::unit().map(|| "Hello"))
+            .bind_tls("127.0.0.1:0", tls_acceptor())
+            .tls_handshake_timeout(Duration::from_millis(500))
+            .max_tls_handshakes(1)
+            .with_graceful_shutdown(rx_shutdown.map_err(|_| ()));
+        let addr = server.local_addrs().next().unwrap();
+        let handle = thread::spawn(move || server.serve().unwrap());
+
+        // A client which never starts the handshake occupies the only slot.
+        let mut stalled = TcpStream::connect(addr).unwrap();
+        stalled
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        let start = std::time::Instant::now();
+        assert!(get_tls(addr, "/").contains("Hello"));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        // The stalled connection has been closed by the timeout.
+        assert_eq!(stalled.read(&mut [0; 16]).unwrap(), 0);
+
+        tx_shutdown.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_bind_error() {
+        let err = start(endpoint::unit())
+            .bind("invalid address")
+            .serve()
+            .unwrap_err();
+        assert!(err.to_string().starts_with("failed to build server config"));
+    }
+}
@@ -0,0 +1,458 @@
+use {
+    super::{Acceptor, Server, ServerError, ServerResult},
+    crate::service::{App, BufferPool, BufferPoolConfig},
+    serde::Deserialize,
+    std::{
+        net::SocketAddr,
+        path::{Path, PathBuf},
+        str::FromStr,
+        time::Duration,
+    },
+};
+
+/// The configuration of `Server`, which can be loaded from a configuration
+/// file with any Serde-compatible format.
+///
+/// ```ignore
+/// // server.toml:
+/// //
+/// // listen = ["0.0.0.0:80"]
+/// // request_timeout = 30
+/// // log_level = "info"
+/// //
+/// // [buffer_pool]
+/// // size_classes = [4096, 65536]
+/// // max_buffers = 128
+/// //
+/// // [tls]
+/// // listen = ["0.0.0.0:443"]
+/// // cert = "/etc/finchers/cert.pem"
+/// // key = "/etc/finchers/key.pem"
+/// // max_concurrent_streams = 100
+/// //
+/// // [h2c]
+/// // listen = ["127.0.0.1:8080"]
+/// // max_concurrent_streams = 1000
+///
+/// let config: Config = toml::from_str(&fs::read_to_string("server.toml")?)?;
+/// let config = config.with_env_overrides()?;
+///
+/// if let Some(level) = config.log_level_filter()? {
+///     log::set_max_level(level);
+/// }
+///
+/// let mut server = Server::from_config(&config, endpoint);
+/// if let Some(ref tls) = config.tls {
+///     server = server.bind_tls_config(tls, |cert, key| load_tls_acceptor(cert, key));
+/// }
+/// server.serve()?;
+/// ```
+///
+/// All of the fields are optional.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The addresses to listen on in plaintext.
+    pub listen: Vec<SocketAddr>,
+
+    /// The configuration of TLS listeners.
+    ///
+    /// Since Finchers does not provide any TLS implementation, the listeners
+    /// are not bound by `Server::from_config`. They are bound by
+    /// `Server::bind_tls_config` with the acceptor built from these paths.
+    pub tls: Option<TlsConfig>,
+
+    /// The maximum duration for processing each request, in seconds.
+    pub request_timeout: Option<u64>,
+
+    /// The duration of TCP keepalive, in seconds.
+    pub tcp_keepalive: Option<u64>,
+
+    /// Whether to disable Nagle's algorithm.
+    pub tcp_nodelay: bool,
+
+    /// Whether to enable HTTP keep-alive.
+    pub keep_alive: bool,
+
+    /// The maximum size of the buffer used for reading the HTTP/1 messages,
+    /// in bytes.
+    pub max_buf_size: Option<usize>,
+
+    /// The maximum level of logging, e.g. `"info"` or `"debug"`.
+    ///
+    /// Finchers does not install any logger, and hence this value is not
+    /// applied by `Server::from_config`. The application passes the value
+    /// returned from `Config::log_level_filter` to its own logger.
+    pub log_level: Option<String>,
+
+    /// The configuration of the buffer pool used for receiving the request bodies.
+    ///
+    /// If omitted, the buffers are allocated for each request.
+    pub buffer_pool: Option<BufferPoolConfig>,
+
+    /// The configuration of the listeners serving HTTP/2 with prior knowledge.
+    pub h2c: Option<H2cConfig>,
+}
+
+/// The configuration of the listeners serving HTTP/2 with prior knowledge (h2c).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct H2cConfig {
+    /// The addresses to listen on, which are bound by `Server::bind_h2c`.
+    pub listen: Vec<SocketAddr>,
+
+    /// The maximum number of concurrent streams on each HTTP/2 connection
+    /// over the plaintext listeners.
+    ///
+    /// This limit is also applied to the connections accepted on `listen`
+    /// of `Config` which have been switched to HTTP/2.
+    pub max_concurrent_streams: Option<u32>,
+}
+
+/// The configuration of TLS listeners.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// The addresses to listen on with TLS.
+    #[serde(default)]
+    pub listen: Vec<SocketAddr>,
+
+    /// The path of the PEM-encoded certificate chain.
+    pub cert: PathBuf,
+
+    /// The path of the PEM-encoded private key.
+    pub key: PathBuf,
+
+    /// The maximum number of concurrent streams on each HTTP/2 connection
+    /// negotiated by ALPN.
+    #[serde(default)]
+    pub max_concurrent_streams: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec![],
+            tls: None,
+            request_timeout: None,
+            tcp_keepalive: None,
+            tcp_nodelay: false,
+            keep_alive: true,
+            max_buf_size: None,
+            log_level: None,
+            buffer_pool: None,
+            h2c: None,
+        }
+    }
+}
+
+impl Config {
+    /// Parses `log_level` into the filter of the `log` crate.
+    pub fn log_level_filter(&self) -> ServerResult<Option<log::LevelFilter>> {
+        match self.log_level {
+            Some(ref level) => parse("log_level", level).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Overrides the configuration with the environment variables.
+    ///
+    /// The following variables are recognized:
+    ///
+    /// * `FINCHERS_LISTEN` - the comma-separated list of `listen`
+    /// * `FINCHERS_TLS_LISTEN`, `FINCHERS_TLS_CERT`, `FINCHERS_TLS_KEY` -
+    ///   the fields of `tls`. If `tls` is not configured, both the certificate
+    ///   and the private key must be specified.
+    /// * `FINCHERS_REQUEST_TIMEOUT` - `request_timeout`
+    /// * `FINCHERS_KEEP_ALIVE` - `keep_alive`
+    /// * `FINCHERS_LOG` - `log_level`
+    pub fn with_env_overrides(self) -> ServerResult<Self> {
+        self.with_overrides(|name| std::env::var(name).ok())
+    }
+
+    fn with_overrides(mut self, var: impl Fn(&str) -> Option<String>) -> ServerResult<Self> {
+        if let Some(listen) = var("FINCHERS_LISTEN") {
+            self.listen = parse_addrs("FINCHERS_LISTEN", &listen)?;
+        }
+
+        let tls_listen = match var("FINCHERS_TLS_LISTEN") {
+            Some(listen) => Some(parse_addrs("FINCHERS_TLS_LISTEN", &listen)?),
+            None => None,
+        };
+        let tls_cert = var("FINCHERS_TLS_CERT").map(PathBuf::from);
+        let tls_key = var("FINCHERS_TLS_KEY").map(PathBuf::from);
+        self.tls = match (self.tls, tls_cert, tls_key) {
+            (Some(tls), cert, key) => Some(TlsConfig {
+                listen: tls_listen.unwrap_or(tls.listen),
+                cert: cert.unwrap_or(tls.cert),
+                key: key.unwrap_or(tls.key),
+                ..tls
+            }),
+            (None, Some(cert), Some(key)) => Some(TlsConfig {
+                listen: tls_listen.unwrap_or_default(),
+                cert,
+                key,
+                max_concurrent_streams: None,
+            }),
+            (None, None, None) if tls_listen.is_none() => None,
+            (None, ..) => {
+                return Err(ServerError::config(failure::err_msg(
+                    "both FINCHERS_TLS_CERT and FINCHERS_TLS_KEY must be specified",
+                )));
+            }
+        };
+
+        if let Some(timeout) = var("FINCHERS_REQUEST_TIMEOUT") {
+            self.request_timeout = Some(parse("FINCHERS_REQUEST_TIMEOUT", &timeout)?);
+        }
+        if let Some(keep_alive) = var("FINCHERS_KEEP_ALIVE") {
+            self.keep_alive = parse("FINCHERS_KEEP_ALIVE", &keep_alive)?;
+        }
+        if let Some(level) = var("FINCHERS_LOG") {
+            self.log_level = Some(level);
+        }
+
+        Ok(self)
+    }
+}
+
+fn parse<T>(name: &str, value: &str) -> ServerResult<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| ServerError::config(failure::format_err!("invalid value of {}: {}", name, e)))
+}
+
+fn parse_addrs(name: &str, value: &str) -> ServerResult<Vec<SocketAddr>> {
+    value
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|addr| parse(name, addr))
+        .collect()
+}
+
+impl<E> Server<App<E>> {
+    /// Create a `Server` which serves the specified endpoint with the configuration.
+    ///
+    /// The server is bound to the plaintext addresses in `config.listen`.
+    /// The TLS listeners need to be registered by `bind_tls_config` separately,
+    /// and the log level is left to the logger of the application.
+    ///
+    /// As with `bind`, the errors in the configuration are reported when
+    /// the server starts.
+    pub fn from_config(config: &Config, endpoint: E) -> Self {
+        let mut app = App::new(endpoint);
+        if let Some(timeout) = config.request_timeout {
+            app = app.with_request_timeout(Duration::from_secs(timeout));
+        }
+        if let Some(ref pool) = config.buffer_pool {
+            app = app.with_buffer_pool(BufferPool::new(pool));
+        }
+
+        let mut server = Server::from(app)
+            .tcp_nodelay(config.tcp_nodelay)
+            .tcp_keepalive(config.tcp_keepalive.map(Duration::from_secs))
+            .keep_alive(config.keep_alive)
+            .h2c_max_concurrent_streams(
+                config
+                    .h2c
+                    .as_ref()
+                    .and_then(|h2c| h2c.max_concurrent_streams),
+            )
+            .h2_max_concurrent_streams(
+                config
+                    .tls
+                    .as_ref()
+                    .and_then(|tls| tls.max_concurrent_streams),
+            );
+
+        if let Err(err) = server.apply_config(config) {
+            server.error = Some(err);
+            return server;
+        }
+
+        if !config.listen.is_empty() {
+            server = server.bind(&config.listen[..]);
+        }
+        if let Some(ref h2c) = config.h2c {
+            if !h2c.listen.is_empty() {
+                server = server.bind_h2c(&h2c.listen[..]);
+            }
+        }
+        server
+    }
+
+    fn apply_config(&mut self, config: &Config) -> ServerResult<()> {
+        if let Some(max_buf_size) = config.max_buf_size {
+            self.set_max_buf_size(max_buf_size)?;
+        }
+        config.log_level_filter()?;
+        Ok(())
+    }
+}
+
+impl<S, B> Server<S, B> {
+    /// Binds the server to the TLS listeners in the configuration.
+    ///
+    /// The acceptor is built by `load_acceptor` from the paths of the
+    /// certificate chain and the private key, and is shared by all of
+    /// the addresses in `config.listen`. The errors occurred while loading
+    /// the acceptor or binding are reported when the server starts.
+    pub fn bind_tls_config<A, F, R>(mut self, config: &TlsConfig, load_acceptor: F) -> Self
+    where
+        F: FnOnce(&Path, &Path) -> Result<A, R>,
+        R: Into<failure::Error>,
+        A: Acceptor + Send + Sync + 'static,
+    {
+        if self.error.is_some() || config.listen.is_empty() {
+            return self;
+        }
+        match load_acceptor(&config.cert, &config.key) {
+            Ok(acceptor) => self.bind_tls(&config.listen[..], acceptor),
+            Err(err) => {
+                self.error = Some(ServerError::config(err));
+                self
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::super::RequestBody,
+        super::*,
+        std::{collections::HashMap, io},
+        tokio::net::TcpStream,
+    };
+
+    fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        let vars: HashMap<_, _> = vars.iter().cloned().collect();
+        move |name| vars.get(name).map(|s| s.to_string())
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "listen": ["127.0.0.1:8080"],
+                "tls": { "cert": "cert.pem", "key": "key.pem" },
+                "request_timeout": 30,
+                "buffer_pool": { "max_buffers": 16 }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.listen, vec!["127.0.0.1:8080".parse().unwrap()]);
+        assert_eq!(config.tls.unwrap().cert, PathBuf::from("cert.pem"));
+        assert_eq!(config.request_timeout, Some(30));
+        let buffer_pool = config.buffer_pool.unwrap();
+        assert_eq!(buffer_pool.max_buffers, 16);
+        assert_eq!(
+            buffer_pool.size_classes,
+            BufferPoolConfig::default().size_classes
+        );
+        assert!(config.keep_alive);
+
+        assert!(serde_json::from_str::<Config>(r#"{ "lisen": [] }"#).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_h2c() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "tls": { "cert": "cert.pem", "key": "key.pem", "max_concurrent_streams": 100 },
+                "h2c": { "listen": ["127.0.0.1:8080"], "max_concurrent_streams": 1000 }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.tls.unwrap().max_concurrent_streams, Some(100));
+        let h2c = config.h2c.unwrap();
+        assert_eq!(h2c.listen, vec!["127.0.0.1:8080".parse().unwrap()]);
+        assert_eq!(h2c.max_concurrent_streams, Some(1000));
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let config = Config::default()
+            .with_overrides(env(&[
+                ("FINCHERS_LISTEN", "127.0.0.1:80, [::1]:80"),
+                ("FINCHERS_TLS_CERT", "cert.pem"),
+                ("FINCHERS_TLS_KEY", "key.pem"),
+                ("FINCHERS_KEEP_ALIVE", "false"),
+            ]))
+            .unwrap();
+        assert_eq!(config.listen.len(), 2);
+        assert_eq!(config.tls.unwrap().key, PathBuf::from("key.pem"));
+        assert!(!config.keep_alive);
+
+        assert!(Config::default()
+            .with_overrides(env(&[("FINCHERS_TLS_CERT", "cert.pem")]))
+            .is_err());
+        assert!(Config::default()
+            .with_overrides(env(&[("FINCHERS_REQUEST_TIMEOUT", "soon")]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = Config {
+            listen: vec!["127.0.0.1:0".parse().unwrap()],
+            ..Config::default()
+        };
+        let server = Server::from_config(&config, crate::endpoint::unit::<RequestBody>());
+        assert_eq!(server.local_addrs().count(), 1);
+        assert!(server.error.is_none());
+
+        let config = Config {
+            max_buf_size: Some(1024),
+            ..config
+        };
+        let server = Server::from_config(&config, crate::endpoint::unit::<RequestBody>());
+        assert!(server.error.is_some());
+
+        let config = Config {
+            max_buf_size: None,
+            log_level: Some("verbose".into()),
+            ..config
+        };
+        assert!(config.log_level_filter().is_err());
+        let server = Server::from_config(&config, crate::endpoint::unit::<RequestBody>());
+        assert!(server.error.is_some());
+    }
+
+    #[test]
+    fn test_bind_tls_config() {
+        type PlainAcceptor = fn(TcpStream) -> io::Result<TcpStream>;
+        fn plain(stream: TcpStream) -> io::Result<TcpStream> {
+            Ok(stream)
+        }
+
+        let config = TlsConfig {
+            listen: vec!["127.0.0.1:0".parse().unwrap()],
+            cert: PathBuf::from("cert.pem"),
+            key: PathBuf::from("key.pem"),
+            max_concurrent_streams: None,
+        };
+
+        let server =
+            Server::from_config(&Config::default(), crate::endpoint::unit::<RequestBody>())
+                .bind_tls_config(&config, |cert, key| {
+                    assert_eq!(cert, Path::new("cert.pem"));
+                    assert_eq!(key, Path::new("key.pem"));
+                    Ok::<PlainAcceptor, failure::Error>(plain)
+                });
+        assert!(server.error.is_none());
+        assert_eq!(server.local_addrs().count(), 1);
+
+        let server =
+            Server::from_config(&Config::default(), crate::endpoint::unit::<RequestBody>())
+                .bind_tls_config(&config, |_, _| {
+                    Err::<PlainAcceptor, _>(failure::err_msg("no such file"))
+                });
+        assert!(server.error.is_some());
+    }
+}
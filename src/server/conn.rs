@@ -0,0 +1,456 @@
+use {
+    super::expect::{ContinueIo, ExpectContinue},
+    crate::endpoints::auth::ClientCertChain,
+    futures::{stream::FuturesUnordered, Async, Future, IntoFuture, Poll, Stream},
+    hyper::server::conn::AddrIncoming,
+    std::{
+        cmp, fmt,
+        io::{self, Read, Write},
+        net::SocketAddr,
+        sync::Arc,
+        time::Duration,
+    },
+    tokio::{
+        io::{AsyncRead, AsyncWrite},
+        net::TcpStream,
+        timer::Timeout,
+    },
+};
+
+/// The default duration of time allowed to complete a handshake, in seconds.
+pub(super) const DEFAULT_HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// The default maximum number of handshakes performed concurrently on a listener.
+pub(super) const DEFAULT_MAX_HANDSHAKES: usize = 128;
+
+/// A trait representing the handshake process performed on the newly accepted
+/// TCP streams, such as the TLS handshake.
+///
+/// Finchers does not provide any TLS implementation by itself. The acceptors
+/// provided by the TLS libraries can be used by wrapping them with a closure,
+/// since this trait is implemented for the functions that returns a future
+/// of an I/O object:
+///
+/// ```no_run
+/// # use finchers::prelude::*;
+/// # use finchers::server;
+/// # use tokio::net::TcpStream;
+/// # // a stand-in for the acceptor of a TLS library
+/// # struct TlsAcceptor;
+/// # impl TlsAcceptor {
+/// #     fn accept(&self, stream: TcpStream) -> Result<TcpStream, std::io::Error> { Ok(stream) }
+/// # }
+/// # let acceptor = TlsAcceptor;
+/// # let endpoint = finchers::endpoint::unit().map(|| "Hello");
+/// server::start(endpoint)
+///     .bind("0.0.0.0:80")
+///     .bind_tls("0.0.0.0:443", move |stream: TcpStream| acceptor.accept(stream))
+///     .serve()
+///     .expect("failed to start the server");
+/// ```
+pub trait Acceptor {
+    /// The type of I/O object established by the handshake.
+    type Conn: AsyncRead + AsyncWrite + Send + 'static;
+
+    /// The type of future which performs the handshake.
+    type Accept: Future<Item = Self::Conn, Error = io::Error> + Send + 'static;
+
+    /// Starts the handshake on the specified TCP stream.
+    fn accept(&self, stream: TcpStream) -> Self::Accept;
+
+    /// Returns the application protocol negotiated by ALPN during the handshake.
+    ///
+    /// The returned value is exposed by `Connection::alpn_protocol`.
+    /// The default implementation returns `None`.
+    fn negotiated_protocol(&self, conn: &Self::Conn) -> Option<Vec<u8>> {
+        let _ = conn;
+        None
+    }
+
+    /// Returns the DER-encoded certificate chain presented by the client and
+    /// verified during the handshake.
+    ///
+    /// The returned chain is stored into the extensions of each request
+    /// received on the connection (see `endpoints::auth::client_cert`).
+    /// The default implementation returns `None`.
+    fn peer_certificates(&self, conn: &Self::Conn) -> Option<Vec<Vec<u8>>> {
+        let _ = conn;
+        None
+    }
+}
+
+impl<F, R> Acceptor for F
+where
+    F: Fn(TcpStream) -> R,
+    R: IntoFuture<Error = io::Error>,
+    R::Item: AsyncRead + AsyncWrite + Send + 'static,
+    R::Future: Send + 'static,
+{
+    type Conn = R::Item;
+    type Accept = R::Future;
+
+    fn accept(&self, stream: TcpStream) -> Self::Accept {
+        (*self)(stream).into_future()
+    }
+}
+
+/// An `Acceptor` which reports the application protocol negotiated by ALPN.
+///
+/// The protocol is retrieved from the established I/O object by using the
+/// specified function, since the way to obtain it depends on the TLS library:
+///
+/// ```ignore
+/// tls_config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+/// let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(tls_config));
+/// server::start(endpoint)
+///     .bind_tls(
+///         "0.0.0.0:443",
+///         Alpn::new(
+///             move |stream| acceptor.accept(stream),
+///             |conn: &TlsStream<_, _>| conn.get_ref().1.get_alpn_protocol().map(|p| p.to_vec()),
+///         ),
+///     )
+///     .serve()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Alpn<A, F> {
+    acceptor: A,
+    f: F,
+}
+
+impl<A, F> Alpn<A, F>
+where
+    A: Acceptor,
+    F: Fn(&A::Conn) -> Option<Vec<u8>>,
+{
+    /// Creates an `Alpn` from the specified acceptor and the function
+    /// which retrieves the negotiated protocol.
+    pub fn new(acceptor: A, f: F) -> Self {
+        Alpn { acceptor, f }
+    }
+}
+
+impl<A, F> Acceptor for Alpn<A, F>
+where
+    A: Acceptor,
+    F: Fn(&A::Conn) -> Option<Vec<u8>>,
+{
+    type Conn = A::Conn;
+    type Accept = A::Accept;
+
+    fn accept(&self, stream: TcpStream) -> Self::Accept {
+        self.acceptor.accept(stream)
+    }
+
+    fn negotiated_protocol(&self, conn: &Self::Conn) -> Option<Vec<u8>> {
+        (self.f)(conn)
+    }
+
+    fn peer_certificates(&self, conn: &Self::Conn) -> Option<Vec<Vec<u8>>> {
+        self.acceptor.peer_certificates(conn)
+    }
+}
+
+/// An `Acceptor` which reports the certificate chain of the client verified
+/// during the handshake, for the listeners requiring the client authentication.
+///
+/// The chain is retrieved from the established I/O object by using the
+/// specified function, since the way to obtain it depends on the TLS library.
+/// The endpoints can extract it with `endpoints::auth::client_cert`:
+///
+/// ```no_run
+/// # use finchers::prelude::*;
+/// # use finchers::endpoints::auth::{self, ClientCertChain};
+/// # use finchers::server::{self, ClientAuth};
+/// # use tokio::net::TcpStream;
+/// # // stand-ins for the acceptor and the connection of a TLS library
+/// # let tls_acceptor = |stream: TcpStream| Ok::<_, std::io::Error>(stream);
+/// # fn peer_cert_chain(_: &TcpStream) -> Option<Vec<Vec<u8>>> { None }
+/// let endpoint = auth::client_cert()
+///     .map(|chain: ClientCertChain| format!("{} certificate(s)", chain.len()));
+///
+/// server::start(endpoint)
+///     .bind_tls("0.0.0.0:443", ClientAuth::new(tls_acceptor, peer_cert_chain))
+///     .serve()
+///     .expect("failed to start the server");
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientAuth<A, F> {
+    acceptor: A,
+    f: F,
+}
+
+impl<A, F> ClientAuth<A, F>
+where
+    A: Acceptor,
+    F: Fn(&A::Conn) -> Option<Vec<Vec<u8>>>,
+{
+    /// Creates a `ClientAuth` from the specified acceptor and the function
+    /// which retrieves the DER-encoded certificate chain of the client.
+    pub fn new(acceptor: A, f: F) -> Self {
+        ClientAuth { acceptor, f }
+    }
+}
+
+impl<A, F> Acceptor for ClientAuth<A, F>
+where
+    A: Acceptor,
+    F: Fn(&A::Conn) -> Option<Vec<Vec<u8>>>,
+{
+    type Conn = A::Conn;
+    type Accept = A::Accept;
+
+    fn accept(&self, stream: TcpStream) -> Self::Accept {
+        self.acceptor.accept(stream)
+    }
+
+    fn negotiated_protocol(&self, conn: &Self::Conn) -> Option<Vec<u8>> {
+        self.acceptor.negotiated_protocol(conn)
+    }
+
+    fn peer_certificates(&self, conn: &Self::Conn) -> Option<Vec<Vec<u8>>> {
+        (self.f)(conn)
+    }
+}
+
+trait Io: AsyncRead + AsyncWrite + Send + 'static {}
+
+impl<T> Io for T where T: AsyncRead + AsyncWrite + Send + 'static {}
+
+/// A connection accepted by the server.
+///
+/// The reference to this value is passed to `MakeService::make_service` as
+/// the context value, and can be inspected by the connection hooks.
+pub struct Connection {
+    io: ContinueIo<Box<dyn Io>>,
+    remote_addr: SocketAddr,
+    secure: bool,
+    alpn_protocol: Option<Vec<u8>>,
+    client_cert_chain: Option<ClientCertChain>,
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("remote_addr", &self.remote_addr)
+            .field("secure", &self.secure)
+            .field("alpn_protocol", &self.alpn_protocol)
+            .field("client_cert_chain", &self.client_cert_chain)
+            .finish()
+    }
+}
+
+impl Connection {
+    /// Returns the address of the remote peer.
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    /// Returns whether the connection has been accepted on a listener
+    /// registered by `Server::bind_tls`.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// Returns the application protocol negotiated by ALPN, e.g. `b"h2"`.
+    ///
+    /// This value is available only if the acceptor reports it (see `Alpn`).
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_ref().map(|p| &p[..])
+    }
+
+    /// Returns the certificate chain of the client verified during the handshake.
+    ///
+    /// This value is available only if the acceptor reports it (see `ClientAuth`).
+    pub fn client_cert_chain(&self) -> Option<&ClientCertChain> {
+        self.client_cert_chain.as_ref()
+    }
+
+    pub(crate) fn expect_continue(&self) -> &Arc<ExpectContinue> {
+        self.io.shared()
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl AsyncRead for Connection {}
+
+impl AsyncWrite for Connection {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+type Accept = Box<dyn Future<Item = Connection, Error = io::Error> + Send + 'static>;
+type AcceptFn = Arc<dyn Fn(TcpStream, SocketAddr) -> Accept + Send + Sync + 'static>;
+
+/// A listener bound to an address.
+pub struct Listener {
+    incoming: AddrIncoming,
+    acceptor: Option<AcceptFn>,
+    h2c: bool,
+    handshake_timeout: Duration,
+    max_handshakes: usize,
+}
+
+impl fmt::Debug for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Listener")
+            .field("incoming", &self.incoming)
+            .field("secure", &self.acceptor.is_some())
+            .field("h2c", &self.h2c)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("max_handshakes", &self.max_handshakes)
+            .finish()
+    }
+}
+
+impl Listener {
+    pub(super) fn plain(incoming: AddrIncoming) -> Self {
+        Listener {
+            incoming,
+            acceptor: None,
+            h2c: false,
+            handshake_timeout: Duration::from_secs(DEFAULT_HANDSHAKE_TIMEOUT_SECS),
+            max_handshakes: DEFAULT_MAX_HANDSHAKES,
+        }
+    }
+
+    pub(super) fn h2c(incoming: AddrIncoming) -> Self {
+        Listener {
+            h2c: true,
+            ..Listener::plain(incoming)
+        }
+    }
+
+    pub(super) fn with_acceptor<A>(incoming: AddrIncoming, acceptor: Arc<A>) -> Self
+    where
+        A: Acceptor + Send + Sync + 'static,
+    {
+        Listener {
+            acceptor: Some(Arc::new(move |stream, remote_addr| {
+                let acceptor = acceptor.clone();
+                Box::new(acceptor.accept(stream).map(move |io| Connection {
+                    alpn_protocol: acceptor.negotiated_protocol(&io),
+                    client_cert_chain: acceptor.peer_certificates(&io).map(ClientCertChain::new),
+                    io: ContinueIo::new(Box::new(io)),
+                    remote_addr,
+                    secure: true,
+                })) as Accept
+            })),
+            ..Listener::plain(incoming)
+        }
+    }
+
+    pub(super) fn is_secure(&self) -> bool {
+        self.acceptor.is_some()
+    }
+
+    pub(super) fn is_h2c(&self) -> bool {
+        self.h2c
+    }
+
+    pub(super) fn set_tcp_options(&mut self, nodelay: bool, keepalive: Option<Duration>) {
+        self.incoming.set_nodelay(nodelay);
+        self.incoming.set_keepalive(keepalive);
+    }
+
+    pub(super) fn set_handshake_options(&mut self, timeout: Duration, max: usize) {
+        self.handshake_timeout = timeout;
+        self.max_handshakes = cmp::max(max, 1);
+    }
+
+    pub(super) fn local_addr(&self) -> SocketAddr {
+        self.incoming.local_addr()
+    }
+
+    pub(super) fn into_incoming(self) -> Incoming {
+        Incoming {
+            listener: self,
+            pending: FuturesUnordered::new(),
+        }
+    }
+}
+
+/// The stream of connections accepted by a `Listener`.
+///
+/// The handshakes are performed concurrently, and the connections whose
+/// handshake has failed or timed out are discarded. While the number of
+/// in-flight handshakes reaches the limit, the new connections are left
+/// in the backlog of the listener.
+#[allow(missing_debug_implementations)]
+pub struct Incoming {
+    listener: Listener,
+    pending: FuturesUnordered<Accept>,
+}
+
+impl Stream for Incoming {
+    type Item = Connection;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            // Complete the handshakes first, in order to release their slots.
+            match self.pending.poll() {
+                Ok(Async::Ready(Some(conn))) => return Ok(Async::Ready(Some(conn))),
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => {}
+                Err(err) => {
+                    log::debug!("failed to complete the handshake: {}", err);
+                    continue;
+                }
+            }
+
+            if self.pending.len() >= self.listener.max_handshakes {
+                return Ok(Async::NotReady);
+            }
+
+            match self.listener.incoming.poll()? {
+                Async::Ready(Some(stream)) => {
+                    let remote_addr = stream.remote_addr();
+                    let stream = stream.into_inner();
+                    match self.listener.acceptor {
+                        Some(ref acceptor) => {
+                            let accept = Timeout::new(
+                                acceptor(stream, remote_addr),
+                                self.listener.handshake_timeout,
+                            )
+                            .map_err(|err| match err.into_inner() {
+                                Some(err) => err,
+                                None => io::Error::new(
+                                    io::ErrorKind::TimedOut,
+                                    "the handshake has timed out",
+                                ),
+                            });
+                            self.pending.push(Box::new(accept));
+                        }
+                        None => {
+                            return Ok(Async::Ready(Some(Connection {
+                                io: ContinueIo::new(Box::new(stream)),
+                                remote_addr,
+                                secure: false,
+                                alpn_protocol: None,
+                                client_cert_chain: None,
+                            })));
+                        }
+                    }
+                }
+                Async::Ready(None) if self.pending.is_empty() => return Ok(Async::Ready(None)),
+                Async::Ready(None) | Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
@@ -11,7 +11,7 @@ enum ServerErrorKind {
     Custom(Error),
 }
 
-/// The error type which will be returned from `ServiceBuilder::serve()`.
+/// The error type which will be returned from `Server::serve()`.
 #[derive(Debug)]
 pub struct ServerError {
     kind: ServerErrorKind,
@@ -42,8 +42,4 @@ impl fmt::Display for ServerError {
     }
 }
 
-impl error::Error for ServerError {
-    fn description(&self) -> &str {
-        "failed to start the server"
-    }
-}
+impl error::Error for ServerError {}
@@ -0,0 +1,226 @@
+//! Deferral of the interim response `100 Continue`.
+//!
+//! Hyper writes `100 Continue` as soon as it has parsed a request head with
+//! `Expect: 100-continue`, before the service is able to decide whether to
+//! receive the message body. The I/O wrapper in this module holds the interim
+//! response back until the request body is polled for the first time, and
+//! discards it if the final response is written before that, so that the
+//! endpoints can reject the request before the client uploads the body.
+
+use {
+    futures::{task::AtomicTask, Poll},
+    std::{
+        io::{self, Read, Write},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    },
+    tokio::io::{AsyncRead, AsyncWrite},
+};
+
+/// The interim response written by Hyper.
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+const IDLE: usize = 0;
+const EXPECTED: usize = 1;
+const HELD: usize = 2;
+const RELEASED: usize = 3;
+
+/// The state shared between a connection and the body of its in-flight request.
+#[derive(Debug)]
+pub(crate) struct ExpectContinue {
+    state: AtomicUsize,
+    task: AtomicTask,
+}
+
+impl ExpectContinue {
+    fn new() -> Self {
+        ExpectContinue {
+            state: AtomicUsize::new(IDLE),
+            task: AtomicTask::new(),
+        }
+    }
+
+    /// Marks that a request with `Expect: 100-continue` has been dispatched,
+    /// so the next write may start with the interim response.
+    pub(crate) fn expect(&self) {
+        self.state.store(EXPECTED, Ordering::SeqCst);
+    }
+
+    /// Marks that the request body has been polled for the first time.
+    pub(crate) fn release(&self) {
+        if self.transition(EXPECTED, IDLE) {
+            // The interim response has not been held yet, and will be
+            // written by Hyper as usual.
+            return;
+        }
+        if self.transition(HELD, RELEASED) {
+            self.task.notify();
+        }
+    }
+
+    fn transition(&self, current: usize, new: usize) -> bool {
+        self.state
+            .compare_exchange(current, new, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+}
+
+/// An I/O object which holds back the interim response written by Hyper
+/// until the request body is polled.
+#[derive(Debug)]
+pub(crate) struct ContinueIo<T> {
+    io: T,
+    shared: Arc<ExpectContinue>,
+    written: usize,
+}
+
+impl<T> ContinueIo<T> {
+    pub(crate) fn new(io: T) -> Self {
+        ContinueIo {
+            io,
+            shared: Arc::new(ExpectContinue::new()),
+            written: 0,
+        }
+    }
+
+    pub(crate) fn shared(&self) -> &Arc<ExpectContinue> {
+        &self.shared
+    }
+}
+
+impl<T: Write> ContinueIo<T> {
+    /// Writes the held interim response if the request body has been polled,
+    /// or returns `WouldBlock` if it is still held.
+    fn write_continue(&mut self) -> io::Result<()> {
+        loop {
+            match self.shared.state.load(Ordering::SeqCst) {
+                HELD => {
+                    self.shared.task.register();
+                    if self.shared.state.load(Ordering::SeqCst) == HELD {
+                        return Err(io::ErrorKind::WouldBlock.into());
+                    }
+                }
+                RELEASED => {
+                    while self.written < CONTINUE.len() {
+                        match self.io.write(&CONTINUE[self.written..])? {
+                            0 => return Err(io::ErrorKind::WriteZero.into()),
+                            n => self.written += n,
+                        }
+                    }
+                    self.io.flush()?;
+                    self.written = 0;
+                    self.shared.state.store(IDLE, Ordering::SeqCst);
+                    return Ok(());
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+impl<T: Read + Write> Read for ContinueIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // The client does not send the body until it receives the interim response.
+        self.write_continue()?;
+        self.io.read(buf)
+    }
+}
+
+impl<T: Write> Write for ContinueIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.shared.state.load(Ordering::SeqCst) {
+            EXPECTED => {
+                if buf.starts_with(CONTINUE) && self.shared.transition(EXPECTED, HELD) {
+                    return Ok(CONTINUE.len());
+                }
+                self.shared.transition(EXPECTED, IDLE);
+            }
+            HELD | RELEASED => {
+                // If the body has not been polled yet, the final response is
+                // written before that and the interim response is no longer needed.
+                self.shared.transition(HELD, IDLE);
+                self.write_continue()?;
+            }
+            _ => {}
+        }
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead + Write> AsyncRead for ContinueIo<T> {}
+
+impl<T: AsyncWrite> AsyncWrite for ContinueIo<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_all(io: &mut ContinueIo<Vec<u8>>, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            let n = io.write(buf).unwrap();
+            buf = &buf[n..];
+        }
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let mut io = ContinueIo::new(Vec::new());
+        write_all(
+            &mut io,
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n\r\n",
+        );
+        assert_eq!(
+            io.io,
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_discarded() {
+        let mut io = ContinueIo::new(Vec::new());
+        io.shared().expect();
+        write_all(&mut io, b"HTTP/1.1 100 Continue\r\n\r\n");
+        write_all(&mut io, b"HTTP/1.1 401 Unauthorized\r\n\r\n");
+        assert_eq!(io.io, b"HTTP/1.1 401 Unauthorized\r\n\r\n");
+    }
+
+    #[test]
+    fn test_released() {
+        let mut io = ContinueIo::new(Vec::new());
+        io.shared().expect();
+        write_all(&mut io, b"HTTP/1.1 100 Continue\r\n\r\n");
+        assert!(io.io.is_empty());
+
+        io.shared().release();
+        write_all(&mut io, b"HTTP/1.1 200 OK\r\n\r\n");
+        assert_eq!(
+            io.io,
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_released_before_written() {
+        let mut io = ContinueIo::new(Vec::new());
+        io.shared().expect();
+        io.shared().release();
+        write_all(
+            &mut io,
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n\r\n",
+        );
+        assert_eq!(
+            io.io,
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n\r\n"
+        );
+    }
+}
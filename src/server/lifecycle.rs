@@ -0,0 +1,247 @@
+use {
+    super::ServerError,
+    crate::service::CancellationToken,
+    futures::{Async, Future, IntoFuture, Poll},
+    std::{fmt, vec},
+};
+
+type HookFuture = Box<dyn Future<Item = (), Error = failure::Error> + Send + 'static>;
+type Hook = Box<dyn FnBox + Send + 'static>;
+
+trait FnBox {
+    fn call_box(self: Box<Self>) -> HookFuture;
+}
+
+impl<F> FnBox for F
+where
+    F: FnOnce() -> HookFuture,
+{
+    fn call_box(self: Box<Self>) -> HookFuture {
+        (*self)()
+    }
+}
+
+/// A set of the hooks called when the server starts or shuts down.
+///
+/// The hooks are registered with `Server::on_start` and `Server::on_shutdown`.
+#[derive(Default)]
+pub struct Lifecycle {
+    on_start: Vec<Hook>,
+    on_shutdown: Vec<Hook>,
+    shutdown: Option<CancellationToken>,
+}
+
+impl fmt::Debug for Lifecycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lifecycle")
+            .field("on_start", &self.on_start.len())
+            .field("on_shutdown", &self.on_shutdown.len())
+            .finish()
+    }
+}
+
+fn hook<F, R>(f: F) -> Hook
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: IntoFuture<Item = ()>,
+    R::Future: Send + 'static,
+    R::Error: Into<failure::Error> + 'static,
+{
+    Box::new(move || Box::new(f().into_future().map_err(Into::into)) as HookFuture)
+}
+
+impl Lifecycle {
+    pub(crate) fn new() -> Self {
+        Lifecycle::default()
+    }
+
+    pub(crate) fn push_start<F, R>(&mut self, f: F)
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: Send + 'static,
+        R::Error: Into<failure::Error> + 'static,
+    {
+        self.on_start.push(hook(f));
+    }
+
+    pub(crate) fn push_shutdown<F, R>(&mut self, f: F)
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: Send + 'static,
+        R::Error: Into<failure::Error> + 'static,
+    {
+        self.on_shutdown.push(hook(f));
+    }
+
+    pub(crate) fn set_shutdown_token(&mut self, token: CancellationToken) {
+        self.shutdown = Some(token);
+    }
+
+    /// Returns the token to be cancelled when the server starts shutting down.
+    pub(crate) fn shutdown_token(&self) -> Option<CancellationToken> {
+        self.shutdown.clone()
+    }
+
+    /// Wraps the future serving the connections so that the hooks are run
+    /// before and after it.
+    pub(crate) fn run<F>(self, serve: F) -> Running<F>
+    where
+        F: Future<Item = (), Error = ()>,
+    {
+        let mut on_shutdown = self.on_shutdown;
+        on_shutdown.reverse();
+        Running {
+            on_start: self.on_start.into_iter(),
+            on_shutdown: on_shutdown.into_iter(),
+            current: None,
+            serve: Some(serve),
+            phase: Phase::Starting,
+            error: None,
+        }
+    }
+}
+
+#[allow(missing_debug_implementations)]
+pub(crate) struct Running<F> {
+    on_start: vec::IntoIter<Hook>,
+    on_shutdown: vec::IntoIter<Hook>,
+    current: Option<HookFuture>,
+    serve: Option<F>,
+    phase: Phase,
+    error: Option<ServerError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Starting,
+    Serving,
+    ShuttingDown,
+}
+
+impl<F> Future for Running<F>
+where
+    F: Future<Item = (), Error = ()>,
+{
+    type Item = ();
+    type Error = ServerError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if let Some(ref mut current) = self.current {
+                match current.poll() {
+                    Ok(Async::Ready(())) => {}
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        let err = if self.phase == Phase::Starting {
+                            // Abort the startup, and release the resources set up
+                            // by the preceding hooks.
+                            self.serve.take();
+                            self.phase = Phase::ShuttingDown;
+                            failure::err_msg(format!("failed to run the startup hook: {}", err))
+                        } else {
+                            failure::err_msg(format!("failed to run the shutdown hook: {}", err))
+                        };
+                        log::error!("{}", err);
+                        self.error.get_or_insert(ServerError::custom(err));
+                    }
+                }
+                self.current = None;
+                continue;
+            }
+
+            match self.phase {
+                Phase::Starting => match self.on_start.next() {
+                    Some(hook) => self.current = Some(hook.call_box()),
+                    None => self.phase = Phase::Serving,
+                },
+                Phase::Serving => {
+                    let serve = self
+                        .serve
+                        .as_mut()
+                        .expect("the server has already finished");
+                    match serve.poll() {
+                        Ok(Async::Ready(())) => {}
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(()) => {
+                            self.error
+                                .get_or_insert(ServerError::custom(failure::err_msg(
+                                    "the server has terminated with an error",
+                                )));
+                        }
+                    }
+                    self.serve.take();
+                    self.phase = Phase::ShuttingDown;
+                }
+                Phase::ShuttingDown => match self.on_shutdown.next() {
+                    Some(hook) => self.current = Some(hook.call_box()),
+                    None => match self.error.take() {
+                        Some(err) => return Err(err),
+                        None => return Ok(Async::Ready(())),
+                    },
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        futures::future,
+        std::sync::{Arc, Mutex},
+    };
+
+    fn record(events: &Arc<Mutex<Vec<&'static str>>>, event: &'static str) -> Hook {
+        let events = events.clone();
+        hook(move || {
+            events.lock().unwrap().push(event);
+            Ok::<(), failure::Error>(())
+        })
+    }
+
+    #[test]
+    fn test_hook_order() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut lifecycle = Lifecycle::new();
+        lifecycle.on_start.push(record(&events, "start1"));
+        lifecycle.on_start.push(record(&events, "start2"));
+        lifecycle.on_shutdown.push(record(&events, "shutdown1"));
+        lifecycle.on_shutdown.push(record(&events, "shutdown2"));
+
+        let serve = {
+            let events = events.clone();
+            future::lazy(move || {
+                events.lock().unwrap().push("serve");
+                Ok(())
+            })
+        };
+        lifecycle.run(serve).wait().unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["start1", "start2", "serve", "shutdown2", "shutdown1"]
+        );
+    }
+
+    #[test]
+    fn test_startup_failure() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut lifecycle = Lifecycle::new();
+        lifecycle.push_start(|| Err::<(), _>(failure::err_msg("migration failed")));
+        lifecycle.on_start.push(record(&events, "start2"));
+        lifecycle.on_shutdown.push(record(&events, "shutdown"));
+
+        let serve = {
+            let events = events.clone();
+            future::lazy(move || {
+                events.lock().unwrap().push("serve");
+                Ok(())
+            })
+        };
+        let err = lifecycle.run(serve).wait().unwrap_err();
+        assert!(err.to_string().contains("migration failed"));
+        assert_eq!(*events.lock().unwrap(), vec!["shutdown"]);
+    }
+}
@@ -0,0 +1,284 @@
+//! The instrumentation of the tasks spawned by the server.
+
+#![cfg_attr(not(feature = "runtime-metrics"), allow(dead_code))]
+
+use {
+    futures::{
+        future::{ExecuteError, Executor},
+        Future, Poll,
+    },
+    serde::Serialize,
+    std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// A collector of the metrics of the tasks spawned by the server.
+///
+/// The server registered with `Server::runtime_metrics` counts the tasks
+/// which serve the connections (and the HTTP/2 streams), and measures how
+/// long each poll of them takes. A poll which takes longer than the threshold
+/// (10 milliseconds by default) is counted as a *slow poll*, which usually
+/// means that the task is blocking the worker thread.
+///
+/// Tokio 0.1 does not expose the internal state of its scheduler, so the
+/// depth of the run queues is not available. The number of the worker threads
+/// is collected only if the runtime is built with `instrument_workers`.
+/// For the same reason, `tokio-console` (which requires Tokio 1.x) is not
+/// supported.
+#[derive(Debug, Clone)]
+pub struct RuntimeMetrics {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    slow_poll_threshold: Duration,
+    workers: AtomicUsize,
+    workers_instrumented: AtomicBool,
+    spawned: AtomicUsize,
+    alive: AtomicUsize,
+    polling: AtomicUsize,
+    polls: Mutex<PollStats>,
+}
+
+/// The statistics of the polls, updated together under the lock.
+#[derive(Debug, Default)]
+struct PollStats {
+    count: u64,
+    time: u64,
+    max_time: u64,
+    slow: u64,
+}
+
+/// A snapshot of the metrics collected by `RuntimeMetrics`.
+///
+/// The durations are in microseconds.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuntimeSnapshot {
+    /// The number of the running worker threads, if the runtime has been
+    /// built with `RuntimeMetrics::instrument_workers`.
+    pub workers: Option<usize>,
+    /// The total number of spawned tasks.
+    pub tasks_spawned: u64,
+    /// The number of tasks which have not been completed yet.
+    pub tasks_alive: usize,
+    /// The number of tasks being polled, i.e. the number of busy workers.
+    pub tasks_polling: usize,
+    /// The total number of polls.
+    pub polls_total: u64,
+    /// The total duration of polls.
+    pub poll_time_total_us: u64,
+    /// The longest duration of a poll.
+    pub poll_time_max_us: u64,
+    /// The number of polls which took longer than the threshold.
+    pub slow_polls_total: u64,
+}
+
+impl Default for RuntimeMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RuntimeMetrics {
+    /// Creates a new `RuntimeMetrics`.
+    pub fn new() -> Self {
+        RuntimeMetrics {
+            inner: Arc::new(Inner {
+                slow_poll_threshold: Duration::from_millis(10),
+                workers: AtomicUsize::new(0),
+                workers_instrumented: AtomicBool::new(false),
+                spawned: AtomicUsize::new(0),
+                alive: AtomicUsize::new(0),
+                polling: AtomicUsize::new(0),
+                polls: Mutex::new(PollStats::default()),
+            }),
+        }
+    }
+
+    /// Sets the duration of a poll regarded as slow.
+    ///
+    /// # Panics
+    /// This method panics if the `RuntimeMetrics` has already been cloned.
+    pub fn slow_poll_threshold(mut self, threshold: Duration) -> Self {
+        Arc::get_mut(&mut self.inner)
+            .expect("the metrics have already been shared")
+            .slow_poll_threshold = threshold;
+        self
+    }
+
+    /// Registers the hooks to the runtime builder which count the running
+    /// worker threads.
+    pub fn instrument_workers<'a>(
+        &self,
+        builder: &'a mut tokio::runtime::Builder,
+    ) -> &'a mut tokio::runtime::Builder {
+        self.inner
+            .workers_instrumented
+            .store(true, Ordering::Relaxed);
+        let (started, stopped) = (self.inner.clone(), self.inner.clone());
+        builder
+            .after_start(move || {
+                started.workers.fetch_add(1, Ordering::Relaxed);
+            })
+            .before_stop(move || {
+                stopped.workers.fetch_sub(1, Ordering::Relaxed);
+            })
+    }
+
+    /// Returns the current values of the metrics.
+    pub fn snapshot(&self) -> RuntimeSnapshot {
+        let inner = &*self.inner;
+        let polls = inner.polls.lock().unwrap_or_else(|e| e.into_inner());
+        RuntimeSnapshot {
+            workers: if inner.workers_instrumented.load(Ordering::Relaxed) {
+                Some(inner.workers.load(Ordering::Relaxed))
+            } else {
+                None
+            },
+            tasks_spawned: inner.spawned.load(Ordering::Relaxed) as u64,
+            tasks_alive: inner.alive.load(Ordering::Relaxed),
+            tasks_polling: inner.polling.load(Ordering::Relaxed),
+            polls_total: polls.count,
+            poll_time_total_us: polls.time,
+            poll_time_max_us: polls.max_time,
+            slow_polls_total: polls.slow,
+        }
+    }
+}
+
+/// A future which records its polls to `RuntimeMetrics`, if any.
+#[allow(missing_debug_implementations)]
+pub(crate) struct Instrumented<F> {
+    future: F,
+    task: Option<Task>,
+}
+
+/// The guard which counts the alive tasks.
+struct Task(Arc<Inner>);
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        self.0.alive.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<F> Instrumented<F> {
+    pub(crate) fn new(future: F, metrics: Option<&RuntimeMetrics>) -> Self {
+        let task = metrics.map(|metrics| {
+            metrics.inner.spawned.fetch_add(1, Ordering::Relaxed);
+            metrics.inner.alive.fetch_add(1, Ordering::Relaxed);
+            Task(metrics.inner.clone())
+        });
+        Instrumented { future, task }
+    }
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = match self.task {
+            Some(Task(ref inner)) => inner,
+            None => return self.future.poll(),
+        };
+
+        inner.polling.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+        let polled = self.future.poll();
+        let elapsed = start.elapsed();
+        inner.polling.fetch_sub(1, Ordering::Relaxed);
+
+        let micros = elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_micros());
+        let mut polls = inner.polls.lock().unwrap_or_else(|e| e.into_inner());
+        polls.count += 1;
+        polls.time += micros;
+        polls.max_time = polls.max_time.max(micros);
+        if elapsed >= inner.slow_poll_threshold {
+            polls.slow += 1;
+        }
+
+        polled
+    }
+}
+
+/// An `Executor` which spawns the tasks wrapped in `Instrumented`.
+#[derive(Clone)]
+#[allow(missing_debug_implementations)]
+pub(crate) struct InstrumentedExecutor<E> {
+    executor: E,
+    metrics: Option<RuntimeMetrics>,
+}
+
+impl<E> InstrumentedExecutor<E> {
+    pub(crate) fn new(executor: E, metrics: Option<RuntimeMetrics>) -> Self {
+        InstrumentedExecutor { executor, metrics }
+    }
+}
+
+impl<E, F> Executor<F> for InstrumentedExecutor<E>
+where
+    E: Executor<Instrumented<F>>,
+    F: Future<Item = (), Error = ()>,
+{
+    fn execute(&self, future: F) -> Result<(), ExecuteError<F>> {
+        self.executor
+            .execute(Instrumented::new(future, self.metrics.as_ref()))
+            .map_err(|err| {
+                let kind = err.kind();
+                let Instrumented { future, task } = err.into_future();
+                // The task has not been spawned.
+                if let Some(Task(ref inner)) = task {
+                    inner.spawned.fetch_sub(1, Ordering::Relaxed);
+                }
+                ExecuteError::new(kind, future)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, futures::future};
+
+    #[test]
+    fn test_instrumented() {
+        let metrics = RuntimeMetrics::new().slow_poll_threshold(Duration::from_millis(5));
+        let mut rt = tokio::runtime::current_thread::Runtime::new().unwrap();
+
+        rt.block_on(Instrumented::new(
+            future::lazy(|| {
+                std::thread::sleep(Duration::from_millis(10));
+                Ok::<(), ()>(())
+            }),
+            Some(&metrics),
+        ))
+        .unwrap();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.workers, None);
+        assert_eq!(snapshot.tasks_spawned, 1);
+        assert_eq!(snapshot.tasks_alive, 0);
+        assert_eq!(snapshot.tasks_polling, 0);
+        assert_eq!(snapshot.polls_total, 1);
+        assert_eq!(snapshot.slow_polls_total, 1);
+        assert!(snapshot.poll_time_max_us >= 10_000);
+    }
+
+    #[test]
+    fn test_instrument_workers() {
+        let metrics = RuntimeMetrics::new();
+        let mut builder = tokio::runtime::Builder::new();
+        let rt = metrics
+            .instrument_workers(builder.core_threads(2))
+            .build()
+            .unwrap();
+        rt.shutdown_on_idle().wait().unwrap();
+        assert_eq!(metrics.snapshot().workers, Some(0));
+    }
+}
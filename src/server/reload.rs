@@ -0,0 +1,370 @@
+use {
+    super::conn::Acceptor,
+    futures::{task::AtomicTask, Async, Future, Poll, Stream},
+    std::{
+        fmt, fs, io,
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex, RwLock,
+        },
+        time::{Duration, Instant, SystemTime},
+    },
+    tokio::{net::TcpStream, timer::Interval},
+};
+
+/// An `Acceptor` whose inner acceptor can be replaced while the server is running.
+///
+/// The replaced acceptor is used for the connections accepted after the
+/// replacement, and the established connections are not affected.
+/// This is typically used for picking up the renewed TLS certificates without
+/// restarting the server:
+///
+/// ```no_run
+/// # use finchers::prelude::*;
+/// # use finchers::server::{self, Reloadable};
+/// # use std::{io, time::Duration};
+/// # use tokio::net::TcpStream;
+/// # // a stand-in for the function which loads the certificates
+/// # fn load_tls_acceptor() -> io::Result<fn(TcpStream) -> io::Result<TcpStream>> {
+/// #     Ok(Ok)
+/// # }
+/// # let endpoint = finchers::endpoint::unit().map(|| "Hello");
+/// let acceptor = Reloadable::new(load_tls_acceptor()?);
+/// let watch = acceptor.watch_files(
+///     vec!["cert.pem".into(), "key.pem".into()],
+///     Duration::from_secs(60),
+///     load_tls_acceptor,
+/// );
+///
+/// let mut rt = tokio::runtime::Runtime::new()?;
+/// rt.spawn(watch);
+/// server::start(endpoint)
+///     .bind_tls("0.0.0.0:443", acceptor)
+///     .serve_with_runtime(rt)
+///     .expect("failed to start the server");
+/// # Ok::<(), io::Error>(())
+/// ```
+pub struct Reloadable<A> {
+    shared: Arc<Shared<A>>,
+}
+
+struct Shared<A> {
+    current: RwLock<Arc<A>>,
+    // The number of `Reloadable`s, which does not include the watchers.
+    handles: AtomicUsize,
+    // The tasks of the watchers, notified when all handles are dropped.
+    watchers: Mutex<Vec<Arc<AtomicTask>>>,
+}
+
+impl<A> fmt::Debug for Reloadable<A>
+where
+    A: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reloadable")
+            .field("current", &*self.current())
+            .finish()
+    }
+}
+
+impl<A> Clone for Reloadable<A> {
+    fn clone(&self) -> Self {
+        self.shared.handles.fetch_add(1, Ordering::SeqCst);
+        Reloadable {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<A> Drop for Reloadable<A> {
+    fn drop(&mut self) {
+        if self.shared.handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+            for watcher in &*self.shared.watchers.lock().unwrap() {
+                watcher.notify();
+            }
+        }
+    }
+}
+
+impl<A> Reloadable<A> {
+    /// Creates a new `Reloadable` with the specified initial acceptor.
+    pub fn new(acceptor: A) -> Self {
+        Reloadable {
+            shared: Arc::new(Shared {
+                current: RwLock::new(Arc::new(acceptor)),
+                handles: AtomicUsize::new(1),
+                watchers: Mutex::new(vec![]),
+            }),
+        }
+    }
+
+    /// Returns the acceptor currently in use.
+    pub fn current(&self) -> Arc<A> {
+        self.shared.current()
+    }
+
+    /// Replaces the acceptor with the specified one.
+    pub fn reload(&self, acceptor: A) {
+        self.shared.reload(acceptor);
+    }
+
+    /// Creates a future which replaces the acceptor periodically with the one
+    /// returned from `provider`.
+    ///
+    /// If `provider` returns an error, the error is logged and the current
+    /// acceptor continues to be used.
+    /// The returned future completes as soon as all of the handles of this
+    /// `Reloadable` (including the one passed to the server) are dropped,
+    /// e.g. when the server has shut down. The watchers themselves are not
+    /// counted as the handles, and hence several watchers can share the same
+    /// `Reloadable`.
+    pub fn watch<F>(&self, interval: Duration, provider: F) -> Watch<A, F>
+    where
+        F: FnMut() -> io::Result<A>,
+    {
+        self.watch_files(None, interval, provider)
+    }
+
+    /// Creates a future which checks the modification time of the specified
+    /// files periodically and replaces the acceptor with the one returned
+    /// from `provider` when any of them has been modified.
+    ///
+    /// The behavior on errors and the completion are the same as `watch`.
+    pub fn watch_files<I, F>(&self, paths: I, interval: Duration, provider: F) -> Watch<A, F>
+    where
+        I: IntoIterator<Item = PathBuf>,
+        F: FnMut() -> io::Result<A>,
+    {
+        let files: Vec<_> = paths
+            .into_iter()
+            .map(|path| {
+                let modified = modified(&path);
+                (path, modified)
+            })
+            .collect();
+        Watch {
+            registration: self.register(),
+            interval: Interval::new(Instant::now() + interval, interval),
+            files,
+            provider,
+        }
+    }
+
+    /// Registers a watcher which is notified when all of the handles are dropped.
+    pub(super) fn register(&self) -> Registration<A> {
+        let task = Arc::new(AtomicTask::new());
+        self.shared.watchers.lock().unwrap().push(task.clone());
+        Registration {
+            shared: self.shared.clone(),
+            task,
+        }
+    }
+}
+
+impl<A> Shared<A> {
+    fn current(&self) -> Arc<A> {
+        self.current.read().unwrap().clone()
+    }
+
+    fn reload(&self, acceptor: A) {
+        *self.current.write().unwrap() = Arc::new(acceptor);
+    }
+
+    fn is_unused(&self) -> bool {
+        self.handles.load(Ordering::SeqCst) == 0
+    }
+}
+
+impl<A> Acceptor for Reloadable<A>
+where
+    A: Acceptor,
+{
+    type Conn = A::Conn;
+    type Accept = A::Accept;
+
+    fn accept(&self, stream: TcpStream) -> Self::Accept {
+        self.current().accept(stream)
+    }
+}
+
+/// The registration of a watcher, which is not counted as a handle of `Reloadable`.
+pub(super) struct Registration<A> {
+    shared: Arc<Shared<A>>,
+    task: Arc<AtomicTask>,
+}
+
+impl<A> Registration<A> {
+    /// Returns whether all of the handles have been dropped.
+    ///
+    /// The current task is registered before checking, in order not to miss
+    /// the notification from the last handle.
+    pub(super) fn is_unused(&self) -> bool {
+        self.task.register();
+        self.shared.is_unused()
+    }
+
+    pub(super) fn reload(&self, acceptor: A) {
+        self.shared.reload(acceptor);
+    }
+}
+
+impl<A> Drop for Registration<A> {
+    fn drop(&mut self) {
+        let task = &self.task;
+        self.shared
+            .watchers
+            .lock()
+            .unwrap()
+            .retain(|watcher| !Arc::ptr_eq(watcher, task));
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// A future which reloads the acceptor periodically.
+///
+/// See the documentation of `Reloadable::watch` for details.
+pub struct Watch<A, F> {
+    registration: Registration<A>,
+    interval: Interval,
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    provider: F,
+}
+
+impl<A, F> fmt::Debug for Watch<A, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watch")
+            .field("interval", &self.interval)
+            .field("files", &self.files)
+            .finish()
+    }
+}
+
+impl<A, F> Watch<A, F>
+where
+    F: FnMut() -> io::Result<A>,
+{
+    fn is_modified(&mut self) -> bool {
+        if self.files.is_empty() {
+            return true;
+        }
+        let mut is_modified = false;
+        for (path, last_modified) in &mut self.files {
+            let modified = modified(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                is_modified = true;
+            }
+        }
+        is_modified
+    }
+}
+
+impl<A, F> Future for Watch<A, F>
+where
+    F: FnMut() -> io::Result<A>,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if self.registration.is_unused() {
+                return Ok(Async::Ready(()));
+            }
+
+            match self.interval.poll() {
+                Ok(Async::Ready(Some(..))) => {}
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => {
+                    log::error!("timer error: {}", err);
+                    return Err(());
+                }
+            }
+
+            if self.is_modified() {
+                match (self.provider)() {
+                    Ok(acceptor) => self.registration.reload(acceptor),
+                    Err(err) => log::error!("failed to reload the acceptor: {}", err),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        futures::future,
+        tokio::{runtime::current_thread::Runtime, timer::Delay},
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct Tag(String);
+
+    impl Acceptor for Tag {
+        type Conn = TcpStream;
+        type Accept = future::FutureResult<TcpStream, io::Error>;
+
+        fn accept(&self, stream: TcpStream) -> Self::Accept {
+            future::ok(stream)
+        }
+    }
+
+    fn sleep(rt: &mut Runtime, millis: u64) {
+        rt.block_on(Delay::new(Instant::now() + Duration::from_millis(millis)))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_watch_files() {
+        let dir = std::env::temp_dir().join(format!("finchers-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cert.pem");
+        fs::write(&path, "v1").unwrap();
+
+        let acceptor = Reloadable::new(Tag("v1".into()));
+        let watch = acceptor.watch_files(vec![path.clone()], Duration::from_millis(10), {
+            let path = path.clone();
+            move || fs::read_to_string(&path).map(Tag)
+        });
+
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(watch);
+        sleep(&mut rt, 30);
+        assert_eq!(*acceptor.current(), Tag("v1".into()));
+
+        fs::write(&path, "v2").unwrap();
+        sleep(&mut rt, 50);
+        assert_eq!(*acceptor.current(), Tag("v2".into()));
+
+        drop(acceptor);
+        rt.run().unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_watch_completes_on_drop() {
+        let acceptor = Reloadable::new(Tag("v1".into()));
+        let interval = Duration::from_secs(60);
+        let watch1 = acceptor.watch(interval, || Ok(Tag("v2".into())));
+        let watch2 = acceptor.clone().watch(interval, || Ok(Tag("v3".into())));
+
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(watch1);
+        rt.spawn(watch2);
+        sleep(&mut rt, 10);
+
+        // Both watchers complete without waiting for the next tick.
+        let start = Instant::now();
+        drop(acceptor);
+        rt.run().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
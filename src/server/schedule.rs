@@ -0,0 +1,407 @@
+use {
+    crate::util::civil_from_days,
+    failure::Fail,
+    futures::{sync::oneshot, Async, Future, IntoFuture, Poll},
+    std::{
+        fmt,
+        str::FromStr,
+        sync::Mutex,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+    tokio_timer::{clock, Delay},
+};
+
+type TaskFuture = Box<dyn Future<Item = (), Error = failure::Error> + Send + 'static>;
+type TaskFn = Box<dyn FnMut() -> TaskFuture + Send + 'static>;
+
+// ==== Schedule ====
+
+/// A schedule of the periodic tasks, written in the cron syntax.
+///
+/// The expression consists of five fields (minute, hour, day of month,
+/// month and day of week), optionally preceded by the field of seconds.
+/// Each field is either `*` or a comma-separated list of values and ranges
+/// (e.g. `1,3,5` or `9-17`), which may be followed by the step (e.g. `*/5`).
+/// Both `0` and `7` mean Sunday in the field of day of week. As in cron,
+/// if both the day of month and the day of week are restricted (i.e. not
+/// starting with `*`), the task runs when either of them matches.
+///
+/// The times are interpreted in UTC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// An error returned when the cron expression is invalid.
+#[derive(Debug, Fail)]
+#[fail(display = "invalid schedule `{}': {}", expr, reason)]
+pub struct InvalidSchedule {
+    expr: String,
+    reason: String,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<(u64, bool), String> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(pos) => {
+                let step: u32 = part[pos + 1..]
+                    .parse()
+                    .map_err(|_| format!("invalid step: `{}'", part))?;
+                if step == 0 {
+                    return Err(format!("invalid step: `{}'", part));
+                }
+                (&part[..pos], step)
+            }
+            None => (part, 1),
+        };
+        let parse = |s: &str| -> Result<u32, String> {
+            match s.parse() {
+                Ok(n) if min <= n && n <= max => Ok(n),
+                _ => Err(format!("out of range: `{}' ({}-{})", s, min, max)),
+            }
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.find('-') {
+                Some(pos) => (parse(&range[..pos])?, parse(&range[pos + 1..])?),
+                None if step > 1 => (parse(range)?, max),
+                None => {
+                    let n = parse(range)?;
+                    (n, n)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("invalid range: `{}'", range));
+        }
+        for n in (start..=end).step_by(step as usize) {
+            bits |= 1 << n;
+        }
+    }
+    // As in cron, the field starting with `*` (e.g. `*/2`) is regarded as
+    // unrestricted when combining the day of month and the day of week.
+    Ok((bits, field.starts_with('*')))
+}
+
+impl FromStr for Schedule {
+    type Err = InvalidSchedule;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| InvalidSchedule {
+            expr: expr.into(),
+            reason,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let (seconds, fields) = match fields.len() {
+            5 => ("0", &fields[..]),
+            6 => (fields[0], &fields[1..]),
+            n => return Err(invalid(format!("expected 5 or 6 fields, found {}", n))),
+        };
+        let (seconds, _) = parse_field(seconds, 0, 59).map_err(invalid)?;
+        let (minutes, _) = parse_field(fields[0], 0, 59).map_err(invalid)?;
+        let (hours, _) = parse_field(fields[1], 0, 23).map_err(invalid)?;
+        let (days, any_day) = parse_field(fields[2], 1, 31).map_err(invalid)?;
+        let (months, _) = parse_field(fields[3], 1, 12).map_err(invalid)?;
+        let (mut weekdays, any_weekday) = parse_field(fields[4], 0, 7).map_err(invalid)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Schedule {
+            seconds,
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            any_day,
+            any_weekday,
+        })
+    }
+}
+
+impl Schedule {
+    fn matches_date(&self, days: i64) -> bool {
+        let (_, month, day) = civil_from_days(days);
+        // 1970-01-01 is Thursday.
+        let weekday = ((days % 7 + 7 + 4) % 7) as u32;
+        let day_matches = self.days & (1 << day) != 0;
+        let weekday_matches = self.weekdays & (1 << weekday) != 0;
+        self.months & (1 << month) != 0
+            && match (self.any_day, self.any_weekday) {
+                (false, false) => day_matches || weekday_matches,
+                _ => day_matches && weekday_matches,
+            }
+    }
+
+    /// Returns the first time after `time` which matches this schedule,
+    /// or `None` if no such time exists within the next few years.
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        const MAX_DAYS: i64 = 366 * 8;
+
+        // `secs` is never negative.
+        let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64 + 1;
+        let (mut days, mut secs_of_day) = (secs / 86400, secs % 86400);
+        let start = days;
+        while days - start <= MAX_DAYS {
+            if !self.matches_date(days) {
+                days += 1;
+                secs_of_day = 0;
+                continue;
+            }
+            while secs_of_day < 86400 {
+                let (hour, minute, second) =
+                    (secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60);
+                if self.hours & (1 << hour) == 0 {
+                    secs_of_day = (hour + 1) * 3600;
+                } else if self.minutes & (1 << minute) == 0 {
+                    secs_of_day = (secs_of_day / 60 + 1) * 60;
+                } else if self.seconds & (1 << second) == 0 {
+                    secs_of_day += 1;
+                } else {
+                    let secs = (days * 86400 + secs_of_day) as u64;
+                    return Some(UNIX_EPOCH + Duration::from_secs(secs));
+                }
+            }
+            days += 1;
+            secs_of_day = 0;
+        }
+        None
+    }
+}
+
+// ==== Scheduled ====
+
+/// The state shared by the lifecycle hooks of a scheduled task.
+#[derive(Default)]
+pub(super) struct Handle {
+    inner: Mutex<Option<(oneshot::Sender<()>, oneshot::Receiver<()>)>>,
+}
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle").finish()
+    }
+}
+
+impl Handle {
+    /// Creates the future which runs the task periodically.
+    pub(super) fn start<F, R>(&self, schedule: Schedule, mut task: F) -> Scheduled
+    where
+        F: FnMut() -> R + Send + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: Send + 'static,
+        R::Error: Into<failure::Error> + 'static,
+    {
+        let (tx_stop, rx_stop) = oneshot::channel();
+        let (tx_done, rx_done) = oneshot::channel();
+        *self.inner.lock().unwrap() = Some((tx_stop, rx_done));
+        let mut scheduled = Scheduled {
+            schedule,
+            task: Box::new(move || Box::new(task().into_future().map_err(Into::into))),
+            state: State::Finished,
+            stop: rx_stop,
+            stopping: false,
+            _done: tx_done,
+        };
+        scheduled.state = scheduled.wait_next();
+        scheduled
+    }
+
+    /// Stops the task, and returns a future which completes after the
+    /// running task is finished.
+    pub(super) fn stop(&self) -> impl Future<Item = (), Error = failure::Error> + Send {
+        let inner = self.inner.lock().unwrap().take();
+        let done = inner.map(|(tx_stop, rx_done)| {
+            let _ = tx_stop.send(());
+            rx_done
+        });
+        // `Canceled` means that the task has finished.
+        done.map_or_else(
+            || Box::new(futures::future::ok(())) as Box<dyn Future<Item = _, Error = _> + Send>,
+            |done| Box::new(done.then(|_| Ok(()))),
+        )
+    }
+}
+
+#[allow(missing_debug_implementations)]
+pub(super) struct Scheduled {
+    schedule: Schedule,
+    task: TaskFn,
+    state: State,
+    stop: oneshot::Receiver<()>,
+    stopping: bool,
+    _done: oneshot::Sender<()>,
+}
+
+enum State {
+    Waiting(Delay),
+    Running(TaskFuture),
+    Finished,
+}
+
+impl Scheduled {
+    fn wait_next(&self) -> State {
+        // The current time follows the clock of the runtime, which may be
+        // advanced manually as in the test runner.
+        let real = Instant::now();
+        let instant = clock::now();
+        let now = SystemTime::now() + instant.duration_since(real);
+        match self.schedule.next_after(now) {
+            Some(next) => {
+                let delay = next.duration_since(now).unwrap_or_default();
+                State::Waiting(Delay::new(instant + delay))
+            }
+            None => {
+                log::warn!("the scheduled task will never run again");
+                State::Finished
+            }
+        }
+    }
+}
+
+impl Future for Scheduled {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if !self.stopping {
+                match self.stop.poll() {
+                    Ok(Async::NotReady) => {}
+                    Ok(Async::Ready(())) | Err(..) => self.stopping = true,
+                }
+            }
+
+            self.state = match self.state {
+                State::Waiting(..) | State::Finished if self.stopping => {
+                    return Ok(Async::Ready(()));
+                }
+                State::Waiting(ref mut delay) => match delay.poll() {
+                    Ok(Async::Ready(())) => State::Running((self.task)()),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        log::error!("timer error: {}", err);
+                        return Ok(Async::Ready(()));
+                    }
+                },
+                State::Running(ref mut future) => {
+                    match future.poll() {
+                        Ok(Async::Ready(())) => {}
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(err) => log::error!("the scheduled task failed: {}", err),
+                    }
+                    // The next run is computed after the current one has
+                    // finished, so the runs never overlap and the missed
+                    // ones are skipped.
+                    self.wait_next()
+                }
+                State::Finished => return Ok(Async::NotReady),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{endpoint, test},
+        std::sync::mpsc,
+    };
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    // 2019-01-01T00:00:00Z (Tuesday)
+    const NEW_YEAR_2019: u64 = 1_546_300_800;
+
+    #[test]
+    fn test_parse() {
+        assert!("* * * * *".parse::<Schedule>().is_ok());
+        assert!("*/10 * * * * *".parse::<Schedule>().is_ok());
+        assert!("0 9-17/2 * * 1-5".parse::<Schedule>().is_ok());
+        assert!("* * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("*/0 * * * *".parse::<Schedule>().is_err());
+        assert!("5-1 * * * *".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn test_next_after() {
+        let schedule: Schedule = "*/5 * * * *".parse().unwrap();
+        assert_eq!(
+            schedule.next_after(at(NEW_YEAR_2019)),
+            Some(at(NEW_YEAR_2019 + 300))
+        );
+        assert_eq!(
+            schedule.next_after(at(NEW_YEAR_2019 + 301)),
+            Some(at(NEW_YEAR_2019 + 600))
+        );
+
+        let schedule: Schedule = "30 9 * * 1".parse().unwrap();
+        // the next Monday is 2019-01-07
+        assert_eq!(
+            schedule.next_after(at(NEW_YEAR_2019)),
+            Some(at(NEW_YEAR_2019 + 6 * 86400 + 9 * 3600 + 30 * 60))
+        );
+
+        let schedule: Schedule = "0 0 29 2 *".parse().unwrap();
+        // 2020-02-29
+        assert_eq!(
+            schedule.next_after(at(NEW_YEAR_2019)),
+            Some(at(1_582_934_400))
+        );
+
+        let schedule: Schedule = "0 0 31 2 *".parse().unwrap();
+        assert_eq!(schedule.next_after(at(NEW_YEAR_2019)), None);
+    }
+
+    #[test]
+    fn test_day_or_weekday() {
+        // the 15th or Sundays
+        let schedule: Schedule = "0 0 15 * 7".parse().unwrap();
+        // 2019-01-06 is Sunday
+        assert_eq!(
+            schedule.next_after(at(NEW_YEAR_2019)),
+            Some(at(NEW_YEAR_2019 + 5 * 86400))
+        );
+
+        // the odd days which are Sundays, since `*/2` is unrestricted as in cron
+        let schedule: Schedule = "0 0 */2 * 0".parse().unwrap();
+        // 2019-01-13 is Sunday
+        assert_eq!(
+            schedule.next_after(at(NEW_YEAR_2019)),
+            Some(at(NEW_YEAR_2019 + 12 * 86400))
+        );
+    }
+
+    #[test]
+    fn test_scheduled() {
+        let mut runner = test::runner(endpoint::unit());
+        let (tx, rx) = mpsc::channel();
+        let handle = Handle::default();
+        // every day at midnight
+        let scheduled = handle.start("0 0 0 * * *".parse().unwrap(), move || {
+            tx.send(()).unwrap();
+            Ok::<(), failure::Error>(())
+        });
+        runner.runtime().spawn(scheduled);
+
+        for _ in 0..3 {
+            assert!(rx.try_recv().is_err());
+            runner.advance(Duration::from_secs(86400));
+            rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        }
+
+        runner.runtime().block_on(handle.stop()).unwrap();
+    }
+}
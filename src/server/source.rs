@@ -0,0 +1,339 @@
+use {
+    super::{conn::Listener, Connection, CritError, RequestBody, Signal},
+    bytes::{Buf, Bytes, BytesMut},
+    futures::{
+        future::{Executor, Shared},
+        sync::mpsc,
+        Async, AsyncSink, Future, Poll, Sink, Stream,
+    },
+    http::{header::HeaderValue, Request, Response, StatusCode},
+    izanami_service::{MakeServiceRef, Service},
+    izanami_util::buf_stream::BufStream,
+    std::{fmt, io, mem, net::SocketAddr, sync::Arc, time::Duration},
+};
+
+/// A trait representing a protocol other than HTTP, which is served on the
+/// listeners registered by `Server::bind_with`.
+///
+/// The listeners registered by `bind`, `bind_tls` and `bind_h2c` exchange the
+/// HTTP messages by using Hyper. A `RequestSource` allows other front-ends, such
+/// as FastCGI (see `fastcgi::FastCgi`), to share the runtime, the instance of
+/// `MakeService`, the graceful shutdown and the lifecycle hooks with them.
+///
+/// The requests received on a connection are dispatched one at a time, and
+/// the response body is entirely buffered before it is passed to the transport.
+pub trait RequestSource {
+    /// The type of transport which receives the requests and sends the
+    /// responses over a connection.
+    type Transport: Stream<Item = Request<RequestBody>, Error = io::Error>
+        + Sink<SinkItem = Response<Bytes>, SinkError = io::Error>
+        + Send
+        + 'static;
+
+    /// Creates a transport on the specified connection.
+    fn transport(&self, conn: Connection) -> Self::Transport;
+}
+
+trait Transport:
+    Stream<Item = Request<RequestBody>, Error = io::Error>
+    + Sink<SinkItem = Response<Bytes>, SinkError = io::Error>
+    + Send
+    + 'static
+{
+}
+
+impl<T> Transport for T where
+    T: Stream<Item = Request<RequestBody>, Error = io::Error>
+        + Sink<SinkItem = Response<Bytes>, SinkError = io::Error>
+        + Send
+        + 'static
+{
+}
+
+type TransportFn = Arc<dyn Fn(Connection) -> Box<dyn Transport> + Send + Sync + 'static>;
+
+/// A listener serving a `RequestSource`.
+pub struct Source {
+    listener: Listener,
+    transport: TransportFn,
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Source")
+            .field("listener", &self.listener)
+            .finish()
+    }
+}
+
+impl Source {
+    pub(super) fn new<R>(listener: Listener, source: Arc<R>) -> Self
+    where
+        R: RequestSource + Send + Sync + 'static,
+    {
+        Source {
+            listener,
+            transport: Arc::new(move |conn| Box::new(source.transport(conn)) as Box<dyn Transport>),
+        }
+    }
+
+    pub(super) fn set_tcp_options(&mut self, nodelay: bool, keepalive: Option<Duration>) {
+        self.listener.set_tcp_options(nodelay, keepalive);
+    }
+
+    pub(super) fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr()
+    }
+
+    /// Serves the connections accepted by the listener until the signal is completed.
+    ///
+    /// The returned future completes after all of the in-flight connections are closed.
+    pub(super) fn serve<S, Bd, E>(
+        self,
+        make_service: Arc<S>,
+        signal: Shared<Signal>,
+        executor: E,
+    ) -> impl Future<Item = (), Error = ()>
+    where
+        S: MakeServiceRef<Connection, Request<RequestBody>, Response = Response<Bd>>,
+        S::Error: Into<CritError>,
+        S::MakeError: Into<CritError>,
+        Bd: BufStream,
+        Bd::Error: Into<CritError>,
+        E: Executor<Dispatch<S::Future, Bd>>,
+    {
+        // Each connection holds a sender, and the receiver is terminated when
+        // all of the connections are closed.
+        let (tx, rx) = mpsc::channel::<()>(0);
+        let transport = self.transport;
+        let accept_signal = signal.clone();
+        let accept = self
+            .listener
+            .into_incoming()
+            .map_err(|err| log::error!("failed to accept a connection: {}", err))
+            .for_each(move |conn| {
+                let future = make_service.make_service_ref(&conn);
+                let dispatch = Dispatch {
+                    transport: transport(conn),
+                    service: None,
+                    state: State::Making(future),
+                    signal: signal.clone(),
+                    _guard: tx.clone(),
+                };
+                executor
+                    .execute(dispatch)
+                    .map_err(|err| log::error!("failed to spawn a connection: {:?}", err.kind()))
+            });
+        accept.select2(accept_signal).then(move |result| {
+            // Stop accepting the connections and release the sender.
+            drop(result);
+            rx.for_each(|()| Ok(()))
+        })
+    }
+}
+
+/// A `Future` which dispatches the requests received on a connection.
+#[allow(missing_debug_implementations)]
+pub struct Dispatch<Fut, Bd>
+where
+    Fut: Future,
+    Fut::Item: Service<Request<RequestBody>>,
+{
+    transport: Box<dyn Transport>,
+    service: Option<Fut::Item>,
+    state: State<Fut, Bd>,
+    signal: Shared<Signal>,
+    _guard: mpsc::Sender<()>,
+}
+
+#[allow(missing_debug_implementations)]
+enum State<Fut, Bd>
+where
+    Fut: Future,
+    Fut::Item: Service<Request<RequestBody>>,
+{
+    Making(Fut),
+    Reading,
+    Ready(Request<RequestBody>),
+    InFlight(<Fut::Item as Service<Request<RequestBody>>>::Future),
+    Receiving(http::response::Parts, ReadAll<Bd>),
+    Sending(Response<Bytes>),
+    Flushing,
+    Closing,
+}
+
+impl<Fut, Bd> Dispatch<Fut, Bd>
+where
+    Fut: Future,
+    Fut::Item: Service<Request<RequestBody>>,
+{
+    fn is_shutting_down(&mut self) -> bool {
+        match self.signal.poll() {
+            Ok(Async::NotReady) => false,
+            Ok(Async::Ready(..)) | Err(..) => true,
+        }
+    }
+}
+
+impl<Fut, Bd> Future for Dispatch<Fut, Bd>
+where
+    Fut: Future,
+    Fut::Error: Into<CritError>,
+    Fut::Item: Service<Request<RequestBody>, Response = Response<Bd>>,
+    <Fut::Item as Service<Request<RequestBody>>>::Error: Into<CritError>,
+    Bd: BufStream,
+    Bd::Error: Into<CritError>,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match mem::replace(&mut self.state, State::Closing) {
+                State::Making(mut future) => match future.poll() {
+                    Ok(Async::Ready(service)) => {
+                        self.service = Some(service);
+                        State::Reading
+                    }
+                    Ok(Async::NotReady) => {
+                        self.state = State::Making(future);
+                        return Ok(Async::NotReady);
+                    }
+                    Err(err) => {
+                        log::error!("failed to create a service: {}", err.into());
+                        return Err(());
+                    }
+                },
+                State::Reading if self.is_shutting_down() => State::Closing,
+                State::Reading => match self.transport.poll() {
+                    Ok(Async::Ready(Some(request))) => State::Ready(request),
+                    Ok(Async::Ready(None)) => State::Closing,
+                    Ok(Async::NotReady) => {
+                        self.state = State::Reading;
+                        return Ok(Async::NotReady);
+                    }
+                    Err(err) => {
+                        log::debug!("connection error: {}", err);
+                        return Err(());
+                    }
+                },
+                State::Ready(request) => {
+                    let service = self.service.as_mut().expect("the service is not created");
+                    match service.poll_ready() {
+                        Ok(Async::Ready(())) => State::InFlight(service.call(request)),
+                        Ok(Async::NotReady) => {
+                            self.state = State::Ready(request);
+                            return Ok(Async::NotReady);
+                        }
+                        Err(err) => {
+                            log::error!("service error: {}", err.into());
+                            State::Sending(error_response(StatusCode::SERVICE_UNAVAILABLE))
+                        }
+                    }
+                }
+                State::InFlight(mut future) => match future.poll() {
+                    Ok(Async::Ready(response)) => {
+                        let (parts, body) = response.into_parts();
+                        State::Receiving(parts, ReadAll::new(body))
+                    }
+                    Ok(Async::NotReady) => {
+                        self.state = State::InFlight(future);
+                        return Ok(Async::NotReady);
+                    }
+                    Err(err) => {
+                        log::error!("service error: {}", err.into());
+                        State::Sending(error_response(StatusCode::INTERNAL_SERVER_ERROR))
+                    }
+                },
+                State::Receiving(parts, mut read_all) => match read_all.poll() {
+                    Ok(Async::Ready(body)) => State::Sending(Response::from_parts(parts, body)),
+                    Ok(Async::NotReady) => {
+                        self.state = State::Receiving(parts, read_all);
+                        return Ok(Async::NotReady);
+                    }
+                    Err(err) => {
+                        log::error!("failed to receive the response body: {}", err);
+                        State::Sending(error_response(StatusCode::INTERNAL_SERVER_ERROR))
+                    }
+                },
+                State::Sending(response) => match self.transport.start_send(response) {
+                    Ok(AsyncSink::Ready) => State::Flushing,
+                    Ok(AsyncSink::NotReady(response)) => {
+                        self.state = State::Sending(response);
+                        return Ok(Async::NotReady);
+                    }
+                    Err(err) => {
+                        log::debug!("connection error: {}", err);
+                        return Err(());
+                    }
+                },
+                State::Flushing => match self.transport.poll_complete() {
+                    Ok(Async::Ready(())) => State::Reading,
+                    Ok(Async::NotReady) => {
+                        self.state = State::Flushing;
+                        return Ok(Async::NotReady);
+                    }
+                    Err(err) => {
+                        log::debug!("connection error: {}", err);
+                        return Err(());
+                    }
+                },
+                State::Closing => {
+                    return self
+                        .transport
+                        .close()
+                        .map_err(|err| log::debug!("connection error: {}", err));
+                }
+            };
+        }
+    }
+}
+
+pub(crate) fn error_response(status: StatusCode) -> Response<Bytes> {
+    let mut response = Response::new(Bytes::from(status.to_string()));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+}
+
+/// A `Future` which receives all of the response body.
+#[allow(missing_debug_implementations)]
+pub(crate) struct ReadAll<Bd> {
+    body: Bd,
+    buf: BytesMut,
+}
+
+impl<Bd> ReadAll<Bd> {
+    pub(crate) fn new(body: Bd) -> Self {
+        ReadAll {
+            body,
+            buf: BytesMut::new(),
+        }
+    }
+}
+
+impl<Bd> Future for ReadAll<Bd>
+where
+    Bd: BufStream,
+    Bd::Error: Into<CritError>,
+{
+    type Item = Bytes;
+    type Error = CritError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while let Some(mut data) = futures::try_ready!(self.body.poll_buf().map_err(Into::into)) {
+            while data.has_remaining() {
+                let n = {
+                    let chunk = data.bytes();
+                    self.buf.extend_from_slice(chunk);
+                    chunk.len()
+                };
+                data.advance(n);
+            }
+        }
+        Ok(Async::Ready(self.buf.take().freeze()))
+    }
+}
@@ -0,0 +1,149 @@
+use {
+    http::{header, HeaderMap, Request},
+    std::net::SocketAddr,
+};
+
+/// The configuration of strict request validation.
+///
+/// When enabled by `Server::strict_parsing`, the requests with the following
+/// ambiguities are rejected with `400 Bad Request` before being passed to
+/// the service, in order to prevent the request smuggling attacks:
+///
+/// * Both `Content-Length` and `Transfer-Encoding` are specified.
+/// * Multiple `Content-Length` values which are not identical, or a value
+///   which is not a decimal number.
+/// * `Transfer-Encoding` whose final coding is not `chunked`.
+/// * Header values containing NUL, CR or LF (including the obsolete line folding).
+///   Hyper's parser already rejects most of them, and this check is kept as
+///   a safeguard against the changes in the underlying parser.
+#[derive(Debug, Clone, Copy)]
+pub struct StrictParsing {
+    log_level: Option<log::Level>,
+}
+
+impl Default for StrictParsing {
+    fn default() -> Self {
+        StrictParsing {
+            log_level: Some(log::Level::Warn),
+        }
+    }
+}
+
+impl StrictParsing {
+    /// Creates a new `StrictParsing` with the default configuration.
+    ///
+    /// By default, the rejected requests are logged at the `WARN` level
+    /// with the address of the peer.
+    pub fn new() -> Self {
+        StrictParsing::default()
+    }
+
+    /// Sets the level at which the rejected requests are logged.
+    ///
+    /// If `None` is specified, the rejected requests are not logged.
+    pub fn log_level(self, level: Option<log::Level>) -> Self {
+        StrictParsing { log_level: level }
+    }
+
+    pub(super) fn check<T>(&self, request: &Request<T>, remote_addr: SocketAddr) -> bool {
+        match validate(request.headers()) {
+            Ok(()) => true,
+            Err(reason) => {
+                if let Some(level) = self.log_level {
+                    log::log!(
+                        level,
+                        "rejected a malformed request from {}: {}",
+                        remote_addr,
+                        reason
+                    );
+                }
+                false
+            }
+        }
+    }
+}
+
+fn validate(headers: &HeaderMap) -> Result<(), &'static str> {
+    for (_, value) in headers {
+        if value
+            .as_bytes()
+            .iter()
+            .any(|&b| b == b'\0' || b == b'\r' || b == b'\n')
+        {
+            return Err("the header value contains an invalid character");
+        }
+    }
+
+    let has_transfer_encoding = headers.contains_key(header::TRANSFER_ENCODING);
+    let mut content_lengths = headers.get_all(header::CONTENT_LENGTH).iter();
+    if let Some(first) = content_lengths.next() {
+        if has_transfer_encoding {
+            return Err("both Content-Length and Transfer-Encoding are specified");
+        }
+        let first = first.as_bytes();
+        if first.is_empty() || !first.iter().all(u8::is_ascii_digit) {
+            return Err("invalid Content-Length");
+        }
+        if content_lengths.any(|value| value.as_bytes() != first) {
+            return Err("conflicting Content-Length values");
+        }
+    }
+
+    if has_transfer_encoding {
+        let last_coding = headers
+            .get_all(header::TRANSFER_ENCODING)
+            .iter()
+            .next_back()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .map(|coding| coding.trim().to_ascii_lowercase());
+        if last_coding.as_ref().map(String::as_str) != Some("chunked") {
+            return Err("the final transfer coding is not chunked");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        http::header::{HeaderName, HeaderValue},
+    };
+
+    fn headers(entries: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for &(name, value) in entries {
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&headers(&[("content-length", "10")])).is_ok());
+        assert!(validate(&headers(&[
+            ("content-length", "10"),
+            ("content-length", "10")
+        ]))
+        .is_ok());
+        assert!(validate(&headers(&[("transfer-encoding", "gzip, chunked")])).is_ok());
+
+        assert!(validate(&headers(&[
+            ("content-length", "10"),
+            ("content-length", "11")
+        ]))
+        .is_err());
+        assert!(validate(&headers(&[("content-length", "+10")])).is_err());
+        assert!(validate(&headers(&[
+            ("content-length", "10"),
+            ("transfer-encoding", "chunked")
+        ]))
+        .is_err());
+        assert!(validate(&headers(&[("transfer-encoding", "chunked, gzip")])).is_err());
+    }
+}
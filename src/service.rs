@@ -7,17 +7,38 @@ use {
         action::{ActionContext, EndpointAction, Preflight, PreflightContext},
         endpoint::{Endpoint, IsEndpoint},
         error::Error,
-        output::IntoResponse,
+        middleware::WithMiddleware,
+        output::{CheckedBody, IntoResponse},
     },
-    bytes::{BufMut, BytesMut},
+    bytes::{BufMut, Bytes, BytesMut},
     cookie::{Cookie, CookieJar},
     futures::{future, Async, Future, Poll},
     http::{
-        header::{HeaderMap, HeaderValue},
-        Request, Response,
+        header::{HeaderMap, HeaderName, HeaderValue},
+        Extensions, Request, Response, StatusCode,
     },
     izanami_service::{MakeService, Service},
-    std::{cell::Cell, io, marker::PhantomData, ptr::NonNull, sync::Arc},
+    std::{
+        cell::{Cell, Ref, RefCell},
+        fmt, io,
+        marker::PhantomData,
+        ptr::NonNull,
+        sync::Arc,
+        time::Duration,
+    },
+    tokio::timer::Delay,
+};
+
+mod cancel;
+mod headers;
+mod pool;
+mod timing;
+
+pub use self::{
+    cancel::{CancellationToken, Cancelled},
+    headers::ResponseHeaders,
+    pool::{BufferPool, BufferPoolConfig, BufferPoolStats, PooledBuf},
+    timing::ServerTimings,
 };
 
 macro_rules! ready {
@@ -41,9 +62,19 @@ impl<E: IsEndpoint> EndpointServiceExt for E {
 }
 
 /// A wrapper struct for lifting the instance of `Endpoint` to an HTTP service.
+///
+/// In addition to `MakeService`, this type also implements `tower_service::Service`
+/// so that it can be embedded into the stack of Tower middlewares.
+///
+/// The hooks called when the connections are opened or closed can be registered
+/// with `App::with_connection_hooks`.
 #[derive(Debug)]
-pub struct App<E> {
+pub struct App<E, H = NoHooks> {
     endpoint: Arc<E>,
+    hooks: Arc<H>,
+    request_timeout: Option<Duration>,
+    buffer_pool: Option<BufferPool>,
+    shutdown: CancellationToken,
 }
 
 impl<E> App<E> {
@@ -51,14 +82,70 @@ impl<E> App<E> {
     pub fn new(endpoint: E) -> Self {
         App {
             endpoint: Arc::new(endpoint),
+            hooks: Arc::new(NoHooks(())),
+            request_timeout: None,
+            buffer_pool: None,
+            shutdown: CancellationToken::new(),
+        }
+    }
+}
+
+impl<E, H> App<E, H> {
+    /// Sets the hooks called at the beginning and the end of each connection.
+    ///
+    /// See the documentation of `ConnectionHooks` for details.
+    pub fn with_connection_hooks<H2>(self, hooks: H2) -> App<E, H2> {
+        App {
+            endpoint: self.endpoint,
+            hooks: Arc::new(hooks),
+            request_timeout: self.request_timeout,
+            buffer_pool: self.buffer_pool,
+            shutdown: self.shutdown,
         }
     }
+
+    /// Sets the maximum duration for processing each request.
+    ///
+    /// When the timeout expires, the cancellation token of the request is
+    /// cancelled and the client receives `503 Service Unavailable`.
+    /// Unlike `wrapper::timeout`, this setting applies to all of the requests
+    /// without changing the type of endpoint.
+    pub fn with_request_timeout(self, timeout: Duration) -> Self {
+        App {
+            request_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Sets the pool of the buffers used by the body extractors for receiving
+    /// the request bodies.
+    ///
+    /// Without the pool, a buffer is allocated for each request.
+    pub fn with_buffer_pool(self, pool: BufferPool) -> Self {
+        App {
+            buffer_pool: Some(pool),
+            ..self
+        }
+    }
+
+    /// Returns the token cancelled when the server starts shutting down gracefully.
+    pub(crate) fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Applies the specified `Middleware` to the services created by this `App`.
+    ///
+    /// See the documentation of the `middleware` module for details.
+    pub fn with_middleware<M>(self, middleware: M) -> WithMiddleware<Self, M> {
+        WithMiddleware::new(self, middleware)
+    }
 }
 
-impl<E, Ctx, Bd> MakeService<Ctx, Request<Bd>> for App<E>
+impl<E, H, Ctx, Bd> MakeService<Ctx, Request<Bd>> for App<E, H>
 where
     E: Endpoint<Bd>,
     E::Output: IntoResponse,
+    H: ConnectionHooks<Ctx> + Send + Sync + 'static,
 {
     type Response = Response<ResponseBody<Bd, E>>;
     type Error = io::Error;
@@ -66,8 +153,45 @@ where
     type MakeError = io::Error;
     type Future = future::FutureResult<Self::Service, Self::MakeError>;
 
-    fn make_service(&self, _: Ctx) -> Self::Future {
-        future::ok(AppService::new(self.endpoint.clone()))
+    fn make_service(&self, ctx: Ctx) -> Self::Future {
+        let mut extensions = Extensions::new();
+        self.hooks.on_open(&ctx, &mut extensions);
+        let connection = Connection {
+            extensions,
+            on_close: Some(on_close_hook(
+                self.hooks.clone(),
+                <H as ConnectionHooks<Ctx>>::on_close,
+            )),
+        };
+
+        let mut service = AppService::new(self.endpoint.clone());
+        service.connection = Some(Arc::new(connection));
+        service.request_timeout = self.request_timeout;
+        service.buffer_pool = self.buffer_pool.clone();
+        service.shutdown = Some(self.shutdown.clone());
+        future::ok(service)
+    }
+}
+
+impl<E, H, Bd> Service<Request<Bd>> for App<E, H>
+where
+    E: Endpoint<Bd>,
+    E::Output: IntoResponse,
+{
+    type Response = Response<ResponseBody<Bd, Arc<E>>>;
+    type Error = io::Error;
+    type Future = AppFuture<Bd, Arc<E>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, request: Request<Bd>) -> Self::Future {
+        let mut service = AppService::new(self.endpoint.clone());
+        service.request_timeout = self.request_timeout;
+        service.buffer_pool = self.buffer_pool.clone();
+        service.shutdown = Some(self.shutdown.clone());
+        service.dispatch(request)
     }
 }
 
@@ -75,6 +199,10 @@ where
 #[allow(missing_debug_implementations)]
 pub struct AppService<Bd, E: Endpoint<Bd>> {
     endpoint: E,
+    connection: Option<Arc<Connection>>,
+    request_timeout: Option<Duration>,
+    buffer_pool: Option<BufferPool>,
+    shutdown: Option<CancellationToken>,
     _marker: PhantomData<fn(Bd)>,
 }
 
@@ -85,16 +213,30 @@ where
     pub(crate) fn new(endpoint: E) -> Self {
         AppService {
             endpoint,
+            connection: None,
+            request_timeout: None,
+            buffer_pool: None,
+            shutdown: None,
             _marker: PhantomData,
         }
     }
 
     pub(crate) fn dispatch(&self, request: Request<Bd>) -> AppFuture<Bd, E> {
         let (parts, body) = request.into_parts();
+        let mut context = Context::new(Request::from_parts(parts, ()));
+        context.connection = self.connection.clone();
+        context.buffer_pool = self.buffer_pool.clone();
+        context.shutdown = self.shutdown.clone();
+        let timer = self.request_timeout.map(|timeout| {
+            let deadline = tokio::clock::now() + timeout;
+            context.cancellation.set_deadline(deadline);
+            Delay::new(deadline)
+        });
         AppFuture {
             state: AppFutureState::Start(Some(self.endpoint.action())),
-            context: Context::new(Request::from_parts(parts, ())),
+            context,
             body: Some(body),
+            timer,
         }
     }
 }
@@ -123,12 +265,14 @@ pub struct AppFuture<Bd, E: Endpoint<Bd>> {
     state: AppFutureState<E::Action>,
     context: Context,
     body: Option<Bd>,
+    timer: Option<Delay>,
 }
 
 #[allow(missing_debug_implementations, clippy::large_enum_variant)]
 enum AppFutureState<A> {
     Start(Option<A>),
     InFlight(A),
+    Done,
 }
 
 impl<Bd, E> AppFuture<Bd, E>
@@ -136,6 +280,32 @@ where
     E: Endpoint<Bd>,
 {
     pub(crate) fn poll_apply(&mut self) -> Poll<E::Output, Error> {
+        let polled = self.poll_apply_inner();
+        match polled {
+            Ok(Async::NotReady) => {}
+            _ => self.state = AppFutureState::Done,
+        }
+        polled
+    }
+
+    fn poll_apply_inner(&mut self) -> Poll<E::Output, Error> {
+        if self.context.cancellation.is_cancelled() {
+            return Err(crate::error::err_msg(
+                "the request has been cancelled",
+                StatusCode::SERVICE_UNAVAILABLE,
+            ));
+        }
+
+        if let Some(ref mut timer) = self.timer {
+            if let Ok(Async::Ready(())) | Err(..) = timer.poll() {
+                self.context.cancellation.cancel();
+                return Err(crate::error::err_msg(
+                    "the request has timed out",
+                    StatusCode::SERVICE_UNAVAILABLE,
+                ));
+            }
+        }
+
         loop {
             self.state = match self.state {
                 AppFutureState::Start(ref mut action) => {
@@ -152,11 +322,31 @@ where
                         &mut self.body,
                     ));
                 }
+                AppFutureState::Done => {
+                    return Err(crate::error::err_msg(
+                        "the future has already completed",
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ));
+                }
             };
         }
     }
 }
 
+impl<Bd, E> Drop for AppFuture<Bd, E>
+where
+    E: Endpoint<Bd>,
+{
+    fn drop(&mut self) {
+        // The future is dropped before completing the response, which typically
+        // means that the client has disconnected.
+        if let AppFutureState::Done = self.state {
+            return;
+        }
+        self.context.cancellation.cancel();
+    }
+}
+
 impl<Bd, E> Future for AppFuture<Bd, E>
 where
     E: Endpoint<Bd>,
@@ -183,21 +373,112 @@ where
             }
         }
 
-        if let Some(mut hdrs) = self.context.response_headers.take() {
-            for (name, values) in hdrs.drain() {
-                response
-                    .headers_mut()
-                    .extend(values.map(|value| (name.clone(), value)));
-            }
+        self.context
+            .response_headers
+            .append_to(response.headers_mut());
+
+        let mut timings = self.context.timings.take();
+        if let Some(extra) = response.extensions_mut().remove::<ServerTimings>() {
+            timings.get_or_insert_with(Default::default).append(extra);
+        }
+        if let Some(value) = timings.and_then(|timings| timings.to_header_value()) {
+            response
+                .headers_mut()
+                .append(HeaderName::from_static("server-timing"), value);
         }
 
-        Ok(Async::Ready(response))
+        Ok(Async::Ready(CheckedBody::wrap(
+            &self.context.request,
+            response,
+        )))
+    }
+}
+
+// ==== Connection ====
+
+/// A trait representing the hooks called at the beginning and the end of
+/// connections.
+///
+/// The type parameter `Ctx` is the context value passed to `MakeService::make_service`
+/// by the server, which typically contains the information about the connection
+/// such as the remote address.
+///
+/// Each connection has its own typed extension map, which is initialized by
+/// `on_open` and can be accessed from the endpoints through
+/// `Context::connection_extensions` while processing the requests on that
+/// connection. The map is useful for storing connection-scoped values, such as
+/// the state of rate limiting and the information of client certificates.
+/// Note that the values shared between requests must provide the interior mutability
+/// by themselves since the map is immutable after opening the connection.
+///
+/// The `on_close` is called after the connection is closed and all of the
+/// in-flight requests on that connection have been completed.
+pub trait ConnectionHooks<Ctx> {
+    /// Called when a new connection is opened.
+    fn on_open(&self, ctx: &Ctx, extensions: &mut Extensions) {
+        let _ = (ctx, extensions);
+    }
+
+    /// Called when the connection is closed.
+    fn on_close(&self, extensions: &mut Extensions) {
+        let _ = extensions;
     }
 }
 
-pub type ResponseBody<Bd, E> = izanami_util::buf_stream::Either<
-    String, //
-    <<E as Endpoint<Bd>>::Output as IntoResponse>::Body,
+/// The default `ConnectionHooks` which does nothing.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NoHooks(());
+
+impl<Ctx> ConnectionHooks<Ctx> for NoHooks {}
+
+trait FnBox {
+    fn call_box(self: Box<Self>, extensions: &mut Extensions);
+}
+
+impl<F> FnBox for F
+where
+    F: FnOnce(&mut Extensions),
+{
+    fn call_box(self: Box<Self>, extensions: &mut Extensions) {
+        (*self)(extensions)
+    }
+}
+
+type OnClose = Box<dyn FnBox + Send + Sync + 'static>;
+
+fn on_close_hook<H>(hooks: Arc<H>, f: fn(&H, &mut Extensions)) -> OnClose
+where
+    H: Send + Sync + 'static,
+{
+    Box::new(move |extensions| f(&*hooks, extensions))
+}
+
+struct Connection {
+    extensions: Extensions,
+    on_close: Option<OnClose>,
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("extensions", &self.extensions)
+            .finish()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(on_close) = self.on_close.take() {
+            on_close.call_box(&mut self.extensions);
+        }
+    }
+}
+
+pub type ResponseBody<Bd, E> = CheckedBody<
+    izanami_util::buf_stream::Either<
+        String, //
+        <<E as Endpoint<Bd>>::Output as IntoResponse>::Body,
+    >,
 >;
 
 /// Encode a Cookie value into a `HeaderValue`
@@ -250,7 +531,14 @@ fn get_task_cx<R>(f: impl FnOnce(&mut Context) -> R) -> Option<R> {
 pub struct Context {
     request: Request<()>,
     cookies: Option<CookieJar>,
-    response_headers: Option<HeaderMap>,
+    response_headers: ResponseHeaders,
+    timings: Option<ServerTimings>,
+    connection: Option<Arc<Connection>>,
+    buffer_pool: Option<BufferPool>,
+    cancellation: CancellationToken,
+    shutdown: Option<CancellationToken>,
+    query_pairs: RefCell<Option<Vec<(String, String)>>>,
+    shared_path: RefCell<Option<Bytes>>,
 }
 
 impl Context {
@@ -258,7 +546,14 @@ impl Context {
         Context {
             request,
             cookies: None,
-            response_headers: None,
+            response_headers: ResponseHeaders::default(),
+            timings: None,
+            connection: None,
+            buffer_pool: None,
+            cancellation: CancellationToken::new(),
+            shutdown: None,
+            query_pairs: RefCell::new(None),
+            shared_path: RefCell::new(None),
         }
     }
 
@@ -288,10 +583,83 @@ impl Context {
     }
 
     /// Returns a mutable reference to the inner `Request<()>`.
+    ///
+    /// The values cached from the URI, such as `query_pairs`, are discarded
+    /// since the URI may be rewritten through the returned reference.
     pub fn request_mut(&mut self) -> &mut Request<()> {
+        self.query_pairs = RefCell::new(None);
+        self.shared_path = RefCell::new(None);
         &mut self.request
     }
 
+    /// Returns a reference to the extension map associated with the connection
+    /// on which the current request has been received.
+    ///
+    /// This method returns `None` if the service is not created through `MakeService`.
+    pub fn connection_extensions(&self) -> Option<&Extensions> {
+        self.connection.as_ref().map(|conn| &conn.extensions)
+    }
+
+    /// Returns a reference to the buffer pool attached to the `App`, if exists.
+    pub fn buffer_pool(&self) -> Option<&BufferPool> {
+        self.buffer_pool.as_ref()
+    }
+
+    /// Returns a reference to the token which notifies the cancellation of
+    /// the current request.
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Returns a reference to the token which is cancelled when the server
+    /// starts shutting down gracefully.
+    ///
+    /// The long-running endpoints, such as the ones waiting for events, can
+    /// observe it in order to complete early and let the connections drain.
+    /// This method returns `None` if the request is not handled by an `App`.
+    /// The token is cancelled only if the `App` is served by the server created
+    /// with `Server::from`.
+    pub fn shutdown(&self) -> Option<&CancellationToken> {
+        self.shutdown.as_ref()
+    }
+
+    /// Returns the raw query string of the request, if exists.
+    pub fn query_str(&self) -> Option<&str> {
+        self.request.uri().query()
+    }
+
+    /// Returns the decoded key-value pairs in the query string.
+    ///
+    /// The query string is parsed on the first call and the result is cached
+    /// during the request handling, so the endpoints and wrappers tried in
+    /// the `or` chain do not need to parse it again.
+    pub fn query_pairs(&self) -> Ref<'_, [(String, String)]> {
+        if self.query_pairs.borrow().is_none() {
+            let pairs = url::form_urlencoded::parse(self.query_str().unwrap_or("").as_bytes())
+                .into_owned()
+                .collect();
+            *self.query_pairs.borrow_mut() = Some(pairs);
+        }
+        Ref::map(self.query_pairs.borrow(), |pairs| {
+            &pairs.as_ref().expect("the query has been parsed")[..]
+        })
+    }
+
+    /// Returns the path of the request stored in a buffer shared during
+    /// the request handling.
+    ///
+    /// The buffer is allocated on the first call. The extractors such as
+    /// `syntax::param_str` return the slices of this buffer, instead of
+    /// allocating a `String` for each extracted value.
+    pub fn shared_path(&self) -> Ref<'_, Bytes> {
+        if self.shared_path.borrow().is_none() {
+            *self.shared_path.borrow_mut() = Some(Bytes::from(self.request.uri().path()));
+        }
+        Ref::map(self.shared_path.borrow(), |path| {
+            path.as_ref().expect("the path has been copied")
+        })
+    }
+
     /// Initializes the inner `CookieJar` and returns a mutable reference to its instance.
     pub fn cookies(&mut self) -> Result<&mut CookieJar, Error> {
         if let Some(ref mut cookies) = self.cookies {
@@ -312,8 +680,40 @@ impl Context {
     }
 
     /// Returns a mutable reference to a `HeaderMap` which contains the supplemental response headers.
+    ///
+    /// This method allocates a `HeaderMap` on the first call. Use
+    /// `append_response_header` or `insert_response_header` for adding
+    /// a few headers without allocating the map.
     pub fn response_headers(&mut self) -> &mut HeaderMap {
-        self.response_headers.get_or_insert_with(Default::default)
+        self.response_headers.as_map_mut()
+    }
+
+    /// Returns a reference to the supplemental response headers, or `None`
+    /// if no headers have been registered.
+    ///
+    /// Unlike `response_headers`, this method never allocates.
+    pub fn try_response_headers(&self) -> Option<&ResponseHeaders> {
+        if self.response_headers.is_empty() {
+            None
+        } else {
+            Some(&self.response_headers)
+        }
+    }
+
+    /// Appends a supplemental response header, keeping the existing values with the same name.
+    pub fn append_response_header(&mut self, name: HeaderName, value: HeaderValue) {
+        self.response_headers.append(name, value);
+    }
+
+    /// Inserts a supplemental response header, replacing the existing values with the same name.
+    pub fn insert_response_header(&mut self, name: HeaderName, value: HeaderValue) {
+        self.response_headers.insert(name, value);
+    }
+
+    /// Returns a mutable reference to the timing metrics, which are sent
+    /// as the `Server-Timing` response header.
+    pub fn timings(&mut self) -> &mut ServerTimings {
+        self.timings.get_or_insert_with(Default::default)
     }
 }
 
@@ -332,3 +732,191 @@ impl std::ops::DerefMut for Context {
         self.request_mut()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            action::{OneshotAction, PreflightContext},
+            endpoint::{self, EndpointExt},
+        },
+        std::sync::atomic::{AtomicUsize, Ordering},
+        tokio::runtime::current_thread::Runtime,
+    };
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct ConnId(usize);
+
+    struct Hooks {
+        closed: Arc<AtomicUsize>,
+    }
+
+    impl ConnectionHooks<usize> for Hooks {
+        fn on_open(&self, id: &usize, extensions: &mut Extensions) {
+            extensions.insert(ConnId(*id));
+        }
+
+        fn on_close(&self, extensions: &mut Extensions) {
+            assert!(extensions.get::<ConnId>().is_some());
+            self.closed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    struct ConnIdAction;
+
+    impl OneshotAction for ConnIdAction {
+        type Output = (Option<ConnId>,);
+
+        fn preflight(self, cx: &mut PreflightContext<'_>) -> Result<Self::Output, Error> {
+            Ok((cx
+                .connection_extensions()
+                .and_then(|ext| ext.get::<ConnId>().cloned()),))
+        }
+    }
+
+    #[test]
+    fn test_connection_hooks() {
+        let closed = Arc::new(AtomicUsize::new(0));
+        let app = endpoint::endpoint(|| ConnIdAction.into_action())
+            .map(|id: Option<ConnId>| format!("{:?}", id))
+            .into_service()
+            .with_connection_hooks(Hooks {
+                closed: closed.clone(),
+            });
+
+        let mut rt = Runtime::new().unwrap();
+        let mut service = rt.block_on(app.make_service(42)).unwrap();
+        for _ in 0..2 {
+            let future = service.call(Request::new(()));
+            let output = rt
+                .block_on(future::poll_fn({
+                    let mut future = future;
+                    move || future.poll_apply()
+                }))
+                .unwrap();
+            assert_eq!(output.0, "Some(ConnId(42))");
+        }
+        assert_eq!(closed.load(Ordering::SeqCst), 0);
+
+        drop(service);
+        assert_eq!(closed.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_cancel_on_drop() {
+        let app = endpoint::unit().map(|| "done").into_service();
+        let mut rt = Runtime::new().unwrap();
+        let mut service = rt.block_on(app.make_service(())).unwrap();
+
+        let future = service.call(Request::new(()));
+        let token = future.context.cancellation().clone();
+        drop(future);
+        assert!(token.is_cancelled());
+
+        let mut future = service.call(Request::new(()));
+        let token = future.context.cancellation().clone();
+        rt.block_on(future::poll_fn(|| future.poll_apply()))
+            .unwrap();
+        // polling the completed future again returns an error instead of panicking.
+        assert!(future.poll_apply().is_err());
+        drop(future);
+        assert!(!token.is_cancelled());
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        let app = endpoint::unit()
+            .and_then(future::empty::<&'static str, Error>)
+            .into_service()
+            .with_request_timeout(std::time::Duration::from_millis(10));
+        let mut rt = Runtime::new().unwrap();
+        let mut service = rt.block_on(app.make_service(())).unwrap();
+
+        let mut future = service.call(Request::new(()));
+        let err = rt
+            .block_on(future::poll_fn(|| future.poll_apply()))
+            .err()
+            .unwrap();
+        assert_eq!(err.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(future.context.cancellation().is_cancelled());
+    }
+
+    #[test]
+    fn test_query_pairs() {
+        let request = Request::get("/?a=1&b=hello+world&a=%32").body(()).unwrap();
+        let context = Context::new(request);
+        assert_eq!(context.query_str(), Some("a=1&b=hello+world&a=%32"));
+
+        let pairs = context.query_pairs();
+        assert_eq!(
+            &*pairs,
+            &[
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "hello world".to_string()),
+                ("a".to_string(), "2".to_string()),
+            ][..]
+        );
+        assert!(std::ptr::eq(&*pairs, &*context.query_pairs()));
+
+        let context = Context::new(Request::get("/").body(()).unwrap());
+        assert!(context.query_str().is_none());
+        assert!(context.query_pairs().is_empty());
+    }
+
+    #[test]
+    fn test_uri_caches_after_rewrite() {
+        let mut context = Context::new(Request::get("/foo?a=1").body(()).unwrap());
+        assert_eq!(&context.shared_path()[..], b"/foo");
+        assert_eq!(context.query_pairs().len(), 1);
+
+        *context.uri_mut() = "/bar?b=2&c=3".parse().unwrap();
+        assert_eq!(&context.shared_path()[..], b"/bar");
+        assert_eq!(context.query_pairs().len(), 2);
+    }
+
+    #[test]
+    fn test_try_response_headers() {
+        let mut context = Context::new(Request::new(()));
+        assert!(context.try_response_headers().is_none());
+
+        context.append_response_header(
+            http::header::VARY,
+            HeaderValue::from_static("accept-encoding"),
+        );
+        context.insert_response_header(
+            http::header::CACHE_CONTROL,
+            HeaderValue::from_static("no-cache"),
+        );
+        let headers = context.try_response_headers().unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers.get(&http::header::VARY).unwrap(), "accept-encoding");
+
+        context
+            .response_headers()
+            .append(http::header::VARY, HeaderValue::from_static("origin"));
+        assert_eq!(context.try_response_headers().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_buffer_pool() {
+        let pool = BufferPool::default();
+        let app = crate::endpoints::body::text()
+            .map(|body: String| body.len().to_string())
+            .into_service()
+            .with_buffer_pool(pool.clone());
+        let mut rt = Runtime::new().unwrap();
+        let mut service = rt.block_on(app.make_service(())).unwrap();
+
+        for _ in 0..3 {
+            let mut future = service.call(Request::post("/").body("Hello").unwrap());
+            let output = rt
+                .block_on(future::poll_fn(|| future.poll_apply()))
+                .unwrap();
+            assert_eq!(output.0, "5");
+        }
+        assert_eq!(pool.stats().misses, 1);
+        assert_eq!(pool.stats().hits, 2);
+        assert_eq!(pool.stats().idle, 1);
+    }
+}
@@ -0,0 +1,175 @@
+use {
+    futures::{task::Task, Async, Future, Poll},
+    std::{
+        fmt,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        time::Instant,
+    },
+    tokio::timer::Delay,
+};
+
+/// A token which notifies that the processing of a request should be stopped.
+///
+/// Each request has its own token, which can be obtained by
+/// `Context::cancellation`. The token is cancelled in the following cases:
+///
+/// * The future handling the request is dropped before it completes,
+///   for example when the client has disconnected.
+/// * The deadline set to the token has expired.
+/// * `cancel` is called explicitly, for example by a timeout wrapper.
+///
+/// Once the token is cancelled, the framework stops polling the endpoint
+/// and responds with `503 Service Unavailable`. The background tasks spawned
+/// by the endpoints can also observe the cancellation through a clone of
+/// the token.
+#[derive(Clone)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    cancelled: AtomicBool,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    deadline: Option<Instant>,
+    waiters: Vec<Task>,
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .field("deadline", &self.deadline())
+            .finish()
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        CancellationToken::new()
+    }
+}
+
+impl CancellationToken {
+    /// Creates a new `CancellationToken`.
+    pub fn new() -> Self {
+        CancellationToken {
+            inner: Arc::new(Inner {
+                cancelled: AtomicBool::new(false),
+                state: Mutex::new(State::default()),
+            }),
+        }
+    }
+
+    /// Cancels this token and wakes up all tasks waiting for the cancellation.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let mut state = self.inner.state.lock().unwrap();
+        for task in state.waiters.drain(..) {
+            task.notify();
+        }
+    }
+
+    /// Returns whether this token has been cancelled.
+    ///
+    /// This method also cancels the token if the deadline has expired.
+    pub fn is_cancelled(&self) -> bool {
+        if self.inner.cancelled.load(Ordering::SeqCst) {
+            return true;
+        }
+        let expired = self
+            .deadline()
+            .map(|deadline| deadline <= tokio::clock::now())
+            .unwrap_or(false);
+        if expired {
+            self.cancel();
+        }
+        expired
+    }
+
+    /// Returns the deadline of the request, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner.state.lock().unwrap().deadline
+    }
+
+    /// Sets the deadline of the request.
+    ///
+    /// If a deadline has already been set, the earlier one is kept.
+    /// The expiration is detected when `is_cancelled` is called, or by the timer
+    /// registered by the future returned from `cancelled`.
+    pub fn set_deadline(&self, deadline: Instant) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.deadline = Some(match state.deadline {
+            Some(current) if current < deadline => current,
+            _ => deadline,
+        });
+    }
+
+    /// Creates a future which will be resolved when this token is cancelled.
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled {
+            token: self.clone(),
+            timer: None,
+        }
+    }
+
+    fn poll_cancelled(&self) -> Async<()> {
+        if self.is_cancelled() {
+            return Async::Ready(());
+        }
+        let mut state = self.inner.state.lock().unwrap();
+        // re-check after acquiring the lock, to avoid missing the notification.
+        if self.inner.cancelled.load(Ordering::SeqCst) {
+            return Async::Ready(());
+        }
+        let task = futures::task::current();
+        if !state.waiters.iter().any(|t| t.will_notify_current()) {
+            state.waiters.push(task);
+        }
+        Async::NotReady
+    }
+}
+
+/// A future which will be resolved when the associated `CancellationToken`
+/// is cancelled.
+///
+/// This future must be polled within the context of a Tokio runtime with
+/// the timer, in order to detect the expiration of the deadline.
+#[derive(Debug)]
+pub struct Cancelled {
+    token: CancellationToken,
+    timer: Option<Delay>,
+}
+
+impl Future for Cancelled {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Async::Ready(()) = self.token.poll_cancelled() {
+            return Ok(Async::Ready(()));
+        }
+
+        if let Some(deadline) = self.token.deadline() {
+            match self.timer {
+                Some(ref mut timer) if timer.deadline() == deadline => {}
+                _ => self.timer = Some(Delay::new(deadline)),
+            }
+            let timer = self.timer.as_mut().unwrap();
+            if let Ok(Async::Ready(())) | Err(..) = timer.poll() {
+                self.token.cancel();
+                return Ok(Async::Ready(()));
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
@@ -0,0 +1,177 @@
+use {
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    smallvec::SmallVec,
+    std::mem,
+};
+
+/// The number of the headers stored without allocating a `HeaderMap`.
+const INLINE_CAPACITY: usize = 8;
+
+type Inline = SmallVec<[(HeaderName, HeaderValue); INLINE_CAPACITY]>;
+
+/// The supplemental response headers registered during handling a request.
+///
+/// A few headers are stored in an inline array, and a `HeaderMap` is
+/// allocated only when more headers are added or the map is requested by
+/// `Context::response_headers`.
+#[derive(Debug, Clone)]
+pub struct ResponseHeaders {
+    storage: Storage,
+}
+
+// The inline storage is intentionally kept unboxed.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+enum Storage {
+    Inline(Inline),
+    Map(HeaderMap),
+}
+
+impl Default for ResponseHeaders {
+    fn default() -> Self {
+        ResponseHeaders {
+            storage: Storage::Inline(SmallVec::new()),
+        }
+    }
+}
+
+impl ResponseHeaders {
+    /// Returns the number of the header values.
+    pub fn len(&self) -> usize {
+        match self.storage {
+            Storage::Inline(ref inline) => inline.len(),
+            Storage::Map(ref map) => map.len(),
+        }
+    }
+
+    /// Returns `true` if no headers have been registered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the header with the specified name has been registered.
+    pub fn contains_key(&self, name: &HeaderName) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Returns the first value of the header with the specified name.
+    pub fn get(&self, name: &HeaderName) -> Option<&HeaderValue> {
+        match self.storage {
+            Storage::Inline(ref inline) => inline
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, value)| value),
+            Storage::Map(ref map) => map.get(name),
+        }
+    }
+
+    /// Returns an iterator over the registered headers.
+    pub fn iter(&self) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+        let (inline, map) = match self.storage {
+            Storage::Inline(ref inline) => (Some(inline.iter().map(|(n, v)| (n, v))), None),
+            Storage::Map(ref map) => (None, Some(map.iter())),
+        };
+        inline
+            .into_iter()
+            .flatten()
+            .chain(map.into_iter().flatten())
+    }
+
+    /// Appends a header value, keeping the existing values with the same name.
+    pub fn append(&mut self, name: HeaderName, value: HeaderValue) {
+        match self.storage {
+            Storage::Inline(ref mut inline) if inline.len() < INLINE_CAPACITY => {
+                inline.push((name, value));
+            }
+            _ => {
+                self.as_map_mut().append(name, value);
+            }
+        }
+    }
+
+    /// Inserts a header value, replacing the existing values with the same name.
+    pub fn insert(&mut self, name: HeaderName, value: HeaderValue) {
+        if let Storage::Inline(ref mut inline) = self.storage {
+            inline.retain(|(n, _)| *n != name);
+        }
+        match self.storage {
+            Storage::Inline(ref mut inline) if inline.len() < INLINE_CAPACITY => {
+                inline.push((name, value));
+            }
+            _ => {
+                self.as_map_mut().insert(name, value);
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the headers as a `HeaderMap`,
+    /// moving the inline headers into a newly allocated map if necessary.
+    pub fn as_map_mut(&mut self) -> &mut HeaderMap {
+        if let Storage::Inline(ref mut inline) = self.storage {
+            let mut map = HeaderMap::with_capacity(inline.len());
+            for (name, value) in inline.drain() {
+                map.append(name, value);
+            }
+            self.storage = Storage::Map(map);
+        }
+        match self.storage {
+            Storage::Map(ref mut map) => map,
+            Storage::Inline(..) => unreachable!(),
+        }
+    }
+
+    /// Moves the headers into the specified map, without cloning the names.
+    pub(crate) fn append_to(&mut self, dst: &mut HeaderMap) {
+        match mem::replace(&mut self.storage, Storage::Inline(SmallVec::new())) {
+            Storage::Inline(inline) => {
+                for (name, value) in inline {
+                    dst.append(name, value);
+                }
+            }
+            Storage::Map(map) => {
+                let mut name = None;
+                for (n, value) in map {
+                    // The name is yielded only at the first value of each header.
+                    if n.is_some() {
+                        name = n;
+                    }
+                    dst.append(name.clone().expect("the first item has a name"), value);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, http::header};
+
+    #[test]
+    fn test_inline_and_spill() {
+        let mut headers = ResponseHeaders::default();
+        assert!(headers.is_empty());
+
+        headers.append(header::VARY, HeaderValue::from_static("accept"));
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers.get(&header::CACHE_CONTROL).unwrap(), "no-store");
+
+        for i in 0..10 {
+            headers.append(
+                HeaderName::from_static("x-item"),
+                HeaderValue::from_str(&i.to_string()).unwrap(),
+            );
+        }
+        assert_eq!(headers.len(), 13);
+        assert_eq!(headers.iter().count(), 13);
+
+        let mut dst = HeaderMap::new();
+        dst.insert(header::VARY, HeaderValue::from_static("cookie"));
+        headers.append_to(&mut dst);
+        assert!(headers.is_empty());
+        assert_eq!(dst.get_all(header::VARY).iter().count(), 3);
+        assert_eq!(dst.get_all("x-item").iter().count(), 10);
+    }
+}
@@ -0,0 +1,299 @@
+use {
+    serde::Deserialize,
+    std::{
+        fmt,
+        ops::{Deref, DerefMut},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    },
+};
+
+/// The configuration of `BufferPool`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BufferPoolConfig {
+    /// The capacities of the pooled buffers, in bytes.
+    ///
+    /// A buffer is taken from the smallest class which can hold the expected
+    /// size of the body, and is returned to the largest class which does not
+    /// exceed its capacity.
+    pub size_classes: Vec<usize>,
+
+    /// The maximum number of the idle buffers kept in each class.
+    pub max_buffers: usize,
+}
+
+impl Default for BufferPoolConfig {
+    fn default() -> Self {
+        BufferPoolConfig {
+            size_classes: vec![4 * 1024, 16 * 1024, 64 * 1024, 256 * 1024],
+            max_buffers: 64,
+        }
+    }
+}
+
+/// A pool of the buffers used for receiving the request bodies.
+///
+/// The body extractors in `endpoints::body` (e.g. `body::json()` and
+/// `body::text()`) take a buffer from the pool attached to the `App` by
+/// `App::with_buffer_pool`, and return it after parsing the body, so that
+/// the allocations are reused across requests instead of growing a fresh
+/// buffer for each request.
+///
+/// The buffers larger than twice the largest class are not returned to
+/// the pool, so that a few huge requests do not pin the memory.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    classes: Vec<SizeClass>,
+    max_buffers: usize,
+    stats: Stats,
+}
+
+struct SizeClass {
+    capacity: usize,
+    buffers: Mutex<Vec<Vec<u8>>>,
+}
+
+#[derive(Default)]
+struct Stats {
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    discarded: AtomicUsize,
+}
+
+/// The statistics of a `BufferPool`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BufferPoolStats {
+    /// The number of the buffers reused from the pool.
+    pub hits: usize,
+    /// The number of the buffers newly allocated.
+    pub misses: usize,
+    /// The number of the buffers dropped without being returned to the pool.
+    pub discarded: usize,
+    /// The number of the idle buffers currently kept in the pool.
+    pub idle: usize,
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field(
+                "size_classes",
+                &self
+                    .inner
+                    .classes
+                    .iter()
+                    .map(|class| class.capacity)
+                    .collect::<Vec<_>>(),
+            )
+            .field("max_buffers", &self.inner.max_buffers)
+            .finish()
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        BufferPool::new(&BufferPoolConfig::default())
+    }
+}
+
+impl BufferPool {
+    /// Creates a new `BufferPool` with the specified configuration.
+    pub fn new(config: &BufferPoolConfig) -> Self {
+        let mut capacities = config.size_classes.clone();
+        capacities.sort();
+        capacities.dedup();
+        capacities.retain(|&capacity| capacity > 0);
+        BufferPool {
+            inner: Arc::new(Inner {
+                classes: capacities
+                    .into_iter()
+                    .map(|capacity| SizeClass {
+                        capacity,
+                        buffers: Mutex::new(vec![]),
+                    })
+                    .collect(),
+                max_buffers: config.max_buffers,
+                stats: Stats::default(),
+            }),
+        }
+    }
+
+    /// Takes an empty buffer which can hold at least `size_hint` bytes
+    /// without reallocation if possible.
+    ///
+    /// The idle buffer in the smallest class which fits `size_hint` is reused,
+    /// and a new buffer with the capacity of that class is allocated if
+    /// no idle buffers are available.
+    pub fn get(&self, size_hint: usize) -> PooledBuf {
+        let mut classes = self
+            .inner
+            .classes
+            .iter()
+            .skip_while(|class| class.capacity < size_hint);
+        let class = classes.clone().next();
+        // The buffers grown while receiving are kept in the larger classes,
+        // so they are also searched when the smallest one is empty.
+        let buf = classes.find_map(|class| class.buffers.lock().unwrap().pop());
+        let buf = match buf {
+            Some(buf) => {
+                self.inner.stats.hits.fetch_add(1, Ordering::Relaxed);
+                buf
+            }
+            None => {
+                self.inner.stats.misses.fetch_add(1, Ordering::Relaxed);
+                let capacity = class.map_or(size_hint, |class| class.capacity);
+                Vec::with_capacity(capacity)
+            }
+        };
+        PooledBuf {
+            buf,
+            pool: Some(self.clone()),
+        }
+    }
+
+    /// Returns the statistics of this pool.
+    pub fn stats(&self) -> BufferPoolStats {
+        let stats = &self.inner.stats;
+        BufferPoolStats {
+            hits: stats.hits.load(Ordering::Relaxed),
+            misses: stats.misses.load(Ordering::Relaxed),
+            discarded: stats.discarded.load(Ordering::Relaxed),
+            idle: self
+                .inner
+                .classes
+                .iter()
+                .map(|class| class.buffers.lock().unwrap().len())
+                .sum(),
+        }
+    }
+
+    fn put(&self, mut buf: Vec<u8>) {
+        let capacity = buf.capacity();
+        let max_capacity = self.inner.classes.last().map_or(0, |class| class.capacity);
+        let class = if capacity <= max_capacity.saturating_mul(2) {
+            self.inner
+                .classes
+                .iter()
+                .rev()
+                .find(|class| class.capacity <= capacity)
+        } else {
+            None
+        };
+        if let Some(class) = class {
+            let mut buffers = class.buffers.lock().unwrap();
+            if buffers.len() < self.inner.max_buffers {
+                buf.clear();
+                buffers.push(buf);
+                return;
+            }
+        }
+        self.inner.stats.discarded.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A buffer taken from `BufferPool`, which is returned to the pool on drop.
+pub struct PooledBuf {
+    buf: Vec<u8>,
+    pool: Option<BufferPool>,
+}
+
+impl fmt::Debug for PooledBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledBuf")
+            .field("len", &self.buf.len())
+            .field("capacity", &self.buf.capacity())
+            .finish()
+    }
+}
+
+impl PooledBuf {
+    /// Creates a buffer which does not belong to any pool.
+    pub fn unpooled(size_hint: usize) -> Self {
+        PooledBuf {
+            buf: Vec::with_capacity(size_hint),
+            pool: None,
+        }
+    }
+
+    /// Detaches the buffer from the pool.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        self.pool = None;
+        std::mem::replace(&mut self.buf, Vec::new())
+    }
+}
+
+impl Deref for PooledBuf {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.put(std::mem::replace(&mut self.buf, Vec::new()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let pool = BufferPool::new(&BufferPoolConfig {
+            size_classes: vec![1024, 16],
+            max_buffers: 1,
+        });
+
+        let mut buf = pool.get(10);
+        assert_eq!(buf.capacity(), 16);
+        buf.extend_from_slice(b"hello");
+        drop(buf);
+
+        let buf = pool.get(0);
+        assert!(buf.is_empty());
+        assert_eq!(buf.capacity(), 16);
+        let other = pool.get(5);
+        drop((buf, other));
+        assert_eq!(
+            pool.stats(),
+            BufferPoolStats {
+                hits: 1,
+                misses: 2,
+                discarded: 1,
+                idle: 1,
+            }
+        );
+
+        // grown buffers are returned to the larger class.
+        let mut buf = pool.get(100);
+        buf.extend_from_slice(&[0; 1500]);
+        drop(buf);
+        assert!(pool.get(1000).capacity() >= 1500);
+
+        // huge buffers are discarded.
+        drop(pool.get(10_000));
+        assert_eq!(pool.stats().discarded, 2);
+
+        let vec = pool.get(1).into_vec();
+        assert_eq!(vec.capacity(), 16);
+        assert_eq!(pool.stats().idle, 1);
+    }
+}
@@ -0,0 +1,167 @@
+use {
+    crate::util::millis,
+    http::header::HeaderValue,
+    std::{borrow::Cow, fmt::Write, time::Duration},
+};
+
+/// A collection of the timing metrics of the backend, which is rendered
+/// into the `Server-Timing` response header.
+///
+/// Each request has its own instance, which can be obtained by
+/// `Context::timings`. The recorded entries are sent with the response
+/// after the endpoint has completed, so that the developer tools of browsers
+/// can show the breakdown of the time spent in the backend.
+///
+/// The endpoints which cannot access the `Context` mutably (e.g. the ones
+/// completed within `EndpointAction::preflight`) can also insert an instance
+/// into the extensions of the response, whose entries are appended to the
+/// ones recorded in the `Context`.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::service::Context;
+/// # use finchers::test;
+/// # use futures::future;
+/// # use std::time::Duration;
+/// let endpoint = endpoint::endpoint(|| {
+///     future::lazy(|| {
+///         Context::with(|cx| {
+///             cx.timings()
+///                 .record("db", Duration::from_millis(53))
+///                 .record_with_description("cache", Duration::from_micros(1500), "miss");
+///         });
+///         Ok::<_, finchers::error::Error>(("Hello",))
+///     })
+/// });
+///
+/// let mut runner = test::runner(endpoint);
+/// runner
+///     .perform("/")
+///     .unwrap()
+///     .assert_header("server-timing", r#"db;dur=53.000, cache;dur=1.500;desc="miss""#);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ServerTimings {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    name: Cow<'static, str>,
+    duration: Duration,
+    description: Option<Cow<'static, str>>,
+}
+
+impl ServerTimings {
+    /// Records the time spent in the specified phase.
+    ///
+    /// The name must be a valid token (e.g. `db` or `render-template`),
+    /// otherwise the whole header is discarded.
+    pub fn record(&mut self, name: impl Into<Cow<'static, str>>, duration: Duration) -> &mut Self {
+        self.entries.push(Entry {
+            name: name.into(),
+            duration,
+            description: None,
+        });
+        self
+    }
+
+    /// Records the time spent in the specified phase, with a human-readable description.
+    pub fn record_with_description(
+        &mut self,
+        name: impl Into<Cow<'static, str>>,
+        duration: Duration,
+        description: impl Into<Cow<'static, str>>,
+    ) -> &mut Self {
+        self.entries.push(Entry {
+            name: name.into(),
+            duration,
+            description: Some(description.into()),
+        });
+        self
+    }
+
+    /// Returns `true` if no entries have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns an iterator over the names and durations of the recorded entries.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Duration)> {
+        self.entries
+            .iter()
+            .map(|entry| (&*entry.name, entry.duration))
+    }
+
+    pub(crate) fn append(&mut self, other: ServerTimings) {
+        self.entries.extend(other.entries);
+    }
+
+    pub(crate) fn to_header_value(&self) -> Option<HeaderValue> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        if let Some(entry) = self.entries.iter().find(|entry| !is_token(&entry.name)) {
+            log::warn!("invalid metric name in Server-Timing: {:?}", entry.name);
+            return None;
+        }
+        let mut value = String::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                value.push_str(", ");
+            }
+            let _ = write!(value, "{};dur={:.3}", entry.name, millis(entry.duration));
+            if let Some(ref description) = entry.description {
+                value.push_str(";desc=\"");
+                for c in description.chars() {
+                    if c == '"' || c == '\\' {
+                        value.push('\\');
+                    }
+                    value.push(c);
+                }
+                value.push('"');
+            }
+        }
+        HeaderValue::from_shared(value.into()).ok()
+    }
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes().all(|b| match b {
+            b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_'
+            | b'`' | b'|' | b'~' => true,
+            b => b.is_ascii_alphanumeric(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_header_value() {
+        let mut timings = ServerTimings::default();
+        assert!(timings.to_header_value().is_none());
+
+        timings
+            .record("db", Duration::from_millis(12))
+            .record_with_description("app", Duration::from_micros(250), r#"say "hi""#);
+        assert_eq!(
+            timings.to_header_value().unwrap(),
+            r#"db;dur=12.000, app;dur=0.250;desc="say \"hi\"""#
+        );
+        assert_eq!(
+            timings.iter().collect::<Vec<_>>(),
+            vec![
+                ("db", Duration::from_millis(12)),
+                ("app", Duration::from_micros(250))
+            ]
+        );
+
+        timings.record("not a token", Duration::from_millis(1));
+        assert!(timings.to_header_value().is_none());
+    }
+}
@@ -19,20 +19,47 @@
 
 use {
     crate::{
-        endpoint::Endpoint,
+        endpoint::{Endpoint, StateOverrides},
         error::Error,
+        output::IntoResponse,
         service::{AppFuture, AppService},
     },
-    bytes::Bytes,
-    futures::{future, Poll},
+    bytes::{Buf, Bytes, BytesMut},
+    futures::{future, Async, Poll},
     http::{
         header::{self, HeaderMap, HeaderName, HeaderValue},
-        Request, Uri,
+        Request, Response, StatusCode, Uri,
     },
-    izanami_util::buf_stream::BufStream,
+    izanami_util::{buf_stream::BufStream, http::Upgrade},
     mime::Mime,
-    std::io,
-    tokio::runtime::current_thread::Runtime,
+    serde::de::DeserializeOwned,
+    std::{borrow::Cow, io, str, time::Duration},
+    tokio::{
+        runtime::current_thread::{Builder as RuntimeBuilder, Runtime},
+        timer::Delay,
+    },
+};
+
+pub mod arbitrary;
+
+mod clock;
+mod contract;
+mod duplex;
+mod fuzz;
+mod replay;
+mod request;
+mod server;
+mod session;
+
+pub use self::{
+    clock::MockClock,
+    contract::{contract, Contract},
+    duplex::{duplex, DuplexStream},
+    fuzz::{fuzz_corpus, fuzz_paths},
+    replay::{replay, Exchange, Recording, Replay, ReplayReport},
+    request::{request, RequestBuilder},
+    server::{serve, ServerReqBody, TestServer},
+    session::TestSession,
 };
 
 // ====
@@ -44,6 +71,15 @@ fn or_insert(headers: &mut HeaderMap, name: HeaderName, value: &'static str) {
         .or_insert_with(|| HeaderValue::from_static(value));
 }
 
+fn set_default_headers(headers: &mut HeaderMap) {
+    or_insert(headers, header::HOST, "localhost");
+    or_insert(
+        headers,
+        header::USER_AGENT,
+        concat!("finchers/", env!("CARGO_PKG_VERSION")),
+    );
+}
+
 /// A trait representing the conversion into an HTTP request.
 ///
 /// This trait is internally used by the test runner.
@@ -56,9 +92,24 @@ pub trait IntoReqBody: self::imp::IntoReqBodyImpl {}
 
 // ==== ReqBody ====
 
-#[allow(missing_docs)]
+/// The type of request body used in the test runner.
+///
+/// This type also implements `Upgrade`, and the upgraded I/O is connected
+/// to the stream returned from `TestResult::take_upgraded`.
 #[derive(Debug)]
-pub struct ReqBody(Option<Bytes>);
+pub struct ReqBody {
+    data: Option<Bytes>,
+    upgrade: Option<DuplexStream>,
+}
+
+impl ReqBody {
+    pub(crate) fn new(data: impl Into<Bytes>) -> Self {
+        ReqBody {
+            data: Some(data.into()),
+            upgrade: None,
+        }
+    }
+}
 
 impl BufStream for ReqBody {
     type Item = io::Cursor<Bytes>;
@@ -66,7 +117,21 @@ impl BufStream for ReqBody {
 
     #[inline]
     fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        Ok(self.0.take().map(io::Cursor::new).into())
+        Ok(self.data.take().map(io::Cursor::new).into())
+    }
+}
+
+impl Upgrade for ReqBody {
+    type Upgraded = DuplexStream;
+    type Error = io::Error;
+
+    fn poll_upgrade(&mut self) -> Poll<Self::Upgraded, Self::Error> {
+        self.upgrade.take().map(Async::Ready).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "the request is not upgradable or has already been upgraded",
+            )
+        })
     }
 }
 
@@ -82,11 +147,17 @@ where
 ///
 /// It uses internally the current thread version of Tokio runtime for executing
 /// asynchronous processes.
+///
+/// The runtime created by `TestRunner::new` is driven by a `MockClock`, so the
+/// timers used in endpoints (timeouts, rate limiters, cache expirations and so on)
+/// can be tested without sleeping by using `TestRunner::advance`.
 #[derive(Debug)]
 pub struct TestRunner<E> {
     endpoint: E,
     rt: Runtime,
+    clock: MockClock,
     default_headers: Option<HeaderMap>,
+    state_overrides: StateOverrides,
 }
 
 #[allow(clippy::new_ret_no_self)]
@@ -96,15 +167,28 @@ where
 {
     /// Create a `TestRunner` from the specified endpoint.
     pub fn new(endpoint: E) -> io::Result<TestRunner<E>> {
-        Runtime::new().map(|rt| TestRunner::with_runtime(endpoint, rt))
+        let clock = MockClock::new();
+        let rt = RuntimeBuilder::new().clock(clock.to_clock()).build()?;
+        Ok(TestRunner {
+            endpoint,
+            rt,
+            clock,
+            default_headers: None,
+            state_overrides: StateOverrides::default(),
+        })
     }
 
     /// Create a `TestRunner` from the specified endpoint with a Tokio runtime.
+    ///
+    /// Note that the clock of the given runtime is not replaced, and hence
+    /// `TestRunner::advance` has no effect on the timers registered with it.
     pub fn with_runtime(endpoint: E, rt: Runtime) -> TestRunner<E> {
         TestRunner {
             endpoint,
             rt,
+            clock: MockClock::new(),
             default_headers: None,
+            state_overrides: StateOverrides::default(),
         }
     }
 
@@ -114,6 +198,69 @@ where
         self.default_headers.get_or_insert_with(Default::default)
     }
 
+    /// Replaces the state of type `S` extracted by `endpoint::state` with the
+    /// specified value, in all subsequent requests applied by this runner.
+    ///
+    /// The overridden state takes precedence over the values attached by
+    /// `Scope::with_state`, so the handlers depending on the external resources
+    /// (e.g. database pools or HTTP clients) can be tested against the fakes
+    /// without rebuilding the endpoint.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use finchers::prelude::*;
+    /// # use finchers::endpoint::syntax;
+    /// # use finchers::test;
+    /// # use std::sync::Arc;
+    /// trait UserRepository: Send + Sync {
+    ///     fn name(&self, id: u32) -> Option<String>;
+    /// }
+    ///
+    /// #[derive(Clone)]
+    /// struct Repo(Arc<dyn UserRepository>);
+    ///
+    /// struct InMemory;
+    /// impl UserRepository for InMemory {
+    ///     fn name(&self, id: u32) -> Option<String> {
+    ///         if id == 1 { Some("alice".into()) } else { None }
+    ///     }
+    /// }
+    ///
+    /// # struct Database;
+    /// # impl UserRepository for Database {
+    /// #     fn name(&self, _: u32) -> Option<String> { None }
+    /// # }
+    /// let users = syntax::param::<u32>()
+    ///     .and(endpoint::state::<Repo>())
+    ///     .and_then(|id: u32, repo: Repo| {
+    ///         repo.0.name(id).ok_or_else(|| finchers::error::not_found("no such user"))
+    ///     });
+    /// let endpoint = endpoint::scope("/users", users).with_state(Repo(Arc::new(Database)));
+    ///
+    /// let mut runner = test::runner(endpoint);
+    /// runner.override_state(Repo(Arc::new(InMemory)));
+    /// assert_eq!(runner.apply("/users/1").unwrap(), "alice");
+    /// assert!(runner.apply("/users/2").is_err());
+    /// ```
+    pub fn override_state<S>(&mut self, state: S) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.state_overrides.insert(state);
+        self
+    }
+
+    /// Removes the override of the state of type `S` registered by `override_state`.
+    ///
+    /// Returns `true` if the state had been overridden.
+    pub fn restore_state<S>(&mut self) -> bool
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.state_overrides.remove::<S>()
+    }
+
     /// Returns a reference to the instance of `Endpoint` owned by this runner.
     pub fn endpoint(&mut self) -> &mut E {
         &mut self.endpoint
@@ -124,6 +271,24 @@ where
         &mut self.rt
     }
 
+    /// Returns a reference to the clock which drives the runtime.
+    ///
+    /// The returned handle can be cloned and moved into a spawned task in order
+    /// to advance the time while a request is in flight.
+    pub fn clock(&self) -> &MockClock {
+        &self.clock
+    }
+
+    /// Moves the clock of the runtime forward by the specified duration,
+    /// and fires the timers expired by this change.
+    pub fn advance(&mut self, duration: Duration) {
+        self.clock.advance(duration);
+        let deadline = self.clock.now();
+        self.rt
+            .block_on(Delay::new(deadline))
+            .expect("failed to drive the timer");
+    }
+
     fn prepare_request(&self, request: impl TestRequest) -> http::Result<Request<ReqBody>> {
         let mut request = request.into_request()?;
 
@@ -133,24 +298,21 @@ where
             }
         }
 
-        or_insert(request.headers_mut(), header::HOST, "localhost");
-        or_insert(
-            request.headers_mut(),
-            header::USER_AGENT,
-            concat!("finchers/", env!("CARGO_PKG_VERSION")),
-        );
+        set_default_headers(request.headers_mut());
+
+        if !self.state_overrides.is_empty() {
+            request
+                .extensions_mut()
+                .insert(self.state_overrides.clone());
+        }
 
         Ok(request)
     }
 
-    fn apply_inner<F, R>(&mut self, request: impl TestRequest, f: F) -> R
+    fn apply_inner<F, R>(&mut self, request: Request<ReqBody>, f: F) -> R
     where
         F: FnOnce(AppFuture<ReqBody, &E>, &mut Runtime) -> R,
     {
-        let request = self
-            .prepare_request(request)
-            .expect("failed to construct a request");
-
         let future = AppService::new(&self.endpoint).dispatch(request);
 
         f(future, &mut self.rt)
@@ -171,10 +333,231 @@ where
     /// Applies the given request to the inner endpoint and retrieves the result of returned future
     /// *without peeling tuples*.
     pub fn apply_raw(&mut self, request: impl TestRequest) -> Result<E::Output, Error> {
+        let request = self
+            .prepare_request(request)
+            .expect("failed to construct a request");
         self.apply_inner(request, |mut future, rt| {
             rt.block_on(future::poll_fn(|| future.poll_apply()))
         })
     }
+
+    /// Applies the given request to the inner endpoint and converts the result
+    /// into an HTTP response, as the server does.
+    ///
+    /// The whole of response body is received before returning.
+    ///
+    /// If the endpoint switches the protocol, the stream connected to the
+    /// upgraded I/O can be obtained by `TestResult::take_upgraded`.
+    pub fn perform(&mut self, request: impl TestRequest) -> io::Result<TestResult>
+    where
+        E::Output: IntoResponse,
+        <E::Output as IntoResponse>::Body: BufStream,
+        <<E::Output as IntoResponse>::Body as BufStream>::Error:
+            Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        let mut request = self
+            .prepare_request(request)
+            .expect("failed to construct a request");
+        let (client_io, server_io) = duplex();
+        request.body_mut().upgrade = Some(server_io);
+
+        self.apply_inner(request, |future, rt| {
+            let response = rt.block_on(future)?;
+            let (parts, mut body) = response.into_parts();
+            let mut buf = BytesMut::new();
+            rt.block_on(future::poll_fn(|| -> Poll<(), io::Error> {
+                while let Some(mut data) = futures::try_ready!(body
+                    .poll_buf()
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e)))
+                {
+                    while data.has_remaining() {
+                        let n = {
+                            let chunk = data.bytes();
+                            buf.extend_from_slice(chunk);
+                            chunk.len()
+                        };
+                        data.advance(n);
+                    }
+                }
+                Ok(().into())
+            }))?;
+            let upgraded = if parts.status == StatusCode::SWITCHING_PROTOCOLS {
+                Some(client_io)
+            } else {
+                None
+            };
+            Ok(TestResult {
+                response: Response::from_parts(parts, buf.freeze()),
+                upgraded,
+            })
+        })
+    }
+}
+
+// ==== TestResult ====
+
+/// A type representing the result of `TestRunner::perform`.
+///
+/// The assertion methods panic with the detailed message, including the
+/// dump of response, if the condition is not satisfied.
+#[derive(Debug)]
+pub struct TestResult {
+    response: Response<Bytes>,
+    upgraded: Option<DuplexStream>,
+}
+
+impl TestResult {
+    /// Takes the stream connected to the upgraded I/O, if the protocol has been switched.
+    ///
+    /// The task spawned by the endpoint for handling the upgraded I/O runs on the
+    /// runtime of `TestRunner`, so the operations on the returned stream should be
+    /// driven by using `TestRunner::runtime`.
+    pub fn take_upgraded(&mut self) -> Option<DuplexStream> {
+        self.upgraded.take()
+    }
+
+    /// Returns a reference to the inner `Response`.
+    pub fn response(&self) -> &Response<Bytes> {
+        &self.response
+    }
+
+    /// Consumes itself and returns the inner `Response`.
+    pub fn into_response(self) -> Response<Bytes> {
+        self.response
+    }
+
+    /// Returns the status code of the response.
+    pub fn status(&self) -> StatusCode {
+        self.response.status()
+    }
+
+    /// Returns a reference to the header map of the response.
+    pub fn headers(&self) -> &HeaderMap {
+        self.response.headers()
+    }
+
+    /// Returns a reference to the response body.
+    pub fn body(&self) -> &Bytes {
+        self.response.body()
+    }
+
+    /// Returns the response body as a UTF-8 string.
+    pub fn to_utf8(&self) -> Result<Cow<'_, str>, str::Utf8Error> {
+        str::from_utf8(self.body()).map(Cow::Borrowed)
+    }
+
+    /// Returns the response body as a string, replacing invalid UTF-8 sequences.
+    pub fn to_utf8_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.body())
+    }
+
+    /// Deserializes the response body as a JSON value.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if the response body is not a valid JSON
+    /// representation of `T`.
+    pub fn json<T>(&self) -> T
+    where
+        T: DeserializeOwned,
+    {
+        match serde_json::from_slice(self.body()) {
+            Ok(value) => value,
+            Err(err) => self.fail(&format!("failed to deserialize the response body: {}", err)),
+        }
+    }
+
+    /// Asserts that the status code of the response is equal to `expected`.
+    pub fn assert_status(&self, expected: u16) -> &Self {
+        if self.status().as_u16() != expected {
+            self.fail(&format!(
+                "status code mismatch\n- {}\n+ {}",
+                expected,
+                self.status().as_u16()
+            ));
+        }
+        self
+    }
+
+    /// Asserts that the response contains the header field with the specified value.
+    pub fn assert_header(&self, name: &str, expected: &str) -> &Self {
+        let values: Vec<_> = self.headers().get_all(name).iter().collect();
+        if !values.iter().any(|value| *value == expected) {
+            let mut msg = format!("header `{}` mismatch\n- {}", name, expected);
+            if values.is_empty() {
+                msg += "\n+ <missing>";
+            }
+            for value in values {
+                msg += &format!("\n+ {}", String::from_utf8_lossy(value.as_bytes()));
+            }
+            self.fail(&msg);
+        }
+        self
+    }
+
+    /// Asserts that the response does not contain the specified header field.
+    pub fn assert_no_header(&self, name: &str) -> &Self {
+        if self.headers().contains_key(name) {
+            self.fail(&format!("unexpected header `{}`", name));
+        }
+        self
+    }
+
+    /// Asserts that the response body is equal to `expected`.
+    pub fn assert_body(&self, expected: impl AsRef<[u8]>) -> &Self {
+        let expected = expected.as_ref();
+        if self.body() != expected {
+            let expected = String::from_utf8_lossy(expected);
+            let actual = self.to_utf8_lossy();
+            self.fail(&format!("body mismatch\n{}", line_diff(&expected, &actual)));
+        }
+        self
+    }
+
+    fn fail(&self, msg: &str) -> ! {
+        panic!("{}\n\n{}", msg, self.dump())
+    }
+
+    fn dump(&self) -> String {
+        let mut dump = format!(
+            "response:\n  {:?} {}\n",
+            self.response.version(),
+            self.status()
+        );
+        for (name, value) in self.headers() {
+            dump += &format!(
+                "  {}: {}\n",
+                name,
+                String::from_utf8_lossy(value.as_bytes())
+            );
+        }
+        dump += "\n";
+        for line in self.to_utf8_lossy().lines() {
+            dump += &format!("  {}\n", line);
+        }
+        dump
+    }
+}
+
+/// Creates a line-by-line diff between two strings.
+fn line_diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<_> = expected.lines().collect();
+    let actual: Vec<_> = actual.lines().collect();
+    let mut diff = String::new();
+    for i in 0..std::cmp::max(expected.len(), actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if e == a => diff += &format!("  {}\n", e),
+            (e, a) => {
+                if let Some(e) = e {
+                    diff += &format!("- {}\n", e);
+                }
+                if let Some(a) = a {
+                    diff += &format!("+ {}\n", a);
+                }
+            }
+        }
+    }
+    diff
 }
 
 mod imp {
@@ -210,7 +593,7 @@ mod imp {
         fn into_request(self) -> http::Result<Request<ReqBody>> {
             let path = self.path_and_query().map(|s| s.as_str()).unwrap_or("/");
             let mut request = Request::get(path) //
-                .body(ReqBody(Some(Bytes::new())))?;
+                .body(ReqBody::new(Bytes::new()))?;
 
             if let Some(authority) = self.authority_part() {
                 request
@@ -247,14 +630,14 @@ mod imp {
     impl TestRequest for http::request::Builder {}
     impl TestRequestImpl for http::request::Builder {
         fn into_request(mut self) -> http::Result<Request<ReqBody>> {
-            self.body(ReqBody(Some(Bytes::new())))
+            self.body(ReqBody::new(Bytes::new()))
         }
     }
 
     impl<'a> TestRequest for &'a mut http::request::Builder {}
     impl<'a> TestRequestImpl for &'a mut http::request::Builder {
         fn into_request(self) -> http::Result<Request<ReqBody>> {
-            self.body(ReqBody(Some(Bytes::new())))
+            self.body(ReqBody::new(Bytes::new()))
         }
     }
 
@@ -283,24 +666,31 @@ mod imp {
         fn into_req_body(self) -> ReqBody;
     }
 
+    impl IntoReqBody for ReqBody {}
+    impl IntoReqBodyImpl for ReqBody {
+        fn into_req_body(self) -> ReqBody {
+            self
+        }
+    }
+
     impl IntoReqBody for () {}
     impl IntoReqBodyImpl for () {
         fn into_req_body(self) -> ReqBody {
-            ReqBody(Some(Bytes::new()))
+            ReqBody::new(Bytes::new())
         }
     }
 
     impl<'a> IntoReqBody for &'a [u8] {}
     impl<'a> IntoReqBodyImpl for &'a [u8] {
         fn into_req_body(self) -> ReqBody {
-            ReqBody(Some(Bytes::from(self)))
+            ReqBody::new(self)
         }
     }
 
     impl IntoReqBody for Vec<u8> {}
     impl<'a> IntoReqBodyImpl for Vec<u8> {
         fn into_req_body(self) -> ReqBody {
-            ReqBody(Some(Bytes::from(self)))
+            ReqBody::new(self)
         }
     }
 
@@ -311,7 +701,7 @@ mod imp {
         }
 
         fn into_req_body(self) -> ReqBody {
-            ReqBody(Some(Bytes::from(self)))
+            ReqBody::new(self)
         }
     }
 
@@ -322,7 +712,7 @@ mod imp {
         }
 
         fn into_req_body(self) -> ReqBody {
-            ReqBody(Some(Bytes::from(self)))
+            ReqBody::new(self)
         }
     }
 }
@@ -331,7 +721,7 @@ mod imp {
 mod tests {
     use super::*;
 
-    use crate::endpoint;
+    use crate::endpoint::{self, EndpointExt};
     use matches::assert_matches;
 
     #[test]
@@ -429,4 +819,110 @@ mod tests {
 
         assert!(runner.apply_raw("/").is_ok());
     }
+
+    #[test]
+    fn test_perform() {
+        let mut runner = runner(endpoint::unit().map(|| crate::output::Json(vec![1, 2, 3])));
+
+        let result = runner.perform("/").unwrap();
+        result
+            .assert_status(200)
+            .assert_header("content-type", "application/json")
+            .assert_body("[1,2,3]");
+        assert_eq!(result.json::<Vec<u32>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "status code mismatch")]
+    fn test_assert_status_failure() {
+        let mut runner = runner(endpoint::unit().map(|| "Hello"));
+        runner.perform("/").unwrap().assert_status(404);
+    }
+
+    #[test]
+    fn test_upgrade() {
+        use {
+            crate::action::{ActionContext, EndpointAction},
+            futures::Future,
+            tokio::io as tokio_io,
+        };
+
+        struct EchoAction;
+
+        impl EndpointAction<ReqBody> for EchoAction {
+            type Output = (Response<&'static str>,);
+
+            fn poll_action(
+                &mut self,
+                cx: &mut ActionContext<'_, ReqBody>,
+            ) -> Poll<Self::Output, Error> {
+                let task = cx
+                    .take_body()?
+                    .on_upgrade()
+                    .and_then(|io| tokio_io::read_exact(io, [0u8; 5]))
+                    .and_then(|(io, buf)| tokio_io::write_all(io, buf))
+                    .map(drop)
+                    .map_err(|e| panic!("{}", e));
+                tokio::executor::current_thread::spawn(task);
+
+                let response = Response::builder()
+                    .status(StatusCode::SWITCHING_PROTOCOLS)
+                    .body("")
+                    .unwrap();
+                Ok((response,).into())
+            }
+        }
+
+        let mut runner = runner(endpoint::endpoint(|| EchoAction));
+        let mut result = runner.perform("/").unwrap();
+        result.assert_status(101);
+
+        let io = result.take_upgraded().expect("should be upgraded");
+        let (_io, buf) = runner
+            .runtime()
+            .block_on(
+                tokio_io::write_all(io, b"hello")
+                    .and_then(|(io, _)| tokio_io::read_exact(io, [0u8; 5])),
+            )
+            .unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn test_advance_clock() {
+        use {
+            futures::Future,
+            std::time::{Duration, Instant},
+            tokio::timer::Delay,
+        };
+
+        let mut runner = runner(endpoint::unit().and_then(|| {
+            Delay::new(tokio::clock::now() + Duration::from_secs(60))
+                .map(|()| "elapsed")
+                .map_err(|e| crate::error::fail(e, StatusCode::INTERNAL_SERVER_ERROR))
+        }));
+
+        let clock = runner.clock().clone();
+        runner.runtime().spawn(future::lazy(move || {
+            clock.advance(Duration::from_secs(60));
+            Ok(())
+        }));
+
+        let start = Instant::now();
+        assert_matches!(runner.apply("/"), Ok("elapsed"));
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        let before = runner.clock().now();
+        runner.advance(Duration::from_secs(3600));
+        let now = runner
+            .runtime()
+            .block_on(future::lazy(|| Ok::<_, ()>(tokio::clock::now())))
+            .unwrap();
+        assert!(now >= before + Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_line_diff() {
+        assert_eq!(line_diff("a\nb", "a\nc"), "  a\n- b\n+ c\n");
+    }
 }
@@ -0,0 +1,610 @@
+//! Deterministic generators of the fake inputs for property-based testing.
+//!
+//! The values are generated from a seedable `Rng`, so a failing input can
+//! always be reproduced from the seed reported by `check`. The generated
+//! values are biased toward the edge cases (e.g. zero, the bounds of integer
+//! types, empty strings and the characters which need percent-encoding).
+//!
+//! # Example
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::endpoint::syntax::path;
+//! # use finchers::endpoints::query;
+//! # use finchers::test::{self, arbitrary::{self, Arbitrary, Rng}};
+//! # use serde::{Deserialize, Serialize};
+//! #[derive(Debug, Serialize, Deserialize)]
+//! struct Paging {
+//!     offset: u32,
+//!     limit: Option<u8>,
+//! }
+//!
+//! impl Arbitrary for Paging {
+//!     fn arbitrary(rng: &mut Rng) -> Self {
+//!         Paging {
+//!             offset: rng.gen(),
+//!             limit: rng.gen(),
+//!         }
+//!     }
+//! }
+//!
+//! let endpoint = path!(@get "/users/<String>")
+//!     .and(query::required::<Paging>())
+//!     .map(|name: String, paging: Paging| format!("{}:{}", name, paging.offset));
+//! let mut runner = test::runner(endpoint);
+//!
+//! arbitrary::check(
+//!     100,
+//!     // the path parameter must not be empty.
+//!     |rng| (rng.gen::<char>().to_string() + &rng.gen::<String>(), rng.gen::<Paging>()),
+//!     |(name, paging)| {
+//!         let uri = format!(
+//!             "/users/{}?{}",
+//!             arbitrary::segment(&name),
+//!             arbitrary::query(&paging),
+//!         );
+//!         let output = runner.apply(uri).unwrap();
+//!         assert_eq!(output, format!("{}:{}", name, paging.offset));
+//!     },
+//! );
+//! ```
+
+use {
+    percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET},
+    serde::Serialize,
+    serde_json::{Map, Value},
+    std::{
+        collections::{BTreeMap, HashMap},
+        env,
+        fmt::{self, Display},
+        hash::Hash,
+        ops::Range,
+        panic::{self, AssertUnwindSafe},
+    },
+};
+
+/// The seed used by `check` if `FINCHERS_TEST_SEED` is not set.
+pub const DEFAULT_SEED: u64 = 0x5eed_f1c4_e125;
+
+const SPECIAL_CHARS: &[char] = &[
+    ' ', '/', '%', '?', '#', '&', '=', '+', '.', '"', '\'', '<', '\\', 'é', 'あ', '😀',
+];
+
+/// A deterministic pseudo random number generator (SplitMix64).
+///
+/// This generator is not cryptographically secure and must be used only for testing.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+    size: usize,
+}
+
+impl Rng {
+    /// Creates a generator with the specified seed.
+    pub fn new(seed: u64) -> Rng {
+        Rng {
+            state: seed,
+            size: 8,
+        }
+    }
+
+    /// Sets the maximum length of the generated collections and strings.
+    ///
+    /// The default value is `8`.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Returns the maximum length of the generated collections and strings.
+    pub fn max_size(&self) -> usize {
+        self.size
+    }
+
+    /// Creates an independent generator derived from this one, e.g. for each test case.
+    pub fn fork(&mut self) -> Rng {
+        Rng {
+            state: self.next_u64(),
+            size: self.size,
+        }
+    }
+
+    /// Returns the next random `u64`.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Generates a random value of `T`.
+    pub fn gen<T: Arbitrary>(&mut self) -> T {
+        T::arbitrary(self)
+    }
+
+    /// Returns a random integer within the range.
+    ///
+    /// # Panics
+    /// This method panics if the range is empty.
+    pub fn gen_range(&mut self, range: Range<i64>) -> i64 {
+        assert!(range.start < range.end, "empty range");
+        let span = range.end.wrapping_sub(range.start) as u64;
+        range.start.wrapping_add((self.next_u64() % span) as i64)
+    }
+
+    /// Returns a random float in `[0, 1)`.
+    pub fn gen_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns `true` with the specified probability.
+    pub fn gen_bool(&mut self, p: f64) -> bool {
+        self.gen_f64() < p
+    }
+
+    /// Returns a random length of collections, up to the maximum size.
+    pub fn gen_len(&mut self) -> usize {
+        self.gen_range(0..self.size as i64 + 1) as usize
+    }
+
+    /// Chooses an element from the slice at random.
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            None
+        } else {
+            Some(&items[self.gen_range(0..items.len() as i64) as usize])
+        }
+    }
+
+    /// Generates a random alphanumeric string with the specified length.
+    pub fn alphanumeric(&mut self, len: usize) -> String {
+        const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        (0..len)
+            .map(|_| *self.choose(CHARS).unwrap() as char)
+            .collect()
+    }
+}
+
+// ==== Arbitrary ====
+
+/// A trait representing the types whose values can be generated at random.
+pub trait Arbitrary: Sized {
+    /// Generates a random value.
+    fn arbitrary(rng: &mut Rng) -> Self;
+}
+
+macro_rules! impl_arbitrary_for_ints {
+    ($($t:ty),*) => {$(
+        impl Arbitrary for $t {
+            fn arbitrary(rng: &mut Rng) -> Self {
+                if rng.gen_bool(0.25) {
+                    *rng.choose(&[0, 1, <$t>::MIN, <$t>::MAX]).unwrap()
+                } else {
+                    rng.next_u64() as $t
+                }
+            }
+        }
+    )*};
+}
+
+impl_arbitrary_for_ints!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl Arbitrary for bool {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        rng.gen_bool(0.5)
+    }
+}
+
+impl Arbitrary for f64 {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        if rng.gen_bool(0.25) {
+            *rng.choose(&[0.0, -0.0, 1.0, -1.0, std::f64::MAX, std::f64::MIN])
+                .unwrap()
+        } else {
+            (rng.gen_f64() - 0.5) * 2e6
+        }
+    }
+}
+
+impl Arbitrary for f32 {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        f64::arbitrary(rng) as f32
+    }
+}
+
+impl Arbitrary for char {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        if rng.gen_bool(0.2) {
+            *rng.choose(SPECIAL_CHARS).unwrap()
+        } else {
+            rng.alphanumeric(1).chars().next().unwrap()
+        }
+    }
+}
+
+impl Arbitrary for String {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        let len = rng.gen_len();
+        (0..len).map(|_| rng.gen::<char>()).collect()
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Option<T> {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        if rng.gen_bool(0.25) {
+            None
+        } else {
+            Some(rng.gen())
+        }
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Box<T> {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        Box::new(rng.gen())
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Vec<T> {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        let len = rng.gen_len();
+        (0..len).map(|_| rng.gen()).collect()
+    }
+}
+
+impl<K: Arbitrary + Eq + Hash, V: Arbitrary> Arbitrary for HashMap<K, V> {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        let len = rng.gen_len();
+        (0..len).map(|_| (rng.gen(), rng.gen())).collect()
+    }
+}
+
+impl<K: Arbitrary + Ord, V: Arbitrary> Arbitrary for BTreeMap<K, V> {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        let len = rng.gen_len();
+        (0..len).map(|_| (rng.gen(), rng.gen())).collect()
+    }
+}
+
+macro_rules! impl_arbitrary_for_tuples {
+    ($($T:ident),*) => {
+        impl<$($T: Arbitrary),*> Arbitrary for ($($T,)*) {
+            fn arbitrary(rng: &mut Rng) -> Self {
+                ($(rng.gen::<$T>(),)*)
+            }
+        }
+    };
+}
+
+impl_arbitrary_for_tuples!(T1);
+impl_arbitrary_for_tuples!(T1, T2);
+impl_arbitrary_for_tuples!(T1, T2, T3);
+impl_arbitrary_for_tuples!(T1, T2, T3, T4);
+
+// ==== extractor inputs ====
+
+/// Formats the value as a path segment, percent-encoding the reserved characters.
+pub fn segment<T: Display + ?Sized>(value: &T) -> String {
+    utf8_percent_encode(&value.to_string(), PATH_SEGMENT_ENCODE_SET).to_string()
+}
+
+/// Serializes the value as a query string, in the format parsed by `endpoints::query`.
+///
+/// # Panics
+/// This function panics if the value cannot be serialized as a query string.
+pub fn query<T: Serialize>(value: &T) -> String {
+    serde_qs::to_string(value).expect("failed to serialize the value")
+}
+
+/// Generates a random JSON value which conforms to the JSON schema.
+///
+/// The supported keywords are `type`, `enum`, `const`, `nullable`, `allOf`,
+/// `anyOf`, `oneOf`, `properties`, `required`, `items`, `minItems`, `maxItems`,
+/// `minLength`, `maxLength`, `format` (`date`, `date-time`, `uuid` and `email`),
+/// `minimum`, `maximum` and their exclusive versions. The local references
+/// (`#/...`) are resolved against `schema` itself. The optional properties
+/// are included at random.
+pub fn json_from_schema(schema: &Value, rng: &mut Rng) -> Value {
+    SchemaGen { root: schema }.generate(schema, rng, 0)
+}
+
+struct SchemaGen<'a> {
+    root: &'a Value,
+}
+
+impl<'a> SchemaGen<'a> {
+    const MAX_DEPTH: usize = 8;
+
+    fn resolve(&self, mut schema: &'a Value) -> &'a Value {
+        while let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            schema = Some(reference)
+                .filter(|reference| reference.starts_with('#'))
+                .and_then(|reference| self.root.pointer(&reference[1..]))
+                .unwrap_or_else(|| panic!("unresolved reference: {}", reference));
+        }
+        schema
+    }
+
+    fn generate(&self, schema: &'a Value, rng: &mut Rng, depth: usize) -> Value {
+        let schema = self.resolve(schema);
+        if let Some(value) = schema.get("const") {
+            return value.clone();
+        }
+        if let Some(variants) = schema.get("enum").and_then(Value::as_array) {
+            return rng.choose(variants).cloned().unwrap_or(Value::Null);
+        }
+        if schema.get("nullable").and_then(Value::as_bool) == Some(true) && rng.gen_bool(0.1) {
+            return Value::Null;
+        }
+        if let Some(schemas) = schema.get("allOf").and_then(Value::as_array) {
+            let mut merged = Map::new();
+            for schema in schemas {
+                match self.generate(schema, rng, depth) {
+                    Value::Object(object) => merged.extend(object),
+                    value => return value,
+                }
+            }
+            return Value::Object(merged);
+        }
+        for key in &["oneOf", "anyOf"] {
+            if let Some(schemas) = schema.get(*key).and_then(Value::as_array) {
+                if let Some(schema) = rng.choose(schemas) {
+                    return self.generate(schema, rng, depth);
+                }
+            }
+        }
+
+        let ty = match schema.get("type") {
+            Some(Value::String(ty)) => ty.as_str(),
+            Some(Value::Array(types)) => {
+                let types: Vec<_> = types.iter().filter_map(Value::as_str).collect();
+                rng.choose(&types).cloned().unwrap_or("null")
+            }
+            _ if schema.get("properties").is_some() => "object",
+            _ => "null",
+        };
+        match ty {
+            "boolean" => Value::Bool(rng.gen()),
+            "integer" => {
+                let (min, max) = self.bounds(schema, 1.0);
+                let min = min.map_or(-1_000_000, |min| min.ceil() as i64);
+                let max = max.map_or(min.saturating_add(2_000_000), |max| max.floor() as i64);
+                if rng.gen_bool(0.25) {
+                    Value::from(*rng.choose(&[min, max]).unwrap())
+                } else {
+                    Value::from(rng.gen_range(min..max.saturating_add(1)))
+                }
+            }
+            "number" => {
+                let (min, max) = self.bounds(schema, std::f64::EPSILON);
+                let min = min.unwrap_or(-1e6);
+                let max = max.unwrap_or(min + 2e6);
+                Value::from(min + (max - min) * rng.gen_f64())
+            }
+            "string" => Value::String(self.string(schema, rng)),
+            "array" => {
+                let len = self.len(schema, "minItems", "maxItems", rng, depth);
+                let items = schema.get("items").unwrap_or(&Value::Null);
+                Value::Array(
+                    (0..len)
+                        .map(|_| self.generate(items, rng, depth + 1))
+                        .collect(),
+                )
+            }
+            "object" => {
+                let required: Vec<_> = schema
+                    .get("required")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .collect();
+                let mut object = Map::new();
+                let properties = schema.get("properties").and_then(Value::as_object);
+                for (name, property) in properties.into_iter().flatten() {
+                    if required.contains(&name.as_str())
+                        || (depth < Self::MAX_DEPTH && rng.gen_bool(0.5))
+                    {
+                        object.insert(name.clone(), self.generate(property, rng, depth + 1));
+                    }
+                }
+                Value::Object(object)
+            }
+            _ => Value::Null,
+        }
+    }
+
+    fn bounds(&self, schema: &Value, step: f64) -> (Option<f64>, Option<f64>) {
+        let bound = |key: &str, exclusive_key: &str, sign: f64| match schema.get(exclusive_key) {
+            Some(Value::Number(n)) => n.as_f64().map(|n| n + sign * step),
+            Some(Value::Bool(true)) => schema
+                .get(key)
+                .and_then(Value::as_f64)
+                .map(|n| n + sign * step),
+            _ => schema.get(key).and_then(Value::as_f64),
+        };
+        (
+            bound("minimum", "exclusiveMinimum", 1.0),
+            bound("maximum", "exclusiveMaximum", -1.0),
+        )
+    }
+
+    fn len(&self, schema: &Value, min: &str, max: &str, rng: &mut Rng, depth: usize) -> usize {
+        let min = schema.get(min).and_then(Value::as_u64).unwrap_or(0) as usize;
+        if depth >= Self::MAX_DEPTH {
+            return min;
+        }
+        let max = schema
+            .get(max)
+            .and_then(Value::as_u64)
+            .map_or(min + rng.max_size(), |max| max as usize);
+        rng.gen_range(min as i64..max.max(min) as i64 + 1) as usize
+    }
+
+    fn string(&self, schema: &Value, rng: &mut Rng) -> String {
+        match schema.get("format").and_then(Value::as_str) {
+            Some("date") => {
+                return format!(
+                    "{:04}-{:02}-{:02}",
+                    rng.gen_range(1970..2100),
+                    rng.gen_range(1..13),
+                    rng.gen_range(1..29)
+                );
+            }
+            Some("date-time") => {
+                return format!(
+                    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                    rng.gen_range(1970..2100),
+                    rng.gen_range(1..13),
+                    rng.gen_range(1..29),
+                    rng.gen_range(0..24),
+                    rng.gen_range(0..60),
+                    rng.gen_range(0..60)
+                );
+            }
+            Some("uuid") => {
+                let (a, b) = (rng.next_u64(), rng.next_u64());
+                return format!(
+                    "{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+                    a >> 32,
+                    (a >> 16) & 0xffff,
+                    a & 0xfff,
+                    0x8000 | (b >> 48) & 0x3fff,
+                    b & 0xffff_ffff_ffff
+                );
+            }
+            Some("email") => {
+                let len = rng.gen_range(1..9) as usize;
+                return format!("{}@example.com", rng.alphanumeric(len));
+            }
+            _ => {}
+        }
+        let len = self.len(schema, "minLength", "maxLength", rng, 0);
+        (0..len).map(|_| rng.gen::<char>()).collect()
+    }
+}
+
+// ==== check ====
+
+/// Checks the property with the inputs generated from the seed in the
+/// environment variable `FINCHERS_TEST_SEED`, or `DEFAULT_SEED` if not set.
+///
+/// See `check_with_seed` for details.
+pub fn check<T, G, F>(cases: usize, gen: G, property: F)
+where
+    T: fmt::Debug,
+    G: FnMut(&mut Rng) -> T,
+    F: FnMut(T),
+{
+    let seed = env::var("FINCHERS_TEST_SEED")
+        .ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or(DEFAULT_SEED);
+    check_with_seed(seed, cases, gen, property)
+}
+
+/// Checks the property with the specified number of inputs generated by `gen`.
+///
+/// Each case uses its own `Rng` forked from the seed, and `property` is expected
+/// to panic (e.g. by using `assert!`) if the input does not satisfy the property.
+///
+/// # Panics
+///
+/// This function panics at the first failing case, with the seed, the index
+/// of the case and the debug representation of the input.
+pub fn check_with_seed<T, G, F>(seed: u64, cases: usize, mut gen: G, mut property: F)
+where
+    T: fmt::Debug,
+    G: FnMut(&mut Rng) -> T,
+    F: FnMut(T),
+{
+    let mut seeds = Rng::new(seed);
+    for i in 0..cases {
+        let input = gen(&mut seeds.fork());
+        let repr = format!("{:?}", input);
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| property(input))) {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "<non-string payload>".into());
+            panic!(
+                "property failed at case #{} (seed = {}): {}\ninput: {}",
+                i, seed, msg, repr
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    #[test]
+    fn test_deterministic() {
+        let gen = |seed| {
+            let mut rng = Rng::new(seed);
+            rng.gen::<(u32, String, Vec<Option<i8>>)>()
+        };
+        assert_eq!(gen(42), gen(42));
+        assert_ne!(gen(42), gen(43));
+    }
+
+    #[test]
+    fn test_gen_range() {
+        let mut rng = Rng::new(0);
+        for _ in 0..1000 {
+            let n = rng.gen_range(-3..4);
+            assert!(n >= -3 && n < 4);
+        }
+        assert_eq!(Rng::new(0).size(0).gen::<String>(), "");
+    }
+
+    #[test]
+    fn test_json_from_schema() {
+        let schema = json!({
+            "type": "object",
+            "required": ["id", "name", "tags"],
+            "properties": {
+                "id": { "type": "integer", "minimum": 1, "exclusiveMaximum": 10 },
+                "name": { "type": "string", "minLength": 2, "maxLength": 4 },
+                "tags": {
+                    "type": "array",
+                    "maxItems": 3,
+                    "items": { "$ref": "#/definitions/Tag" }
+                },
+                "created": { "type": "string", "format": "date-time" }
+            },
+            "definitions": {
+                "Tag": { "enum": ["a", "b"] }
+            }
+        });
+
+        let mut rng = Rng::new(1);
+        for _ in 0..200 {
+            let value = json_from_schema(&schema, &mut rng);
+            let id = value["id"].as_i64().unwrap();
+            assert!(id >= 1 && id < 10, "id = {}", id);
+            let len = value["name"].as_str().unwrap().chars().count();
+            assert!(len >= 2 && len <= 4, "name = {}", value["name"]);
+            let tags = value["tags"].as_array().unwrap();
+            assert!(tags.len() <= 3);
+            assert!(tags.iter().all(|tag| *tag == "a" || *tag == "b"));
+            if let Some(created) = value.get("created") {
+                assert_eq!(created.as_str().unwrap().len(), 20);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "property failed at case #")]
+    fn test_check_failure() {
+        check_with_seed(
+            1,
+            100,
+            |rng| rng.gen::<u8>(),
+            |n| assert!(n < 200, "too large"),
+        );
+    }
+}
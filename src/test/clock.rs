@@ -0,0 +1,49 @@
+use {
+    std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+    tokio_timer::clock::{Clock, Now},
+};
+
+/// A clock used by the test runner, which can be advanced manually.
+///
+/// The time returned from this clock follows the system clock, shifted by
+/// the total amount of durations passed to `advance`. Inside of the runtime
+/// managed by `TestRunner`, `tokio::clock::now()` and all timers registered
+/// with the runtime observe this clock.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    offset: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    pub(super) fn new() -> Self {
+        MockClock {
+            offset: Arc::new(Mutex::new(Duration::from_secs(0))),
+        }
+    }
+
+    /// Returns the current instant observed by this clock.
+    pub fn now(&self) -> Instant {
+        Instant::now() + *self.offset.lock().unwrap()
+    }
+
+    /// Moves this clock forward by the specified duration.
+    ///
+    /// The timers that expire by this change are fired at the next time
+    /// the runtime is driven.
+    pub fn advance(&self, duration: Duration) {
+        *self.offset.lock().unwrap() += duration;
+    }
+
+    pub(super) fn to_clock(&self) -> Clock {
+        Clock::new_with_now(self.clone())
+    }
+}
+
+impl Now for MockClock {
+    fn now(&self) -> Instant {
+        MockClock::now(self)
+    }
+}
@@ -0,0 +1,1143 @@
+use {
+    super::{ReqBody, TestResult, TestRunner},
+    crate::{endpoint::Endpoint, output::IntoResponse},
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        Method, Request,
+    },
+    izanami_util::buf_stream::BufStream,
+    percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET},
+    serde_json::{Map, Value},
+    std::{
+        collections::HashSet,
+        fmt::Write as _,
+        panic::{self, AssertUnwindSafe},
+    },
+};
+
+const METHODS: &[&str] = &["get", "put", "post", "delete", "options", "head", "patch"];
+
+/// Creates a contract test from the specified OpenAPI (3.x) document.
+///
+/// The test generates the requests for each operation in the document,
+/// applies them to the endpoint and checks that the responses conform to
+/// the document:
+///
+/// * A *valid* request, built from the examples, defaults or the minimal
+///   values satisfying the schemas of the parameters and the request body,
+///   must receive a response whose status code is documented. If the
+///   documented response has a JSON schema, the response body must match it.
+/// * A *boundary-invalid* request, in which a required parameter or property
+///   is missing or a value violates its schema (type, range, length or enum),
+///   must be rejected with a client error (4xx).
+///
+/// The references (`$ref`) within the document are resolved, but only the
+/// local ones (`#/...`) are supported.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax::path;
+/// # use finchers::output;
+/// # use finchers::test;
+/// # use serde_json::json;
+/// let spec = json!({
+///     "openapi": "3.0.0",
+///     "info": { "title": "users", "version": "1.0" },
+///     "paths": {
+///         "/users/{id}": {
+///             "get": {
+///                 "operationId": "getUser",
+///                 "parameters": [{
+///                     "name": "id",
+///                     "in": "path",
+///                     "required": true,
+///                     "schema": { "type": "integer", "minimum": 0 }
+///                 }],
+///                 "responses": {
+///                     "200": {
+///                         "description": "the user",
+///                         "content": {
+///                             "application/json": {
+///                                 "schema": {
+///                                     "type": "object",
+///                                     "required": ["id"],
+///                                     "properties": { "id": { "type": "integer" } }
+///                                 }
+///                             }
+///                         }
+///                     }
+///                 }
+///             }
+///         }
+///     }
+/// });
+///
+/// let endpoint = path!(@get "/users/<u32>")
+///     .map(|id: u32| output::Json(json!({ "id": id })));
+///
+/// test::contract(spec).run(endpoint);
+/// ```
+pub fn contract(spec: Value) -> Contract {
+    Contract {
+        spec,
+        base_path: String::new(),
+        invalid_requests: true,
+        skipped: HashSet::new(),
+        headers: HeaderMap::new(),
+    }
+}
+
+/// A contract test created by `contract`.
+#[derive(Debug)]
+pub struct Contract {
+    spec: Value,
+    base_path: String,
+    invalid_requests: bool,
+    skipped: HashSet<String>,
+    headers: HeaderMap,
+}
+
+#[derive(Debug)]
+struct Operation {
+    label: String,
+    method: Method,
+    path: String,
+    params: Vec<Param>,
+    body: Option<RequestBody>,
+    responses: Map<String, Value>,
+}
+
+#[derive(Debug, Clone)]
+struct Param {
+    name: String,
+    location: String,
+    required: bool,
+    schema: Value,
+    example: Option<Value>,
+}
+
+#[derive(Debug, Clone)]
+struct RequestBody {
+    required: bool,
+    schema: Value,
+    example: Option<Value>,
+}
+
+/// A request generated from an operation.
+#[derive(Debug, Clone)]
+struct Case {
+    description: String,
+    params: Vec<(Param, Option<Value>)>,
+    body: Option<Option<Result<Value, &'static str>>>,
+}
+
+impl Contract {
+    /// Sets the prefix of the paths, e.g. the path of the server URL.
+    pub fn base_path(mut self, base_path: impl Into<String>) -> Self {
+        self.base_path = base_path.into().trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Sets whether to generate the boundary-invalid requests.
+    ///
+    /// The default value is `true`.
+    pub fn invalid_requests(mut self, enabled: bool) -> Self {
+        self.invalid_requests = enabled;
+        self
+    }
+
+    /// Skips the operation with the specified `operationId` or the label
+    /// in the form of `"GET /users/{id}"`.
+    pub fn skip(mut self, operation: impl Into<String>) -> Self {
+        self.skipped.insert(operation.into());
+        self
+    }
+
+    /// Appends a header field to all requests, e.g. the credential of the API.
+    ///
+    /// # Panics
+    /// This method panics if the name or value is invalid.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        let name: HeaderName = name.parse().expect("invalid header name");
+        let value: HeaderValue = value.parse().expect("invalid header value");
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Runs the contract test against the specified endpoint.
+    ///
+    /// # Panics
+    ///
+    /// This method panics with the list of the violations if any check fails,
+    /// or if the document contains no operations.
+    pub fn run<E>(&self, endpoint: E)
+    where
+        E: Endpoint<ReqBody>,
+        E::Output: IntoResponse,
+        <E::Output as IntoResponse>::Body: BufStream,
+        <<E::Output as IntoResponse>::Body as BufStream>::Error:
+            Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        let operations = self.operations();
+        if operations.is_empty() {
+            panic!("the specification contains no operations");
+        }
+
+        let mut runner = TestRunner::new(endpoint).expect("failed to start the runtime");
+        *runner.default_headers() = self.headers.clone();
+
+        let mut failures = String::new();
+        let mut num_failures = 0;
+        for op in &operations {
+            let valid = self.valid_case(op);
+            let mut cases = vec![(valid.clone(), true)];
+            if self.invalid_requests {
+                cases.extend(
+                    self.invalid_cases(op, &valid)
+                        .into_iter()
+                        .map(|c| (c, false)),
+                );
+            }
+
+            for (case, is_valid) in cases {
+                let request = match self.build_request(op, &case) {
+                    Ok(request) => request,
+                    Err(..) => continue,
+                };
+                let result = panic::catch_unwind(AssertUnwindSafe(|| runner.perform(request)));
+                let failure = match result {
+                    Ok(Ok(response)) => {
+                        if is_valid {
+                            self.check_response(op, &response)
+                        } else if response.status().is_client_error() {
+                            None
+                        } else {
+                            Some(format!(
+                                "expected a client error, but got {}",
+                                response.status()
+                            ))
+                        }
+                    }
+                    Ok(Err(err)) => Some(format!("failed to receive the response: {}", err)),
+                    Err(payload) => Some(format!(
+                        "panicked: {}",
+                        payload
+                            .downcast_ref::<&str>()
+                            .map(|s| s.to_string())
+                            .or_else(|| payload.downcast_ref::<String>().cloned())
+                            .unwrap_or_else(|| "<non-string payload>".into())
+                    )),
+                };
+                if let Some(failure) = failure {
+                    num_failures += 1;
+                    let _ = writeln!(
+                        failures,
+                        "  {} ({}): {}",
+                        op.label, case.description, failure
+                    );
+                }
+            }
+        }
+
+        if num_failures > 0 {
+            panic!("{} contract violation(s):\n{}", num_failures, failures);
+        }
+    }
+
+    // ==== document ====
+
+    /// Follows the local references.
+    fn resolve<'a>(&'a self, mut value: &'a Value) -> &'a Value {
+        for _ in 0..32 {
+            match value.get("$ref").and_then(Value::as_str) {
+                Some(reference) if reference.starts_with('#') => {
+                    match self.spec.pointer(&reference[1..]) {
+                        Some(target) => value = target,
+                        None => panic!("unresolved reference: {}", reference),
+                    }
+                }
+                Some(reference) => panic!("unsupported reference: {}", reference),
+                None => return value,
+            }
+        }
+        panic!("too deep references")
+    }
+
+    fn operations(&self) -> Vec<Operation> {
+        let mut operations = vec![];
+        let paths = match self.spec.get("paths").and_then(Value::as_object) {
+            Some(paths) => paths,
+            None => return operations,
+        };
+        for (path, item) in paths {
+            let item = self.resolve(item);
+            let common = self.params(item.get("parameters"));
+            for &method in METHODS {
+                let op = match item.get(method) {
+                    Some(op) => op,
+                    None => continue,
+                };
+                let label = format!("{} {}", method.to_uppercase(), path);
+                let id = op.get("operationId").and_then(Value::as_str);
+                if self.skipped.contains(&label) || id.map_or(false, |id| self.skipped.contains(id))
+                {
+                    continue;
+                }
+
+                let mut params = common.clone();
+                for param in self.params(op.get("parameters")) {
+                    params.retain(|p| p.name != param.name || p.location != param.location);
+                    params.push(param);
+                }
+
+                let body = op
+                    .get("requestBody")
+                    .map(|body| self.resolve(body))
+                    .and_then(|body| {
+                        let media = body.get("content")?.get("application/json")?;
+                        Some(RequestBody {
+                            required: body.get("required").and_then(Value::as_bool) == Some(true),
+                            schema: media.get("schema").cloned().unwrap_or(Value::Null),
+                            example: media.get("example").cloned(),
+                        })
+                    });
+
+                operations.push(Operation {
+                    label: match id {
+                        Some(id) => format!("{} [{}]", label, id),
+                        None => label,
+                    },
+                    method: method.to_uppercase().parse().expect("valid method"),
+                    path: path.clone(),
+                    params,
+                    body,
+                    responses: op
+                        .get("responses")
+                        .and_then(Value::as_object)
+                        .cloned()
+                        .unwrap_or_default(),
+                });
+            }
+        }
+        operations
+    }
+
+    fn params(&self, params: Option<&Value>) -> Vec<Param> {
+        params
+            .and_then(Value::as_array)
+            .map(|params| {
+                params
+                    .iter()
+                    .map(|param| self.resolve(param))
+                    .filter_map(|param| {
+                        let location = param.get("in")?.as_str()?.to_owned();
+                        Some(Param {
+                            name: param.get("name")?.as_str()?.to_owned(),
+                            required: location == "path"
+                                || param.get("required").and_then(Value::as_bool) == Some(true),
+                            location,
+                            schema: param.get("schema").cloned().unwrap_or(Value::Null),
+                            example: param.get("example").cloned(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // ==== generation ====
+
+    fn valid_case(&self, op: &Operation) -> Case {
+        Case {
+            description: "valid request".into(),
+            params: op
+                .params
+                .iter()
+                .map(|param| {
+                    let value = param
+                        .example
+                        .clone()
+                        .unwrap_or_else(|| self.sample(&param.schema));
+                    (param.clone(), Some(value))
+                })
+                .collect(),
+            body: op.body.as_ref().map(|body| {
+                Some(Ok(body
+                    .example
+                    .clone()
+                    .unwrap_or_else(|| self.sample(&body.schema))))
+            }),
+        }
+    }
+
+    fn invalid_cases(&self, op: &Operation, valid: &Case) -> Vec<Case> {
+        let mut cases = vec![];
+        for (i, (param, _)) in valid.params.iter().enumerate() {
+            if param.required && param.location != "path" {
+                let mut case = valid.clone();
+                case.description = format!("missing {} parameter `{}'", param.location, param.name);
+                case.params[i].1 = None;
+                cases.push(case);
+            }
+            for (reason, value) in self.invalid_values(&param.schema) {
+                let mut case = valid.clone();
+                case.description =
+                    format!("{} parameter `{}' {}", param.location, param.name, reason);
+                case.params[i].1 = Some(value);
+                cases.push(case);
+            }
+        }
+
+        if let Some(ref body) = op.body {
+            if body.required {
+                let mut case = valid.clone();
+                case.description = "missing request body".into();
+                case.body = Some(None);
+                cases.push(case);
+            }
+
+            let mut case = valid.clone();
+            case.description = "malformed request body".into();
+            case.body = Some(Some(Err("{")));
+            cases.push(case);
+
+            let schema = self.resolve(&body.schema);
+            let object = match valid.body {
+                Some(Some(Ok(Value::Object(ref object)))) => object,
+                _ => return cases,
+            };
+            for name in required(schema) {
+                let mut object = object.clone();
+                object.remove(name);
+                let mut case = valid.clone();
+                case.description = format!("missing property `{}'", name);
+                case.body = Some(Some(Ok(Value::Object(object))));
+                cases.push(case);
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, schema) in properties.into_iter().flatten() {
+                if !object.contains_key(name) {
+                    continue;
+                }
+                for (reason, value) in self.invalid_values(schema) {
+                    let mut object = object.clone();
+                    object.insert(name.clone(), value);
+                    let mut case = valid.clone();
+                    case.description = format!("property `{}' {}", name, reason);
+                    case.body = Some(Some(Ok(Value::Object(object))));
+                    cases.push(case);
+                }
+            }
+        }
+
+        cases
+    }
+
+    /// Generates a value which satisfies the schema.
+    fn sample(&self, schema: &Value) -> Value {
+        let schema = self.resolve(schema);
+        for key in &["example", "default", "const"] {
+            if let Some(value) = schema.get(*key) {
+                return value.clone();
+            }
+        }
+        if let Some(value) = schema.get("enum").and_then(|e| e.get(0)) {
+            return value.clone();
+        }
+        if let Some(schemas) = schema.get("allOf").and_then(Value::as_array) {
+            let mut merged = Map::new();
+            for schema in schemas {
+                match self.sample(schema) {
+                    Value::Object(object) => merged.extend(object),
+                    value => return value,
+                }
+            }
+            return Value::Object(merged);
+        }
+        for key in &["oneOf", "anyOf"] {
+            if let Some(schema) = schema.get(*key).and_then(|s| s.get(0)) {
+                return self.sample(schema);
+            }
+        }
+
+        match schema_type(schema) {
+            Some("integer") => {
+                let (min, max) = bounds(schema);
+                let value = match (min, max) {
+                    (Some(min), _) => min.ceil(),
+                    (None, Some(max)) => max.floor().min(1.0),
+                    (None, None) => 1.0,
+                };
+                Value::from(value as i64)
+            }
+            Some("number") => {
+                let (min, max) = bounds(schema);
+                Value::from(min.or_else(|| max.map(|max| max.min(1.0))).unwrap_or(1.0))
+            }
+            Some("boolean") => Value::Bool(true),
+            Some("string") => Value::String(match schema.get("format").and_then(Value::as_str) {
+                Some("date") => "2000-01-01".into(),
+                Some("date-time") => "2000-01-01T00:00:00Z".into(),
+                Some("uuid") => "00000000-0000-4000-8000-000000000000".into(),
+                Some("email") => "user@example.com".into(),
+                Some("uri") | Some("url") => "http://example.com/".into(),
+                _ => {
+                    let min = uint(schema, "minLength").unwrap_or(1).max(1);
+                    let len = uint(schema, "maxLength").map_or(min, |max| min.min(max));
+                    "a".repeat(len as usize)
+                }
+            }),
+            Some("array") => {
+                let len = uint(schema, "minItems").unwrap_or(1).max(1);
+                let item = schema
+                    .get("items")
+                    .map_or(Value::Null, |items| self.sample(items));
+                Value::Array(vec![item; len as usize])
+            }
+            Some("object") => Value::Object(
+                required(schema)
+                    .filter_map(|name| {
+                        let property = schema.get("properties")?.get(name)?;
+                        Some((name.to_owned(), self.sample(property)))
+                    })
+                    .collect(),
+            ),
+            _ => Value::Null,
+        }
+    }
+
+    /// Generates the values just outside of the schema.
+    fn invalid_values(&self, schema: &Value) -> Vec<(String, Value)> {
+        let schema = self.resolve(schema);
+        let mut values = vec![];
+        if let Some(variants) = schema.get("enum").and_then(Value::as_array) {
+            let value = Value::from("__invalid__");
+            if !variants.contains(&value) {
+                values.push(("out of enum".into(), value));
+            }
+            return values;
+        }
+
+        match schema_type(schema) {
+            Some(ty @ "integer") | Some(ty @ "number") => {
+                let reason = if ty == "integer" {
+                    "not an integer"
+                } else {
+                    "not a number"
+                };
+                values.push((reason.into(), Value::from("abc")));
+                let (min, max) = bounds(schema);
+                if let Some(min) = min {
+                    values.push(("below minimum".into(), number(min.ceil() - 1.0, ty)));
+                }
+                if let Some(max) = max {
+                    values.push(("above maximum".into(), number(max.floor() + 1.0, ty)));
+                }
+            }
+            Some("boolean") => values.push(("not a boolean".into(), Value::from("abc"))),
+            Some("string") => {
+                if let Some(min) = uint(schema, "minLength").filter(|&min| min > 0) {
+                    values.push((
+                        "too short".into(),
+                        Value::from("a".repeat(min as usize - 1)),
+                    ));
+                }
+                if let Some(max) = uint(schema, "maxLength") {
+                    values.push(("too long".into(), Value::from("a".repeat(max as usize + 1))));
+                }
+            }
+            _ => {}
+        }
+        values
+    }
+
+    fn build_request(&self, op: &Operation, case: &Case) -> http::Result<Request<Vec<u8>>> {
+        let mut path = op.path.clone();
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        let mut has_query = false;
+        let mut cookies = vec![];
+        let mut request = Request::builder();
+        request.method(op.method.clone());
+        for (param, value) in &case.params {
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+            match &*param.location {
+                "path" => {
+                    let value: String =
+                        utf8_percent_encode(&to_param(value), PATH_SEGMENT_ENCODE_SET).collect();
+                    path = path.replace(&format!("{{{}}}", param.name), &value);
+                }
+                "query" => {
+                    has_query = true;
+                    match value {
+                        Value::Array(values) => {
+                            for value in values {
+                                query.append_pair(&param.name, &to_param(value));
+                            }
+                        }
+                        value => {
+                            query.append_pair(&param.name, &to_param(value));
+                        }
+                    }
+                }
+                "header" => {
+                    request.header(&*param.name, &*to_param(value));
+                }
+                "cookie" => cookies.push(format!("{}={}", param.name, to_param(value))),
+                _ => {}
+            }
+        }
+        if !cookies.is_empty() {
+            request.header(header::COOKIE, &*cookies.join("; "));
+        }
+
+        let mut uri = format!("{}{}", self.base_path, path);
+        if has_query {
+            uri += "?";
+            uri += &query.finish();
+        }
+        request.uri(&*uri);
+
+        let body = match case.body {
+            Some(Some(Ok(ref value))) => serde_json::to_vec(value).expect("serializable value"),
+            Some(Some(Err(raw))) => raw.as_bytes().to_vec(),
+            Some(None) | None => return request.body(vec![]),
+        };
+        request.header(header::CONTENT_TYPE, "application/json");
+        request.body(body)
+    }
+
+    // ==== validation ====
+
+    fn check_response(&self, op: &Operation, response: &TestResult) -> Option<String> {
+        let status = response.status();
+        let code = status.as_str();
+        let range = format!("{}XX", &code[..1]);
+        let documented = op
+            .responses
+            .iter()
+            .find(|(key, _)| *key == code)
+            .or_else(|| {
+                op.responses
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(&range))
+            })
+            .or_else(|| op.responses.iter().find(|(key, _)| *key == "default"));
+        let documented = match documented {
+            Some((_, documented)) => self.resolve(documented),
+            None => return Some(format!("status {} is not documented", status)),
+        };
+
+        let content = match documented.get("content").and_then(Value::as_object) {
+            Some(content) if !content.is_empty() => content,
+            _ => return None,
+        };
+        if response.body().is_empty() && status == http::StatusCode::NO_CONTENT {
+            return None;
+        }
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase());
+        let media = content.iter().find(|(key, _)| {
+            let key = key.to_ascii_lowercase();
+            match content_type {
+                Some(ref content_type) => {
+                    key == *content_type
+                        || key == "*/*"
+                        || (key.ends_with("/*") && content_type.starts_with(&key[..key.len() - 1]))
+                }
+                None => false,
+            }
+        });
+        let (media_type, media) = match media {
+            Some(media) => media,
+            None => {
+                return Some(format!(
+                    "content type {} is not documented for status {}",
+                    content_type.as_ref().map_or("<missing>", String::as_str),
+                    status
+                ));
+            }
+        };
+
+        let schema = match media.get("schema") {
+            Some(schema) if media_type.contains("json") => schema,
+            _ => return None,
+        };
+        let body: Value = match serde_json::from_slice(response.body()) {
+            Ok(body) => body,
+            Err(err) => return Some(format!("the response body is not a valid JSON: {}", err)),
+        };
+        let mut errors = vec![];
+        self.validate(schema, &body, "$", &mut errors);
+        if errors.is_empty() {
+            None
+        } else {
+            Some(format!(
+                "the response body does not match the schema: {}",
+                errors.join(", ")
+            ))
+        }
+    }
+
+    fn validate(&self, schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+        let schema = self.resolve(schema);
+        if value.is_null() && schema.get("nullable").and_then(Value::as_bool) == Some(true) {
+            return;
+        }
+
+        if let Some(schemas) = schema.get("allOf").and_then(Value::as_array) {
+            for schema in schemas {
+                self.validate(schema, value, path, errors);
+            }
+        }
+        for (key, exactly_one) in &[("oneOf", true), ("anyOf", false)] {
+            if let Some(schemas) = schema.get(*key).and_then(Value::as_array) {
+                let matched = schemas
+                    .iter()
+                    .filter(|schema| {
+                        let mut errors = vec![];
+                        self.validate(schema, value, path, &mut errors);
+                        errors.is_empty()
+                    })
+                    .count();
+                if matched == 0 || (*exactly_one && matched > 1) {
+                    errors.push(format!(
+                        "{}: {} schema(s) of `{}' matched",
+                        path, matched, key
+                    ));
+                }
+            }
+        }
+        if let Some(variants) = schema.get("enum").and_then(Value::as_array) {
+            if !variants.contains(value) {
+                errors.push(format!("{}: {} is not one of the enum values", path, value));
+            }
+        }
+
+        let expected: Vec<&str> = match schema.get("type") {
+            Some(Value::String(ty)) => vec![ty],
+            Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !expected.is_empty() && !expected.iter().any(|ty| is_type(value, ty)) {
+            errors.push(format!(
+                "{}: expected {}, found {}",
+                path,
+                expected.join(" or "),
+                value
+            ));
+            return;
+        }
+
+        match *value {
+            Value::Number(ref n) => {
+                let n = n.as_f64().unwrap_or(0.0);
+                let (min, max) = bounds(schema);
+                if min.map_or(false, |min| n < min) {
+                    errors.push(format!("{}: {} is below the minimum", path, n));
+                }
+                if max.map_or(false, |max| n > max) {
+                    errors.push(format!("{}: {} is above the maximum", path, n));
+                }
+            }
+            Value::String(ref s) => {
+                let len = s.chars().count() as u64;
+                if uint(schema, "minLength").map_or(false, |min| len < min) {
+                    errors.push(format!("{}: the string is too short", path));
+                }
+                if uint(schema, "maxLength").map_or(false, |max| len > max) {
+                    errors.push(format!("{}: the string is too long", path));
+                }
+            }
+            Value::Array(ref items) => {
+                let len = items.len() as u64;
+                if uint(schema, "minItems").map_or(false, |min| len < min) {
+                    errors.push(format!("{}: too few items", path));
+                }
+                if uint(schema, "maxItems").map_or(false, |max| len > max) {
+                    errors.push(format!("{}: too many items", path));
+                }
+                if let Some(item_schema) = schema.get("items") {
+                    for (i, item) in items.iter().enumerate() {
+                        self.validate(item_schema, item, &format!("{}[{}]", path, i), errors);
+                    }
+                }
+            }
+            Value::Object(ref object) => {
+                for name in required(schema) {
+                    if !object.contains_key(name) {
+                        errors.push(format!("{}: missing property `{}'", path, name));
+                    }
+                }
+                let properties = schema.get("properties").and_then(Value::as_object);
+                for (name, value) in object {
+                    let path = format!("{}.{}", path, name);
+                    match properties.and_then(|p| p.get(name)) {
+                        Some(property) => self.validate(property, value, &path, errors),
+                        None => match schema.get("additionalProperties") {
+                            Some(Value::Bool(false)) => {
+                                errors.push(format!("{}: unexpected property", path))
+                            }
+                            Some(additional) if additional.is_object() => {
+                                self.validate(additional, value, &path, errors)
+                            }
+                            _ => {}
+                        },
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn schema_type(schema: &Value) -> Option<&str> {
+    match schema.get("type") {
+        Some(Value::String(ty)) => Some(ty),
+        Some(Value::Array(types)) => types
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|&ty| ty != "null"),
+        _ if schema.get("properties").is_some() => Some("object"),
+        _ => None,
+    }
+}
+
+fn is_type(value: &Value, ty: &str) -> bool {
+    match ty {
+        "integer" => value.as_f64().map_or(false, |n| n.fract() == 0.0),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn required(schema: &Value) -> impl Iterator<Item = &str> {
+    schema
+        .get("required")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+}
+
+fn uint(schema: &Value, key: &str) -> Option<u64> {
+    schema.get(key).and_then(Value::as_u64)
+}
+
+/// Returns the inclusive bounds of the numeric schema.
+fn bounds(schema: &Value) -> (Option<f64>, Option<f64>) {
+    let step = if schema_type(schema) == Some("integer") {
+        1.0
+    } else {
+        std::f64::EPSILON
+    };
+    let bound = |key: &str, exclusive_key: &str, sign: f64| {
+        match schema.get(exclusive_key) {
+            // OpenAPI 3.1
+            Some(Value::Number(n)) => n.as_f64().map(|n| n + sign * step),
+            // OpenAPI 3.0
+            Some(Value::Bool(true)) => schema
+                .get(key)
+                .and_then(Value::as_f64)
+                .map(|n| n + sign * step),
+            _ => schema.get(key).and_then(Value::as_f64),
+        }
+    };
+    (
+        bound("minimum", "exclusiveMinimum", 1.0),
+        bound("maximum", "exclusiveMaximum", -1.0),
+    )
+}
+
+fn number(value: f64, ty: &str) -> Value {
+    if ty == "integer" {
+        Value::from(value as i64)
+    } else {
+        Value::from(value)
+    }
+}
+
+fn to_param(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(values) => values.iter().map(to_param).collect::<Vec<_>>().join(","),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            endpoint::{
+                syntax::{self, verb},
+                EndpointExt,
+            },
+            endpoints::{body, query},
+            error::{self, Error},
+            output::{status::Created, Json},
+        },
+        http::StatusCode,
+        serde::{Deserialize, Serialize},
+        serde_json::json,
+    };
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct User {
+        id: u32,
+        name: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct NewUser {
+        name: String,
+        #[allow(dead_code)]
+        age: u32,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Paging {
+        limit: u32,
+    }
+
+    fn spec() -> Value {
+        json!({
+            "openapi": "3.0.0",
+            "info": { "title": "users", "version": "1.0" },
+            "paths": {
+                "/users": {
+                    "get": {
+                        "operationId": "listUsers",
+                        "parameters": [{
+                            "name": "limit",
+                            "in": "query",
+                            "required": true,
+                            "schema": { "type": "integer", "minimum": 1, "maximum": 100 }
+                        }],
+                        "responses": {
+                            "200": {
+                                "description": "the users",
+                                "content": {
+                                    "application/json": {
+                                        "schema": {
+                                            "type": "array",
+                                            "items": { "$ref": "#/components/schemas/User" }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    "post": {
+                        "operationId": "createUser",
+                        "requestBody": {
+                            "required": true,
+                            "content": {
+                                "application/json": {
+                                    "schema": { "$ref": "#/components/schemas/NewUser" }
+                                }
+                            }
+                        },
+                        "responses": {
+                            "201": {
+                                "description": "the created user",
+                                "content": {
+                                    "application/json": {
+                                        "schema": { "$ref": "#/components/schemas/User" }
+                                    }
+                                }
+                            },
+                            "4XX": { "description": "invalid user" }
+                        }
+                    }
+                },
+                "/users/{id}": {
+                    "parameters": [{
+                        "name": "id",
+                        "in": "path",
+                        "schema": { "type": "integer", "minimum": 1 }
+                    }],
+                    "get": {
+                        "operationId": "getUser",
+                        "responses": {
+                            "200": {
+                                "description": "the user",
+                                "content": {
+                                    "application/json": {
+                                        "schema": { "$ref": "#/components/schemas/User" }
+                                    }
+                                }
+                            },
+                            "404": { "description": "not found" }
+                        }
+                    }
+                }
+            },
+            "components": {
+                "schemas": {
+                    "User": {
+                        "type": "object",
+                        "required": ["id", "name"],
+                        "properties": {
+                            "id": { "type": "integer" },
+                            "name": { "type": "string" }
+                        },
+                        "additionalProperties": false
+                    },
+                    "NewUser": {
+                        "type": "object",
+                        "required": ["name", "age"],
+                        "properties": {
+                            "name": { "type": "string", "minLength": 1, "maxLength": 8 },
+                            "age": { "type": "integer", "minimum": 0 }
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    fn user(id: u32) -> User {
+        User {
+            id,
+            name: "alice".into(),
+        }
+    }
+
+    fn check(cond: bool) -> Result<(), Error> {
+        if cond {
+            Ok(())
+        } else {
+            Err(error::err_msg("invalid parameter", StatusCode::BAD_REQUEST))
+        }
+    }
+
+    #[test]
+    fn test_contract() {
+        let endpoint = verb::get()
+            .and(syntax::segment("users"))
+            .and(syntax::eos())
+            .and(query::required::<Paging>())
+            .and_then(|paging: Paging| {
+                check(paging.limit >= 1 && paging.limit <= 100).map(|()| Json(vec![user(1)]))
+            })
+            .or(verb::post()
+                .and(syntax::segment("users"))
+                .and(syntax::eos())
+                .and(body::json::<NewUser>())
+                .and_then(|new_user: NewUser| {
+                    let len = new_user.name.chars().count();
+                    check(len >= 1 && len <= 8).map(|()| Created(Json(user(2))))
+                }))
+            .or(verb::get()
+                .and(syntax::segment("users"))
+                .and(syntax::param::<u32>())
+                .and(syntax::eos())
+                .and_then(|id: u32| check(id >= 1).map(|()| Json(user(id)))));
+
+        contract(spec()).run(endpoint);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "the response body does not match the schema: $.id: expected integer"
+    )]
+    fn test_contract_response_mismatch() {
+        let endpoint = verb::get()
+            .and(syntax::segment("users"))
+            .and(syntax::param::<u32>())
+            .and(syntax::eos())
+            .map(|id: u32| Json(json!({ "id": id.to_string(), "name": "alice" })));
+
+        contract(spec())
+            .skip("listUsers")
+            .skip("POST /users")
+            .invalid_requests(false)
+            .run(endpoint);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "GET /users [listUsers] (query parameter `limit' above maximum): \
+                               expected a client error, but got 200 OK"
+    )]
+    fn test_contract_accepts_invalid() {
+        let endpoint = verb::get()
+            .and(syntax::segment("users"))
+            .and(syntax::eos())
+            .and(query::required::<Paging>())
+            .map(|_: Paging| Json(vec![user(1)]));
+
+        contract(spec())
+            .skip("createUser")
+            .skip("getUser")
+            .run(endpoint);
+    }
+
+    #[test]
+    fn test_validate() {
+        let contract = contract(spec());
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "tags": { "type": "array", "items": { "type": "string" }, "maxItems": 1 },
+                "owner": {
+                    "oneOf": [
+                        { "$ref": "#/components/schemas/User" },
+                        { "type": "string", "enum": ["nobody"] }
+                    ]
+                }
+            }
+        });
+        let validate = |value: Value| {
+            let mut errors = vec![];
+            contract.validate(&schema, &value, "$", &mut errors);
+            errors
+        };
+
+        assert!(validate(json!({ "tags": ["a"], "owner": "nobody" })).is_empty());
+        assert!(validate(json!({ "owner": { "id": 1, "name": "alice" } })).is_empty());
+        assert_eq!(
+            validate(json!({ "tags": ["a", 1] })),
+            vec![
+                "$.tags: too many items",
+                "$.tags[1]: expected string, found 1"
+            ]
+        );
+        assert_eq!(
+            validate(json!({ "owner": "somebody" })),
+            vec!["$.owner: 0 schema(s) of `oneOf' matched"]
+        );
+    }
+
+    #[test]
+    fn test_sample() {
+        let contract = contract(spec());
+        assert_eq!(
+            contract.sample(&json!({ "$ref": "#/components/schemas/NewUser" })),
+            json!({ "name": "a", "age": 0 })
+        );
+        assert_eq!(
+            contract.sample(&json!({ "type": "integer", "exclusiveMinimum": true, "minimum": 5 })),
+            json!(6)
+        );
+        assert_eq!(
+            contract.sample(&json!({ "type": "string", "format": "date" })),
+            json!("2000-01-01")
+        );
+    }
+}
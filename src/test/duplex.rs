@@ -0,0 +1,130 @@
+use {
+    bytes::BytesMut,
+    futures::{
+        task::{self, Task},
+        Async, Poll,
+    },
+    std::{
+        io,
+        sync::{Arc, Mutex},
+    },
+    tokio::io::{AsyncRead, AsyncWrite},
+};
+
+/// Creates a pair of in-memory streams connected to each other.
+///
+/// The data written to one of the streams can be read from the other one.
+/// When a stream is shut down or dropped, the peer receives EOF.
+pub fn duplex() -> (DuplexStream, DuplexStream) {
+    let a = Arc::new(Mutex::new(Pipe::default()));
+    let b = Arc::new(Mutex::new(Pipe::default()));
+    (
+        DuplexStream {
+            read: a.clone(),
+            write: b.clone(),
+        },
+        DuplexStream { read: b, write: a },
+    )
+}
+
+/// One end of the in-memory stream created by `duplex`.
+#[derive(Debug)]
+pub struct DuplexStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+}
+
+#[derive(Debug, Default)]
+struct Pipe {
+    buf: BytesMut,
+    closed: bool,
+    reader: Option<Task>,
+}
+
+impl Pipe {
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(task) = self.reader.take() {
+            task.notify();
+        }
+    }
+}
+
+impl io::Read for DuplexStream {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.read.lock().unwrap();
+        if pipe.buf.is_empty() {
+            if pipe.closed {
+                return Ok(0);
+            }
+            pipe.reader = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = std::cmp::min(dst.len(), pipe.buf.len());
+        dst[..n].copy_from_slice(&pipe.buf[..n]);
+        pipe.buf.advance(n);
+        Ok(n)
+    }
+}
+
+impl io::Write for DuplexStream {
+    fn write(&mut self, src: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.write.lock().unwrap();
+        if pipe.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        pipe.buf.extend_from_slice(src);
+        if let Some(task) = pipe.reader.take() {
+            task.notify();
+        }
+        Ok(src.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for DuplexStream {}
+
+impl AsyncWrite for DuplexStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.write.lock().unwrap().close();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        self.write.lock().unwrap().close();
+        self.read.lock().unwrap().close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        futures::Future,
+        tokio::{io as tokio_io, runtime::current_thread::Runtime},
+    };
+
+    #[test]
+    fn test_duplex() {
+        let (client, server) = duplex();
+        let mut rt = Runtime::new().unwrap();
+
+        let echo = tokio_io::read_exact(server, [0u8; 5])
+            .and_then(|(server, buf)| tokio_io::write_all(server, buf))
+            .map(drop);
+        rt.spawn(echo.map_err(|e| panic!("{}", e)));
+
+        let (_client, buf) = rt
+            .block_on(
+                tokio_io::write_all(client, b"hello")
+                    .and_then(|(client, _)| tokio_io::read_exact(client, [0u8; 5])),
+            )
+            .unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+}
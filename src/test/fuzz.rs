@@ -0,0 +1,185 @@
+use {
+    super::{ReqBody, TestRunner},
+    crate::endpoint::Endpoint,
+    http::{Request, Uri},
+    std::{
+        fmt::Write as _,
+        panic::{self, AssertUnwindSafe},
+    },
+};
+
+const SEGMENTS: &[&str] = &[
+    "",
+    "%",
+    "%2",
+    "%zz",
+    "%00",
+    "%FF",
+    "%C0%AF",
+    "%E3%81%82",
+    "%2F",
+    "..",
+    "..%2F..",
+    ".%2E",
+    "a%20b",
+    "+",
+    "-1",
+    "0",
+    "-0",
+    "1e309",
+    "NaN",
+    "18446744073709551616",
+    "-9223372036854775809",
+    "9999999999999999999999999999999999999999",
+];
+
+const QUERIES: &[&str] = &[
+    "",
+    "=",
+    "&&",
+    "%",
+    "%FF=%FF",
+    "a",
+    "a=",
+    "=a",
+    "a=1&a=2",
+    "a[b]=c",
+    "a[=b",
+    "a=%00",
+    "a=%zz",
+    "a=18446744073709551616",
+];
+
+/// Generates the list of unusual paths derived from the specified base path.
+///
+/// The generated paths contain the edge cases which tend to cause panics in
+/// routing, such as malformed percent-encodings, invalid UTF-8 sequences,
+/// empty segments and numbers out of the range of integer types.
+pub fn fuzz_corpus(base: &str) -> Vec<String> {
+    let base = base.trim_end_matches('/');
+    let mut corpus = vec![];
+    for segment in SEGMENTS {
+        corpus.push(format!("{}/{}", base, segment));
+        corpus.push(format!("{}/{}/", base, segment));
+        corpus.push(format!("{}/{}/{}", base, segment, segment));
+    }
+    for query in QUERIES {
+        corpus.push(format!("{}/?{}", base, query));
+        corpus.push(format!(
+            "{}?{}",
+            if base.is_empty() { "/" } else { base },
+            query
+        ));
+    }
+    corpus
+}
+
+/// Applies the requests with the specified paths to the endpoint, and checks
+/// that all of them are handled gracefully.
+///
+/// Each input is treated as the request target of a `GET` request, and the inputs
+/// which cannot be parsed as an URI are skipped since such requests would be
+/// rejected by the server. The check fails if applying a request panics, or if the
+/// returned error is not a client error (4xx).
+///
+/// The corpus can be any iterator of strings, such as the paths generated by
+/// `fuzz_corpus` or the values generated by the strategies of property-based
+/// testing libraries.
+///
+/// # Panics
+///
+/// This function panics with the list of failed inputs if any check fails.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax::path;
+/// # use finchers::test;
+/// let endpoint = path!(@get "/users/<u32>").map(|id: u32| format!("user {}", id));
+///
+/// test::fuzz_paths(endpoint, test::fuzz_corpus("/users"));
+/// ```
+pub fn fuzz_paths<E, I>(endpoint: E, corpus: I)
+where
+    E: Endpoint<ReqBody>,
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let mut runner = TestRunner::new(endpoint).expect("failed to start the runtime");
+
+    let mut failures = String::new();
+    let mut num_failures = 0;
+    for input in corpus {
+        let input = input.as_ref();
+        let uri = match input.parse::<Uri>() {
+            Ok(uri) => uri,
+            Err(..) => continue,
+        };
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            runner.apply_raw(Request::get(uri)).map(drop)
+        }));
+        let failure = match result {
+            Ok(Ok(())) => continue,
+            Ok(Err(ref err)) if err.status_code().is_client_error() => continue,
+            Ok(Err(err)) => format!("unexpected error ({}): {}", err.status_code(), err),
+            Err(payload) => {
+                let msg = payload
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "<non-string payload>".into());
+                format!("panicked: {}", msg)
+            }
+        };
+        num_failures += 1;
+        let _ = writeln!(failures, "  {:?}: {}", input, failure);
+    }
+
+    if num_failures > 0 {
+        panic!(
+            "{} input(s) were not handled gracefully:\n{}",
+            num_failures, failures
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            endpoint::{syntax, EndpointExt},
+            endpoints::query,
+        },
+        serde::Deserialize,
+    };
+
+    #[test]
+    fn test_fuzz_paths() {
+        #[derive(Debug, Deserialize)]
+        struct Param {
+            #[allow(dead_code)]
+            a: Option<u64>,
+        }
+
+        let endpoint = syntax::param::<u32>()
+            .and(syntax::eos())
+            .and(query::required::<Param>())
+            .map(|id: u32, _: Param| id);
+        fuzz_paths(endpoint, fuzz_corpus("/"));
+    }
+
+    #[test]
+    #[should_panic(expected = "panicked: boom")]
+    fn test_fuzz_paths_panic() {
+        let endpoint = syntax::param::<String>().map(|s: String| {
+            if s == "boom" {
+                panic!("boom");
+            }
+            s
+        });
+        fuzz_paths(endpoint, vec!["/foo", "/boom"]);
+    }
+}
@@ -0,0 +1,704 @@
+use {
+    super::{line_diff, ReqBody, TestResult, TestRunner},
+    crate::{
+        endpoint::Endpoint,
+        middleware::audit::{AuditRecord, REDACTED},
+        output::IntoResponse,
+    },
+    bytes::Bytes,
+    failure::format_err,
+    http::{
+        header::{HeaderMap, HeaderName, HeaderValue},
+        Method, Request, StatusCode, Uri,
+    },
+    izanami_util::buf_stream::BufStream,
+    serde_json::Value,
+    std::{fmt, fmt::Write as _, fs, path::Path},
+};
+
+/// A recorded pair of request and response, which can be replayed by `replay`.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    /// The method of the request.
+    pub method: Method,
+    /// The URI of the request, in the origin form.
+    pub uri: Uri,
+    /// The request headers.
+    pub request_headers: HeaderMap,
+    /// The request body.
+    pub request_body: Bytes,
+    /// Whether the recorded request body has been truncated.
+    pub request_truncated: bool,
+    /// The status code of the response.
+    pub status: StatusCode,
+    /// The response headers.
+    pub response_headers: HeaderMap,
+    /// The response body.
+    pub response_body: Bytes,
+    /// Whether the recorded response body has been truncated.
+    pub response_truncated: bool,
+}
+
+impl<'a> From<&'a AuditRecord> for Exchange {
+    fn from(record: &'a AuditRecord) -> Self {
+        let uri = record
+            .uri
+            .path_and_query()
+            .map_or_else(|| Uri::from_static("/"), |p| p.as_str().parse().unwrap());
+        Exchange {
+            method: record.method.clone(),
+            uri,
+            request_headers: record.request_headers.clone(),
+            request_body: record.request_body.data.clone().into(),
+            request_truncated: record.request_body.truncated,
+            status: record.status,
+            response_headers: record.response_headers.clone(),
+            response_body: record.response_body.data.clone().into(),
+            response_truncated: record.response_body.truncated || !record.completed,
+        }
+    }
+}
+
+/// A sequence of recorded exchanges.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    exchanges: Vec<Exchange>,
+}
+
+impl Recording {
+    /// Creates a recording from the entries in a HAR (HTTP Archive) document,
+    /// e.g. the one exported by `HarRecorder`.
+    ///
+    /// The bodies encoded in base64 are not supported, and such entries are
+    /// treated as truncated.
+    pub fn from_har(har: &Value) -> Result<Recording, failure::Error> {
+        let entries = har
+            .pointer("/log/entries")
+            .and_then(Value::as_array)
+            .ok_or_else(|| format_err!("missing `log.entries'"))?;
+        let exchanges = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                from_har_entry(entry).map_err(|err| format_err!("invalid entry #{}: {}", i, err))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Recording { exchanges })
+    }
+
+    /// Reads a HAR document from the file and creates a recording from it.
+    pub fn from_har_file(path: impl AsRef<Path>) -> Result<Recording, failure::Error> {
+        let har: Value = serde_json::from_slice(&fs::read(path)?)?;
+        Recording::from_har(&har)
+    }
+
+    /// Returns the exchanges in the recording.
+    pub fn exchanges(&self) -> &[Exchange] {
+        &self.exchanges
+    }
+}
+
+impl From<Vec<Exchange>> for Recording {
+    fn from(exchanges: Vec<Exchange>) -> Self {
+        Recording { exchanges }
+    }
+}
+
+impl<'a> From<&'a [AuditRecord]> for Recording {
+    fn from(records: &'a [AuditRecord]) -> Self {
+        Recording {
+            exchanges: records.iter().map(Exchange::from).collect(),
+        }
+    }
+}
+
+impl From<Vec<AuditRecord>> for Recording {
+    fn from(records: Vec<AuditRecord>) -> Self {
+        Recording::from(&records[..])
+    }
+}
+
+fn from_har_entry(entry: &Value) -> Result<Exchange, failure::Error> {
+    let request = &entry["request"];
+    let response = &entry["response"];
+    let str_field = |value: &Value, name: &str| {
+        value[name]
+            .as_str()
+            .map(ToOwned::to_owned)
+            .ok_or_else(|| format_err!("missing `{}'", name))
+    };
+
+    let url = str_field(request, "url")?;
+    let uri: Uri = url.parse()?;
+    let uri = uri
+        .path_and_query()
+        .map_or("/", |p| p.as_str())
+        .parse()
+        .expect("should be a valid URI");
+    let (request_body, request_truncated) = har_body(&request["postData"]);
+    let (response_body, response_truncated) = har_body(&response["content"]);
+    let status = response["status"]
+        .as_u64()
+        .ok_or_else(|| format_err!("missing `status'"))?;
+    Ok(Exchange {
+        method: str_field(request, "method")?.parse()?,
+        uri,
+        request_headers: har_headers(&request["headers"])?,
+        request_body,
+        request_truncated,
+        status: StatusCode::from_u16(status as u16)?,
+        response_headers: har_headers(&response["headers"])?,
+        response_body,
+        response_truncated: response_truncated || response["_completed"].as_bool() == Some(false),
+    })
+}
+
+fn har_headers(headers: &Value) -> Result<HeaderMap, failure::Error> {
+    let mut map = HeaderMap::new();
+    for header in headers.as_array().into_iter().flatten() {
+        let name = header["name"].as_str().unwrap_or("");
+        let value = header["value"].as_str().unwrap_or("");
+        // HTTP/2 pseudo headers exported by the browsers are skipped.
+        if name.starts_with(':') {
+            continue;
+        }
+        map.append(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+    Ok(map)
+}
+
+fn har_body(content: &Value) -> (Bytes, bool) {
+    if content.is_null() {
+        return (Bytes::new(), false);
+    }
+    let truncated = content["_truncated"].as_bool() == Some(true)
+        || content.get("encoding").is_some()
+        || content.get("comment").is_some();
+    let text = content["text"].as_str().unwrap_or("");
+    (Bytes::from(text.as_bytes()), truncated)
+}
+
+// ==== Replay ====
+
+/// Creates a replay test from the recorded exchanges.
+///
+/// The recorded requests are applied to the endpoint in order, and the
+/// responses are compared with the recorded ones, so that a refactoring
+/// of the endpoint can be checked against the traffic in production or
+/// staging environments. The exchanges can be taken from the audit records
+/// (`AuditRecord`) or the HAR documents exported by `HarRecorder` (see
+/// `Recording::from_har`).
+///
+/// The values redacted by the audit middleware are handled as follows:
+///
+/// * The exchanges whose request contains a redacted value are skipped,
+///   unless the redacted header is replaced by `Replay::header`, since the
+///   endpoint would not behave as recorded.
+/// * The redacted values in the recorded response match any value.
+///
+/// The exchanges with a truncated request body are skipped, and only the
+/// recorded prefix of a truncated response body is compared.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::endpoint::syntax::path;
+/// # use finchers::output;
+/// # use finchers::test::{self, Recording};
+/// # use serde_json::json;
+/// let har = json!({
+///     "log": {
+///         "version": "1.2",
+///         "entries": [{
+///             "request": {
+///                 "method": "GET",
+///                 "url": "http://localhost/users/42",
+///                 "headers": [],
+///             },
+///             "response": {
+///                 "status": 200,
+///                 "headers": [{ "name": "content-type", "value": "application/json" }],
+///                 "content": { "text": "{\"id\":42,\"fetched_at\":1546300800}" },
+///             },
+///         }],
+///     }
+/// });
+///
+/// let endpoint = path!(@get "/users/<u32>")
+///     .map(|id: u32| output::Json(json!({ "id": id, "fetched_at": 1700000000 })));
+///
+/// let report = test::replay(Recording::from_har(&har).unwrap())
+///     .ignore_fields(&["fetched_at"])
+///     .run(endpoint);
+/// assert_eq!(report.replayed(), 1);
+/// ```
+pub fn replay(recording: impl Into<Recording>) -> Replay {
+    Replay {
+        recording: recording.into(),
+        ignore_headers: DEFAULT_IGNORE_HEADERS
+            .iter()
+            .map(|&name| HeaderName::from_static(name))
+            .collect(),
+        ignore_fields: vec![],
+        headers: HeaderMap::new(),
+        filter: None,
+    }
+}
+
+/// The headers which are set by the server or the proxies, rather than the endpoint.
+const DEFAULT_IGNORE_HEADERS: &[&str] = &[
+    "date",
+    "server",
+    "connection",
+    "keep-alive",
+    "content-length",
+    "transfer-encoding",
+];
+
+type FilterFn = dyn Fn(&Exchange) -> bool;
+
+/// A replay test created by `replay`.
+pub struct Replay {
+    recording: Recording,
+    ignore_headers: Vec<HeaderName>,
+    ignore_fields: Vec<String>,
+    headers: HeaderMap,
+    filter: Option<Box<FilterFn>>,
+}
+
+impl fmt::Debug for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Replay")
+            .field("recording", &self.recording)
+            .field("ignore_headers", &self.ignore_headers)
+            .field("ignore_fields", &self.ignore_fields)
+            .field("headers", &self.headers)
+            .finish()
+    }
+}
+
+/// The summary of a replay test returned from `Replay::run`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayReport {
+    replayed: usize,
+    skipped: usize,
+}
+
+impl ReplayReport {
+    /// Returns the number of exchanges which have been replayed.
+    pub fn replayed(&self) -> usize {
+        self.replayed
+    }
+
+    /// Returns the number of exchanges which have been skipped.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+}
+
+impl Replay {
+    /// Sets the names of the response headers which are not compared.
+    ///
+    /// By default, the headers set by the server rather than the endpoint
+    /// (`Date`, `Server`, `Connection`, `Keep-Alive`, `Content-Length` and
+    /// `Transfer-Encoding`) are ignored.
+    ///
+    /// # Panics
+    /// This method panics if any of the names is invalid.
+    pub fn ignore_headers(mut self, names: &[&str]) -> Self {
+        self.ignore_headers = names
+            .iter()
+            .map(|name| name.parse().expect("invalid header name"))
+            .collect();
+        self
+    }
+
+    /// Sets the fields ignored in the JSON response bodies, e.g. the timestamps
+    /// and generated identifiers.
+    ///
+    /// A field starting with `/` is a JSON pointer from the root, and the others
+    /// are the names of the object fields at any depth.
+    pub fn ignore_fields(mut self, fields: &[&str]) -> Self {
+        self.ignore_fields = fields.iter().map(|&field| field.to_owned()).collect();
+        self
+    }
+
+    /// Sets a request header of all replayed requests, replacing the recorded
+    /// one (e.g. the credential redacted in the recording).
+    ///
+    /// # Panics
+    /// This method panics if the name or value is invalid.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        let name: HeaderName = name.parse().expect("invalid header name");
+        let value: HeaderValue = value.parse().expect("invalid header value");
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sets the function which selects the exchanges to replay.
+    pub fn filter<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Exchange) -> bool + 'static,
+    {
+        self.filter = Some(Box::new(f));
+        self
+    }
+
+    /// Replays the recorded exchanges against the specified endpoint.
+    ///
+    /// # Panics
+    ///
+    /// This method panics with the differences of the responses if any of
+    /// the replayed responses does not match the recorded one.
+    pub fn run<E>(&self, endpoint: E) -> ReplayReport
+    where
+        E: Endpoint<ReqBody>,
+        E::Output: IntoResponse,
+        <E::Output as IntoResponse>::Body: BufStream,
+        <<E::Output as IntoResponse>::Body as BufStream>::Error:
+            Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        let mut runner = TestRunner::new(endpoint).expect("failed to start the runtime");
+        let mut report = ReplayReport {
+            replayed: 0,
+            skipped: 0,
+        };
+        let mut failures = String::new();
+        let mut num_failures = 0;
+        for (i, exchange) in self.recording.exchanges.iter().enumerate() {
+            if self.filter.as_ref().map_or(false, |f| !f(exchange)) || !self.is_replayable(exchange)
+            {
+                report.skipped += 1;
+                continue;
+            }
+            report.replayed += 1;
+
+            let mut request = Request::new(exchange.request_body.to_vec());
+            *request.method_mut() = exchange.method.clone();
+            *request.uri_mut() = exchange.uri.clone();
+            *request.headers_mut() = exchange.request_headers.clone();
+            for (name, value) in &self.headers {
+                request.headers_mut().insert(name, value.clone());
+            }
+
+            let diff = match runner.perform(request) {
+                Ok(response) => self.diff(exchange, &response),
+                Err(err) => format!("failed to receive the response: {}\n", err),
+            };
+            if !diff.is_empty() {
+                num_failures += 1;
+                let _ = write!(
+                    failures,
+                    "#{} {} {}:\n{}",
+                    i, exchange.method, exchange.uri, diff
+                );
+            }
+        }
+
+        if num_failures > 0 {
+            panic!(
+                "{} of {} replayed response(s) differ from the recording:\n{}",
+                num_failures, report.replayed, failures
+            );
+        }
+        report
+    }
+
+    fn is_replayable(&self, exchange: &Exchange) -> bool {
+        if exchange.request_truncated {
+            return false;
+        }
+        let redacted_header = exchange
+            .request_headers
+            .iter()
+            .any(|(name, value)| value == REDACTED && !self.headers.contains_key(name));
+        let redacted_body = exchange
+            .request_body
+            .windows(REDACTED.len())
+            .any(|window| window == REDACTED.as_bytes());
+        !redacted_header && !redacted_body
+    }
+
+    fn diff(&self, exchange: &Exchange, response: &TestResult) -> String {
+        let mut diff = String::new();
+        if response.status() != exchange.status {
+            let _ = writeln!(
+                diff,
+                "  status\n  - {}\n  + {}",
+                exchange.status,
+                response.status()
+            );
+        }
+
+        for name in exchange.response_headers.keys() {
+            if self.ignore_headers.contains(name) {
+                continue;
+            }
+            let expected: Vec<_> = exchange.response_headers.get_all(name).iter().collect();
+            let actual: Vec<_> = response.headers().get_all(name).iter().collect();
+            if expected.iter().any(|value| *value == REDACTED) {
+                continue;
+            }
+            if expected != actual {
+                let _ = writeln!(diff, "  header `{}`", name);
+                for value in expected {
+                    let _ = writeln!(diff, "  - {}", String::from_utf8_lossy(value.as_bytes()));
+                }
+                for value in actual {
+                    let _ = writeln!(diff, "  + {}", String::from_utf8_lossy(value.as_bytes()));
+                }
+            }
+        }
+
+        let body_diff = self.diff_body(exchange, response.body());
+        if !body_diff.is_empty() {
+            let _ = writeln!(diff, "  body");
+            for line in body_diff.lines() {
+                let _ = writeln!(diff, "  {}", line);
+            }
+        }
+        diff
+    }
+
+    fn diff_body(&self, exchange: &Exchange, body: &Bytes) -> String {
+        let expected = &exchange.response_body;
+        if exchange.response_truncated {
+            if body.starts_with(expected) {
+                return String::new();
+            }
+            let actual = &body[..std::cmp::min(body.len(), expected.len())];
+            return line_diff(
+                &String::from_utf8_lossy(expected),
+                &String::from_utf8_lossy(actual),
+            );
+        }
+
+        let json = serde_json::from_slice::<Value>(expected)
+            .and_then(|expected| serde_json::from_slice::<Value>(body).map(|a| (expected, a)));
+        match json {
+            Ok((mut expected, mut actual)) => {
+                for field in &self.ignore_fields {
+                    remove_field(&mut expected, field);
+                    remove_field(&mut actual, field);
+                }
+                mask_redacted(&expected, &mut actual);
+                if expected == actual {
+                    return String::new();
+                }
+                line_diff(
+                    &serde_json::to_string_pretty(&expected).expect("serializable"),
+                    &serde_json::to_string_pretty(&actual).expect("serializable"),
+                )
+            }
+            Err(..) if expected == body => String::new(),
+            Err(..) => line_diff(
+                &String::from_utf8_lossy(expected),
+                &String::from_utf8_lossy(body),
+            ),
+        }
+    }
+}
+
+fn remove_field(value: &mut Value, field: &str) {
+    if field.starts_with('/') {
+        let (parent, name) = match field.rfind('/') {
+            Some(pos) => (&field[..pos], &field[pos + 1..]),
+            None => return,
+        };
+        match value.pointer_mut(parent) {
+            Some(Value::Object(map)) => {
+                map.remove(name);
+            }
+            Some(Value::Array(items)) => {
+                if let Ok(index) = name.parse::<usize>() {
+                    if index < items.len() {
+                        items[index] = Value::Null;
+                    }
+                }
+            }
+            _ => {}
+        }
+        return;
+    }
+    match value {
+        Value::Object(map) => {
+            map.remove(field);
+            for value in map.values_mut() {
+                remove_field(value, field);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                remove_field(item, field);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replaces the values in `actual` at the positions redacted in `expected`.
+fn mask_redacted(expected: &Value, actual: &mut Value) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, expected) in expected {
+                if let Some(actual) = actual.get_mut(key) {
+                    if *expected == REDACTED {
+                        *actual = Value::String(REDACTED.into());
+                    } else {
+                        mask_redacted(expected, actual);
+                    }
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for (expected, actual) in expected.iter().zip(actual) {
+                mask_redacted(expected, actual);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            endpoint::{syntax, EndpointExt},
+            endpoints::header,
+            middleware::{
+                audit::{AuditSink, CapturedBody},
+                har::HarRecorder,
+            },
+            output::Json,
+        },
+        futures::Future,
+        http::Version,
+        serde_json::json,
+        std::time::{Duration, SystemTime},
+    };
+
+    fn record(
+        uri: &'static str,
+        request_headers: &[(&'static str, &'static str)],
+        body: &str,
+    ) -> AuditRecord {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            pairs
+                .iter()
+                .map(|&(name, value)| {
+                    (
+                        HeaderName::from_static(name),
+                        HeaderValue::from_static(value),
+                    )
+                })
+                .collect::<HeaderMap>()
+        };
+        AuditRecord {
+            timestamp: SystemTime::now(),
+            elapsed: Duration::from_millis(3),
+            method: Method::GET,
+            uri: Uri::from_static(uri),
+            version: Version::HTTP_11,
+            request_headers: headers(request_headers),
+            request_body: CapturedBody::default(),
+            status: StatusCode::OK,
+            response_headers: headers(&[
+                ("content-type", "application/json"),
+                ("date", "Tue, 01 Jan 2019 00:00:00 GMT"),
+            ]),
+            response_body: CapturedBody {
+                data: body.as_bytes().to_vec(),
+                size: body.len() as u64,
+                truncated: false,
+            },
+            completed: true,
+        }
+    }
+
+    fn endpoint(
+        version: u32,
+    ) -> impl Endpoint<ReqBody, Output = (Json<Value>,)> + Send + Sync + 'static {
+        syntax::segment("users")
+            .and(syntax::param::<u32>())
+            .and(header::optional::<String>("authorization"))
+            .map(move |id: u32, auth: Option<String>| {
+                Json(json!({
+                    "id": id,
+                    "version": version,
+                    "token": auth,
+                }))
+            })
+    }
+
+    #[test]
+    fn test_replay_audit_records() {
+        let records = vec![
+            record("/users/1", &[], r#"{"id":1,"version":1,"token":null}"#),
+            record("/users/2", &[], r#"{"id":2,"version":1,"token":null}"#),
+        ];
+        let report = replay(records.clone()).run(endpoint(1));
+        assert_eq!(report.replayed(), 2);
+        assert_eq!(report.skipped(), 0);
+
+        let report = replay(records)
+            .ignore_fields(&["version"])
+            .filter(|exchange| exchange.uri.path() != "/users/2")
+            .run(endpoint(2));
+        assert_eq!(report.replayed(), 1);
+        assert_eq!(report.skipped(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "1 of 1 replayed response(s) differ from the recording:\n\
+                               #0 GET /users/1:\n  body")]
+    fn test_replay_mismatch() {
+        let records = vec![record(
+            "/users/1",
+            &[],
+            r#"{"id":1,"version":1,"token":null}"#,
+        )];
+        replay(records).run(endpoint(2));
+    }
+
+    #[test]
+    fn test_replay_redacted() {
+        let records = vec![record(
+            "/users/1",
+            &[("authorization", "[REDACTED]")],
+            r#"{"id":1,"version":1,"token":"[REDACTED]"}"#,
+        )];
+        let report = replay(records.clone()).run(endpoint(1));
+        assert_eq!(report.replayed(), 0);
+        assert_eq!(report.skipped(), 1);
+
+        let report = replay(records)
+            .header("authorization", "Bearer fake")
+            .run(endpoint(1));
+        assert_eq!(report.replayed(), 1);
+    }
+
+    #[test]
+    fn test_replay_har() {
+        let recorder = HarRecorder::new(10);
+        recorder
+            .record(record(
+                "/users/1?verbose=true",
+                &[],
+                r#"{"id":1,"version":1,"token":null}"#,
+            ))
+            .wait()
+            .unwrap();
+
+        let recording = Recording::from_har(&recorder.to_har()).unwrap();
+        assert_eq!(recording.exchanges().len(), 1);
+        assert_eq!(recording.exchanges()[0].uri, "/users/1?verbose=true");
+
+        let report = replay(recording).run(endpoint(1));
+        assert_eq!(report.replayed(), 1);
+    }
+}
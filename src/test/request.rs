@@ -0,0 +1,261 @@
+use {
+    super::{imp::TestRequestImpl, IntoReqBody, ReqBody, TestRequest},
+    bytes::Bytes,
+    http::{
+        header::{self, HeaderName, HeaderValue},
+        HttpTryFrom, Method, Request, Uri,
+    },
+    mime::Mime,
+    serde::Serialize,
+};
+
+/// Creates a builder of a request used in the test runner.
+///
+/// # Example
+///
+/// ```
+/// # use finchers::prelude::*;
+/// # use finchers::test;
+/// # #[derive(serde::Deserialize)]
+/// # struct Param { name: String }
+/// let mut runner = test::runner(endpoints::body::json::<Param>());
+///
+/// let param = runner
+///     .apply(
+///         test::request()
+///             .post("/")
+///             .json(&serde_json::json!({ "name": "alice" })),
+///     )
+///     .unwrap();
+/// assert_eq!(param.name, "alice");
+/// ```
+pub fn request() -> RequestBuilder {
+    RequestBuilder {
+        builder: Request::builder(),
+        body: None,
+        cookies: vec![],
+    }
+}
+
+/// A builder of an HTTP request which implements `TestRequest`.
+#[derive(Debug)]
+pub struct RequestBuilder {
+    builder: http::request::Builder,
+    body: Option<Body>,
+    cookies: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Body {
+    data: Bytes,
+    content_type: Option<Mime>,
+    typed: bool,
+}
+
+macro_rules! define_methods {
+    ($($name:ident => $method:ident, $doc:expr;)*) => {$(
+        #[doc = $doc]
+        pub fn $name<T>(self, uri: T) -> Self
+        where
+            Uri: HttpTryFrom<T>,
+        {
+            self.method(Method::$method).uri(uri)
+        }
+    )*};
+}
+
+impl RequestBuilder {
+    /// Sets the request method.
+    pub fn method<T>(mut self, method: T) -> Self
+    where
+        Method: HttpTryFrom<T>,
+    {
+        self.builder.method(method);
+        self
+    }
+
+    /// Sets the request URI.
+    pub fn uri<T>(mut self, uri: T) -> Self
+    where
+        Uri: HttpTryFrom<T>,
+    {
+        self.builder.uri(uri);
+        self
+    }
+
+    define_methods! {
+        get => GET, "Sets the request method to `GET` and the request URI.";
+        post => POST, "Sets the request method to `POST` and the request URI.";
+        put => PUT, "Sets the request method to `PUT` and the request URI.";
+        delete => DELETE, "Sets the request method to `DELETE` and the request URI.";
+        head => HEAD, "Sets the request method to `HEAD` and the request URI.";
+        patch => PATCH, "Sets the request method to `PATCH` and the request URI.";
+        options => OPTIONS, "Sets the request method to `OPTIONS` and the request URI.";
+    }
+
+    /// Appends a header field to the request.
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        HeaderName: HttpTryFrom<K>,
+        HeaderValue: HttpTryFrom<V>,
+    {
+        self.builder.header(name, value);
+        self
+    }
+
+    /// Appends a cookie to the request.
+    ///
+    /// The values of cookies are sent in a single `Cookie` header field.
+    pub fn cookie(mut self, name: &str, value: &str) -> Self {
+        self.cookies.push(format!("{}={}", name, value));
+        self
+    }
+
+    /// Sets the message body of the request.
+    pub fn body(mut self, body: impl IntoReqBody) -> Self {
+        let content_type = body.content_type();
+        let data = body.into_req_body().data;
+        self.body = Some(Body {
+            data: data.unwrap_or_default(),
+            content_type,
+            typed: false,
+        });
+        self
+    }
+
+    /// Sets a text as the message body of the request.
+    ///
+    /// The header fields `Content-type` and `Content-length` are automatically
+    /// set if they are not specified.
+    pub fn text(self, body: impl Into<String>) -> Self {
+        self.typed_body(body.into().into(), mime::TEXT_PLAIN_UTF_8)
+    }
+
+    /// Sets a JSON value as the message body of the request.
+    ///
+    /// The header fields `Content-type` and `Content-length` are automatically
+    /// set if they are not specified.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if the value cannot be serialized into JSON.
+    pub fn json<T>(self, value: &T) -> Self
+    where
+        T: Serialize + ?Sized,
+    {
+        let body = serde_json::to_vec(value).expect("failed to serialize the value into JSON");
+        self.typed_body(body.into(), mime::APPLICATION_JSON)
+    }
+
+    /// Sets a value encoded as `application/x-www-form-urlencoded` as the
+    /// message body of the request.
+    ///
+    /// The header fields `Content-type` and `Content-length` are automatically
+    /// set if they are not specified.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if the value cannot be serialized.
+    pub fn form<T>(self, value: &T) -> Self
+    where
+        T: Serialize,
+    {
+        let body = serde_qs::to_string(value).expect("failed to serialize the value");
+        self.typed_body(body.into(), mime::APPLICATION_WWW_FORM_URLENCODED)
+    }
+
+    fn typed_body(mut self, data: Bytes, content_type: Mime) -> Self {
+        self.body = Some(Body {
+            data,
+            content_type: Some(content_type),
+            typed: true,
+        });
+        self
+    }
+}
+
+impl TestRequest for RequestBuilder {}
+impl TestRequestImpl for RequestBuilder {
+    fn into_request(mut self) -> http::Result<Request<ReqBody>> {
+        let mut request = self.builder.body(ReqBody::new(Bytes::new()))?;
+
+        if !self.cookies.is_empty() {
+            let cookies = self.cookies.join("; ");
+            request
+                .headers_mut()
+                .append(header::COOKIE, HeaderValue::try_from(cookies)?);
+        }
+
+        if let Some(body) = self.body {
+            if let Some(content_type) = body.content_type {
+                request
+                    .headers_mut()
+                    .entry(header::CONTENT_TYPE)
+                    .unwrap()
+                    .or_insert(HeaderValue::try_from(content_type.as_ref())?);
+            }
+            if body.typed {
+                request
+                    .headers_mut()
+                    .entry(header::CONTENT_LENGTH)
+                    .unwrap()
+                    .or_insert(HeaderValue::from(body.data.len()));
+            }
+            *request.body_mut() = ReqBody::new(body.data);
+        }
+
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_body() {
+        #[derive(Serialize)]
+        struct Login {
+            user: &'static str,
+            pass: &'static str,
+        }
+
+        let request = request()
+            .post("/login")
+            .cookie("session", "xxx")
+            .cookie("lang", "ja")
+            .form(&Login {
+                user: "alice",
+                pass: "secret",
+            })
+            .into_request()
+            .unwrap();
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.uri(), "/login");
+        assert_eq!(request.headers()["cookie"], "session=xxx; lang=ja");
+        assert_eq!(
+            request.headers()["content-type"],
+            "application/x-www-form-urlencoded"
+        );
+        assert_eq!(request.headers()["content-length"], "22");
+        assert_eq!(
+            request.body().data.as_ref().map(|b| &b[..]),
+            Some(&b"user=alice&pass=secret"[..])
+        );
+    }
+
+    #[test]
+    fn test_explicit_content_type() {
+        let request = request()
+            .put("/")
+            .header("content-type", "application/vnd.api+json")
+            .json(&vec![1, 2])
+            .into_request()
+            .unwrap();
+        assert_eq!(
+            request.headers()["content-type"],
+            "application/vnd.api+json"
+        );
+        assert_eq!(request.headers()["content-length"], "5");
+    }
+}
@@ -0,0 +1,256 @@
+use {
+    super::{duplex, set_default_headers, DuplexStream, TestRequest, TestResult},
+    crate::{
+        endpoint::Endpoint,
+        output::IntoResponse,
+        server::{LiftedHttpServiceFuture, RequestBody, WrappedBodyStream},
+        service::{AppFuture, AppService, ResponseBody},
+    },
+    futures::{future, Future, Stream},
+    http::{Request, Response},
+    hyper::{
+        client::conn::{self as client_conn, SendRequest},
+        server::conn::Http,
+    },
+    izanami_util::buf_stream::BufStream,
+    std::{error::Error as StdError, io, sync::Arc},
+    tokio::{executor::current_thread::TaskExecutor, runtime::current_thread::Runtime},
+};
+
+type CritError = Box<dyn StdError + Send + Sync + 'static>;
+
+/// A helper function for creating a new `TestServer` from the specified endpoint.
+pub fn serve<E>(endpoint: E) -> TestServer<E>
+where
+    E: Endpoint<ServerReqBody> + 'static,
+{
+    TestServer::new(endpoint).expect("failed to start the runtime")
+}
+
+/// The type of request body used in `TestServer`.
+///
+/// This type wraps the message body received by Hyper's HTTP server.
+pub type ServerReqBody = RequestBody;
+
+/// A test server which serves an endpoint using Hyper's HTTP/1 implementation
+/// over in-memory connections.
+///
+/// Unlike `TestRunner`, the requests and responses go through the actual
+/// HTTP encoding, so this is useful for testing the behavior depending on the
+/// protocol such as keep-alive, `Expect: 100-continue` and the framing of
+/// message bodies.
+///
+/// The requests are sent over the same connection as long as the server
+/// keeps it alive.
+#[derive(Debug)]
+pub struct TestServer<E> {
+    endpoint: Arc<E>,
+    rt: Runtime,
+    protocol: Http<TaskExecutor>,
+    sender: Option<SendRequest<hyper::Body>>,
+    connections: usize,
+}
+
+#[allow(clippy::new_ret_no_self)]
+impl<E> TestServer<E>
+where
+    E: Endpoint<ServerReqBody> + 'static,
+{
+    /// Create a `TestServer` from the specified endpoint.
+    pub fn new(endpoint: E) -> io::Result<TestServer<E>> {
+        Runtime::new().map(|rt| TestServer::with_runtime(endpoint, rt))
+    }
+
+    /// Create a `TestServer` from the specified endpoint with a Tokio runtime.
+    pub fn with_runtime(endpoint: E, rt: Runtime) -> TestServer<E> {
+        let mut protocol = Http::new().with_executor(TaskExecutor::current());
+        protocol.http1_only(true);
+        TestServer {
+            endpoint: Arc::new(endpoint),
+            rt,
+            protocol,
+            sender: None,
+            connections: 0,
+        }
+    }
+
+    /// Returns a reference to the Tokio runtime managed by this server.
+    pub fn runtime(&mut self) -> &mut Runtime {
+        &mut self.rt
+    }
+
+    /// Returns a reference to the HTTP configuration used for the new connections.
+    pub fn protocol(&mut self) -> &mut Http<TaskExecutor> {
+        &mut self.protocol
+    }
+
+    /// Returns the number of connections established so far.
+    pub fn connections(&self) -> usize {
+        self.connections
+    }
+
+    /// Closes the current connection, if any.
+    ///
+    /// The subsequent request will be sent over a new connection.
+    pub fn disconnect(&mut self) {
+        self.sender = None;
+    }
+}
+
+impl<E, Bd> TestServer<E>
+where
+    E: Endpoint<ServerReqBody> + 'static,
+    E::Output: IntoResponse<Body = Bd>,
+    Bd: BufStream + Send + 'static,
+    Bd::Item: Send,
+    Bd::Error: Into<CritError>,
+{
+    fn connect(&mut self) -> io::Result<SendRequest<hyper::Body>> {
+        let (client_io, server_io) = duplex();
+
+        let service = LiftedHttpService {
+            endpoint: self.endpoint.clone(),
+        };
+        let conn = self
+            .protocol
+            .serve_connection(server_io, service)
+            .with_upgrades()
+            .map_err(|e| log::debug!("connection error: {}", e));
+        self.rt.spawn(conn);
+
+        let (sender, conn) = self
+            .rt
+            .block_on(client_conn::handshake::<DuplexStream>(client_io))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        self.rt
+            .spawn(conn.map_err(|e| log::debug!("connection error: {}", e)));
+        self.connections += 1;
+
+        Ok(sender)
+    }
+
+    fn ready_sender(&mut self) -> io::Result<SendRequest<hyper::Body>> {
+        if let Some(mut sender) = self.sender.take() {
+            let ready = self
+                .rt
+                .block_on(future::poll_fn(|| sender.poll_ready()))
+                .is_ok();
+            if ready {
+                return Ok(sender);
+            }
+        }
+        self.connect()
+    }
+
+    /// Sends the given request to the server and receives its response.
+    ///
+    /// The whole of response body is received before returning.
+    pub fn perform(&mut self, request: impl TestRequest) -> io::Result<TestResult> {
+        let mut request = request
+            .into_request()
+            .expect("failed to construct a request");
+        set_default_headers(request.headers_mut());
+        let request = request.map(|body| match body.data {
+            Some(data) => hyper::Body::from(data),
+            None => hyper::Body::empty(),
+        });
+
+        let mut sender = self.ready_sender()?;
+        let response = self
+            .rt
+            .block_on(sender.send_request(request).and_then(|response| {
+                let (parts, body) = response.into_parts();
+                body.concat2()
+                    .map(|body| Response::from_parts(parts, body.into_bytes()))
+            }))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        self.sender = Some(sender);
+
+        Ok(TestResult {
+            response,
+            upgraded: None,
+        })
+    }
+}
+
+#[allow(missing_debug_implementations)]
+struct LiftedHttpService<E> {
+    endpoint: Arc<E>,
+}
+
+impl<E, Bd> hyper::service::Service for LiftedHttpService<E>
+where
+    E: Endpoint<ServerReqBody>,
+    E::Output: IntoResponse<Body = Bd>,
+    Bd: BufStream + Send + 'static,
+    Bd::Item: Send,
+    Bd::Error: Into<CritError>,
+{
+    type ReqBody = hyper::Body;
+    type ResBody = WrappedBodyStream<ResponseBody<ServerReqBody, Arc<E>>>;
+    type Error = CritError;
+    type Future = LiftedHttpServiceFuture<AppFuture<ServerReqBody, Arc<E>>>;
+
+    fn call(&mut self, request: Request<hyper::Body>) -> Self::Future {
+        LiftedHttpServiceFuture {
+            inner: AppService::new(self.endpoint.clone())
+                .dispatch(request.map(RequestBody::from_hyp)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            endpoint::{syntax, EndpointExt},
+            endpoints::body,
+        },
+        http::{header, Request},
+    };
+
+    #[test]
+    fn test_keep_alive() {
+        let mut server = serve(syntax::verb::get().map(|| "Hello"));
+
+        server
+            .perform("/")
+            .unwrap()
+            .assert_status(200)
+            .assert_body("Hello");
+        server.perform("/").unwrap().assert_body("Hello");
+        assert_eq!(server.connections(), 1);
+
+        server
+            .perform(Request::get("/").header(header::CONNECTION, "close"))
+            .unwrap()
+            .assert_status(200);
+        server.perform("/").unwrap().assert_status(200);
+        assert_eq!(server.connections(), 2);
+    }
+
+    #[test]
+    fn test_expect_continue() {
+        let mut server = serve(
+            syntax::verb::post()
+                .and(body::text())
+                .map(|text: String| format!("received: {}", text)),
+        );
+
+        server
+            .perform(
+                Request::post("/")
+                    .header(header::EXPECT, "100-continue")
+                    .body("payload"),
+            )
+            .unwrap()
+            .assert_status(200)
+            .assert_body("received: payload");
+
+        server
+            .perform(Request::get("/"))
+            .unwrap()
+            .assert_status(405);
+    }
+}
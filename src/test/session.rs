@@ -0,0 +1,163 @@
+use {
+    super::{ReqBody, TestRequest, TestResult, TestRunner},
+    crate::{endpoint::Endpoint, output::IntoResponse},
+    cookie::{Cookie, CookieJar},
+    http::header::{self, HeaderMap, HeaderValue},
+    izanami_util::buf_stream::BufStream,
+    std::io,
+};
+
+/// A handle for performing a sequence of requests which shares the cookies
+/// and the default headers.
+///
+/// The cookies sent from the endpoint by `Set-Cookie` are stored in the
+/// session and sent back with the subsequent requests.
+/// Note that the attributes of cookies such as `Path` and `Domain` are
+/// not taken into account.
+#[derive(Debug)]
+pub struct TestSession<'a, E> {
+    runner: &'a mut TestRunner<E>,
+    cookies: CookieJar,
+    headers: HeaderMap,
+}
+
+impl<E> TestRunner<E>
+where
+    E: Endpoint<ReqBody>,
+{
+    /// Creates a session which performs the requests with this runner.
+    pub fn session(&mut self) -> TestSession<'_, E> {
+        TestSession {
+            runner: self,
+            cookies: CookieJar::new(),
+            headers: HeaderMap::new(),
+        }
+    }
+}
+
+impl<'a, E> TestSession<'a, E>
+where
+    E: Endpoint<ReqBody>,
+{
+    /// Returns a reference to the header map whose values are set to
+    /// all requests in this session.
+    pub fn headers(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    /// Returns a reference to the cookie jar of this session.
+    pub fn cookies(&mut self) -> &mut CookieJar {
+        &mut self.cookies
+    }
+
+    /// Performs the specified request with the cookies and the headers
+    /// stored in this session.
+    pub fn perform(&mut self, request: impl TestRequest) -> io::Result<TestResult>
+    where
+        E::Output: IntoResponse,
+        <E::Output as IntoResponse>::Body: BufStream,
+        <<E::Output as IntoResponse>::Body as BufStream>::Error:
+            Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    {
+        let mut request = request
+            .into_request()
+            .expect("failed to construct a request");
+
+        for (name, value) in &self.headers {
+            if !request.headers().contains_key(name) {
+                request.headers_mut().insert(name, value.clone());
+            }
+        }
+
+        let cookies: Vec<_> = self
+            .cookies
+            .iter()
+            .map(|cookie| {
+                Cookie::new(cookie.name().to_owned(), cookie.value().to_owned())
+                    .encoded()
+                    .to_string()
+            })
+            .collect();
+        if !cookies.is_empty() {
+            let value = HeaderValue::from_shared(cookies.join("; ").into())
+                .expect("should be a valid header value");
+            request.headers_mut().append(header::COOKIE, value);
+        }
+
+        let result = self.runner.perform(request)?;
+
+        for value in result.headers().get_all(header::SET_COOKIE) {
+            let cookie = match value
+                .to_str()
+                .ok()
+                .and_then(|s| Cookie::parse_encoded(s.to_owned()).ok())
+            {
+                Some(cookie) => cookie,
+                None => continue,
+            };
+            let expired = cookie
+                .max_age()
+                .map(|max_age| max_age.num_seconds() <= 0)
+                .unwrap_or(false);
+            if expired {
+                self.cookies.force_remove(cookie);
+            } else {
+                self.cookies.add(cookie);
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            endpoint::{endpoint, syntax, EndpointExt},
+            error::Error,
+            service::Context,
+        },
+        futures::future,
+    };
+
+    #[test]
+    fn test_session_cookies() {
+        let login = syntax::segment("login").and(endpoint(|| {
+            future::lazy(|| {
+                Context::with(|cx| -> Result<_, Error> {
+                    cx.cookies()?.add(Cookie::new("session", "alice"));
+                    Ok(("logged in".to_owned(),))
+                })
+            })
+        }));
+        let logout = syntax::segment("logout").and(endpoint(|| {
+            future::lazy(|| {
+                Context::with(|cx| -> Result<_, Error> {
+                    cx.cookies()?.remove(Cookie::named("session"));
+                    Ok(("logged out".to_owned(),))
+                })
+            })
+        }));
+        let whoami = syntax::segment("whoami").and(endpoint(|| {
+            future::lazy(|| {
+                Context::with(|cx| -> Result<_, Error> {
+                    let name = cx
+                        .cookies()?
+                        .get("session")
+                        .map_or_else(|| "anonymous".to_owned(), |c| c.value().to_owned());
+                    Ok((name,))
+                })
+            })
+        }));
+        let mut runner = super::super::runner(login.or_strict(logout).or_strict(whoami));
+        let mut session = runner.session();
+
+        session.perform("/whoami").unwrap().assert_body("anonymous");
+        session.perform("/login").unwrap().assert_status(200);
+        session.perform("/whoami").unwrap().assert_body("alice");
+        session.perform("/logout").unwrap().assert_status(200);
+        session.perform("/whoami").unwrap().assert_body("anonymous");
+    }
+}
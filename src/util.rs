@@ -1,6 +1,12 @@
 #![allow(missing_docs)]
 
-use std::{error, fmt};
+mod blocking;
+pub mod either;
+pub(crate) mod scan;
+
+pub use self::blocking::{blocking, Blocking};
+
+use std::{error, fmt, time::Duration};
 
 /// A type which has no possible values.
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Ord, Eq)]
@@ -30,3 +36,59 @@ impl error::Error for Never {
         match *self {}
     }
 }
+
+/// Returns the duration in milliseconds, with the fractional part.
+pub(crate) fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + f64::from(duration.subsec_nanos()) / 1_000_000.0
+}
+
+/// Converts the number of days since the UNIX epoch into the civil date.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Computes the FNV-1a hash of the specified parts, which is stable across
+/// the builds and the platforms unlike `DefaultHasher`.
+pub(crate) fn stable_hash(parts: &[&[u8]]) -> u64 {
+    parts
+        .iter()
+        .enumerate()
+        .flat_map(|(i, part)| {
+            // The parts are separated so that `("ab", "c")` and `("a", "bc")` differ.
+            (if i > 0 { Some(0) } else { None })
+                .into_iter()
+                .chain(part.iter().cloned())
+        })
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// Maps the specified parts onto a stable position in `[0, 1)`.
+///
+/// Assigning the keys to the ranges of positions gives a consistent hashing
+/// between the weighted buckets: when a weight is changed, only the keys
+/// within the changed range move to another bucket.
+pub(crate) fn stable_position(parts: &[&[u8]]) -> f64 {
+    let hash = stable_hash(parts);
+    // Mix the bits since the lower bits of FNV are not well distributed.
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+    let hash = hash ^ (hash >> 33);
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Compares the secrets in constant time with respect to their contents.
+///
+/// Only the length may be leaked, by returning early if they differ.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
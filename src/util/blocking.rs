@@ -0,0 +1,52 @@
+use futures::{Async, Future, Poll};
+
+/// Creates a future which runs the specified blocking function.
+///
+/// When polled on the threadpool of Tokio runtime, the function is run by
+/// using `tokio_threadpool::blocking` so that the other tasks are not starved
+/// during the blocking section. Otherwise (e.g. on the current thread runtime
+/// used by the test runner), the function is called directly.
+///
+/// The blocking section requires the feature `tokio-threadpool`. Without it,
+/// the function is always called directly.
+pub fn blocking<F, T, E>(f: F) -> Blocking<F>
+where
+    F: FnOnce() -> Result<T, E>,
+{
+    Blocking { f: Some(f) }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Blocking<F> {
+    f: Option<F>,
+}
+
+impl<F, T, E> Future for Blocking<F>
+where
+    F: FnOnce() -> Result<T, E>,
+{
+    type Item = T;
+    type Error = E;
+
+    #[cfg(feature = "tokio-threadpool")]
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let f = &mut self.f;
+        match tokio_threadpool::blocking(|| {
+            (f.take().expect("the future has already been polled"))()
+        }) {
+            Ok(Async::Ready(result)) => result.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // not running on the threadpool.
+            Err(..) => {
+                (self.f.take().expect("the future has already been polled"))().map(Async::Ready)
+            }
+        }
+    }
+
+    #[cfg(not(feature = "tokio-threadpool"))]
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        (self.f.take().expect("the future has already been polled"))().map(Async::Ready)
+    }
+}
@@ -0,0 +1,211 @@
+//! The variants of `Either` with three or more alternatives.
+//!
+//! These types are useful for returning the different types of responses
+//! from a handler without nesting `Either`s, e.g.
+//!
+//! ```
+//! # use finchers::prelude::*;
+//! # use finchers::endpoint::syntax;
+//! # use finchers::output::{Json, Redirect};
+//! # use finchers::util::either::Either3;
+//! # use http::StatusCode;
+//! let endpoint = syntax::param::<u32>().map(|id: u32| match id {
+//!     0 => Either3::A(Redirect::see_other("/")),
+//!     1 => Either3::B(Json(vec!["admin"])),
+//!     _ => Either3::C(StatusCode::NOT_FOUND),
+//! });
+//! # let mut runner = finchers::test::runner(endpoint);
+//! # runner.perform("/1").unwrap().assert_body(r#"["admin"]"#);
+//! ```
+//!
+//! The outputs of the `or` chains, which are nested `Either`s such as
+//! `Either<Either<T1, T2>, T3>`, can be converted into them by `From`.
+
+use {
+    crate::output::IntoResponse,
+    bytes::Buf,
+    either::Either,
+    futures::{Async, Poll},
+    http::{Request, Response},
+    izanami_util::buf_stream::{BufStream, SizeHint},
+    std::error,
+};
+
+type BoxedError = Box<dyn error::Error + Send + Sync + 'static>;
+
+macro_rules! define_either {
+    (
+        $(#[$m:meta])*
+        $Either:ident<$($T:ident => $V:ident),*>,
+        from $Prev:ident { $($PV:ident),* } and $Last:ident => $LastV:ident
+    ) => {
+        $(#[$m])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub enum $Either<$($T,)* $Last> {
+            $(
+                #[allow(missing_docs)]
+                $V($T),
+            )*
+            #[allow(missing_docs)]
+            $LastV($Last),
+        }
+
+        /// Converts the nested `Either`s returned from the `or` chains.
+        impl<$($T,)* $Last, P> From<Either<P, $Last>> for $Either<$($T,)* $Last>
+        where
+            P: Into<$Prev<$($T),*>>,
+        {
+            fn from(either: Either<P, $Last>) -> Self {
+                match either {
+                    Either::Left(prev) => match prev.into() {
+                        $( $Prev::$PV(x) => $Either::$V(x), )*
+                    },
+                    Either::Right(x) => $Either::$LastV(x),
+                }
+            }
+        }
+
+        impl<$($T,)* $Last> IntoResponse for $Either<$($T,)* $Last>
+        where
+            $( $T: IntoResponse, )*
+            $Last: IntoResponse,
+        {
+            type Body = $Either<$($T::Body,)* $Last::Body>;
+
+            fn into_response(self, request: &Request<()>) -> Response<Self::Body> {
+                match self {
+                    $( $Either::$V(x) => x.into_response(request).map($Either::$V), )*
+                    $Either::$LastV(x) => x.into_response(request).map($Either::$LastV),
+                }
+            }
+        }
+
+        impl<$($T,)* $Last> BufStream for $Either<$($T,)* $Last>
+        where
+            $( $T: BufStream, $T::Error: Into<BoxedError>, )*
+            $Last: BufStream,
+            $Last::Error: Into<BoxedError>,
+        {
+            type Item = $Either<$($T::Item,)* $Last::Item>;
+            type Error = BoxedError;
+
+            fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+                match self {
+                    $(
+                        $Either::$V(x) => match x.poll_buf().map_err(Into::into)? {
+                            Async::Ready(buf) => Ok(Async::Ready(buf.map($Either::$V))),
+                            Async::NotReady => Ok(Async::NotReady),
+                        },
+                    )*
+                    $Either::$LastV(x) => match x.poll_buf().map_err(Into::into)? {
+                        Async::Ready(buf) => Ok(Async::Ready(buf.map($Either::$LastV))),
+                        Async::NotReady => Ok(Async::NotReady),
+                    },
+                }
+            }
+
+            fn size_hint(&self) -> SizeHint {
+                match self {
+                    $( $Either::$V(x) => x.size_hint(), )*
+                    $Either::$LastV(x) => x.size_hint(),
+                }
+            }
+
+            fn consume_hint(&mut self, amount: usize) {
+                match self {
+                    $( $Either::$V(x) => x.consume_hint(amount), )*
+                    $Either::$LastV(x) => x.consume_hint(amount),
+                }
+            }
+        }
+
+        impl<$($T,)* $Last> Buf for $Either<$($T,)* $Last>
+        where
+            $( $T: Buf, )*
+            $Last: Buf,
+        {
+            fn remaining(&self) -> usize {
+                match self {
+                    $( $Either::$V(x) => x.remaining(), )*
+                    $Either::$LastV(x) => x.remaining(),
+                }
+            }
+
+            fn bytes(&self) -> &[u8] {
+                match self {
+                    $( $Either::$V(x) => x.bytes(), )*
+                    $Either::$LastV(x) => x.bytes(),
+                }
+            }
+
+            fn advance(&mut self, cnt: usize) {
+                match self {
+                    $( $Either::$V(x) => x.advance(cnt), )*
+                    $Either::$LastV(x) => x.advance(cnt),
+                }
+            }
+        }
+    };
+}
+
+define_either! {
+    /// A value of one of the three types.
+    Either3<T1 => A, T2 => B>,
+    from Either { Left, Right } and T3 => C
+}
+
+define_either! {
+    /// A value of one of the four types.
+    Either4<T1 => A, T2 => B, T3 => C>,
+    from Either3 { A, B, C } and T4 => D
+}
+
+define_either! {
+    /// A value of one of the five types.
+    Either5<T1 => A, T2 => B, T3 => C, T4 => D>,
+    from Either4 { A, B, C, D } and T5 => E
+}
+
+define_either! {
+    /// A value of one of the six types.
+    Either6<T1 => A, T2 => B, T3 => C, T4 => D, T5 => E>,
+    from Either5 { A, B, C, D, E } and T6 => F
+}
+
+define_either! {
+    /// A value of one of the seven types.
+    Either7<T1 => A, T2 => B, T3 => C, T4 => D, T5 => E, T6 => F>,
+    from Either6 { A, B, C, D, E, F } and T7 => G
+}
+
+define_either! {
+    /// A value of one of the eight types.
+    Either8<T1 => A, T2 => B, T3 => C, T4 => D, T5 => E, T6 => F, T7 => G>,
+    from Either7 { A, B, C, D, E, F, G } and T8 => H
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_nested() {
+        type Nested = Either<Either<Either<u8, &'static str>, bool>, char>;
+
+        let values: Vec<Either4<_, _, _, _>> = vec![
+            Nested::Left(Either::Left(Either::Left(1))).into(),
+            Nested::Left(Either::Left(Either::Right("two"))).into(),
+            Nested::Left(Either::Right(true)).into(),
+            Nested::Right('4').into(),
+        ];
+        assert_eq!(
+            values,
+            vec![
+                Either4::A(1),
+                Either4::B("two"),
+                Either4::C(true),
+                Either4::D('4'),
+            ]
+        );
+    }
+}
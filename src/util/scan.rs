@@ -0,0 +1,139 @@
+//! The byte scanning routines used for splitting and decoding the request paths.
+//!
+//! When the feature `simd` is enabled, the searches are delegated to `memchr`,
+//! which uses the vectorized instructions available on the target. Otherwise,
+//! the scalar implementations are used.
+
+use std::borrow::Cow;
+
+/// Returns the index of the first occurrence of `needle` in `haystack`.
+#[inline]
+pub(crate) fn find_byte(needle: u8, haystack: &[u8]) -> Option<usize> {
+    #[cfg(feature = "simd")]
+    {
+        memchr::memchr(needle, haystack)
+    }
+    #[cfg(not(feature = "simd"))]
+    {
+        scalar::find_byte(needle, haystack)
+    }
+}
+
+/// Decodes the percent-encoded bytes.
+///
+/// The invalid sequences, i.e. `%` which is not followed by two hexadecimal
+/// digits, are kept as they are. The input is borrowed if it does not contain
+/// any `%`.
+#[inline]
+pub(crate) fn percent_decode(input: &[u8]) -> Cow<'_, [u8]> {
+    percent_decode_with(input, find_byte)
+}
+
+fn percent_decode_with(input: &[u8], find: fn(u8, &[u8]) -> Option<usize>) -> Cow<'_, [u8]> {
+    let first = match find(b'%', input) {
+        Some(pos) => pos,
+        None => return Cow::Borrowed(input),
+    };
+
+    let mut decoded = Vec::with_capacity(input.len());
+    decoded.extend_from_slice(&input[..first]);
+    let mut rest = &input[first..];
+    loop {
+        // `rest` starts with '%' here.
+        match (
+            rest.get(1).cloned().and_then(hex_value),
+            rest.get(2).cloned().and_then(hex_value),
+        ) {
+            (Some(high), Some(low)) => {
+                decoded.push(high << 4 | low);
+                rest = &rest[3..];
+            }
+            _ => {
+                decoded.push(b'%');
+                rest = &rest[1..];
+            }
+        }
+
+        match find(b'%', rest) {
+            Some(pos) => {
+                decoded.extend_from_slice(&rest[..pos]);
+                rest = &rest[pos..];
+            }
+            None => {
+                decoded.extend_from_slice(rest);
+                break;
+            }
+        }
+    }
+    Cow::Owned(decoded)
+}
+
+#[inline]
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg_attr(feature = "simd", allow(dead_code))]
+mod scalar {
+    #[inline]
+    pub(super) fn find_byte(needle: u8, haystack: &[u8]) -> Option<usize> {
+        haystack.iter().position(|&b| b == needle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::test::arbitrary::{check, Rng},
+        percent_encoding::percent_decode as reference_decode,
+    };
+
+    fn gen_path(rng: &mut Rng) -> Vec<u8> {
+        const ALPHABET: &[u8] = b"/%%%aAfFgG09-._~";
+        let len = rng.gen_range(0..96) as usize;
+        (0..len)
+            .map(|_| match rng.gen_range(0..8) {
+                0 => rng.next_u64() as u8,
+                _ => *rng.choose(ALPHABET).unwrap(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_find_byte() {
+        check(2000, gen_path, |input| {
+            for &needle in b"/%a" {
+                let expected = scalar::find_byte(needle, &input);
+                assert_eq!(find_byte(needle, &input), expected);
+                #[cfg(feature = "simd")]
+                assert_eq!(memchr::memchr(needle, &input), expected);
+            }
+        });
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode(b"a%20b"), &b"a b"[..]);
+        assert_eq!(percent_decode(b"%zz%2"), &b"%zz%2"[..]);
+        assert_eq!(percent_decode(b"%%41"), &b"%A"[..]);
+        assert!(match percent_decode(b"abc") {
+            Cow::Borrowed(..) => true,
+            Cow::Owned(..) => false,
+        });
+
+        check(2000, gen_path, |input| {
+            let expected = reference_decode(&input).collect::<Vec<u8>>();
+            assert_eq!(
+                percent_decode_with(&input, scalar::find_byte).into_owned(),
+                expected
+            );
+            assert_eq!(percent_decode(&input).into_owned(), expected);
+        });
+    }
+}
@@ -0,0 +1,169 @@
+use finchers::client::{self, Client};
+use finchers::endpoint::syntax;
+use finchers::prelude::*;
+use finchers::test;
+use futures::{Future, Stream};
+use hyper::service::service_fn;
+use hyper::{Body, Response, Server};
+use matches::assert_matches;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+
+/// Spawns an upstream server which echoes the method, the path, the `x-test` header
+/// and the body of each request, and responds with the status specified by the path.
+fn spawn_upstream() -> SocketAddr {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(|| {
+            service_fn(|req: hyper::Request<Body>| {
+                let method = req.method().clone();
+                let path = req.uri().path().to_owned();
+                let x_test = req
+                    .headers()
+                    .get("x-test")
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or("")
+                    .to_owned();
+                req.into_body().concat2().map(move |body| {
+                    let status = match &*path {
+                        "/not-found" => 404,
+                        _ => 200,
+                    };
+                    let body = match &*path {
+                        "/json" => r#"{"name":"alice","age":42}"#.to_owned(),
+                        _ => format!(
+                            "{} {} {} {}",
+                            method,
+                            path,
+                            x_test,
+                            String::from_utf8_lossy(&body)
+                        ),
+                    };
+                    Response::builder()
+                        .status(status)
+                        .body(Body::from(body))
+                        .unwrap()
+                })
+            })
+        });
+        tx.send(server.local_addr()).unwrap();
+        hyper::rt::run(server.map_err(|e| panic!("{}", e)));
+    });
+    rx.recv().unwrap()
+}
+
+fn uri(addr: SocketAddr, path: &str) -> http::Uri {
+    format!("http://{}{}", addr, path).parse().unwrap()
+}
+
+#[test]
+fn test_client_request() {
+    let addr = spawn_upstream();
+    let mut runner = test::runner(syntax::segment("proxy").and(client::client()).and_then(
+        move |client: Client| {
+            let request = http::Request::post(uri(addr, "/echo"))
+                .header("x-test", "finchers")
+                .body(client::Body::from("hello"))
+                .unwrap();
+            client.request(request).and_then(|response| {
+                let status = response.status();
+                response
+                    .into_body()
+                    .concat2()
+                    .map(move |body| (status, body.to_vec()))
+                    .map_err(|e| finchers::error::fail(e, http::StatusCode::BAD_GATEWAY))
+            })
+        },
+    ));
+
+    let (status, body) = runner.apply("/proxy").unwrap();
+    assert_eq!(status, 200);
+    assert_eq!(body, b"POST /echo finchers hello");
+}
+
+#[test]
+fn test_client_get_bytes() {
+    let addr = spawn_upstream();
+    let mut runner = test::runner(
+        syntax::segment("bytes")
+            .and(client::client())
+            .and_then(move |client: Client| client.get_bytes(uri(addr, "/foo")))
+            .map(|body: bytes::Bytes| body.to_vec()),
+    );
+
+    runner
+        .perform("/bytes")
+        .unwrap()
+        .assert_status(200)
+        .assert_body("GET /foo  ");
+}
+
+#[test]
+fn test_client_get_json() {
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct User {
+        name: String,
+        age: u32,
+    }
+
+    let addr = spawn_upstream();
+    let mut runner = test::runner(
+        client::client().and_then(move |client: Client| client.get_json(uri(addr, "/json"))),
+    );
+
+    assert_eq!(
+        runner.apply("/").ok(),
+        Some(User {
+            name: "alice".into(),
+            age: 42,
+        })
+    );
+}
+
+#[test]
+fn test_client_upstream_errors() {
+    let addr = spawn_upstream();
+    let mut runner = test::runner(
+        syntax::segment("not-found")
+            .and(client::client())
+            .and_then(move |client: Client| client.get_bytes(uri(addr, "/not-found")))
+            .or(syntax::segment("invalid-json")
+                .and(client::client())
+                .and_then(move |client: Client| client.get_json::<Vec<u32>>(uri(addr, "/foo"))))
+            .or(syntax::segment("refused")
+                .and(client::client())
+                .and_then(|client: Client| {
+                    // port 1 is reserved and no server listens on it.
+                    client.get_bytes("http://127.0.0.1:1/".parse().unwrap())
+                })),
+    );
+
+    assert_matches!(
+        runner.apply_raw("/not-found"),
+        Err(ref err) if err.status_code() == 502
+    );
+    assert_matches!(
+        runner.apply_raw("/invalid-json"),
+        Err(ref err) if err.status_code() == 502
+    );
+    assert_matches!(
+        runner.apply_raw("/refused"),
+        Err(ref err) if err.status_code() == 502
+    );
+}
+
+#[test]
+fn test_client_from_extensions() {
+    let addr = spawn_upstream();
+    let mut runner = test::runner(
+        client::client().and_then(move |client: Client| client.get_bytes(uri(addr, "/ext"))),
+    );
+
+    let mut request = http::Request::get("/").body("").unwrap();
+    request.extensions_mut().insert(Client::new());
+    runner
+        .perform(request)
+        .unwrap()
+        .assert_status(200)
+        .assert_body("GET /ext  ");
+}
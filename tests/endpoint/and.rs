@@ -19,3 +19,62 @@ fn test_and_flatten() {
 
     assert_matches!(runner.apply_raw("/"), Ok(("Hello", "world", ":)")));
 }
+
+#[test]
+fn test_and_large_arity() {
+    macro_rules! first8 {
+        () => {
+            value(0)
+                .and(value(1))
+                .and(value(2))
+                .and(value(3))
+                .and(value(4))
+                .and(value(5))
+                .and(value(6))
+                .and(value(7))
+        };
+    }
+    let mut runner = test::runner(first8!().and(first8!()).and(first8!()).and(value(24)));
+
+    // The standard library does not implement `Debug` for such large tuples.
+    let (first, .., second_last, last) = runner.apply_raw("/").ok().unwrap();
+    assert_eq!((first, second_last, last), (0, 7, 24));
+}
+
+#[test]
+fn test_flatten() {
+    let mut runner = test::runner(
+        value(("Hello", "world"))
+            .and(unit().map(|| ()))
+            .and(value((":)",)))
+            .flatten(),
+    );
+
+    assert_matches!(runner.apply_raw("/"), Ok(("Hello", "world", ":)")));
+}
+
+#[test]
+fn test_method_not_allowed_requires_path_match() {
+    use finchers::endpoint::syntax;
+
+    let mut runner = test::runner(
+        syntax::verb::get()
+            .and(syntax::segment("foo"))
+            .and(syntax::eos())
+            .map(|| "foo"),
+    );
+
+    runner.perform("/foo").unwrap().assert_body("foo");
+    runner
+        .perform(http::Request::post("/foo"))
+        .unwrap()
+        .assert_status(405);
+    runner
+        .perform(http::Request::post("/bar"))
+        .unwrap()
+        .assert_status(404);
+    runner
+        .perform(http::Request::post("/foo/bar"))
+        .unwrap()
+        .assert_status(404);
+}
@@ -17,3 +17,38 @@ fn test_boxed_local() {
     let mut runner = test::runner(endpoint.boxed_local());
     assert_matches!(runner.apply_raw("/foo"), Ok(..));
 }
+
+mod routes {
+    use finchers::endpoint::{self, syntax, Route};
+    use finchers::output::Json;
+    use finchers::prelude::*;
+
+    pub fn users<Bd: 'static>() -> Route<Bd> {
+        endpoint::boxed(syntax::segment("users").map(|| Json(vec!["alice", "bob"])))
+    }
+
+    pub fn health<Bd: 'static>() -> Route<Bd> {
+        endpoint::boxed(syntax::segment("health").map(|| "ok"))
+    }
+}
+
+#[test]
+fn test_route() {
+    let mut runner = test::runner(routes::users().or_strict(routes::health()));
+    runner
+        .perform("/users")
+        .unwrap()
+        .assert_header("content-type", "application/json")
+        .assert_body(r#"["alice","bob"]"#);
+    runner.perform("/health").unwrap().assert_body("ok");
+}
+
+#[test]
+fn test_local_route() {
+    // The endpoint is not `Send` since the closure holds an `Rc`.
+    let greeting = std::rc::Rc::new("hello".to_string());
+    let route: finchers::endpoint::LocalRoute<_> =
+        finchers::endpoint::boxed_local(syntax::segment("foo").map(move || greeting.to_string()));
+    let mut runner = test::runner(route);
+    runner.perform("/foo").unwrap().assert_body("hello");
+}
@@ -0,0 +1,76 @@
+use finchers::endpoints::header::Header;
+use finchers::error::Error;
+use finchers::output::Json;
+use finchers::prelude::*;
+use finchers::test;
+use http::Request;
+use serde::Deserialize;
+
+#[finchers::get("/users/{id}/posts/{*rest}")]
+fn user_posts(rest: String, id: u32) -> String {
+    format!("{} {}", id, rest)
+}
+
+#[derive(Debug, Deserialize)]
+struct NewPost {
+    title: String,
+}
+
+#[finchers::post("/users/{id}/posts")]
+fn create_post(
+    id: u32,
+    post: Json<NewPost>,
+    x_request_id: Option<Header<String>>,
+) -> Result<String, Error> {
+    Ok(format!(
+        "{} {} {:?}",
+        id,
+        post.0.title,
+        x_request_id.map(|Header(id)| id)
+    ))
+}
+
+#[finchers::delete("/users/{id}")]
+fn delete_user(
+    id: u32,
+    authorization: Header<String>,
+) -> impl futures::Future<Item = String, Error = Error> {
+    futures::future::ok(format!("{} {}", id, authorization.0))
+}
+
+#[test]
+fn test_handler_path_params() {
+    let mut runner = test::runner(user_posts());
+    assert_eq!(
+        runner.apply("/users/42/posts/foo/bar").ok(),
+        Some("42 foo/bar".into())
+    );
+    assert!(runner.apply(Request::post("/users/42/posts/foo")).is_err());
+}
+
+#[test]
+fn test_handler_body_and_headers() {
+    let mut runner = test::runner(create_post().or(delete_user()));
+
+    let request = Request::post("/users/1/posts")
+        .header("content-type", "application/json")
+        .header("x-request-id", "abc")
+        .body(r#"{"title":"Hello"}"#)
+        .unwrap();
+    runner
+        .perform(request)
+        .unwrap()
+        .assert_body(r#"1 Hello Some("abc")"#);
+
+    let request = Request::delete("/users/1")
+        .header("authorization", "Bearer xxx")
+        .body("")
+        .unwrap();
+    runner.perform(request).unwrap().assert_body("1 Bearer xxx");
+
+    let mut runner = test::runner(delete_user());
+    runner
+        .perform(Request::delete("/users/1"))
+        .unwrap()
+        .assert_status(400);
+}
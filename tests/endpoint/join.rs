@@ -0,0 +1,48 @@
+use finchers::endpoint::syntax;
+use finchers::prelude::*;
+use finchers::test;
+use futures::{future, task, Async};
+use matches::assert_matches;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn test_join_isolated_cursor() {
+    let mut runner = test::runner(
+        endpoint::join((
+            syntax::segment("foo"),
+            syntax::param::<String>(),
+            syntax::segment("foo").and(syntax::param::<u32>()),
+        ))
+        .and(syntax::eos()),
+    );
+
+    assert_matches!(runner.apply_raw("/foo/42"), Ok((ref s, 42)) if s == "foo");
+    assert_matches!(runner.apply_raw("/bar/42"), Err(..));
+    assert_matches!(runner.apply_raw("/foo/42/baz"), Err(..));
+}
+
+#[test]
+fn test_join_concurrent() {
+    let log = Arc::new(Mutex::new(vec![]));
+    let pending = |name: &'static str| {
+        let log = log.clone();
+        endpoint::unit().and_then(move || {
+            let log = log.clone();
+            let mut polled = false;
+            future::poll_fn(move || {
+                log.lock().unwrap().push(name);
+                if polled {
+                    Ok::<_, finchers::util::Never>(Async::Ready(name))
+                } else {
+                    polled = true;
+                    task::current().notify();
+                    Ok(Async::NotReady)
+                }
+            })
+        })
+    };
+
+    let mut runner = test::runner(endpoint::join((pending("a"), pending("b"))));
+    assert_matches!(runner.apply_raw("/"), Ok(("a", "b")));
+    assert_eq!(*log.lock().unwrap(), vec!["a", "b", "a", "b"]);
+}
@@ -0,0 +1,69 @@
+use finchers::endpoint::syntax;
+use finchers::prelude::*;
+use finchers::test;
+use futures::future;
+use matches::assert_matches;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[derive(Default)]
+struct Counters {
+    acquired: AtomicUsize,
+    released: AtomicUsize,
+}
+
+struct Conn(Arc<Counters>);
+
+impl Drop for Conn {
+    fn drop(&mut self) {
+        self.0.released.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn checkout(
+    counters: &Arc<Counters>,
+) -> impl Fn() -> future::FutureResult<Conn, finchers::error::Error> + Clone {
+    let counters = counters.clone();
+    move || {
+        counters.acquired.fetch_add(1, Ordering::SeqCst);
+        future::ok(Conn(counters.clone()))
+    }
+}
+
+#[test]
+fn test_lazy_async() {
+    let counters = Arc::new(Counters::default());
+    let mut runner = test::runner(
+        syntax::segment("users")
+            .and(endpoint::lazy_async(checkout(&counters)))
+            .map(|conn: Conn| {
+                drop(conn);
+                "done"
+            }),
+    );
+
+    assert_matches!(runner.apply("/users"), Ok("done"));
+    assert_eq!(counters.acquired.load(Ordering::SeqCst), 1);
+    assert_eq!(counters.released.load(Ordering::SeqCst), 1);
+
+    // The resource is not acquired if the routing fails.
+    assert_matches!(runner.apply("/posts"), Err(..));
+    assert_eq!(counters.acquired.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_lazy_async_released_on_rejection() {
+    let counters = Arc::new(Counters::default());
+    let mut runner = test::runner(
+        endpoint::lazy_async(checkout(&counters))
+            .and(
+                endpoint::unit()
+                    .and_then(|| future::err::<(), _>(finchers::error::bad_request("rejected"))),
+            )
+            .map(|_: Conn, _: ()| "unreachable"),
+    );
+
+    assert_matches!(runner.apply("/"), Err(..));
+    assert_eq!(counters.acquired.load(Ordering::SeqCst), 1);
+    assert_eq!(counters.released.load(Ordering::SeqCst), 1);
+}
@@ -16,15 +16,43 @@ fn compile_test_path() {
     let _ = syntax::path!(@get "/posts/<i32>/repo/<..String>");
 }
 
-// #[test]
-// fn compile_test_routes() {
-//     use finchers::endpoint::syntax;
+#[test]
+fn compile_test_routes() {
+    use finchers::endpoint::EndpointExt;
 
-//     let e1 = syntax::segment("foo");
-//     let e2 = routes!(e1, syntax::segment("bar"), syntax::segment("baz"));
-//     let e3 = routes!(syntax::segment("foobar"), e2);
-//     let _e4 = routes!(syntax::segment("foobar"), e3,);
-// }
+    let e1 = syntax::segment("foo").map(|| "foo");
+    let e2 = finchers::routes!(
+        e1,
+        syntax::segment("bar").map(|| "bar"),
+        syntax::segment("baz").map(|| "baz")
+    );
+    let e3 = finchers::routes!(syntax::segment("foobar").map(|| "foobar"), e2);
+    let e4 = finchers::routes!(syntax::segment("foobar").map(|| "foobar"), e3,);
+    drop(finchers::test::runner(e4));
+}
+
+#[test]
+fn test_routes() {
+    use finchers::endpoint::EndpointExt;
+
+    let mut runner = finchers::test::runner(finchers::routes![
+        syntax::segment("foo").map(|| "foo"),
+        syntax::segment("foo").map(|| "shadowed"),
+        syntax::verb::post()
+            .and(syntax::segment("bar"))
+            .map(|| http::StatusCode::CREATED),
+        syntax::segment("baz").map(|| String::from("baz")),
+    ]);
+
+    runner.perform("/foo").unwrap().assert_body("foo");
+    runner
+        .perform(http::Request::post("/bar"))
+        .unwrap()
+        .assert_status(201);
+    runner.perform("/baz").unwrap().assert_body("baz");
+    runner.perform("/bar").unwrap().assert_status(404);
+    runner.perform("/qux").unwrap().assert_status(404);
+}
 
 #[test]
 fn test_extract_path_statics() {
@@ -53,3 +81,96 @@ fn test_extract_path_catch_all_param() {
     assert_eq!(runner.apply("/foo/bar/baz").ok(), Some("bar/baz".into()));
     matches::assert_matches!(runner.apply("/"), Err(..));
 }
+
+#[test]
+fn test_extract_path_eos() {
+    let mut runner = finchers::test::runner({ syntax::path!("/foo/<i32>") });
+    matches::assert_matches!(runner.apply("/foo/42/"), Ok(42_i32));
+    matches::assert_matches!(runner.apply("/foo/42/bar"), Err(..));
+
+    let mut runner = finchers::test::runner({ syntax::path!("/foo/<i32>/...") });
+    matches::assert_matches!(runner.apply("/foo/42/bar"), Ok(42_i32));
+}
+
+#[test]
+fn test_extract_path_trailing_capture() {
+    let mut runner = finchers::test::runner({ syntax::path!("/foo/*rest") });
+
+    assert_eq!(runner.apply("/foo/bar/baz").ok(), Some("bar/baz".into()));
+    assert_eq!(runner.apply("/foo").ok(), Some("".into()));
+    matches::assert_matches!(runner.apply("/bar/baz"), Err(..));
+}
+
+#[test]
+fn test_extract_path_query_and_body() {
+    use finchers::endpoint::EndpointExt;
+    use finchers::endpoints::body;
+
+    let mut runner = finchers::test::runner({
+        syntax::path!(@get "/posts/<u32>" ? param::<u32>("page") & optional_param::<String>("q"))
+            .map(|id: u32, page: u32, q: Option<String>| format!("{} {} {:?}", id, page, q))
+    });
+    assert_eq!(
+        runner.apply("/posts/1?page=2&q=foo").ok(),
+        Some(r#"1 2 Some("foo")"#.into())
+    );
+    assert_eq!(
+        runner.apply("/posts/1?page=2").ok(),
+        Some("1 2 None".into())
+    );
+    matches::assert_matches!(
+        runner.apply("/posts/1"),
+        Err(ref e) if e.status_code().as_u16() == 400
+    );
+
+    let mut runner = finchers::test::runner({ syntax::path!(@post "/echo" => body::text()) });
+    let request = http::Request::post("/echo").body("Hello").unwrap();
+    assert_eq!(runner.apply(request).ok(), Some("Hello".into()));
+
+    let mut runner = finchers::test::runner({
+        syntax::path!(@post "/echo" ? param::<u32>("n") => body::text())
+            .map(|n: u32, body: String| body.repeat(n as usize))
+    });
+    let request = http::Request::post("/echo?n=2").body("Hello").unwrap();
+    assert_eq!(runner.apply(request).ok(), Some("HelloHello".into()));
+}
+
+#[test]
+fn test_derive_extract() {
+    use finchers::endpoint::{value, EndpointExt, Extract};
+    use finchers::test;
+
+    #[derive(Debug, PartialEq, Extract)]
+    struct Named {
+        id: u32,
+        name: &'static str,
+        admin: bool,
+    }
+
+    #[derive(Debug, PartialEq, Extract)]
+    struct Unnamed<T>(T, u32);
+
+    #[derive(Debug, PartialEq, Extract)]
+    struct Unit;
+
+    let mut runner = test::runner(
+        value(42_u32)
+            .and(value("alice"))
+            .and(value(true))
+            .extract::<Named>(),
+    );
+    assert_eq!(
+        runner.apply("/").ok(),
+        Some(Named {
+            id: 42,
+            name: "alice",
+            admin: true,
+        })
+    );
+
+    let mut runner = test::runner(value("x").and(value(1_u32)).extract::<Unnamed<_>>());
+    assert_eq!(runner.apply("/").ok(), Some(Unnamed("x", 1)));
+
+    let mut runner = test::runner(syntax::segment("foo").extract::<Unit>());
+    assert_eq!(runner.apply("/foo").ok(), Some(Unit));
+}
@@ -1,9 +1,17 @@
 mod and;
 mod and_then;
 mod boxed;
+mod handler;
+mod join;
+mod lazy;
 mod macros;
 mod map;
+mod optimize;
+mod optional;
 mod or;
 mod or_strict;
 mod recover;
+mod scope;
+mod split;
 mod syntax;
+mod wrapper;
@@ -0,0 +1,115 @@
+use finchers::endpoint::syntax::{self, verb};
+use finchers::prelude::*;
+use finchers::test;
+use http::{Method, Request};
+
+macro_rules! routes {
+    () => {
+        verb::get()
+            .and(syntax::segment("users"))
+            .and(syntax::eos())
+            .map(|| "list users")
+            .or(verb::post()
+                .and(syntax::segment("users"))
+                .and(syntax::eos())
+                .map(|| "create user"))
+            .or(syntax::segment("users").map(|| "users prefix"))
+            .or(syntax::segment("users")
+                .and(syntax::segment("me"))
+                .map(|| "me"))
+            .or(verb::get()
+                .and(syntax::segment("users"))
+                .and(syntax::segment("me"))
+                .map(|| "me (shadowed)"))
+            .or(verb::verbs(verb::Verbs::GET | verb::Verbs::HEAD)
+                .and(syntax::segment("a b"))
+                .map(|| "encoded"))
+            .or(syntax::eos().map(|| "index"))
+            .or(syntax::eos()
+                .and(syntax::segment("never"))
+                .map(|| "unreachable"))
+    };
+}
+
+fn assert_same_responses(requests: &[(Method, &str)]) {
+    let mut expected = test::runner(routes!());
+    let mut optimized = test::runner(routes!().optimize());
+    for (method, uri) in requests {
+        let request = || {
+            Request::builder()
+                .method(method.clone())
+                .uri(*uri)
+                .body("")
+                .unwrap()
+        };
+        let expected = expected.perform(request()).unwrap();
+        let optimized = optimized.perform(request()).unwrap();
+        assert_eq!(optimized.status(), expected.status(), "{} {}", method, uri);
+        assert_eq!(
+            optimized.to_utf8_lossy(),
+            expected.to_utf8_lossy(),
+            "{} {}",
+            method,
+            uri
+        );
+    }
+}
+
+#[test]
+fn test_optimize_same_as_or_chain() {
+    assert_same_responses(&[
+        (Method::GET, "/users"),
+        (Method::POST, "/users"),
+        (Method::PUT, "/users"),
+        (Method::GET, "/users/"),
+        (Method::GET, "/users/alice"),
+        (Method::GET, "/users/me"),
+        (Method::DELETE, "/users/me/extra"),
+        (Method::GET, "/a%20b"),
+        (Method::HEAD, "/a%20b/c"),
+        (Method::POST, "/a%20b"),
+        (Method::GET, "/"),
+        (Method::GET, "/never"),
+        (Method::GET, "/posts"),
+        (Method::GET, "/users?q=1"),
+    ]);
+}
+
+#[test]
+fn test_optimize_precedence() {
+    let mut runner = test::runner(routes!().optimize());
+    runner.perform("/users").unwrap().assert_body("list users");
+    runner
+        .perform(Request::post("/users"))
+        .unwrap()
+        .assert_body("create user");
+    runner
+        .perform(Request::put("/users"))
+        .unwrap()
+        .assert_body("users prefix");
+    runner.perform("/users/me").unwrap().assert_body("me");
+    runner.perform("/").unwrap().assert_body("index");
+    runner.perform("/posts").unwrap().assert_status(404);
+}
+
+#[test]
+fn test_optimize_in_scope() {
+    let mut runner = test::runner(endpoint::scope(
+        "/api/v1",
+        syntax::segment("health")
+            .and(syntax::eos())
+            .map(|| "ok")
+            .or(syntax::segment("version").map(|| "1.0"))
+            .optimize(),
+    ));
+    runner.perform("/api/v1/health").unwrap().assert_body("ok");
+    runner
+        .perform("/api/v1/version/x")
+        .unwrap()
+        .assert_body("1.0");
+    runner.perform("/health").unwrap().assert_status(404);
+    runner
+        .perform("/api/v1/health/x")
+        .unwrap()
+        .assert_status(404);
+}
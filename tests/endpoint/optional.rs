@@ -0,0 +1,34 @@
+use finchers::endpoint::syntax;
+use finchers::prelude::*;
+use finchers::test;
+use matches::assert_matches;
+
+#[test]
+fn test_optional() {
+    let mut runner = test::runner(
+        syntax::segment("foo")
+            .and(syntax::param::<u32>().optional())
+            .and(syntax::remains::<String>())
+            .map(|x, rest: String| (x, rest)),
+    );
+
+    assert_matches!(runner.apply("/foo/42/bar"), Ok((Some(42), ref s)) if s == "bar");
+    assert_matches!(runner.apply("/foo"), Ok((None, ref s)) if s.is_empty());
+    assert_matches!(
+        runner.apply("/foo/bar"),
+        Err(ref e) if e.status_code().as_u16() == 400
+    );
+}
+
+#[test]
+fn test_or_default() {
+    let mut runner = test::runner(
+        syntax::segment("foo")
+            .and(syntax::param::<u32>().or_default())
+            .and(syntax::remains::<String>())
+            .map(|x, rest: String| (x, rest)),
+    );
+
+    assert_matches!(runner.apply("/foo/42/bar"), Ok((42, ref s)) if s == "bar");
+    assert_matches!(runner.apply("/foo"), Ok((0, ref s)) if s.is_empty());
+}
@@ -30,3 +30,97 @@ fn test_or_choose_longer_segments() {
     assert_matches!(runner.apply("/foo"), Ok(..));
     assert_matches!(runner.apply("/foo/bar"), Ok(..));
 }
+
+#[test]
+fn test_or_prefers_unsupported_media_type() {
+    use finchers::endpoints::body;
+    use http::Request;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Param {}
+
+    let mut runner = test::runner({
+        let e1 = syntax::segment("foo")
+            .and(body::json::<Param>())
+            .map(|_: Param| "json");
+        let e2 = syntax::segment("bar").map(|| "bar");
+        e1.or(e2).or(syntax::segment("baz").map(|| "baz"))
+    });
+
+    assert_matches!(
+        runner.apply(Request::post("/foo").header("content-type", "text/plain").body("{}")),
+        Err(ref err) if err.status_code().as_u16() == 415
+    );
+    assert_matches!(
+        runner.apply("/foobar"),
+        Err(ref err) if err.status_code().as_u16() == 404
+    );
+}
+
+#[test]
+fn test_or_into_either3() {
+    use finchers::output::Json;
+    use finchers::util::either::Either3;
+
+    let mut runner = test::runner(
+        syntax::segment("text")
+            .map(|| "text")
+            .or(syntax::segment("json").map(|| Json(vec![1, 2])))
+            .or(syntax::segment("empty").map(|| ()))
+            .map(Either3::from),
+    );
+
+    runner.perform("/text").unwrap().assert_body("text");
+    runner
+        .perform("/json")
+        .unwrap()
+        .assert_header("content-type", "application/json")
+        .assert_body("[1,2]");
+    assert_eq!(runner.perform("/empty").unwrap().status(), 204);
+}
+
+#[test]
+fn test_or_fallback() {
+    use finchers::endpoint::{fallback, RouteMiss};
+
+    let mut runner = test::runner({
+        let users = syntax::verb::get()
+            .and(syntax::segment("api"))
+            .and(syntax::segment("users"))
+            .and(syntax::param::<u32>())
+            .and(syntax::eos())
+            .map(|id: u32| format!("user {}", id));
+        let posts = syntax::verb::post()
+            .and(syntax::segment("api"))
+            .and(syntax::segment("posts"))
+            .and(syntax::eos())
+            .map(|| "posted".to_owned());
+        users
+            .or_strict(posts)
+            .or_strict(fallback(|miss: RouteMiss| {
+                let candidates = miss
+                    .candidates()
+                    .iter()
+                    .map(|(status, _)| status.as_str().to_owned())
+                    .collect::<Vec<_>>();
+                Ok::<_, finchers::error::Error>(format!(
+                    "{} {} [{}] {:?}",
+                    miss.method(),
+                    miss.path(),
+                    candidates.join(","),
+                    miss.suggestions(),
+                ))
+            }))
+    });
+
+    assert_eq!(runner.apply("/api/users/42").unwrap(), "user 42");
+    assert_eq!(
+        runner.apply("/api/usr/42").unwrap(),
+        "GET /api/usr/42 [] [\"/api/users/42\"]"
+    );
+    assert_eq!(runner.apply("/unknown").unwrap(), "GET /unknown [] []");
+    assert_eq!(
+        runner.apply(http::Request::post("/api/users/42")).unwrap(),
+        "POST /api/users/42 [405] []"
+    );
+}
@@ -0,0 +1,89 @@
+use finchers::endpoint::{syntax, wrapper};
+use finchers::prelude::*;
+use finchers::test;
+use matches::assert_matches;
+
+#[test]
+fn test_scope() {
+    let users = syntax::segment("users")
+        .and(syntax::param::<u32>())
+        .map(|id: u32| format!("user {}", id));
+    let mut runner = test::runner(endpoint::scope("/api/v1/", users));
+
+    assert_eq!(
+        runner.apply("/api/v1/users/42").ok(),
+        Some("user 42".into())
+    );
+    assert_matches!(runner.apply("/api/v2/users/42"), Err(..));
+    assert_matches!(runner.apply("/api"), Err(..));
+}
+
+#[test]
+fn test_nest_wrap_subtree() {
+    let mut runner = test::runner(
+        syntax::segment("foo")
+            .map(|| "foo")
+            .or_strict(syntax::segment("bar").map(|| "bar"))
+            .nest("/api")
+            .wrap(wrapper::map_output(|s: &'static str| s.to_uppercase()))
+            .or_strict(syntax::segment("baz").map(|| String::from("baz"))),
+    );
+
+    assert_eq!(runner.apply("/api/bar").ok(), Some("BAR".into()));
+    assert_eq!(runner.apply("/baz").ok(), Some("baz".into()));
+}
+
+#[test]
+fn test_scope_with_state() {
+    #[derive(Clone)]
+    struct Prefix(&'static str);
+
+    let greet = syntax::param::<String>()
+        .and(endpoint::state::<Prefix>())
+        .map(|name: String, prefix: Prefix| format!("{}, {}", prefix.0, name));
+
+    let mut runner = test::runner(
+        endpoint::scope("/en", greet)
+            .with_state(Prefix("Hello"))
+            .or_strict(endpoint::scope("/fr", greet).with_state(Prefix("Bonjour")))
+            .or_strict(endpoint::scope("/none", greet)),
+    );
+
+    assert_eq!(runner.apply("/en/alice").ok(), Some("Hello, alice".into()));
+    assert_eq!(
+        runner.apply("/fr/alice").ok(),
+        Some("Bonjour, alice".into())
+    );
+    assert_matches!(
+        runner.apply("/none/alice"),
+        Err(ref e) if e.status_code().as_u16() == 500
+    );
+}
+
+#[test]
+fn test_override_state() {
+    #[derive(Clone)]
+    struct Prefix(&'static str);
+
+    let greet = syntax::param::<String>()
+        .and(endpoint::state::<Prefix>())
+        .map(|name: String, prefix: Prefix| format!("{}, {}", prefix.0, name));
+
+    let mut runner = test::runner(
+        endpoint::scope("/en", greet)
+            .with_state(Prefix("Hello"))
+            .or_strict(endpoint::scope("/none", greet)),
+    );
+
+    runner.override_state(Prefix("Fake"));
+    assert_eq!(runner.apply("/en/alice").ok(), Some("Fake, alice".into()));
+    assert_eq!(runner.apply("/none/alice").ok(), Some("Fake, alice".into()));
+
+    assert!(runner.restore_state::<Prefix>());
+    assert!(!runner.restore_state::<Prefix>());
+    assert_eq!(runner.apply("/en/alice").ok(), Some("Hello, alice".into()));
+    assert_matches!(
+        runner.apply("/none/alice"),
+        Err(ref e) if e.status_code().as_u16() == 500
+    );
+}
@@ -0,0 +1,115 @@
+use finchers::endpoint::{syntax, SplitKey, Variant};
+use finchers::prelude::*;
+use finchers::service::Context;
+use finchers::test;
+use http::Request;
+
+fn variants(weight: f64, keys: impl Iterator<Item = String>) -> Vec<String> {
+    let mut runner = test::runner(
+        endpoint::split(
+            weight,
+            syntax::segment("a").map(|| "a"),
+            syntax::segment("a").map(|| "b"),
+        )
+        .key(SplitKey::header("x-user")),
+    );
+    keys.map(|key| {
+        let request = Request::get("/a").header("x-user", key).body("").unwrap();
+        runner
+            .perform(request)
+            .unwrap()
+            .to_utf8_lossy()
+            .into_owned()
+    })
+    .collect()
+}
+
+#[test]
+fn test_split_deterministic() {
+    let split = endpoint::split(
+        0.5,
+        syntax::segment("a").map(|| "a"),
+        syntax::segment("a").map(|| "b"),
+    )
+    .key(SplitKey::header("x-user"));
+    let mut runner = test::runner(split.clone());
+
+    let first: Vec<_> = (0..100)
+        .map(|i| {
+            let request = Request::get("/a")
+                .header("x-user", i.to_string())
+                .body("")
+                .unwrap();
+            runner
+                .perform(request)
+                .unwrap()
+                .to_utf8_lossy()
+                .into_owned()
+        })
+        .collect();
+    let second: Vec<_> = (0..100)
+        .map(|i| {
+            let request = Request::get("/a")
+                .header("x-user", i.to_string())
+                .body("")
+                .unwrap();
+            runner
+                .perform(request)
+                .unwrap()
+                .to_utf8_lossy()
+                .into_owned()
+        })
+        .collect();
+    assert_eq!(first, second);
+
+    let b = first.iter().filter(|v| *v == "b").count() as u64;
+    assert!(30 < b && b < 70, "{}", b);
+    assert_eq!(split.stats().b(), b * 2);
+    assert_eq!(split.stats().requests(), 200);
+
+    // the requests without the key are routed to `a`.
+    assert_eq!(runner.perform("/a").unwrap().to_utf8_lossy(), "a");
+}
+
+#[test]
+fn test_split_weight() {
+    let keys = || (0..1000).map(|i| format!("user-{}", i));
+    let narrow = variants(0.1, keys());
+    let wide = variants(0.9, keys());
+
+    let a = narrow.iter().filter(|v| *v == "a").count();
+    assert!(50 < a && a < 150, "{}", a);
+    let a = wide.iter().filter(|v| *v == "a").count();
+    assert!(850 < a && a < 950, "{}", a);
+
+    // increasing the weight only moves the keys from `b` to `a`.
+    assert!(narrow
+        .iter()
+        .zip(&wide)
+        .all(|(narrow, wide)| narrow == "b" || wide == "a"));
+}
+
+#[test]
+fn test_split_variant() {
+    let variant = || {
+        endpoint::endpoint(|| {
+            futures::future::lazy(|| {
+                let variant = Context::with(|cx| cx.extensions().get::<Variant>().cloned());
+                Ok::<_, finchers::error::Error>((variant,))
+            })
+        })
+    };
+    let mut runner = test::runner(
+        endpoint::split(0.0, variant(), variant())
+            .name("exp")
+            .key(SplitKey::cookie("uid")),
+    );
+
+    let variant = runner
+        .apply(Request::get("/").header("cookie", "lang=ja; uid=alice"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(variant.experiment(), "exp");
+    assert!(variant.is_b());
+    assert_eq!(variant.labels(), [("experiment", "exp"), ("variant", "b")]);
+}
@@ -48,15 +48,204 @@ fn test_extract_strings() {
     );
 }
 
-// #[test]
-// fn test_path_macro() {
-//     let mut runner = test::runner(
-//         path!(@get / "posts" / u32 / "stars" /)
-//             .map(|id: u32| format!("id={}", id))
-//             .with_output::<(String,)>(),
-//     );
-//     assert_matches!(
-//         runner.apply("/posts/42/stars"),
-//         Ok(ref s) if s == "id=42"
-//     );
-// }
+#[test]
+fn test_path_macro() {
+    let mut runner = test::runner(
+        syntax::path!(@get "/posts/<u32>/stars/") //
+            .map(|id: u32| format!("id={}", id)),
+    );
+    assert_matches!(
+        runner.apply("/posts/42/stars"),
+        Ok(ref s) if s == "id=42"
+    );
+    assert_matches!(runner.apply("/posts/42/stars/1"), Err(..));
+}
+
+#[test]
+fn test_remains_safe_path() {
+    use finchers::endpoint::syntax::encoded::SafePathBuf;
+    use std::path::Path;
+
+    let mut runner = test::runner(syntax::segment("files").and(syntax::remains::<SafePathBuf>()));
+
+    assert_matches!(
+        runner.apply("/files/a/./b%20c.txt"),
+        Ok(ref path) if **path == *Path::new("a/b c.txt")
+    );
+    assert_matches!(runner.apply("/files"), Ok(ref path) if path.as_os_str().is_empty());
+    assert_matches!(runner.apply("/files/a/../../etc/passwd"), Err(..));
+    assert_matches!(runner.apply("/files/%2e%2e/etc/passwd"), Err(..));
+    assert_matches!(runner.apply("/files/a%2F..%2F..%2Fetc"), Err(..));
+    assert_matches!(runner.apply("/files/a%5C..%5Cetc"), Err(..));
+    assert_matches!(runner.apply("/files/a%00.txt"), Err(..));
+}
+
+#[test]
+fn test_decode_policies() {
+    use finchers::endpoint::syntax::encoded::{DecodeExceptSlash, PercentDecoded, Raw};
+
+    let mut runner = test::runner(syntax::param::<Raw>());
+    assert_matches!(runner.apply("/a%20b"), Ok(Raw(ref s)) if s == "a%20b");
+
+    let mut runner = test::runner(syntax::param::<Raw<u32>>());
+    assert_matches!(runner.apply("/42"), Ok(Raw(42)));
+    assert_matches!(runner.apply("/%342"), Err(..));
+
+    let mut runner = test::runner(syntax::remains::<DecodeExceptSlash>());
+    assert_matches!(
+        runner.apply("/a%20b/c%2fd%25"),
+        Ok(DecodeExceptSlash(ref s)) if s == "a b/c%2fd%25"
+    );
+    assert_matches!(
+        runner.apply("/a%252Fb"),
+        Ok(DecodeExceptSlash(ref s)) if s == "a%252Fb"
+    );
+    assert_matches!(runner.apply("/%FF"), Err(..));
+
+    let mut runner = test::runner(syntax::param::<PercentDecoded<u32>>());
+    assert_matches!(
+        runner.apply("/%342"),
+        Ok(ref p) if p.raw() == "%342" && *p.value() == 42
+    );
+}
+
+#[test]
+fn test_matrix_params() {
+    use finchers::endpoint::syntax::matrix::{self, MatrixParams};
+
+    let mut runner = test::runner(
+        matrix::segment("cars")
+            .and(matrix::segment("doors"))
+            .map(|cars: MatrixParams, doors: MatrixParams| (cars, doors)),
+    );
+    let (cars, doors) = runner
+        .apply("/cars;color=red;color=blue;used/doors;count=4")
+        .unwrap();
+    assert_eq!(cars.get::<String>("color").unwrap(), Some("red".into()));
+    assert_eq!(
+        cars.get_all::<String>("color").unwrap(),
+        vec!["red".to_string(), "blue".to_string()]
+    );
+    assert!(cars.contains("used"));
+    assert_eq!(doors.get::<u32>("count").unwrap(), Some(4));
+    assert!(doors.get::<u32>("missing").unwrap().is_none());
+    assert!(runner.apply("/cars/doors").is_ok());
+    assert!(runner.apply("/trucks;color=red/doors").is_err());
+
+    let mut runner =
+        test::runner(matrix::param::<String>().map(|s: String, params: MatrixParams| (s, params)));
+    assert_matches!(
+        runner.apply("/a%20b;x=%3B"),
+        Ok((ref s, ref params)) if s == "a b" && params.get::<String>("x").unwrap() == Some(";".into())
+    );
+
+    // The default endpoints compare the whole segment.
+    let mut runner = test::runner(syntax::segment("cars"));
+    assert_matches!(runner.apply_raw("/cars;color=red"), Err(..));
+}
+
+#[test]
+fn test_api_version() {
+    use finchers::endpoint::syntax::ApiVersion;
+    use http::Request;
+
+    let version = syntax::api_version(&[1, 2]).header("x-api-version");
+    let mut runner = test::runner(
+        version
+            .only(1)
+            .and(syntax::segment("users"))
+            .map(|| "users v1")
+            .or_strict(
+                version
+                    .only(2)
+                    .and(syntax::segment("users"))
+                    .map(|| "users v2"),
+            ),
+    );
+
+    assert_matches!(runner.apply("/v1/users"), Ok("users v1"));
+    assert_matches!(runner.apply("/V2/users"), Ok("users v2"));
+    assert_matches!(
+        runner.apply(Request::get("/users").header("x-api-version", "2")),
+        Ok("users v2")
+    );
+    assert_matches!(
+        runner.apply(
+            Request::get("/users").header("accept", "application/vnd.example+json; version=1")
+        ),
+        Ok("users v1")
+    );
+
+    let response = runner
+        .perform(Request::get("/users").header("x-api-version", "3"))
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 406);
+    assert_eq!(
+        response.to_utf8_lossy(),
+        "unsupported API version: v3 (supported versions: v1, v2)"
+    );
+
+    runner
+        .perform("/v3/users")
+        .unwrap()
+        .assert_status(404)
+        .assert_body("unsupported API version: v3 (supported versions: v1, v2)");
+    runner.perform("/users").unwrap().assert_status(404);
+
+    let mut runner = test::runner(
+        version
+            .only(1)
+            .map(|| "v1")
+            .or_strict(version.only(2).map(|| "v2"))
+            .or_strict(syntax::segment("health").map(|| "ok")),
+    );
+    runner
+        .perform(Request::get("/").header("x-api-version", "3"))
+        .unwrap()
+        .assert_status(406)
+        .assert_body("unsupported API version: v3 (supported versions: v1, v2)");
+    runner
+        .perform("/v3")
+        .unwrap()
+        .assert_status(404)
+        .assert_body("unsupported API version: v3 (supported versions: v1, v2)");
+
+    // The other errors are not reported instead of `404 Not Found`, even if they are the same.
+    let mut runner = test::runner(
+        syntax::verb::get()
+            .and(syntax::segment("foo"))
+            .or_strict(syntax::verb::get().and(syntax::segment("foo"))),
+    );
+    runner
+        .perform(Request::post("/foo"))
+        .unwrap()
+        .assert_status(404);
+
+    let mut runner = test::runner(syntax::api_version(&[1, 2]).default_version(2));
+    assert_matches!(runner.apply("/"), Ok(ApiVersion(2)));
+    assert_matches!(runner.apply("/v1"), Ok(ApiVersion(1)));
+}
+
+#[test]
+fn test_param_str() {
+    use finchers::endpoint::syntax::encoded::SharedStr;
+
+    let mut runner = test::runner(
+        syntax::segment("users")
+            .and(syntax::param_str())
+            .and(syntax::param_str())
+            .map(|a: SharedStr, b: SharedStr| format!("{}:{}", a, b)),
+    );
+
+    assert_eq!(
+        runner.apply("/users/alice/bob").ok(),
+        Some("alice:bob".into())
+    );
+    assert_eq!(runner.apply("/users/a%20b/c").ok(), Some("a b:c".into()));
+    assert_matches!(runner.apply("/users/alice"), Err(..));
+    assert_matches!(runner.apply("/users//bob"), Err(..));
+    assert_matches!(
+        runner.apply("/users/%FF/bob"),
+        Err(ref e) if e.status_code().as_u16() == 400
+    );
+}
@@ -0,0 +1,404 @@
+use finchers::endpoint::wrapper;
+use finchers::prelude::*;
+use finchers::test;
+use http::Response;
+use matches::assert_matches;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn test_map_output() {
+    let mut runner =
+        test::runner(endpoint::value("Foo").wrap(wrapper::map_output(|s: &'static str| s.len())));
+    assert_matches!(runner.apply("/"), Ok(3));
+}
+
+#[test]
+fn test_map_response() {
+    let mut runner = test::runner(endpoint::value("Foo").wrap(wrapper::map_response(
+        |response: Response<&'static str>| {
+            let (mut parts, body) = response.into_parts();
+            parts
+                .headers
+                .insert("x-body-length", body.len().to_string().parse().unwrap());
+            Response::from_parts(parts, body.to_lowercase())
+        },
+    )));
+
+    runner
+        .perform("/")
+        .unwrap()
+        .assert_status(200)
+        .assert_header("x-body-length", "3")
+        .assert_body("foo");
+}
+
+#[test]
+fn test_map_response_passes_through_errors() {
+    let mut runner = test::runner(endpoint::syntax::segment("foo").map(|| "Foo").wrap(
+        wrapper::map_response(|response: Response<&'static str>| {
+            response.map(|body| body.to_uppercase())
+        }),
+    ));
+
+    runner.perform("/bar").unwrap().assert_status(404);
+}
+
+#[test]
+fn test_timeout() {
+    let mut runner = test::runner(
+        endpoint::unit()
+            .and_then(futures::future::empty::<&'static str, finchers::error::Error>)
+            .wrap(wrapper::timeout(Duration::from_millis(10))),
+    );
+    assert_matches!(
+        runner.apply("/"),
+        Err(ref err) if err.status_code().as_u16() == 503
+    );
+
+    let mut runner =
+        test::runner(endpoint::value("Foo").wrap(wrapper::timeout(Duration::from_millis(10))));
+    assert_matches!(runner.apply("/"), Ok("Foo"));
+}
+
+#[test]
+fn test_security_headers() {
+    let mut runner = test::runner(
+        endpoint::syntax::segment("ok")
+            .map(|| "Foo")
+            .or(endpoint::syntax::segment("err").and_then(|| {
+                futures::future::err::<&'static str, _>(finchers::error::bad_request("bar"))
+            }))
+            .wrap(wrapper::security_headers()),
+    );
+
+    runner
+        .perform("/ok")
+        .unwrap()
+        .assert_status(200)
+        .assert_header(
+            "strict-transport-security",
+            "max-age=31536000; includeSubDomains",
+        )
+        .assert_header("x-content-type-options", "nosniff")
+        .assert_header("x-frame-options", "DENY")
+        .assert_header("referrer-policy", "strict-origin-when-cross-origin")
+        .assert_no_header("content-security-policy");
+
+    runner
+        .perform("/err")
+        .unwrap()
+        .assert_status(400)
+        .assert_header("x-frame-options", "DENY");
+
+    runner
+        .perform("/missing")
+        .unwrap()
+        .assert_status(404)
+        .assert_header("x-frame-options", "DENY")
+        .assert_header("x-content-type-options", "nosniff");
+}
+
+#[test]
+fn test_security_headers_csp() {
+    let csp = wrapper::ContentSecurityPolicy::new()
+        .default_src(&["'self'"])
+        .merge(
+            wrapper::ContentSecurityPolicy::new()
+                .default_src(&["'self'", "https://cdn.example.com"])
+                .script_src(&["'none'"])
+                .report_uri("/csp-reports"),
+        )
+        .report_uri("/csp-violations")
+        .report_only(true);
+    let mut runner = test::runner(
+        endpoint::value("Foo").wrap(
+            wrapper::security_headers()
+                .without_hsts()
+                .frame_options(wrapper::FrameOptions::SameOrigin)
+                .content_security_policy(csp),
+        ),
+    );
+
+    runner
+        .perform("/")
+        .unwrap()
+        .assert_no_header("strict-transport-security")
+        .assert_header("x-frame-options", "SAMEORIGIN")
+        .assert_header(
+            "content-security-policy-report-only",
+            "default-src 'self' https://cdn.example.com; script-src 'none'; \
+             report-uri /csp-violations",
+        );
+}
+
+#[test]
+fn test_tarpit() {
+    let called = Arc::new(AtomicUsize::new(0));
+    let mut runner = test::runner({
+        let called = called.clone();
+        endpoint::unit()
+            .map(move || {
+                called.fetch_add(1, Ordering::SeqCst);
+                "Foo"
+            })
+            .wrap(
+                wrapper::tarpit()
+                    .delay(Duration::from_millis(10))
+                    .close_connection(),
+            )
+    });
+
+    runner.perform("/foo").unwrap().assert_body("Foo");
+    runner
+        .perform("/wp-admin/install.php")
+        .unwrap()
+        .assert_status(404);
+    runner
+        .perform("/static/%2E%2E/%2e%2e/etc/passwd")
+        .unwrap()
+        .assert_status(404);
+    assert_eq!(called.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_is_suspicious_path() {
+    assert!(wrapper::is_suspicious_path("/.env"));
+    assert!(wrapper::is_suspicious_path("/foo/../bar"));
+    assert!(wrapper::is_suspicious_path("/foo/%2e./bar"));
+    assert!(wrapper::is_suspicious_path("/foo/..%2fbar"));
+    assert!(wrapper::is_suspicious_path(
+        "/foo/..%5C..%5Cwindows/win.ini"
+    ));
+    assert!(wrapper::is_suspicious_path("/download.php%00.jpg"));
+    assert!(wrapper::is_suspicious_path("/etc/passwd%00"));
+    assert!(!wrapper::is_suspicious_path("/foo/bar.env"));
+    assert!(!wrapper::is_suspicious_path("/api/v1/users"));
+    assert!(!wrapper::is_suspicious_path("/files/docs%2Freport.pdf"));
+    assert!(!wrapper::is_suspicious_path("/repos/a%5Cb"));
+    assert!(!wrapper::is_suspicious_path("/items/%00abc"));
+}
+
+#[test]
+fn test_route_diagnostics() {
+    use finchers::endpoint::syntax;
+
+    let mut runner = test::runner(
+        syntax::verb::get()
+            .and(syntax::segment("foo"))
+            .map(|| "foo")
+            .or(syntax::verb::post()
+                .and(syntax::segment("bar"))
+                .map(|| "bar"))
+            .or(syntax::segment("baz").map(|| "baz"))
+            .wrap(wrapper::route_diagnostics()),
+    );
+
+    runner.perform("/foo").unwrap().assert_body("foo");
+
+    let response = runner.perform(http::Request::put("/foo")).unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+    let body = response.to_utf8_lossy();
+    assert!(body.starts_with("not matched\n\ncandidate routes:"));
+    assert!(body.contains("- 405 Method Not Allowed: invalid method (expected GET)"));
+    assert!(!body.contains("expected POST"));
+
+    runner
+        .perform("/qux")
+        .unwrap()
+        .assert_status(404)
+        .assert_body("not matched\n\nno candidate routes");
+
+    let mut runner = test::runner(
+        syntax::segment("foo")
+            .map(|| "foo")
+            .or(syntax::segment("bar").map(|| "bar"))
+            .wrap(wrapper::route_diagnostics()),
+    );
+    runner
+        .perform("/baz")
+        .unwrap()
+        .assert_status(404)
+        .assert_body("not matched\n\nno candidate routes");
+}
+
+#[test]
+fn test_trace() {
+    use finchers::endpoint::syntax;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Logger(Arc<Mutex<Vec<String>>>);
+
+    impl log::Log for Logger {
+        fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+            metadata.target() == "finchers::trace"
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            if self.enabled(record.metadata()) {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    let logger = Logger::default();
+
+    let mut runner = test::runner(
+        syntax::segment("foo")
+            .and(syntax::segment("bar"))
+            .map(|| "foo")
+            .wrap(wrapper::trace("foo").logger(logger.clone()))
+            .or(syntax::segment("baz")
+                .and_then(|| futures::future::ok::<_, finchers::error::Error>("baz"))
+                .wrap(wrapper::trace("baz").logger(logger.clone()))),
+    );
+    runner.perform("/foo/bar").unwrap().assert_body("foo");
+    runner.perform("/baz").unwrap().assert_body("baz");
+
+    let logs = logger.0.lock().unwrap();
+    assert_eq!(logs.len(), 6);
+    assert_eq!(logs[0], r#"[foo] matched (consumed: "foo/bar")"#);
+    assert_eq!(logs[1], "[baz] not matched: not matched (404 Not Found)");
+    assert!(logs[2].starts_with("[foo] completed in "));
+    assert_eq!(logs[3], "[foo] not matched: not matched (404 Not Found)");
+    assert_eq!(logs[4], r#"[baz] matched (consumed: "baz")"#);
+    assert!(logs[5].starts_with("[baz] completed in "));
+}
+
+#[test]
+fn test_latency_budget() {
+    use futures::Future;
+    use std::sync::Mutex;
+    use tokio::timer::Delay;
+
+    let violations = Arc::new(Mutex::new(vec![]));
+    let metrics = wrapper::BudgetMetrics::new();
+    let mut runner = test::runner({
+        let violations = violations.clone();
+        endpoint::syntax::segment("fast")
+            .map(|| "fast")
+            .or(
+                endpoint::syntax::segment("slow").and(endpoint::endpoint(|| {
+                    futures::future::lazy(|| {
+                        finchers::service::Context::with(|cx| {
+                            cx.timings().record("db", Duration::from_millis(150));
+                        });
+                        Delay::new(tokio::clock::now() + Duration::from_millis(200))
+                            .map(|()| ("slow",))
+                            .map_err(finchers::error::internal_server_error)
+                    })
+                })),
+            )
+            .wrap(
+                wrapper::latency_budget(Duration::from_millis(100))
+                    .name("api")
+                    .server_timing(true)
+                    .metrics(&metrics)
+                    .on_violation(move |violation: &wrapper::BudgetViolation| {
+                        violations.lock().unwrap().push(violation.clone());
+                    }),
+            )
+    });
+
+    let response = runner.perform("/fast").unwrap();
+    response.assert_body("fast");
+    let server_timing = response.headers()["server-timing"].to_str().unwrap();
+    assert!(server_timing.starts_with("routing;dur="));
+    assert!(server_timing.contains(", handler;dur="));
+    assert!(violations.lock().unwrap().is_empty());
+
+    let clock = runner.clock().clone();
+    runner.runtime().spawn(futures::future::lazy(move || {
+        clock.advance(Duration::from_millis(200));
+        Ok(())
+    }));
+    let response = runner.perform("/slow").unwrap();
+    response.assert_body("slow");
+    // the phase timings are merged into the ones recorded in the context.
+    let server_timing: Vec<_> = response.headers().get_all("server-timing").iter().collect();
+    assert_eq!(server_timing.len(), 1);
+    let server_timing = server_timing[0].to_str().unwrap();
+    assert!(server_timing.starts_with("db;dur=150.000, routing;dur="));
+    assert!(server_timing.contains(", serialization;dur="));
+
+    let violations = violations.lock().unwrap();
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].name.as_ref().map(|name| &**name), Some("api"));
+    assert!(violations[0].timings.handler >= Duration::from_millis(200));
+    assert!(violations[0].timings.total() > violations[0].budget);
+
+    let stats = metrics.get("api").expect("should be recorded");
+    assert_eq!(stats.requests, 2);
+    assert_eq!(stats.violations, 1);
+    assert!(stats.total.handler >= Duration::from_millis(200));
+    assert_eq!(stats.max, violations[0].timings.total());
+    assert_eq!(metrics.snapshot().len(), 1);
+}
+
+#[test]
+fn test_pipeline() {
+    let standard = wrapper::pipeline()
+        .with(wrapper::map_output(|s: &'static str| s.len()))
+        .with(wrapper::map_output(|n: usize| n * 2));
+
+    let mut runner = test::runner(endpoint::value("Foo").wrap(standard));
+    assert_matches!(runner.apply("/"), Ok(6));
+
+    let nested = wrapper::pipeline()
+        .with(wrapper::map_output(|s: &'static str| s.to_uppercase()))
+        .then(wrapper::pipeline().with(wrapper::map_output(|s: String| s + "!")));
+
+    let mut runner = test::runner(endpoint::value("Foo").wrap(nested));
+    assert_eq!(runner.apply("/").ok(), Some("FOO!".into()));
+}
+
+#[test]
+fn test_when() {
+    let endpoint = endpoint::syntax::segment("foo")
+        .map(|| "foo")
+        .or(endpoint::syntax::segment("bar").map(|| "bar"))
+        .wrap(wrapper::when(
+            wrapper::header_present(http::header::HeaderName::from_static("x-debug")),
+            wrapper::map_output(|s: either::Either<&'static str, &'static str>| {
+                s.into_inner().to_uppercase()
+            }),
+        ));
+    let mut runner = test::runner(endpoint);
+
+    assert_matches!(
+        runner.apply(http::Request::get("/foo").header("x-debug", "1").body("").unwrap()),
+        Ok(either::Either::Left(ref s)) if s == "FOO"
+    );
+    assert_matches!(
+        runner.apply("/bar"),
+        Ok(either::Either::Right(either::Either::Right("bar")))
+    );
+    assert_matches!(
+        runner.apply(http::Request::get("/baz").header("x-debug", "1").body("").unwrap()),
+        Err(ref err) if err.status_code().as_u16() == 404
+    );
+}
+
+#[test]
+fn test_when_path_prefix() {
+    let endpoint = endpoint::syntax::segment("admin")
+        .map(|| "admin")
+        .or(endpoint::syntax::segment("administrator").map(|| "administrator"))
+        .wrap(wrapper::when(
+            wrapper::path_prefix("/admin"),
+            wrapper::map_output(|_| "wrapped"),
+        ));
+    let mut runner = test::runner(endpoint);
+
+    assert_matches!(runner.apply("/admin"), Ok(either::Either::Left("wrapped")));
+    assert_matches!(
+        runner.apply("/administrator"),
+        Ok(either::Either::Right(either::Either::Right(
+            "administrator"
+        )))
+    );
+}
@@ -0,0 +1,29 @@
+use finchers::endpoints::auth::{self, ClientCertChain};
+use finchers::test;
+use http::Request;
+use matches::assert_matches;
+
+#[test]
+fn test_client_cert() {
+    let mut runner = test::runner(auth::client_cert());
+
+    let chain = ClientCertChain::new(vec![b"leaf".to_vec(), b"intermediate".to_vec()]);
+    assert_matches!(
+        runner.apply(Request::get("/").extension(chain)),
+        Ok(ref chain) if chain.leaf() == Some(&b"leaf"[..]) && chain.len() == 2
+    );
+}
+
+#[test]
+fn test_client_cert_missing() {
+    let mut runner = test::runner(auth::client_cert());
+
+    assert_matches!(
+        runner.apply(Request::get("/")),
+        Err(ref err) if err.status_code().as_u16() == 401
+    );
+    assert_matches!(
+        runner.apply(Request::get("/").extension(ClientCertChain::new(Vec::<Vec<u8>>::new()))),
+        Err(ref err) if err.status_code().as_u16() == 401
+    );
+}
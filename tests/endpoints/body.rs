@@ -17,6 +17,55 @@ fn test_body_text() {
     );
 }
 
+#[test]
+fn test_body_text_charset() {
+    let mut runner = test::runner(body::text());
+
+    assert_matches!(
+        runner.apply(Request::post("/")
+            .header("content-type", "text/plain; charset=x-unknown")
+            .body("foo")),
+        Err(ref err) if err.status_code().as_u16() == 415
+    );
+    assert_matches!(
+        runner.apply(Request::post("/")
+            .header("content-type", "text/plain")
+            .body(&b"\xff"[..])),
+        Err(ref err) if err.status_code().as_u16() == 400
+    );
+}
+
+#[cfg(feature = "encoding")]
+#[test]
+fn test_body_text_encoding() {
+    let mut runner = test::runner(body::text());
+
+    assert_matches!(
+        runner.apply(Request::post("/")
+            .header("content-type", "text/plain; charset=iso-8859-1")
+            .body(&b"caf\xe9"[..])),
+        Ok(ref s) if s == "caf\u{e9}"
+    );
+    assert_matches!(
+        runner.apply(Request::post("/")
+            .header("content-type", "text/plain; charset=utf-16")
+            .body(&b"\xfe\xff\x00h\x00i"[..])),
+        Ok(ref s) if s == "hi"
+    );
+    assert_matches!(
+        runner.apply(Request::post("/")
+            .header("content-type", "text/plain; charset=utf-16le")
+            .body(&b"h\x00i\x00"[..])),
+        Ok(ref s) if s == "hi"
+    );
+    assert_matches!(
+        runner.apply(Request::post("/")
+            .header("content-type", "text/plain; charset=utf-16le")
+            .body(&b"h\x00i"[..])),
+        Err(ref err) if err.status_code().as_u16() == 400
+    );
+}
+
 #[test]
 fn test_body_json() {
     #[derive(Debug, PartialEq, serde::Deserialize)]
@@ -46,7 +95,7 @@ fn test_body_json() {
                 .header("content-type", "text/plain")
                 .body(r#"{ "text": "TRPL2" }"#)
         ),
-        Err(..)
+        Err(ref err) if err.status_code().as_u16() == 415
     );
 
     // invalid data
@@ -107,3 +156,63 @@ fn test_body_urlencoded() {
         Err(..)
     );
 }
+
+#[cfg(feature = "integrity")]
+#[test]
+fn test_body_verified() {
+    use finchers::endpoints::body::Algorithm;
+
+    // SHA-256 of "hello"
+    const SHA256_HEX: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    const SHA256_BASE64: &str = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+
+    let mut runner = test::runner(body::verified(Algorithm::Sha256));
+
+    assert_matches!(
+        runner.apply(Request::post("/")
+            .header("digest", format!("SHA-256={}", SHA256_BASE64).as_str())
+            .body("hello")),
+        Ok(ref body) if body.data() == b"hello" && body.digest().len() == 32
+    );
+    assert_matches!(
+        runner.apply(
+            Request::post("/")
+                .header(
+                    "content-digest",
+                    format!("sha-256=:{}:", SHA256_BASE64).as_str()
+                )
+                .body("hello")
+        ),
+        Ok(..)
+    );
+    assert_matches!(
+        runner.apply(
+            Request::post("/")
+                .header("x-amz-content-sha256", SHA256_HEX)
+                .body("hello")
+        ),
+        Ok(..)
+    );
+
+    // mismatched digest
+    assert_matches!(
+        runner.apply(Request::post("/")
+            .header("x-amz-content-sha256", SHA256_HEX)
+            .body("hello!")),
+        Err(ref err) if err.status_code().as_u16() == 400
+    );
+
+    // missing digest
+    assert_matches!(
+        runner.apply(Request::post("/")
+            .header("x-amz-content-sha256", "UNSIGNED-PAYLOAD")
+            .body("hello")),
+        Err(ref err) if err.status_code().as_u16() == 400
+    );
+
+    let mut runner = test::runner(body::verified(Algorithm::Sha256).allow_missing(true));
+    assert_matches!(
+        runner.apply(Request::post("/").body("hello")),
+        Ok(ref body) if body.digest()[..2] == [0x2c, 0xf2]
+    );
+}
@@ -0,0 +1,35 @@
+use finchers::endpoint::wrapper;
+use finchers::endpoints;
+use finchers::prelude::*;
+use finchers::service::Cancelled;
+use finchers::test;
+use futures::Future;
+use matches::assert_matches;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn test_on_disconnect_with_timeout() {
+    let stopped = Arc::new(AtomicBool::new(false));
+    let mut runner = test::runner({
+        let stopped = stopped.clone();
+        endpoints::on_disconnect()
+            .and_then(move |cancelled: Cancelled| {
+                let stopped = stopped.clone();
+                tokio::spawn(cancelled.then(move |_| {
+                    stopped.store(true, Ordering::SeqCst);
+                    Ok(())
+                }));
+                futures::future::empty::<&'static str, finchers::error::Error>()
+            })
+            .wrap(wrapper::timeout(Duration::from_millis(10)))
+    });
+
+    assert_matches!(
+        runner.apply("/"),
+        Err(ref err) if err.status_code().as_u16() == 503
+    );
+    runner.runtime().run().unwrap();
+    assert!(stopped.load(Ordering::SeqCst));
+}
@@ -0,0 +1,69 @@
+use finchers::endpoints::expect;
+use finchers::error;
+use finchers::prelude::*;
+use finchers::test;
+use http::Request;
+use matches::assert_matches;
+
+#[test]
+fn test_continue_if() {
+    let mut runner = test::runner(expect::continue_if(|request| {
+        if request.headers().contains_key("authorization") {
+            Ok(())
+        } else {
+            Err(error::unauthorized("missing credential"))
+        }
+    }));
+
+    assert_matches!(runner.apply_raw(Request::post("/")), Ok(()));
+    assert_matches!(
+        runner.apply_raw(Request::post("/").header("expect", "100-continue")),
+        Err(ref err) if err.status_code().as_u16() == 401
+    );
+    assert_matches!(
+        runner.apply_raw(
+            Request::post("/")
+                .header("expect", "100-continue")
+                .header("authorization", "Bearer xxx")
+        ),
+        Ok(())
+    );
+    assert_matches!(
+        runner.apply_raw(Request::post("/").header("expect", "unknown")),
+        Err(ref err) if err.status_code().as_u16() == 417
+    );
+}
+
+#[test]
+fn test_max_length() {
+    let mut runner = test::runner(
+        expect::max_length(4)
+            .and(endpoints::body::text())
+            .map(|text: String| text),
+    );
+
+    assert_matches!(
+        runner.apply(Request::post("/").body("foo")),
+        Ok(ref text) if text == "foo"
+    );
+    assert_matches!(
+        runner.apply(
+            Request::post("/")
+                .header("expect", "100-continue")
+                .header("content-length", "6")
+                .body("foobar")
+        ),
+        Err(ref err) if err.status_code().as_u16() == 413
+    );
+}
+
+#[test]
+fn test_reject() {
+    let mut runner = test::runner(expect::reject());
+
+    assert_matches!(runner.apply_raw(Request::post("/")), Ok(()));
+    assert_matches!(
+        runner.apply_raw(Request::post("/").header("expect", "100-continue")),
+        Err(ref err) if err.status_code().as_u16() == 417
+    );
+}
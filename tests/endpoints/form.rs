@@ -0,0 +1,134 @@
+use finchers::endpoint::syntax;
+use finchers::endpoints::form::{self, CsrfToken, Validate, ValidationErrors};
+use finchers::prelude::*;
+use finchers::test;
+use http::Request;
+use serde::Deserialize;
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct SignUp {
+    name: String,
+    email: String,
+}
+
+impl Validate for SignUp {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if self.name.is_empty() {
+            errors.add("name", "must not be empty");
+        }
+        if !self.email.contains('@') {
+            errors.add("email", "must contain `@`");
+        }
+        errors.into_result()
+    }
+}
+
+fn post() -> http::request::Builder {
+    let mut request = Request::post("/");
+    request.header("content-type", "application/x-www-form-urlencoded");
+    request
+}
+
+#[test]
+fn test_validated_form() {
+    let mut runner = test::runner(
+        form::validated()
+            .without_csrf()
+            .map(|form: SignUp| form.name),
+    );
+
+    runner
+        .perform(post().body("name=alice&email=alice%40example.com"))
+        .unwrap()
+        .assert_status(200)
+        .assert_body("alice");
+
+    let response = runner.perform(post().body("name=&email=alice")).unwrap();
+    response
+        .assert_status(422)
+        .assert_header("content-type", "application/json")
+        .assert_body(r#"{"errors":{"email":["must contain `@`"],"name":["must not be empty"]}}"#);
+
+    // malformed forms are reported as in `body::urlencoded`
+    runner
+        .perform(post().body("name=alice"))
+        .unwrap()
+        .assert_status(400);
+    runner
+        .perform(Request::post("/").body("name=alice&email=alice%40example.com"))
+        .unwrap()
+        .assert_status(415);
+}
+
+#[test]
+fn test_csrf_token() {
+    let mut runner = test::runner(
+        syntax::verb::get()
+            .and(form::csrf_token())
+            .map(|token: CsrfToken| token.to_string())
+            .or(syntax::verb::post()
+                .and(form::validated())
+                .map(|form: SignUp| form.name)),
+    );
+    let mut session = runner.session();
+
+    let token = session
+        .perform("/")
+        .unwrap()
+        .to_utf8()
+        .unwrap()
+        .into_owned();
+    assert_eq!(token.len(), 64);
+    // the issued token is kept until the cookie is removed
+    session
+        .perform("/")
+        .unwrap()
+        .assert_status(200)
+        .assert_body(token.as_str());
+
+    session
+        .perform(post().body(format!(
+            "name=alice&_csrf={}&email=alice%40example.com",
+            token
+        )))
+        .unwrap()
+        .assert_status(200)
+        .assert_body("alice");
+    session
+        .perform(
+            post()
+                .header("x-csrf-token", token.as_str())
+                .body("name=alice&email=alice%40example.com"),
+        )
+        .unwrap()
+        .assert_status(200);
+
+    // the forms are rejected before the validation
+    session
+        .perform(post().body("name=&email=alice"))
+        .unwrap()
+        .assert_status(403);
+    session
+        .perform(post().body(format!("name=alice&email=alice&_csrf={}", "0".repeat(64))))
+        .unwrap()
+        .assert_status(403);
+
+    // the cookie is required in addition to the field
+    runner
+        .perform(post().body(format!("name=alice&email=alice&_csrf={}", token)))
+        .unwrap()
+        .assert_status(403);
+}
+
+#[test]
+fn test_validation_errors() {
+    let mut errors = ValidationErrors::new();
+    assert!(errors.is_empty());
+    errors.add("name", "too short");
+    errors.add("name", "invalid character");
+    assert_eq!(errors.get("name"), ["too short", "invalid character"]);
+    assert!(errors.get("email").is_empty());
+    assert_eq!(errors.iter().count(), 1);
+    assert!(errors.into_result().is_err());
+}
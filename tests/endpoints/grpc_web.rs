@@ -0,0 +1,153 @@
+use finchers::endpoints::grpc_web::{self, Code, Status};
+use finchers::test;
+use http::Request;
+use prost::Message;
+
+#[derive(Clone, PartialEq, Message)]
+struct HelloRequest {
+    #[prost(string, tag = "1")]
+    name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct HelloReply {
+    #[prost(string, tag = "1")]
+    message: String,
+}
+
+fn frame(flag: u8, payload: &[u8]) -> Vec<u8> {
+    let len = payload.len() as u32;
+    let mut buf = vec![
+        flag,
+        (len >> 24) as u8,
+        (len >> 16) as u8,
+        (len >> 8) as u8,
+        len as u8,
+    ];
+    buf.extend_from_slice(payload);
+    buf
+}
+
+fn request_body(name: &str) -> Vec<u8> {
+    let message = HelloRequest { name: name.into() };
+    let mut payload = vec![];
+    message.encode(&mut payload).unwrap();
+    frame(0, &payload)
+}
+
+fn say_hello(
+) -> impl finchers::endpoint::Endpoint<finchers::test::ReqBody, Output = (grpc_web::GrpcWebResponse,)>
+{
+    grpc_web::unary("/helloworld.Greeter/SayHello", |req: HelloRequest| {
+        if req.name.is_empty() {
+            Err(Status::new(Code::InvalidArgument, "empty name"))
+        } else {
+            Ok(HelloReply {
+                message: format!("Hello, {}", req.name),
+            })
+        }
+    })
+}
+
+#[test]
+fn test_unary_binary() {
+    let mut runner = test::runner(say_hello());
+
+    let response = runner
+        .perform(
+            Request::post("/helloworld.Greeter/SayHello")
+                .header("content-type", "application/grpc-web+proto")
+                .body(request_body("Alice")),
+        )
+        .unwrap();
+    response
+        .assert_status(200)
+        .assert_header("content-type", "application/grpc-web+proto");
+
+    let mut reply = vec![];
+    HelloReply {
+        message: "Hello, Alice".into(),
+    }
+    .encode(&mut reply)
+    .unwrap();
+    let mut expected = frame(0, &reply);
+    expected.extend(frame(0x80, b"grpc-status:0\r\n"));
+    response.assert_body(expected);
+}
+
+#[test]
+fn test_unary_text_and_error() {
+    let mut runner = test::runner(say_hello());
+
+    let response = runner
+        .perform(
+            Request::post("/helloworld.Greeter/SayHello")
+                .header("content-type", "application/grpc-web-text")
+                .body(base64::encode(&request_body(""))),
+        )
+        .unwrap();
+    response
+        .assert_status(200)
+        .assert_header("content-type", "application/grpc-web-text+proto");
+    let body = base64::decode(&*response.to_utf8().unwrap()).unwrap();
+    assert_eq!(
+        body,
+        frame(0x80, b"grpc-status:3\r\ngrpc-message:empty name\r\n")
+    );
+}
+
+#[test]
+fn test_unary_not_matched() {
+    let mut runner = test::runner(say_hello());
+
+    runner
+        .perform(
+            Request::post("/helloworld.Greeter/SayGoodbye")
+                .header("content-type", "application/grpc-web"),
+        )
+        .unwrap()
+        .assert_status(404);
+    runner
+        .perform(
+            Request::post("/helloworld.Greeter/SayHello")
+                .header("content-type", "application/json"),
+        )
+        .unwrap()
+        .assert_status(415);
+}
+
+#[test]
+fn test_unary_malformed_and_too_large() {
+    let mut runner = test::runner(
+        grpc_web::unary("/helloworld.Greeter/SayHello", |req: HelloRequest| {
+            Err::<HelloReply, _>(Status::new(Code::Internal, format!("{}% done", req.name)))
+        })
+        .max_request_size(16),
+    );
+    let request = |body: Vec<u8>| {
+        Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc-web")
+            .body(body)
+    };
+
+    runner
+        .perform(request(request_body("Bob")))
+        .unwrap()
+        .assert_body(frame(
+            0x80,
+            b"grpc-status:13\r\ngrpc-message:Bob%25 done\r\n",
+        ));
+
+    let mut truncated = request_body("Bob");
+    truncated.pop();
+    let response = runner.perform(request(truncated)).unwrap();
+    assert!(response
+        .to_utf8_lossy()
+        .contains("grpc-status:3\r\ngrpc-message:incomplete frame"));
+
+    let response = runner
+        .perform(request(request_body("a very long name")))
+        .unwrap();
+    response.assert_status(200);
+    assert!(response.to_utf8_lossy().contains("grpc-status:8\r\n"));
+}
@@ -49,3 +49,20 @@ fn test_header_optional() {
 
     assert_matches!(runner.apply(Request::new(())), Ok(None));
 }
+
+#[test]
+fn test_header_str() {
+    use finchers::endpoints::header::HeaderStr;
+
+    let mut runner = test::runner(endpoints::header::optional::<HeaderStr>("x-api-key"));
+
+    assert_matches!(
+        runner.apply(Request::get("/").header("x-api-key", "secret")),
+        Ok(Some(ref key)) if *key == "secret"
+    );
+    assert_matches!(
+        runner.apply(Request::get("/").header("x-api-key", &b"\xff"[..])),
+        Err(ref e) if e.status_code().as_u16() == 400
+    );
+    assert_matches!(runner.apply(Request::new(())), Ok(None));
+}
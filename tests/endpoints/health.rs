@@ -0,0 +1,36 @@
+use finchers::endpoints::health::{self, Registry};
+use finchers::test;
+use futures::future;
+
+#[test]
+fn test_liveness() {
+    let mut runner = test::runner(health::liveness());
+    runner
+        .perform("/")
+        .unwrap()
+        .assert_status(200)
+        .assert_header("content-type", "application/json")
+        .assert_body(r#"{"status":"up"}"#);
+}
+
+#[test]
+fn test_readiness() {
+    let registry = Registry::new();
+    let mut runner = test::runner(health::readiness(&registry));
+
+    registry.register("database", || Ok::<(), String>(()));
+    runner
+        .perform("/")
+        .unwrap()
+        .assert_status(200)
+        .assert_body(r#"{"status":"up","checks":{"database":{"status":"up"}}}"#);
+
+    registry.register("queue", || future::err::<(), _>("too many jobs"));
+    runner
+        .perform("/")
+        .unwrap()
+        .assert_status(503)
+        .assert_body(
+            r#"{"status":"down","checks":{"database":{"status":"up"},"queue":{"status":"down","error":"too many jobs"}}}"#,
+        );
+}
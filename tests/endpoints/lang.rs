@@ -0,0 +1,90 @@
+use finchers::endpoints::lang::{self, LanguageTag};
+use finchers::prelude::*;
+use finchers::service::Context;
+use finchers::test;
+use http::Request;
+use matches::assert_matches;
+
+fn request(accept_language: &str) -> Request<&'static str> {
+    Request::get("/")
+        .header("accept-language", accept_language)
+        .body("")
+        .unwrap()
+}
+
+#[test]
+fn test_parse_accept_language() {
+    let ranges = lang::parse_accept_language("da, en-GB;q=0.8, en;q=0.7, *;q=0.1, ??, fr;q=2");
+    let ranges: Vec<_> = ranges.iter().map(|(tag, q)| (tag.as_str(), *q)).collect();
+    assert_eq!(
+        ranges,
+        vec![("da", 1.0), ("en-GB", 0.8), ("en", 0.7), ("*", 0.1)]
+    );
+
+    assert!(LanguageTag::new("en").matches(&LanguageTag::new("EN-us")));
+    assert!(!LanguageTag::new("en").matches(&LanguageTag::new("eng")));
+    assert!("en_US".parse::<LanguageTag>().is_err());
+}
+
+#[test]
+fn test_preferred() {
+    let supported = [
+        LanguageTag::new("en"),
+        LanguageTag::new("ja"),
+        LanguageTag::new("de-CH"),
+    ];
+    let mut runner = test::runner(lang::preferred(&supported));
+
+    assert_matches!(runner.apply("/"), Ok(ref tag) if tag.as_str() == "en");
+    assert_matches!(
+        runner.apply(request("fr, ja;q=0.5, en;q=0.3")),
+        Ok(ref tag) if tag.as_str() == "ja"
+    );
+    // the supported tags which match a prefix of the range
+    assert_matches!(
+        runner.apply(request("ja-JP, en;q=0.5")),
+        Ok(ref tag) if tag.as_str() == "ja"
+    );
+    // the supported tags matched by the range
+    assert_matches!(runner.apply(request("de")), Ok(ref tag) if tag.as_str() == "de-CH");
+    // the exclusion by `q=0`
+    assert_matches!(
+        runner.apply(request("en;q=0, *")),
+        Ok(ref tag) if tag.as_str() == "ja"
+    );
+    assert_matches!(runner.apply(request("fr")), Ok(ref tag) if tag.as_str() == "en");
+}
+
+#[test]
+fn test_accepted() {
+    let mut runner = test::runner(lang::accepted());
+    assert_matches!(
+        runner.apply(request("en;q=0.5, ja")),
+        Ok(ref ranges) if ranges.len() == 2 && ranges[0].0.as_str() == "ja"
+    );
+    assert_matches!(runner.apply("/"), Ok(ref ranges) if ranges.is_empty());
+}
+
+#[test]
+fn test_negotiate() {
+    let mut runner = test::runner(
+        endpoint::endpoint(|| {
+            futures::future::lazy(|| {
+                let lang = Context::with(|cx| cx.extensions().get::<LanguageTag>().cloned());
+                Ok::<_, finchers::error::Error>((lang,))
+            })
+        })
+        .wrap(lang::negotiate(&[
+            LanguageTag::new("en"),
+            LanguageTag::new("ja"),
+        ])),
+    );
+    assert_matches!(
+        runner.apply(request("ja")),
+        Ok(Some(ref tag)) if tag.as_str() == "ja"
+    );
+
+    let mut runner =
+        test::runner(endpoint::value("Hello").wrap(lang::negotiate(&[LanguageTag::new("en")])));
+    assert_matches!(runner.apply(request("ja")), Ok("Hello"));
+}
@@ -1,5 +1,20 @@
+mod auth;
 mod body;
+mod cancel;
+mod expect;
+mod form;
+#[cfg(feature = "grpc-web")]
+mod grpc_web;
 //mod cookie;
 mod header;
+mod health;
+mod lang;
+#[cfg(feature = "oauth2")]
+mod oauth2;
+mod pagination;
 mod query;
+mod tower;
+mod webdav;
+#[cfg(feature = "webhook")]
+mod webhook;
 //mod upgrade;
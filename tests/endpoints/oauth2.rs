@@ -0,0 +1,252 @@
+use finchers::endpoint::syntax;
+use finchers::endpoints::auth::oauth2::{OAuth2, Token};
+use finchers::prelude::*;
+use finchers::test;
+use futures::{Future, Stream};
+use hyper::service::service_fn;
+use hyper::{Body, Response, Server};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::sign::Signer;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The ID token issued by the test server.
+#[derive(Debug, Default)]
+struct Issued {
+    nonce: String,
+    issuer: Option<String>,
+    tampered: bool,
+}
+
+fn base64url(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn sign_id_token(key: &PKey<Private>, issued: &Issued) -> String {
+    let header = base64url(br#"{"alg":"RS256","kid":"k1"}"#);
+    let exp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 60;
+    let claims = format!(
+        r#"{{"iss":"{}","aud":"client","exp":{},"sub":"alice","nonce":"{}"}}"#,
+        issued
+            .issuer
+            .as_ref()
+            .map_or("https://auth.example.com", |s| &**s),
+        exp,
+        issued.nonce,
+    );
+    let signing_input = format!("{}.{}", header, base64url(claims.as_bytes()));
+    let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+    signer.update(signing_input.as_bytes()).unwrap();
+    let mut signature = signer.sign_to_vec().unwrap();
+    if issued.tampered {
+        signature[0] ^= 1;
+    }
+    format!("{}.{}", signing_input, base64url(&signature))
+}
+
+/// Spawns an authorization server which publishes the JWK set and records
+/// the request bodies to the token endpoint.
+fn spawn_token_server(issued: Arc<Mutex<Issued>>, bodies: Arc<Mutex<Vec<String>>>) -> SocketAddr {
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let jwks = {
+        let rsa = key.rsa().unwrap();
+        format!(
+            r#"{{"keys":[{{"kty":"RSA","kid":"k1","use":"sig","n":"{}","e":"{}"}}]}}"#,
+            base64url(&rsa.n().to_vec()),
+            base64url(&rsa.e().to_vec()),
+        )
+    };
+
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(move || {
+            let key = key.clone();
+            let jwks = jwks.clone();
+            let issued = issued.clone();
+            let bodies = bodies.clone();
+            service_fn(move |req: hyper::Request<Body>| {
+                let key = key.clone();
+                let jwks = jwks.clone();
+                let issued = issued.clone();
+                let bodies = bodies.clone();
+                let is_jwks = req.uri().path() == "/jwks";
+                req.into_body().concat2().map(move |body| {
+                    if is_jwks {
+                        return Response::new(Body::from(jwks));
+                    }
+                    bodies
+                        .lock()
+                        .unwrap()
+                        .push(String::from_utf8(body.to_vec()).unwrap());
+                    let id_token = sign_id_token(&key, &issued.lock().unwrap());
+                    Response::new(Body::from(format!(
+                        r#"{{"access_token":"at","token_type":"Bearer","id_token":"{}"}}"#,
+                        id_token
+                    )))
+                })
+            })
+        });
+        tx.send(server.local_addr()).unwrap();
+        hyper::rt::run(server.map_err(|e| panic!("{}", e)));
+    });
+    rx.recv().unwrap()
+}
+
+fn query_param(uri: &str, name: &str) -> String {
+    url::Url::parse(uri)
+        .unwrap()
+        .query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+}
+
+#[test]
+fn test_oauth2_flow() {
+    let issued = Arc::new(Mutex::new(Issued::default()));
+    let bodies = Arc::new(Mutex::new(vec![]));
+    let addr = spawn_token_server(issued.clone(), bodies.clone());
+
+    let oauth2 = OAuth2::new(
+        "client",
+        "https://auth.example.com/authorize",
+        &format!("http://{}/token", addr),
+        "http://localhost/callback",
+    )
+    .client_secret("secret")
+    .openid_connect("https://auth.example.com", &format!("http://{}/jwks", addr))
+    .scopes(&["email"]);
+
+    let mut runner = test::runner(
+        syntax::segment("login")
+            .and(oauth2.authorize())
+            .or(syntax::segment("callback")
+                .and(oauth2.callback())
+                .map(|token: Token| {
+                    let claims: Claims = token.claims().unwrap();
+                    format!("{}:{}", token.access_token, claims.sub)
+                })),
+    );
+    let mut session = runner.session();
+
+    let response = session.perform("/login").unwrap();
+    response.assert_status(302);
+    let location = response.headers()["location"].to_str().unwrap().to_owned();
+    assert!(location.starts_with("https://auth.example.com/authorize?response_type=code"));
+    assert_eq!(query_param(&location, "scope"), "openid email");
+    assert_eq!(query_param(&location, "code_challenge_method"), "S256");
+    assert!(response.headers()["set-cookie"]
+        .to_str()
+        .unwrap()
+        .starts_with("finchers-oauth2="));
+
+    // mismatched state
+    session
+        .perform("/callback?code=xyz&state=forged")
+        .unwrap()
+        .assert_status(400);
+
+    // the cookie has been removed by the failed attempt, so start over
+    let mut login = || {
+        let response = session.perform("/login").unwrap();
+        let location = response.headers()["location"].to_str().unwrap().to_owned();
+        issued.lock().unwrap().nonce = query_param(&location, "nonce");
+        let state = query_param(&location, "state");
+        session.perform(format!("/callback?code=xyz&state={}", state).as_str())
+    };
+
+    login().unwrap().assert_status(200).assert_body("at:alice");
+
+    // the ID tokens which are not validated
+    issued.lock().unwrap().tampered = true;
+    login().unwrap().assert_status(401);
+    issued.lock().unwrap().tampered = false;
+
+    issued.lock().unwrap().issuer = Some("https://evil.example.com".into());
+    login().unwrap().assert_status(401);
+    issued.lock().unwrap().issuer = None;
+
+    let bodies = bodies.lock().unwrap();
+    assert_eq!(bodies.len(), 3);
+    assert!(bodies[0].starts_with("grant_type=authorization_code&code=xyz"));
+    assert!(bodies[0].contains("client_secret=secret"));
+    assert!(bodies[0].contains("code_verifier="));
+}
+
+#[test]
+fn test_oauth2_nonce_mismatch() {
+    let issued = Arc::new(Mutex::new(Issued::default()));
+    let addr = spawn_token_server(issued.clone(), Arc::new(Mutex::new(vec![])));
+
+    let oauth2 = OAuth2::new(
+        "client",
+        "https://auth.example.com/authorize",
+        &format!("http://{}/token", addr),
+        "http://localhost/callback",
+    )
+    .openid_connect("https://auth.example.com", &format!("http://{}/jwks", addr));
+    let mut runner = test::runner(
+        syntax::segment("login")
+            .and(oauth2.authorize())
+            .or(syntax::segment("callback")
+                .and(oauth2.callback())
+                .map(|token: Token| token.access_token)),
+    );
+    let mut session = runner.session();
+
+    let response = session.perform("/login").unwrap();
+    let location = response.headers()["location"].to_str().unwrap().to_owned();
+    issued.lock().unwrap().nonce = "replayed".into();
+    session
+        .perform(
+            format!(
+                "/callback?code=xyz&state={}",
+                query_param(&location, "state")
+            )
+            .as_str(),
+        )
+        .unwrap()
+        .assert_status(401);
+}
+
+#[test]
+#[should_panic(expected = "openid_connect")]
+fn test_oauth2_openid_scope_requires_openid_connect() {
+    let oauth2 = OAuth2::new(
+        "client",
+        "https://auth.example.com/authorize",
+        "http://127.0.0.1:1/token",
+        "http://localhost/callback",
+    )
+    .scopes(&["openid"]);
+    drop(oauth2.authorize());
+}
+
+#[test]
+fn test_oauth2_error_response() {
+    let oauth2 = OAuth2::new(
+        "client",
+        "https://auth.example.com/authorize",
+        "http://127.0.0.1:1/token",
+        "http://localhost/callback",
+    );
+    let mut runner = test::runner(oauth2.callback().map(|token: Token| token.access_token));
+    runner
+        .perform("/?error=access_denied&state=foo")
+        .unwrap()
+        .assert_status(401);
+    runner.perform("/?code=xyz").unwrap().assert_status(400);
+}
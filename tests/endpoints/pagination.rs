@@ -0,0 +1,72 @@
+use finchers::endpoints::pagination::{self, Page};
+use finchers::prelude::*;
+use finchers::test;
+use matches::assert_matches;
+
+#[test]
+fn test_page_params() {
+    let mut runner = test::runner(pagination::page().default_per_page(10).max_per_page(50));
+
+    assert_matches!(
+        runner.apply("/"),
+        Ok(ref page) if page.number() == 1 && page.per_page() == 10 && page.offset() == 0
+    );
+    assert_matches!(
+        runner.apply("/?page=3&per_page=1000"),
+        Ok(ref page) if page.number() == 3 && page.per_page() == 50 && page.offset() == 100
+    );
+    assert_matches!(
+        runner.apply("/?cursor=abc%3D"),
+        Ok(ref page) if page.cursor() == Some("abc=")
+    );
+    assert_matches!(
+        runner.apply("/?page=0"),
+        Err(ref err) if err.status_code().as_u16() == 400
+    );
+    assert_matches!(
+        runner.apply("/?per_page=many"),
+        Err(ref err) if err.status_code().as_u16() == 400
+    );
+}
+
+#[test]
+fn test_paginate_links() {
+    let mut runner = test::runner(pagination::page().map(|page: Page| page.paginate("items", 40)));
+
+    let response = runner.perform("/items?q=rust&page=2&per_page=20").unwrap();
+    assert_eq!(
+        response.headers()["link"],
+        "</items?q=rust&page=1&per_page=20>; rel=\"prev\", \
+         </items?q=rust&page=1&per_page=20>; rel=\"first\", \
+         </items?q=rust&page=2&per_page=20>; rel=\"last\""
+    );
+
+    let response = runner.perform("/items").unwrap();
+    assert_eq!(
+        response.headers()["link"],
+        "</items?page=2&per_page=20>; rel=\"next\", \
+         </items?page=1&per_page=20>; rel=\"first\", \
+         </items?page=2&per_page=20>; rel=\"last\""
+    );
+    assert_eq!(response.headers()["x-total-count"], "40");
+}
+
+#[test]
+fn test_paginate_cursor() {
+    let mut runner = test::runner(pagination::page().map(|page: Page| {
+        let next = match page.cursor() {
+            None => Some("b".to_owned()),
+            Some(..) => None,
+        };
+        page.paginate_cursor("items", next)
+    }));
+
+    let response = runner.perform("/items?per_page=5").unwrap();
+    assert_eq!(
+        response.headers()["link"],
+        "</items?cursor=b&per_page=5>; rel=\"next\""
+    );
+
+    let response = runner.perform("/items?cursor=b").unwrap();
+    assert!(response.headers().get("link").is_none());
+}
@@ -51,3 +51,21 @@ fn test_query_optional() {
 
     assert_matches!(runner.apply("/"), Ok(None));
 }
+
+#[test]
+fn test_query_param() {
+    let mut runner = test::runner(query::param::<u32>("page"));
+    assert_matches!(runner.apply("/?page=3&page=4"), Ok(3));
+    assert_matches!(runner.apply("/?per_page=3"), Err(..));
+    assert_matches!(runner.apply("/?page=three"), Err(..));
+    assert_matches!(runner.apply("/"), Err(..));
+
+    let mut runner = test::runner(query::optional_param::<String>("q"));
+    assert_matches!(runner.apply("/?q=rust+lang%21"), Ok(Some(ref s)) if s == "rust lang!");
+    assert_matches!(runner.apply("/"), Ok(None));
+
+    let mut runner = test::runner(query::params::<u32>("id"));
+    assert_matches!(runner.apply("/?id=1&x=0&id=2"), Ok(ref ids) if *ids == [1, 2]);
+    assert_matches!(runner.apply("/"), Ok(ref ids) if ids.is_empty());
+    assert_matches!(runner.apply("/?id=1&id=x"), Err(..));
+}
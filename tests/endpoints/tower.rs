@@ -0,0 +1,64 @@
+use finchers::endpoints::tower;
+use finchers::prelude::*;
+use finchers::test;
+use futures::{future, Async, Poll};
+use http::{Method, Request, Response};
+use matches::assert_matches;
+use tower_service::Service;
+
+#[derive(Debug, Clone)]
+struct Echo;
+
+impl<Bd> Service<Request<Bd>> for Echo {
+    type Response = Response<String>;
+    type Error = finchers::error::Error;
+    type Future = future::FutureResult<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, request: Request<Bd>) -> Self::Future {
+        future::ok(Response::new(format!(
+            "{} {}",
+            request.method(),
+            request.uri().path()
+        )))
+    }
+}
+
+#[test]
+fn test_tower_service_endpoint() {
+    let mut runner = test::runner(tower::service(Echo));
+
+    assert_matches!(
+        runner.apply(Request::post("/foo/bar")),
+        Ok(ref response) if response.body() == "POST /foo/bar"
+    );
+}
+
+#[test]
+fn test_tower_service_body_stolen() {
+    let mut runner = test::runner(endpoints::body::raw().and(tower::service(Echo)));
+
+    assert_matches!(runner.apply_raw("/"), Err(..));
+}
+
+#[test]
+fn test_app_as_tower_service() {
+    let mut app = endpoint::syntax::verb::get().map(|| "Hello").into_service();
+
+    let mut rt = tokio::runtime::current_thread::Runtime::new().unwrap();
+
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri("/")
+        .body(())
+        .unwrap();
+    assert_matches!(
+        Service::<Request<()>>::poll_ready(&mut app),
+        Ok(Async::Ready(()))
+    );
+    let response = rt.block_on(Service::call(&mut app, request)).unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+}
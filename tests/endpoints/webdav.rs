@@ -0,0 +1,119 @@
+use finchers::endpoints::webdav::{self, Depth, Destination, Multistatus, Overwrite, PropStat};
+use finchers::prelude::*;
+use finchers::test;
+use http::{Request, StatusCode};
+use matches::assert_matches;
+
+fn request(method: &str) -> http::request::Builder {
+    let mut builder = Request::builder();
+    builder.method(method).uri("/");
+    builder
+}
+
+#[test]
+fn test_methods() {
+    let mut runner = test::runner(
+        webdav::mkcol()
+            .map(|| "mkcol")
+            .or_strict(webdav::move_().map(|| "move")),
+    );
+    assert_matches!(runner.apply(request("MKCOL")), Ok("mkcol"));
+    assert_matches!(runner.apply(request("MOVE")), Ok("move"));
+    assert_matches!(runner.apply(request("COPY")), Err(..));
+}
+
+#[test]
+fn test_depth() {
+    let mut runner = test::runner(webdav::depth());
+    assert_matches!(runner.apply(request("PROPFIND")), Ok(Depth::Infinity));
+    assert_matches!(
+        runner.apply(request("PROPFIND").header("depth", "0")),
+        Ok(Depth::Zero)
+    );
+    assert_matches!(
+        runner.apply(request("PROPFIND").header("depth", "Infinity")),
+        Ok(Depth::Infinity)
+    );
+    assert_matches!(
+        runner.apply(request("PROPFIND").header("depth", "2")),
+        Err(ref err) if err.status_code() == StatusCode::BAD_REQUEST
+    );
+}
+
+#[test]
+fn test_copy_headers() {
+    let mut runner = test::runner(
+        webdav::copy()
+            .and(webdav::destination())
+            .and(webdav::overwrite())
+            .map(|dest: Destination, overwrite: Overwrite| (dest.path().to_owned(), overwrite.0)),
+    );
+
+    assert_matches!(
+        runner.apply(
+            request("COPY")
+                .header("destination", "http://example.com/dest/file.txt")
+                .header("overwrite", "F")
+        ),
+        Ok((ref path, false)) if path == "/dest/file.txt"
+    );
+    assert_matches!(
+        runner.apply(request("COPY").header("destination", "/dest")),
+        Ok((ref path, true)) if path == "/dest"
+    );
+    assert_matches!(
+        runner.apply(request("COPY")),
+        Err(ref err) if err.status_code() == StatusCode::BAD_REQUEST
+    );
+}
+
+#[test]
+fn test_multistatus() {
+    let mut multistatus = Multistatus::new();
+    multistatus
+        .namespace("Z", "http://ns.example.com/z?a&b")
+        .response(
+            "/a&b",
+            vec![
+                PropStat::new(StatusCode::OK).prop("displayname", "<a>\r\n\u{1}"),
+                PropStat::new(StatusCode::NOT_FOUND).empty_prop("Z:author"),
+            ],
+        )
+        .status("/c", StatusCode::LOCKED);
+
+    let mut runner = test::runner(endpoint::value(multistatus));
+    runner
+        .perform("/")
+        .unwrap()
+        .assert_status(207)
+        .assert_header("content-type", "application/xml; charset=utf-8")
+        .assert_body(concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            r#"<D:multistatus xmlns:D="DAV:" xmlns:Z="http://ns.example.com/z?a&amp;b">"#,
+            "<D:response><D:href>/a&amp;b</D:href>",
+            "<D:propstat><D:prop><D:displayname>&lt;a&gt;&#xD;\n\u{FFFD}</D:displayname></D:prop>",
+            "<D:status>HTTP/1.1 200 OK</D:status></D:propstat>",
+            "<D:propstat><D:prop><Z:author/></D:prop>",
+            "<D:status>HTTP/1.1 404 Not Found</D:status></D:propstat>",
+            "</D:response>",
+            "<D:response><D:href>/c</D:href><D:status>HTTP/1.1 423 Locked</D:status></D:response>",
+            "</D:multistatus>",
+        ));
+}
+
+#[test]
+#[should_panic(expected = "undeclared namespace prefix: Z")]
+fn test_multistatus_undeclared_prefix() {
+    let mut multistatus = Multistatus::new();
+    multistatus.response(
+        "/",
+        vec![PropStat::new(StatusCode::OK).empty_prop("Z:author")],
+    );
+    multistatus.to_xml();
+}
+
+#[test]
+#[should_panic(expected = "invalid property name")]
+fn test_multistatus_invalid_prop_name() {
+    PropStat::new(StatusCode::OK).prop("a><b", "c");
+}
@@ -0,0 +1,175 @@
+use finchers::endpoints::webhook::{self, Algorithm};
+use finchers::prelude::*;
+use finchers::test;
+use hmac::{Hmac, Mac};
+use http::Request;
+use matches::assert_matches;
+use serde::Deserialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn sign_sha256(secret: &str, content: &str) -> String {
+    let mut mac = Hmac::<sha2::Sha256>::new_varkey(secret.as_bytes()).unwrap();
+    mac.input(content.as_bytes());
+    hex(&mac.result().code())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[derive(Debug, Deserialize)]
+struct Event {
+    action: String,
+}
+
+#[test]
+fn test_github() {
+    let body = r#"{"action":"opened"}"#;
+    let mut runner = test::runner(
+        webhook::github("secret")
+            .json::<Event>()
+            .map(|raw: Vec<u8>, event: Event| (raw.len(), event.action)),
+    );
+
+    assert_matches!(
+        runner.apply(
+            Request::post("/")
+                .header(
+                    "x-hub-signature-256",
+                    format!("sha256={}", sign_sha256("secret", body)),
+                )
+                .body(body)
+        ),
+        Ok((19, ref action)) if action == "opened"
+    );
+
+    assert_matches!(
+        runner.apply(
+            Request::post("/")
+                .header(
+                    "x-hub-signature-256",
+                    format!("sha256={}", sign_sha256("wrong", body)),
+                )
+                .body(body)
+        ),
+        Err(ref err) if err.status_code().as_u16() == 401
+    );
+
+    assert_matches!(
+        runner.apply(Request::post("/").body(body)),
+        Err(ref err) if err.status_code().as_u16() == 401
+    );
+}
+
+#[test]
+fn test_hmac_sha1() {
+    let body = "payload";
+    let mut mac = Hmac::<sha1::Sha1>::new_varkey(b"key").unwrap();
+    mac.input(body.as_bytes());
+    let signature = hex(&mac.result().code());
+
+    let mut runner = test::runner(webhook::hmac(Algorithm::Sha1, "key", "x-signature"));
+    assert_matches!(
+        runner.apply(Request::post("/").header("x-signature", signature).body(body)),
+        Ok(ref raw) if raw == b"payload"
+    );
+}
+
+#[test]
+fn test_stripe() {
+    let body = r#"{"action":"paid"}"#;
+    let mut runner = test::runner(webhook::stripe("whsec"));
+
+    let t = now();
+    let signature = sign_sha256("whsec", &format!("{}.{}", t, body));
+    assert_matches!(
+        runner.apply(
+            Request::post("/")
+                .header(
+                    "stripe-signature",
+                    format!("t={},v1=deadbeef,v1={}", t, signature),
+                )
+                .body(body)
+        ),
+        Ok(..)
+    );
+
+    let t = now() - 600;
+    let signature = sign_sha256("whsec", &format!("{}.{}", t, body));
+    assert_matches!(
+        runner.apply(
+            Request::post("/")
+                .header("stripe-signature", format!("t={},v1={}", t, signature))
+                .body(body)
+        ),
+        Err(ref err) if err.to_string().contains("tolerance")
+    );
+}
+
+#[test]
+fn test_slack() {
+    let body = "token=xyz&team_id=T1";
+    let mut runner = test::runner(webhook::slack("slack-secret"));
+
+    let t = now();
+    let signature = sign_sha256("slack-secret", &format!("v0:{}:{}", t, body));
+    assert_matches!(
+        runner.apply(
+            Request::post("/")
+                .header("x-slack-signature", format!("v0={}", signature))
+                .header("x-slack-request-timestamp", t.to_string())
+                .body(body)
+        ),
+        Ok(..)
+    );
+    assert_matches!(
+        runner.apply(
+            Request::post("/")
+                .header("x-slack-signature", format!("v0={}", signature))
+                .header("x-slack-request-timestamp", (t + 1).to_string())
+                .body(body)
+        ),
+        Err(ref err) if err.to_string() == "signature mismatch"
+    );
+}
+
+#[test]
+fn test_max_body_size() {
+    let body = "0123456789";
+    let signature = sign_sha256("key", body);
+    let mut runner = test::runner(
+        webhook::hmac(Algorithm::Sha256, "key", "x-signature").max_body_size(body.len()),
+    );
+    assert_matches!(
+        runner.apply(
+            Request::post("/")
+                .header("x-signature", signature.as_str())
+                .body(body)
+        ),
+        Ok(ref raw) if raw == body.as_bytes()
+    );
+
+    let body = "0123456789a";
+    let signature = sign_sha256("key", body);
+    assert_matches!(
+        runner.apply(
+            Request::post("/")
+                .header("x-signature", signature.as_str())
+                .body(body)
+        ),
+        Err(ref err) if err.status_code().as_u16() == 413
+    );
+}
+
+#[test]
+fn test_debug_omits_secret() {
+    let endpoint = webhook::github("my-secret").json::<Event>();
+    assert!(!format!("{:?}", endpoint).contains("my-secret"));
+}
@@ -1,3 +1,4 @@
+mod client;
 mod endpoint;
 mod endpoints;
 
@@ -7,14 +8,19 @@ fn version_sync() {
     version_sync::assert_markdown_deps_updated!("README.md");
 }
 
-// #[test]
-// fn test_path_macro() {
-//     let _ = path!(@get /);
-//     let _ = path!(@get / "foo" / u32);
-//     let _ = path!(@get / "foo" / { syntax::remains::<String>() });
-// }
+#[test]
+fn test_path_macro() {
+    use finchers::path;
+
+    let _ = path!(@get "/");
+    let _ = path!(@get "/foo/<u32>");
+    let _ = path!(@get "/foo/*rest");
+}
 
-// #[test]
-// fn test_routes_macro() {
-//     let _ = routes![endpoint::unit(), endpoint::value(42),];
-// }
+#[test]
+fn test_routes_macro() {
+    use finchers::{endpoint, routes};
+
+    let endpoint = routes![endpoint::value("foo"), endpoint::value(String::from("bar")),];
+    drop(finchers::test::runner(endpoint));
+}